@@ -1,30 +1,149 @@
-use crate::config::Config;
-use crate::context::{Context, DEFAULT_LIBJXL_COMMIT};
+use crate::binary_results::{append_comparisons, read_comparisons, write_comparisons};
+use crate::cache::EncodeCache;
+use crate::cancellation::{CancellationToken, Cancelled};
+use crate::color::to_original_space;
+use crate::config::{
+    BuildConfig, Config, DuplicatePolicy, Gate, LocalDiff, MetricValidation, OutputFormat,
+    PrebuiltCjxl, QualityTarget, SizeRegressionPolicy, SizeTarget, SizeTargetStatus,
+    SummaryWeighting, Sweep, Toolchain,
+};
+use crate::contact_sheet::ContactSheet;
+use crate::context::{Context, DEFAULT_LIBJXL_COMMIT, LOCAL_SRC_COMMIT, PINNED_LIBJXL_COMMIT};
 use crate::csv_writer::*;
-use crate::docker_manager::DockerManager;
-use crate::image_reader::{ImageFileData, ImageFormat, ImageReader};
+use crate::decode_limiter::DecodeLimiter;
+use crate::docker_manager::{DockerError, DockerManager, EffortRange};
+use crate::duplicates::DuplicateImages;
+use crate::failures::RunFailures;
+use crate::image_limits::{ImageLimits, OversizedImage};
+use crate::image_reader::{jxl_decode_available, ImageFileData, ImageFormat, ImageReader};
+use crate::logging::set_worker_context;
+use crate::manifest::RunManifest;
 use crate::metrics::*;
+use crate::object_store::ObjectStore;
+use crate::reference_cache::ReferenceCache;
+use crate::report::HtmlReport;
+use crate::resource_monitor::{ResourceMonitor, ResourceUsage};
+use crate::results_db::ResultsDatabase;
+use crate::run_timing::{Phase, RunTiming};
 use crate::utils::*;
 
+use log::{debug, error, info, warn};
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
 
 /// All benchmarks should implement this trait.
-/// The run method should be called to run the benchmark.
+/// The run method should be called to run the benchmark. It returns an error if the image could
+/// not be benchmarked, or a Cancelled error if the run was cancelled while it was in progress.
 pub trait Benchmark: Sync + Send {
-    fn run(docker_manager: DockerManager, payload: &WorkerPayload);
+    fn run(docker_manager: DockerManager, payload: &WorkerPayload) -> Result<(), Box<dyn Error>>;
 }
 
 /// Benchmark for JPEG XL compression.
 /// Implements the Benchmark trait.
 pub struct JXLCompressionBenchmark {}
 
+/// Errors that prevent the benchmarker from being set up.
+#[derive(Debug)]
+pub enum BenchmarkError {
+    /// Docker cannot be used at all.
+    Docker(DockerError),
+    /// The configuration is invalid.
+    Config(String),
+    /// A directory of the benchmark could not be created.
+    Directory(String, std::io::Error),
+    /// The encode cache of prior runs could not be loaded.
+    EncodeCache(String),
+    /// The container of a worker could not be set up.
+    WorkerSetup { worker_id: usize, message: String },
+    /// JXL images cannot be decoded on the local machine, so no encode could be measured.
+    JxlDecodeUnavailable,
+    /// The summaries of the comparisons violated the gates of the run.
+    GateFailed(Vec<String>),
+    /// The run was cancelled with its cancellation token, its results are partial.
+    Cancelled,
+    /// An image could not be benchmarked by a worker.
+    Image { image_name: String, message: String },
+}
+
+impl BenchmarkError {
+    /// Gets the exit code of the process for the error.
+    /// Violated gates exit with 2, so that CI can tell a regression from a broken run.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            BenchmarkError::GateFailed(_) => 2,
+            _ => 1,
+        }
+    }
+}
+
+impl Display for BenchmarkError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            BenchmarkError::Docker(e) => write!(f, "{}", e),
+            BenchmarkError::Config(message) => write!(f, "{}", message),
+            BenchmarkError::Directory(path, e) => {
+                write!(f, "Could not create the directory {}: {}", path, e)
+            }
+            BenchmarkError::EncodeCache(message) => {
+                write!(f, "Could not load the encode cache: {}", message)
+            }
+            BenchmarkError::WorkerSetup { worker_id, message } => write!(
+                f,
+                "Could not set up the container of worker {}: {}",
+                worker_id,
+                message.trim()
+            ),
+            BenchmarkError::JxlDecodeUnavailable => {
+                write!(f, "JXL decoding unavailable; install libjxl")
+            }
+            BenchmarkError::GateFailed(failures) => {
+                write!(
+                    f,
+                    "{} gate(s) failed: {}",
+                    failures.len(),
+                    failures.join("; ")
+                )
+            }
+            BenchmarkError::Cancelled => {
+                write!(f, "The run was cancelled, its results are partial")
+            }
+            BenchmarkError::Image {
+                image_name,
+                message,
+            } => write!(f, "Could not benchmark {}: {}", image_name, message),
+        }
+    }
+}
+
+impl Error for BenchmarkError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            BenchmarkError::Docker(e) => Some(e),
+            BenchmarkError::Directory(_, e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
 /// Runs benchmarks on multiple workers.
 #[derive(Debug)]
 pub struct Benchmarker {
     pub context: Context,
     pub workers: Vec<BenchmarkWorker>,
     pub current_worker_id: usize,
+    pub encode_cache: Arc<Mutex<EncodeCache>>,
+    pub failures: Arc<RunFailures>,
+    /// Cancels the run cooperatively, shared with the workers and their containers.
+    pub cancellation: CancellationToken,
+    pub csv_sink: Arc<Mutex<CsvSink>>,
+    pub decode_limiter: Arc<DecodeLimiter>,
+    pub run_timing: Arc<RunTiming>,
 }
 
 /// Represents a worker that runs a benchmark.
@@ -34,9 +153,17 @@ pub struct Benchmarker {
 pub struct BenchmarkWorker {
     pub id: usize,
     pub docker_manager: Option<DockerManager>,
-    pub thread_handle: Option<std::thread::JoinHandle<()>>,
+    /// The Docker managers of the other toolchains of the run, whose containers are idle while
+    /// `docker_manager` is in use.
+    pub toolchain_managers: Vec<DockerManager>,
+    pub thread_handle: Option<std::thread::JoinHandle<Result<(), BenchmarkError>>>,
     pub payload: Option<WorkerPayload>,
     pub working: bool,
+    /// The number of times the container of the worker was restarted after it died.
+    pub restarts: usize,
+    /// Whether the container of the worker died and could not be restarted, which takes the
+    /// worker out of dispatch for the rest of the run.
+    pub retired: bool,
 }
 
 /// Represents the payload for a worker.
@@ -52,6 +179,12 @@ pub struct WorkerPayload {
     pub current_image_file_path: String, // "kodim/kodim06.png"
     pub current_image_format: ImageFormat,
     pub current_test_set: String,
+    pub encode_cache: Arc<Mutex<EncodeCache>>,
+    pub failures: Arc<RunFailures>,
+    pub cancellation: CancellationToken,
+    pub csv_sink: Arc<Mutex<CsvSink>>,
+    pub decode_limiter: Arc<DecodeLimiter>,
+    pub run_timing: Arc<RunTiming>,
 }
 
 impl BenchmarkWorker {
@@ -67,10 +200,82 @@ impl BenchmarkWorker {
         BenchmarkWorker {
             id,
             docker_manager: None,
+            toolchain_managers: Vec::new(),
             thread_handle: None,
             payload: Some(payload.clone()),
             working: false,
+            restarts: 0,
+            retired: false,
+        }
+    }
+
+    /// Switches the worker to the container of a toolchain, keeping the container in use for
+    /// a later switch back. The worker must not be working.
+    ///
+    /// # Arguments
+    /// * `toolchain` - The name of the toolchain, or None if the run has no toolchains.
+    pub fn use_toolchain(&mut self, toolchain: Option<&str>) {
+        let current = self
+            .docker_manager
+            .as_ref()
+            .and_then(|m| m.toolchain.as_deref());
+        if toolchain.is_none() || current == toolchain {
+            return;
+        }
+        if let Some(position) = self
+            .toolchain_managers
+            .iter()
+            .position(|manager| manager.toolchain.as_deref() == toolchain)
+        {
+            let next = self.toolchain_managers.remove(position);
+            if let Some(current) = self.docker_manager.replace(next) {
+                self.toolchain_managers.push(current);
+            }
+        }
+    }
+
+    /// Restarts the containers of the worker that are not running, e.g. after one was killed for
+    /// running out of memory during a build, so later work does not fail on a dead container.
+    /// The idle containers of the other toolchains are checked too, since the worker switches
+    /// back to them later.
+    ///
+    /// # Arguments
+    /// * `libjxl_src` - An absolute path to a local libjxl source tree to mount at /libjxl, if any.
+    ///
+    /// # Returns
+    /// Whether a container was restarted, or an error if a new container could not be set up.
+    pub fn ensure_container_running(
+        &mut self,
+        libjxl_src: Option<&str>,
+    ) -> Result<bool, Box<dyn Error>> {
+        let mut restarted = false;
+        for docker_manager in self
+            .docker_manager
+            .iter_mut()
+            .chain(self.toolchain_managers.iter_mut())
+        {
+            let container = match &docker_manager.toolchain {
+                Some(toolchain) => format!("the {} container", toolchain),
+                None => "the container".to_string(),
+            };
+            match docker_manager.container_state() {
+                Ok(state) if state == "running" => continue,
+                Ok(state) => warn!(
+                    "{} of worker {} is {}, restarting it",
+                    container, self.id, state
+                ),
+                Err(e) => warn!(
+                    "{} of worker {} could not be inspected, restarting it: {}",
+                    container,
+                    self.id,
+                    e.to_string().trim()
+                ),
+            }
+            docker_manager.restart(self.id, libjxl_src)?;
+            self.restarts += 1;
+            restarted = true;
         }
+        Ok(restarted)
     }
 
     /// Runs the benchmark on the worker.
@@ -98,10 +303,44 @@ impl BenchmarkWorker {
         self.working = true;
 
         // Spawn a new thread to run the benchmark with the given payload.
+        // Its messages are prefixed with the worker and image.
         self.thread_handle = Some(std::thread::spawn(move || {
-            T::run(docker, &payload);
+            set_worker_context(payload.current_worker_id, &payload.current_image_name);
+            match T::run(docker, &payload) {
+                Ok(()) => {
+                    payload.run_timing.image_finished();
+                    Ok(())
+                }
+                Err(e) if e.is::<Cancelled>() => Err(BenchmarkError::Cancelled),
+                Err(e) => Err(BenchmarkError::Image {
+                    image_name: payload.current_image_name.clone(),
+                    message: e.to_string(),
+                }),
+            }
         }));
     }
+
+    /// Waits for the thread of the worker to finish.
+    /// An error or panic of the thread, e.g. from a failed measurement, is recorded as a failure.
+    ///
+    /// # Arguments
+    /// * `failures` - The failures of the run.
+    pub fn join(&mut self, failures: &RunFailures) {
+        if let Some(thread_handle) = self.thread_handle.take() {
+            match thread_handle.join() {
+                Ok(Ok(())) => {}
+                // A cancelled run refuses the commands of the combination in progress, which
+                // is dropped rather than recorded as a failure.
+                Ok(Err(BenchmarkError::Cancelled)) => warn!(
+                    "worker {} stopped its combination as the run was cancelled",
+                    self.id
+                ),
+                Ok(Err(e)) => failures.record(format!("Worker {} failed: {}", self.id, e)),
+                Err(panic) => failures.record_panic(self.id, panic),
+            }
+        }
+        self.working = false;
+    }
 }
 
 impl WorkerPayload {
@@ -134,6 +373,48 @@ impl WorkerPayload {
         );
         exists_or_create_dir(&path).unwrap()
     }
+
+    /// Records the outcome of a planned encode of the current image in the `status.csv` of the
+    /// current run, which has a row for every planned combination of the run.
+    ///
+    /// # Arguments
+    /// * `commit` - The libjxl commit the image is encoded with.
+    /// * `distance` - The distance of the encode.
+    /// * `quality` - The cjxl quality of the encode in quality mode.
+    /// * `effort` - The effort of the encode.
+    /// * `outcome` - The outcome of the encode.
+    pub fn record_status(
+        &self,
+        commit: &str,
+        distance: f64,
+        quality: Option<f64>,
+        effort: u32,
+        outcome: ComboOutcome,
+    ) {
+        let status_file = format!(
+            "{}/status.csv",
+            WorkerPayload::get_result_dir(&self.context.benchmark_dir, self.context.current_run)
+        );
+        let status = ComboStatus {
+            test_set: self.current_test_set.clone(),
+            commit: commit.to_string(),
+            image_name: self.current_image_name.clone(),
+            distance,
+            quality: quality.map(|q| q.to_string()).unwrap_or_default(),
+            effort,
+            outcome,
+        };
+        // The status is only advisory, so a failed write is reported without losing the encode
+        // or poisoning the sink shared by the workers.
+        let appended = self.csv_sink.lock().unwrap().append(
+            &ComboStatusCSV::new(),
+            &vec![status],
+            &status_file,
+        );
+        if let Err(e) = appended {
+            warn!("Failed to record the status in {}: {}", status_file, e);
+        }
+    }
 }
 
 impl Benchmarker {
@@ -143,22 +424,463 @@ impl Benchmarker {
     /// * `config` - The config for the benchmarker.
     ///
     /// # Returns
-    /// A new Benchmarker.
-    pub fn new(config: &Config) -> Benchmarker {
+    /// A new Benchmarker, or an error if Docker or JXL decoding is unavailable, the benchmark
+    /// directory could not be created or a worker container could not be set up, naming the
+    /// worker.
+    pub fn new(config: &Config) -> Result<Benchmarker, BenchmarkError> {
+        // Make sure Docker can be used before creating anything.
+        DockerManager::preflight_check(&config.docker_bin).map_err(BenchmarkError::Docker)?;
+
+        // The encodes are decoded locally to measure them, which needs libjxl.
+        if !jxl_decode_available() {
+            return Err(BenchmarkError::JxlDecodeUnavailable);
+        }
+
+        // A run without test sets would benchmark nothing, so it fails before anything is
+        // created.
+        let test_sets = Benchmarker::find_test_sets(&config.local_test_image_dir_path)?;
+
+        // Every image the run is restricted to must be in one of the test sets.
+        for image in &config.images {
+            let found = test_sets.iter().any(|test_set| {
+                Benchmarker::get_test_set_image_paths(&format!(
+                    "{}/{}",
+                    config.local_test_image_dir_path, test_set
+                ))
+                .iter()
+                .any(|path| Benchmarker::image_selected(&[image.clone()], path))
+            });
+            if !found {
+                return Err(BenchmarkError::Config(format!(
+                    "The image {} is not in any test set of {}",
+                    image, config.local_test_image_dir_path
+                )));
+            }
+        }
+
+        // Extra cjxl arguments must not override the swept distances and efforts.
+        DockerManager::validate_cjxl_extra_args(&config.cjxl_extra_args)
+            .map_err(|e| BenchmarkError::Config(e.to_string()))?;
+        DockerManager::validate_build_args(&config.docker_build_args)
+            .map_err(|e| BenchmarkError::Config(e.to_string()))?;
+
+        // Comparing to the previous run reuses its results as the baseline, so only the local
+        // build is benchmarked, with the distances and efforts of the previous run.
+        let previous_run = match config.compare_to_previous {
+            true => {
+                if config.compare_to_local
+                    || config.compare_to_commit.is_some()
+                    || !config.diffs.is_empty()
+                {
+                    return Err(BenchmarkError::Config(
+                        "--compare-to-previous cannot be combined with --compare_to_local, --compare_to_commit or local diffs"
+                            .to_string(),
+                    ));
+                }
+                let previous_run =
+                    RunManifest::latest(&config.benchmark_dir_path).ok_or_else(|| {
+                        BenchmarkError::Config(format!(
+                            "No previous run with a manifest found in {}",
+                            config.benchmark_dir_path
+                        ))
+                    })?;
+                if previous_run.efforts.is_empty()
+                    || (previous_run.distances.is_empty() && previous_run.qualities.is_empty())
+                {
+                    return Err(BenchmarkError::Config(format!(
+                        "Run {} does not record its distances and efforts, so it cannot be compared to",
+                        previous_run.run
+                    )));
+                }
+                info!(
+                    "Comparing to run {} with its distances and efforts",
+                    previous_run.run
+                );
+                Some(previous_run)
+            }
+            false => None,
+        };
+
+        // The local commit is the base commit with the local changes applied from their diff,
+        // so without the diff the base commit would silently be benchmarked as local. The hash
+        // of the diff is recorded to tell which changes were benchmarked.
+        let benchmarks_local = config.compare_to_local
+            || previous_run.is_some()
+            || config.libjxl_commit.as_deref() == Some("local")
+            || config.compare_to_commit.as_deref() == Some("local");
+        let local_diff_hash = match benchmarks_local {
+            true => Some(Benchmarker::local_diff_hash(DockerManager::LOCAL_DIFF_PATH)?),
+            false => None,
+        };
+
+        // Distances and qualities are mutually exclusive.
+        let sweep = match &previous_run {
+            Some(previous_run) => Sweep::new(&previous_run.distances, &previous_run.qualities),
+            None => Sweep::new(&config.distances, &config.qualities),
+        }
+        .map_err(BenchmarkError::Config)?;
+        let efforts = match &previous_run {
+            Some(previous_run) => previous_run.efforts.clone(),
+            None => config.efforts.clone(),
+        };
+
+        // Docker needs an absolute path to bind-mount a local libjxl source tree.
+        let libjxl_src = match &config.libjxl_src {
+            Some(libjxl_src) => Some(
+                fs::canonicalize(libjxl_src)
+                    .map_err(|e| {
+                        BenchmarkError::Config(format!(
+                            "Invalid libjxl source path {}: {}",
+                            libjxl_src, e
+                        ))
+                    })?
+                    .to_str()
+                    .unwrap()
+                    .to_string(),
+            ),
+            None => None,
+        };
+
+        // A prebuilt version replaces the checkout and build of the benchmarked commit, and its
+        // binaries are copied into the containers from their absolute paths.
+        let prebuilt_cjxl = match &config.prebuilt_cjxl {
+            Some(prebuilt_cjxl) => {
+                if config.libjxl_commit.is_some()
+                    || config.libjxl_src.is_some()
+                    || config.compare_to_local
+                    || config.compare_to_previous
+                    || !config.diffs.is_empty()
+                {
+                    return Err(BenchmarkError::Config(
+                        "A prebuilt libjxl version cannot be combined with --libjxl_commit, --libjxl-src, --compare_to_local, --compare-to-previous or local diffs"
+                            .to_string(),
+                    ));
+                }
+                prebuilt_cjxl.validate().map_err(BenchmarkError::Config)?;
+                let binary_dir = match &prebuilt_cjxl.binary_dir {
+                    Some(binary_dir) => {
+                        let binary_dir = fs::canonicalize(binary_dir).map_err(|e| {
+                            BenchmarkError::Config(format!(
+                                "Invalid prebuilt binary directory {}: {}",
+                                binary_dir, e
+                            ))
+                        })?;
+                        for tool in ["cjxl", "djxl"] {
+                            if !binary_dir.join(tool).is_file() {
+                                return Err(BenchmarkError::Config(format!(
+                                    "The prebuilt binary directory {} has no {}",
+                                    binary_dir.display(),
+                                    tool
+                                )));
+                            }
+                        }
+                        Some(binary_dir.to_str().unwrap().to_string())
+                    }
+                    None => None,
+                };
+                Some(PrebuiltCjxl {
+                    version: prebuilt_cjxl.version.clone(),
+                    binary_dir,
+                })
+            }
+            None => None,
+        };
+
+        // Local diffs replace the commit comparison and are copied into the containers from
+        // their absolute paths. Their labels name the output directories, so they must be
+        // distinct and must not be taken for a commit label.
+        if !config.diffs.is_empty()
+            && (config.compare_to_local
+                || config.compare_to_commit.is_some()
+                || config.libjxl_src.is_some())
+        {
+            return Err(BenchmarkError::Config(
+                "Local diffs cannot be combined with --compare_to_local, --compare_to_commit or --libjxl-src"
+                    .to_string(),
+            ));
+        }
+        let mut diffs = Vec::<LocalDiff>::new();
+        for diff in &config.diffs {
+            if diff.name == "local"
+                || diff.name == LOCAL_SRC_COMMIT
+                || diffs.iter().any(|other| other.name == diff.name)
+            {
+                return Err(BenchmarkError::Config(format!(
+                    "Invalid local diff name {}, names must be distinct and not local or {}",
+                    diff.name, LOCAL_SRC_COMMIT
+                )));
+            }
+            let path = fs::canonicalize(&diff.path).map_err(|e| {
+                BenchmarkError::Config(format!("Invalid local diff path {}: {}", diff.path, e))
+            })?;
+            diffs.push(LocalDiff {
+                name: diff.name.clone(),
+                path: path.to_str().unwrap().to_string(),
+            });
+        }
+
+        // Toolchains label the output directories next to the commit and name their images and
+        // containers, so their names must be distinct tags. A mounted local source tree is built
+        // once in the container of the first worker, so it cannot be built with several
+        // toolchains.
+        if !config.toolchains.is_empty() && config.libjxl_src.is_some() {
+            return Err(BenchmarkError::Config(
+                "Toolchains cannot be combined with --libjxl-src".to_string(),
+            ));
+        }
+        for (i, toolchain) in config.toolchains.iter().enumerate() {
+            if config.toolchains[..i]
+                .iter()
+                .any(|other| other.name == toolchain.name)
+                || !toolchain
+                    .name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || ".-_".contains(c))
+            {
+                return Err(BenchmarkError::Config(format!(
+                    "Invalid toolchain name {}, names must be distinct and only contain letters, digits, dots, dashes and underscores",
+                    toolchain.name
+                )));
+            }
+            if !Path::new(&toolchain.dockerfile).is_file() {
+                return Err(BenchmarkError::Config(format!(
+                    "The Dockerfile {} of toolchain {} does not exist",
+                    toolchain.dockerfile, toolchain.name
+                )));
+            }
+        }
+
+        // Build configurations label the output directories like toolchains. They rebuild libjxl
+        // before each image, which a mounted local source tree shared by all workers and a
+        // prebuilt version are not.
+        if !config.build_configs.is_empty()
+            && (config.libjxl_src.is_some() || config.prebuilt_cjxl.is_some())
+        {
+            return Err(BenchmarkError::Config(
+                "Build configurations cannot be combined with --libjxl-src or a prebuilt cjxl"
+                    .to_string(),
+            ));
+        }
+        for (i, build_config) in config.build_configs.iter().enumerate() {
+            if config.build_configs[..i]
+                .iter()
+                .any(|other| other.name == build_config.name)
+                || !build_config
+                    .name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || ".-_".contains(c))
+            {
+                return Err(BenchmarkError::Config(format!(
+                    "Invalid build configuration name {}, names must be distinct and only contain letters, digits, dots, dashes and underscores",
+                    build_config.name
+                )));
+            }
+        }
+
+        // The master directory must exist, it is canonicalized for the workers.
+        let master_dir = match &config.master_dir {
+            Some(master_dir) => Some(
+                fs::canonicalize(master_dir)
+                    .map_err(|e| {
+                        BenchmarkError::Config(format!(
+                            "Invalid master directory {}: {}",
+                            master_dir, e
+                        ))
+                    })?
+                    .to_str()
+                    .unwrap()
+                    .to_string(),
+            ),
+            None => None,
+        };
+
+        // The delimiter must be a single ASCII character that cannot occur in quoted fields.
+        if !config.csv_delimiter.is_ascii() || b"\"\r\n".contains(&config.csv_delimiter) {
+            return Err(BenchmarkError::Config(format!(
+                "Invalid CSV delimiter {:?}, expected an ASCII character other than a quote or line break",
+                config.csv_delimiter as char
+            )));
+        }
+
+        // The metrics of an image need at least one thread.
+        if config.metric_threads == 0 {
+            return Err(BenchmarkError::Config(
+                "The number of metric threads must be positive".to_string(),
+            ));
+        }
+
+        // At least one combination must be sampled per image.
+        if config.grid_sample.map(|grid_sample| grid_sample.count) == Some(0) {
+            return Err(BenchmarkError::Config(
+                "The number of sampled combinations must be positive".to_string(),
+            ));
+        }
+
+        // The expensive metrics must be sampled at a positive effort step, and at distances
+        // only when distances are swept.
+        if let Some(expensive_metric_sample) = &config.expensive_metric_sample {
+            if expensive_metric_sample.effort_step == 0 {
+                return Err(BenchmarkError::Config(
+                    "The effort step of the expensive metrics must be positive".to_string(),
+                ));
+            }
+            if !expensive_metric_sample.distances.is_empty() && !config.qualities.is_empty() {
+                return Err(BenchmarkError::Config(
+                    "The expensive metrics can only be sampled at distances when sweeping distances"
+                        .to_string(),
+                ));
+            }
+        }
+
+        // Only compressed images kept on disk can be stored by their content.
+        if config.content_addressed && !config.keep_compressed {
+            return Err(BenchmarkError::Config(
+                "Content-addressed storage needs the compressed images to be kept".to_string(),
+            ));
+        }
+
+        // The effort curve repeats the quality target search at every effort.
+        if config.effort_curve && config.quality_target.is_none() {
+            return Err(BenchmarkError::Config(
+                "The effort curve needs a quality target".to_string(),
+            ));
+        }
+
+        // A size target replaces the sweep like a quality target, so only one can be searched.
+        if let Some(size_target) = &config.size_target {
+            if config.quality_target.is_some() {
+                return Err(BenchmarkError::Config(
+                    "A size target cannot be combined with a quality target".to_string(),
+                ));
+            }
+            if size_target.target_bytes == 0 || size_target.max_iterations == 0 {
+                return Err(BenchmarkError::Config(
+                    "A size target needs a positive size and number of iterations".to_string(),
+                ));
+            }
+        }
+
+        // Progressive decodes truncate the bitstream to a percentage of its size.
+        if let Some(truncation) = config
+            .progressive_truncations
+            .iter()
+            .find(|truncation| !(**truncation > 0.0 && **truncation <= 100.0))
+        {
+            return Err(BenchmarkError::Config(format!(
+                "Progressive truncations must be percentages above 0 and up to 100, got {}",
+                truncation
+            )));
+        }
+
+        // The decode speed is measured over at least one timed decode.
+        if config.decode_speed && config.decode_repeats == 0 {
+            return Err(BenchmarkError::Config(
+                "The number of timed decodes must be positive".to_string(),
+            ));
+        }
+
+        // The native SSIM window must be odd and positive if Gaussian.
+        let ssim_window = SsimWindow::new(config.ssim_window_size, config.ssim_sigma)
+            .map_err(BenchmarkError::Config)?;
+
         // Create the context for the benchmarker out of the config.
         let c = Context {
-            benchmark_dir: exists_or_create_dir(&config.benchmark_dir_path).unwrap(),
-            test_sets: Benchmarker::get_all_test_set_names(
-                config.local_test_image_dir_path.clone(),
-            ),
+            benchmark_dir: exists_or_create_dir(&config.benchmark_dir_path)
+                .map_err(|e| BenchmarkError::Directory(config.benchmark_dir_path.clone(), e))?,
+            test_sets,
             current_run: Benchmarker::get_current_run(config.benchmark_dir_path.clone()),
             local_test_image_dir: config.local_test_image_dir_path.clone(),
+            images: config.images.clone(),
             docker_test_image_dir: config.docker_test_image_dir_path.clone(),
             num_workers: config.num_workers,
             use_temp_dir: config.use_temp_dir,
-            libjxl_commit: config.libjxl_commit.clone(),
+            libjxl_commit: match &previous_run {
+                Some(_) => Some("local".to_string()),
+                None => config.libjxl_commit.clone(),
+            },
             compare_to_local: config.compare_to_local,
             compare_to_commit: config.compare_to_commit.clone(),
+            previous_run,
+            local_diff_hash,
+            max_dimension: config.max_dimension,
+            modular: config.modular,
+            size_regression_policy: config.size_regression_policy,
+            metric_validation: config.metric_validation,
+            use_encode_cache: config.use_encode_cache,
+            resource_metrics: config.resource_metrics,
+            tidy_output: config.tidy_output,
+            normalized_scores: config.normalized_scores,
+            score_weights: config.score_weights.clone(),
+            output_format: config.output_format,
+            roi: config.roi,
+            keep_compressed: config.keep_compressed,
+            keep_originals: config.keep_originals,
+            content_addressed: config.content_addressed,
+            cjxl_extra_args: config.cjxl_extra_args.clone(),
+            libjxl_src,
+            prebuilt_cjxl,
+            deterministic: config.deterministic,
+            quality_target: config.quality_target,
+            size_target: config.size_target,
+            grid_sample: config.grid_sample,
+            expensive_metric_sample: config.expensive_metric_sample.clone(),
+            master_dir,
+            reference_cache_dir: config.reference_cache_dir.clone(),
+            gates: config.gates.clone(),
+            baseline_distance: config.baseline_distance,
+            baseline_csv: config.baseline_csv.clone(),
+            baseline_codec: config.baseline_codec.clone(),
+            intensity_target: config.intensity_target,
+            diff_images: config.diff_images,
+            fail_fast: config.fail_fast,
+            error_histograms: config.error_histograms,
+            channel_space: config.channel_space,
+            color_management: config.color_management,
+            size_ratio: config.size_ratio,
+            efforts,
+            sweep,
+            reuse_containers: config.reuse_containers,
+            docker_bin: config.docker_bin.clone(),
+            external_metrics: config.external_metrics.clone(),
+            contact_sheets: config.contact_sheets,
+            html_report: config.html_report,
+            decode_speed: config.decode_speed,
+            decode_repeats: config.decode_repeats,
+            decode_warmup: config.decode_warmup,
+            progressive_truncations: config.progressive_truncations.clone(),
+            max_concurrent_decodes: config.max_concurrent_decodes,
+            image_limits: ImageLimits::new(config.max_pixels, config.max_decode_bytes),
+            metric_threads: config.metric_threads,
+            diffs,
+            toolchains: config.toolchains.clone(),
+            build_configs: config.build_configs.clone(),
+            ssim_window,
+            csv_float_digits: config.csv_float_digits,
+            csv_delimiter: config.csv_delimiter,
+            csv_quoting: config.csv_quoting,
+            summary_weighting: config.summary_weighting,
+            duplicate_policy: config.duplicate_policy,
+            record_environment: config.record_environment,
+            cross_check_decode: config.cross_check_decode,
+            effort_curve: config.effort_curve,
+            effort_range: None,
+        };
+
+        set_csv_format(c.csv_delimiter, c.csv_quoting);
+        let csv_float_digits = c.csv_float_digits;
+
+        // Load the encode cache index from prior runs if enabled.
+        let encode_cache = match c.use_encode_cache {
+            true => EncodeCache::load(&c.benchmark_dir)
+                .map_err(|e| BenchmarkError::EncodeCache(e.to_string()))?,
+            false => EncodeCache::default(),
+        };
+
+        // The results database records the comparisons at full precision rather than reading
+        // back their rounded CSV values, so the sink keeps them.
+        let csv_sink = match config.output_format {
+            OutputFormat::Sqlite => CsvSink::new(csv_float_digits).with_kept_comparisons(),
+            _ => CsvSink::new(csv_float_digits),
         };
 
         // Create a new Benchmarker with the given context.
@@ -166,10 +888,15 @@ impl Benchmarker {
             context: c,
             workers: Vec::new(),
             current_worker_id: 0,
+            encode_cache: Arc::new(Mutex::new(encode_cache)),
+            failures: Arc::new(RunFailures::new(config.fail_fast)),
+            cancellation: CancellationToken::new(),
+            csv_sink: Arc::new(Mutex::new(csv_sink)),
+            decode_limiter: Arc::new(DecodeLimiter::new(config.max_concurrent_decodes)),
+            run_timing: Arc::new(RunTiming::new()),
         };
 
         // Create workers for the benchmarker.
-        let config = Config::default();
         for x in 0..b.context.num_workers {
             // Initialize an empty payload for each worker.
             let payload = WorkerPayload {
@@ -183,20 +910,65 @@ impl Benchmarker {
                 current_image_file_path: "".to_string(),
                 current_image_format: ImageFormat::Unsupported,
                 current_test_set: "".to_string(),
+                encode_cache: b.encode_cache.clone(),
+                failures: b.failures.clone(),
+                cancellation: b.cancellation.clone(),
+                csv_sink: b.csv_sink.clone(),
+                decode_limiter: b.decode_limiter.clone(),
+                run_timing: b.run_timing.clone(),
             };
 
             // Create a new worker with the given worker index as id and payload.
             let mut worker = BenchmarkWorker::new(x, &payload);
 
-            // Create and setup a new DockerManager for the worker.
-            let mut docker_manager = DockerManager::new(&config.docker_file_path, x);
-            let _ = docker_manager.setup(worker.id).unwrap();
-            worker.docker_manager = Some(docker_manager);
+            // Create and setup a new DockerManager for the worker, or one per toolchain, the
+            // first of which is used until the worker switches toolchains.
+            let docker_managers = match b.context.toolchains.is_empty() {
+                true => vec![DockerManager::new(&config.docker_file_path, x)],
+                false => b
+                    .context
+                    .toolchains
+                    .iter()
+                    .map(|toolchain| {
+                        DockerManager::for_toolchain(&toolchain.dockerfile, x, &toolchain.name)
+                    })
+                    .collect(),
+            };
+            for mut docker_manager in docker_managers {
+                docker_manager.reuse_containers = b.context.reuse_containers;
+                docker_manager.docker_bin = b.context.docker_bin.clone();
+                docker_manager.build_args = config.docker_build_args.clone();
+                docker_manager.context_dir = config.docker_context_dir.clone();
+                docker_manager.cancellation = b.cancellation.clone();
+                let setup = b.run_timing.time(Phase::Build, || {
+                    docker_manager.setup(worker.id, b.context.libjxl_src.as_deref())
+                });
+                if let Err(e) = setup {
+                    // Tear down the containers that are already set up, and whatever the failed
+                    // setup left behind, so that they do not outlive the failed run.
+                    if let Err(e) = docker_manager.teardown() {
+                        debug!(
+                            "Failed to tear down the container of worker {}: {}",
+                            worker.id, e
+                        );
+                    }
+                    b.workers.push(worker);
+                    b.teardown_workers();
+                    return Err(BenchmarkError::WorkerSetup {
+                        worker_id: x,
+                        message: e.to_string(),
+                    });
+                }
+                match worker.docker_manager {
+                    None => worker.docker_manager = Some(docker_manager),
+                    Some(_) => worker.toolchain_managers.push(docker_manager),
+                }
+            }
 
             // Add the worker to the benchmarker.
             b.workers.push(worker);
         }
-        b
+        Ok(b)
     }
 
     /// Gets the next worker id.
@@ -301,6 +1073,52 @@ impl Benchmarker {
         current_run
     }
 
+    /// Reserves the number of a new run by creating its run directory.
+    /// Creating the directory fails if it already exists, so concurrent invocations sharing a
+    /// benchmark directory never reserve the same number. The next number is tried until a
+    /// directory could be created.
+    ///
+    /// # Arguments
+    /// * `benchmark_dir` - The benchmark directory.
+    ///
+    /// # Returns
+    /// The reserved run number, or an error if the run directory could not be created.
+    pub fn reserve_run(benchmark_dir: &str) -> Result<usize, std::io::Error> {
+        fs::create_dir_all(benchmark_dir)?;
+        let mut run = Benchmarker::get_current_run(benchmark_dir.to_string());
+        loop {
+            match fs::create_dir(PathBuf::from(benchmark_dir).join(run.to_string())) {
+                Ok(()) => return Ok(run),
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => run += 1,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Gets the test set names in the local test image directory, see
+    /// `get_all_test_set_names`, and fails if there are none.
+    ///
+    /// # Arguments
+    /// * `local_test_image_dir` - The local test image directory.
+    ///
+    /// # Returns
+    /// The test set names, or an error telling how to add test sets if the directory is missing
+    /// or has no test set directories.
+    pub fn find_test_sets(local_test_image_dir: &str) -> Result<Vec<String>, BenchmarkError> {
+        let test_sets = Benchmarker::get_all_test_set_names(local_test_image_dir.to_string());
+        if test_sets.is_empty() {
+            let problem = match Path::new(local_test_image_dir).is_dir() {
+                true => "has no test sets",
+                false => "does not exist",
+            };
+            return Err(BenchmarkError::Config(format!(
+                "The test image directory {} {}. Add a directory of images for each test set to it, or fetch a dataset into it with `fetch kodak`",
+                local_test_image_dir, problem
+            )));
+        }
+        Ok(test_sets)
+    }
+
     /// Gets all the test set names in the local test image directory.
     /// The test set names are the names of the directories in the local test image directory.
     ///
@@ -327,29 +1145,338 @@ impl Benchmarker {
             }
             test_sets.push(test_set_dir_name.to_string());
         }
+
+        // Directory entries are returned in arbitrary order, so sort them for stable runs.
+        test_sets.sort();
         test_sets
     }
 
+    /// Gets the paths of the supported images in a test set directory, sorted by file name so
+    /// that images are processed in the same order on every run and platform.
+    ///
+    /// # Arguments
+    /// * `local_test_set_path` - The local test set directory.
+    ///
+    /// # Returns
+    /// A vector of image paths.
+    pub fn get_test_set_image_paths(local_test_set_path: &str) -> Vec<PathBuf> {
+        let mut image_paths = Vec::new();
+        for entry in fs::read_dir(local_test_set_path).unwrap() {
+            let path = entry.unwrap().path();
+
+            // Check if entry is a supported image file.
+            // Get extension of the file and check if it is supported.
+            if path.is_dir() {
+                continue;
+            }
+            match ImageFormat::from_file_name(path.to_str().unwrap()) {
+                ImageFormat::Unsupported => continue,
+                _ => {}
+            }
+            image_paths.push(path);
+        }
+        image_paths.sort_by(|a, b| a.file_name().cmp(&b.file_name()));
+        image_paths
+    }
+
     /// Waits for the next worker to be available to run a benchmark and returns a mutable
     /// reference to the worker.
     ///
     /// # Returns
-    /// A mutable reference to the next available worker.
-    pub fn wait_for_available_worker(&mut self) -> &mut BenchmarkWorker {
+    /// A mutable reference to the next available worker, or an error if no worker has a
+    /// running container left.
+    pub fn wait_for_available_worker(&mut self) -> Result<&mut BenchmarkWorker, BenchmarkError> {
         let id = self.get_next_worker_id();
+        self.wait_for_worker(id)
+    }
+
+    /// Waits for the worker with the given id to finish its current work.
+    /// Before the worker is handed new work, its container is restarted if it died. A worker
+    /// whose container cannot be restarted is retired, and the next live worker is waited for
+    /// instead.
+    ///
+    /// # Arguments
+    /// * `id` - The id of the worker.
+    ///
+    /// # Returns
+    /// A mutable reference to the worker, or to the next live worker if it is retired, or an
+    /// error if every worker is retired.
+    pub fn wait_for_worker(&mut self, id: usize) -> Result<&mut BenchmarkWorker, BenchmarkError> {
+        let num_workers = self.workers.len();
+        for offset in 0..num_workers {
+            let candidate = (id + offset) % num_workers;
+            if self.prepare_worker(candidate) {
+                return Ok(&mut self.workers[candidate]);
+            }
+        }
+        Err(BenchmarkError::WorkerSetup {
+            worker_id: id,
+            message: "no worker has a running container left".to_string(),
+        })
+    }
+
+    /// Waits for a worker to finish its current work and makes sure its containers are running.
+    ///
+    /// # Arguments
+    /// * `id` - The id of the worker.
+    ///
+    /// # Returns
+    /// Whether the worker can be handed new work, false if it is retired.
+    fn prepare_worker(&mut self, id: usize) -> bool {
         let worker = &mut self.workers[id] as &mut BenchmarkWorker;
+        if worker.retired {
+            return false;
+        }
 
         // If the worker is working, wait for it to finish.
         if worker.working {
-            if let Some(thread_handle) = worker.thread_handle.take() {
-                let _ = thread_handle.join();
-                worker.thread_handle = None;
-                worker.working = false;
-            } else {
+            if worker.thread_handle.is_none() {
                 panic!("Working worker thread handle is None");
             }
+            worker.join(&self.failures);
+        }
+
+        // A container that could not be restarted is recorded like any other failure, and its
+        // worker is not handed work again.
+        if let Err(e) = worker.ensure_container_running(self.context.libjxl_src.as_deref()) {
+            self.failures.record(format!(
+                "Could not restart the container of worker {}, retiring it: {}",
+                id, e
+            ));
+            worker.retired = true;
+            return false;
+        }
+        true
+    }
+
+    /// Plans which worker each image of a test set is assigned to in deterministic mode.
+    /// Each image is a work unit covering every distance and effort combination, so images are
+    /// assigned by a stable hash of their name and planned in sorted order.
+    ///
+    /// # Arguments
+    /// * `image_paths` - The paths of the images in the test set.
+    /// * `num_workers` - The number of workers.
+    ///
+    /// # Returns
+    /// The image paths in processing order, each with the id of the worker assigned to it.
+    pub fn plan_assignments(image_paths: &Vec<PathBuf>, num_workers: usize) -> Vec<(PathBuf, usize)> {
+        let mut image_paths = image_paths.clone();
+        image_paths.sort();
+        image_paths
+            .into_iter()
+            .map(|path| {
+                let image_name = path.file_name().unwrap().to_str().unwrap().to_string();
+                let worker_id = (stable_hash(&image_name) % num_workers as u64) as usize;
+                (path, worker_id)
+            })
+            .collect()
+    }
+
+    /// Gets the name an image is benchmarked under, its file name up to the first dot.
+    ///
+    /// # Arguments
+    /// * `path` - The path of the image.
+    ///
+    /// # Returns
+    /// The name of the image, which the compressed image names are based on.
+    pub fn image_name(path: &Path) -> String {
+        let file_name = path.file_name().unwrap().to_str().unwrap();
+        file_name.split('.').collect::<Vec<&str>>()[0].to_string()
+    }
+
+    /// Hashes the diff of the local libjxl changes that are benchmarked as the `local` commit.
+    ///
+    /// # Arguments
+    /// * `diff_path` - The path to the diff.
+    ///
+    /// # Returns
+    /// The SHA-256 hash of the diff, or an error explaining how to create the diff if it does
+    /// not exist.
+    pub fn local_diff_hash(diff_path: &str) -> Result<String, BenchmarkError> {
+        if !Path::new(diff_path).is_file() {
+            return Err(BenchmarkError::Config(format!(
+                "The local libjxl changes are applied from {}, which was not found in {}. Write them with `git diff > local.diff` in the libjxl checkout and copy the diff there",
+                diff_path,
+                std::env::current_dir()
+                    .map(|dir| dir.display().to_string())
+                    .unwrap_or_default()
+            )));
+        }
+        hash_file(diff_path)
+            .map_err(|e| BenchmarkError::Config(format!("Could not read {}: {}", diff_path, e)))
+    }
+
+    /// Checks whether an image is benchmarked when the run is restricted to some images.
+    ///
+    /// # Arguments
+    /// * `images` - The names of the images the run is restricted to, with or without their
+    ///   extension, or empty to benchmark every image.
+    /// * `path` - The path of the image.
+    ///
+    /// # Returns
+    /// Whether the image is benchmarked.
+    pub fn image_selected(images: &[String], path: &Path) -> bool {
+        let file_name = path.file_name().unwrap().to_str().unwrap();
+        let image_name = Benchmarker::image_name(path);
+        images.is_empty()
+            || images
+                .iter()
+                .any(|image| image == file_name || *image == image_name)
+    }
+
+    /// Maps the path of a test image to its path in the container, keeping its test set and
+    /// file name, which may contain spaces or non-ASCII characters.
+    ///
+    /// # Arguments
+    /// * `local_test_image_dir` - The local directory of the test sets.
+    /// * `docker_test_image_dir` - The directory of the test sets in the container.
+    /// * `image_path` - The local path of the image.
+    ///
+    /// # Returns
+    /// The path of the image in the container.
+    pub fn container_image_path(
+        local_test_image_dir: &str,
+        docker_test_image_dir: &str,
+        image_path: &str,
+    ) -> String {
+        let relative_path = Path::new(image_path)
+            .strip_prefix(local_test_image_dir)
+            .unwrap_or_else(|_| Path::new(Path::new(image_path).file_name().unwrap()));
+        Path::new(docker_test_image_dir)
+            .join(relative_path)
+            .to_str()
+            .unwrap()
+            .to_string()
+    }
+
+    /// Prepares the libjxl of a worker container for encoding with a commit: the commit is
+    /// checked out, a local diff or the local changes are applied on top of it, and libjxl is
+    /// rebuilt. A prebuilt version is installed instead of being built. A mounted local source
+    /// tree is never cleaned, checked out or rebuilt here, since it is the user's checkout and is
+    /// built once before the images are dispatched.
+    ///
+    /// # Arguments
+    /// * `docker_manager` - The docker manager of the worker.
+    /// * `commit` - The label of the commit, local diff or prebuilt version to encode with.
+    /// * `diffs` - The local diffs of the run.
+    /// * `diff_base_commit` - The commit the local diffs are applied on top of.
+    /// * `prebuilt_cjxl` - The prebuilt version of the run, if any.
+    ///
+    /// # Returns
+    /// An error if a step failed, or a Cancelled error if the run was cancelled before a step.
+    pub fn prepare_libjxl(
+        docker_manager: &DockerManager,
+        commit: &str,
+        diffs: &[LocalDiff],
+        diff_base_commit: &str,
+        prebuilt_cjxl: Option<&PrebuiltCjxl>,
+    ) -> Result<(), Box<dyn Error>> {
+        if commit == LOCAL_SRC_COMMIT {
+            return Ok(());
+        }
+
+        // Each step is only started if the run was not cancelled in the meantime.
+        docker_manager.cancellation.check()?;
+
+        // A prebuilt version replaces the tools without touching the libjxl checkout.
+        if let Some(prebuilt_cjxl) = prebuilt_cjxl.filter(|prebuilt| prebuilt.version == commit) {
+            return docker_manager.install_prebuilt_cjxl(
+                &prebuilt_cjxl.version,
+                prebuilt_cjxl.binary_dir.as_deref(),
+            );
+        }
+
+        // Clean the libjxl branch on the docker manager of the worker.
+        docker_manager.clean_libjxl()?;
+
+        // Set the current commit of libjxl on the docker manager.
+        docker_manager.cancellation.check()?;
+        match diffs.iter().find(|diff| diff.name == commit) {
+            // Apply the local diff on top of the commit on the worker container.
+            Some(diff) => {
+                docker_manager.apply_local_diff(&diff.path, &diff.name, diff_base_commit)?;
+            }
+            // Apply a diff of the local changes to libjxl on the worker container.
+            None if commit == "local" => {
+                docker_manager.apply_local_as_diff(DockerManager::LOCAL_DIFF_PATH)?;
+            }
+            // Set the current commit of libjxl on the worker container.
+            None => {
+                docker_manager.change_libjxl_commit(commit)?;
+            }
+        }
+
+        // Re-build libjxl on the docker manager of the worker.
+        docker_manager.cancellation.check()?;
+        docker_manager.build_libjxl()?;
+        Ok(())
+    }
+
+    /// Copies a source image into the original output directory, so that the run keeps its
+    /// originals when the test image directory changes or is removed.
+    /// A downscaled or converted reference written under the same name is not overwritten, the
+    /// source is copied into the `source` subdirectory instead.
+    ///
+    /// # Arguments
+    /// * `image_path` - The local path of the source image.
+    /// * `out_orig_path` - The original output path.
+    /// * `reference_path` - The path of the downscaled or converted reference, if any.
+    ///
+    /// # Returns
+    /// The path of the copy, or an error if the image could not be copied.
+    pub fn keep_original(
+        image_path: &str,
+        out_orig_path: &str,
+        reference_path: Option<&str>,
+    ) -> Result<String, Box<dyn Error>> {
+        let file_name = Path::new(image_path).file_name().unwrap();
+        let mut copy_path = Path::new(out_orig_path).join(file_name);
+        if reference_path.map_or(false, |path| Path::new(path) == copy_path) {
+            copy_path = Path::new(out_orig_path).join("source").join(file_name);
+        }
+        fs::create_dir_all(copy_path.parent().unwrap())?;
+        fs::copy(image_path, &copy_path)?;
+        Ok(copy_path.to_str().unwrap().to_string())
+    }
+
+    /// Plans the compressed image names of a test set and checks that no two encodes would
+    /// write the same file, e.g. for images whose names only differ in their extension or
+    /// distances that are configured twice, which would otherwise overwrite results silently.
+    ///
+    /// # Arguments
+    /// * `image_paths` - The paths of the images in the test set.
+    /// * `encodes` - The distances of the sweep and, in quality mode, their qualities.
+    /// * `efforts` - The efforts of the sweep.
+    ///
+    /// # Returns
+    /// An error naming the two colliding encodes and the file they would both write.
+    pub fn check_output_collisions(
+        image_paths: &[PathBuf],
+        encodes: &[(f64, Option<f64>)],
+        efforts: &[u32],
+    ) -> Result<(), String> {
+        let mut planned = HashMap::<String, String>::new();
+        for path in image_paths {
+            let image_name = Benchmarker::image_name(path);
+            for (distance, quality) in encodes {
+                for effort in efforts {
+                    let comp_image_name =
+                        ImageReader::compressed_image_name(&image_name, *distance, *effort);
+                    let encode = match quality {
+                        Some(quality) => format!("{} at quality {}", path.display(), quality),
+                        None => format!("{} at distance {}", path.display(), distance),
+                    };
+                    let encode = format!("{} and effort {}", encode, effort);
+                    if let Some(other) = planned.insert(comp_image_name.clone(), encode.clone()) {
+                        return Err(format!(
+                            "{} and {} would both be written to {}",
+                            other, encode, comp_image_name
+                        ));
+                    }
+                }
+            }
         }
-        worker
+        Ok(())
     }
 
     /// Gets the current worker and returns a mutable reference to it.
@@ -363,15 +1490,10 @@ impl Benchmarker {
 
     /// Waits on the current thread for all workers to finish working.
     pub fn wait_for_all_workers(&mut self) {
-        
         // Wait for all workers to finish working and join their threads.
         for worker in &mut self.workers {
-            if let Some(thread_handle) = worker.thread_handle.take() {
-                let _ = thread_handle.join();
-                worker.thread_handle = None;
-                worker.working = false;
-            } else {
-                continue;
+            if worker.thread_handle.is_some() {
+                worker.join(&self.failures);
             }
         }
 
@@ -388,143 +1510,389 @@ impl Benchmarker {
     ///
     /// # Arguments
     /// * `T` - The benchmark to run.
-    pub fn run_benchmark<T: Benchmark + 'static>(&mut self) {
-        // Set the current run of the context.
-        self.context.current_run = Benchmarker::get_current_run(self.context.benchmark_dir.clone());
-
-        // Get the libjxl commit for the benchmark or use the default commit (main).
-        let libjxl_commit = self.context.libjxl_commit.clone();
-        let mut commit = match libjxl_commit {
-            Some(commit) => Some(commit),
-            None => Some(DEFAULT_LIBJXL_COMMIT.to_string()),
-        };
+    ///
+    /// # Returns
+    /// An error with the first failure if the run was stopped in fail-fast mode. Otherwise
+    /// failures are reported at the end of the run.
+    pub fn run_benchmark<T: Benchmark + 'static>(&mut self) -> Result<(), Box<dyn Error>> {
+        // Check that no two encodes write the same output before anything is run.
+        for test_set in &self.context.test_sets {
+            let image_paths = Benchmarker::get_test_set_image_paths(&format!(
+                "{}/{}",
+                self.context.local_test_image_dir, test_set
+            ));
+            Benchmarker::check_output_collisions(
+                &image_paths,
+                &self.context.sweep.encodes(),
+                &self.context.efforts,
+            )
+            .map_err(BenchmarkError::Config)?;
+        }
+
+        // Reserve the current run of the context, so concurrent invocations do not share it.
+        self.context.current_run = Benchmarker::reserve_run(&self.context.benchmark_dir)?;
+
+        // Get the libjxl commit for the benchmark or use the default commit.
+        // The default commit is resolved to a SHA so that runs are reproducible.
+        // A mounted local source tree is labelled as "local-src" instead, and a prebuilt
+        // version by its version.
+        let libjxl_commit = self.context.libjxl_commit.clone();
+        let mut commit = match (
+            &self.context.libjxl_src,
+            &self.context.prebuilt_cjxl,
+            libjxl_commit,
+        ) {
+            (Some(_), _, _) => Some(LOCAL_SRC_COMMIT.to_string()),
+            (None, Some(prebuilt_cjxl), _) => Some(prebuilt_cjxl.version.clone()),
+            (None, None, Some(commit)) => Some(commit),
+            (None, None, None) => match self.resolve_default_commit() {
+                Ok(sha) => Some(sha),
+                Err(e) => {
+                    return Err(Box::new(BenchmarkError::WorkerSetup {
+                        worker_id: self.workers[0].id,
+                        message: format!(
+                            "the default libjxl commit {} could not be resolved: {}",
+                            DEFAULT_LIBJXL_COMMIT, e
+                        ),
+                    }))
+                }
+            },
+        };
+
+        // Local diffs are applied on top of the commit and benchmarked in turn, labelled by
+        // their names instead of the commit.
+        let diff_base_commit = commit.clone().unwrap();
+        if let Some(diff) = self.context.diffs.first() {
+            commit = Some(diff.name.clone());
+        }
+
+        // Record the settings of the run in its manifest.
+        let mut manifest = RunManifest::new(&self.context);
+        if self.context.record_environment {
+            manifest.record_environment(self.workers[0].docker_manager.as_ref().unwrap());
+        }
+        manifest.save(&self.context.benchmark_dir).unwrap();
 
         // Initialize the benchmark comparison CSVs vector.
         let mut comparison_csvs = Vec::<String>::new();
 
+        // The report of the run, if enabled.
+        let mut report = match self.context.html_report {
+            true => Some(HtmlReport::with_weighting(self.context.summary_weighting)),
+            false => None,
+        };
+
+        // Failures are shared with the workers, which stop early in fail-fast mode.
+        let failures = self.failures.clone();
+
+        // The gates violated by the comparisons, which fail the run once it is finished.
+        let mut gate_failures = Vec::<String>::new();
+
+        // The toolchains each commit is benchmarked with, None for the default Dockerfile.
+        let toolchains = match self.context.toolchains.is_empty() {
+            true => vec![None],
+            false => self
+                .context
+                .toolchains
+                .iter()
+                .map(|toolchain| Some(toolchain.name.clone()))
+                .collect::<Vec<Option<String>>>(),
+        };
+
+        // The build configurations each toolchain builds the commit with, None for the default
+        // flags.
+        let build_configs = match self.context.build_configs.is_empty() {
+            true => vec![None],
+            false => self
+                .context
+                .build_configs
+                .iter()
+                .cloned()
+                .map(Some)
+                .collect::<Vec<Option<BuildConfig>>>(),
+        };
+        let variants = toolchains
+            .iter()
+            .flat_map(|toolchain| {
+                build_configs
+                    .iter()
+                    .map(move |build_config| (toolchain, build_config))
+            })
+            .collect::<Vec<(&Option<String>, &Option<BuildConfig>)>>();
+
         // Run the benchmark for each test set.
         let test_sets = self.context.test_sets.clone();
         for test_set in &test_sets {
             // Make sure test_set is a directory.
             let local_test_set_path = dir_exists(format!("{}/{}", self.context.local_test_image_dir, test_set).as_str()).unwrap();
 
-            // Run the benchmark for each commit in the case of a comparison.
-            while commit.is_some() {
-                // Set up output and result paths.
-                let out_orig_path = self.get_output_path_for(
-                    format!("orig/{}/{}", test_set, commit.clone().unwrap()).as_str(),
-                );
-                let out_comp_path = self.get_output_path_for(
-                    format!("comp/{}/{}", test_set, commit.clone().unwrap()).as_str(),
-                );
-                let res_orig_path = self.get_result_path_for(
-                    format!("orig/{}/{}", test_set, commit.clone().unwrap()).as_str(),
-                );
-                let res_comp_path = self.get_result_path_for(
-                    format!("comp/{}/{}", test_set, commit.clone().unwrap()).as_str(),
-                );
-
-                // Create a context for the worker payload.
-                // Set the libjxl commit for the worker payload context.
-                let mut context = self.context.clone();
-                context.libjxl_commit = Some(commit.clone().unwrap().to_string());
+            // Remember where the comparison CSVs of this test set start.
+            let test_set_csvs_start = comparison_csvs.len();
 
-                // Iterate over the images in the local test set path.
-                for entry in fs::read_dir(local_test_set_path.clone()).unwrap() {
-                    let entry = entry;
-
-                    // Check if entry is a supported image file.
-                    // Get extension of the file and check if it is supported.
-                    if entry.as_ref().unwrap().path().is_dir() {
+            // Skip the images that exceed the image limits before anything decodes them, and
+            // record them in the manifest.
+            let mut skipped_images = Vec::<PathBuf>::new();
+            if !self.context.image_limits.is_unlimited() {
+                for path in Benchmarker::get_test_set_image_paths(&local_test_set_path) {
+                    if !Benchmarker::image_selected(&self.context.images, &path) {
                         continue;
                     }
-                    match ImageFormat::from_file_name(
-                        entry.as_ref().unwrap().path().to_str().unwrap(),
-                    ) {
-                        ImageFormat::Unsupported => continue,
-                        _ => {}
+                    if let Err(reason) = self.context.image_limits.check(path.to_str().unwrap()) {
+                        warn!("skipping {}: {}", path.display(), reason);
+                        manifest.oversized_images.push(OversizedImage {
+                            test_set: test_set.clone(),
+                            image: path.file_name().unwrap().to_str().unwrap().to_string(),
+                            reason,
+                        });
+                        manifest.save(&self.context.benchmark_dir).unwrap();
+                        skipped_images.push(path);
+                    }
+                }
+            }
+
+            // Look for images with the same content under different names, which would count
+            // the same image several times, and record them in the manifest.
+            if self.context.duplicate_policy != DuplicatePolicy::Ignore {
+                let image_paths = Benchmarker::get_test_set_image_paths(&local_test_set_path)
+                    .into_iter()
+                    .filter(|path| !skipped_images.contains(path))
+                    .collect::<Vec<PathBuf>>();
+                let mut duplicates = DuplicateImages::find(test_set, &image_paths);
+                for duplicate in duplicates.iter_mut() {
+                    warn!(
+                        "{} contains duplicate images: {}",
+                        test_set,
+                        duplicate.images.join(", ")
+                    );
+                    if self.context.duplicate_policy == DuplicatePolicy::Skip {
+                        duplicate.skipped = true;
+                        skipped_images.extend(
+                            duplicate
+                                .repeats()
+                                .iter()
+                                .map(|name| Path::new(&local_test_set_path).join(name)),
+                        );
                     }
+                }
+                if !duplicates.is_empty() {
+                    manifest.duplicates.extend(duplicates);
+                    manifest.save(&self.context.benchmark_dir).unwrap();
+                }
+            }
 
-                    // Wait for the next available worker.
-                    let worker = self.wait_for_available_worker();
+            // Run the benchmark for each commit in the case of a comparison.
+            while commit.is_some() {
+                // Run the sweep with each toolchain and build configuration in turn, or once with
+                // the default Dockerfile and flags.
+                for (toolchain, build_config) in &variants {
+                    // Nothing more is benchmarked once the run was cancelled.
+                    if self.cancellation.is_cancelled() {
+                        break;
+                    }
 
-                    // Clean the libjxl branch on the docker manager of the worker.
-                    let _ = worker
-                        .docker_manager
-                        .as_ref()
-                        .unwrap()
-                        .clean_libjxl()
-                        .unwrap();
-
-                    // Set the current commit of libjxl on the docker manager.
-                    if commit.is_some() && commit.as_ref().unwrap() == "local" {
-                        // Apply a diff of the local changes to libjxl on the worker container.
-                        let _ = worker
-                            .docker_manager
-                            .as_ref()
-                            .unwrap()
-                            .apply_local_as_diff()
-                            .unwrap();
-                    } else {
-                        // Set the current commit of libjxl on the worker container.
-                        let _ = worker
-                            .docker_manager
+                    // Set up output and result paths, labelled by the commit, toolchain and build
+                    // configuration.
+                    let label = BuildConfig::label(
+                        &Toolchain::label(commit.as_ref().unwrap(), toolchain.as_deref()),
+                        build_config
                             .as_ref()
-                            .unwrap()
-                            .change_libjxl_commit(commit.clone().unwrap().as_str())
-                            .unwrap();
+                            .map(|build_config| build_config.name.as_str()),
+                    );
+                    let out_orig_path =
+                        self.get_output_path_for(format!("orig/{}/{}", test_set, label).as_str());
+                    let out_comp_path =
+                        self.get_output_path_for(format!("comp/{}/{}", test_set, label).as_str());
+                    let res_orig_path =
+                        self.get_result_path_for(format!("orig/{}/{}", test_set, label).as_str());
+                    let res_comp_path =
+                        self.get_result_path_for(format!("comp/{}/{}", test_set, label).as_str());
+
+                    // Record the commit, toolchain and build configuration in the manifest.
+                    if !manifest.libjxl_commits.contains(&label) {
+                        manifest.libjxl_commits.push(label.clone());
+                        manifest.save(&self.context.benchmark_dir).unwrap();
                     }
 
-                    // Re-build libjxl on the docker manager of the worker.
-                    let _ = worker
-                        .docker_manager
-                        .as_ref()
-                        .unwrap()
-                        .build_libjxl()
-                        .unwrap();
-
-                    // Set current image file path and name for the worker payload.
-                    let entry = entry.unwrap();
-                    let path = entry.path();
-                    worker.payload.as_mut().unwrap().current_image_file_path =
-                        path.to_str().unwrap().to_string();
-                    worker.payload.as_mut().unwrap().current_image_name =
-                        path.file_name().unwrap().to_str().unwrap().to_string();
-
-                    // Remove the file extension from the current image name.
-                    worker.payload.as_mut().unwrap().current_image_name = worker
-                        .payload
-                        .as_mut()
-                        .unwrap()
-                        .current_image_name
-                        .as_str()
-                        .split(".")
-                        .collect::<Vec<&str>>()[0]
-                        .to_string();
-
-                    // Set the current output and result directories for the worker payload.
-                    worker.payload.as_mut().unwrap().current_out_orig_path = out_orig_path.clone();
-                    worker.payload.as_mut().unwrap().current_out_comp_path = out_comp_path.clone();
-                    worker.payload.as_mut().unwrap().current_res_orig_path = res_orig_path.clone();
-                    worker.payload.as_mut().unwrap().current_res_comp_path = res_comp_path.clone();
-
-                    // Set the current image format and test set for the worker payload.
-                    worker.payload.as_mut().unwrap().current_image_format =
-                        ImageFormat::from_file_name(
-                            &worker.payload.as_ref().unwrap().current_image_file_path,
-                        );
-                    worker.payload.as_mut().unwrap().current_test_set = test_set.clone();
+                    // A mounted local source tree is shared by all workers, so it is built once
+                    // up front instead of being checked out and rebuilt per image.
+                    if commit.as_ref().unwrap() == LOCAL_SRC_COMMIT {
+                        let build = self.run_timing.time(Phase::Build, || {
+                            self.workers[0]
+                                .docker_manager
+                                .as_ref()
+                                .unwrap()
+                                .build_libjxl()
+                        });
+                        if let Err(e) = build {
+                            self.wait_for_all_workers();
+                            return Err(Box::new(BenchmarkError::WorkerSetup {
+                                worker_id: self.workers[0].id,
+                                message: format!(
+                                    "the local libjxl source could not be built: {}",
+                                    e
+                                ),
+                            }));
+                        }
+                    }
+
+                    // Create a context for the worker payload.
+                    // Set the libjxl commit for the worker payload context.
+                    let mut context = self.context.clone();
+                    context.libjxl_commit = Some(commit.clone().unwrap().to_string());
+
+                    // The libjxl setup of the images reads the diffs and prebuilt version and
+                    // records its build time while a worker is borrowed.
+                    let diffs = self.context.diffs.clone();
+                    let prebuilt_cjxl = self.context.prebuilt_cjxl.clone();
+                    let run_timing = self.run_timing.clone();
+                    let cancellation = self.cancellation.clone();
+
+                    // Collect the supported image files in the local test set path, without the
+                    // oversized images, the skipped duplicates and the images the run is not
+                    // restricted to.
+                    let image_paths = Benchmarker::get_test_set_image_paths(&local_test_set_path)
+                        .into_iter()
+                        .filter(|path| !skipped_images.contains(path))
+                        .filter(|path| Benchmarker::image_selected(&self.context.images, path))
+                        .collect::<Vec<PathBuf>>();
+
+                    // In deterministic mode, images are processed in sorted order on fixed workers.
+                    let assignments = match self.context.deterministic {
+                        true => {
+                            Benchmarker::plan_assignments(&image_paths, self.context.num_workers)
+                                .into_iter()
+                                .map(|(path, worker_id)| (path, Some(worker_id)))
+                                .collect::<Vec<(PathBuf, Option<usize>)>>()
+                        }
+                        false => image_paths.into_iter().map(|path| (path, None)).collect(),
+                    };
+
+                    // Iterate over the images in the local test set path.
+                    for (path, worker_id) in assignments {
+                        // Wait for the assigned worker or the next available worker.
+                        let worker = match worker_id {
+                            Some(worker_id) => self.wait_for_worker(worker_id),
+                            None => self.wait_for_available_worker(),
+                        };
+                        let worker = match worker {
+                            Ok(worker) => worker,
+                            Err(e) => {
+                                self.wait_for_all_workers();
+                                return Err(Box::new(e));
+                            }
+                        };
+
+                        // Stop dispatching images once a failure stopped the run.
+                        if failures.stopped() {
+                            self.wait_for_all_workers();
+                            return Err(Benchmarker::abort_error(&failures));
+                        }
+
+                        // Stop dispatching images once the run was cancelled.
+                        if cancellation.is_cancelled() {
+                            break;
+                        }
 
-                    // Set the context for the worker payload.
-                    worker.payload.as_mut().unwrap().context = context.clone();
+                        // Check out and build the commit with the build configuration, or install the
+                        // prebuilt version, on the container of the toolchain of the worker.
+                        worker.use_toolchain(toolchain.as_deref());
+                        worker.docker_manager.as_mut().unwrap().build_config =
+                            (*build_config).clone();
+                        let build_start = Instant::now();
+                        if let Err(e) = Benchmarker::prepare_libjxl(
+                            worker.docker_manager.as_ref().unwrap(),
+                            commit.as_ref().unwrap(),
+                            &diffs,
+                            &diff_base_commit,
+                            prebuilt_cjxl.as_ref(),
+                        ) {
+                            // A build refused by the cancellation ends the run like any other
+                            // cancellation, after the workers finished their images.
+                            if e.is::<Cancelled>() {
+                                break;
+                            }
+                            let worker_id = worker.id;
+                            self.wait_for_all_workers();
+                            return Err(Box::new(BenchmarkError::WorkerSetup {
+                                worker_id,
+                                message: format!(
+                                    "libjxl {} could not be prepared: {}",
+                                    commit.as_ref().unwrap(),
+                                    e
+                                ),
+                            }));
+                        }
+                        run_timing.add(Phase::Build, build_start.elapsed());
+
+                        // Read the efforts the build accepts once, and skip its images if it
+                        // accepts none of the requested efforts.
+                        if context.effort_range.is_none() {
+                            let (effort_range, accepted) = Benchmarker::probe_efforts(
+                                worker.docker_manager.as_ref().unwrap(),
+                                &label,
+                                &JXLCompressionBenchmark::requested_efforts(&context),
+                                &failures,
+                            );
+                            context.effort_range = Some(effort_range);
+                            if !accepted {
+                                warn!(
+                                    "skipping the images of {} on libjxl {}, it accepts none of the requested efforts",
+                                    test_set, label
+                                );
+                                break;
+                            }
+                        }
+
+                        // Set current image file path and name for the worker payload.
+                        // The name is the file name without the extension.
+                        worker.payload.as_mut().unwrap().current_image_file_path =
+                            path.to_str().unwrap().to_string();
+                        worker.payload.as_mut().unwrap().current_image_name =
+                            Benchmarker::image_name(&path);
+
+                        // Set the current output and result directories for the worker payload.
+                        worker.payload.as_mut().unwrap().current_out_orig_path =
+                            out_orig_path.clone();
+                        worker.payload.as_mut().unwrap().current_out_comp_path =
+                            out_comp_path.clone();
+                        worker.payload.as_mut().unwrap().current_res_orig_path =
+                            res_orig_path.clone();
+                        worker.payload.as_mut().unwrap().current_res_comp_path =
+                            res_comp_path.clone();
+
+                        // Set the current image format and test set for the worker payload.
+                        worker.payload.as_mut().unwrap().current_image_format =
+                            ImageFormat::from_file_name(
+                                &worker.payload.as_ref().unwrap().current_image_file_path,
+                            );
+                        worker.payload.as_mut().unwrap().current_test_set = test_set.clone();
+
+                        // Set the context for the worker payload.
+                        worker.payload.as_mut().unwrap().context = context.clone();
+
+                        // Run the benchmark for the current image on the worker.
+                        worker.run::<T>();
+                    }
 
-                    // Run the benchmark for the current image on the worker.
-                    worker.run::<T>();
+                    // Add the benchmark result file to the comparison CSVs vector.
+                    let result_file = self.context.output_format.comparisons_file(&res_comp_path);
+                    comparison_csvs.push(result_file.clone());
                 }
 
-                // Add the benchmark result file to the comparison CSVs vector.
-                let result_file = format!("{}/comparisons.csv", res_comp_path);
-                comparison_csvs.push(result_file.clone());
+                // Benchmark the next local diff, and start over from the first diff for the next
+                // test set once all diffs are benchmarked.
+                if let Some(first_diff) = self.context.diffs.first() {
+                    match LocalDiff::next(&self.context.diffs, commit.as_ref().unwrap()) {
+                        Some(next_diff) => {
+                            commit = Some(next_diff);
+                            continue;
+                        }
+                        None => {
+                            commit = Some(first_diff.name.clone());
+                            break;
+                        }
+                    }
+                }
 
                 // If the benchmark is not a comparison, break here.
                 if !self.context.compare_to_local && self.context.compare_to_commit.is_none() {
@@ -549,17 +1917,361 @@ impl Benchmarker {
 
             // When all workers are finished, both commits have been benchmarked on all images.
             self.wait_for_all_workers();
+            if failures.stopped() {
+                return Err(Benchmarker::abort_error(&failures));
+            }
 
-            // Compare the results of the benchmarks if applicable.
-            if comparison_csvs.len() == 2 {
-                // TODO: This isn't generalic to all benchmarks, but this doesn't matter if we only have one JPEG XL benchmark at this moment.
-                JXLCompressionBenchmark::compare_results(&comparison_csvs[0], &comparison_csvs[1]);
-            } else if comparison_csvs.len() == 1 {
+            // A cancelled run keeps the comparisons completed so far, sorted like those of a
+            // finished run, and stops before the summaries and diffs of the test set.
+            if self.cancellation.is_cancelled() {
+                for comparisons_file in &comparison_csvs[test_set_csvs_start..] {
+                    JXLCompressionBenchmark::sort_comparisons(
+                        comparisons_file,
+                        self.context.csv_float_digits,
+                    );
+                }
+                manifest.timing = Some(self.run_timing.summary());
+                manifest.save(&self.context.benchmark_dir).unwrap();
+                warn!("the run was cancelled, its results are partial");
+                return Err(Box::new(BenchmarkError::Cancelled));
+            }
+
+            // Sort the comparisons, which workers append in completion order, and write the per
+            // distance and effort averages of the test set for each commit.
+            for comparisons_file in &comparison_csvs[test_set_csvs_start..] {
+                JXLCompressionBenchmark::sort_comparisons(
+                    comparisons_file,
+                    self.context.csv_float_digits,
+                );
+                JXLCompressionBenchmark::write_aggregate(
+                    test_set,
+                    comparisons_file,
+                    self.context.summary_weighting,
+                    self.context.csv_float_digits,
+                );
+
+                if !PathBuf::from(comparisons_file).exists() {
+                    continue;
+                }
+                let commit_dir = PathBuf::from(comparisons_file)
+                    .parent()
+                    .and_then(|dir| dir.file_name())
+                    .map(|name| name.to_str().unwrap().to_string())
+                    .unwrap_or_default();
+
+                // Record the comparisons in the results database of the benchmark directory.
+                if self.context.output_format == OutputFormat::Sqlite {
+                    let results = self
+                        .csv_sink
+                        .lock()
+                        .unwrap()
+                        .take_comparisons(comparisons_file);
+                    if let Err(e) = Benchmarker::record_in_database(
+                        &self.context.benchmark_dir,
+                        self.context.current_run,
+                        test_set,
+                        &commit_dir,
+                        &results,
+                    ) {
+                        warn!(
+                            "could not record {} in the results database: {}",
+                            comparisons_file, e
+                        );
+                    }
+                }
+
+                // Add a section per commit to the report, titled by the test set and commit.
+                if let Some(report) = report.as_mut() {
+                    if let Err(e) = report.add_comparisons_file(
+                        &format!("{} ({})", test_set, commit_dir),
+                        comparisons_file,
+                    ) {
+                        warn!("could not add {} to the report: {}", comparisons_file, e);
+                    }
+                }
+            }
+
+            // Diff the local build against the previous run, whose results are the baseline.
+            // The diffs are written next to the results of this run.
+            if let Some(previous_run) = &self.context.previous_run {
+                let comparisons_file = comparison_csvs.last().unwrap();
+                match previous_run.comparisons_file(&self.context.benchmark_dir, test_set) {
+                    Some(previous_file) if PathBuf::from(comparisons_file).exists() => {
+                        let summary = JXLCompressionBenchmark::compare_results(
+                            &previous_file,
+                            comparisons_file,
+                            None,
+                            &Benchmarker::parent_dir(comparisons_file),
+                            self.context.summary_weighting,
+                            self.context.baseline_distance,
+                            self.context.csv_float_digits,
+                        );
+                        gate_failures.extend(JXLCompressionBenchmark::check_gates(
+                            &self.context.gates,
+                            &summary,
+                            test_set,
+                        ));
+                    }
+                    Some(_) => warn!(
+                        "no comparisons were recorded for {}, skipping the diff against run {}",
+                        test_set, previous_run.run
+                    ),
+                    None => warn!(
+                        "run {} has no comparisons for {}, skipping the diff",
+                        previous_run.run, test_set
+                    ),
+                }
                 continue;
-            } else if comparison_csvs.len() > 2 {
-                panic!("More than 2 comparison CSVs found");
-            } else {
-                panic!("No comparison CSVs found");
+            }
+
+            // Diff the comparisons of this test set, each variant against its counterpart, or
+            // each variant against the baseline codec if one was given and a single commit was
+            // benchmarked. The baseline may be anywhere, so its diffs are written next to the
+            // JPEG XL run.
+            let test_set_csvs = &comparison_csvs[test_set_csvs_start..];
+            let (diffs, baseline_codec) = match &self.context.baseline_csv {
+                Some(baseline_csv) if test_set_csvs.len() == variants.len() => (
+                    test_set_csvs
+                        .iter()
+                        .map(|comparisons_file| {
+                            (
+                                baseline_csv.clone(),
+                                comparisons_file.clone(),
+                                Benchmarker::parent_dir(comparisons_file),
+                            )
+                        })
+                        .collect::<Vec<(String, String, String)>>(),
+                    Some(self.context.baseline_codec.as_str()),
+                ),
+                _ => (
+                    Benchmarker::comparison_pairs(test_set_csvs, variants.len())
+                        .map_err(|message| Box::new(BenchmarkError::Config(message)))?,
+                    None,
+                ),
+            };
+            for (comparisons_file_a, comparisons_file_b, output_dir) in &diffs {
+                // TODO: This isn't generalic to all benchmarks, but this doesn't matter if we only have one JPEG XL benchmark at this moment.
+                let summary = JXLCompressionBenchmark::compare_results(
+                    comparisons_file_a,
+                    comparisons_file_b,
+                    baseline_codec,
+                    output_dir,
+                    self.context.summary_weighting,
+                    self.context.baseline_distance,
+                    self.context.csv_float_digits,
+                );
+                gate_failures.extend(JXLCompressionBenchmark::check_gates(
+                    &self.context.gates,
+                    &summary,
+                    test_set,
+                ));
+            }
+        }
+
+        // Write the report once all test sets are done.
+        if let Some(report) = report {
+            let report_path = format!(
+                "{}/{}/report.html",
+                self.context.benchmark_dir, self.context.current_run
+            );
+            match report.save(&report_path) {
+                Ok(report_path) => info!("Wrote the report to {}", report_path),
+                Err(e) => warn!("could not write the report: {}", e),
+            }
+        }
+
+        // Record how long the run took and where the time went.
+        let timing = self.run_timing.summary();
+        info!(
+            "Benchmarked {} image(s) in {:.1}s ({:.2} images/s)",
+            timing.images, timing.wall_time_s, timing.images_per_s
+        );
+        info!(
+            "Worker time: build {:.1}s, encode {:.1}s, decode {:.1}s, metrics {:.1}s",
+            timing.build_time_s, timing.encode_time_s, timing.decode_time_s, timing.metrics_time_s
+        );
+        manifest.timing = Some(timing);
+        manifest.save(&self.context.benchmark_dir).unwrap();
+
+        // Report the failures collected during the run.
+        let messages = failures.messages();
+        if !messages.is_empty() {
+            error!("{} failure(s) during the run:", messages.len());
+            for message in &messages {
+                error!("  {}", message);
+            }
+        }
+
+        // Fail the run if a comparison violated a gate, now that all results are written.
+        if !gate_failures.is_empty() {
+            return Err(Box::new(BenchmarkError::GateFailed(gate_failures)));
+        }
+        Ok(())
+    }
+
+    /// Pairs the comparison files of a test set to diff. The files are ordered by commit and
+    /// then by variant, the toolchain and build configuration, so with two commits or local
+    /// diffs each variant is diffed against the same variant of the other commit, and the diff
+    /// is written next to the first. With a single commit, every other variant is diffed
+    /// against the first variant, and each diff is written next to the other variant.
+    ///
+    /// # Arguments
+    /// * `comparison_csvs` - The comparison files of the test set, in the order benchmarked.
+    /// * `num_variants` - The number of variants each commit was benchmarked with.
+    ///
+    /// # Returns
+    /// The files to diff and the directory to write each diff to, empty if there is nothing to
+    /// compare, or an error if the files cannot be paired.
+    pub fn comparison_pairs(
+        comparison_csvs: &[String],
+        num_variants: usize,
+    ) -> Result<Vec<(String, String, String)>, String> {
+        if num_variants == 0 || !comparison_csvs.len().is_multiple_of(num_variants) {
+            return Err(format!(
+                "{} comparison files cannot be split into {} variant(s)",
+                comparison_csvs.len(),
+                num_variants
+            ));
+        }
+        let commits = comparison_csvs
+            .chunks(num_variants)
+            .collect::<Vec<&[String]>>();
+        match commits.as_slice() {
+            [] => Ok(Vec::new()),
+            [variants] => Ok(variants
+                .iter()
+                .skip(1)
+                .map(|variant| {
+                    (
+                        variants[0].clone(),
+                        variant.clone(),
+                        Benchmarker::parent_dir(variant),
+                    )
+                })
+                .collect()),
+            [variants_a, variants_b] => Ok(variants_a
+                .iter()
+                .zip(variants_b.iter())
+                .map(|(a, b)| (a.clone(), b.clone(), Benchmarker::parent_dir(a)))
+                .collect()),
+            _ => Err(format!(
+                "Only two commits can be compared, {} were benchmarked",
+                commits.len()
+            )),
+        }
+    }
+
+    /// Gets the directory of a results file, which files derived from it are written to.
+    fn parent_dir(file_path: &str) -> String {
+        PathBuf::from(file_path)
+            .parent()
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_string()
+    }
+
+    /// Records comparison results in the results database of a benchmark directory.
+    ///
+    /// # Arguments
+    /// * `benchmark_dir` - The benchmark directory, which holds the database.
+    /// * `run` - The run the results belong to.
+    /// * `test_set` - The test set of the images.
+    /// * `commit` - The label of the libjxl commit the images were compressed with.
+    /// * `results` - The comparison results, at full precision.
+    ///
+    /// # Returns
+    /// An error if the results could not be recorded.
+    fn record_in_database(
+        benchmark_dir: &str,
+        run: usize,
+        test_set: &str,
+        commit: &str,
+        results: &[ComparisonResult],
+    ) -> Result<(), Box<dyn Error>> {
+        let mut database = ResultsDatabase::open(&ResultsDatabase::path(benchmark_dir))?;
+        database.insert(run, test_set, commit, results)
+    }
+
+    /// Reads the efforts the cjxl build on a worker accepts and reports the requested efforts it
+    /// rejects. A help text that cannot be read is reported and `EffortRange::DEFAULT` assumed.
+    ///
+    /// # Arguments
+    /// * `docker_manager` - The docker manager of the worker the build was prepared on.
+    /// * `label` - The label of the commit, toolchain and build configuration of the build.
+    /// * `requested_efforts` - The efforts the run encodes with.
+    /// * `failures` - The failures of the run, which the rejected efforts are recorded in.
+    ///
+    /// # Returns
+    /// The effort range of the build, and whether it accepts any of the requested efforts.
+    fn probe_efforts(
+        docker_manager: &DockerManager,
+        label: &str,
+        requested_efforts: &[u32],
+        failures: &RunFailures,
+    ) -> (EffortRange, bool) {
+        let effort_range = docker_manager.cjxl_effort_range().unwrap_or_else(|e| {
+            warn!(
+                "assuming cjxl efforts {} for libjxl {}, the effort range could not be read: {}",
+                EffortRange::DEFAULT,
+                label,
+                e
+            );
+            EffortRange::DEFAULT
+        });
+        let mut accepted = false;
+        for effort in requested_efforts {
+            match effort_range.check(*effort) {
+                Ok(_) => accepted = true,
+                Err(e) => {
+                    let message = format!("libjxl {}: {}", label, e);
+                    error!("{}", message);
+                    failures.record(message);
+                }
+            }
+        }
+        (effort_range, accepted)
+    }
+
+    /// Creates the error returned when a run is stopped by a failure in fail-fast mode.
+    fn abort_error(failures: &RunFailures) -> Box<dyn Error> {
+        let first = failures.messages().into_iter().next().unwrap_or_default();
+        Box::from(format!("Stopped the run after a failure: {}", first))
+    }
+
+    /// Resolves the default libjxl commit to a SHA.
+    /// Uses `PINNED_LIBJXL_COMMIT` if set, otherwise resolves `DEFAULT_LIBJXL_COMMIT` on the
+    /// container of the first worker.
+    ///
+    /// # Returns
+    /// The 40 character SHA of the default libjxl commit, or an error if it could not be
+    /// resolved on the container.
+    pub fn resolve_default_commit(&self) -> Result<String, Box<dyn Error>> {
+        match PINNED_LIBJXL_COMMIT {
+            Some(sha) => Ok(sha.to_string()),
+            None => self.workers[0]
+                .docker_manager
+                .as_ref()
+                .unwrap()
+                .resolve_libjxl_ref(DEFAULT_LIBJXL_COMMIT),
+        }
+    }
+
+    /// Tears down the docker managers of the workers that are set up, warning instead of failing
+    /// if one cannot be torn down, so that the others are still torn down.
+    fn teardown_workers(&self) {
+        for worker in &self.workers {
+            for docker_manager in worker
+                .docker_manager
+                .iter()
+                .chain(worker.toolchain_managers.iter())
+            {
+                if let Err(e) = docker_manager.teardown() {
+                    warn!(
+                        "Failed to tear down the container of worker {}: {}",
+                        worker.id, e
+                    );
+                }
             }
         }
     }
@@ -569,6 +2281,9 @@ impl Benchmarker {
     pub fn teardown(&mut self) {
         for worker in &mut self.workers {
             worker.docker_manager.as_ref().unwrap().teardown().unwrap();
+            for docker_manager in &worker.toolchain_managers {
+                docker_manager.teardown().unwrap();
+            }
         }
     }
 }
@@ -585,8 +2300,13 @@ impl Benchmark for JXLCompressionBenchmark {
     /// # Arguments
     /// * `docker_manager` - The DockerManager to use for running the benchmark, from the worker.
     /// * `payload` - The payload for the benchmark, from the worker.
-    fn run(docker_manager: DockerManager, payload: &WorkerPayload) {
-        // Get the libjxl commit for the benchmark or use the default commit (main).
+    ///
+    /// # Returns
+    /// An error if a command in the container failed, or a Cancelled error if the run was
+    /// cancelled while the image was being benchmarked.
+    fn run(docker_manager: DockerManager, payload: &WorkerPayload) -> Result<(), Box<dyn Error>> {
+        // Get the libjxl commit of the benchmark, which the run resolved before dispatching the
+        // image, or else the default branch.
         let commit = match &payload.context.libjxl_commit {
             Some(commit) => Some(commit.as_str()),
             None => Some(DEFAULT_LIBJXL_COMMIT),
@@ -596,7 +2316,7 @@ impl Benchmark for JXLCompressionBenchmark {
         let _test_set = payload.current_test_set.clone();
 
         // Set up output and result paths.
-        let _out_orig_path = payload.current_out_orig_path.clone(); // Not used.
+        let out_orig_path = payload.current_out_orig_path.clone();
         let out_comp_path = payload.current_out_comp_path.clone();
         let res_orig_path = payload.current_res_orig_path.clone();
         let res_comp_path = payload.current_res_comp_path.clone();
@@ -608,180 +2328,1887 @@ impl Benchmark for JXLCompressionBenchmark {
         }
 
         // Get the file path for the current image.
-        let file_path = format!(
-            "{}/{}",
-            payload.context.docker_test_image_dir,
-            payload
-                .current_image_file_path
-                .split(&format!("{}/", &payload.context.local_test_image_dir))
-                .collect::<Vec<&str>>()[1]
+        let mut file_path = Benchmarker::container_image_path(
+            &payload.context.local_test_image_dir,
+            &payload.context.docker_test_image_dir,
+            &payload.current_image_file_path,
         );
+        let mut orig_image_file_path = payload.current_image_file_path.clone();
+
+        // Downscale the current image if it exceeds the maximum dimension.
+        // The scaled image is written to the original output directory, copied into the
+        // container and used as the reference for the rest of the benchmark.
+        let mut scaled_path = match payload.context.max_dimension {
+            Some(max_dimension) => ImageReader::downscale_to_max_dimension(
+                &payload.current_image_file_path,
+                &out_orig_path,
+                max_dimension,
+            )?,
+            None => None,
+        };
+
+        // CMYK JPEGs are converted to RGB the same way, so that cjxl and the metrics read the
+        // same pixels. A downscaled image is already RGB.
+        if scaled_path.is_none() {
+            scaled_path =
+                ImageReader::convert_cmyk_to_rgb(&payload.current_image_file_path, &out_orig_path)?;
+        }
+        if let Some(scaled_path) = scaled_path {
+            let docker_scaled_path = format!(
+                "/temp/orig/{}",
+                PathBuf::from(&scaled_path)
+                    .file_name()
+                    .unwrap()
+                    .to_str()
+                    .unwrap()
+            );
+            let _ = docker_manager.execute_in_container("mkdir", vec!["-p", "/temp/orig"])?;
+            docker_manager.copy_file_to_container(scaled_path.clone(), docker_scaled_path.clone())?;
+            file_path = docker_scaled_path;
+            orig_image_file_path = scaled_path;
+        }
+
+        // Copy the source image into the run, next to the reference it was benchmarked with.
+        if payload.context.keep_originals {
+            let reference_path = match orig_image_file_path != payload.current_image_file_path {
+                true => Some(orig_image_file_path.as_str()),
+                false => None,
+            };
+            Benchmarker::keep_original(
+                &payload.current_image_file_path,
+                &out_orig_path,
+                reference_path,
+            )?;
+        }
 
         // Initialize an ImageReader to read the current image.
-        let image_reader = ImageReader::new(
-            payload.current_image_file_path.clone().to_string(),
+        let image_reader = ImageReader::try_new_with_limits(
+            orig_image_file_path.clone(),
             commit.unwrap().to_string(),
-        );
+            &payload.context.image_limits,
+        )?;
 
         // Write the original image file data to a CSV file.
-        let image_file_data = image_reader.file_data;
+        // Keep the dimensions of the source image if it was downscaled.
+        let mut image_file_data = image_reader.file_data;
+        if orig_image_file_path != payload.current_image_file_path {
+            let (source_width, source_height) =
+                image::image_dimensions(&payload.current_image_file_path).unwrap();
+            image_file_data.source_width = source_width;
+            image_file_data.source_height = source_height;
+            image_file_data.test_set = payload.current_test_set.clone();
+            image_file_data.source_is_lossy =
+                ImageReader::is_lossy_source(&payload.current_image_file_path);
+            image_file_data.source_color_model =
+                ImageReader::source_color_model(&payload.current_image_file_path);
+        }
+        let (orig_width, orig_height) = (image_file_data.width, image_file_data.height);
+        let source_is_lossy = image_file_data.source_is_lossy;
+        let orig_image_name = PathBuf::from(&orig_image_file_path)
+            .file_name()
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_string();
         let result_file = format!("{}/results.csv", res_orig_path,);
-        let csv_writer = ImageFileDataCSV::new();
-        csv_writer.write_csv_header(&result_file).unwrap();
-        csv_writer
-            .write_csv(&vec![image_file_data], &result_file)
+        payload
+            .csv_sink
+            .lock()
+            .unwrap()
+            .append(
+                &ImageFileDataCSV::new(),
+                &vec![image_file_data],
+                &result_file,
+            )
             .unwrap();
 
-        // The JXL compression benchmark tests combinations of the following distances and efforts.
-        // TODO: Make these configurable.
-        let distances = vec![0.5, 1.0, 1.5, 3.0, 4.0, 6.0, 8.0, 10.0, 12.0, 14.0];
-        let efforts = (5..=9).collect::<Vec<u32>>();
+        // Hash the source image and resolve the libjxl commit SHA for the encode cache, along
+        // with the toolchain and build configuration it is built with.
+        // Local diffs and source trees are not cached since they may change between runs.
+        let cache_identity = match payload.context.use_encode_cache
+            && commit.unwrap() != "local"
+            && commit.unwrap() != LOCAL_SRC_COMMIT
+            && !payload
+                .context
+                .diffs
+                .iter()
+                .any(|diff| diff.name == commit.unwrap())
+        {
+            true => Some((
+                hash_file(&orig_image_file_path)?,
+                // A prebuilt version is not a checkout, but its binaries never change.
+                match &payload.context.prebuilt_cjxl {
+                    Some(prebuilt_cjxl) if prebuilt_cjxl.version == commit.unwrap() => {
+                        prebuilt_cjxl.version.clone()
+                    }
+                    _ => BuildConfig::label(
+                        &Toolchain::label(
+                            &docker_manager.resolve_commit()?,
+                            docker_manager.toolchain.as_deref(),
+                        ),
+                        docker_manager
+                            .build_config
+                            .as_ref()
+                            .map(|build_config| build_config.spec())
+                            .as_deref(),
+                    ),
+                },
+            )),
+            false => None,
+        };
 
-        // Run the compression benchmark for each distance and effort combination.
-        for distance in distances {
-            for effort in efforts.clone() {
-                // Create the compressed image name.
-                let comp_image_name = format!(
-                    "{}-{}-{}.{}",
-                    payload.current_image_name,
-                    distance,
+        // Check the efforts against the efforts the cjxl build of this commit accepts, which
+        // were read and reported once for the build before its images were dispatched.
+        // Efforts only allowed as expert options are encoded with `--allow_expert_options`.
+        let effort_range = payload.context.effort_range.unwrap_or(EffortRange::DEFAULT);
+        let mut efforts = Vec::<(u32, Vec<String>)>::new();
+        let mut rejected_efforts = Vec::<(u32, String)>::new();
+        for effort in JXLCompressionBenchmark::requested_efforts(&payload.context) {
+            match effort_range.check(effort) {
+                Ok(expert) => efforts.push((
                     effort,
-                    ImageFormat::JpegXl.to_string()
-                );
-
-                // Execute the cjxl encoder on the current image with the current distance and
-                // effort on the provided docker manager.
-                let skip = docker_manager
-                    .execute_cjxl(
-                        file_path.to_string().clone(),
-                        comp_image_name.clone(),
-                        distance,
-                        effort,
-                    )
-                    .unwrap_or_else(|e| {
-                        println!(
-                            "Failed to execute cjxl on image [1] {} with distance {} and effort {}: {}",
-                            file_path, distance, effort, e
-                        );
-                        Ok("skip".to_string())
-                    }).unwrap_or_else(|e| {
-                        println!(
-                            "Failed to execute cjxl on image [2] {} with distance {} and effort {}: {}",
-                            file_path, distance, effort, e
-                        );
-                        "skip".to_string()
-                    });
-
-                if skip == "skip" {
-                    continue;
+                    DockerManager::effort_extra_args(&payload.context.cjxl_extra_args, expert),
+                )),
+                Err(e) => {
+                    rejected_efforts.push((effort, format!("libjxl {}: {}", commit.unwrap(), e)))
                 }
+            }
+        }
 
-                // Retrieve the compressed image from the docker manager.
-                let src_path = format!("/temp/{}", comp_image_name);
-                let dest_path = format!("{}/{}", out_comp_path, comp_image_name);
-                docker_manager
-                    .retrieve_file(src_path.clone(), dest_path)
-                    .unwrap();
-
-                // Read the compressed image file data.
-                let image_reader = ImageReader::new(
-                    format!("{}/{}", out_comp_path, comp_image_name),
-                    commit.unwrap().to_string(),
-                );
-
-                // Write the compressed image file data to a CSV file.
-                let image_file_data = image_reader.file_data;
-                let result_file = format!("{}/results.csv", res_comp_path);
-                let csv_writer = ImageFileDataCSV::new();
-                csv_writer.write_csv_header(&result_file).unwrap();
-                csv_writer
-                    .write_csv(&vec![image_file_data.clone()], &result_file)
-                    .unwrap();
-
-                // Compare the original and compressed images.
-                JXLCompressionBenchmark::compare_to_orig(
-                    &image_file_data,
-                    &out_comp_path,
-                    &res_orig_path,
-                    &res_comp_path,
+        // Search the distance reaching the quality target instead of sweeping if enabled, at
+        // every effort if the effort curve is recorded.
+        if let Some(quality_target) = &payload.context.quality_target {
+            let mut effort_curve = Vec::<EffortCurvePoint>::new();
+            for (effort, cjxl_extra_args) in &efforts {
+                effort_curve.extend(JXLCompressionBenchmark::run_quality_target(
                     &docker_manager,
+                    payload,
+                    quality_target,
+                    *effort,
                     &file_path,
-                    &src_path,
+                    &orig_image_file_path,
+                    commit.unwrap(),
+                    cjxl_extra_args,
+                )?);
+            }
+            if payload.context.effort_curve {
+                JXLCompressionBenchmark::write_effort_curve(
+                    &payload.current_res_comp_path,
+                    &payload.current_image_name,
+                    &mut effort_curve,
+                    payload.context.csv_float_digits,
                 );
             }
+            return Ok(());
         }
-    }
-}
 
-impl JXLCompressionBenchmark {
-    /// Compares JPEG XL benchmarking results from two different commits/versions of the codec.
-    /// The comparison results are written to a CSV file.
-    ///
-    /// # Arguments
-    /// * `results_1` - The path to the first run's results CSV file.
-    /// * `results_2` - The path to the second run's results CSV file.
-    fn compare_results(results_1: &str, results_2: &str) {
-        // Initialize a csv handler for reading the comparison results.
-        let csv_reader = ComparisonResultCSV::new();
+        // Search the distance reaching the size target instead of sweeping if enabled.
+        if let Some(size_target) = &payload.context.size_target {
+            let cjxl_extra_args = match efforts.first() {
+                Some((_, cjxl_extra_args)) => cjxl_extra_args,
+                None => return Ok(()),
+            };
+            return JXLCompressionBenchmark::run_size_target(
+                &docker_manager,
+                payload,
+                size_target,
+                &file_path,
+                commit.unwrap(),
+                cjxl_extra_args,
+            );
+        }
 
-        // Read the comparison results from the CSV files.
-        let comparison_results_1 = csv_reader.read_csv(results_1).unwrap();
-        let comparison_results_2 = csv_reader.read_csv(results_2).unwrap();
+        // The JXL compression benchmark tests combinations of the configured distances, or
+        // qualities in quality mode, and the configured efforts.
+        let encodes = payload.context.sweep.encodes();
+        for (effort, message) in &rejected_efforts {
+            for (distance, quality) in encodes.iter().copied() {
+                payload.record_status(
+                    commit.unwrap(),
+                    distance,
+                    quality,
+                    *effort,
+                    ComboOutcome::Failed(message.clone()),
+                );
+            }
+        }
 
-        // Sort the comparison results by image name, in case they are not already in the same
-        // order, so that they can be compared.
-        let mut comparison_results_1 = comparison_results_1.clone();
-        comparison_results_1.sort_by(|a, b| a.orig_image_name.cmp(&b.orig_image_name));
-        let mut comparison_results_2 = comparison_results_2.clone();
-        comparison_results_2.sort_by(|a, b| a.orig_image_name.cmp(&b.orig_image_name));
+        // Only encode a random sample of the combinations if enabled, and record the sample.
+        let sampled = payload.context.grid_sample.map(|grid_sample| {
+            grid_sample.sample(encodes.len() * efforts.len(), &payload.current_image_name)
+        });
+        if let Some(sampled) = &sampled {
+            let sampled_combinations = sampled
+                .iter()
+                .map(|index| SampledCombination {
+                    image_name: payload.current_image_name.clone(),
+                    distance: encodes[index / efforts.len()].0,
+                    effort: efforts[index % efforts.len()].0,
+                })
+                .collect::<Vec<SampledCombination>>();
+            let sampled_file = format!("{}/sampled_combinations.csv", res_comp_path);
+            payload
+                .csv_sink
+                .lock()
+                .unwrap()
+                .append(
+                    &SampledCombinationCSV::new(),
+                    &sampled_combinations,
+                    &sampled_file,
+                )
+                .unwrap();
+        }
 
-        let mut results = Vec::<ComparisonResultDiff>::new();
+        // The contact sheet of the image if enabled, with the original followed by a tile per
+        // successful encode from the images decoded for its metrics.
+        let mut contact_sheet = match payload.context.contact_sheets {
+            true => Some(ContactSheet::new()),
+            false => None,
+        };
 
-        // Compare each entry in the results CSVs.
-        assert!(comparison_results_1.len() == comparison_results_2.len());
-        for i in 0..comparison_results_1.len() {
-            // Assert that the right entries are being compared.
-            assert!(
-                comparison_results_1[i].orig_image_name == comparison_results_2[i].orig_image_name
-            );
-            assert!(
-                comparison_results_1[i].comp_image_name == comparison_results_2[i].comp_image_name
-            );
-            assert!(comparison_results_1[i].distance == comparison_results_2[i].distance);
-            assert!(comparison_results_1[i].effort == comparison_results_2[i].effort);
+        // Run the compression benchmark for each distance and effort combination.
+        for (encode_index, (distance, quality)) in encodes.iter().copied().enumerate() {
+            for (effort_index, (effort, cjxl_extra_args)) in efforts.iter().cloned().enumerate() {
+                // Skip the combinations that were not sampled.
+                let index = encode_index * efforts.len() + effort_index;
+                if matches!(&sampled, Some(sampled) if !sampled.contains(&index)) {
+                    payload.record_status(
+                        commit.unwrap(),
+                        distance,
+                        quality,
+                        effort,
+                        ComboOutcome::Skipped("not sampled".to_string()),
+                    );
+                    continue;
+                }
 
-            // Calculate the differences between the comparison results as:
-            //  diff = result_2 - result_1
-            let diff_orig_file_size = comparison_results_2[i].orig_file_size as f64
-                - comparison_results_1[i].orig_file_size as f64;
-            let diff_comp_file_size = comparison_results_2[i].comp_file_size as f64
-                - comparison_results_1[i].comp_file_size as f64;
-            let diff_orig_raw_size = comparison_results_2[i].orig_raw_size as f64
-                - comparison_results_1[i].orig_raw_size as f64;
-            let diff_comp_raw_size = comparison_results_2[i].comp_raw_size as f64
-                - comparison_results_1[i].comp_raw_size as f64;
-            let diff_comp_file_size_ratio = comparison_results_2[i].comp_file_size_ratio
-                - comparison_results_1[i].comp_file_size_ratio;
-            let diff_raw_file_size_ratio = comparison_results_2[i].raw_file_size_ratio
-                - comparison_results_1[i].raw_file_size_ratio;
-            let diff_mse = comparison_results_2[i].mse - comparison_results_1[i].mse;
-            let diff_psnr = comparison_results_2[i].psnr - comparison_results_1[i].psnr;
-            let diff_ssim = comparison_results_2[i].ssim - comparison_results_1[i].ssim;
-            let diff_ms_ssim = comparison_results_2[i].ms_ssim - comparison_results_1[i].ms_ssim;
-            let diff_butteraugli =
-                comparison_results_2[i].butteraugli - comparison_results_1[i].butteraugli;
-            let diff_butteraugli_pnorm = comparison_results_2[i].butteraugli_pnorm
-                - comparison_results_1[i].butteraugli_pnorm;
-            let diff_ssimulacra2 =
-                comparison_results_2[i].ssimulacra2 - comparison_results_1[i].ssimulacra2;
+                // Stop encoding once a failure stopped the run, recording the rest as skipped.
+                if payload.failures.stopped() {
+                    payload.record_status(
+                        commit.unwrap(),
+                        distance,
+                        quality,
+                        effort,
+                        ComboOutcome::Skipped("the run was stopped".to_string()),
+                    );
+                    continue;
+                }
 
-            // Create a comparison result difference struct and add it to the results vector.
-            let result = ComparisonResultDiff {
-                orig_image_name: comparison_results_1[i].orig_image_name.clone(),
-                comp_image_name: comparison_results_1[i].comp_image_name.clone(),
-                distance: comparison_results_1[i].distance,
-                effort: comparison_results_1[i].effort,
-                diff_orig_file_size,
+                // Stop encoding once the run was cancelled, keeping the completed combinations.
+                if payload.cancellation.is_cancelled() {
+                    payload.record_status(
+                        commit.unwrap(),
+                        distance,
+                        quality,
+                        effort,
+                        ComboOutcome::Skipped("the run was cancelled".to_string()),
+                    );
+                    continue;
+                }
+
+                // Create the compressed image name.
+                let comp_image_name = ImageReader::compressed_image_name(
+                    &payload.current_image_name,
+                    distance,
+                    effort,
+                );
+
+                let src_path = format!("/temp/{}", comp_image_name);
+                let dest_path = format!("{}/{}", out_comp_path, comp_image_name);
+
+                // Look up the encode in the cache if enabled.
+                let cache_key = cache_identity.as_ref().map(|(source_hash, libjxl_sha)| {
+                    EncodeCache::key(
+                        source_hash,
+                        distance,
+                        effort,
+                        &JXLCompressionBenchmark::coding_mode(payload.context.modular),
+                        libjxl_sha,
+                        &format!(
+                            "{}{}",
+                            cjxl_extra_args.join(" "),
+                            quality
+                                .map(|q| format!(" --quality={}", q))
+                                .unwrap_or_default()
+                        ),
+                    )
+                });
+                let cached_path = cache_key
+                    .as_ref()
+                    .and_then(|key| payload.encode_cache.lock().unwrap().lookup(key));
+                let cached = cached_path.is_some();
+
+                // Resource usage is only measured for actual encodes.
+                let mut resource_usage = ResourceUsage::default();
+
+                // The compressed image when it is held in memory instead of on disk.
+                let mut comp_bytes: Option<Vec<u8>> = None;
+
+                if let Some(cached_path) = cached_path {
+                    // Reuse the cached output instead of re-encoding, and copy it into the
+                    // container for the Butteraugli and SSIMULACRA2 comparisons.
+                    debug!("Reusing the cached encode of {}", comp_image_name);
+                    if cached_path != dest_path {
+                        fs::copy(&cached_path, &dest_path)?;
+                    }
+                    docker_manager.copy_file_to_container(dest_path.clone(), src_path.clone())?;
+                } else {
+                    // Sample the container's resource usage during the encode if enabled.
+                    let resource_monitor = match payload.context.resource_metrics {
+                        true => Some(ResourceMonitor::start(&docker_manager)),
+                        false => None,
+                    };
+
+                    // Execute the cjxl encoder on the current image with the current distance and
+                    // effort on the provided docker manager.
+                    debug!("Encoding {}", comp_image_name);
+                    let encode_start = Instant::now();
+                    let encode_error = match docker_manager.execute_cjxl(
+                        file_path.to_string().clone(),
+                        comp_image_name.clone(),
+                        distance,
+                        quality,
+                        effort,
+                        payload.context.modular,
+                        &cjxl_extra_args,
+                    ) {
+                        Ok(Ok(_)) => None,
+                        Ok(Err(e)) => Some(format!(
+                            "Failed to execute cjxl on image [2] {} with distance {} and effort {}: {}",
+                            file_path, distance, effort, e
+                        )),
+                        Err(e) => Some(format!(
+                            "Failed to execute cjxl on image [1] {} with distance {} and effort {}: {}",
+                            file_path, distance, effort, e
+                        )),
+                    };
+
+                    // Stop sampling now that the encode has completed.
+                    if let Some(resource_monitor) = resource_monitor {
+                        resource_usage = resource_monitor.stop();
+                    }
+
+                    // An encode refused or completed after the run was cancelled is dropped
+                    // rather than recorded as a failed or partially measured combination.
+                    if payload.cancellation.is_cancelled() {
+                        payload.record_status(
+                            commit.unwrap(),
+                            distance,
+                            quality,
+                            effort,
+                            ComboOutcome::Skipped("the run was cancelled".to_string()),
+                        );
+                        continue;
+                    }
+
+                    if let Some(message) = encode_error {
+                        error!("{}", message);
+                        payload.failures.record(message.clone());
+                        payload.record_status(
+                            commit.unwrap(),
+                            distance,
+                            quality,
+                            effort,
+                            ComboOutcome::Failed(message),
+                        );
+                        JXLCompressionBenchmark::record_encode_failure(
+                            &res_comp_path,
+                            payload.context.output_format,
+                            &JXLCompressionBenchmark::failed_encode(
+                                &orig_image_name,
+                                &comp_image_name,
+                                distance,
+                                quality,
+                                effort,
+                                source_is_lossy,
+                                &payload.context,
+                            ),
+                            &payload.csv_sink,
+                        );
+                        continue;
+                    }
+
+                    // Retrieve the compressed image from the docker manager.
+                    // Without keeping compressed images, it is read into memory instead.
+                    if !payload.context.keep_compressed {
+                        comp_bytes = Some(docker_manager.retrieve_file_bytes(src_path.clone())?);
+                    } else {
+                        docker_manager.retrieve_file(src_path.clone(), dest_path.clone())?;
+                    }
+                    payload
+                        .run_timing
+                        .add(Phase::Encode, encode_start.elapsed());
+
+                    // Record the encode in the cache.
+                    if let Some(cache_key) = cache_key {
+                        let mut encode_cache = payload.encode_cache.lock().unwrap();
+                        encode_cache.insert(cache_key, dest_path.clone());
+                        if let Err(e) = encode_cache.save(&payload.context.benchmark_dir) {
+                            warn!("Failed to save the encode cache: {}", e);
+                        }
+                    }
+                }
+
+                // Replace the compressed image with a link to its content-addressed object.
+                if payload.context.content_addressed {
+                    ObjectStore::new(&payload.context.benchmark_dir).store(&dest_path)?;
+                }
+
+                // Wait for a decode permit, held until the metrics of this encode are computed.
+                let _decode_permit = payload.decode_limiter.acquire();
+
+                // Read the compressed image file data.
+                // Empty, corrupt or wrongly sized images are recorded as failed encodes.
+                let image_reader = payload
+                    .run_timing
+                    .time(Phase::Decode, || match &comp_bytes {
+                        Some(comp_bytes) => payload
+                            .context
+                            .image_limits
+                            .check_jxl(comp_bytes)
+                            .map_err(|e| e.into())
+                            .and_then(|_| {
+                                ImageReader::from_jxl_bytes(
+                                    comp_bytes,
+                                    dest_path.clone(),
+                                    commit.unwrap().to_string(),
+                                )
+                            }),
+                        None => ImageReader::try_new_with_limits(
+                            dest_path.clone(),
+                            commit.unwrap().to_string(),
+                            &payload.context.image_limits,
+                        ),
+                    });
+                let mut image_file_data = match JXLCompressionBenchmark::validate_encode(
+                    image_reader,
+                    orig_width,
+                    orig_height,
+                ) {
+                    Ok(image_file_data) => image_file_data,
+                    Err(e) => {
+                        warn!("skipping {}: {}", comp_image_name, e);
+                        let message = format!("Could not decode {}: {}", comp_image_name, e);
+                        payload.failures.record(message.clone());
+                        payload.record_status(
+                            commit.unwrap(),
+                            distance,
+                            quality,
+                            effort,
+                            ComboOutcome::Failed(message),
+                        );
+                        JXLCompressionBenchmark::record_encode_failure(
+                            &res_comp_path,
+                            payload.context.output_format,
+                            &JXLCompressionBenchmark::failed_encode(
+                                &orig_image_name,
+                                &comp_image_name,
+                                distance,
+                                quality,
+                                effort,
+                                source_is_lossy,
+                                &payload.context,
+                            ),
+                            &payload.csv_sink,
+                        );
+                        continue;
+                    }
+                };
+
+                // Cross-check the decode against the pure-Rust decoder if enabled.
+                if payload.context.cross_check_decode {
+                    JXLCompressionBenchmark::cross_check_decode(
+                        &mut image_file_data,
+                        comp_bytes.as_deref(),
+                    );
+                }
+
+                // Check the parameters from the file name against the header of the encode.
+                if let Ok(Ok(output)) = docker_manager.execute_jxlinfo(&src_path) {
+                    ImageReader::apply_jxlinfo(&mut image_file_data, &output);
+                }
+
+                // Write the compressed image file data to a CSV file.
+                let result_file = format!("{}/results.csv", res_comp_path);
+                payload
+                    .csv_sink
+                    .lock()
+                    .unwrap()
+                    .append(
+                        &ImageFileDataCSV::new(),
+                        &vec![image_file_data.clone()],
+                        &result_file,
+                    )
+                    .unwrap();
+
+                // Compare the original and compressed images.
+                let metrics_start = Instant::now();
+                let comparison_result = match JXLCompressionBenchmark::compare_to_orig(
+                    &image_file_data,
+                    &out_comp_path,
+                    &res_orig_path,
+                    &res_comp_path,
+                    &docker_manager,
+                    &file_path,
+                    &src_path,
+                    &payload.context,
+                    &resource_usage,
+                    comp_bytes.as_deref(),
+                    quality,
+                    &payload.csv_sink,
+                    contact_sheet.as_mut(),
+                ) {
+                    Ok(comparison_result) => comparison_result,
+                    // A combination the cancellation interrupted is dropped rather than
+                    // recorded partially measured.
+                    Err(e) if e.is::<Cancelled>() => {
+                        payload.record_status(
+                            commit.unwrap(),
+                            distance,
+                            quality,
+                            effort,
+                            ComboOutcome::Skipped("the run was cancelled".to_string()),
+                        );
+                        continue;
+                    }
+                    Err(e) => return Err(e),
+                };
+                payload
+                    .run_timing
+                    .add(Phase::Metrics, metrics_start.elapsed());
+                let outcome = match (comparison_result, cached) {
+                    (None, _) => ComboOutcome::Skipped("larger than the original".to_string()),
+                    // Implausible metrics in strict validation are a failure of the encode.
+                    (Some(result), _) if !result.is_ok() => {
+                        let message = format!(
+                            "Implausible metrics for {}: {}",
+                            result.comp_image_name,
+                            result.implausible_metrics().join(", ")
+                        );
+                        payload.failures.record(message.clone());
+                        ComboOutcome::Failed(message)
+                    }
+                    (Some(_), true) => ComboOutcome::Cached,
+                    (Some(_), false) => ComboOutcome::Encoded,
+                };
+                payload.record_status(commit.unwrap(), distance, quality, effort, outcome);
+            }
+        }
+
+        // Write the contact sheet once all encodes of the image are compared.
+        if let Some(contact_sheet) = contact_sheet.filter(|sheet| !sheet.is_empty()) {
+            let sheet_path = format!(
+                "{}/contact_sheets/{}.contact.png",
+                out_comp_path, payload.current_image_name
+            );
+            if let Err(e) = contact_sheet.save(&sheet_path) {
+                warn!("could not write the contact sheet {}: {}", sheet_path, e);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl JXLCompressionBenchmark {
+    /// The largest difference in bytes accepted between the sizes of an original image in two
+    /// compared runs. Sizes are exact, so any difference is a mismatch.
+    pub const ORIGINAL_SIZE_TOLERANCE: f64 = 0.0;
+
+    /// Gets the efforts a run encodes each image with: the swept efforts, or the effort of the
+    /// quality or size target search unless the effort curve of the quality target is recorded.
+    ///
+    /// # Arguments
+    /// * `context` - The context of the run.
+    ///
+    /// # Returns
+    /// The requested efforts, before they are checked against the cjxl build.
+    pub fn requested_efforts(context: &Context) -> Vec<u32> {
+        match (&context.quality_target, &context.size_target) {
+            (Some(_), _) if context.effort_curve => context.efforts.clone(),
+            (Some(quality_target), _) => vec![quality_target.effort],
+            (None, Some(size_target)) => vec![size_target.effort],
+            (None, None) => context.efforts.clone(),
+        }
+    }
+
+    /// Gets the label of the cjxl coding mode recorded in the comparison results.
+    ///
+    /// # Arguments
+    /// * `modular` - Whether the Modular (true) or VarDCT (false) coding mode was forced, or None
+    /// if the mode was left to cjxl's defaults.
+    ///
+    /// # Returns
+    /// The coding mode label: "modular", "vardct" or "default".
+    pub fn coding_mode(modular: Option<bool>) -> String {
+        match modular {
+            Some(true) => "modular".to_string(),
+            Some(false) => "vardct".to_string(),
+            None => "default".to_string(),
+        }
+    }
+
+    /// Checks whether a compressed file is larger than its original, i.e. whether encoding to
+    /// JPEG XL was counterproductive for the image.
+    ///
+    /// # Arguments
+    /// * `orig_file_size` - The size of the original file in bytes.
+    /// * `comp_file_size` - The size of the compressed file in bytes.
+    ///
+    /// # Returns
+    /// True if the compressed file is larger than the original file.
+    pub fn is_size_regression(orig_file_size: u64, comp_file_size: u64) -> bool {
+        comp_file_size > orig_file_size
+    }
+
+    /// Searches the distance at which a metric reaches a target value.
+    /// The metric is assumed to be monotonic in the distance, so the distance is binary searched
+    /// between `QualityTarget::MIN_DISTANCE` and `QualityTarget::MAX_DISTANCE`.
+    ///
+    /// # Arguments
+    /// * `quality_target` - The metric, target value, tolerance and iteration bound.
+    /// * `measure` - Encodes at a distance and returns the metric value, or None if it failed.
+    ///
+    /// # Returns
+    /// The (distance, value, iterations, converged) of the distance whose value was closest to
+    /// the target, or None if no encode succeeded.
+    pub fn search_distance<F>(
+        quality_target: &QualityTarget,
+        mut measure: F,
+    ) -> Option<(f64, f64, u32, bool)>
+    where
+        F: FnMut(f64) -> Option<f64>,
+    {
+        let mut low = QualityTarget::MIN_DISTANCE;
+        let mut high = QualityTarget::MAX_DISTANCE;
+        let mut best: Option<(f64, f64)> = None;
+
+        for iteration in 1..=quality_target.max_iterations {
+            let distance = (low + high) / 2.0;
+            let value = match measure(distance) {
+                Some(value) => value,
+                None => return best.map(|(d, v)| (d, v, iteration, false)),
+            };
+
+            // Keep the distance closest to the target.
+            let error = (value - quality_target.target).abs();
+            if best.map_or(true, |(_, v)| error < (v - quality_target.target).abs()) {
+                best = Some((distance, value));
+            }
+            if error <= quality_target.tolerance {
+                return Some((distance, value, iteration, true));
+            }
+
+            // Increase the distance while the quality is better than the target.
+            let better_than_target = match quality_target.metric.higher_is_better() {
+                true => value > quality_target.target,
+                false => value < quality_target.target,
+            };
+            match better_than_target {
+                true => low = distance,
+                false => high = distance,
+            }
+        }
+
+        best.map(|(distance, value)| (distance, value, quality_target.max_iterations, false))
+    }
+
+    /// Searches the distance at which the encoded file reaches a target size.
+    /// The size is assumed to decrease with the distance, so the highest distance is encoded
+    /// first to check that the target is reachable, then the distance is binary searched between
+    /// `QualityTarget::MIN_DISTANCE` and `SizeTarget::MAX_DISTANCE`.
+    ///
+    /// # Arguments
+    /// * `size_target` - The target size, tolerance and iteration bound.
+    /// * `measure` - Encodes at a distance and returns the file size, or None if it failed.
+    ///
+    /// # Returns
+    /// The (distance, file size, iterations, status) of the distance whose size was closest to
+    /// the target, or None if no encode succeeded.
+    pub fn search_size_distance<F>(
+        size_target: &SizeTarget,
+        mut measure: F,
+    ) -> Option<(f64, u64, u32, SizeTargetStatus)>
+    where
+        F: FnMut(f64) -> Option<u64>,
+    {
+        // The smallest encode is still too large, so no distance reaches the target.
+        let size = measure(SizeTarget::MAX_DISTANCE)?;
+        if size_target.exceeds(size) {
+            return Some((
+                SizeTarget::MAX_DISTANCE,
+                size,
+                1,
+                SizeTargetStatus::Unreachable,
+            ));
+        }
+        if size_target.within_tolerance(size) {
+            return Some((
+                SizeTarget::MAX_DISTANCE,
+                size,
+                1,
+                SizeTargetStatus::Converged,
+            ));
+        }
+
+        let mut low = QualityTarget::MIN_DISTANCE;
+        let mut high = SizeTarget::MAX_DISTANCE;
+        let mut best = (SizeTarget::MAX_DISTANCE, size);
+        let error = |size: u64| (size as f64 - size_target.target_bytes as f64).abs();
+
+        for iteration in 2..=size_target.max_iterations {
+            let distance = (low + high) / 2.0;
+            let size = match measure(distance) {
+                Some(size) => size,
+                None => return Some((best.0, best.1, iteration, SizeTargetStatus::NotConverged)),
+            };
+
+            // Keep the distance closest to the target.
+            if error(size) < error(best.1) {
+                best = (distance, size);
+            }
+            if size_target.within_tolerance(size) {
+                return Some((distance, size, iteration, SizeTargetStatus::Converged));
+            }
+
+            // Increase the distance while the file is larger than the target.
+            match size > size_target.target_bytes {
+                true => low = distance,
+                false => high = distance,
+            }
+        }
+
+        Some((
+            best.0,
+            best.1,
+            size_target.max_iterations,
+            SizeTargetStatus::NotConverged,
+        ))
+    }
+
+    /// Runs the size target search for the current image of a worker.
+    /// Each step encodes the image at the searched distance and measures the file size.
+    /// The encode at the found distance is compared to the original like a sweep encode, and the
+    /// search result with the resulting quality is written to `size_target.csv`.
+    ///
+    /// # Arguments
+    /// * `docker_manager` - The DockerManager of the worker.
+    /// * `payload` - The payload of the worker.
+    /// * `size_target` - The size target to search for.
+    /// * `file_path` - The path of the original image in the docker container.
+    /// * `commit` - The libjxl commit of the run.
+    /// * `cjxl_extra_args` - The extra cjxl arguments for the effort of the search.
+    ///
+    /// # Returns
+    /// An error if the comparison failed, or a Cancelled error if the run was cancelled.
+    fn run_size_target(
+        docker_manager: &DockerManager,
+        payload: &WorkerPayload,
+        size_target: &SizeTarget,
+        file_path: &str,
+        commit: &str,
+        cjxl_extra_args: &[String],
+    ) -> Result<(), Box<dyn Error>> {
+        let effort = size_target.effort;
+        let comp_paths = |distance: f64| {
+            let comp_image_name =
+                ImageReader::compressed_image_name(&payload.current_image_name, distance, effort);
+            (
+                comp_image_name.clone(),
+                format!("/temp/{}", comp_image_name),
+                format!("{}/{}", payload.current_out_comp_path, comp_image_name),
+            )
+        };
+        let encode = |distance: f64| -> Option<(String, String)> {
+            let (comp_image_name, src_path, dest_path) = comp_paths(distance);
+            docker_manager
+                .execute_cjxl(
+                    file_path.to_string(),
+                    comp_image_name,
+                    distance,
+                    None,
+                    effort,
+                    payload.context.modular,
+                    cjxl_extra_args,
+                )
+                .ok()?
+                .ok()?;
+            docker_manager
+                .retrieve_file(src_path.clone(), dest_path.clone())
+                .ok()?;
+            Some((src_path, dest_path))
+        };
+
+        let search = JXLCompressionBenchmark::search_size_distance(size_target, |distance| {
+            let (_, dest_path) = encode(distance)?;
+            fs::metadata(dest_path).ok().map(|metadata| metadata.len())
+        });
+
+        // The encodes of the search are refused once the run was cancelled, which is not a
+        // failed search.
+        payload.cancellation.check()?;
+        let (distance, file_size, iterations, status) = match search {
+            Some(search) => search,
+            None => {
+                warn!(
+                    "size target search failed for {}",
+                    payload.current_image_name
+                );
+                return Ok(());
+            }
+        };
+        if status == SizeTargetStatus::Unreachable {
+            warn!(
+                "{} is larger than {} bytes even at distance {}",
+                payload.current_image_name,
+                size_target.target_bytes,
+                SizeTarget::MAX_DISTANCE
+            );
+        }
+
+        // Re-encode at the found distance in case a later step overwrote the output, then
+        // compare it to the original like any other encode.
+        let (src_path, dest_path) = match encode(distance) {
+            Some(paths) => paths,
+            None => {
+                payload.cancellation.check()?;
+                warn!(
+                    "size target encode failed for {}",
+                    payload.current_image_name
+                );
+                return Ok(());
+            }
+        };
+        let _decode_permit = payload.decode_limiter.acquire();
+        let mut image_file_data = ImageReader::try_new_with_limits(
+            dest_path,
+            commit.to_string(),
+            &payload.context.image_limits,
+        )?
+        .file_data;
+        if payload.context.cross_check_decode {
+            JXLCompressionBenchmark::cross_check_decode(&mut image_file_data, None);
+        }
+        if let Ok(Ok(output)) = docker_manager.execute_jxlinfo(&src_path) {
+            ImageReader::apply_jxlinfo(&mut image_file_data, &output);
+        }
+        let result_file = format!("{}/results.csv", payload.current_res_comp_path);
+        payload
+            .csv_sink
+            .lock()
+            .unwrap()
+            .append(
+                &ImageFileDataCSV::new(),
+                &vec![image_file_data.clone()],
+                &result_file,
+            )
+            .unwrap();
+        let comparison_result = JXLCompressionBenchmark::compare_to_orig(
+            &image_file_data,
+            &payload.current_out_comp_path,
+            &payload.current_res_orig_path,
+            &payload.current_res_comp_path,
+            docker_manager,
+            file_path,
+            &src_path,
+            &payload.context,
+            &ResourceUsage::default(),
+            None,
+            None,
+            &payload.csv_sink,
+            None,
+        )?;
+
+        // Record the search result with the quality the size resulted in.
+        let quality = |metric: fn(&ComparisonResult) -> f64| {
+            comparison_result.as_ref().map_or(f64::NAN, metric)
+        };
+        let result = SizeTargetResult {
+            image_name: payload.current_image_name.clone(),
+            target_size: size_target.target_bytes,
+            distance,
+            effort,
+            file_size,
+            bpp: JXLCompressionBenchmark::bits_per_pixel(
+                file_size,
+                image_file_data.width,
+                image_file_data.height,
+            ),
+            psnr: quality(|result| result.psnr),
+            ssimulacra2: quality(|result| result.ssimulacra2),
+            butteraugli: quality(|result| result.butteraugli),
+            iterations,
+            status: status.name().to_string(),
+        };
+        let result_file = format!("{}/size_target.csv", payload.current_res_comp_path);
+        payload
+            .csv_sink
+            .lock()
+            .unwrap()
+            .append(&SizeTargetResultCSV::new(), &vec![result], &result_file)
+            .unwrap();
+        Ok(())
+    }
+
+    /// Runs the quality target search for the current image of a worker at an effort.
+    /// Each step encodes the image at the searched distance and measures the target metric.
+    /// The encode at the found distance is compared to the original like a sweep encode, and the
+    /// search result is written to `quality_target.csv`.
+    ///
+    /// # Arguments
+    /// * `docker_manager` - The DockerManager of the worker.
+    /// * `payload` - The payload of the worker.
+    /// * `quality_target` - The quality target to search for.
+    /// * `effort` - The effort of all encodes of the search.
+    /// * `file_path` - The path of the original image in the docker container.
+    /// * `orig_image_file_path` - The local path of the original image.
+    /// * `commit` - The libjxl commit of the run.
+    /// * `cjxl_extra_args` - The extra cjxl arguments for the effort of the search.
+    ///
+    /// # Returns
+    /// The point of the effort curve of the image at the effort, or None if the search failed,
+    /// or an error if the encode at the found distance failed or the run was cancelled.
+    fn run_quality_target(
+        docker_manager: &DockerManager,
+        payload: &WorkerPayload,
+        quality_target: &QualityTarget,
+        effort: u32,
+        file_path: &str,
+        orig_image_file_path: &str,
+        commit: &str,
+        cjxl_extra_args: &[String],
+    ) -> Result<Option<EffortCurvePoint>, Box<dyn Error>> {
+        let comp_paths = |distance: f64| {
+            let comp_image_name =
+                ImageReader::compressed_image_name(&payload.current_image_name, distance, effort);
+            (
+                comp_image_name.clone(),
+                format!("/temp/{}", comp_image_name),
+                format!("{}/{}", payload.current_out_comp_path, comp_image_name),
+            )
+        };
+
+        let measure = |distance: f64| -> Result<f64, Box<dyn Error>> {
+            let (comp_image_name, src_path, dest_path) = comp_paths(distance);
+            docker_manager.execute_cjxl(
+                file_path.to_string(),
+                comp_image_name,
+                distance,
+                None,
+                effort,
+                payload.context.modular,
+                cjxl_extra_args,
+            )??;
+            docker_manager.retrieve_file(src_path.clone(), dest_path.clone())?;
+            Ok(match quality_target.metric {
+                MetricKind::Butteraugli => {
+                    calculate_butteraugli(
+                        file_path,
+                        &src_path,
+                        docker_manager,
+                        payload.context.intensity_target,
+                    )?
+                    .0
+                }
+                MetricKind::Ssimulacra2 => calculate_ssimulacra2(
+                    file_path,
+                    &src_path,
+                    docker_manager,
+                    payload.context.intensity_target,
+                )?,
+                kind => {
+                    let _decode_permit = payload.decode_limiter.acquire();
+                    let metric_set = compare_images(
+                        Path::new(orig_image_file_path),
+                        Path::new(&dest_path),
+                        &[kind],
+                    )?;
+                    metric_set
+                        .mse
+                        .or(metric_set.psnr)
+                        .or(metric_set.ssim)
+                        .or(metric_set.ms_ssim)
+                        .ok_or(format!("{} was not computed", kind.name()))?
+                }
+            })
+        };
+        let search = JXLCompressionBenchmark::search_distance(quality_target, |distance| {
+            measure(distance)
+                .map_err(|e| {
+                    warn!(
+                        "quality target search of {} at distance {} failed: {}",
+                        payload.current_image_name, distance, e
+                    )
+                })
+                .ok()
+        });
+
+        // The encodes of the search are refused once the run was cancelled, which is not a
+        // failed search.
+        payload.cancellation.check()?;
+        let (distance, value, iterations, converged) = match search {
+            Some(search) => search,
+            None => {
+                warn!(
+                    "quality target search failed for {} at effort {}",
+                    payload.current_image_name, effort
+                );
+                return Ok(None);
+            }
+        };
+
+        // Re-encode at the found distance in case a later step overwrote the output, then
+        // compare it to the original like any other encode. The encode is timed for the curve.
+        let (comp_image_name, src_path, dest_path) = comp_paths(distance);
+        let encode_start = Instant::now();
+        docker_manager.execute_cjxl(
+            file_path.to_string(),
+            comp_image_name,
+            distance,
+            None,
+            effort,
+            payload.context.modular,
+            cjxl_extra_args,
+        )??;
+        let encode_time_ms = encode_start.elapsed().as_secs_f64() * 1000.0;
+        docker_manager.retrieve_file(src_path.clone(), dest_path.clone())?;
+        let _decode_permit = payload.decode_limiter.acquire();
+        let mut image_file_data = ImageReader::try_new_with_limits(
+            dest_path,
+            commit.to_string(),
+            &payload.context.image_limits,
+        )?
+        .file_data;
+        if payload.context.cross_check_decode {
+            JXLCompressionBenchmark::cross_check_decode(&mut image_file_data, None);
+        }
+        if let Ok(Ok(output)) = docker_manager.execute_jxlinfo(&src_path) {
+            ImageReader::apply_jxlinfo(&mut image_file_data, &output);
+        }
+        let result_file = format!("{}/results.csv", payload.current_res_comp_path);
+        payload.csv_sink.lock().unwrap().append(
+            &ImageFileDataCSV::new(),
+            &vec![image_file_data.clone()],
+            &result_file,
+        )?;
+        JXLCompressionBenchmark::compare_to_orig(
+            &image_file_data,
+            &payload.current_out_comp_path,
+            &payload.current_res_orig_path,
+            &payload.current_res_comp_path,
+            docker_manager,
+            file_path,
+            &src_path,
+            &payload.context,
+            &ResourceUsage::default(),
+            None,
+            None,
+            &payload.csv_sink,
+            None,
+        )?;
+
+        // Record the search result.
+        let result = QualityTargetResult {
+            image_name: payload.current_image_name.clone(),
+            metric: quality_target.metric.name().to_string(),
+            target: quality_target.target,
+            distance,
+            effort,
+            value,
+            bpp: JXLCompressionBenchmark::bits_per_pixel(
+                image_file_data.file_size as u64,
+                image_file_data.width,
+                image_file_data.height,
+            ),
+            iterations,
+            converged,
+        };
+        let result_file = format!("{}/quality_target.csv", payload.current_res_comp_path);
+        payload.csv_sink.lock().unwrap().append(
+            &QualityTargetResultCSV::new(),
+            &vec![result.clone()],
+            &result_file,
+        )?;
+
+        Ok(Some(EffortCurvePoint {
+            image_name: result.image_name,
+            effort,
+            metric: result.metric,
+            target: result.target,
+            distance,
+            value,
+            bpp: result.bpp,
+            encode_time_ms,
+            converged,
+        }))
+    }
+
+    /// Writes the effort curve of an image to `{image_name}/effort_curve.csv` in the result
+    /// directory, one row per effort in increasing order. Any previous curve is replaced.
+    ///
+    /// # Arguments
+    /// * `res_comp_path` - The result directory of the compressed images.
+    /// * `image_name` - The name of the image without extension.
+    /// * `effort_curve` - The points of the curve, sorted in place by effort.
+    /// * `float_digits` - The number of significant digits floating point values are written
+    ///   with, 0 for full precision.
+    ///
+    /// # Returns
+    /// The path to the effort curve file.
+    pub fn write_effort_curve(
+        res_comp_path: &str,
+        image_name: &str,
+        effort_curve: &mut [EffortCurvePoint],
+        float_digits: usize,
+    ) -> String {
+        effort_curve.sort_by_key(|point| point.effort);
+        let curve_file = format!("{}/{}/effort_curve.csv", res_comp_path, image_name);
+        let _ = fs::remove_file(&curve_file);
+        let csv_writer = EffortCurvePointCSV::new();
+        csv_writer.write_csv_header(&curve_file).unwrap();
+        csv_writer
+            .write_csv(&effort_curve.to_vec(), &curve_file, float_digits)
+            .unwrap();
+        curve_file
+    }
+
+    /// Decodes a compressed image with the jxl-oxide decoder as well and records whether it
+    /// disagrees with the jpegxl_rs decoder, warning if it does.
+    /// An image that cannot be cross-checked is warned about and recorded as agreeing.
+    ///
+    /// # Arguments
+    /// * `image_file_data` - The file data of the compressed image.
+    /// * `comp_bytes` - The compressed image if it is held in memory instead of on disk.
+    pub fn cross_check_decode(image_file_data: &mut ImageFileData, comp_bytes: Option<&[u8]>) {
+        let disagree = match comp_bytes {
+            Some(comp_bytes) => ImageReader::decoders_disagree(comp_bytes),
+            None => match fs::read(&image_file_data.file_path) {
+                Ok(sample) => ImageReader::decoders_disagree(&sample),
+                Err(e) => Err(Box::from(e)),
+            },
+        };
+        match disagree {
+            Ok(disagree) => {
+                if disagree {
+                    warn!(
+                        "the jxl-oxide and jpegxl_rs decoders disagree on {}",
+                        image_file_data.image_name
+                    );
+                }
+                image_file_data.decoder_mismatch = disagree;
+            }
+            Err(e) => warn!(
+                "could not cross-check the decode of {}: {}",
+                image_file_data.image_name, e
+            ),
+        }
+    }
+
+    /// Checks that an encode produced a non-empty image that decodes to the dimensions of the
+    /// original, so that metrics are never computed on garbage.
+    ///
+    /// # Arguments
+    /// * `image_reader` - The result of reading the compressed image.
+    /// * `width` - The width of the original image.
+    /// * `height` - The height of the original image.
+    ///
+    /// # Returns
+    /// The compressed image file data, or the reason the encode is invalid.
+    pub fn validate_encode(
+        image_reader: Result<ImageReader, Box<dyn Error>>,
+        width: u32,
+        height: u32,
+    ) -> Result<ImageFileData, String> {
+        let image_file_data = image_reader.map_err(|e| e.to_string())?.file_data;
+        if image_file_data.file_size == 0 {
+            return Err("the compressed file is empty".to_string());
+        }
+        if (image_file_data.width, image_file_data.height) != (width, height) {
+            return Err(format!(
+                "the compressed image is {}x{} instead of {}x{}",
+                image_file_data.width, image_file_data.height, width, height
+            ));
+        }
+        Ok(image_file_data)
+    }
+
+    /// Checks the metrics of a comparison result against their plausible ranges. Implausible
+    /// metrics are reported with a warning, and in strict validation mark the result with the
+    /// "implausible_metrics" status so it is left out of the summaries.
+    ///
+    /// # Arguments
+    /// * `comparison_result` - The comparison result to check.
+    /// * `validation` - How implausible metrics are handled.
+    ///
+    /// # Returns
+    /// A description of each implausible metric, empty if all are plausible or validation is off.
+    pub fn validate_metrics(
+        comparison_result: &mut ComparisonResult,
+        validation: MetricValidation,
+    ) -> Vec<String> {
+        if validation == MetricValidation::Off {
+            return Vec::new();
+        }
+        let implausible = comparison_result.implausible_metrics();
+        if implausible.is_empty() {
+            return implausible;
+        }
+        match validation {
+            MetricValidation::Strict => {
+                error!(
+                    "implausible metrics for {}: {}",
+                    comparison_result.comp_image_name,
+                    implausible.join(", ")
+                );
+                comparison_result.status = ComparisonResult::STATUS_IMPLAUSIBLE_METRICS.to_string();
+            }
+            _ => warn!(
+                "implausible metrics for {}, which usually means a parsing bug or mismatched inputs: {}",
+                comparison_result.comp_image_name,
+                implausible.join(", ")
+            ),
+        }
+        implausible
+    }
+
+    /// Creates the "encode_failed" comparison result of a failed encode.
+    ///
+    /// # Arguments
+    /// * `orig_image_name` - The file name of the original image.
+    /// * `comp_image_name` - The file name the compressed image would have had.
+    /// * `distance` - The distance of the encode.
+    /// * `quality` - The cjxl quality of the encode in quality mode.
+    /// * `effort` - The effort of the encode.
+    /// * `source_is_lossy` - Whether the original image was already lossy.
+    /// * `context` - The context of the worker.
+    ///
+    /// # Returns
+    /// The comparison result of the failed encode.
+    fn failed_encode(
+        orig_image_name: &str,
+        comp_image_name: &str,
+        distance: f64,
+        quality: Option<f64>,
+        effort: u32,
+        source_is_lossy: bool,
+        context: &Context,
+    ) -> ComparisonResult {
+        let mut comparison_result = ComparisonResult::encode_failed(
+            orig_image_name,
+            comp_image_name,
+            distance as f32,
+            effort,
+        );
+        comparison_result.coding_mode = JXLCompressionBenchmark::coding_mode(context.modular);
+        comparison_result.size_ratio = context.size_ratio;
+        comparison_result.roi = context.roi.map(|roi| roi.to_string()).unwrap_or_default();
+        comparison_result.quality = quality.map(|q| q.to_string()).unwrap_or_default();
+        comparison_result.source_is_lossy = source_is_lossy;
+        comparison_result.external_metrics = context
+            .external_metrics
+            .iter()
+            .map(|metric| (metric.name.clone(), f64::NAN))
+            .collect();
+        comparison_result
+    }
+
+    /// Appends the comparison result of a failed encode to the comparison results, so the
+    /// failure shows up in the CSV instead of silently missing.
+    ///
+    /// # Arguments
+    /// * `res_comp_path` - The compressed image results path.
+    /// * `output_format` - The format the comparison results are written in.
+    /// * `comparison_result` - The comparison result of the failed encode.
+    /// * `csv_sink` - The sink the comparison results are written through.
+    pub fn record_encode_failure(
+        res_comp_path: &str,
+        output_format: OutputFormat,
+        comparison_result: &ComparisonResult,
+        csv_sink: &Mutex<CsvSink>,
+    ) {
+        append_comparisons(
+            &mut csv_sink.lock().unwrap(),
+            &output_format.comparisons_file(res_comp_path),
+            &vec![comparison_result.clone()],
+        )
+        .unwrap();
+    }
+
+    /// Calculates the bits per pixel of a compressed image.
+    ///
+    /// # Arguments
+    /// * `file_size` - The size of the compressed file in bytes.
+    /// * `width` - The width of the image.
+    /// * `height` - The height of the image.
+    ///
+    /// # Returns
+    /// The number of bits per pixel, or 0 for an empty image.
+    pub fn bits_per_pixel(file_size: u64, width: u32, height: u32) -> f64 {
+        let pixels = width as u64 * height as u64;
+        if pixels == 0 {
+            return 0.0;
+        }
+        (file_size * 8) as f64 / pixels as f64
+    }
+
+    /// Finds the lossless master of an image in the master directory. Masters are matched by
+    /// image name, so a PNG master is found for a JPEG original of the same name.
+    ///
+    /// # Arguments
+    /// * `master_dir` - The directory holding the lossless masters.
+    /// * `image_name` - The file name of the original image.
+    ///
+    /// # Returns
+    /// The path of the master, or None if the directory holds no master for the image.
+    pub fn find_master(master_dir: &str, image_name: &str) -> Option<String> {
+        let image_name = Benchmarker::image_name(Path::new(image_name));
+        let mut masters = fs::read_dir(master_dir)
+            .ok()?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.is_file())
+            .filter(|path| {
+                !matches!(
+                    ImageFormat::from_file_name(path.to_str().unwrap()),
+                    ImageFormat::Unsupported
+                )
+            })
+            .filter(|path| Benchmarker::image_name(path) == image_name)
+            .collect::<Vec<PathBuf>>();
+        masters.sort();
+        masters
+            .first()
+            .map(|path| path.to_str().unwrap().to_string())
+    }
+
+    /// Compares a decoded compressed image against the lossless master of its original with the
+    /// native metrics.
+    ///
+    /// # Arguments
+    /// * `master_path` - The path of the master, already cropped to the region of interest.
+    /// * `comp_pixels` - The interleaved RGB samples of the compressed image.
+    /// * `width` - The width of the compressed image.
+    /// * `height` - The height of the compressed image.
+    /// * `ssim_window` - The window SSIM and MS-SSIM are computed over.
+    ///
+    /// # Returns
+    /// The MSE, PSNR, SSIM and MS-SSIM against the master, or an error if the master cannot be
+    /// decoded or its dimensions differ from the compressed image.
+    pub fn compare_to_master(
+        master_path: &str,
+        comp_pixels: &[u8],
+        width: u32,
+        height: u32,
+        ssim_window: &SsimWindow,
+    ) -> Result<MetricSet, String> {
+        let (master_width, master_height, master_pixels) =
+            ImageReader::decode_rgb8(master_path).map_err(|e| e.to_string())?;
+        if (master_width, master_height) != (width, height) {
+            return Err(format!(
+                "the master {} is {}x{} but the compressed image is {}x{}",
+                master_path, master_width, master_height, width, height
+            ));
+        }
+        Ok(compare_pixels(
+            &master_pixels,
+            comp_pixels,
+            width,
+            height,
+            &[
+                MetricKind::Mse,
+                MetricKind::Psnr,
+                MetricKind::Ssim,
+                MetricKind::MsSsim,
+            ],
+            ssim_window,
+        ))
+    }
+
+    /// Gets the number of bytes a progressive decode truncates a bitstream to.
+    ///
+    /// # Arguments
+    /// * `file_size` - The size of the bitstream in bytes.
+    /// * `truncation` - The percentage of the bitstream that is decoded.
+    ///
+    /// # Returns
+    /// The size of the prefix, rounded up to at least one byte.
+    pub fn truncated_size(file_size: u64, truncation: f64) -> u64 {
+        ((file_size as f64 * truncation / 100.0).ceil() as u64).clamp(1, file_size.max(1))
+    }
+
+    /// Decodes prefixes of a compressed image with djxl in the container and compares each
+    /// decoded image to the original, characterizing the quality of the image while it loads.
+    ///
+    /// # Arguments
+    /// * `comp_bytes` - The compressed image.
+    /// * `comp_stem` - The file stem of the compressed image, which names the prefixes.
+    /// * `orig_pixels` - The interleaved 8-bit RGB samples of the original image.
+    /// * `width` - The width of the original image.
+    /// * `height` - The height of the original image.
+    /// * `res_comp_path` - The compressed image results path, the prefixes are written under it
+    ///   before they are copied into the container.
+    /// * `docker_manager` - The DockerManager to decode the prefixes with.
+    /// * `context` - The context of the worker, with the truncations to decode.
+    ///
+    /// # Returns
+    /// One progressive decode per truncation, in the configured order. A prefix that cannot be
+    /// decoded is reported and recorded as NaN.
+    pub fn progressive_decodes(
+        comp_bytes: &[u8],
+        comp_stem: &str,
+        orig_pixels: &[u8],
+        width: u32,
+        height: u32,
+        res_comp_path: &str,
+        docker_manager: &DockerManager,
+        context: &Context,
+    ) -> Vec<ProgressiveDecode> {
+        context
+            .progressive_truncations
+            .iter()
+            .map(|&truncation| {
+                let bytes =
+                    JXLCompressionBenchmark::truncated_size(comp_bytes.len() as u64, truncation);
+                let metric_set = JXLCompressionBenchmark::decode_prefix(
+                    &comp_bytes[..bytes as usize],
+                    &format!("{}.{}", comp_stem, bytes),
+                    res_comp_path,
+                    docker_manager,
+                )
+                .and_then(|(prefix_width, prefix_height, prefix_pixels)| {
+                    if (prefix_width, prefix_height) != (width, height) {
+                        return Err(Box::from(format!(
+                            "the decoded image is {}x{} but the original is {}x{}",
+                            prefix_width, prefix_height, width, height
+                        )));
+                    }
+                    Ok(compare_pixels(
+                        orig_pixels,
+                        &prefix_pixels,
+                        width,
+                        height,
+                        &[MetricKind::Mse, MetricKind::Psnr, MetricKind::Ssim],
+                        &context.ssim_window,
+                    ))
+                })
+                .unwrap_or_else(|e| {
+                    warn!(
+                        "progressive decode of {}% of {} failed: {}",
+                        truncation, comp_stem, e
+                    );
+                    MetricSet::default()
+                });
+                ProgressiveDecode {
+                    truncation,
+                    bytes,
+                    mse: metric_set.mse.unwrap_or(f64::NAN),
+                    psnr: metric_set.psnr.unwrap_or(f64::NAN),
+                    ssim: metric_set.ssim.unwrap_or(f64::NAN),
+                }
+            })
+            .collect()
+    }
+
+    /// Decodes a prefix of a compressed image with djxl in the container.
+    /// The prefix is written locally, copied into the container and decoded to a PNG, which is
+    /// read back into memory. The local and container files are removed afterwards.
+    ///
+    /// # Arguments
+    /// * `prefix` - The prefix of the compressed image.
+    /// * `name` - The file stem of the prefix.
+    /// * `res_comp_path` - The compressed image results path, the prefix is written under it.
+    /// * `docker_manager` - The DockerManager to decode the prefix with.
+    ///
+    /// # Returns
+    /// The width, height and interleaved RGB samples of the decoded image, or an error if djxl
+    /// cannot decode the prefix.
+    fn decode_prefix(
+        prefix: &[u8],
+        name: &str,
+        res_comp_path: &str,
+        docker_manager: &DockerManager,
+    ) -> Result<(u32, u32, Vec<u8>), Box<dyn Error>> {
+        let local_path = format!("{}/progressive/{}.jxl", res_comp_path, name);
+        let docker_input_path = format!("/temp/progressive/{}.jxl", name);
+        let docker_output_path = format!("/temp/progressive/{}.png", name);
+        fs::create_dir_all(format!("{}/progressive", res_comp_path))?;
+        fs::write(&local_path, prefix)?;
+        let decoded = docker_manager
+            .execute_in_container("mkdir", vec!["-p", "/temp/progressive"])?
+            .and_then(|_| {
+                docker_manager
+                    .copy_file_to_container(local_path.clone(), docker_input_path.clone())
+                    .map_err(|e| e.to_string())
+            })
+            .and_then(|_| {
+                docker_manager
+                    .execute_djxl_partial(docker_input_path.clone(), docker_output_path.clone())
+                    .map_err(|e| e.to_string())?
+            })
+            .and_then(|_| {
+                docker_manager
+                    .retrieve_file_bytes(docker_output_path.clone())
+                    .map_err(|e| e.to_string())
+            });
+        let _ = fs::remove_file(&local_path);
+        let _ = docker_manager.execute_in_container(
+            "rm",
+            vec!["-f", docker_input_path.as_str(), docker_output_path.as_str()],
+        );
+
+        let image = image::load_from_memory(&decoded?)?.to_rgb8();
+        Ok((image.width(), image.height(), image.into_raw()))
+    }
+
+    /// Averages the comparison results of a test set per distance and effort combination.
+    /// Combinations are only reported for the images they were recorded for, so a set where some
+    /// encodes were skipped still aggregates the remaining images.
+    ///
+    /// # Arguments
+    /// * `test_set` - The name of the test set.
+    /// * `results` - The comparison results of all images in the test set.
+    /// * `weighting` - How the images are weighted in the averages.
+    ///
+    /// # Returns
+    /// One aggregate per distance and effort combination, ordered by distance then effort.
+    /// Averages are NaN if no image of a combination has a weight.
+    pub fn aggregate_results(
+        test_set: &str,
+        results: &Vec<ComparisonResult>,
+        weighting: SummaryWeighting,
+    ) -> Vec<AggregateResult> {
+        // Group the results by distance and effort, leaving out failed encodes.
+        let mut groups: Vec<((DistanceKey, u32), Vec<&ComparisonResult>)> = Vec::new();
+        for result in results.iter().filter(|result| result.is_ok()) {
+            let key = (result.distance_key(), result.effort);
+            match groups.iter_mut().find(|(group_key, _)| *group_key == key) {
+                Some((_, group)) => group.push(result),
+                None => groups.push((key, vec![result])),
+            }
+        }
+        groups.sort_by_key(|(key, _)| *key);
+
+        // Average every metric within each group.
+        groups
+            .into_iter()
+            .map(|((_, effort), group)| {
+                // The distance is reported as recorded for the first image of the group.
+                let distance = group[0].distance;
+                let weights = group
+                    .iter()
+                    .map(|result| weighting.weight(result))
+                    .collect::<Vec<f64>>();
+                let avg = |value: fn(&ComparisonResult) -> f64| {
+                    let values = group
+                        .iter()
+                        .map(|result| value(result))
+                        .collect::<Vec<f64>>();
+                    weighted_mean(&values, &weights).unwrap_or(f64::NAN)
+                };
+                AggregateResult {
+                    test_set: test_set.to_string(),
+                    distance,
+                    effort,
+                    num_images: group.len(),
+                    avg_comp_file_size_ratio: avg(|result| result.comp_file_size_ratio),
+                    avg_raw_file_size_ratio: avg(|result| result.raw_file_size_ratio),
+                    avg_mse: avg(|result| result.mse),
+                    avg_psnr: avg(|result| result.psnr),
+                    avg_ssim: avg(|result| result.ssim),
+                    avg_ms_ssim: avg(|result| result.ms_ssim),
+                    avg_butteraugli: avg(|result| result.butteraugli),
+                    avg_butteraugli_pnorm: avg(|result| result.butteraugli_pnorm),
+                    avg_ssimulacra2: avg(|result| result.ssimulacra2),
+                    avg_bpp: avg(|result| result.bpp),
+                    weighting: weighting.name().to_string(),
+                }
+            })
+            .collect()
+    }
+
+    /// Sorts the rows of a comparison results CSV file by image name, distance and effort.
+    /// Workers append rows as they finish, so the order would otherwise vary between runs.
+    ///
+    /// # Arguments
+    /// * `comparisons_file` - The comparison results CSV file to sort in place.
+    /// * `float_digits` - The number of significant digits floating point values are written
+    ///   with, 0 for full precision.
+    pub fn sort_comparisons(comparisons_file: &str, float_digits: usize) {
+        if !PathBuf::from(comparisons_file).exists() {
+            return;
+        }
+        let mut results = read_comparisons(comparisons_file).unwrap();
+        results.sort_by(|a, b| {
+            a.orig_image_name
+                .cmp(&b.orig_image_name)
+                .then(a.distance_key().cmp(&b.distance_key()))
+                .then(a.effort.cmp(&b.effort))
+        });
+
+        write_comparisons(comparisons_file, &results, float_digits).unwrap();
+    }
+
+    /// Writes the aggregate table of a test set next to its comparison results.
+    /// Any previous aggregate table is replaced.
+    ///
+    /// # Arguments
+    /// * `test_set` - The name of the test set.
+    /// * `comparisons_file` - The comparison results CSV file of the test set.
+    /// * `weighting` - How the images are weighted in the averages.
+    /// * `float_digits` - The number of significant digits floating point values are written
+    ///   with, 0 for full precision.
+    pub fn write_aggregate(
+        test_set: &str,
+        comparisons_file: &str,
+        weighting: SummaryWeighting,
+        float_digits: usize,
+    ) {
+        // A test set without any recorded comparisons has nothing to aggregate.
+        if !PathBuf::from(comparisons_file).exists() {
+            return;
+        }
+        let results = read_comparisons(comparisons_file).unwrap();
+        let aggregates = JXLCompressionBenchmark::aggregate_results(test_set, &results, weighting);
+
+        let aggregate_file = format!(
+            "{}/aggregate.csv",
+            PathBuf::from(comparisons_file).parent().unwrap().to_str().unwrap()
+        );
+        let _ = fs::remove_file(&aggregate_file);
+        let size_ratio = results
+            .first()
+            .map(|result| result.size_ratio)
+            .unwrap_or_default();
+        let csv_writer = AggregateResultCSV::new().with_size_ratio(size_ratio);
+        csv_writer.write_csv_header(&aggregate_file).unwrap();
+        csv_writer
+            .write_csv(&aggregates, &aggregate_file, float_digits)
+            .unwrap();
+    }
+
+    /// Averages comparison result differences into a summary row.
+    /// Non-finite differences (e.g. from a lossless encode without a PSNR cap) are skipped so they
+    /// cannot poison the averages, and are counted in the summary's `non_finite_values` instead.
+    ///
+    /// # Arguments
+    /// * `results` - The comparison result differences to summarize.
+    /// * `weights` - The weight of each difference in the averages, see `SummaryWeighting`.
+    ///
+    /// # Returns
+    /// The summary comparison result difference.
+    pub fn summarize_diffs(
+        results: &Vec<ComparisonResultDiff>,
+        weights: &[f64],
+    ) -> ComparisonResultDiff {
+        let mut non_finite_values = 0;
+        let mut average = |value: fn(&ComparisonResultDiff) -> f64| {
+            let (finite, finite_weights): (Vec<f64>, Vec<f64>) = results
+                .iter()
+                .map(value)
+                .zip(weights.iter().copied())
+                .filter(|(value, _)| value.is_finite())
+                .unzip();
+            non_finite_values += (results.len() - finite.len()) as u64;
+            weighted_mean(&finite, &finite_weights).unwrap_or(0.0)
+        };
+
+        ComparisonResultDiff {
+            orig_image_name: "Summary".to_string(),
+            comp_image_name: "Summary".to_string(),
+            distance: 0.0,
+            effort: 0,
+            diff_orig_file_size: average(|result| result.diff_orig_file_size),
+            diff_comp_file_size: average(|result| result.diff_comp_file_size),
+            diff_orig_raw_size: average(|result| result.diff_orig_raw_size),
+            diff_comp_raw_size: average(|result| result.diff_comp_raw_size),
+            diff_comp_file_size_ratio: average(|result| result.diff_comp_file_size_ratio),
+            diff_raw_file_size_ratio: average(|result| result.diff_raw_file_size_ratio),
+            diff_mse: average(|result| result.diff_mse),
+            diff_psnr: average(|result| result.diff_psnr),
+            diff_ssim: average(|result| result.diff_ssim),
+            diff_ms_ssim: average(|result| result.diff_ms_ssim),
+            diff_butteraugli: average(|result| result.diff_butteraugli),
+            diff_butteraugli_pnorm: average(|result| result.diff_butteraugli_pnorm),
+            diff_ssimulacra2: average(|result| result.diff_ssimulacra2),
+            diff_channel_psnr: [
+                average(|result| result.diff_channel_psnr[0]),
+                average(|result| result.diff_channel_psnr[1]),
+                average(|result| result.diff_channel_psnr[2]),
+            ],
+            non_finite_values,
+            baseline_codec: results
+                .first()
+                .map(|result| result.baseline_codec.clone())
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Normalizes the quality setting of a comparison result to a 0 to 100 quality bucket, so
+    /// that results of different codecs can be joined.
+    /// JPEG XL results store the distance, which is converted with the cjxl quality mapping,
+    /// while the results of other codecs store their quality setting in the distance column.
+    ///
+    /// # Arguments
+    /// * `distance` - The distance column of the comparison result.
+    /// * `codec` - The codec of the comparison result, e.g. jxl, jpeg or avif.
+    ///
+    /// # Returns
+    /// The quality rounded to the nearest integer.
+    pub fn quality_bucket(distance: f32, codec: &str) -> i64 {
+        let quality = match codec {
+            "jxl" => match distance as f64 {
+                // Inverse of the mapping cjxl uses for `--quality`.
+                d if d <= 6.4 => 100.0 - (d - 0.1) / 0.09,
+                d => 30.0 - 5.0 * ((d - 6.4) * 6.25).ln() / 2.5f64.ln(),
+            },
+            _ => distance as f64,
+        };
+        quality.clamp(0.0, 100.0).round() as i64
+    }
+
+    /// Calculates the differences between the comparison results of two runs as
+    /// `result_2 - result_1`.
+    /// Runs of two JPEG XL commits are joined on the image, distance and effort. If the first
+    /// run is a baseline of another codec, the results are instead joined on the image and the
+    /// quality bucket. Results without a match are left out.
+    ///
+    /// # Arguments
+    /// * `results_1` - The comparison results of the first run, or of the baseline codec.
+    /// * `results_2` - The comparison results of the second run.
+    /// * `baseline_codec` - The codec of the first run if it is not JPEG XL.
+    ///
+    /// # Returns
+    /// The comparison result differences.
+    pub fn diff_results(
+        results_1: &Vec<ComparisonResult>,
+        results_2: &Vec<ComparisonResult>,
+        baseline_codec: Option<&str>,
+    ) -> Vec<ComparisonResultDiff> {
+        // Sort the comparison results by image name, distance and effort, in case they are not
+        // already in the same order, so that they can be compared.
+        let join_key = |result: &ComparisonResult| {
+            (
+                result.orig_image_name.clone(),
+                result.distance_key(),
+                result.effort,
+            )
+        };
+        let mut comparison_results_1 = results_1.clone();
+        comparison_results_1.sort_by_key(join_key);
+        let mut comparison_results_2 = results_2.clone();
+        comparison_results_2.sort_by_key(join_key);
+
+        // Pair the entries of both runs.
+        let pairs = match baseline_codec {
+            None => {
+                // The compressed image names and distances are not compared as written, since
+                // they can differ in how the distance was formatted.
+                let pairs = comparison_results_2
+                    .iter()
+                    .filter_map(|result_2| {
+                        comparison_results_1
+                            .binary_search_by_key(&join_key(result_2), join_key)
+                            .ok()
+                            .map(|index| (&comparison_results_1[index], result_2))
+                    })
+                    .collect::<Vec<(&ComparisonResult, &ComparisonResult)>>();
+                // Rows of the first run may be paired more than once if a key is repeated, so
+                // the unmatched rows of each run are counted separately.
+                let unmatched_1 = comparison_results_1
+                    .iter()
+                    .filter(|result_1| {
+                        comparison_results_2
+                            .binary_search_by_key(&join_key(result_1), join_key)
+                            .is_err()
+                    })
+                    .count();
+                let unmatched = unmatched_1 + comparison_results_2.len() - pairs.len();
+                if unmatched > 0 {
+                    warn!(
+                        "{} result(s) are only in one of the runs and are not diffed",
+                        unmatched
+                    );
+                }
+                pairs
+            }
+            Some(codec) => comparison_results_2
+                .iter()
+                .filter_map(|result_2| {
+                    let bucket = JXLCompressionBenchmark::quality_bucket(result_2.distance, "jxl");
+                    comparison_results_1
+                        .iter()
+                        .find(|result_1| {
+                            result_1.orig_image_name == result_2.orig_image_name
+                                && JXLCompressionBenchmark::quality_bucket(result_1.distance, codec)
+                                    == bucket
+                        })
+                        .map(|result_1| (result_1, result_2))
+                })
+                .collect(),
+        };
+        let baseline_codec = baseline_codec.unwrap_or("jxl").to_string();
+
+        let mut results = Vec::<ComparisonResultDiff>::new();
+        for (result_1, result_2) in pairs {
+            // Calculate the differences between the comparison results as:
+            //  diff = result_2 - result_1
+            // Failed encodes record 0 as the original sizes, so their originals are unknown.
+            let originals_known = result_1.is_ok() && result_2.is_ok();
+            let diff_orig_file_size = match originals_known {
+                true => result_2.orig_file_size as f64 - result_1.orig_file_size as f64,
+                false => f64::NAN,
+            };
+            let diff_comp_file_size =
+                result_2.comp_file_size as f64 - result_1.comp_file_size as f64;
+            let diff_orig_raw_size = match originals_known {
+                true => result_2.orig_raw_size as f64 - result_1.orig_raw_size as f64,
+                false => f64::NAN,
+            };
+            let diff_comp_raw_size = result_2.comp_raw_size as f64 - result_1.comp_raw_size as f64;
+            let diff_comp_file_size_ratio =
+                result_2.comp_file_size_ratio - result_1.comp_file_size_ratio;
+            let diff_raw_file_size_ratio =
+                result_2.raw_file_size_ratio - result_1.raw_file_size_ratio;
+            let diff_mse = result_2.mse - result_1.mse;
+            let diff_psnr = result_2.psnr - result_1.psnr;
+            let diff_ssim = result_2.ssim - result_1.ssim;
+            let diff_ms_ssim = result_2.ms_ssim - result_1.ms_ssim;
+            let diff_butteraugli = result_2.butteraugli - result_1.butteraugli;
+            let diff_butteraugli_pnorm = result_2.butteraugli_pnorm - result_1.butteraugli_pnorm;
+            let diff_ssimulacra2 = result_2.ssimulacra2 - result_1.ssimulacra2;
+
+            // Channel PSNRs are only comparable in the same channel space.
+            let diff_channel_psnr = match result_2.channel_space == result_1.channel_space {
+                true => [0, 1, 2]
+                    .map(|channel| result_2.channel_psnr[channel] - result_1.channel_psnr[channel]),
+                false => [f64::NAN; 3],
+            };
+
+            // Count the differences that are not finite, e.g. from an uncapped lossless PSNR.
+            let non_finite_values = [
+                diff_comp_file_size_ratio,
+                diff_raw_file_size_ratio,
+                diff_mse,
+                diff_psnr,
+                diff_ssim,
+                diff_ms_ssim,
+                diff_butteraugli,
+                diff_butteraugli_pnorm,
+                diff_ssimulacra2,
+                diff_channel_psnr[0],
+                diff_channel_psnr[1],
+                diff_channel_psnr[2],
+            ]
+            .iter()
+            .filter(|value| !value.is_finite())
+            .count() as u64;
+
+            // Create a comparison result difference struct and add it to the results vector.
+            // The names and settings of the JPEG XL run are kept.
+            results.push(ComparisonResultDiff {
+                orig_image_name: result_2.orig_image_name.clone(),
+                comp_image_name: result_2.comp_image_name.clone(),
+                distance: result_2.distance,
+                effort: result_2.effort,
+                diff_orig_file_size,
                 diff_comp_file_size,
                 diff_orig_raw_size,
                 diff_comp_raw_size,
@@ -794,80 +4221,358 @@ impl JXLCompressionBenchmark {
                 diff_butteraugli,
                 diff_butteraugli_pnorm,
                 diff_ssimulacra2,
+                non_finite_values,
+                baseline_codec: baseline_codec.clone(),
+                diff_channel_psnr,
+            });
+        }
+        results
+    }
+
+    /// Interpolates the comparison results of a run to the rates of a baseline run, so that the
+    /// runs are diffed at equal rate instead of at equal distance, whose meaning can drift
+    /// between commits.
+    /// The successful results of each original image and effort form a curve of the metrics
+    /// over bpp, which is interpolated linearly at the bpp of each successful baseline result.
+    /// The interpolated result takes the distance and name of the baseline result, so that it
+    /// is paired with it. Curves are not extrapolated, so baseline rates outside the range of
+    /// the run are left out.
+    ///
+    /// # Arguments
+    /// * `baseline_results` - The comparison results whose rates are the anchor points.
+    /// * `results` - The comparison results to interpolate.
+    ///
+    /// # Returns
+    /// The interpolated comparison results, one per baseline result within range.
+    pub fn rate_aligned_results(
+        baseline_results: &Vec<ComparisonResult>,
+        results: &Vec<ComparisonResult>,
+    ) -> Vec<ComparisonResult> {
+        let mut aligned = Vec::<ComparisonResult>::new();
+        let mut out_of_range = 0;
+        for baseline in baseline_results
+            .iter()
+            .filter(|result| result.is_ok() && result.bpp.is_finite())
+        {
+            let mut curve = results
+                .iter()
+                .filter(|result| {
+                    result.is_ok()
+                        && result.bpp.is_finite()
+                        && result.orig_image_name == baseline.orig_image_name
+                        && result.effort == baseline.effort
+                })
+                .collect::<Vec<&ComparisonResult>>();
+            curve.sort_by(|a, b| a.bpp.total_cmp(&b.bpp));
+
+            // Find the segment of the curve the rate of the baseline falls in.
+            let segment = curve
+                .windows(2)
+                .find(|pair| pair[0].bpp <= baseline.bpp && baseline.bpp <= pair[1].bpp)
+                .map(|pair| (pair[0], pair[1]))
+                .or_else(|| {
+                    curve
+                        .iter()
+                        .find(|result| result.bpp == baseline.bpp)
+                        .map(|result| (*result, *result))
+                });
+            let (lower, upper) = match segment {
+                Some(segment) => segment,
+                None => {
+                    out_of_range += 1;
+                    continue;
+                }
             };
-            results.push(result);
+            let t = match upper.bpp > lower.bpp {
+                true => (baseline.bpp - lower.bpp) / (upper.bpp - lower.bpp),
+                false => 0.0,
+            };
+            let lerp = |a: f64, b: f64| a + (b - a) * t;
+
+            let mut result = lower.clone();
+            result.comp_image_name = baseline.comp_image_name.clone();
+            result.distance = baseline.distance;
+            result.quality = baseline.quality.clone();
+            result.bpp = baseline.bpp;
+            result.comp_file_size =
+                lerp(lower.comp_file_size as f64, upper.comp_file_size as f64).round() as u64;
+            result.comp_file_size_ratio =
+                lerp(lower.comp_file_size_ratio, upper.comp_file_size_ratio);
+            result.raw_file_size_ratio = lerp(lower.raw_file_size_ratio, upper.raw_file_size_ratio);
+            result.mse = lerp(lower.mse, upper.mse);
+            result.psnr = lerp(lower.psnr, upper.psnr);
+            result.ssim = lerp(lower.ssim, upper.ssim);
+            result.ms_ssim = lerp(lower.ms_ssim, upper.ms_ssim);
+            result.butteraugli = lerp(lower.butteraugli, upper.butteraugli);
+            result.butteraugli_pnorm = lerp(lower.butteraugli_pnorm, upper.butteraugli_pnorm);
+            result.ssimulacra2 = lerp(lower.ssimulacra2, upper.ssimulacra2);
+            result.channel_psnr = [0, 1, 2]
+                .map(|channel| lerp(lower.channel_psnr[channel], upper.channel_psnr[channel]));
+            result.mse_vs_master = lerp(lower.mse_vs_master, upper.mse_vs_master);
+            result.psnr_vs_master = lerp(lower.psnr_vs_master, upper.psnr_vs_master);
+            result.ssim_vs_master = lerp(lower.ssim_vs_master, upper.ssim_vs_master);
+            result.ms_ssim_vs_master = lerp(lower.ms_ssim_vs_master, upper.ms_ssim_vs_master);
+            for (name, value) in result.external_metrics.iter_mut() {
+                let upper_value = upper
+                    .external_metrics
+                    .iter()
+                    .find(|(upper_name, _)| upper_name == name)
+                    .map_or(f64::NAN, |(_, value)| *value);
+                *value = lerp(*value, upper_value);
+            }
+            aligned.push(result);
+        }
+        if out_of_range > 0 {
+            warn!(
+                "{} baseline rate(s) are outside the rates of the compared run and are not diffed",
+                out_of_range
+            );
+        }
+        aligned
+    }
+
+    /// Finds the original images whose file or raw size differs between two compared runs.
+    /// Both runs of a comparison must encode the same source images, so any difference means
+    /// the whole comparison is invalid. Differences of failed encodes are unknown and ignored.
+    ///
+    /// # Arguments
+    /// * `diffs` - The comparison result differences of the two runs.
+    ///
+    /// # Returns
+    /// The names of the mismatching original images, each listed once.
+    pub fn mismatched_originals(diffs: &Vec<ComparisonResultDiff>) -> Vec<String> {
+        let mut names = Vec::<String>::new();
+        for diff in diffs {
+            let mismatch = [diff.diff_orig_file_size, diff.diff_orig_raw_size]
+                .iter()
+                .any(|value| {
+                    value.is_finite()
+                        && value.abs() > JXLCompressionBenchmark::ORIGINAL_SIZE_TOLERANCE
+                });
+            if mismatch && !names.contains(&diff.orig_image_name) {
+                names.push(diff.orig_image_name.clone());
+            }
         }
+        names
+    }
 
-        // Initialize a summary comparison result difference struct as zeros.
-        let mut summary = ComparisonResultDiff {
-            orig_image_name: "Summary".to_string(),
-            comp_image_name: "Summary".to_string(),
-            distance: 0.0,
-            effort: 0,
-            diff_orig_file_size: 0.0,
-            diff_comp_file_size: 0.0,
-            diff_orig_raw_size: 0.0,
-            diff_comp_raw_size: 0.0,
-            diff_comp_file_size_ratio: 0.0,
-            diff_raw_file_size_ratio: 0.0,
-            diff_mse: 0.0,
-            diff_psnr: 0.0,
-            diff_ssim: 0.0,
-            diff_ms_ssim: 0.0,
-            diff_butteraugli: 0.0,
-            diff_butteraugli_pnorm: 0.0,
-            diff_ssimulacra2: 0.0,
-        };
-
-        // Calculate the average differences between the comparison results.
-        for result in &results {
-            summary.diff_orig_file_size += result.diff_orig_file_size;
-            summary.diff_comp_file_size += result.diff_comp_file_size;
-            summary.diff_orig_raw_size += result.diff_orig_raw_size;
-            summary.diff_comp_raw_size += result.diff_comp_raw_size;
-            summary.diff_comp_file_size_ratio += result.diff_comp_file_size_ratio;
-            summary.diff_raw_file_size_ratio += result.diff_raw_file_size_ratio;
-            summary.diff_mse += result.diff_mse;
-            summary.diff_psnr += result.diff_psnr;
-            summary.diff_ssim += result.diff_ssim;
-            summary.diff_ms_ssim += result.diff_ms_ssim;
-            summary.diff_butteraugli += result.diff_butteraugli;
-            summary.diff_butteraugli_pnorm += result.diff_butteraugli_pnorm;
-            summary.diff_ssimulacra2 += result.diff_ssimulacra2;
-        }
-
-        summary.diff_orig_file_size /= results.len() as f64;
-        summary.diff_comp_file_size /= results.len() as f64;
-        summary.diff_orig_raw_size /= results.len() as f64;
-        summary.diff_comp_raw_size /= results.len() as f64;
-        summary.diff_comp_file_size_ratio /= results.len() as f64;
-        summary.diff_raw_file_size_ratio /= results.len() as f64;
-        summary.diff_mse /= results.len() as f64;
-        summary.diff_psnr /= results.len() as f64;
-        summary.diff_ssim /= results.len() as f64;
-        summary.diff_ms_ssim /= results.len() as f64;
-        summary.diff_butteraugli /= results.len() as f64;
-        summary.diff_butteraugli_pnorm /= results.len() as f64;
-        summary.diff_ssimulacra2 /= results.len() as f64;
+    /// Diffs the comparisons of two finished runs without encoding anything, so that any two
+    /// runs can be compared after the fact.
+    /// The comparisons of the last commit benchmarked in each run are diffed for every test set
+    /// both runs recorded comparisons for. The diffs are written to
+    /// `{run_b}/results/diff/{run_a}/{test_set}` in the benchmark directory.
+    ///
+    /// # Arguments
+    /// * `benchmark_dir` - The benchmark directory.
+    /// * `run_a` - The run whose results are the baseline.
+    /// * `run_b` - The run diffed against the baseline.
+    /// * `weighting` - How the images are weighted in the summary.
+    /// * `gates` - The gates the summary of each test set must pass.
+    /// * `baseline_distance` - Whether to diff run B at the rates of run A instead of at equal
+    ///   distance.
+    ///
+    /// # Returns
+    /// The directories the diffs were written to, or an error if a manifest could not be read,
+    /// the runs have no test set in common or a summary violated a gate.
+    pub fn diff_runs(
+        benchmark_dir: &str,
+        run_a: usize,
+        run_b: usize,
+        weighting: SummaryWeighting,
+        gates: &[Gate],
+        baseline_distance: bool,
+    ) -> Result<Vec<String>, Box<dyn Error>> {
+        let manifest_a = RunManifest::load(benchmark_dir, run_a)
+            .map_err(|e| format!("Could not read the manifest of run {}: {}", run_a, e))?;
+        let manifest_b = RunManifest::load(benchmark_dir, run_b)
+            .map_err(|e| format!("Could not read the manifest of run {}: {}", run_b, e))?;
+
+        let mut result_dirs = Vec::<String>::new();
+        let mut gate_failures = Vec::<String>::new();
+        for test_set in &manifest_b.test_sets {
+            let (results_a, results_b) = match (
+                manifest_a.comparisons_file(benchmark_dir, test_set),
+                manifest_b.comparisons_file(benchmark_dir, test_set),
+            ) {
+                (Some(results_a), Some(results_b)) => (results_a, results_b),
+                _ => {
+                    warn!(
+                        "runs {} and {} do not both have comparisons for {}, skipping it",
+                        run_a, run_b, test_set
+                    );
+                    continue;
+                }
+            };
+            let result_dir = format!(
+                "{}/{}/results/diff/{}/{}",
+                benchmark_dir, run_b, run_a, test_set
+            );
+            fs::create_dir_all(&result_dir)?;
+            let summary = JXLCompressionBenchmark::compare_results(
+                &results_a,
+                &results_b,
+                None,
+                &result_dir,
+                weighting,
+                baseline_distance,
+                manifest_b.csv_float_digits,
+            );
+            gate_failures.extend(JXLCompressionBenchmark::check_gates(
+                gates, &summary, test_set,
+            ));
+            result_dirs.push(result_dir);
+        }
+
+        if result_dirs.is_empty() {
+            return Err(Box::new(BenchmarkError::Config(format!(
+                "Runs {} and {} have no comparisons of the same test set",
+                run_a, run_b
+            ))));
+        }
+        if !gate_failures.is_empty() {
+            return Err(Box::new(BenchmarkError::GateFailed(gate_failures)));
+        }
+        Ok(result_dirs)
+    }
+
+    /// Compares JPEG XL benchmarking results from two different commits/versions of the codec,
+    /// or from a run of the codec and a baseline of another codec.
+    /// The comparison results are written to a CSV file in the result directory.
+    ///
+    /// # Arguments
+    /// * `results_1` - The path to the first run's results CSV file, or the baseline CSV file.
+    /// * `results_2` - The path to the second run's results CSV file.
+    /// * `baseline_codec` - The codec of the baseline if `results_1` is not a JPEG XL run.
+    /// * `result_dir` - The directory the differences and their summary are written to.
+    /// * `weighting` - How the images are weighted in the summary.
+    /// * `baseline_distance` - Whether to diff the second run at the rates of the first, see
+    ///   `rate_aligned_results`. Diffs against a baseline codec are paired by quality bucket
+    ///   and are not aligned.
+    /// * `float_digits` - The number of significant digits floating point values are written
+    ///   with, 0 for full precision.
+    ///
+    /// # Returns
+    /// The summary of the differences.
+    fn compare_results(
+        results_1: &str,
+        results_2: &str,
+        baseline_codec: Option<&str>,
+        result_dir: &str,
+        weighting: SummaryWeighting,
+        baseline_distance: bool,
+        float_digits: usize,
+    ) -> ComparisonResultDiff {
+        // Read the comparison results from the CSV or binary files.
+        let comparison_results_1 = read_comparisons(results_1).unwrap();
+        let mut comparison_results_2 = read_comparisons(results_2).unwrap();
+
+        // Interpolate the second run to the rates of the first, so quality is diffed at equal
+        // rate.
+        if baseline_distance && baseline_codec.is_none() {
+            comparison_results_2 = JXLCompressionBenchmark::rate_aligned_results(
+                &comparison_results_1,
+                &comparison_results_2,
+            );
+        }
+
+        // Compare each entry in the results CSVs.
+        let results = JXLCompressionBenchmark::diff_results(
+            &comparison_results_1,
+            &comparison_results_2,
+            baseline_codec,
+        );
+
+        // Both commits must have encoded the same originals for the differences to mean anything.
+        // The originals of a baseline codec may have been stored differently, so they are not
+        // checked.
+        if baseline_codec.is_none() {
+            let mismatched = JXLCompressionBenchmark::mismatched_originals(&results);
+            if !mismatched.is_empty() {
+                warn!("{}", "!".repeat(80));
+                warn!(
+                    "{} original image(s) differ between {} and {}: {}",
+                    mismatched.len(),
+                    results_1,
+                    results_2,
+                    mismatched.join(", ")
+                );
+                warn!("The runs used different source images, so this comparison is invalid.");
+                warn!("{}", "!".repeat(80));
+            }
+        }
+
+        // Size ratios of opposite directions cannot be subtracted.
+        let mut size_ratios = comparison_results_1
+            .iter()
+            .chain(comparison_results_2.iter())
+            .map(|result| result.size_ratio);
+        let first_size_ratio = size_ratios.next();
+        if size_ratios.any(|ratio| Some(ratio) != first_size_ratio) {
+            warn!(
+                "{} and {} record size ratios in different directions, so their differences are invalid.",
+                results_1, results_2
+            );
+        }
+
+        // Average the differences between the comparison results, weighing each difference by
+        // the image of the second run.
+        let weights = results
+            .iter()
+            .map(|diff| {
+                comparison_results_2
+                    .iter()
+                    .find(|result| result.comp_image_name == diff.comp_image_name)
+                    .map_or(0.0, |result| weighting.weight(result))
+            })
+            .collect::<Vec<f64>>();
+        let summary = JXLCompressionBenchmark::summarize_diffs(&results, &weights);
 
         // Initialize a CSV handler for the comparison result differences.
-        let csv_writer = ComparisonResultDiffCSV::new();
+        let size_ratio = comparison_results_2
+            .first()
+            .map(|result| result.size_ratio)
+            .unwrap_or_default();
+        let csv_writer = ComparisonResultDiffCSV::new().with_size_ratio(size_ratio);
 
         // Write the comparison result differences to a CSV file.
-        let result_file = format!(
-            "{}/comparison_diffs.csv",
-            PathBuf::from(results_1).parent().unwrap().to_str().unwrap()
-        );
+        let result_file = format!("{}/comparison_diffs.csv", result_dir);
         csv_writer.write_csv_header(&result_file).unwrap();
-        csv_writer.write_csv(&results, &result_file).unwrap();
+        csv_writer
+            .write_csv(&results, &result_file, float_digits)
+            .unwrap();
 
         // Write the summary to a CSV file.
-        let summary_file = format!(
-            "{}/summary.csv",
-            PathBuf::from(results_1).parent().unwrap().to_str().unwrap()
-        );
+        let summary_file = format!("{}/summary.csv", result_dir);
         csv_writer.write_csv_header(&summary_file).unwrap();
-        csv_writer.write_csv(&vec![summary], &summary_file).unwrap();
+        csv_writer
+            .write_csv(&vec![summary.clone()], &summary_file, float_digits)
+            .unwrap();
+        summary
+    }
+
+    /// Checks the summary of a comparison against gates and logs each violated gate.
+    ///
+    /// # Arguments
+    /// * `gates` - The gates to check.
+    /// * `summary` - The summary of the comparison result differences.
+    /// * `test_set` - The test set of the comparison, named in the violations.
+    ///
+    /// # Returns
+    /// A description of each violated gate.
+    pub fn check_gates(
+        gates: &[Gate],
+        summary: &ComparisonResultDiff,
+        test_set: &str,
+    ) -> Vec<String> {
+        let failures = gates
+            .iter()
+            .filter_map(|gate| gate.check(summary))
+            .map(|failure| format!("{}: {}", test_set, failure))
+            .collect::<Vec<String>>();
+        for failure in &failures {
+            error!("{}", failure);
+        }
+        failures
     }
 
     /// Compares the compressed image to the original image and produces a result CSV file.
@@ -883,95 +4588,631 @@ impl JXLCompressionBenchmark {
     ///   - Multi-Scale Structural Similarity Index (MS-SSIM)
     ///   - Butteraugli (also reports the Butteraugli p-norm)
     ///   - SSIMULACRA2
+    ///   - Any configured external metrics
     ///
     /// # Arguments
     /// * `comp_image_data` - The compressed image file data.
-    /// * `out_comp_path` - The output compressed image path. (Not currently used)
+    /// * `out_comp_path` - The output compressed image path, diff images are written under it.
     /// * `res_orig_path` - The original image results path.
     /// * `res_comp_path` - The compressed image results path.
     /// * `docker_manager` - The DockerManager to use for running the comparison.
     /// * `docker_input_path` - The input path for the Butteraugli and SSIMULACRA2 comparison.
     /// * `docker_output_path` - The output path for the Butteraugli and SSIMULACRA2 comparison.
+    /// * `context` - The context of the worker running the comparison.
+    /// * `resource_usage` - The resources used by the encode, if measured.
+    /// * `comp_bytes` - The compressed image if it is held in memory instead of on disk.
+    /// * `quality` - The cjxl quality the image was encoded with in quality mode.
+    /// * `csv_sink` - The sink the comparison results are written through.
+    ///
+    /// # Returns
+    /// The comparison result, or None if it was skipped as a size regression, or an error if a
+    /// Docker metric failed or the run was cancelled before the result was written.
     fn compare_to_orig(
         comp_image_data: &ImageFileData,
-        _out_comp_path: &str, // not currently used
+        out_comp_path: &str,
         res_orig_path: &str,
         res_comp_path: &str,
         docker_manager: &DockerManager,
         docker_input_path: &str,
         docker_output_path: &str,
-    ) {
+        context: &Context,
+        resource_usage: &ResourceUsage,
+        comp_bytes: Option<&[u8]>,
+        quality: Option<f64>,
+        csv_sink: &Mutex<CsvSink>,
+        contact_sheet: Option<&mut ContactSheet>,
+    ) -> Result<Option<ComparisonResult>, Box<dyn Error>> {
         // Initialize a CSV handler for the orig image file data.
         let csv_writer = ImageFileDataCSV::new();
 
         // Find the original image file data from the original results CSV file.
+        // The original is matched by name without extension, so any source format is found.
         let orig_entry = csv_writer
-            .find_entry(
-                format!("{}/results.csv", res_orig_path).as_str(),
-                0,
-                format!("{}.png", &comp_image_data.jxl_orig_image_name).as_str(),
-            )
+            .read_csv(format!("{}/results.csv", res_orig_path).as_str())
+            .unwrap()
+            .into_iter()
+            .find(|entry| {
+                PathBuf::from(&entry.image_name).file_stem().unwrap().to_str().unwrap()
+                    == comp_image_data.jxl_orig_image_name.to_string()
+            })
             .unwrap();
 
         // Comparison calculations
         // Original file size to compressed file size ratio
-        let comp_file_size_ratio = file_size_ratio(orig_entry.file_size, comp_image_data.file_size, "comp");
+        let size_ratio = context.size_ratio;
+        let comp_file_size_ratio =
+            size_ratio.ratio(orig_entry.file_size, comp_image_data.file_size);
 
         // Raw image size to compressed file size ratio
-        let raw_file_size_ratio = file_size_ratio(comp_image_data.raw_size, comp_image_data.file_size, "comp");
+        let raw_file_size_ratio =
+            size_ratio.ratio(comp_image_data.raw_size, comp_image_data.file_size);
+
+        // Decode speed
+        // The compressed image is decoded repeatedly by djxl in the container after the warm-up
+        // decodes, and the time is taken from the speed djxl reports. A failing decode is
+        // reported and recorded as NaN.
+        let (decode_time_ms_min, decode_time_ms_mean) = match context.decode_speed {
+            true => docker_manager
+                .time_djxl(
+                    docker_output_path,
+                    context.decode_warmup,
+                    context.decode_repeats,
+                )
+                .unwrap_or_else(|e| {
+                    warn!(
+                        "decode timing failed on {}: {}",
+                        comp_image_data.image_name, e
+                    );
+                    (f64::NAN, f64::NAN)
+                }),
+            false => (f64::NAN, f64::NAN),
+        };
 
-        // MSE
-        let mse = calculate_mse(&orig_entry.file_path, &comp_image_data.file_path);
+        // The images the quality metrics are computed on.
+        let mut orig_metric_path = orig_entry.file_path.clone();
+        let mut comp_metric_path = comp_image_data.file_path.clone();
+        let mut docker_input_path = docker_input_path.to_string();
+        let mut docker_output_path = docker_output_path.to_string();
 
-        // PSNR
-        let psnr = calculate_psnr(&orig_entry.file_path, &comp_image_data.file_path, 255.0);
+        // A compressed image held in memory is written to its usual path while its metrics are
+        // computed, so it is measured with the same metric functions as a kept image, and is
+        // removed again when the comparison returns.
+        let _comp_scratch_file = match comp_bytes {
+            Some(comp_bytes) => {
+                fs::write(&comp_metric_path, comp_bytes)?;
+                Some(ScratchFile(comp_metric_path.clone()))
+            }
+            None => None,
+        };
+
+        // Read the original from its decoded PNG reference if references are cached, so that
+        // sources in other formats are decoded once across runs. A reference that cannot be
+        // written is reported and the source is read instead.
+        if let Some(reference_cache_dir) = &context.reference_cache_dir {
+            match ReferenceCache::new(reference_cache_dir).reference(&orig_metric_path) {
+                Ok((reference_path, _)) => orig_metric_path = reference_path,
+                Err(e) => warn!(
+                    "could not cache the reference of {}: {}",
+                    orig_entry.image_name, e
+                ),
+            }
+        }
+
+        // Progressive decode
+        // Prefixes of the whole compressed image are decoded and compared to the whole
+        // original, independent of the region of interest, and written next to the results.
+        if !context.progressive_truncations.is_empty() {
+            let comp_stem = PathBuf::from(&comp_image_data.file_path)
+                .file_stem()
+                .unwrap()
+                .to_str()
+                .unwrap()
+                .to_string();
+            let comp_file_bytes = match comp_bytes {
+                Some(comp_bytes) => comp_bytes.to_vec(),
+                None => fs::read(&comp_image_data.file_path).unwrap(),
+            };
+            let (width, height, orig_pixels) =
+                ImageReader::decode_rgb8_with_limits(&orig_metric_path, &context.image_limits)?;
+            let progressive_decodes = JXLCompressionBenchmark::progressive_decodes(
+                &comp_file_bytes,
+                &comp_stem,
+                &orig_pixels,
+                width,
+                height,
+                res_comp_path,
+                docker_manager,
+                context,
+            );
+            let progressive_file = format!("{}/{}.progressive.csv", res_comp_path, comp_stem);
+            let _ = fs::remove_file(&progressive_file);
+            let csv_writer = ProgressiveDecodeCSV::new();
+            csv_writer.write_csv_header(&progressive_file).unwrap();
+            csv_writer
+                .write_csv(
+                    &progressive_decodes,
+                    &progressive_file,
+                    context.csv_float_digits,
+                )
+                .unwrap();
+        }
+
+        // Crop both images to the region of interest if set.
+        // The cropped images are written next to the compressed image and copied into the
+        // container for the Butteraugli and SSIMULACRA2 comparisons.
+        if let Some(roi) = &context.roi {
+            let comp_stem = PathBuf::from(&comp_image_data.file_path)
+                .file_stem()
+                .unwrap()
+                .to_str()
+                .unwrap()
+                .to_string();
+            let roi_dir = format!("{}/roi", res_comp_path);
+            orig_metric_path = ImageReader::crop_to_png(
+                &orig_entry.file_path,
+                roi,
+                &format!("{}/{}.orig.png", roi_dir, comp_stem),
+            )?;
+            comp_metric_path = ImageReader::crop_to_png(
+                &comp_image_data.file_path,
+                roi,
+                &format!("{}/{}.png", roi_dir, comp_stem),
+            )?;
+
+            docker_input_path = format!("/temp/roi/{}.orig.png", comp_stem);
+            docker_output_path = format!("/temp/roi/{}.png", comp_stem);
+            let _ = docker_manager.execute_in_container("mkdir", vec!["-p", "/temp/roi"])?;
+            docker_manager
+                .copy_file_to_container(orig_metric_path.clone(), docker_input_path.clone())?;
+            docker_manager
+                .copy_file_to_container(comp_metric_path.clone(), docker_output_path.clone())?;
+        }
 
-        // SSIM
-        let ssim = calculate_ssim(&orig_entry.file_path, &comp_image_data.file_path);
+        // Decode both images once if their pixels are compared or shown here: for the
+        // per-channel PSNR, color management, the master comparison, a diff image, an error
+        // histogram or a contact sheet. The other metrics read the images themselves.
+        let mut decoded = match context.channel_space.is_some()
+            || context.color_management
+            || context.master_dir.is_some()
+            || context.diff_images
+            || context.error_histograms
+            || contact_sheet.is_some()
+        {
+            true => {
+                let (width, height, orig_pixels) =
+                    ImageReader::decode_rgb8_with_limits(&orig_metric_path, &context.image_limits)?;
+                let (_, _, comp_pixels) = match comp_bytes {
+                    Some(comp_bytes) => {
+                        context.image_limits.check_jxl(comp_bytes)?;
+                        ImageReader::decode_jxl_rgb8(comp_bytes)?
+                    }
+                    None => ImageReader::decode_rgb8_with_limits(
+                        &comp_metric_path,
+                        &context.image_limits,
+                    )?,
+                };
+                Some((width, height, orig_pixels, comp_pixels))
+            }
+            false => None,
+        };
+
+        // Convert the compressed image to the color space of the original with their embedded
+        // ICC profiles if color management is enabled. The profiles are read from the source
+        // files, since cropped images and cached references are written without one. A profile
+        // that cannot be applied is reported and both images are compared as sRGB.
+        let color_managed = match (context.color_management, &mut decoded) {
+            (true, Some((_, _, _, comp_pixels))) => {
+                let orig_icc = ImageReader::icc_profile(&orig_entry.file_path);
+                let comp_icc = match comp_bytes {
+                    Some(comp_bytes) => ImageReader::jxl_icc_profile(comp_bytes),
+                    None => ImageReader::icc_profile(&comp_image_data.file_path),
+                };
+                to_original_space(orig_icc.as_deref(), comp_pixels, comp_icc.as_deref())
+                    .unwrap_or_else(|e| {
+                        warn!(
+                            "color management failed on {}: {}",
+                            comp_image_data.image_name, e
+                        );
+                        false
+                    })
+            }
+            _ => false,
+        };
+
+        let comp_stem = PathBuf::from(&comp_image_data.file_path)
+            .file_stem()
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_string();
+
+        // Write a heatmap of the per-pixel differences for visual inspection if enabled. A heatmap
+        // that cannot be written, e.g. when the decoded images differ in size, is reported and
+        // skipped, since the metrics below still apply.
+        if let (true, Some((width, height, orig_pixels, comp_pixels))) =
+            (context.diff_images, &decoded)
+        {
+            if let Err(e) = ImageReader::write_diff_image(
+                orig_pixels,
+                comp_pixels,
+                *width,
+                *height,
+                &format!("{}/diff/{}.diff.png", out_comp_path, comp_stem),
+            ) {
+                warn!("diff image failed on {}: {}", comp_image_data.image_name, e);
+            }
+        }
+
+        // Write the distribution of the per-sample errors next to the results if enabled.
+        if let (true, Some((_, _, orig_pixels, comp_pixels))) = (context.error_histograms, &decoded)
+        {
+            let histogram = error_histogram(orig_pixels, comp_pixels)
+                .iter()
+                .zip(ERROR_HISTOGRAM_BINS)
+                .map(|(count, bin)| ErrorHistogramBin {
+                    bin: bin.to_string(),
+                    count: *count,
+                })
+                .collect::<Vec<ErrorHistogramBin>>();
+            let histogram_file = format!("{}/{}.errhist.csv", res_comp_path, comp_stem);
+            let _ = fs::remove_file(&histogram_file);
+            let csv_writer = ErrorHistogramCSV::new();
+            csv_writer.write_csv_header(&histogram_file).unwrap();
+            csv_writer
+                .write_csv(&histogram, &histogram_file, context.csv_float_digits)
+                .unwrap();
+        }
+
+        // Color managed images only exist in memory, so they are compared natively. SSIM is then
+        // computed natively on the luma channel instead of with ImageMagick.
+        let in_memory_metrics = match (color_managed, &decoded) {
+            (true, Some((width, height, orig_pixels, comp_pixels))) => {
+                Some(compare_pixels_parallel(
+                    orig_pixels,
+                    comp_pixels,
+                    *width,
+                    *height,
+                    &[MetricKind::Mse, MetricKind::Ssim, MetricKind::MsSsim],
+                    &context.ssim_window,
+                    context.metric_threads,
+                ))
+            }
+            _ => None,
+        };
+
+        // Metrics against the lossless master
+        // The master is cropped like the original, a missing or mismatched master is reported
+        // and recorded as NaN.
+        let master_metrics = match (&context.master_dir, &decoded) {
+            (Some(master_dir), Some((width, height, _, comp_pixels))) => {
+                match JXLCompressionBenchmark::find_master(master_dir, &orig_entry.image_name) {
+                    Some(master_path) => {
+                        let master_path = match &context.roi {
+                            Some(roi) => ImageReader::crop_to_png(
+                                &master_path,
+                                roi,
+                                &format!("{}/roi/{}.master.png", res_comp_path, comp_stem),
+                            )
+                            .map_err(|e| e.to_string()),
+                            None => Ok(master_path),
+                        };
+                        master_path
+                            .and_then(|master_path| {
+                                JXLCompressionBenchmark::compare_to_master(
+                                    &master_path,
+                                    comp_pixels,
+                                    *width,
+                                    *height,
+                                    &context.ssim_window,
+                                )
+                            })
+                            .unwrap_or_else(|e| {
+                                warn!(
+                                    "master comparison failed on {}: {}",
+                                    comp_image_data.image_name, e
+                                );
+                                MetricSet::default()
+                            })
+                    }
+                    None => {
+                        warn!(
+                            "no master found for {} in {}",
+                            orig_entry.image_name, master_dir
+                        );
+                        MetricSet::default()
+                    }
+                }
+            }
+            _ => MetricSet::default(),
+        };
+
+        // The native metrics all read the same images, so they are computed on up to
+        // `metric_threads` threads at once. The Docker-backed metrics below stay serial.
+        let (mut mse, mut peak) = (0.0, ImageReader::UINT8_PEAK);
+        let mut channel_psnr_values = [f64::NAN; 3];
+        let mut ssim = 0.0;
+        let mut ms_ssim = 0.0;
+        run_metric_jobs(
+            vec![
+                // MSE
+                // The cropped compressed image is a PNG, so it is compared natively.
+                // Float images are compared on a normalized scale, which changes the PSNR peak.
+                Box::new(|| {
+                    (mse, peak) = match (&in_memory_metrics, &context.roi) {
+                        (Some(metric_set), _) => {
+                            (metric_set.mse.unwrap(), ImageReader::UINT8_PEAK)
+                        }
+                        (None, Some(_)) => (
+                            compare_images(
+                                Path::new(&orig_metric_path),
+                                Path::new(&comp_metric_path),
+                                &[MetricKind::Mse],
+                            )
+                            .unwrap()
+                            .mse
+                            .unwrap(),
+                            ImageReader::UINT8_PEAK,
+                        ),
+                        (None, None) => {
+                            calculate_mse_with_peak(&orig_metric_path, &comp_metric_path)
+                        }
+                    }
+                }),
+                Box::new(|| {
+                    if let (Some(space), Some((_, _, orig_pixels, comp_pixels))) =
+                        (context.channel_space, &decoded)
+                    {
+                        channel_psnr_values = channel_psnr(orig_pixels, comp_pixels, space)
+                    }
+                }),
+                // SSIM
+                Box::new(|| {
+                    ssim = match &in_memory_metrics {
+                        Some(metric_set) => metric_set.ssim.unwrap(),
+                        None => calculate_ssim(&orig_metric_path, &comp_metric_path),
+                    }
+                }),
+                // MS-SSIM
+                // Images smaller than the SSIM window have no MS-SSIM, which is reported and
+                // recorded as NaN.
+                Box::new(|| {
+                    ms_ssim = match &in_memory_metrics {
+                        Some(metric_set) => metric_set.ms_ssim.unwrap_or(f64::NAN),
+                        None => calculate_ms_ssim(
+                            &orig_metric_path,
+                            &comp_metric_path,
+                            &context.ssim_window,
+                        )
+                        .unwrap_or_else(|e| {
+                            warn!("MS-SSIM failed on {}: {}", comp_image_data.image_name, e);
+                            f64::NAN
+                        }),
+                    }
+                }),
+            ],
+            context.metric_threads,
+        );
+
+        // PSNR
+        let psnr = ImageReader::calculate_psnr(mse, peak);
 
-        // MS-SSIM
-        // TODO: Implement MS-SSIM.
+        // The expensive Docker metrics are skipped and recorded as NaN outside their sample.
+        let distance: f32 = comp_image_data.jxl_distance.into();
+        let effort: u32 = comp_image_data.jxl_effort.into();
+        let expensive_metrics = context
+            .expensive_metric_sample
+            .as_ref()
+            .map_or(true, |sample| {
+                sample.includes(distance as f64, effort, &context.efforts)
+            });
 
         // Butteraugli
-        let (butteraugli, pnorm) =
-            calculate_butteraugli(docker_input_path, docker_output_path, docker_manager);
+        // Output without a distance is reported and recorded as NaN, other failures stop the
+        // comparison.
+        let (butteraugli, pnorm) = match expensive_metrics {
+            true => match calculate_butteraugli(
+                &docker_input_path,
+                &docker_output_path,
+                docker_manager,
+                context.intensity_target,
+            ) {
+                Err(e) if e.is::<MetricError>() => {
+                    warn!("{} on {}", e, comp_image_data.image_name);
+                    (f64::NAN, f64::NAN)
+                }
+                result => result?,
+            },
+            false => (f64::NAN, f64::NAN),
+        };
 
         // SSIMULACRA2
-        let ssimulacra2 =
-            calculate_ssimulacra2(docker_input_path, docker_output_path, docker_manager);
+        // A failing tool or output without a score is reported and recorded as NaN.
+        let ssimulacra2 = match expensive_metrics {
+            true => calculate_ssimulacra2(
+                &docker_input_path,
+                &docker_output_path,
+                docker_manager,
+                context.intensity_target,
+            )
+            .unwrap_or_else(|e| {
+                warn!("{} on {}", e, comp_image_data.image_name);
+                f64::NAN
+            }),
+            false => f64::NAN,
+        };
+
+        // External metrics
+        // A failing tool is reported and recorded as NaN instead of stopping the comparison.
+        let external_metrics = context
+            .external_metrics
+            .iter()
+            .map(|metric| {
+                let value = calculate_external_metric(
+                    metric,
+                    &orig_metric_path,
+                    &comp_metric_path,
+                    &docker_input_path,
+                    &docker_output_path,
+                    docker_manager,
+                )
+                .unwrap_or_else(|e| {
+                    warn!(
+                        "external metric {} failed on {}: {}",
+                        metric.name, comp_image_data.image_name, e
+                    );
+                    f64::NAN
+                });
+                (metric.name.clone(), value)
+            })
+            .collect::<Vec<(String, f64)>>();
+
+        // The Docker metrics of a cancelled run are refused and recorded as NaN, so the
+        // partially measured result is dropped instead of written.
+        docker_manager.cancellation.check()?;
 
         // Create the comparison result struct.
-        let comparison_result = ComparisonResult {
+        let mut comparison_result = ComparisonResult {
             orig_image_name: orig_entry.image_name.clone(),
             comp_image_name: comp_image_data.image_name.clone(),
-            distance: comp_image_data.jxl_distance.into(),
-            effort: comp_image_data.jxl_effort.into(),
+            distance,
+            effort,
             orig_file_size: orig_entry.file_size as u64,
             comp_file_size: comp_image_data.file_size as u64,
             orig_raw_size: orig_entry.raw_size as u64,
             comp_raw_size: comp_image_data.raw_size as u64,
             comp_file_size_ratio,
             raw_file_size_ratio,
+            size_ratio,
             mse,
             psnr,
             ssim,
-            ms_ssim: 0.0,
+            ms_ssim,
             butteraugli,
             butteraugli_pnorm: pnorm,
             ssimulacra2,
+            coding_mode: JXLCompressionBenchmark::coding_mode(context.modular),
+            size_regression: JXLCompressionBenchmark::is_size_regression(
+                orig_entry.file_size as u64,
+                comp_image_data.file_size as u64,
+            ),
+            peak_rss_bytes: resource_usage.peak_rss_bytes,
+            cpu_seconds: resource_usage.cpu_seconds,
+            roi: context.roi.map(|roi| roi.to_string()).unwrap_or_default(),
+            bpp: JXLCompressionBenchmark::bits_per_pixel(
+                comp_image_data.file_size as u64,
+                comp_image_data.width,
+                comp_image_data.height,
+            ),
+            intensity_target: context
+                .intensity_target
+                .map(|intensity_target| intensity_target.to_string())
+                .unwrap_or_default(),
+            status: ComparisonResult::STATUS_OK.to_string(),
+            channel_psnr: channel_psnr_values,
+            channel_space: context
+                .channel_space
+                .map(|space| space.name().to_string())
+                .unwrap_or_default(),
+            source_is_lossy: orig_entry.source_is_lossy,
+            quality: quality.map(|q| q.to_string()).unwrap_or_default(),
+            decode_time_ms_min,
+            decode_time_ms_mean,
+            mse_vs_master: master_metrics.mse.unwrap_or(f64::NAN),
+            psnr_vs_master: master_metrics.psnr.unwrap_or(f64::NAN),
+            ssim_vs_master: master_metrics.ssim.unwrap_or(f64::NAN),
+            ms_ssim_vs_master: master_metrics.ms_ssim.unwrap_or(f64::NAN),
+            width: comp_image_data.width,
+            height: comp_image_data.height,
+            color_managed,
+            external_metrics,
         };
 
-        // The comparison result is stored in a CSV file under the result comparison directory.
-        let result_file = format!("{}/comparisons.csv", res_comp_path,);
+        // Flag or skip encodes that are larger than the original file.
+        if comparison_result.size_regression {
+            match context.size_regression_policy {
+                SizeRegressionPolicy::Record => {}
+                SizeRegressionPolicy::Warn => warn!(
+                    "{} ({} bytes) is larger than the original {} ({} bytes)",
+                    comparison_result.comp_image_name,
+                    comparison_result.comp_file_size,
+                    comparison_result.orig_image_name,
+                    comparison_result.orig_file_size
+                ),
+                SizeRegressionPolicy::Skip => return Ok(None),
+            }
+        }
 
-        // Initialize a CSV handler for the comparison result.
-        let csv_writer = ComparisonResultCSV::new();
+        // Check that the metrics are in their plausible ranges if enabled.
+        JXLCompressionBenchmark::validate_metrics(
+            &mut comparison_result,
+            context.metric_validation,
+        );
 
-        // Write the comparison result to the CSV file.
-        csv_writer.write_csv_header(&result_file).unwrap();
-        csv_writer
-            .write_csv(&vec![comparison_result], &result_file)
-            .unwrap();
+        // Add the decoded images to the contact sheet, led by the original.
+        if let (Some(contact_sheet), Some((width, height, orig_pixels, comp_pixels))) =
+            (contact_sheet, &decoded)
+        {
+            if comparison_result.is_ok() {
+                if contact_sheet.is_empty() {
+                    contact_sheet.add_pixels(*width, *height, orig_pixels, "original")?;
+                }
+                let caption = format!(
+                    "d{} e{} {:.2} bpp",
+                    comparison_result.distance, comparison_result.effort, comparison_result.bpp
+                );
+                contact_sheet.add_pixels(*width, *height, comp_pixels, &caption)?;
+            }
+        }
+
+        // The comparison result is stored in a CSV or binary file under the result comparison
+        // directory.
+        let result_file = context.output_format.comparisons_file(res_comp_path);
+
+        // Write the comparison result to the file.
+        append_comparisons(
+            &mut csv_sink.lock().unwrap(),
+            &result_file,
+            &vec![comparison_result.clone()],
+        )
+        .unwrap();
+
+        // Also write the comparison result in long format for plotting if enabled.
+        if context.tidy_output {
+            let tidy_file = format!("{}/comparisons_tidy.csv", res_comp_path);
+            let commit = context.libjxl_commit.clone().unwrap_or_default();
+            csv_sink
+                .lock()
+                .unwrap()
+                .append(
+                    &TidyResultCSV::new(),
+                    &TidyResult::from_comparison_results(&vec![comparison_result.clone()], &commit),
+                    &tidy_file,
+                )
+                .unwrap();
+        }
+
+        // Also write the metrics on a common scale and their composite score if enabled.
+        if context.normalized_scores {
+            let scores_file = format!("{}/scores.csv", res_comp_path);
+            let scores = NormalizedScores::new(&comparison_result, &context.score_weights);
+            csv_sink
+                .lock()
+                .unwrap()
+                .append(&NormalizedScoresCSV::new(), &vec![scores], &scores_file)
+                .unwrap();
+        }
+
+        Ok(Some(comparison_result))
+    }
+}
+
+/// A file that is removed when it goes out of scope, e.g. a compressed image held in memory
+/// that is only written to disk while its metrics are computed.
+struct ScratchFile(String);
+
+impl Drop for ScratchFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
     }
 }
 
@@ -995,6 +5236,12 @@ impl Clone for Benchmarker {
             context: self.context.clone(),
             workers: Vec::new(),
             current_worker_id: self.current_worker_id,
+            encode_cache: self.encode_cache.clone(),
+            failures: self.failures.clone(),
+            cancellation: self.cancellation.clone(),
+            csv_sink: self.csv_sink.clone(),
+            decode_limiter: self.decode_limiter.clone(),
+            run_timing: self.run_timing.clone(),
         }
     }
 }
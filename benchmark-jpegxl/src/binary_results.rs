@@ -0,0 +1,409 @@
+use crate::config::OutputFormat;
+use crate::csv_writer::{CSVReader, CSVWriter, ComparisonResult, ComparisonResultCSV, CsvSink};
+use crate::metrics::SizeRatio;
+
+use std::error::Error;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+
+/// The bytes a binary comparison results file starts with, followed by the format version.
+const MAGIC: &[u8; 4] = b"JXLC";
+
+/// The version of the record layout, increased whenever a field is added.
+const VERSION: u8 = 1;
+
+/// A compact binary format for the comparison results of very large sweeps, where writing and
+/// parsing CSV dominates the run.
+///
+/// The file starts with `MAGIC` and `VERSION`, followed by one record per comparison result,
+/// each prefixed with its length in bytes. Within a record, integers are LEB128 varints,
+/// strings are prefixed with their length in bytes, and floating point values are stored in
+/// full precision. NaN values, e.g. of metrics that were not computed, are left out and marked
+/// in a bitmask instead.
+#[derive(Debug, Default)]
+pub struct ComparisonResultBinary {}
+
+impl ComparisonResultBinary {
+    pub fn new() -> Self {
+        ComparisonResultBinary {}
+    }
+
+    /// Encodes a comparison result into a record, without its length prefix.
+    fn encode_record(result: &ComparisonResult) -> Vec<u8> {
+        let mut record = Vec::new();
+        let floats = ComparisonResultBinary::floats(result);
+        let nan_mask = floats
+            .iter()
+            .enumerate()
+            .filter(|(_, value)| value.is_nan())
+            .fold(0u64, |mask, (i, _)| mask | (1 << i));
+
+        write_string(&mut record, &result.orig_image_name);
+        write_string(&mut record, &result.comp_image_name);
+        record.extend_from_slice(&result.distance.to_le_bytes());
+        write_varint(&mut record, result.effort as u64);
+        write_varint(&mut record, result.orig_file_size);
+        write_varint(&mut record, result.comp_file_size);
+        write_varint(&mut record, result.orig_raw_size);
+        write_varint(&mut record, result.comp_raw_size);
+        write_varint(&mut record, result.peak_rss_bytes);
+        write_varint(&mut record, result.width as u64);
+        write_varint(&mut record, result.height as u64);
+        record.push(
+            result.size_regression as u8
+                | ((result.source_is_lossy as u8) << 1)
+                | ((result.color_managed as u8) << 2)
+                | (((result.size_ratio == SizeRatio::RelativeSize) as u8) << 3),
+        );
+        for value in [
+            &result.coding_mode,
+            &result.roi,
+            &result.intensity_target,
+            &result.status,
+            &result.channel_space,
+            &result.quality,
+        ] {
+            write_string(&mut record, value);
+        }
+        write_varint(&mut record, nan_mask);
+        for value in floats.iter().filter(|value| !value.is_nan()) {
+            record.extend_from_slice(&value.to_le_bytes());
+        }
+        write_varint(&mut record, result.external_metrics.len() as u64);
+        for (name, value) in &result.external_metrics {
+            write_string(&mut record, name);
+            record.extend_from_slice(&value.to_le_bytes());
+        }
+        record
+    }
+
+    /// Decodes a record created by `encode_record`.
+    fn decode_record(record: &[u8]) -> Result<ComparisonResult, Box<dyn Error>> {
+        let mut reader = RecordReader { record, pos: 0 };
+        let orig_image_name = reader.string()?;
+        let comp_image_name = reader.string()?;
+        let distance = f32::from_le_bytes(reader.bytes::<4>()?);
+        let effort = reader.varint()? as u32;
+        let orig_file_size = reader.varint()?;
+        let comp_file_size = reader.varint()?;
+        let orig_raw_size = reader.varint()?;
+        let comp_raw_size = reader.varint()?;
+        let peak_rss_bytes = reader.varint()?;
+        let width = reader.varint()? as u32;
+        let height = reader.varint()? as u32;
+        let flags = reader.bytes::<1>()?[0];
+        let coding_mode = reader.string()?;
+        let roi = reader.string()?;
+        let intensity_target = reader.string()?;
+        let status = reader.string()?;
+        let channel_space = reader.string()?;
+        let quality = reader.string()?;
+        let nan_mask = reader.varint()?;
+        let mut floats = [f64::NAN; ComparisonResultBinary::FLOAT_FIELDS];
+        for (i, value) in floats.iter_mut().enumerate() {
+            if nan_mask & (1 << i) == 0 {
+                *value = f64::from_le_bytes(reader.bytes::<8>()?);
+            }
+        }
+        let mut external_metrics = Vec::new();
+        for _ in 0..reader.varint()? {
+            let name = reader.string()?;
+            external_metrics.push((name, f64::from_le_bytes(reader.bytes::<8>()?)));
+        }
+
+        Ok(ComparisonResult {
+            orig_image_name,
+            comp_image_name,
+            distance,
+            effort,
+            orig_file_size,
+            comp_file_size,
+            orig_raw_size,
+            comp_raw_size,
+            comp_file_size_ratio: floats[0],
+            raw_file_size_ratio: floats[1],
+            size_ratio: match flags & 8 != 0 {
+                true => SizeRatio::RelativeSize,
+                false => SizeRatio::Compression,
+            },
+            mse: floats[2],
+            psnr: floats[3],
+            ssim: floats[4],
+            ms_ssim: floats[5],
+            butteraugli: floats[6],
+            butteraugli_pnorm: floats[7],
+            ssimulacra2: floats[8],
+            coding_mode,
+            size_regression: flags & 1 != 0,
+            peak_rss_bytes,
+            cpu_seconds: floats[9],
+            roi,
+            bpp: floats[10],
+            intensity_target,
+            status,
+            channel_psnr: [floats[11], floats[12], floats[13]],
+            channel_space,
+            source_is_lossy: flags & 2 != 0,
+            quality,
+            decode_time_ms_min: floats[14],
+            decode_time_ms_mean: floats[15],
+            mse_vs_master: floats[16],
+            psnr_vs_master: floats[17],
+            ssim_vs_master: floats[18],
+            ms_ssim_vs_master: floats[19],
+            width,
+            height,
+            color_managed: flags & 4 != 0,
+            external_metrics,
+        })
+    }
+
+    /// The number of floating point fields of a record.
+    const FLOAT_FIELDS: usize = 20;
+
+    /// Gets the floating point fields of a comparison result in record order.
+    fn floats(result: &ComparisonResult) -> [f64; ComparisonResultBinary::FLOAT_FIELDS] {
+        [
+            result.comp_file_size_ratio,
+            result.raw_file_size_ratio,
+            result.mse,
+            result.psnr,
+            result.ssim,
+            result.ms_ssim,
+            result.butteraugli,
+            result.butteraugli_pnorm,
+            result.ssimulacra2,
+            result.cpu_seconds,
+            result.bpp,
+            result.channel_psnr[0],
+            result.channel_psnr[1],
+            result.channel_psnr[2],
+            result.decode_time_ms_min,
+            result.decode_time_ms_mean,
+            result.mse_vs_master,
+            result.psnr_vs_master,
+            result.ssim_vs_master,
+            result.ms_ssim_vs_master,
+        ]
+    }
+}
+
+impl CSVWriter<ComparisonResult> for ComparisonResultBinary {
+    /// Appends records to a binary file. Values are stored exactly, so `float_digits` does not
+    /// apply.
+    fn write_csv(
+        &self,
+        data: &Vec<ComparisonResult>,
+        file_name: &str,
+        _float_digits: usize,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut bytes = Vec::new();
+        for result in data {
+            let record = ComparisonResultBinary::encode_record(result);
+            write_varint(&mut bytes, record.len() as u64);
+            bytes.extend_from_slice(&record);
+        }
+        let mut file = OpenOptions::new().append(true).open(file_name)?;
+        file.write_all(&bytes)?;
+        Ok(())
+    }
+
+    fn write_csv_header(&self, file_name: &str) -> Result<(), Box<dyn Error>> {
+        let path = Path::new(file_name);
+        if path.exists() && path.metadata()?.len() > 0 {
+            return Ok(());
+        }
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut header = MAGIC.to_vec();
+        header.push(VERSION);
+        fs::write(file_name, header)?;
+        Ok(())
+    }
+}
+
+impl CSVReader<ComparisonResult> for ComparisonResultBinary {
+    fn read_csv(&self, file_name: &str) -> Result<Vec<ComparisonResult>, Box<dyn Error>> {
+        let bytes = fs::read(file_name)?;
+        if bytes.len() < MAGIC.len() + 1 || &bytes[..MAGIC.len()] != MAGIC {
+            return Err(format!("{} is not a binary comparison results file", file_name).into());
+        }
+        if bytes[MAGIC.len()] != VERSION {
+            return Err(format!(
+                "{} has version {} of the binary format, expected {}",
+                file_name,
+                bytes[MAGIC.len()],
+                VERSION
+            )
+            .into());
+        }
+
+        let mut reader = RecordReader {
+            record: &bytes,
+            pos: MAGIC.len() + 1,
+        };
+        let mut data = Vec::new();
+        while reader.pos < bytes.len() {
+            let length = reader.varint()? as usize;
+            let record = reader.slice(length)?;
+            data.push(ComparisonResultBinary::decode_record(record)?);
+        }
+        Ok(data)
+    }
+
+    fn read_entry(
+        &self,
+        file_name: &str,
+        entry: usize,
+    ) -> Result<ComparisonResult, Box<dyn Error>> {
+        self.read_csv(file_name)?
+            .into_iter()
+            .nth(entry)
+            .ok_or_else(|| format!("No entry {} in {}", entry, file_name).into())
+    }
+
+    /// Finds a comparison result by its original (column 0) or compressed (column 1) image name.
+    fn find_entry(
+        &self,
+        file_name: &str,
+        column: usize,
+        value: &str,
+    ) -> Result<ComparisonResult, Box<dyn Error>> {
+        self.read_csv(file_name)?
+            .into_iter()
+            .find(|result| match column {
+                0 => result.orig_image_name == value,
+                1 => result.comp_image_name == value,
+                _ => false,
+            })
+            .ok_or_else(|| format!("No entry found for {} in column {}", value, column).into())
+    }
+}
+
+/// Reads a comparison results file in the output format of its extension.
+///
+/// # Arguments
+/// * `file_name` - The path to the comparison results file.
+///
+/// # Returns
+/// The comparison results, or an error if the file could not be read.
+pub fn read_comparisons(file_name: &str) -> Result<Vec<ComparisonResult>, Box<dyn Error>> {
+    match OutputFormat::from_file_name(file_name) {
+        OutputFormat::Csv | OutputFormat::Sqlite => ComparisonResultCSV::new().read_csv(file_name),
+        OutputFormat::Binary => ComparisonResultBinary::new().read_csv(file_name),
+    }
+}
+
+/// Replaces a comparison results file with the given results, in the output format of its
+/// extension.
+///
+/// # Arguments
+/// * `file_name` - The path to the comparison results file.
+/// * `results` - The comparison results to write.
+/// * `float_digits` - The number of significant digits floating point values are written to CSV
+///   files with, 0 for full precision.
+///
+/// # Returns
+/// An error if the file could not be written.
+pub fn write_comparisons(
+    file_name: &str,
+    results: &Vec<ComparisonResult>,
+    float_digits: usize,
+) -> Result<(), Box<dyn Error>> {
+    let _ = fs::remove_file(file_name);
+    append_comparisons(&mut CsvSink::new(float_digits), file_name, results)
+}
+
+/// Appends comparison results to a file in the output format of its extension, writing its
+/// header first if it is empty. A sink that keeps comparison results also keeps them.
+///
+/// # Arguments
+/// * `sink` - The sink the results are written through.
+/// * `file_name` - The path to the comparison results file.
+/// * `results` - The comparison results to append.
+///
+/// # Returns
+/// An error if the file could not be written.
+pub fn append_comparisons(
+    sink: &mut CsvSink,
+    file_name: &str,
+    results: &Vec<ComparisonResult>,
+) -> Result<(), Box<dyn Error>> {
+    match OutputFormat::from_file_name(file_name) {
+        OutputFormat::Csv | OutputFormat::Sqlite => {
+            let external_metrics = results
+                .first()
+                .map(|result| result.external_metric_names())
+                .unwrap_or_default();
+            let size_ratio = results
+                .first()
+                .map(|result| result.size_ratio)
+                .unwrap_or_default();
+            sink.append(
+                &ComparisonResultCSV::with_external_metrics(external_metrics)
+                    .with_size_ratio(size_ratio),
+                results,
+                file_name,
+            )?;
+        }
+        OutputFormat::Binary => sink.append(&ComparisonResultBinary::new(), results, file_name)?,
+    }
+    sink.keep_comparisons(file_name, results);
+    Ok(())
+}
+
+/// Appends an unsigned integer as an LEB128 varint.
+fn write_varint(bytes: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        bytes.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+
+/// Appends a string prefixed with its length in bytes.
+fn write_string(bytes: &mut Vec<u8>, value: &str) {
+    write_varint(bytes, value.len() as u64);
+    bytes.extend_from_slice(value.as_bytes());
+}
+
+/// Reads the fields of a record in order.
+struct RecordReader<'a> {
+    record: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> RecordReader<'a> {
+    fn slice(&mut self, length: usize) -> Result<&'a [u8], Box<dyn Error>> {
+        let end = self.pos + length;
+        if end > self.record.len() {
+            return Err("Truncated binary comparison record".into());
+        }
+        let slice = &self.record[self.pos..end];
+        self.pos = end;
+        Ok(slice)
+    }
+
+    fn bytes<const N: usize>(&mut self) -> Result<[u8; N], Box<dyn Error>> {
+        Ok(self.slice(N)?.try_into().unwrap())
+    }
+
+    fn varint(&mut self) -> Result<u64, Box<dyn Error>> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.bytes::<1>()?[0];
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err("Invalid varint in binary comparison record".into())
+    }
+
+    fn string(&mut self) -> Result<String, Box<dyn Error>> {
+        let length = self.varint()? as usize;
+        Ok(String::from_utf8(self.slice(length)?.to_vec())?)
+    }
+}
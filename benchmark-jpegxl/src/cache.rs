@@ -0,0 +1,98 @@
+use serde_derive::{Deserialize, Serialize};
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::Path;
+
+/// An index of previously encoded images, used to skip re-encoding an image when the same
+/// libjxl commit already produced the output in a prior run.
+/// Entries are keyed on the source image hash, the distance, the effort, the coding mode, the
+/// resolved libjxl commit SHA and the remaining encoder options, and map to the path of the
+/// compressed output.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct EncodeCache {
+    entries: HashMap<String, String>,
+}
+
+impl EncodeCache {
+    /// The name of the cache index file in the benchmark directory.
+    pub const FILE_NAME: &'static str = "encode_cache.json";
+
+    /// Creates the cache key for an encode.
+    ///
+    /// # Arguments
+    /// * `source_hash` - The hash of the source image bytes.
+    /// * `distance` - The distance used for the encode.
+    /// * `effort` - The effort used for the encode.
+    /// * `coding_mode` - The cjxl coding mode of the encode, see
+    ///   `JXLCompressionBenchmark::coding_mode`.
+    /// * `libjxl_sha` - The resolved libjxl commit SHA used for the encode.
+    /// * `encoder_options` - Any other options that change the output, e.g. extra cjxl arguments.
+    ///
+    /// # Returns
+    /// The cache key.
+    pub fn key(
+        source_hash: &str,
+        distance: f64,
+        effort: u32,
+        coding_mode: &str,
+        libjxl_sha: &str,
+        encoder_options: &str,
+    ) -> String {
+        format!(
+            "{}:{}:{}:{}:{}:{}",
+            source_hash, distance, effort, coding_mode, libjxl_sha, encoder_options
+        )
+    }
+
+    /// Loads the cache index from the benchmark directory.
+    /// An empty cache is returned if no index exists yet.
+    ///
+    /// # Arguments
+    /// * `benchmark_dir` - The benchmark directory.
+    ///
+    /// # Returns
+    /// The cache, or an error if the index exists but could not be read.
+    pub fn load(benchmark_dir: &str) -> Result<EncodeCache, Box<dyn Error>> {
+        let path = Path::new(benchmark_dir).join(EncodeCache::FILE_NAME);
+        if !path.exists() {
+            return Ok(EncodeCache::default());
+        }
+        let json = std::fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&json)?)
+    }
+
+    /// Saves the cache index to the benchmark directory.
+    ///
+    /// # Arguments
+    /// * `benchmark_dir` - The benchmark directory.
+    pub fn save(&self, benchmark_dir: &str) -> Result<(), Box<dyn Error>> {
+        let path = Path::new(benchmark_dir).join(EncodeCache::FILE_NAME);
+        std::fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Looks up the compressed output for a cache key.
+    /// Entries whose output file no longer exists are treated as misses.
+    ///
+    /// # Arguments
+    /// * `key` - The cache key.
+    ///
+    /// # Returns
+    /// The path to the cached compressed output, if any.
+    pub fn lookup(&self, key: &str) -> Option<String> {
+        match self.entries.get(key) {
+            Some(path) if Path::new(path).exists() => Some(path.clone()),
+            _ => None,
+        }
+    }
+
+    /// Records the compressed output for a cache key.
+    ///
+    /// # Arguments
+    /// * `key` - The cache key.
+    /// * `path` - The path to the compressed output.
+    pub fn insert(&mut self, key: String, path: String) {
+        self.entries.insert(key, path);
+    }
+}
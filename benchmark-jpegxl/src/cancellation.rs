@@ -0,0 +1,61 @@
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// A token to stop a benchmark run cooperatively, e.g. from an embedding application or on a
+/// timeout. Clones share the same state, so a clone of the token of a benchmarker can be
+/// cancelled from another thread while the run is in progress.
+///
+/// Workers check the token between distance and effort combinations and before each command
+/// they execute in their containers. The combinations that were completed are kept, so the
+/// results of a cancelled run are partial but well-formed.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    /// Creates a token that is not cancelled.
+    ///
+    /// # Returns
+    /// The CancellationToken.
+    pub fn new() -> CancellationToken {
+        CancellationToken::default()
+    }
+
+    /// Cancels the run of the token and of all its clones.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    /// Returns whether the token was cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Checks the token before starting a step of the run.
+    ///
+    /// # Returns
+    /// A Cancelled error if the token was cancelled, so that the caller can propagate it.
+    pub fn check(&self) -> Result<(), Cancelled> {
+        match self.is_cancelled() {
+            true => Err(Cancelled),
+            false => Ok(()),
+        }
+    }
+}
+
+/// The error of a step that was refused because the run was cancelled.
+/// Workers propagate it instead of recording the step as failed, so it can be told apart from
+/// real failures with `is::<Cancelled>()` on a boxed error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cancelled;
+
+impl Display for Cancelled {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "the run was cancelled")
+    }
+}
+
+impl Error for Cancelled {}
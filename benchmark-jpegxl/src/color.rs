@@ -0,0 +1,323 @@
+use std::error::Error;
+
+/// The colorants of sRGB in the D50 profile connection space, as in the ICC sRGB profiles.
+/// The columns are the XYZ values of the red, green and blue primaries.
+const SRGB_TO_XYZ_D50: [[f64; 3]; 3] = [
+    [0.436066, 0.385147, 0.143066],
+    [0.222488, 0.716873, 0.060608],
+    [0.013916, 0.097076, 0.714096],
+];
+
+/// The tone response curve of one channel of an ICC profile, mapping encoded values to linear
+/// light, both in the range 0 to 1.
+#[derive(Debug, Clone, PartialEq)]
+enum ToneCurve {
+    /// A pure power function.
+    Gamma(f64),
+    /// Equally spaced samples, interpolated linearly.
+    Table(Vec<f64>),
+    /// A `para` curve of the given function type with its parameters g, a, b, c, d, e and f.
+    Parametric(u16, [f64; 7]),
+}
+
+impl ToneCurve {
+    /// Maps every 8-bit sample to linear light.
+    fn lookup_table(&self) -> Vec<f64> {
+        (0..=255)
+            .map(|value| self.linearize(value as f64 / 255.0))
+            .collect()
+    }
+
+    /// Maps an encoded value to linear light.
+    fn linearize(&self, x: f64) -> f64 {
+        match self {
+            ToneCurve::Gamma(gamma) => x.powf(*gamma),
+            ToneCurve::Table(table) => {
+                let position = x * (table.len() - 1) as f64;
+                let i = (position.floor() as usize).min(table.len() - 2);
+                let t = position - i as f64;
+                table[i] * (1.0 - t) + table[i + 1] * t
+            }
+            ToneCurve::Parametric(function_type, [g, a, b, c, d, e, f]) => match function_type {
+                0 => x.powf(*g),
+                1 if x >= -b / a => (a * x + b).powf(*g),
+                1 => 0.0,
+                2 if x >= -b / a => (a * x + b).powf(*g) + c,
+                2 => *c,
+                3 if x >= *d => (a * x + b).powf(*g),
+                3 => c * x,
+                _ if x >= *d => (a * x + b).powf(*g) + e,
+                _ => c * x + f,
+            },
+        }
+    }
+}
+
+/// An RGB matrix/TRC ICC profile, the kind embedded in PNG and JPEG images and synthesized by
+/// libjxl for the color encoding of a JXL image. LUT-based profiles are not supported.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IccProfile {
+    /// The XYZ values in the D50 profile connection space of the red, green and blue primaries,
+    /// one column per primary.
+    to_xyz: [[f64; 3]; 3],
+    /// The tone response curves of the red, green and blue channels.
+    curves: [ToneCurve; 3],
+}
+
+impl IccProfile {
+    /// Parses an ICC profile.
+    ///
+    /// # Arguments
+    /// * `bytes` - The ICC profile, as embedded in an image.
+    ///
+    /// # Returns
+    /// The profile, or an error if it is not a valid RGB matrix/TRC profile.
+    pub fn parse(bytes: &[u8]) -> Result<IccProfile, Box<dyn Error>> {
+        if bytes.len() < 132 || &bytes[36..40] != b"acsp" {
+            return Err(Box::from("Not an ICC profile"));
+        }
+        if &bytes[16..20] != b"RGB " {
+            return Err(Box::from(format!(
+                "Only RGB ICC profiles are supported, got {}",
+                String::from_utf8_lossy(&bytes[16..20]).trim()
+            )));
+        }
+
+        let mut to_xyz = [[0.0; 3]; 3];
+        for (column, signature) in [b"rXYZ", b"gXYZ", b"bXYZ"].into_iter().enumerate() {
+            let data = IccProfile::tag(bytes, signature)?;
+            if data.get(0..4) != Some(&b"XYZ "[..]) {
+                return Err(Box::from("An ICC profile colorant is not of type XYZ"));
+            }
+            for (row, values) in to_xyz.iter_mut().enumerate() {
+                values[column] = read_s15_fixed16(data, 8 + 4 * row)?;
+            }
+        }
+        let curves = [
+            IccProfile::parse_curve(IccProfile::tag(bytes, b"rTRC")?)?,
+            IccProfile::parse_curve(IccProfile::tag(bytes, b"gTRC")?)?,
+            IccProfile::parse_curve(IccProfile::tag(bytes, b"bTRC")?)?,
+        ];
+
+        Ok(IccProfile { to_xyz, curves })
+    }
+
+    /// Finds the data of a tag of an ICC profile in its tag table.
+    fn tag<'a>(bytes: &'a [u8], signature: &[u8; 4]) -> Result<&'a [u8], Box<dyn Error>> {
+        let tag_count = read_u32(bytes, 128)? as usize;
+        let entry = (0..tag_count)
+            .map(|i| 132 + 12 * i)
+            .find(|&entry| bytes.get(entry..entry + 4) == Some(&signature[..]))
+            .ok_or_else(|| {
+                format!(
+                    "The ICC profile has no {} tag, only matrix/TRC profiles are supported",
+                    String::from_utf8_lossy(signature)
+                )
+            })?;
+        let offset = read_u32(bytes, entry + 4)? as usize;
+        let size = read_u32(bytes, entry + 8)? as usize;
+        bytes
+            .get(offset..offset.saturating_add(size))
+            .ok_or_else(|| Box::from("An ICC profile tag is out of bounds"))
+    }
+
+    /// Parses a `curv` or `para` tone response curve.
+    fn parse_curve(data: &[u8]) -> Result<ToneCurve, Box<dyn Error>> {
+        match data.get(0..4) {
+            Some(b"curv") => {
+                let count = read_u32(data, 8)? as usize;
+                let samples = (0..count)
+                    .map(|i| read_u16(data, 12 + 2 * i))
+                    .collect::<Result<Vec<u16>, Box<dyn Error>>>()?;
+                Ok(match samples.len() {
+                    0 => ToneCurve::Gamma(1.0),
+                    1 => ToneCurve::Gamma(samples[0] as f64 / 256.0),
+                    _ => ToneCurve::Table(
+                        samples
+                            .iter()
+                            .map(|&sample| sample as f64 / 65535.0)
+                            .collect(),
+                    ),
+                })
+            }
+            Some(b"para") => {
+                let function_type = read_u16(data, 8)?;
+                let parameter_count = match function_type {
+                    0 => 1,
+                    1 => 3,
+                    2 => 4,
+                    3 => 5,
+                    4 => 7,
+                    _ => {
+                        return Err(Box::from(format!(
+                            "Unknown ICC parametric curve type {}",
+                            function_type
+                        )))
+                    }
+                };
+                let mut parameters = [0.0; 7];
+                for (i, parameter) in parameters.iter_mut().take(parameter_count).enumerate() {
+                    *parameter = read_s15_fixed16(data, 12 + 4 * i)?;
+                }
+                Ok(ToneCurve::Parametric(function_type, parameters))
+            }
+            _ => Err(Box::from(
+                "An ICC tone response curve is not of type curv or para",
+            )),
+        }
+    }
+
+    /// Returns the sRGB profile.
+    pub fn srgb() -> IccProfile {
+        let curve = ToneCurve::Parametric(
+            3,
+            [
+                2.4,
+                1.0 / 1.055,
+                0.055 / 1.055,
+                1.0 / 12.92,
+                0.04045,
+                0.0,
+                0.0,
+            ],
+        );
+        IccProfile {
+            to_xyz: SRGB_TO_XYZ_D50,
+            curves: [curve.clone(), curve.clone(), curve],
+        }
+    }
+
+    /// Converts 8-bit RGB samples in the color space of the profile to the color space of
+    /// another profile in place, with relative colorimetric intent. Colors outside the gamut of
+    /// the target profile are clipped.
+    ///
+    /// # Arguments
+    /// * `target` - The profile of the color space to convert to.
+    /// * `pixels` - The interleaved RGB samples.
+    pub fn convert_to(&self, target: &IccProfile, pixels: &mut [u8]) {
+        let to_target = multiply(&invert(&target.to_xyz), &self.to_xyz);
+        let linear = self
+            .curves
+            .iter()
+            .map(|curve| curve.lookup_table())
+            .collect::<Vec<Vec<f64>>>();
+        let target_linear = target
+            .curves
+            .iter()
+            .map(|curve| curve.lookup_table())
+            .collect::<Vec<Vec<f64>>>();
+
+        for pixel in pixels.chunks_exact_mut(3) {
+            let rgb = [
+                linear[0][pixel[0] as usize],
+                linear[1][pixel[1] as usize],
+                linear[2][pixel[2] as usize],
+            ];
+            for ((sample, row), table) in pixel.iter_mut().zip(to_target.iter()).zip(&target_linear)
+            {
+                let value = row[0] * rgb[0] + row[1] * rgb[1] + row[2] * rgb[2];
+                *sample = nearest_sample(table, value);
+            }
+        }
+    }
+}
+
+/// Converts the compressed image to the color space of the original with their embedded ICC
+/// profiles, so images in other color spaces, e.g. Display P3, are compared by color rather
+/// than by sample value. The original is the common space, so it is never changed and keeps
+/// its full gamut, and only colors of the compressed image the original cannot hold are
+/// clipped. The compressed image is converted only if both images carry a profile and the
+/// profiles differ.
+///
+/// # Arguments
+/// * `orig_icc` - The ICC profile of the original image, if it has one.
+/// * `comp_pixels` - The interleaved 8-bit RGB samples of the compressed image.
+/// * `comp_icc` - The ICC profile of the compressed image, if it has one.
+///
+/// # Returns
+/// Whether the images were compared by their profiles, or an error if a profile cannot be
+/// applied, in which case the compressed image is not changed.
+pub fn to_original_space(
+    orig_icc: Option<&[u8]>,
+    comp_pixels: &mut [u8],
+    comp_icc: Option<&[u8]>,
+) -> Result<bool, Box<dyn Error>> {
+    let (orig_icc, comp_icc) = match (orig_icc, comp_icc) {
+        (Some(orig_icc), Some(comp_icc)) => (orig_icc, comp_icc),
+        _ => return Ok(false),
+    };
+    let orig_profile = IccProfile::parse(orig_icc)?;
+    let comp_profile = IccProfile::parse(comp_icc)?;
+    if comp_profile != orig_profile {
+        comp_profile.convert_to(&orig_profile, comp_pixels);
+    }
+    Ok(true)
+}
+
+/// Finds the 8-bit sample whose linear value is closest to a linear value.
+///
+/// # Arguments
+/// * `table` - The linear values of all 8-bit samples, in increasing order.
+/// * `linear` - The linear value.
+///
+/// # Returns
+/// The sample, 0 or 255 for values outside the range of the table.
+fn nearest_sample(table: &[f64], linear: f64) -> u8 {
+    let above = table.partition_point(|&value| value < linear);
+    let sample = match above {
+        0 => 0,
+        above if above == table.len() => table.len() - 1,
+        above if linear - table[above - 1] <= table[above] - linear => above - 1,
+        above => above,
+    };
+    sample as u8
+}
+
+/// Multiplies two 3x3 matrices.
+fn multiply(a: &[[f64; 3]; 3], b: &[[f64; 3]; 3]) -> [[f64; 3]; 3] {
+    let mut product = [[0.0; 3]; 3];
+    for (i, row) in product.iter_mut().enumerate() {
+        for (j, value) in row.iter_mut().enumerate() {
+            *value = (0..3).map(|k| a[i][k] * b[k][j]).sum();
+        }
+    }
+    product
+}
+
+/// Inverts a 3x3 matrix, which must not be singular.
+fn invert(m: &[[f64; 3]; 3]) -> [[f64; 3]; 3] {
+    let cofactor = |i: usize, j: usize| {
+        let (r0, r1) = ((i + 1) % 3, (i + 2) % 3);
+        let (c0, c1) = ((j + 1) % 3, (j + 2) % 3);
+        m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0]
+    };
+    let determinant = (0..3).map(|j| m[0][j] * cofactor(0, j)).sum::<f64>();
+    let mut inverse = [[0.0; 3]; 3];
+    for (i, row) in inverse.iter_mut().enumerate() {
+        for (j, value) in row.iter_mut().enumerate() {
+            *value = cofactor(j, i) / determinant;
+        }
+    }
+    inverse
+}
+
+/// Reads a big-endian u32 of an ICC profile.
+fn read_u32(bytes: &[u8], offset: usize) -> Result<u32, Box<dyn Error>> {
+    bytes
+        .get(offset..offset + 4)
+        .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or_else(|| Box::from("The ICC profile is truncated"))
+}
+
+/// Reads a big-endian u16 of an ICC profile.
+fn read_u16(bytes: &[u8], offset: usize) -> Result<u16, Box<dyn Error>> {
+    bytes
+        .get(offset..offset + 2)
+        .map(|b| u16::from_be_bytes([b[0], b[1]]))
+        .ok_or_else(|| Box::from("The ICC profile is truncated"))
+}
+
+/// Reads a big-endian s15Fixed16 number of an ICC profile.
+fn read_s15_fixed16(bytes: &[u8], offset: usize) -> Result<f64, Box<dyn Error>> {
+    Ok(read_u32(bytes, offset)? as i32 as f64 / 65536.0)
+}
@@ -1,8 +1,918 @@
+use crate::csv_writer::{
+    ComparisonResult, ComparisonResultDiff, CsvQuoting, DEFAULT_CSV_DELIMITER,
+    DEFAULT_FLOAT_DIGITS,
+};
+use crate::docker_manager::DockerManager;
+use crate::metrics::{
+    capture_value, ChannelSpace, MetricKind, SizeRatio, SSIM_SIGMA, SSIM_WINDOW_SIZE,
+};
+use crate::sampling::{image_seed, sample_indices};
+
+use clap_derive::ValueEnum;
+use regex::Regex;
+use std::str::FromStr;
+
+/// How to handle encodes whose compressed file is larger than the original file.
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum SizeRegressionPolicy {
+    /// Record the result like any other.
+    #[value(name = "record")]
+    Record,
+    /// Record the result and print a warning.
+    #[value(name = "warn")]
+    Warn,
+    /// Do not record the result.
+    #[value(name = "skip")]
+    Skip,
+}
+
+/// Whether to check that computed metrics are in their plausible ranges, e.g. SSIM in -1 to 1.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum MetricValidation {
+    /// Do not check the metrics.
+    #[default]
+    Off,
+    /// Print a warning for each encode with an implausible metric.
+    Warn,
+    /// Mark encodes with an implausible metric as failed, so they are left out of the summaries
+    /// and recorded as failures of the run.
+    Strict,
+}
+
+/// What to do with images of a test set that have the same content as another image.
+#[derive(Debug, Clone, Copy, PartialEq, Default, ValueEnum)]
+pub enum DuplicatePolicy {
+    /// Do not look for duplicates.
+    #[default]
+    #[value(name = "ignore")]
+    Ignore,
+    /// Record the duplicates in the manifest and print a warning.
+    #[value(name = "report")]
+    Report,
+    /// Record and report the duplicates, and benchmark only the first image of each group.
+    #[value(name = "skip")]
+    Skip,
+}
+
+/// Which messages are logged.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum LogLevel {
+    /// Only warnings and errors.
+    Quiet,
+    /// Progress, warnings and errors.
+    #[default]
+    Normal,
+    /// Also debug messages, e.g. every encode and comparison of the workers.
+    Verbose,
+}
+
+/// How the images are weighted when their comparison results are averaged into aggregates and
+/// summaries.
+#[derive(Debug, Clone, Copy, PartialEq, Default, ValueEnum)]
+pub enum SummaryWeighting {
+    /// Every image counts the same.
+    #[default]
+    #[value(name = "equal")]
+    Equal,
+    /// Images are weighted by their width times height, so the averages follow the rate and
+    /// quality of the dataset as a whole, where large images make up most of the pixels.
+    #[value(name = "pixels")]
+    ByPixels,
+    /// Images are weighted by the file size of the original.
+    #[value(name = "bytes")]
+    ByBytes,
+}
+
+/// The format the comparison results of a run are written in.
+#[derive(Debug, Clone, Copy, PartialEq, Default, ValueEnum)]
+pub enum OutputFormat {
+    /// CSV files, readable by any tool.
+    #[default]
+    #[value(name = "csv")]
+    Csv,
+    /// The compact binary format of `ComparisonResultBinary`, for very large sweeps where
+    /// writing and parsing CSV dominates the run.
+    #[value(name = "binary")]
+    Binary,
+    /// CSV files, with the comparison results also recorded in the `ResultsDatabase` of the
+    /// benchmark directory, to query them across runs.
+    #[value(name = "sqlite")]
+    Sqlite,
+}
+
+impl OutputFormat {
+    /// Gets the extension of the files written in the format.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Csv | OutputFormat::Sqlite => "csv",
+            OutputFormat::Binary => "bin",
+        }
+    }
+
+    /// Gets the format of a file from its extension, CSV unless it is a binary file.
+    ///
+    /// # Arguments
+    /// * `file_name` - The path to the file.
+    pub fn from_file_name(file_name: &str) -> OutputFormat {
+        match std::path::Path::new(file_name)
+            .extension()
+            .and_then(|extension| extension.to_str())
+        {
+            Some("bin") => OutputFormat::Binary,
+            _ => OutputFormat::Csv,
+        }
+    }
+
+    /// Gets the path of the comparison results file in the format.
+    ///
+    /// # Arguments
+    /// * `res_comp_path` - The compressed image results path.
+    pub fn comparisons_file(&self, res_comp_path: &str) -> String {
+        format!("{}/comparisons.{}", res_comp_path, self.extension())
+    }
+
+    /// Finds the comparison results file of a results path in either format.
+    ///
+    /// # Arguments
+    /// * `res_comp_path` - The compressed image results path.
+    ///
+    /// # Returns
+    /// The path of the comparison results file, or None if there is none.
+    pub fn find_comparisons_file(res_comp_path: &str) -> Option<String> {
+        [OutputFormat::Csv, OutputFormat::Binary]
+            .iter()
+            .map(|format| format.comparisons_file(res_comp_path))
+            .find(|path| std::path::Path::new(path).exists())
+    }
+}
+
+impl SummaryWeighting {
+    /// The name of the weighting, as accepted by `--summary-weighting`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            SummaryWeighting::Equal => "equal",
+            SummaryWeighting::ByPixels => "pixels",
+            SummaryWeighting::ByBytes => "bytes",
+        }
+    }
+
+    /// Gets the weight of the image of a comparison result.
+    ///
+    /// # Arguments
+    /// * `result` - The comparison result.
+    ///
+    /// # Returns
+    /// The weight, 0 if the pixels or bytes of the image are unknown.
+    pub fn weight(&self, result: &ComparisonResult) -> f64 {
+        match self {
+            SummaryWeighting::Equal => 1.0,
+            SummaryWeighting::ByPixels => result.width as f64 * result.height as f64,
+            SummaryWeighting::ByBytes => result.orig_file_size as f64,
+        }
+    }
+}
+
+/// A rectangular region of interest of an image, in pixels.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Roi {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Roi {
+    /// Checks that the region of interest is not empty and fits within an image.
+    ///
+    /// # Arguments
+    /// * `image_width` - The width of the image.
+    /// * `image_height` - The height of the image.
+    ///
+    /// # Returns
+    /// An error describing why the region does not fit, if it does not.
+    pub fn validate(&self, image_width: u32, image_height: u32) -> Result<(), String> {
+        if self.width == 0 || self.height == 0 {
+            return Err(format!("Region of interest {} is empty", self));
+        }
+        if self.x as u64 + self.width as u64 > image_width as u64
+            || self.y as u64 + self.height as u64 > image_height as u64
+        {
+            return Err(format!(
+                "Region of interest {} does not fit within the {}x{} image",
+                self, image_width, image_height
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for Roi {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{},{},{},{}", self.x, self.y, self.width, self.height)
+    }
+}
+
+impl FromStr for Roi {
+    type Err = String;
+
+    /// Parses a region of interest from `x,y,w,h`, e.g. `0,0,256,256`.
+    fn from_str(roi: &str) -> Result<Self, Self::Err> {
+        let values = roi
+            .split(',')
+            .map(|value| value.trim().parse::<u32>())
+            .collect::<Result<Vec<u32>, _>>();
+        match values.as_deref() {
+            Ok([x, y, width, height]) => Ok(Roi {
+                x: *x,
+                y: *y,
+                width: *width,
+                height: *height,
+            }),
+            _ => Err(format!(
+                "Invalid region of interest, expected x,y,w,h: {}",
+                roi
+            )),
+        }
+    }
+}
+
+/// A target value of a metric to search the distance for, instead of sweeping all distances.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QualityTarget {
+    pub metric: MetricKind,
+    pub target: f64,
+    /// The search stops once the metric is within this distance of the target.
+    pub tolerance: f64,
+    /// The maximum number of encodes per image.
+    pub max_iterations: u32,
+    /// The effort used for all encodes of the search.
+    pub effort: u32,
+}
+
+impl QualityTarget {
+    /// The lowest distance searched.
+    pub const MIN_DISTANCE: f64 = 0.0;
+    /// The highest distance searched, the maximum cjxl accepts.
+    pub const MAX_DISTANCE: f64 = 25.0;
+}
+
+/// A target file size to search the distance for, instead of sweeping all distances.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SizeTarget {
+    /// The requested size of the encoded file in bytes.
+    pub target_bytes: u64,
+    /// The search stops once the size is within this fraction of the target, e.g. 0.05 for 5%.
+    pub tolerance: f64,
+    /// The maximum number of encodes per image.
+    pub max_iterations: u32,
+    /// The effort used for all encodes of the search.
+    pub effort: u32,
+}
+
+impl SizeTarget {
+    /// The highest distance searched. If the encode at this distance is still larger than the
+    /// target, the target is unreachable.
+    pub const MAX_DISTANCE: f64 = 14.0;
+
+    /// Checks whether a file size is within the tolerance of the target.
+    ///
+    /// # Arguments
+    /// * `size` - The size of the encoded file in bytes.
+    pub fn within_tolerance(&self, size: u64) -> bool {
+        (size as f64 - self.target_bytes as f64).abs() <= self.tolerance * self.target_bytes as f64
+    }
+
+    /// Checks whether a file size exceeds the target by more than the tolerance.
+    ///
+    /// # Arguments
+    /// * `size` - The size of the encoded file in bytes.
+    pub fn exceeds(&self, size: u64) -> bool {
+        size > self.target_bytes && !self.within_tolerance(size)
+    }
+}
+
+/// The outcome of a size target search for an image.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SizeTargetStatus {
+    /// An encode within the tolerance of the target was found.
+    Converged,
+    /// The iterations ran out, the encode closest to the target was kept.
+    NotConverged,
+    /// Even the encode at `SizeTarget::MAX_DISTANCE` exceeds the target.
+    Unreachable,
+}
+
+impl SizeTargetStatus {
+    /// Returns the name the status is recorded with in the CSV files.
+    pub fn name(&self) -> &'static str {
+        match self {
+            SizeTargetStatus::Converged => "converged",
+            SizeTargetStatus::NotConverged => "not converged",
+            SizeTargetStatus::Unreachable => "budget unreachable",
+        }
+    }
+}
+
+/// A random sample of the distance and effort combinations encoded per image, instead of the
+/// full grid, for quick approximate sweeps.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GridSample {
+    /// The number of combinations sampled per image.
+    pub count: usize,
+    /// The seed of the sampling, the same seed samples the same combinations.
+    pub seed: u64,
+}
+
+impl GridSample {
+    /// Samples the combinations to encode for an image.
+    ///
+    /// # Arguments
+    /// * `total` - The number of combinations in the full grid.
+    /// * `image_name` - The name of the image, each image samples its own combinations.
+    ///
+    /// # Returns
+    /// The indices of the sampled combinations in ascending order.
+    pub fn sample(&self, total: usize, image_name: &str) -> Vec<usize> {
+        sample_indices(total, self.count, image_seed(self.seed, image_name))
+    }
+}
+
+/// The subset of the sweep the expensive Docker metrics, Butteraugli and SSIMULACRA2, are
+/// computed on, to cut the time of exploratory sweeps. The native metrics are still computed on
+/// every encode, and the skipped metrics are recorded as NaN.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExpensiveMetricSample {
+    /// The distances the expensive metrics are computed at, every distance if empty.
+    pub distances: Vec<f64>,
+    /// The expensive metrics are computed at every nth effort of the sweep, starting with the
+    /// first.
+    pub effort_step: usize,
+}
+
+impl ExpensiveMetricSample {
+    /// Returns whether the expensive metrics are computed for an encode.
+    /// Efforts that are not swept, e.g. the effort of a quality target, are always included.
+    ///
+    /// # Arguments
+    /// * `distance` - The distance of the encode.
+    /// * `effort` - The effort of the encode.
+    /// * `efforts` - The swept efforts, in order.
+    pub fn includes(&self, distance: f64, effort: u32, efforts: &[u32]) -> bool {
+        // Distances are recorded as f32, so they are matched with a tolerance.
+        let at_distance = self.distances.is_empty()
+            || self
+                .distances
+                .iter()
+                .any(|sampled| (sampled - distance).abs() < 1e-4);
+        let at_effort = efforts
+            .iter()
+            .position(|swept| *swept == effort)
+            .map_or(true, |index| index % self.effort_step == 0);
+        at_distance && at_effort
+    }
+}
+
+/// The encoder settings swept by the benchmark, either Butteraugli distances or cjxl qualities.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Sweep {
+    /// Encode with `--distance`.
+    Distance(Vec<f64>),
+    /// Encode with `--quality` (0 to 100), the scale of JPEG encoders.
+    Quality(Vec<f64>),
+}
+
+impl Sweep {
+    /// The distances swept when neither distances nor qualities are configured.
+    pub const DEFAULT_DISTANCES: [f64; 10] = [0.5, 1.0, 1.5, 3.0, 4.0, 6.0, 8.0, 10.0, 12.0, 14.0];
+
+    /// Creates the sweep out of the configured distances and qualities.
+    ///
+    /// # Arguments
+    /// * `distances` - The distances to sweep, the default distances if both are empty.
+    /// * `qualities` - The qualities to sweep.
+    ///
+    /// # Returns
+    /// The sweep, or an error if both distances and qualities are configured or a quality is
+    /// outside of 0 to 100.
+    pub fn new(distances: &[f64], qualities: &[f64]) -> Result<Sweep, String> {
+        match (distances.is_empty(), qualities.is_empty()) {
+            (false, false) => Err(
+                "Distances and qualities cannot both be swept, choose either --distance or --quality"
+                    .to_string(),
+            ),
+            (true, false) => match qualities.iter().find(|q| !(0.0..=100.0).contains(*q)) {
+                Some(quality) => Err(format!("Invalid quality {}, expected 0 to 100", quality)),
+                None => Ok(Sweep::Quality(qualities.to_vec())),
+            },
+            (false, true) => Ok(Sweep::Distance(distances.to_vec())),
+            (true, true) => Ok(Sweep::Distance(Sweep::DEFAULT_DISTANCES.to_vec())),
+        }
+    }
+
+    /// Returns the encodes of the sweep as the distance of each encode and, in quality mode,
+    /// the quality passed to cjxl.
+    /// The distance of a quality is the distance cjxl maps it to, so compressed images are
+    /// named and compared the same way in both modes.
+    pub fn encodes(&self) -> Vec<(f64, Option<f64>)> {
+        match self {
+            Sweep::Distance(distances) => distances.iter().map(|d| (*d, None)).collect(),
+            Sweep::Quality(qualities) => qualities
+                .iter()
+                .map(|q| (Sweep::distance_from_quality(*q), Some(*q)))
+                .collect(),
+        }
+    }
+
+    /// Converts a cjxl quality into the distance cjxl encodes it with, following libjxl's
+    /// `JxlEncoderDistanceFromQuality`. The distance is rounded to 3 decimals for file names.
+    ///
+    /// # Arguments
+    /// * `quality` - The quality, 100 is lossless.
+    ///
+    /// # Returns
+    /// The distance.
+    pub fn distance_from_quality(quality: f64) -> f64 {
+        let distance = match quality {
+            q if q >= 100.0 => 0.0,
+            q if q >= 30.0 => 0.1 + (100.0 - q) * 0.09,
+            q => 53.0 / 3000.0 * q * q - 23.0 / 20.0 * q + 25.0,
+        };
+        (distance * 1000.0).round() / 1000.0
+    }
+}
+
+/// A user-provided metric tool invoked on every comparison, recorded under its own column.
+/// The tool is called with the paths of the original and compressed image as its arguments, and
+/// the value is captured from its output with `pattern`.
+#[derive(Debug, Clone)]
+pub struct ExternalMetric {
+    /// The name of the metric, also the name of its column in the comparison results.
+    pub name: String,
+    /// The path to the binary of the tool.
+    pub binary: String,
+    /// Whether the binary is run inside the worker container on the container paths of the
+    /// images, instead of locally.
+    pub in_container: bool,
+    /// The regex matching the value in the output, with a single capture group for the value.
+    /// For example `VMAF score: (\S+)` captures 93.5 from `VMAF score: 93.5`.
+    pub pattern: Regex,
+}
+
+impl ExternalMetric {
+    /// The prefix of binaries that are run inside the worker container.
+    pub const CONTAINER_PREFIX: &'static str = "docker:";
+
+    /// Captures the value of the metric from the output of its tool.
+    /// The first match whose capture is a number is used.
+    ///
+    /// # Arguments
+    /// * `output` - The output of the tool.
+    ///
+    /// # Returns
+    /// The captured value, or None if the pattern does not match a number.
+    pub fn parse_output(&self, output: &str) -> Option<f64> {
+        capture_value(&self.pattern, output)
+    }
+}
+
+impl PartialEq for ExternalMetric {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+            && self.binary == other.binary
+            && self.in_container == other.in_container
+            && self.pattern.as_str() == other.pattern.as_str()
+    }
+}
+
+impl TryFrom<String> for ExternalMetric {
+    type Error = String;
+
+    /// Parses an external metric from `name,binary,pattern`.
+    /// Binaries prefixed with `docker:` are run inside the worker container, and the pattern is a
+    /// regex with a single capture group, which may contain commas.
+    fn try_from(metric: String) -> Result<Self, Self::Error> {
+        let invalid = |reason: String| {
+            format!(
+                "Invalid external metric {}, expected name,binary,pattern: {}",
+                metric, reason
+            )
+        };
+        let parts = metric.splitn(3, ',').collect::<Vec<&str>>();
+        let [name, binary, pattern] = parts.as_slice() else {
+            return Err(invalid("missing fields".to_string()));
+        };
+        if name.is_empty() || binary.is_empty() {
+            return Err(invalid("the name and binary must not be empty".to_string()));
+        }
+        let pattern = Regex::new(pattern).map_err(|e| invalid(e.to_string()))?;
+        if pattern.captures_len() != 2 {
+            return Err(invalid(
+                "the pattern must have exactly one capture group for the value".to_string(),
+            ));
+        }
+        let (binary, in_container) = match binary.strip_prefix(Self::CONTAINER_PREFIX) {
+            Some(binary) => (binary, true),
+            None => (*binary, false),
+        };
+        Ok(ExternalMetric {
+            name: name.to_string(),
+            binary: binary.to_string(),
+            in_container,
+            pattern,
+        })
+    }
+}
+
+/// A local libjxl patch benchmarked as its own run, labelled by a name in place of a commit.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LocalDiff {
+    /// The label of the diff, used in place of the commit in output paths and results.
+    pub name: String,
+    /// The local path to the diff file.
+    pub path: String,
+}
+
+impl LocalDiff {
+    /// Finds the diff that follows the diff with the given label.
+    ///
+    /// # Arguments
+    /// * `diffs` - The diffs of the run, in the order they are benchmarked.
+    /// * `name` - The label of the current diff.
+    ///
+    /// # Returns
+    /// The label of the next diff, or None if the current diff is the last.
+    pub fn next(diffs: &[LocalDiff], name: &str) -> Option<String> {
+        let position = diffs.iter().position(|diff| diff.name == name)?;
+        diffs.get(position + 1).map(|diff| diff.name.clone())
+    }
+}
+
+/// A released libjxl version benchmarked with prebuilt binaries instead of a checkout and build
+/// of libjxl, labelled by its version in place of a commit.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PrebuiltCjxl {
+    /// The release tag, e.g. `v0.10.2`.
+    pub version: String,
+    /// A local directory holding the prebuilt `cjxl` and `djxl` of the version, or None to
+    /// download the release in the containers.
+    pub binary_dir: Option<String>,
+}
+
+impl PrebuiltCjxl {
+    /// Checks that the version is a plain release tag, since it names output directories and
+    /// the download URL.
+    ///
+    /// # Returns
+    /// An error if the version is empty or contains other characters than letters, digits, dots,
+    /// dashes and underscores.
+    pub fn validate(&self) -> Result<(), String> {
+        let valid = !self.version.is_empty()
+            && self
+                .version
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || ".-_".contains(c));
+        match valid {
+            true => Ok(()),
+            false => Err(format!(
+                "Invalid libjxl version {:?}, expected a release tag like v0.10.2",
+                self.version
+            )),
+        }
+    }
+}
+
+impl FromStr for LocalDiff {
+    type Err = String;
+
+    /// Parses a local diff from `name=path`, or `path` to label the diff by its file stem.
+    fn from_str(diff: &str) -> Result<Self, Self::Err> {
+        let (name, path) = match diff.split_once('=') {
+            Some((name, path)) => (name.to_string(), path.to_string()),
+            None => (
+                std::path::Path::new(diff)
+                    .file_stem()
+                    .map(|stem| stem.to_string_lossy().to_string())
+                    .unwrap_or_default(),
+                diff.to_string(),
+            ),
+        };
+        if name.is_empty() || path.is_empty() || name.contains('/') {
+            return Err(format!(
+                "Invalid local diff, expected name=path or path: {}",
+                diff
+            ));
+        }
+        Ok(LocalDiff { name, path })
+    }
+}
+
+/// A toolchain libjxl is built with, set up from its own Dockerfile, e.g. to compare compilers.
+/// Each toolchain runs the whole sweep in its own containers, labelled by its name next to the
+/// commit.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Toolchain {
+    /// The label of the toolchain, appended to the commit in output paths and results.
+    pub name: String,
+    /// The path to the Dockerfile of the toolchain.
+    pub dockerfile: String,
+}
+
+impl Toolchain {
+    /// Labels a commit benchmarked with a toolchain, as used for its output directories.
+    ///
+    /// # Arguments
+    /// * `commit` - The label of the commit.
+    /// * `toolchain` - The name of the toolchain, or None if the run has no toolchains.
+    ///
+    /// # Returns
+    /// The label of the commit and toolchain.
+    pub fn label(commit: &str, toolchain: Option<&str>) -> String {
+        match toolchain {
+            Some(toolchain) => format!("{}@{}", commit, toolchain),
+            None => commit.to_string(),
+        }
+    }
+}
+
+impl TryFrom<&str> for Toolchain {
+    type Error = String;
+
+    /// Parses a toolchain from `name=dockerfile`, or `dockerfile` to label the toolchain by the
+    /// extension of the Dockerfile, e.g. `clang` for `Dockerfile.clang`, or else its file stem.
+    fn try_from(toolchain: &str) -> Result<Self, Self::Error> {
+        let (name, dockerfile) = match toolchain.split_once('=') {
+            Some((name, dockerfile)) => (name.to_string(), dockerfile.to_string()),
+            None => {
+                let path = std::path::Path::new(toolchain);
+                (
+                    path.extension()
+                        .or_else(|| path.file_stem())
+                        .map(|name| name.to_string_lossy().to_string())
+                        .unwrap_or_default(),
+                    toolchain.to_string(),
+                )
+            }
+        };
+        if name.is_empty() || dockerfile.is_empty() || name.contains('/') {
+            return Err(format!(
+                "Invalid toolchain, expected name=dockerfile or dockerfile: {}",
+                toolchain
+            ));
+        }
+        Ok(Toolchain { name, dockerfile })
+    }
+}
+
+/// A configuration libjxl is built with, e.g. to compare builds with and without AVX2.
+/// Each build configuration runs the whole sweep with libjxl rebuilt with its environment and
+/// CMake flags, labelled by its name next to the commit and toolchain.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BuildConfig {
+    /// The label of the build configuration, appended to the commit in output paths and results.
+    pub name: String,
+    /// The `KEY=VALUE` environment variables `ci.sh` is run with, e.g. `CC=clang`.
+    pub env: Vec<String>,
+    /// The flags passed to CMake through `CMAKE_FLAGS`, e.g. `-DJPEGXL_ENABLE_SKCMS=OFF`.
+    pub cmake_flags: Vec<String>,
+}
+
+impl BuildConfig {
+    /// Labels a commit benchmarked with a build configuration, as used for its output
+    /// directories.
+    ///
+    /// # Arguments
+    /// * `label` - The label of the commit and toolchain.
+    /// * `build_config` - The name of the build configuration, or None if the run has none.
+    ///
+    /// # Returns
+    /// The label of the commit, toolchain and build configuration.
+    pub fn label(label: &str, build_config: Option<&str>) -> String {
+        match build_config {
+            Some(build_config) => format!("{}+{}", label, build_config),
+            None => label.to_string(),
+        }
+    }
+
+    /// Gets the specification of the build configuration, which identifies the build with its
+    /// flags rather than only its name, e.g. `clang:["CC=clang", "CXX=clang++"]`. The arguments
+    /// are quoted since they may contain any separator.
+    pub fn spec(&self) -> String {
+        let args = self
+            .env
+            .iter()
+            .chain(self.cmake_flags.iter())
+            .cloned()
+            .collect::<Vec<String>>();
+        match args.is_empty() {
+            true => self.name.clone(),
+            false => format!("{}:{:?}", self.name, args),
+        }
+    }
+
+    /// Parses the build configurations of repeated `name` or `name:arg` specifications, see
+    /// `TryFrom<&str>`. The arguments of specifications with the same name are collected into
+    /// one build configuration, in the order the names first appear.
+    ///
+    /// # Arguments
+    /// * `specs` - The specifications, e.g. `clang:CC=clang` and `clang:CXX=clang++`.
+    ///
+    /// # Returns
+    /// The build configurations, or an error naming the first invalid specification.
+    pub fn parse_all(specs: &[String]) -> Result<Vec<BuildConfig>, String> {
+        let mut build_configs = Vec::<BuildConfig>::new();
+        for spec in specs {
+            let parsed = BuildConfig::try_from(spec.as_str())?;
+            match build_configs
+                .iter_mut()
+                .find(|build_config| build_config.name == parsed.name)
+            {
+                Some(build_config) => {
+                    build_config.env.extend(parsed.env);
+                    build_config.cmake_flags.extend(parsed.cmake_flags);
+                }
+                None => build_configs.push(parsed),
+            }
+        }
+        Ok(build_configs)
+    }
+}
+
+impl TryFrom<&str> for BuildConfig {
+    type Error = String;
+
+    /// Parses a build configuration from `name:arg` with a single argument, which is a CMake
+    /// flag if it starts with a dash and a `KEY=VALUE` environment variable otherwise, e.g.
+    /// `no-avx2:-DCMAKE_CXX_FLAGS=-DHWY_DISABLED_TARGETS=HWY_AVX2`. The argument is taken as is,
+    /// so it may contain commas. A name alone builds with the default flags.
+    fn try_from(build_config: &str) -> Result<Self, Self::Error> {
+        let (name, arg) = build_config.split_once(':').unwrap_or((build_config, ""));
+        if name.is_empty() {
+            return Err(format!(
+                "Invalid build configuration, expected name or name:arg: {}",
+                build_config
+            ));
+        }
+        let mut env = Vec::new();
+        let mut cmake_flags = Vec::new();
+        match arg {
+            "" => {}
+            arg if arg.starts_with('-') => cmake_flags.push(arg.to_string()),
+            arg if matches!(arg.split_once('='), Some((key, _)) if !key.is_empty()) => {
+                env.push(arg.to_string())
+            }
+            arg => {
+                return Err(format!(
+                    "Invalid build configuration argument {}, expected a -D flag or KEY=VALUE: {}",
+                    arg, build_config
+                ))
+            }
+        }
+        Ok(BuildConfig {
+            name: name.to_string(),
+            env,
+            cmake_flags,
+        })
+    }
+}
+
+/// A pass/fail gate on the summary of a comparison, e.g. to fail a regression CI job when a
+/// change makes the mean SSIMULACRA2 drop by more than 0.5.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Gate {
+    pub metric: MetricKind,
+    /// The largest regression of the mean of the metric that passes, in the units of the metric.
+    pub threshold: f64,
+}
+
+impl Gate {
+    /// Gets how much the mean of the metric got worse in the summary of a comparison, where the
+    /// second run is compared against the first.
+    ///
+    /// # Arguments
+    /// * `summary` - The summary of the comparison result differences.
+    ///
+    /// # Returns
+    /// The regression, which is negative if the metric improved.
+    pub fn regression(&self, summary: &ComparisonResultDiff) -> f64 {
+        let diff = match self.metric {
+            MetricKind::Mse => summary.diff_mse,
+            MetricKind::Psnr => summary.diff_psnr,
+            MetricKind::Ssim => summary.diff_ssim,
+            MetricKind::MsSsim => summary.diff_ms_ssim,
+            MetricKind::Butteraugli => summary.diff_butteraugli,
+            MetricKind::Ssimulacra2 => summary.diff_ssimulacra2,
+        };
+        match self.metric.higher_is_better() {
+            true => -diff,
+            false => diff,
+        }
+    }
+
+    /// Checks the gate against the summary of a comparison.
+    ///
+    /// # Arguments
+    /// * `summary` - The summary of the comparison result differences.
+    ///
+    /// # Returns
+    /// A description of the violation, or None if the gate passed.
+    pub fn check(&self, summary: &ComparisonResultDiff) -> Option<String> {
+        let regression = self.regression(summary);
+        match regression > self.threshold {
+            true => Some(format!(
+                "gate {} failed: the mean {} regressed by {} (threshold {})",
+                self,
+                self.metric.name(),
+                regression,
+                self.threshold
+            )),
+            false => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Gate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}:{}",
+            self.metric.name().to_lowercase(),
+            self.threshold
+        )
+    }
+}
+
+impl FromStr for Gate {
+    type Err = String;
+
+    /// Parses a gate from `metric:threshold`, e.g. `ssimulacra2:0.5`.
+    fn from_str(gate: &str) -> Result<Self, Self::Err> {
+        let (metric, threshold) = gate
+            .split_once(':')
+            .ok_or_else(|| format!("Invalid gate, expected metric:threshold: {}", gate))?;
+        match threshold.trim().parse::<f64>() {
+            Ok(threshold) if threshold.is_finite() && threshold >= 0.0 => Ok(Gate {
+                metric: metric.trim().parse()?,
+                threshold,
+            }),
+            _ => Err(format!(
+                "Invalid gate threshold, expected a non-negative number: {}",
+                gate
+            )),
+        }
+    }
+}
+
+/// The weight of a metric in the composite score of an encode, see `NormalizedScores`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScoreWeight {
+    pub metric: MetricKind,
+    pub weight: f64,
+}
+
+impl FromStr for ScoreWeight {
+    type Err = String;
+
+    /// Parses a score weight from `metric:weight`, e.g. `ssimulacra2:2`.
+    fn from_str(score_weight: &str) -> Result<Self, Self::Err> {
+        let (metric, weight) = score_weight.split_once(':').ok_or_else(|| {
+            format!(
+                "Invalid score weight, expected metric:weight: {}",
+                score_weight
+            )
+        })?;
+        match weight.trim().parse::<f64>() {
+            Ok(weight) if weight.is_finite() && weight > 0.0 => Ok(ScoreWeight {
+                metric: metric.trim().parse()?,
+                weight,
+            }),
+            _ => Err(format!(
+                "Invalid score weight, expected a positive number: {}",
+                score_weight
+            )),
+        }
+    }
+}
+
 /// Configuration for the benchmarking tool.
 pub struct Config {
     pub benchmark_dir_path: String,
     pub docker_file_path: String,
+    /// The `KEY=VALUE` build arguments of the docker image, e.g. to override its base image.
+    pub docker_build_args: Vec<String>,
+    /// The directory the docker image is built in.
+    pub docker_context_dir: String,
+    /// The docker binary all docker commands of the run are executed with, e.g. `podman`.
+    pub docker_bin: String,
+    /// The toolchains the sweep is run with, each with its own Dockerfile in place of
+    /// `docker_file_path`. The sweep is run once with `docker_file_path` if empty.
+    pub toolchains: Vec<Toolchain>,
+    /// The build configurations to run the sweep with, each labelled by its name, or empty to
+    /// build libjxl with the default flags.
+    pub build_configs: Vec<BuildConfig>,
     pub local_test_image_dir_path: String,
+    /// Only benchmarks the images with these names, with or without their extension, in any
+    /// test set. Every image is benchmarked if empty.
+    pub images: Vec<String>,
     pub docker_test_image_dir_path: String,
     pub num_workers: usize,
 
@@ -10,6 +920,136 @@ pub struct Config {
     pub libjxl_commit: Option<String>,
     pub compare_to_local: bool,
     pub compare_to_commit: Option<String>,
+    /// Benchmarks only the local build and diffs it against the results of the latest run,
+    /// with the distances and efforts of that run.
+    pub compare_to_previous: bool,
+    pub max_dimension: Option<u32>,
+    pub modular: Option<bool>,
+    pub size_regression_policy: SizeRegressionPolicy,
+    /// Whether to check that the computed metrics are in their plausible ranges.
+    pub metric_validation: MetricValidation,
+    pub use_encode_cache: bool,
+    pub resource_metrics: bool,
+    pub tidy_output: bool,
+    /// Whether to also write the metrics of each encode mapped to a common 0 to 1 scale where
+    /// higher is better, see `MetricKind::score`.
+    pub normalized_scores: bool,
+    /// The weights of the metrics in the composite score of each encode. Without weights no
+    /// composite score is computed.
+    pub score_weights: Vec<ScoreWeight>,
+    /// The format the comparison results are written in.
+    pub output_format: OutputFormat,
+    pub roi: Option<Roi>,
+    pub keep_compressed: bool,
+    /// Whether to copy each source image into the original output directory of the run, so
+    /// the run can be reproduced without the test image directory.
+    pub keep_originals: bool,
+    /// Whether to store compressed images once per content as `objects/{sha256}.jxl` in the
+    /// benchmark directory, with symlinks at their usual paths, to deduplicate identical encodes.
+    pub content_addressed: bool,
+    pub cjxl_extra_args: Vec<String>,
+    pub libjxl_src: Option<String>,
+    /// The released libjxl version to benchmark with prebuilt binaries instead of building a
+    /// commit, if set.
+    pub prebuilt_cjxl: Option<PrebuiltCjxl>,
+    pub deterministic: bool,
+    pub quality_target: Option<QualityTarget>,
+    /// Searches the distance reaching a file size per image instead of sweeping, if set.
+    pub size_target: Option<SizeTarget>,
+    /// Encodes a random sample of the distance and effort combinations per image, if set.
+    pub grid_sample: Option<GridSample>,
+    /// Computes Butteraugli and SSIMULACRA2 only on this subset of the sweep, if set.
+    pub expensive_metric_sample: Option<ExpensiveMetricSample>,
+    /// The directory of lossless masters, matched by image name, that the compressed images are
+    /// also compared against, if set.
+    pub master_dir: Option<String>,
+    /// The directory decoded PNG references of the source images are kept in across runs, so
+    /// that sources in other formats are not decoded again for the metrics, if set.
+    pub reference_cache_dir: Option<String>,
+    /// The gates the summaries of the comparisons of a run must pass, or the run fails with a
+    /// nonzero exit code after all comparisons are written.
+    pub gates: Vec<Gate>,
+    /// Whether comparisons are diffed at the rates of the baseline run, interpolating the
+    /// quality of each image over bpp, instead of at equal distance.
+    pub baseline_distance: bool,
+    pub baseline_csv: Option<String>,
+    pub baseline_codec: String,
+    pub intensity_target: Option<f64>,
+    pub diff_images: bool,
+    pub fail_fast: bool,
+    pub error_histograms: bool,
+    /// The channels the per-channel PSNR is computed on, not computed if None.
+    pub channel_space: Option<ChannelSpace>,
+    /// Whether the compressed image is converted to the color space of the original with their
+    /// embedded ICC profiles before the pixel metrics, if both images carry one.
+    pub color_management: bool,
+    /// The direction of the recorded size ratios.
+    pub size_ratio: SizeRatio,
+    /// The efforts swept, checked against the efforts the cjxl build of each commit accepts.
+    pub efforts: Vec<u32>,
+    /// The distances swept, the default distances if neither distances nor qualities are set.
+    pub distances: Vec<f64>,
+    /// The cjxl qualities swept instead of distances.
+    pub qualities: Vec<f64>,
+    pub reuse_containers: bool,
+    /// User-provided metric tools recorded for every comparison.
+    pub external_metrics: Vec<ExternalMetric>,
+    /// Whether to write a contact sheet of the original and compressed images of each image.
+    pub contact_sheets: bool,
+    /// Whether to write a self-contained HTML report with tables and charts of the run.
+    pub html_report: bool,
+    /// Whether to benchmark the decode speed of each compressed image with djxl.
+    pub decode_speed: bool,
+    /// The number of timed djxl decodes of each compressed image.
+    pub decode_repeats: u32,
+    /// The number of leading decodes discarded as warm-up runs.
+    pub decode_warmup: u32,
+    /// The percentages of the bitstream of each compressed image that are decoded as a
+    /// progressive decode, empty to not benchmark progressive decoding.
+    pub progressive_truncations: Vec<f64>,
+    /// The maximum number of images decoded for the metrics at the same time across workers, 0
+    /// for no limit beyond the number of workers.
+    pub max_concurrent_decodes: usize,
+    /// The maximum number of pixels of a source image, 0 for no limit. Larger images are
+    /// skipped before they are decoded and recorded in the manifest.
+    pub max_pixels: u64,
+    /// The maximum number of bytes decoding a source image may allocate, 0 for no limit. Larger
+    /// images are skipped and recorded in the manifest.
+    pub max_decode_bytes: u64,
+    /// The number of threads the native metrics (MSE, SSIM, MS-SSIM and per-channel PSNR) of
+    /// an image are computed on at once, 1 to compute them one after another.
+    pub metric_threads: usize,
+    /// Local libjxl patches applied on top of the libjxl commit and compared against each other
+    /// in separate runs, in place of comparing commits.
+    pub diffs: Vec<LocalDiff>,
+    /// The size of the window the native SSIM and MS-SSIM are computed over.
+    pub ssim_window_size: usize,
+    /// The standard deviation of the Gaussian SSIM window, or None for a uniform window.
+    pub ssim_sigma: Option<f64>,
+    /// The number of significant digits floating point values are written to CSV files with, 0
+    /// for full precision.
+    pub csv_float_digits: usize,
+    /// The delimiter between the fields of CSV files, an ASCII character.
+    pub csv_delimiter: u8,
+    /// How the fields of CSV files are quoted.
+    pub csv_quoting: CsvQuoting,
+    /// How the images are weighted in the aggregates, the report and the diff summaries.
+    pub summary_weighting: SummaryWeighting,
+    /// Whether to look for images with the same content in each test set before benchmarking
+    /// it, and whether to skip them.
+    pub duplicate_policy: DuplicatePolicy,
+    /// Whether to record the command line, host and docker and cjxl versions in the manifest.
+    pub record_environment: bool,
+    /// Whether to decode every compressed image with the jxl-oxide decoder as well and record
+    /// whether it disagrees with the jpegxl_rs decoder.
+    pub cross_check_decode: bool,
+    /// Whether to search the quality target at every effort of `efforts` instead of only the
+    /// effort of the target, and write how the rate and encode time of each image vary with it.
+    pub effort_curve: bool,
+    /// Which messages are logged.
+    pub log_level: LogLevel,
+    /// The file messages are appended to instead of stderr, if set.
+    pub log_file: Option<String>,
 }
 
 impl Default for Config {
@@ -17,7 +1057,13 @@ impl Default for Config {
         Config {
             benchmark_dir_path: "./benchmarks".to_string(),
             docker_file_path: "./Dockerfile".to_string(),
+            docker_build_args: Vec::new(),
+            docker_context_dir: DockerManager::DEFAULT_CONTEXT_DIR.to_string(),
+            docker_bin: DockerManager::DEFAULT_DOCKER_BIN.to_string(),
+            toolchains: Vec::new(),
+            build_configs: Vec::new(),
             local_test_image_dir_path: "./test_images".to_string(),
+            images: Vec::new(),
             docker_test_image_dir_path: "/test_images".to_string(),
             num_workers: 6,
 
@@ -25,6 +1071,70 @@ impl Default for Config {
             libjxl_commit: None,
             compare_to_local: false,
             compare_to_commit: None,
+            compare_to_previous: false,
+            max_dimension: None,
+            modular: None,
+            size_regression_policy: SizeRegressionPolicy::Record,
+            metric_validation: MetricValidation::Off,
+            use_encode_cache: false,
+            resource_metrics: false,
+            tidy_output: false,
+            normalized_scores: false,
+            score_weights: Vec::new(),
+            output_format: OutputFormat::Csv,
+            roi: None,
+            keep_compressed: true,
+            keep_originals: false,
+            content_addressed: false,
+            cjxl_extra_args: Vec::new(),
+            libjxl_src: None,
+            prebuilt_cjxl: None,
+            deterministic: false,
+            quality_target: None,
+            size_target: None,
+            grid_sample: None,
+            expensive_metric_sample: None,
+            master_dir: None,
+            reference_cache_dir: None,
+            gates: Vec::new(),
+            baseline_distance: false,
+            baseline_csv: None,
+            baseline_codec: "jpeg".to_string(),
+            intensity_target: None,
+            diff_images: false,
+            fail_fast: false,
+            error_histograms: false,
+            channel_space: None,
+            color_management: false,
+            size_ratio: SizeRatio::Compression,
+            efforts: (5..=9).collect(),
+            distances: Vec::new(),
+            qualities: Vec::new(),
+            reuse_containers: false,
+            external_metrics: Vec::new(),
+            contact_sheets: false,
+            html_report: false,
+            decode_speed: false,
+            decode_repeats: 5,
+            decode_warmup: 1,
+            progressive_truncations: Vec::new(),
+            max_concurrent_decodes: 0,
+            max_pixels: 0,
+            max_decode_bytes: 0,
+            metric_threads: 1,
+            diffs: Vec::new(),
+            ssim_window_size: SSIM_WINDOW_SIZE,
+            ssim_sigma: Some(SSIM_SIGMA),
+            csv_float_digits: DEFAULT_FLOAT_DIGITS,
+            csv_delimiter: DEFAULT_CSV_DELIMITER,
+            csv_quoting: CsvQuoting::Necessary,
+            summary_weighting: SummaryWeighting::Equal,
+            duplicate_policy: DuplicatePolicy::Ignore,
+            record_environment: false,
+            cross_check_decode: false,
+            effort_curve: false,
+            log_level: LogLevel::Normal,
+            log_file: None,
         }
     }
 }
@@ -0,0 +1,202 @@
+use image::{GenericImageView, ImageBuffer, Rgb, RgbImage};
+use std::error::Error;
+use std::path::Path;
+
+/// The glyphs of the caption font, 3 pixels wide and 5 pixels high.
+/// Each row is a bit mask with the leftmost pixel in the highest of the 3 bits.
+/// Captions are lowercased, so the font covers digits, the lowercase alphabet and the punctuation
+/// of metric names and values. Other characters are left blank.
+const GLYPHS: [(char, [u8; 5]); 44] = [
+    ('0', [0b111, 0b101, 0b101, 0b101, 0b111]),
+    ('1', [0b010, 0b110, 0b010, 0b010, 0b111]),
+    ('2', [0b111, 0b001, 0b111, 0b100, 0b111]),
+    ('3', [0b111, 0b001, 0b111, 0b001, 0b111]),
+    ('4', [0b101, 0b101, 0b111, 0b001, 0b001]),
+    ('5', [0b111, 0b100, 0b111, 0b001, 0b111]),
+    ('6', [0b111, 0b100, 0b111, 0b101, 0b111]),
+    ('7', [0b111, 0b001, 0b001, 0b001, 0b001]),
+    ('8', [0b111, 0b101, 0b111, 0b101, 0b111]),
+    ('9', [0b111, 0b101, 0b111, 0b001, 0b111]),
+    ('.', [0b000, 0b000, 0b000, 0b000, 0b010]),
+    ('-', [0b000, 0b000, 0b111, 0b000, 0b000]),
+    (':', [0b000, 0b010, 0b000, 0b010, 0b000]),
+    ('/', [0b001, 0b001, 0b010, 0b100, 0b100]),
+    ('%', [0b101, 0b001, 0b010, 0b100, 0b101]),
+    ('(', [0b001, 0b010, 0b010, 0b010, 0b001]),
+    (')', [0b100, 0b010, 0b010, 0b010, 0b100]),
+    ('a', [0b000, 0b011, 0b101, 0b101, 0b011]),
+    ('b', [0b100, 0b100, 0b111, 0b101, 0b111]),
+    ('c', [0b000, 0b111, 0b100, 0b100, 0b111]),
+    ('d', [0b001, 0b001, 0b111, 0b101, 0b111]),
+    ('e', [0b000, 0b111, 0b111, 0b100, 0b111]),
+    ('f', [0b011, 0b100, 0b111, 0b100, 0b100]),
+    ('g', [0b011, 0b100, 0b101, 0b101, 0b011]),
+    ('h', [0b100, 0b100, 0b111, 0b101, 0b101]),
+    ('i', [0b010, 0b000, 0b010, 0b010, 0b010]),
+    ('j', [0b001, 0b000, 0b001, 0b101, 0b111]),
+    ('k', [0b100, 0b101, 0b110, 0b110, 0b101]),
+    ('l', [0b110, 0b010, 0b010, 0b010, 0b111]),
+    ('m', [0b000, 0b111, 0b111, 0b101, 0b101]),
+    ('n', [0b000, 0b110, 0b101, 0b101, 0b101]),
+    ('o', [0b000, 0b111, 0b101, 0b101, 0b111]),
+    ('p', [0b000, 0b111, 0b101, 0b111, 0b100]),
+    ('q', [0b000, 0b111, 0b101, 0b111, 0b001]),
+    ('r', [0b000, 0b101, 0b110, 0b100, 0b100]),
+    ('s', [0b000, 0b011, 0b110, 0b011, 0b110]),
+    ('t', [0b010, 0b111, 0b010, 0b010, 0b011]),
+    ('u', [0b000, 0b101, 0b101, 0b101, 0b111]),
+    ('v', [0b000, 0b101, 0b101, 0b101, 0b010]),
+    ('w', [0b000, 0b101, 0b101, 0b111, 0b111]),
+    ('x', [0b000, 0b101, 0b010, 0b010, 0b101]),
+    ('y', [0b000, 0b101, 0b111, 0b001, 0b110]),
+    ('z', [0b000, 0b111, 0b011, 0b110, 0b111]),
+    (' ', [0b000, 0b000, 0b000, 0b000, 0b000]),
+];
+
+/// The number of screen pixels per font pixel.
+const FONT_SCALE: u32 = 2;
+
+/// A grid of captioned thumbnails for reviewing the decoded images of a sweep side by side.
+/// Every tile has the same size, with the thumbnail centered above its caption.
+#[derive(Debug, Default)]
+pub struct ContactSheet {
+    tiles: Vec<(RgbImage, String)>,
+}
+
+impl ContactSheet {
+    /// The largest width and height of a thumbnail. Smaller images are not scaled up.
+    pub const THUMBNAIL_SIZE: u32 = 192;
+    /// The height of the caption below each thumbnail.
+    pub const CAPTION_HEIGHT: u32 = 16;
+    /// The number of tiles per row.
+    pub const COLUMNS: u32 = 4;
+
+    pub fn new() -> Self {
+        ContactSheet { tiles: Vec::new() }
+    }
+
+    /// Adds a thumbnail of an image as the next tile.
+    ///
+    /// # Arguments
+    /// * `image` - The image to add.
+    /// * `caption` - The caption drawn below the thumbnail.
+    pub fn add<I: GenericImageView<Pixel = Rgb<u8>>>(&mut self, image: &I, caption: &str) {
+        let scale = (ContactSheet::THUMBNAIL_SIZE as f64 / image.width().max(1) as f64)
+            .min(ContactSheet::THUMBNAIL_SIZE as f64 / image.height().max(1) as f64)
+            .min(1.0);
+        let width = ((image.width() as f64 * scale).round() as u32).max(1);
+        let height = ((image.height() as f64 * scale).round() as u32).max(1);
+        let thumbnail =
+            image::imageops::resize(image, width, height, image::imageops::FilterType::Triangle);
+        self.tiles.push((thumbnail, caption.to_lowercase()));
+    }
+
+    /// Adds a thumbnail of a decoded image as the next tile.
+    ///
+    /// # Arguments
+    /// * `width` - The width of the image.
+    /// * `height` - The height of the image.
+    /// * `pixels` - The 8-bit RGB samples of the image.
+    /// * `caption` - The caption drawn below the thumbnail.
+    ///
+    /// # Returns
+    /// An error if the number of samples does not match the dimensions.
+    pub fn add_pixels(
+        &mut self,
+        width: u32,
+        height: u32,
+        pixels: &[u8],
+        caption: &str,
+    ) -> Result<(), Box<dyn Error>> {
+        let image = ImageBuffer::<Rgb<u8>, &[u8]>::from_raw(width, height, pixels)
+            .ok_or("Decoded pixel buffer does not match its size")?;
+        self.add(&image, caption);
+        Ok(())
+    }
+
+    /// Returns whether no tile was added yet.
+    pub fn is_empty(&self) -> bool {
+        self.tiles.is_empty()
+    }
+
+    /// Returns the width and height of a contact sheet with the given number of tiles.
+    pub fn dimensions(num_tiles: u32) -> (u32, u32) {
+        let columns = num_tiles.clamp(1, ContactSheet::COLUMNS);
+        let rows = num_tiles.max(1).div_ceil(ContactSheet::COLUMNS);
+        (
+            columns * ContactSheet::THUMBNAIL_SIZE,
+            rows * (ContactSheet::THUMBNAIL_SIZE + ContactSheet::CAPTION_HEIGHT),
+        )
+    }
+
+    /// Draws the tiles row by row on a white background.
+    pub fn render(&self) -> RgbImage {
+        let (width, height) = ContactSheet::dimensions(self.tiles.len() as u32);
+        let mut sheet = RgbImage::from_pixel(width, height, Rgb([255, 255, 255]));
+        let tile_height = ContactSheet::THUMBNAIL_SIZE + ContactSheet::CAPTION_HEIGHT;
+        for (index, (thumbnail, caption)) in self.tiles.iter().enumerate() {
+            let x = (index as u32 % ContactSheet::COLUMNS) * ContactSheet::THUMBNAIL_SIZE;
+            let y = (index as u32 / ContactSheet::COLUMNS) * tile_height;
+            image::imageops::replace(
+                &mut sheet,
+                thumbnail,
+                (x + (ContactSheet::THUMBNAIL_SIZE - thumbnail.width()) / 2) as i64,
+                (y + (ContactSheet::THUMBNAIL_SIZE - thumbnail.height()) / 2) as i64,
+            );
+
+            // Captions are cut to the width of the tile so they do not run into the next one.
+            let max_chars =
+                ((ContactSheet::THUMBNAIL_SIZE - FONT_SCALE) / (4 * FONT_SCALE)) as usize;
+            let caption = caption.chars().take(max_chars).collect::<String>();
+            let caption_y = y + ContactSheet::THUMBNAIL_SIZE;
+            ContactSheet::draw_caption(
+                &mut sheet,
+                x + FONT_SCALE,
+                caption_y + (ContactSheet::CAPTION_HEIGHT - 5 * FONT_SCALE) / 2,
+                &caption,
+            );
+        }
+        sheet
+    }
+
+    /// Renders the contact sheet and writes it as a PNG.
+    ///
+    /// # Arguments
+    /// * `out_path` - The path to write the PNG to.
+    ///
+    /// # Returns
+    /// The path to the contact sheet, or an error if it could not be written.
+    pub fn save(&self, out_path: &str) -> Result<String, Box<dyn Error>> {
+        if let Some(parent) = Path::new(out_path).parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        self.render().save(out_path)?;
+        Ok(out_path.to_string())
+    }
+
+    /// Draws a caption in black with the built-in font.
+    fn draw_caption(sheet: &mut RgbImage, x: u32, y: u32, caption: &str) {
+        for (index, character) in caption.chars().enumerate() {
+            let rows = match GLYPHS.iter().find(|(glyph, _)| *glyph == character) {
+                Some((_, rows)) => rows,
+                None => continue,
+            };
+            // Glyphs are 3 font pixels wide with 1 font pixel of spacing.
+            let glyph_x = x + index as u32 * 4 * FONT_SCALE;
+            for (row, bits) in rows.iter().enumerate() {
+                for column in 0..3 {
+                    if bits & (0b100 >> column) == 0 {
+                        continue;
+                    }
+                    for dy in 0..FONT_SCALE {
+                        for dx in 0..FONT_SCALE {
+                            let px = glyph_x + column * FONT_SCALE + dx;
+                            let py = y + row as u32 * FONT_SCALE + dy;
+                            sheet.put_pixel(px, py, Rgb([0, 0, 0]));
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
@@ -1,3 +1,14 @@
+use crate::config::{
+    BuildConfig, DuplicatePolicy, ExpensiveMetricSample, ExternalMetric, Gate, GridSample,
+    LocalDiff, MetricValidation, OutputFormat, PrebuiltCjxl, QualityTarget, Roi, ScoreWeight,
+    SizeRegressionPolicy, SizeTarget, SummaryWeighting, Sweep, Toolchain,
+};
+use crate::csv_writer::CsvQuoting;
+use crate::docker_manager::EffortRange;
+use crate::image_limits::ImageLimits;
+use crate::manifest::RunManifest;
+use crate::metrics::{ChannelSpace, SizeRatio, SsimWindow};
+
 /// Context struct that holds all the information needed to run the benchmark.
 #[derive(Debug, Clone)]
 pub struct Context {
@@ -5,13 +16,87 @@ pub struct Context {
     pub test_sets: Vec<String>,
     pub current_run: usize,
     pub local_test_image_dir: String,
+    pub images: Vec<String>,
     pub docker_test_image_dir: String,
     pub num_workers: usize,
     pub use_temp_dir: bool,
     pub libjxl_commit: Option<String>,
     pub compare_to_local: bool,
     pub compare_to_commit: Option<String>,
+    pub previous_run: Option<RunManifest>,
+    pub local_diff_hash: Option<String>,
+    pub max_dimension: Option<u32>,
+    pub modular: Option<bool>,
+    pub size_regression_policy: SizeRegressionPolicy,
+    pub metric_validation: MetricValidation,
+    pub use_encode_cache: bool,
+    pub resource_metrics: bool,
+    pub tidy_output: bool,
+    pub normalized_scores: bool,
+    pub score_weights: Vec<ScoreWeight>,
+    pub output_format: OutputFormat,
+    pub roi: Option<Roi>,
+    pub keep_compressed: bool,
+    pub keep_originals: bool,
+    pub content_addressed: bool,
+    pub cjxl_extra_args: Vec<String>,
+    pub libjxl_src: Option<String>,
+    pub prebuilt_cjxl: Option<PrebuiltCjxl>,
+    pub deterministic: bool,
+    pub quality_target: Option<QualityTarget>,
+    pub size_target: Option<SizeTarget>,
+    pub grid_sample: Option<GridSample>,
+    pub expensive_metric_sample: Option<ExpensiveMetricSample>,
+    pub master_dir: Option<String>,
+    pub reference_cache_dir: Option<String>,
+    pub gates: Vec<Gate>,
+    pub baseline_distance: bool,
+    pub baseline_csv: Option<String>,
+    pub baseline_codec: String,
+    pub intensity_target: Option<f64>,
+    pub diff_images: bool,
+    pub fail_fast: bool,
+    pub error_histograms: bool,
+    pub channel_space: Option<ChannelSpace>,
+    pub color_management: bool,
+    pub size_ratio: SizeRatio,
+    pub efforts: Vec<u32>,
+    pub sweep: Sweep,
+    pub reuse_containers: bool,
+    pub docker_bin: String,
+    pub external_metrics: Vec<ExternalMetric>,
+    pub contact_sheets: bool,
+    pub html_report: bool,
+    pub decode_speed: bool,
+    pub decode_repeats: u32,
+    pub decode_warmup: u32,
+    pub progressive_truncations: Vec<f64>,
+    pub max_concurrent_decodes: usize,
+    pub image_limits: ImageLimits,
+    pub metric_threads: usize,
+    pub diffs: Vec<LocalDiff>,
+    pub toolchains: Vec<Toolchain>,
+    pub build_configs: Vec<BuildConfig>,
+    pub ssim_window: SsimWindow,
+    pub csv_float_digits: usize,
+    pub csv_delimiter: u8,
+    pub csv_quoting: CsvQuoting,
+    pub summary_weighting: SummaryWeighting,
+    pub duplicate_policy: DuplicatePolicy,
+    pub record_environment: bool,
+    pub cross_check_decode: bool,
+    pub effort_curve: bool,
+    /// The efforts the cjxl build of the commit accepts, read once per build before its images
+    /// are benchmarked.
+    pub effort_range: Option<EffortRange>,
 }
 
 /// Default values for the context struct.
 pub const DEFAULT_LIBJXL_COMMIT: &str = "main";
+
+/// The commit label recorded for a local libjxl source tree mounted into the containers.
+pub const LOCAL_SRC_COMMIT: &str = "local-src";
+
+/// A known-good libjxl commit SHA to use instead of resolving `DEFAULT_LIBJXL_COMMIT`.
+/// When None, `DEFAULT_LIBJXL_COMMIT` is resolved to the SHA it points to at the start of a run.
+pub const PINNED_LIBJXL_COMMIT: Option<&str> = None;
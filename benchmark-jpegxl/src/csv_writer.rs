@@ -1,16 +1,213 @@
-use crate::image_reader::ImageFileData;
+use crate::config::ScoreWeight;
+use crate::image_reader::{ImageFileData, ImageFormat};
+use crate::metrics::{MetricKind, SizeRatio};
 
+use clap_derive::ValueEnum;
+use serde::de::{self, Visitor};
+use serde::Deserialize;
+use std::collections::HashMap;
 use std::error::Error;
 use std::fs::OpenOptions;
+use std::sync::RwLock;
+
+/// The number of significant digits floating point values are written to CSV files with by
+/// default. Enough for analysis, while keeping the files stable to diff across platforms.
+pub const DEFAULT_FLOAT_DIGITS: usize = 6;
+
+/// The delimiter of CSV files by default.
+pub const DEFAULT_CSV_DELIMITER: u8 = b',';
+
+/// How the fields of CSV files are quoted.
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum CsvQuoting {
+    /// Quote fields only if they contain the delimiter, a quote or a line break.
+    #[value(name = "necessary")]
+    Necessary,
+    /// Quote every field.
+    #[value(name = "always")]
+    Always,
+    /// Quote every field that is not a number.
+    #[value(name = "non-numeric")]
+    NonNumeric,
+}
+
+impl CsvQuoting {
+    fn style(&self) -> csv::QuoteStyle {
+        match self {
+            CsvQuoting::Necessary => csv::QuoteStyle::Necessary,
+            CsvQuoting::Always => csv::QuoteStyle::Always,
+            CsvQuoting::NonNumeric => csv::QuoteStyle::NonNumeric,
+        }
+    }
+}
+
+/// The delimiter and quoting CSV files are written and read with, set once per run.
+static CSV_FORMAT: RwLock<(u8, CsvQuoting)> =
+    RwLock::new((DEFAULT_CSV_DELIMITER, CsvQuoting::Necessary));
+
+/// Sets the delimiter and quoting CSV files are written and read with, e.g. semicolons for
+/// spreadsheets in locales that use the comma as the decimal separator.
+///
+/// # Arguments
+/// * `delimiter` - The delimiter between fields.
+/// * `quoting` - How fields are quoted.
+pub fn set_csv_format(delimiter: u8, quoting: CsvQuoting) {
+    *CSV_FORMAT.write().unwrap() = (delimiter, quoting);
+}
+
+/// Returns the delimiter and quoting CSV files are written and read with.
+pub fn csv_format() -> (u8, CsvQuoting) {
+    *CSV_FORMAT.read().unwrap()
+}
+
+/// Creates a CSV writer builder with the configured delimiter and quoting.
+fn csv_writer_builder() -> csv::WriterBuilder {
+    let (delimiter, quoting) = csv_format();
+    let mut builder = csv::WriterBuilder::new();
+    builder.delimiter(delimiter).quote_style(quoting.style());
+    builder
+}
+
+/// Creates a CSV reader builder with the configured delimiter. Quoted fields are always read.
+fn csv_reader_builder() -> csv::ReaderBuilder {
+    let (delimiter, _) = csv_format();
+    let mut builder = csv::ReaderBuilder::new();
+    builder.delimiter(delimiter);
+    builder
+}
+
+/// Formats a floating point value with a number of significant digits, in plain decimal notation
+/// without trailing zeros, so the same value is always written the same way.
+///
+/// # Arguments
+/// * `value` - The value to format.
+/// * `digits` - The number of significant digits, 0 for the shortest representation that parses
+/// back to the exact value.
+///
+/// # Returns
+/// The formatted value. NaN and infinite values are written as `NaN`, `inf` and `-inf`.
+pub fn format_float(value: f64, digits: usize) -> String {
+    if digits == 0 || value == 0.0 || !value.is_finite() {
+        return value.to_string();
+    }
+    let exponent = value.abs().log10().floor() as i32;
+    let decimals = digits as i32 - 1 - exponent;
+    if decimals <= 0 {
+        // Digits left of the decimal point beyond the significant digits are rounded to zeros.
+        let scale = 10f64.powi(-decimals);
+        return ((value / scale).round() * scale).to_string();
+    }
+    let formatted = format!("{:.*}", decimals as usize, value);
+    let formatted = formatted.trim_end_matches('0').trim_end_matches('.');
+    match formatted {
+        "-0" => "0".to_string(),
+        _ => formatted.to_string(),
+    }
+}
 
 pub trait CSVWriter<T>
 where
     T: Sized,
 {
-    fn write_csv(&self, data: &Vec<T>, file_name: &str) -> Result<(), Box<dyn Error>>;
+    /// Appends rows to a CSV file, with floating point values rounded to `float_digits`
+    /// significant digits, 0 for full precision.
+    fn write_csv(
+        &self,
+        data: &Vec<T>,
+        file_name: &str,
+        float_digits: usize,
+    ) -> Result<(), Box<dyn Error>>;
     fn write_csv_header(&self, file_name: &str) -> Result<(), Box<dyn Error>>;
 }
 
+/// Appends rows to the CSV files of a run on behalf of all workers.
+/// Shared as an `Arc<Mutex<CsvSink>>`, the header check and the rows of every write happen while
+/// holding the lock, so concurrent writes to the same file never interleave and the header is
+/// written exactly once. Floating point values are written with the precision of the run.
+#[derive(Debug)]
+pub struct CsvSink {
+    float_digits: usize,
+    /// The comparison results appended to each comparisons file at full precision, if they are
+    /// kept for the results database.
+    kept_comparisons: Option<HashMap<String, Vec<ComparisonResult>>>,
+}
+
+impl Default for CsvSink {
+    fn default() -> Self {
+        CsvSink::new(DEFAULT_FLOAT_DIGITS)
+    }
+}
+
+impl CsvSink {
+    /// Creates a sink.
+    ///
+    /// # Arguments
+    /// * `float_digits` - The number of significant digits floating point values are written
+    ///   with, 0 to write values at full precision.
+    pub fn new(float_digits: usize) -> Self {
+        CsvSink {
+            float_digits,
+            kept_comparisons: None,
+        }
+    }
+
+    /// Keeps the comparison results appended through the sink until they are taken, so that
+    /// they can be recorded without reading back their rounded values.
+    pub fn with_kept_comparisons(mut self) -> Self {
+        self.kept_comparisons = Some(HashMap::new());
+        self
+    }
+
+    /// Keeps comparison results appended to a comparisons file, if results are kept.
+    ///
+    /// # Arguments
+    /// * `file_name` - The path to the comparisons file.
+    /// * `results` - The appended comparison results.
+    pub fn keep_comparisons(&mut self, file_name: &str, results: &[ComparisonResult]) {
+        if let Some(kept_comparisons) = self.kept_comparisons.as_mut() {
+            kept_comparisons
+                .entry(file_name.to_string())
+                .or_default()
+                .extend_from_slice(results);
+        }
+    }
+
+    /// Takes the comparison results kept for a comparisons file.
+    ///
+    /// # Arguments
+    /// * `file_name` - The path to the comparisons file.
+    ///
+    /// # Returns
+    /// The comparison results in the order they were appended, empty if none were kept.
+    pub fn take_comparisons(&mut self, file_name: &str) -> Vec<ComparisonResult> {
+        self.kept_comparisons
+            .as_mut()
+            .and_then(|kept_comparisons| kept_comparisons.remove(file_name))
+            .unwrap_or_default()
+    }
+
+    /// Returns the number of significant digits floating point values are written with.
+    pub fn float_digits(&self) -> usize {
+        self.float_digits
+    }
+
+    /// Writes the header of a CSV file if it is empty, then appends the rows.
+    ///
+    /// # Arguments
+    /// * `writer` - The CSV handler of the rows.
+    /// * `data` - The rows to append.
+    /// * `file_name` - The path to the CSV file.
+    pub fn append<T>(
+        &mut self,
+        writer: &impl CSVWriter<T>,
+        data: &Vec<T>,
+        file_name: &str,
+    ) -> Result<(), Box<dyn Error>> {
+        writer.write_csv_header(file_name)?;
+        writer.write_csv(data, file_name, self.float_digits)
+    }
+}
+
 pub trait CSVReader<T>
 where
     T: Sized,
@@ -20,61 +217,1129 @@ where
     fn find_entry(&self, file_name: &str, column: usize, value: &str) -> Result<T, Box<dyn Error>>;
 }
 
-#[derive(Debug, Clone)]
-pub struct ComparisonResult {
-    pub orig_image_name: String,
-    pub comp_image_name: String,
-    pub distance: f32,
-    pub effort: u32,
-    pub orig_file_size: u64,
-    pub comp_file_size: u64,
-    pub orig_raw_size: u64,
-    pub comp_raw_size: u64,
-    pub comp_file_size_ratio: f64,
-    pub raw_file_size_ratio: f64,
-    pub mse: f64,
-    pub psnr: f64,
-    pub ssim: f64,
-    pub ms_ssim: f64,
-    pub butteraugli: f64,
-    pub butteraugli_pnorm: f64,
-    pub ssimulacra2: f64,
+/// A distance rounded to thousandths, used to sort, group and join results by distance.
+/// Distances read back from CSV files can differ in their last bits depending on how they were
+/// written, e.g. 1.5 and 1.5000001, so they are never compared as floats.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct DistanceKey(pub i32);
+
+impl DistanceKey {
+    /// The number of key steps per unit of distance.
+    pub const SCALE: f64 = 1000.0;
+
+    /// Creates the key of a distance.
+    ///
+    /// # Arguments
+    /// * `distance` - The distance.
+    ///
+    /// # Returns
+    /// The distance in thousandths, rounded to the nearest.
+    pub fn new(distance: f32) -> DistanceKey {
+        DistanceKey((distance as f64 * DistanceKey::SCALE).round() as i32)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ComparisonResult {
+    pub orig_image_name: String,
+    pub comp_image_name: String,
+    pub distance: f32,
+    pub effort: u32,
+    pub orig_file_size: u64,
+    pub comp_file_size: u64,
+    pub orig_raw_size: u64,
+    pub comp_raw_size: u64,
+    /// The ratio of the original to the compressed file size, in the direction of `size_ratio`.
+    pub comp_file_size_ratio: f64,
+    /// The ratio of the raw image size to the compressed file size, in the direction of
+    /// `size_ratio`.
+    pub raw_file_size_ratio: f64,
+    /// The direction of the size ratios.
+    pub size_ratio: SizeRatio,
+    pub mse: f64,
+    pub psnr: f64,
+    pub ssim: f64,
+    pub ms_ssim: f64,
+    pub butteraugli: f64,
+    pub butteraugli_pnorm: f64,
+    pub ssimulacra2: f64,
+    pub coding_mode: String,
+    pub size_regression: bool,
+    pub peak_rss_bytes: u64,
+    pub cpu_seconds: f64,
+    pub roi: String,
+    pub bpp: f64,
+    pub intensity_target: String,
+    /// Whether the encode succeeded, see `STATUS_OK`, `STATUS_ENCODE_FAILED` and
+    /// `STATUS_IMPLAUSIBLE_METRICS`.
+    pub status: String,
+    /// The PSNR of the R, G and B, or Y, Cb and Cr channels, see `channel_space`.
+    pub channel_psnr: [f64; 3],
+    pub channel_space: String,
+    /// Whether the original image was already lossy, so its error is partly JPEG or WebP loss
+    /// rather than JXL loss.
+    pub source_is_lossy: bool,
+    /// The cjxl quality the image was encoded with in quality mode, empty when sweeping
+    /// distances. The distance is then the distance cjxl maps the quality to.
+    pub quality: String,
+    /// The fastest and mean time in milliseconds of the timed djxl decodes, NaN unless decode
+    /// speed is benchmarked.
+    pub decode_time_ms_min: f64,
+    pub decode_time_ms_mean: f64,
+    /// The MSE, PSNR, SSIM and MS-SSIM against the lossless master of the original image, NaN
+    /// unless a master directory is configured. Together with the metrics against the lossy
+    /// original they separate the loss of the source from the loss of the encode.
+    pub mse_vs_master: f64,
+    pub psnr_vs_master: f64,
+    pub ssim_vs_master: f64,
+    pub ms_ssim_vs_master: f64,
+    /// The dimensions of the image, which weigh it in pixel-weighted summaries. 0 if unknown.
+    pub width: u32,
+    pub height: u32,
+    /// Whether the images were compared in the color space of the original with their embedded
+    /// ICC profiles, false if they were compared as sRGB.
+    pub color_managed: bool,
+    /// The name and value of each external metric, written as one column per metric after the
+    /// built-in columns.
+    pub external_metrics: Vec<(String, f64)>,
+}
+
+impl ComparisonResult {
+    /// The status of a successful encode.
+    pub const STATUS_OK: &'static str = "ok";
+    /// The status of an encode that failed or produced an empty or corrupt file.
+    pub const STATUS_ENCODE_FAILED: &'static str = "encode_failed";
+    /// The status of an encode with a metric outside of its plausible range in strict metric
+    /// validation, whose metrics cannot be trusted.
+    pub const STATUS_IMPLAUSIBLE_METRICS: &'static str = "implausible_metrics";
+
+    /// Gets the key of the distance of the result, to compare it to the distances of other
+    /// results.
+    pub fn distance_key(&self) -> DistanceKey {
+        DistanceKey::new(self.distance)
+    }
+
+    /// Creates the row of an encode that failed.
+    /// The sizes are 0 and the metrics are NaN, so they cannot be mistaken for measurements.
+    ///
+    /// # Arguments
+    /// * `orig_image_name` - The file name of the original image.
+    /// * `comp_image_name` - The file name the compressed image would have had.
+    /// * `distance` - The distance of the encode.
+    /// * `effort` - The effort of the encode.
+    ///
+    /// # Returns
+    /// The comparison result of the failed encode.
+    pub fn encode_failed(
+        orig_image_name: &str,
+        comp_image_name: &str,
+        distance: f32,
+        effort: u32,
+    ) -> ComparisonResult {
+        ComparisonResult {
+            orig_image_name: orig_image_name.to_string(),
+            comp_image_name: comp_image_name.to_string(),
+            distance,
+            effort,
+            orig_file_size: 0,
+            comp_file_size: 0,
+            orig_raw_size: 0,
+            comp_raw_size: 0,
+            comp_file_size_ratio: f64::NAN,
+            raw_file_size_ratio: f64::NAN,
+            size_ratio: SizeRatio::default(),
+            mse: f64::NAN,
+            psnr: f64::NAN,
+            ssim: f64::NAN,
+            ms_ssim: f64::NAN,
+            butteraugli: f64::NAN,
+            butteraugli_pnorm: f64::NAN,
+            ssimulacra2: f64::NAN,
+            coding_mode: "".to_string(),
+            size_regression: false,
+            peak_rss_bytes: 0,
+            cpu_seconds: 0.0,
+            roi: "".to_string(),
+            bpp: f64::NAN,
+            intensity_target: "".to_string(),
+            status: ComparisonResult::STATUS_ENCODE_FAILED.to_string(),
+            channel_psnr: [f64::NAN; 3],
+            channel_space: "".to_string(),
+            source_is_lossy: false,
+            quality: "".to_string(),
+            decode_time_ms_min: f64::NAN,
+            decode_time_ms_mean: f64::NAN,
+            mse_vs_master: f64::NAN,
+            psnr_vs_master: f64::NAN,
+            ssim_vs_master: f64::NAN,
+            ms_ssim_vs_master: f64::NAN,
+            width: 0,
+            height: 0,
+            color_managed: false,
+            external_metrics: Vec::new(),
+        }
+    }
+
+    /// Returns whether the encode of the comparison result succeeded.
+    pub fn is_ok(&self) -> bool {
+        self.status == ComparisonResult::STATUS_OK
+    }
+
+    /// Returns the names of the external metrics of the comparison result.
+    pub fn external_metric_names(&self) -> Vec<String> {
+        self.external_metrics
+            .iter()
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+
+    /// Returns the value of a built-in metric of the comparison result.
+    pub fn metric(&self, metric: MetricKind) -> f64 {
+        match metric {
+            MetricKind::Mse => self.mse,
+            MetricKind::Psnr => self.psnr,
+            MetricKind::Ssim => self.ssim,
+            MetricKind::MsSsim => self.ms_ssim,
+            MetricKind::Butteraugli => self.butteraugli,
+            MetricKind::Ssimulacra2 => self.ssimulacra2,
+        }
+    }
+
+    /// Checks every metric of the comparison result against its plausible range.
+    ///
+    /// # Returns
+    /// A description of each metric outside of its range, empty if all metrics are plausible.
+    pub fn implausible_metrics(&self) -> Vec<String> {
+        MetricKind::ALL
+            .iter()
+            .filter_map(|metric| metric.check_plausible(self.metric(*metric)).err())
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ComparisonResultDiff {
+    pub orig_image_name: String,
+    pub comp_image_name: String,
+    pub distance: f32,
+    pub effort: u32,
+    pub diff_orig_file_size: f64,
+    pub diff_comp_file_size: f64,
+    pub diff_orig_raw_size: f64,
+    pub diff_comp_raw_size: f64,
+    pub diff_comp_file_size_ratio: f64,
+    pub diff_raw_file_size_ratio: f64,
+    pub diff_mse: f64,
+    pub diff_psnr: f64,
+    pub diff_ssim: f64,
+    pub diff_ms_ssim: f64,
+    pub diff_butteraugli: f64,
+    pub diff_butteraugli_pnorm: f64,
+    pub diff_ssimulacra2: f64,
+    pub non_finite_values: u64,
+    pub baseline_codec: String,
+    pub diff_channel_psnr: [f64; 3],
+}
+
+/// A single metric value of a comparison result in long (tidy) format.
+#[derive(Debug, Clone)]
+pub struct TidyResult {
+    pub image: String,
+    pub distance: f32,
+    pub effort: u32,
+    pub commit: String,
+    pub metric_name: String,
+    pub value: f64,
+}
+
+impl TidyResult {
+    /// The names of the metrics emitted for every comparison result.
+    pub const METRIC_NAMES: [&'static str; 9] = [
+        "comp_file_size_ratio",
+        "raw_file_size_ratio",
+        "mse",
+        "psnr",
+        "ssim",
+        "ms_ssim",
+        "butteraugli",
+        "butteraugli_pnorm",
+        "ssimulacra2",
+    ];
+
+    /// Transforms wide comparison results into one tidy row per metric value.
+    ///
+    /// # Arguments
+    /// * `results` - The comparison results to transform.
+    /// * `commit` - The libjxl commit the results were produced with.
+    ///
+    /// # Returns
+    /// The tidy rows, `METRIC_NAMES.len()` plus one per external metric for each comparison
+    /// result.
+    pub fn from_comparison_results(results: &Vec<ComparisonResult>, commit: &str) -> Vec<TidyResult> {
+        let mut rows = Vec::new();
+        for result in results.iter().filter(|result| result.is_ok()) {
+            let values = [
+                result.comp_file_size_ratio,
+                result.raw_file_size_ratio,
+                result.mse,
+                result.psnr,
+                result.ssim,
+                result.ms_ssim,
+                result.butteraugli,
+                result.butteraugli_pnorm,
+                result.ssimulacra2,
+            ];
+            let external_metrics = result
+                .external_metrics
+                .iter()
+                .map(|(metric_name, value)| (metric_name.as_str(), *value));
+            for (metric_name, value) in TidyResult::METRIC_NAMES
+                .iter()
+                .copied()
+                .zip(values)
+                .chain(external_metrics)
+            {
+                rows.push(TidyResult {
+                    image: result.orig_image_name.clone(),
+                    distance: result.distance,
+                    effort: result.effort,
+                    commit: commit.to_string(),
+                    metric_name: metric_name.to_string(),
+                    value,
+                });
+            }
+        }
+        rows
+    }
+}
+
+/// The metrics of a comparison result mapped to a common 0 to 1 scale where higher is better,
+/// see `MetricKind::score`, and their weighted composite to rank encodes by.
+#[derive(Debug, Clone)]
+pub struct NormalizedScores {
+    pub comp_image_name: String,
+    pub distance: f32,
+    pub effort: u32,
+    /// The score of each metric, in the order of `MetricKind::ALL`.
+    pub scores: [f64; 6],
+    /// The weighted mean of the scores of the weighted metrics that were computed, NaN if no
+    /// weights are configured or none of the weighted metrics was computed.
+    pub composite: f64,
+}
+
+impl NormalizedScores {
+    /// Scores the metrics of a comparison result.
+    ///
+    /// # Arguments
+    /// * `result` - The comparison result.
+    /// * `weights` - The weights of the metrics in the composite score.
+    ///
+    /// # Returns
+    /// The scores of the comparison result.
+    pub fn new(result: &ComparisonResult, weights: &[ScoreWeight]) -> NormalizedScores {
+        let scores = MetricKind::ALL.map(|metric| metric.score(result.metric(metric)));
+        let (weighted_sum, total_weight) = weights
+            .iter()
+            .map(|weight| {
+                (
+                    weight.metric.score(result.metric(weight.metric)),
+                    weight.weight,
+                )
+            })
+            .filter(|(score, _)| score.is_finite())
+            .fold((0.0, 0.0), |(sum, total), (score, weight)| {
+                (sum + score * weight, total + weight)
+            });
+        let composite = match total_weight > 0.0 {
+            true => weighted_sum / total_weight,
+            false => f64::NAN,
+        };
+        NormalizedScores {
+            comp_image_name: result.comp_image_name.clone(),
+            distance: result.distance,
+            effort: result.effort,
+            scores,
+            composite,
+        }
+    }
+}
+
+/// The average comparison results of all images in a test set at one distance and effort.
+#[derive(Debug, Clone)]
+pub struct AggregateResult {
+    pub test_set: String,
+    pub distance: f32,
+    pub effort: u32,
+    pub num_images: usize,
+    pub avg_comp_file_size_ratio: f64,
+    pub avg_raw_file_size_ratio: f64,
+    pub avg_mse: f64,
+    pub avg_psnr: f64,
+    pub avg_ssim: f64,
+    pub avg_ms_ssim: f64,
+    pub avg_butteraugli: f64,
+    pub avg_butteraugli_pnorm: f64,
+    pub avg_ssimulacra2: f64,
+    pub avg_bpp: f64,
+    /// How the images are weighted in the averages: equal, pixels or bytes.
+    pub weighting: String,
+}
+
+/// The distance found by a quality target search for an image.
+#[derive(Debug, Clone)]
+pub struct QualityTargetResult {
+    pub image_name: String,
+    pub metric: String,
+    pub target: f64,
+    pub distance: f64,
+    pub effort: u32,
+    pub value: f64,
+    pub bpp: f64,
+    pub iterations: u32,
+    pub converged: bool,
+}
+
+/// The distance found by the quality target search of an image at one effort, for the curve of
+/// how the rate and encode time of the image vary with the effort at a fixed quality.
+#[derive(Debug, Clone)]
+pub struct EffortCurvePoint {
+    pub image_name: String,
+    pub effort: u32,
+    pub metric: String,
+    pub target: f64,
+    /// The distance the search found, and the metric value it achieved.
+    pub distance: f64,
+    pub value: f64,
+    pub bpp: f64,
+    /// The wall time of the encode at the found distance, including the docker exec overhead.
+    pub encode_time_ms: f64,
+    pub converged: bool,
+}
+
+/// The distance found by a size target search for an image, and the quality it resulted in.
+#[derive(Debug, Clone)]
+pub struct SizeTargetResult {
+    pub image_name: String,
+    pub target_size: u64,
+    pub distance: f64,
+    pub effort: u32,
+    pub file_size: u64,
+    pub bpp: f64,
+    pub psnr: f64,
+    pub ssimulacra2: f64,
+    pub butteraugli: f64,
+    pub iterations: u32,
+    pub status: String,
+}
+
+/// A distance and effort combination sampled for an image when the grid is sampled.
+#[derive(Debug, Clone)]
+pub struct SampledCombination {
+    pub image_name: String,
+    pub distance: f64,
+    pub effort: u32,
+}
+
+/// The outcome of a planned encode of a run.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ComboOutcome {
+    /// The image was encoded and compared to the original.
+    Encoded,
+    /// The encode was reused from the encode cache and compared to the original.
+    Cached,
+    /// The encode was not run, or its result was not recorded, for the given reason.
+    Skipped(String),
+    /// The encode or the decode of the compressed image failed, for the given reason.
+    Failed(String),
+}
+
+impl ComboOutcome {
+    /// Returns the status written for the outcome.
+    pub fn name(&self) -> &'static str {
+        match self {
+            ComboOutcome::Encoded => "encoded",
+            ComboOutcome::Cached => "cached",
+            ComboOutcome::Skipped(_) => "skipped",
+            ComboOutcome::Failed(_) => "failed",
+        }
+    }
+
+    /// Returns the reason a combination was skipped or failed, empty otherwise.
+    pub fn reason(&self) -> &str {
+        match self {
+            ComboOutcome::Skipped(reason) | ComboOutcome::Failed(reason) => reason,
+            _ => "",
+        }
+    }
+}
+
+/// The outcome of one planned distance (or quality) and effort combination of an image at a
+/// commit, written to the status file of the run so that its completeness can be audited.
+/// Only sweeps plan combinations, the searches of quality and size targets have no status.
+#[derive(Debug, Clone)]
+pub struct ComboStatus {
+    pub test_set: String,
+    pub commit: String,
+    pub image_name: String,
+    pub distance: f64,
+    /// The cjxl quality in quality mode, empty when sweeping distances.
+    pub quality: String,
+    pub effort: u32,
+    pub outcome: ComboOutcome,
+}
+
+/// The number of samples in a bin of absolute errors between an original and compressed image.
+#[derive(Debug, Clone)]
+pub struct ErrorHistogramBin {
+    pub bin: String,
+    pub count: u64,
+}
+
+/// The quality of a compressed image decoded from a prefix of its bitstream, as a browser
+/// shows it while the image is still loading.
+#[derive(Debug, Clone)]
+pub struct ProgressiveDecode {
+    /// The percentage of the bitstream that was decoded.
+    pub truncation: f64,
+    pub bytes: u64,
+    /// The metrics against the original image, NaN if the prefix could not be decoded.
+    pub mse: f64,
+    pub psnr: f64,
+    pub ssim: f64,
+}
+
+pub struct ComparisonResultCSV {
+    /// The external metric columns written after the built-in columns.
+    external_metrics: Vec<String>,
+    /// The direction of the size ratios, which names their columns.
+    size_ratio: SizeRatio,
+}
+
+pub struct ErrorHistogramCSV {}
+
+pub struct ProgressiveDecodeCSV {}
+
+pub struct QualityTargetResultCSV {}
+
+pub struct EffortCurvePointCSV {}
+
+pub struct SizeTargetResultCSV {}
+
+pub struct SampledCombinationCSV {}
+
+pub struct ComboStatusCSV {}
+
+pub struct AggregateResultCSV {
+    /// The direction of the size ratios, which names their columns.
+    size_ratio: SizeRatio,
+}
+
+pub struct TidyResultCSV {}
+
+pub struct NormalizedScoresCSV {}
+
+pub struct ComparisonResultDiffCSV {
+    /// The direction of the size ratios, which names their columns.
+    size_ratio: SizeRatio,
+}
+
+impl ComparisonResultCSV {
+    pub fn new() -> Self {
+        ComparisonResultCSV {
+            external_metrics: Vec::new(),
+            size_ratio: SizeRatio::default(),
+        }
+    }
+
+    /// Creates a CSV handler that also writes a column for each external metric.
+    /// Rows without a value for an external metric get NaN in its column.
+    ///
+    /// # Arguments
+    /// * `external_metrics` - The names of the external metrics, in column order.
+    pub fn with_external_metrics(external_metrics: Vec<String>) -> Self {
+        ComparisonResultCSV {
+            external_metrics,
+            size_ratio: SizeRatio::default(),
+        }
+    }
+
+    /// Sets the direction of the size ratios, which names their columns.
+    ///
+    /// # Arguments
+    /// * `size_ratio` - The direction of the size ratios of the written results.
+    pub fn with_size_ratio(mut self, size_ratio: SizeRatio) -> Self {
+        self.size_ratio = size_ratio;
+        self
+    }
+}
+
+impl ComparisonResultDiffCSV {
+    pub fn new() -> Self {
+        ComparisonResultDiffCSV {
+            size_ratio: SizeRatio::default(),
+        }
+    }
+
+    /// Sets the direction of the size ratios, which names their columns.
+    ///
+    /// # Arguments
+    /// * `size_ratio` - The direction of the size ratios of the diffed results.
+    pub fn with_size_ratio(mut self, size_ratio: SizeRatio) -> Self {
+        self.size_ratio = size_ratio;
+        self
+    }
+}
+
+impl TidyResultCSV {
+    pub fn new() -> Self {
+        TidyResultCSV {}
+    }
+}
+
+impl NormalizedScoresCSV {
+    pub fn new() -> Self {
+        NormalizedScoresCSV {}
+    }
+}
+
+impl AggregateResultCSV {
+    pub fn new() -> Self {
+        AggregateResultCSV {
+            size_ratio: SizeRatio::default(),
+        }
+    }
+
+    /// Sets the direction of the size ratios, which names their columns.
+    ///
+    /// # Arguments
+    /// * `size_ratio` - The direction of the size ratios of the averaged results.
+    pub fn with_size_ratio(mut self, size_ratio: SizeRatio) -> Self {
+        self.size_ratio = size_ratio;
+        self
+    }
+}
+
+impl QualityTargetResultCSV {
+    pub fn new() -> Self {
+        QualityTargetResultCSV {}
+    }
+}
+
+impl EffortCurvePointCSV {
+    pub fn new() -> Self {
+        EffortCurvePointCSV {}
+    }
+}
+
+impl SizeTargetResultCSV {
+    pub fn new() -> Self {
+        SizeTargetResultCSV {}
+    }
+}
+
+impl SampledCombinationCSV {
+    pub fn new() -> Self {
+        SampledCombinationCSV {}
+    }
+}
+
+impl ComboStatusCSV {
+    pub fn new() -> Self {
+        ComboStatusCSV {}
+    }
+}
+
+impl ErrorHistogramCSV {
+    pub fn new() -> Self {
+        ErrorHistogramCSV {}
+    }
+}
+
+impl CSVWriter<ErrorHistogramBin> for ErrorHistogramCSV {
+    fn write_csv(
+        &self,
+        data: &Vec<ErrorHistogramBin>,
+        file_name: &str,
+        _float_digits: usize,
+    ) -> Result<(), Box<dyn Error>> {
+        let file = OpenOptions::new().append(true).open(file_name)?;
+        let mut wtr = csv_writer_builder().from_writer(file);
+        for record in data {
+            wtr.write_record(&[&record.bin, &record.count.to_string()])?;
+        }
+        wtr.flush()?;
+        Ok(())
+    }
+
+    fn write_csv_header(&self, file_name: &str) -> Result<(), Box<dyn Error>> {
+        let path = std::path::Path::new(file_name);
+        if path.exists() && path.metadata()?.len() > 0 {
+            return Ok(());
+        }
+        if let Some(parent) = path.parent() {
+            if !parent.exists() {
+                std::fs::create_dir_all(parent)?;
+            }
+        }
+        let mut wtr = csv_writer_builder().from_path(file_name)?;
+        wtr.write_record(&["Absolute Error", "Count"])?;
+        wtr.flush()?;
+        Ok(())
+    }
+}
+
+impl ProgressiveDecodeCSV {
+    pub fn new() -> Self {
+        ProgressiveDecodeCSV {}
+    }
+}
+
+impl CSVWriter<ProgressiveDecode> for ProgressiveDecodeCSV {
+    fn write_csv(
+        &self,
+        data: &Vec<ProgressiveDecode>,
+        file_name: &str,
+        float_digits: usize,
+    ) -> Result<(), Box<dyn Error>> {
+        let file = OpenOptions::new().append(true).open(file_name)?;
+        let mut wtr = csv_writer_builder().from_writer(file);
+        for record in data {
+            wtr.write_record(&[
+                format_float(record.truncation, float_digits),
+                record.bytes.to_string(),
+                format_float(record.mse, float_digits),
+                format_float(record.psnr, float_digits),
+                format_float(record.ssim, float_digits),
+            ])?;
+        }
+        wtr.flush()?;
+        Ok(())
+    }
+
+    fn write_csv_header(&self, file_name: &str) -> Result<(), Box<dyn Error>> {
+        let path = std::path::Path::new(file_name);
+        if path.exists() && path.metadata()?.len() > 0 {
+            return Ok(());
+        }
+        if let Some(parent) = path.parent() {
+            if !parent.exists() {
+                std::fs::create_dir_all(parent)?;
+            }
+        }
+        let mut wtr = csv_writer_builder().from_path(file_name)?;
+        wtr.write_record(&["Truncation (%)", "Bytes", "MSE", "PSNR", "SSIM"])?;
+        wtr.flush()?;
+        Ok(())
+    }
+}
+
+impl CSVWriter<QualityTargetResult> for QualityTargetResultCSV {
+    fn write_csv(
+        &self,
+        data: &Vec<QualityTargetResult>,
+        file_name: &str,
+        float_digits: usize,
+    ) -> Result<(), Box<dyn Error>> {
+        let file = OpenOptions::new().append(true).open(file_name)?;
+        let mut wtr = csv_writer_builder().from_writer(file);
+        for record in data {
+            wtr.write_record(&[
+                &record.image_name,
+                &record.metric,
+                &format_float(record.target, float_digits),
+                &format_float(record.distance, float_digits),
+                &record.effort.to_string(),
+                &format_float(record.value, float_digits),
+                &format_float(record.bpp, float_digits),
+                &record.iterations.to_string(),
+                &record.converged.to_string(),
+            ])?;
+        }
+        wtr.flush()?;
+        Ok(())
+    }
+
+    fn write_csv_header(&self, file_name: &str) -> Result<(), Box<dyn Error>> {
+        let path = std::path::Path::new(file_name);
+        if path.exists() && path.metadata()?.len() > 0 {
+            return Ok(());
+        }
+        if let Some(parent) = path.parent() {
+            if !parent.exists() {
+                std::fs::create_dir_all(parent)?;
+            }
+        }
+        let mut wtr = csv_writer_builder().from_path(file_name)?;
+        wtr.write_record(&[
+            "Image Name",
+            "Metric",
+            "Target",
+            "Distance",
+            "Effort",
+            "Value",
+            "BPP",
+            "Iterations",
+            "Converged",
+        ])?;
+        wtr.flush()?;
+        Ok(())
+    }
+}
+
+impl CSVWriter<EffortCurvePoint> for EffortCurvePointCSV {
+    fn write_csv(
+        &self,
+        data: &Vec<EffortCurvePoint>,
+        file_name: &str,
+        float_digits: usize,
+    ) -> Result<(), Box<dyn Error>> {
+        let file = OpenOptions::new().append(true).open(file_name)?;
+        let mut wtr = csv_writer_builder().from_writer(file);
+        for record in data {
+            wtr.write_record(&[
+                &record.image_name,
+                &record.effort.to_string(),
+                &record.metric,
+                &format_float(record.target, float_digits),
+                &format_float(record.distance, float_digits),
+                &format_float(record.value, float_digits),
+                &format_float(record.bpp, float_digits),
+                &format_float(record.encode_time_ms, float_digits),
+                &record.converged.to_string(),
+            ])?;
+        }
+        wtr.flush()?;
+        Ok(())
+    }
+
+    fn write_csv_header(&self, file_name: &str) -> Result<(), Box<dyn Error>> {
+        let path = std::path::Path::new(file_name);
+        if path.exists() && path.metadata()?.len() > 0 {
+            return Ok(());
+        }
+        if let Some(parent) = path.parent() {
+            if !parent.exists() {
+                std::fs::create_dir_all(parent)?;
+            }
+        }
+        let mut wtr = csv_writer_builder().from_path(file_name)?;
+        wtr.write_record(&[
+            "Image Name",
+            "Effort",
+            "Metric",
+            "Target",
+            "Distance",
+            "Value",
+            "BPP",
+            "Encode Time (ms)",
+            "Converged",
+        ])?;
+        wtr.flush()?;
+        Ok(())
+    }
+}
+
+impl CSVWriter<SizeTargetResult> for SizeTargetResultCSV {
+    fn write_csv(
+        &self,
+        data: &Vec<SizeTargetResult>,
+        file_name: &str,
+        float_digits: usize,
+    ) -> Result<(), Box<dyn Error>> {
+        let file = OpenOptions::new().append(true).open(file_name)?;
+        let mut wtr = csv_writer_builder().from_writer(file);
+        for record in data {
+            wtr.write_record(&[
+                &record.image_name,
+                &record.target_size.to_string(),
+                &format_float(record.distance, float_digits),
+                &record.effort.to_string(),
+                &record.file_size.to_string(),
+                &format_float(record.bpp, float_digits),
+                &format_float(record.psnr, float_digits),
+                &format_float(record.ssimulacra2, float_digits),
+                &format_float(record.butteraugli, float_digits),
+                &record.iterations.to_string(),
+                &record.status,
+            ])?;
+        }
+        wtr.flush()?;
+        Ok(())
+    }
+
+    fn write_csv_header(&self, file_name: &str) -> Result<(), Box<dyn Error>> {
+        let path = std::path::Path::new(file_name);
+        if path.exists() && path.metadata()?.len() > 0 {
+            return Ok(());
+        }
+        if let Some(parent) = path.parent() {
+            if !parent.exists() {
+                std::fs::create_dir_all(parent)?;
+            }
+        }
+        let mut wtr = csv_writer_builder().from_path(file_name)?;
+        wtr.write_record(&[
+            "Image Name",
+            "Target Size",
+            "Distance",
+            "Effort",
+            "File Size",
+            "BPP",
+            "PSNR",
+            "SSIMULACRA2",
+            "Butteraugli",
+            "Iterations",
+            "Status",
+        ])?;
+        wtr.flush()?;
+        Ok(())
+    }
+}
+
+impl CSVWriter<SampledCombination> for SampledCombinationCSV {
+    fn write_csv(
+        &self,
+        data: &Vec<SampledCombination>,
+        file_name: &str,
+        float_digits: usize,
+    ) -> Result<(), Box<dyn Error>> {
+        let file = OpenOptions::new().append(true).open(file_name)?;
+        let mut wtr = csv_writer_builder().from_writer(file);
+        for record in data {
+            wtr.write_record(&[
+                &record.image_name,
+                &format_float(record.distance, float_digits),
+                &record.effort.to_string(),
+            ])?;
+        }
+        wtr.flush()?;
+        Ok(())
+    }
+
+    fn write_csv_header(&self, file_name: &str) -> Result<(), Box<dyn Error>> {
+        let path = std::path::Path::new(file_name);
+        if path.exists() && path.metadata()?.len() > 0 {
+            return Ok(());
+        }
+        if let Some(parent) = path.parent() {
+            if !parent.exists() {
+                std::fs::create_dir_all(parent)?;
+            }
+        }
+        let mut wtr = csv_writer_builder().from_path(file_name)?;
+        wtr.write_record(&["Image Name", "Distance", "Effort"])?;
+        wtr.flush()?;
+        Ok(())
+    }
 }
 
-#[derive(Debug, Clone)]
-pub struct ComparisonResultDiff {
-    pub orig_image_name: String,
-    pub comp_image_name: String,
-    pub distance: f32,
-    pub effort: u32,
-    pub diff_orig_file_size: f64,
-    pub diff_comp_file_size: f64,
-    pub diff_orig_raw_size: f64,
-    pub diff_comp_raw_size: f64,
-    pub diff_comp_file_size_ratio: f64,
-    pub diff_raw_file_size_ratio: f64,
-    pub diff_mse: f64,
-    pub diff_psnr: f64,
-    pub diff_ssim: f64,
-    pub diff_ms_ssim: f64,
-    pub diff_butteraugli: f64,
-    pub diff_butteraugli_pnorm: f64,
-    pub diff_ssimulacra2: f64,
+impl CSVWriter<ComboStatus> for ComboStatusCSV {
+    fn write_csv(
+        &self,
+        data: &Vec<ComboStatus>,
+        file_name: &str,
+        float_digits: usize,
+    ) -> Result<(), Box<dyn Error>> {
+        let file = OpenOptions::new().append(true).open(file_name)?;
+        let mut wtr = csv_writer_builder().from_writer(file);
+        for record in data {
+            wtr.write_record(&[
+                record.test_set.as_str(),
+                record.commit.as_str(),
+                record.image_name.as_str(),
+                format_float(record.distance, float_digits).as_str(),
+                record.quality.as_str(),
+                record.effort.to_string().as_str(),
+                record.outcome.name(),
+                record.outcome.reason(),
+            ])?;
+        }
+        wtr.flush()?;
+        Ok(())
+    }
+
+    fn write_csv_header(&self, file_name: &str) -> Result<(), Box<dyn Error>> {
+        let path = std::path::Path::new(file_name);
+        if path.exists() && path.metadata()?.len() > 0 {
+            return Ok(());
+        }
+        if let Some(parent) = path.parent() {
+            if !parent.exists() {
+                std::fs::create_dir_all(parent)?;
+            }
+        }
+        let mut wtr = csv_writer_builder().from_path(file_name)?;
+        wtr.write_record(&[
+            "Test Set",
+            "Commit",
+            "Image Name",
+            "Distance",
+            "Quality",
+            "Effort",
+            "Status",
+            "Reason",
+        ])?;
+        wtr.flush()?;
+        Ok(())
+    }
 }
 
-pub struct ComparisonResultCSV {}
+impl CSVWriter<AggregateResult> for AggregateResultCSV {
+    fn write_csv(
+        &self,
+        data: &Vec<AggregateResult>,
+        file_name: &str,
+        float_digits: usize,
+    ) -> Result<(), Box<dyn Error>> {
+        let file = OpenOptions::new().append(true).open(file_name)?;
+        let mut wtr = csv_writer_builder().from_writer(file);
+        for record in data {
+            wtr.write_record(&[
+                &record.test_set,
+                &record.distance.to_string(),
+                &record.effort.to_string(),
+                &record.num_images.to_string(),
+                &format_float(record.avg_comp_file_size_ratio, float_digits),
+                &format_float(record.avg_raw_file_size_ratio, float_digits),
+                &format_float(record.avg_mse, float_digits),
+                &format_float(record.avg_psnr, float_digits),
+                &format_float(record.avg_ssim, float_digits),
+                &format_float(record.avg_ms_ssim, float_digits),
+                &format_float(record.avg_butteraugli, float_digits),
+                &format_float(record.avg_butteraugli_pnorm, float_digits),
+                &format_float(record.avg_ssimulacra2, float_digits),
+                &format_float(record.avg_bpp, float_digits),
+                &record.weighting,
+            ])?;
+        }
+        wtr.flush()?;
+        Ok(())
+    }
+
+    fn write_csv_header(&self, file_name: &str) -> Result<(), Box<dyn Error>> {
+        let path = std::path::Path::new(file_name);
+        if path.exists() && path.metadata()?.len() > 0 {
+            return Ok(());
+        }
+        if let Some(parent) = path.parent() {
+            if !parent.exists() {
+                std::fs::create_dir_all(parent)?;
+            }
+        }
+        let mut wtr = csv_writer_builder().from_path(file_name)?;
+        let comp_ratio = self.size_ratio.column("Avg ", "orig");
+        let raw_ratio = self.size_ratio.column("Avg Raw ", "raw");
+        wtr.write_record(&[
+            "Test Set",
+            "Distance",
+            "Effort",
+            "Images",
+            comp_ratio.as_str(),
+            raw_ratio.as_str(),
+            "Avg MSE",
+            "Avg PSNR",
+            "Avg SSIM",
+            "Avg MS-SSIM",
+            "Avg Butteraugli",
+            "Avg Butteraugli 3-Norm",
+            "Avg SSIMULACRA2",
+            "Avg BPP",
+            "Weighting",
+        ])?;
+        wtr.flush()?;
+        Ok(())
+    }
+}
 
-pub struct ComparisonResultDiffCSV {}
+impl CSVWriter<TidyResult> for TidyResultCSV {
+    fn write_csv(
+        &self,
+        data: &Vec<TidyResult>,
+        file_name: &str,
+        float_digits: usize,
+    ) -> Result<(), Box<dyn Error>> {
+        let file = OpenOptions::new().append(true).open(file_name)?;
+        let mut wtr = csv_writer_builder().from_writer(file);
+        for record in data {
+            wtr.write_record(&[
+                &record.image,
+                &record.distance.to_string(),
+                &record.effort.to_string(),
+                &record.commit,
+                &record.metric_name,
+                &format_float(record.value, float_digits),
+            ])?;
+        }
+        wtr.flush()?;
+        Ok(())
+    }
 
-impl ComparisonResultCSV {
-    pub fn new() -> Self {
-        ComparisonResultCSV {}
+    fn write_csv_header(&self, file_name: &str) -> Result<(), Box<dyn Error>> {
+        let path = std::path::Path::new(file_name);
+        if path.exists() && path.metadata()?.len() > 0 {
+            return Ok(());
+        }
+        if let Some(parent) = path.parent() {
+            if !parent.exists() {
+                std::fs::create_dir_all(parent)?;
+            }
+        }
+        let mut wtr = csv_writer_builder().from_path(file_name)?;
+        wtr.write_record(&["image", "distance", "effort", "commit", "metric_name", "value"])?;
+        wtr.flush()?;
+        Ok(())
     }
 }
 
-impl ComparisonResultDiffCSV {
-    pub fn new() -> Self {
-        ComparisonResultDiffCSV {}
+impl CSVWriter<NormalizedScores> for NormalizedScoresCSV {
+    fn write_csv(
+        &self,
+        data: &Vec<NormalizedScores>,
+        file_name: &str,
+        float_digits: usize,
+    ) -> Result<(), Box<dyn Error>> {
+        let file = OpenOptions::new().append(true).open(file_name)?;
+        let mut wtr = csv_writer_builder().from_writer(file);
+        for record in data {
+            let fields = [
+                record.comp_image_name.clone(),
+                record.distance.to_string(),
+                record.effort.to_string(),
+            ];
+            let scores = record
+                .scores
+                .iter()
+                .chain(std::iter::once(&record.composite))
+                .map(|score| format_float(*score, float_digits));
+            wtr.write_record(fields.into_iter().chain(scores))?;
+        }
+        wtr.flush()?;
+        Ok(())
+    }
+
+    fn write_csv_header(&self, file_name: &str) -> Result<(), Box<dyn Error>> {
+        let path = std::path::Path::new(file_name);
+        if path.exists() && path.metadata()?.len() > 0 {
+            return Ok(());
+        }
+        if let Some(parent) = path.parent() {
+            if !parent.exists() {
+                std::fs::create_dir_all(parent)?;
+            }
+        }
+        let mut wtr = csv_writer_builder().from_path(file_name)?;
+        let score_columns = MetricKind::ALL
+            .iter()
+            .map(|metric| format!("{} Score", metric.name()))
+            .chain(std::iter::once("Composite Score".to_string()));
+        wtr.write_record(
+            ["Compressed Image Name", "Distance", "Effort"]
+                .map(String::from)
+                .into_iter()
+                .chain(score_columns),
+        )?;
+        wtr.flush()?;
+        Ok(())
     }
 }
 
@@ -83,11 +1348,26 @@ impl CSVWriter<ComparisonResult> for ComparisonResultCSV {
         &self,
         data: &Vec<ComparisonResult>,
         file_name: &str,
+        float_digits: usize,
     ) -> Result<(), Box<dyn Error>> {
         let file = OpenOptions::new().append(true).open(file_name)?;
-        let mut wtr = csv::Writer::from_writer(file);
+        let mut wtr = csv_writer_builder().from_writer(file);
         for record in data {
-            wtr.write_record(&[
+            let external_values = self
+                .external_metrics
+                .iter()
+                .map(|name| {
+                    format_float(
+                        record
+                            .external_metrics
+                            .iter()
+                            .find(|(metric, _)| metric == name)
+                            .map_or(f64::NAN, |(_, value)| *value),
+                        float_digits,
+                    )
+                })
+                .collect::<Vec<String>>();
+            let fields = [
                 &record.orig_image_name,
                 &record.comp_image_name,
                 &record.distance.to_string(),
@@ -96,16 +1376,40 @@ impl CSVWriter<ComparisonResult> for ComparisonResultCSV {
                 &record.comp_file_size.to_string(),
                 &record.orig_raw_size.to_string(),
                 &record.comp_raw_size.to_string(),
-                &record.comp_file_size_ratio.to_string(),
-                &record.raw_file_size_ratio.to_string(),
-                &record.mse.to_string(),
-                &record.psnr.to_string(),
-                &record.ssim.to_string(),
-                &record.ms_ssim.to_string(),
-                &record.butteraugli.to_string(),
-                &record.butteraugli_pnorm.to_string(),
-                &record.ssimulacra2.to_string(),
-            ])?;
+                &format_float(record.comp_file_size_ratio, float_digits),
+                &format_float(record.raw_file_size_ratio, float_digits),
+                &format_float(record.mse, float_digits),
+                &format_float(record.psnr, float_digits),
+                &format_float(record.ssim, float_digits),
+                &format_float(record.ms_ssim, float_digits),
+                &format_float(record.butteraugli, float_digits),
+                &format_float(record.butteraugli_pnorm, float_digits),
+                &format_float(record.ssimulacra2, float_digits),
+                &record.coding_mode,
+                &record.size_regression.to_string(),
+                &record.peak_rss_bytes.to_string(),
+                &format_float(record.cpu_seconds, float_digits),
+                &record.roi,
+                &format_float(record.bpp, float_digits),
+                &record.intensity_target,
+                &record.status,
+                &format_float(record.channel_psnr[0], float_digits),
+                &format_float(record.channel_psnr[1], float_digits),
+                &format_float(record.channel_psnr[2], float_digits),
+                &record.channel_space,
+                &record.source_is_lossy.to_string(),
+                &record.quality,
+                &format_float(record.decode_time_ms_min, float_digits),
+                &format_float(record.decode_time_ms_mean, float_digits),
+                &format_float(record.mse_vs_master, float_digits),
+                &format_float(record.psnr_vs_master, float_digits),
+                &format_float(record.ssim_vs_master, float_digits),
+                &format_float(record.ms_ssim_vs_master, float_digits),
+                &record.width.to_string(),
+                &record.height.to_string(),
+                &record.color_managed.to_string(),
+            ];
+            wtr.write_record(fields.into_iter().chain(external_values.iter()))?;
         }
         wtr.flush()?;
         Ok(())
@@ -121,8 +1425,10 @@ impl CSVWriter<ComparisonResult> for ComparisonResultCSV {
                 std::fs::create_dir_all(parent)?;
             }
         }
-        let mut wtr = csv::Writer::from_path(file_name)?;
-        wtr.write_record(&[
+        let mut wtr = csv_writer_builder().from_path(file_name)?;
+        let comp_ratio = self.size_ratio.column("", "orig");
+        let raw_ratio = self.size_ratio.column("Raw ", "raw");
+        let headers = [
             "Original Image Name",
             "Compressed Image Name",
             "Distance",
@@ -131,8 +1437,8 @@ impl CSVWriter<ComparisonResult> for ComparisonResultCSV {
             "Compressed File Size",
             "Original Raw Size",
             "Compressed Raw Size",
-            "File Size Ratio",
-            "Raw Size Ratio",
+            comp_ratio.as_str(),
+            raw_ratio.as_str(),
             "MSE",
             "PSNR",
             "SSIM",
@@ -140,7 +1446,32 @@ impl CSVWriter<ComparisonResult> for ComparisonResultCSV {
             "Butteraugli",
             "Butteraugli 3-Norm", // TODO: Support for multiple butteraugli p-norms?
             "SSIMULACRA2",
-        ])?;
+            "Coding Mode",
+            "Size Regression",
+            "Peak RSS Bytes",
+            "CPU Seconds",
+            "ROI",
+            "BPP",
+            "Intensity Target",
+            "Status",
+            "PSNR R/Y",
+            "PSNR G/Cb",
+            "PSNR B/Cr",
+            "PSNR Channels",
+            "Source Is Lossy",
+            "Quality",
+            "Decode Time Min (ms)",
+            "Decode Time Mean (ms)",
+            "MSE vs Master",
+            "PSNR vs Master",
+            "SSIM vs Master",
+            "MS-SSIM vs Master",
+            "Width",
+            "Height",
+            "Color Managed",
+        ];
+        let external_headers = self.external_metrics.iter().map(|name| name.as_str());
+        wtr.write_record(headers.into_iter().chain(external_headers))?;
         wtr.flush()?;
         Ok(())
     }
@@ -151,9 +1482,10 @@ impl CSVWriter<ComparisonResultDiff> for ComparisonResultDiffCSV {
         &self,
         data: &Vec<ComparisonResultDiff>,
         file_name: &str,
+        float_digits: usize,
     ) -> Result<(), Box<dyn Error>> {
         let file = OpenOptions::new().append(true).open(file_name)?;
-        let mut wtr = csv::Writer::from_writer(file);
+        let mut wtr = csv_writer_builder().from_writer(file);
         for record in data {
             wtr.write_record(&[
                 &record.orig_image_name,
@@ -164,15 +1496,20 @@ impl CSVWriter<ComparisonResultDiff> for ComparisonResultDiffCSV {
                 &record.diff_comp_file_size.to_string(),
                 &record.diff_orig_raw_size.to_string(),
                 &record.diff_comp_raw_size.to_string(),
-                &record.diff_comp_file_size_ratio.to_string(),
-                &record.diff_raw_file_size_ratio.to_string(),
-                &record.diff_mse.to_string(),
-                &record.diff_psnr.to_string(),
-                &record.diff_ssim.to_string(),
-                &record.diff_ms_ssim.to_string(),
-                &record.diff_butteraugli.to_string(),
-                &record.diff_butteraugli_pnorm.to_string(),
-                &record.diff_ssimulacra2.to_string(),
+                &format_float(record.diff_comp_file_size_ratio, float_digits),
+                &format_float(record.diff_raw_file_size_ratio, float_digits),
+                &format_float(record.diff_mse, float_digits),
+                &format_float(record.diff_psnr, float_digits),
+                &format_float(record.diff_ssim, float_digits),
+                &format_float(record.diff_ms_ssim, float_digits),
+                &format_float(record.diff_butteraugli, float_digits),
+                &format_float(record.diff_butteraugli_pnorm, float_digits),
+                &format_float(record.diff_ssimulacra2, float_digits),
+                &record.non_finite_values.to_string(),
+                &record.baseline_codec,
+                &format_float(record.diff_channel_psnr[0], float_digits),
+                &format_float(record.diff_channel_psnr[1], float_digits),
+                &format_float(record.diff_channel_psnr[2], float_digits),
             ])?;
         }
         wtr.flush()?;
@@ -189,7 +1526,9 @@ impl CSVWriter<ComparisonResultDiff> for ComparisonResultDiffCSV {
                 std::fs::create_dir_all(parent)?;
             }
         }
-        let mut wtr = csv::Writer::from_path(file_name)?;
+        let mut wtr = csv_writer_builder().from_path(file_name)?;
+        let comp_ratio = self.size_ratio.column("Diff ", "orig");
+        let raw_ratio = self.size_ratio.column("Diff Raw ", "raw");
         wtr.write_record(&[
             "Original Image Name",
             "Compressed Image Name",
@@ -199,8 +1538,8 @@ impl CSVWriter<ComparisonResultDiff> for ComparisonResultDiffCSV {
             "Diff Compressed File Size",
             "Diff Original Raw Size",
             "Diff Compressed Raw Size",
-            "Diff File Size Ratio",
-            "Diff Raw Size Ratio",
+            comp_ratio.as_str(),
+            raw_ratio.as_str(),
             "Diff MSE",
             "Diff PSNR",
             "Diff SSIM",
@@ -208,38 +1547,128 @@ impl CSVWriter<ComparisonResultDiff> for ComparisonResultDiffCSV {
             "Diff Butteraugli",
             "Diff Butteraugli 3-Norm", // TODO: Support for multiple butteraugli p-norms?
             "Diff SSIMULACRA2",
+            "Non-Finite Values",
+            "Baseline Codec",
+            "Diff PSNR R/Y",
+            "Diff PSNR G/Cb",
+            "Diff PSNR B/Cr",
         ])?;
         wtr.flush()?;
         Ok(())
     }
 }
 
+impl ComparisonResultCSV {
+    /// The number of built-in columns, any further columns are external metrics.
+    const BUILT_IN_COLUMNS: usize = 40;
+
+    /// Parses a comparison result from a CSV record.
+    /// Columns added after the original 17 are optional so that older result files still parse.
+    /// Columns after the built-in columns are external metrics named by their header.
+    fn parse_record(
+        headers: &csv::StringRecord,
+        record: &csv::StringRecord,
+    ) -> Result<ComparisonResult, Box<dyn Error>> {
+        Ok(ComparisonResult {
+            orig_image_name: record[0].to_string(),
+            comp_image_name: record[1].to_string(),
+            distance: record[2].parse::<f32>()?,
+            effort: record[3].parse::<u32>()?,
+            orig_file_size: record[4].parse::<u64>()?,
+            comp_file_size: record[5].parse::<u64>()?,
+            orig_raw_size: record[6].parse::<u64>()?,
+            comp_raw_size: record[7].parse::<u64>()?,
+            comp_file_size_ratio: record[8].parse::<f64>()?,
+            raw_file_size_ratio: record[9].parse::<f64>()?,
+            size_ratio: SizeRatio::from_column(&headers[8]),
+            mse: record[10].parse::<f64>()?,
+            psnr: record[11].parse::<f64>()?,
+            ssim: record[12].parse::<f64>()?,
+            ms_ssim: record[13].parse::<f64>()?,
+            butteraugli: record[14].parse::<f64>()?,
+            butteraugli_pnorm: record[15].parse::<f64>()?,
+            ssimulacra2: record[16].parse::<f64>()?,
+            coding_mode: record.get(17).unwrap_or("default").to_string(),
+            size_regression: match record.get(18) {
+                Some(value) => value.parse::<bool>()?,
+                None => record[5].parse::<u64>()? > record[4].parse::<u64>()?,
+            },
+            peak_rss_bytes: match record.get(19) {
+                Some(value) => value.parse::<u64>()?,
+                None => 0,
+            },
+            cpu_seconds: match record.get(20) {
+                Some(value) => value.parse::<f64>()?,
+                None => 0.0,
+            },
+            roi: record.get(21).unwrap_or("").to_string(),
+            bpp: match record.get(22) {
+                Some(value) => value.parse::<f64>()?,
+                None => 0.0,
+            },
+            intensity_target: record.get(23).unwrap_or("").to_string(),
+            status: record
+                .get(24)
+                .unwrap_or(ComparisonResult::STATUS_OK)
+                .to_string(),
+            channel_psnr: [
+                ComparisonResultCSV::parse_optional_f64(record, 25)?,
+                ComparisonResultCSV::parse_optional_f64(record, 26)?,
+                ComparisonResultCSV::parse_optional_f64(record, 27)?,
+            ],
+            channel_space: record.get(28).unwrap_or("").to_string(),
+            source_is_lossy: match record.get(29) {
+                Some(value) => value.parse::<bool>()?,
+                None => false,
+            },
+            quality: record.get(30).unwrap_or("").to_string(),
+            decode_time_ms_min: ComparisonResultCSV::parse_optional_f64(record, 31)?,
+            decode_time_ms_mean: ComparisonResultCSV::parse_optional_f64(record, 32)?,
+            mse_vs_master: ComparisonResultCSV::parse_optional_f64(record, 33)?,
+            psnr_vs_master: ComparisonResultCSV::parse_optional_f64(record, 34)?,
+            ssim_vs_master: ComparisonResultCSV::parse_optional_f64(record, 35)?,
+            ms_ssim_vs_master: ComparisonResultCSV::parse_optional_f64(record, 36)?,
+            width: match record.get(37) {
+                Some(value) => value.parse::<u32>()?,
+                None => 0,
+            },
+            height: match record.get(38) {
+                Some(value) => value.parse::<u32>()?,
+                None => 0,
+            },
+            color_managed: match record.get(39) {
+                Some(value) => value.parse::<bool>()?,
+                None => false,
+            },
+            external_metrics: headers
+                .iter()
+                .zip(record.iter())
+                .skip(ComparisonResultCSV::BUILT_IN_COLUMNS)
+                .map(|(name, value)| Ok((name.to_string(), value.parse::<f64>()?)))
+                .collect::<Result<Vec<(String, f64)>, Box<dyn Error>>>()?,
+        })
+    }
+
+    /// Parses an optional floating point column, NaN if the record does not have it.
+    fn parse_optional_f64(
+        record: &csv::StringRecord,
+        column: usize,
+    ) -> Result<f64, Box<dyn Error>> {
+        match record.get(column) {
+            Some(value) => Ok(value.parse::<f64>()?),
+            None => Ok(f64::NAN),
+        }
+    }
+}
+
 impl CSVReader<ComparisonResult> for ComparisonResultCSV {
     fn read_csv(&self, file_name: &str) -> Result<Vec<ComparisonResult>, Box<dyn Error>> {
-        let mut rdr = csv::Reader::from_path(file_name)?;
+        let mut rdr = csv_reader_builder().from_path(file_name)?;
+        let headers = rdr.headers()?.clone();
         let mut data = Vec::new();
         for result in rdr.records() {
             let record = result?;
-            let comparison_result = ComparisonResult {
-                orig_image_name: record[0].to_string(),
-                comp_image_name: record[1].to_string(),
-                distance: record[2].parse::<f32>().unwrap(),
-                effort: record[3].parse::<u32>().unwrap(),
-                orig_file_size: record[4].parse::<u64>().unwrap(),
-                comp_file_size: record[5].parse::<u64>().unwrap(),
-                orig_raw_size: record[6].parse::<u64>().unwrap(),
-                comp_raw_size: record[7].parse::<u64>().unwrap(),
-                comp_file_size_ratio: record[8].parse::<f64>().unwrap(),
-                raw_file_size_ratio: record[9].parse::<f64>().unwrap(),
-                mse: record[10].parse::<f64>().unwrap(),
-                psnr: record[11].parse::<f64>().unwrap(),
-                ssim: record[12].parse::<f64>().unwrap(),
-                ms_ssim: record[13].parse::<f64>().unwrap(),
-                butteraugli: record[14].parse::<f64>().unwrap(),
-                butteraugli_pnorm: record[15].parse::<f64>().unwrap(),
-                ssimulacra2: record[16].parse::<f64>().unwrap(),
-            };
-            data.push(comparison_result);
+            data.push(ComparisonResultCSV::parse_record(&headers, &record)?);
         }
         Ok(data)
     }
@@ -249,30 +1678,12 @@ impl CSVReader<ComparisonResult> for ComparisonResultCSV {
         file_name: &str,
         entry: usize,
     ) -> Result<ComparisonResult, Box<dyn Error>> {
-        let mut rdr = csv::Reader::from_path(file_name)?;
+        let mut rdr = csv_reader_builder().from_path(file_name)?;
+        let headers = rdr.headers()?.clone();
         let mut data = Vec::new();
         for result in rdr.records() {
             let record = result?;
-            let comparison_result = ComparisonResult {
-                orig_image_name: record[0].to_string(),
-                comp_image_name: record[1].to_string(),
-                distance: record[2].parse::<f32>()?,
-                effort: record[3].parse::<u32>()?,
-                orig_file_size: record[4].parse::<u64>()?,
-                comp_file_size: record[5].parse::<u64>()?,
-                orig_raw_size: record[6].parse::<u64>()?,
-                comp_raw_size: record[7].parse::<u64>()?,
-                comp_file_size_ratio: record[8].parse::<f64>()?,
-                raw_file_size_ratio: record[9].parse::<f64>()?,
-                mse: record[10].parse::<f64>()?,
-                psnr: record[11].parse::<f64>()?,
-                ssim: record[12].parse::<f64>()?,
-                ms_ssim: record[13].parse::<f64>()?,
-                butteraugli: record[14].parse::<f64>()?,
-                butteraugli_pnorm: record[15].parse::<f64>()?,
-                ssimulacra2: record[16].parse::<f64>()?,
-            };
-            data.push(comparison_result);
+            data.push(ComparisonResultCSV::parse_record(&headers, &record)?);
             if data.len() > entry {
                 break;
             }
@@ -286,30 +1697,12 @@ impl CSVReader<ComparisonResult> for ComparisonResultCSV {
         column: usize,
         value: &str,
     ) -> Result<ComparisonResult, Box<dyn Error>> {
-        let mut rdr = csv::Reader::from_path(file_name)?;
+        let mut rdr = csv_reader_builder().from_path(file_name)?;
+        let headers = rdr.headers()?.clone();
         for result in rdr.records() {
             let record = result?;
-            let comparison_result = ComparisonResult {
-                orig_image_name: record[0].to_string(),
-                comp_image_name: record[1].to_string(),
-                distance: record[2].parse::<f32>()?,
-                effort: record[3].parse::<u32>()?,
-                orig_file_size: record[4].parse::<u64>()?,
-                comp_file_size: record[5].parse::<u64>()?,
-                orig_raw_size: record[6].parse::<u64>()?,
-                comp_raw_size: record[7].parse::<u64>()?,
-                comp_file_size_ratio: record[8].parse::<f64>()?,
-                raw_file_size_ratio: record[9].parse::<f64>()?,
-                mse: record[10].parse::<f64>()?,
-                psnr: record[11].parse::<f64>()?,
-                ssim: record[12].parse::<f64>()?,
-                ms_ssim: record[13].parse::<f64>()?,
-                butteraugli: record[14].parse::<f64>()?,
-                butteraugli_pnorm: record[15].parse::<f64>()?,
-                ssimulacra2: record[16].parse::<f64>()?,
-            };
             if record[column] == value.to_string() {
-                return Ok(comparison_result);
+                return ComparisonResultCSV::parse_record(&headers, &record);
             }
         }
         Err(Box::new(std::io::Error::new(
@@ -327,58 +1720,54 @@ impl ImageFileDataCSV {
     }
 }
 
+impl ImageFileDataCSV {
+    /// Parses a row of a results file into the fields of the same names.
+    /// Results files written before the source dimensions or lossiness were recorded describe
+    /// sources that were not downscaled and JPEG sources as the only lossy ones.
+    ///
+    /// # Arguments
+    /// * `headers` - The header of the results file.
+    /// * `record` - The row to parse.
+    ///
+    /// # Returns
+    /// The image file data of the row, or an error if a value could not be parsed.
+    fn parse_record(
+        headers: &csv::StringRecord,
+        record: &csv::StringRecord,
+    ) -> Result<ImageFileData, Box<dyn Error>> {
+        let mut image_file_data: ImageFileData = record.deserialize(Some(headers))?;
+        if !headers.iter().any(|header| header == "Source Width") {
+            image_file_data.source_width = image_file_data.width;
+            image_file_data.source_height = image_file_data.height;
+        }
+        if !headers.iter().any(|header| header == "Source Is Lossy") {
+            image_file_data.source_is_lossy = image_file_data.file_format == ImageFormat::Jpeg;
+        }
+        Ok(image_file_data)
+    }
+}
+
 impl CSVReader<ImageFileData> for ImageFileDataCSV {
     fn read_csv(&self, file_name: &str) -> Result<Vec<ImageFileData>, Box<dyn Error>> {
-        let mut rdr = csv::Reader::from_path(file_name)?;
+        let mut rdr = csv_reader_builder().from_path(file_name)?;
+        let headers = rdr.headers()?.clone();
         let mut data = Vec::new();
         for result in rdr.records() {
-            let record = result?;
-            let image_file_data = ImageFileData {
-                image_name: record[0].to_string(),
-                commit: record[1].to_string(),
-                test_set: record[2].to_string(),
-                file_path: record[3].to_string(),
-                width: record[4].parse::<u32>()?,
-                height: record[5].parse::<u32>()?,
-                file_size: record[6].parse::<usize>()?,
-                raw_size: record[7].parse::<usize>()?,
-                color_space: record[8].to_string().into(),
-                file_format: record[9].to_string().into(),
-                jxl_orig_image_name: record[10].to_string().into(),
-                jxl_distance: record[11].parse::<f32>().unwrap().into(),
-                jxl_effort: record[12].parse::<u32>().unwrap().into(),
-            };
-            data.push(image_file_data);
+            data.push(ImageFileDataCSV::parse_record(&headers, &result?)?);
         }
         Ok(data)
     }
 
     fn read_entry(&self, file_name: &str, entry: usize) -> Result<ImageFileData, Box<dyn Error>> {
-        let mut rdr = csv::Reader::from_path(file_name)?;
-        let mut data = Vec::new();
-        for result in rdr.records() {
-            let record = result?;
-            let image_file_data = ImageFileData {
-                image_name: record[0].to_string(),
-                commit: record[1].to_string(),
-                test_set: record[2].to_string(),
-                file_path: record[3].to_string(),
-                width: record[4].parse::<u32>()?,
-                height: record[5].parse::<u32>()?,
-                file_size: record[6].parse::<usize>()?,
-                raw_size: record[7].parse::<usize>()?,
-                color_space: record[8].to_string().into(),
-                file_format: record[9].to_string().into(),
-                jxl_orig_image_name: record[10].to_string().into(),
-                jxl_distance: record[11].to_string().into(),
-                jxl_effort: record[12].to_string().into(),
-            };
-            data.push(image_file_data);
-            if data.len() > entry {
-                break;
-            }
+        let mut rdr = csv_reader_builder().from_path(file_name)?;
+        let headers = rdr.headers()?.clone();
+        match rdr.records().nth(entry) {
+            Some(record) => ImageFileDataCSV::parse_record(&headers, &record?),
+            None => Err(Box::new(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("No entry {} in {}", entry, file_name),
+            ))),
         }
-        Ok(data[entry].clone())
     }
 
     fn find_entry(
@@ -387,26 +1776,12 @@ impl CSVReader<ImageFileData> for ImageFileDataCSV {
         column: usize,
         value: &str,
     ) -> Result<ImageFileData, Box<dyn Error>> {
-        let mut rdr = csv::Reader::from_path(file_name)?;
+        let mut rdr = csv_reader_builder().from_path(file_name)?;
+        let headers = rdr.headers()?.clone();
         for result in rdr.records() {
             let record = result?;
-            let image_file_data = ImageFileData {
-                image_name: record[0].to_string(),
-                commit: record[1].to_string(),
-                test_set: record[2].to_string(),
-                file_path: record[3].to_string(),
-                width: record[4].parse::<u32>()?,
-                height: record[5].parse::<u32>()?,
-                file_size: record[6].parse::<usize>()?,
-                raw_size: record[7].parse::<usize>()?,
-                color_space: record[8].to_string().into(),
-                file_format: record[9].to_string().into(),
-                jxl_orig_image_name: record[10].to_string().into(),
-                jxl_distance: record[11].to_string().into(),
-                jxl_effort: record[12].to_string().into(),
-            };
-            if record[column] == value.to_string() {
-                return Ok(image_file_data);
+            if record.get(column) == Some(value) {
+                return ImageFileDataCSV::parse_record(&headers, &record);
             }
         }
         Err(Box::new(std::io::Error::new(
@@ -417,33 +1792,32 @@ impl CSVReader<ImageFileData> for ImageFileDataCSV {
 }
 
 impl CSVWriter<ImageFileData> for ImageFileDataCSV {
-    fn write_csv(&self, data: &Vec<ImageFileData>, file_name: &str) -> Result<(), Box<dyn Error>> {
+    /// Appends rows to a results file. The header is generated from the fields of
+    /// ImageFileData and written by `write_csv_header`, or with the first rows of an empty file.
+    /// Its values are serialized as they are, so `float_digits` does not apply.
+    fn write_csv(
+        &self,
+        data: &Vec<ImageFileData>,
+        file_name: &str,
+        _float_digits: usize,
+    ) -> Result<(), Box<dyn Error>> {
         let file = OpenOptions::new().append(true).open(file_name)?;
-        let mut wtr = csv::Writer::from_writer(file);
+        let write_header = file.metadata()?.len() == 0;
+        let mut wtr = csv_writer_builder()
+            .has_headers(write_header)
+            .from_writer(file);
         for record in data {
-            wtr.write_record(&[
-                &record.image_name,
-                &record.commit,
-                &record.test_set,
-                &record.file_path,
-                &record.width.to_string(),
-                &record.height.to_string(),
-                &record.file_size.to_string(),
-                &record.raw_size.to_string(),
-                &record.color_space.to_string(),
-                &record.file_format.to_string(),
-                &record.jxl_orig_image_name.to_string(),
-                &record.jxl_distance.to_string(),
-                &record.jxl_effort.to_string(),
-            ])?;
+            wtr.serialize(record)?;
         }
         wtr.flush()?;
         Ok(())
     }
 
+    /// Creates a results file with the header generated from the fields of ImageFileData, so
+    /// that a results file without rows still has its columns.
     fn write_csv_header(&self, file_name: &str) -> Result<(), Box<dyn Error>> {
         let path = std::path::Path::new(file_name);
-        if path.exists() && path.metadata()?.len() > 0 {
+        if path.exists() {
             return Ok(());
         }
         if let Some(parent) = path.parent() {
@@ -451,23 +1825,46 @@ impl CSVWriter<ImageFileData> for ImageFileDataCSV {
                 std::fs::create_dir_all(parent)?;
             }
         }
-        let mut wtr = csv::Writer::from_path(file_name)?;
-        wtr.write_record(&[
-            "Image Name",
-            "Commit",
-            "Test Set",
-            "File Path",
-            "Image Width",
-            "Image Height",
-            "File Size",
-            "Raw Image Size",
-            "Image Color Space",
-            "File Format",
-            "JXL Original Image Name",
-            "JXL Distance",
-            "JXL Effort",
-        ])?;
+        let mut wtr = csv_writer_builder().from_path(path)?;
+        wtr.write_record(struct_fields::<ImageFileData>())?;
         wtr.flush()?;
         Ok(())
     }
 }
+
+/// Gets the serialized field names of a struct from its derived Deserialize implementation,
+/// which passes them to the deserializer before reading any values.
+///
+/// # Returns
+/// The field names, with their serde renames applied.
+fn struct_fields<'de, T: Deserialize<'de>>() -> &'static [&'static str] {
+    struct FieldsDeserializer<'a>(&'a mut &'static [&'static str]);
+
+    impl<'de> serde::Deserializer<'de> for FieldsDeserializer<'_> {
+        type Error = serde::de::value::Error;
+
+        fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
+            Err(de::Error::custom("only the fields of a struct are read"))
+        }
+
+        fn deserialize_struct<V: Visitor<'de>>(
+            self,
+            _name: &'static str,
+            fields: &'static [&'static str],
+            _visitor: V,
+        ) -> Result<V::Value, Self::Error> {
+            *self.0 = fields;
+            Err(de::Error::custom("the fields were read"))
+        }
+
+        serde::forward_to_deserialize_any! {
+            bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf
+            option unit unit_struct newtype_struct seq tuple tuple_struct map enum identifier
+            ignored_any
+        }
+    }
+
+    let mut fields: &'static [&'static str] = &[];
+    let _ = T::deserialize(FieldsDeserializer(&mut fields));
+    fields
+}
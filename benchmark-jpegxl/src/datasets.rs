@@ -0,0 +1,204 @@
+use crate::utils::{exists_or_create_dir, hash_file};
+
+use clap_derive::ValueEnum;
+use log::info;
+
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+/// A standard test image dataset that can be fetched into the local test image directory.
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum Dataset {
+    /// The 24 images of the Kodak Lossless True Color Image Suite.
+    Kodak,
+    /// The professional validation set of the Challenge on Learned Image Compression.
+    #[value(name = "clic", alias = "clic-professional-valid")]
+    ClicProfessionalValid,
+}
+
+impl Dataset {
+    /// Returns the name of the dataset, which is also the name of its test set directory.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Dataset::Kodak => "kodak",
+            Dataset::ClicProfessionalValid => "clic",
+        }
+    }
+
+    /// Returns the URL the dataset is fetched from when no source URL is configured.
+    pub fn default_source_url(&self) -> &'static str {
+        match self {
+            Dataset::Kodak => "https://r0k.us/graphics/kodak/kodak",
+            Dataset::ClicProfessionalValid => {
+                "https://data.vision.ee.ethz.ch/cvl/clic/professional_valid_2020"
+            }
+        }
+    }
+
+    /// Returns the checksums of the files of the dataset, pinned in the binary so that a
+    /// compromised or changed source cannot change the images a run is reproduced with.
+    ///
+    /// # Returns
+    /// The expected hash and file name of each file, or an error if no checksums are pinned
+    /// for the dataset.
+    pub fn checksums(&self) -> Result<Vec<(String, String)>, String> {
+        let pinned = match self {
+            Dataset::Kodak => include_str!("datasets/kodak.sha256"),
+            Dataset::ClicProfessionalValid => include_str!("datasets/clic.sha256"),
+        };
+        let checksums = DatasetFetcher::parse_checksums(pinned)?;
+        match checksums.is_empty() {
+            true => Err(format!("No checksums are pinned for {}", self.name())),
+            false => Ok(checksums),
+        }
+    }
+}
+
+/// The number of files downloaded and skipped by a fetch.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct FetchSummary {
+    pub downloaded: usize,
+    pub skipped: usize,
+}
+
+/// Downloads the files of standard test image datasets and verifies their checksums.
+/// A source serves the files of a dataset by name, and every file is verified against the
+/// checksums pinned for the dataset, see `Dataset::checksums`, so nothing the source serves is
+/// trusted. Any URL curl supports can be used as a source, including `file://` URLs of local
+/// mirrors.
+pub struct DatasetFetcher {}
+
+impl DatasetFetcher {
+    /// Fetches a dataset into its test set directory in the local test image directory,
+    /// verified against the checksums pinned for the dataset.
+    ///
+    /// # Arguments
+    /// * `dataset` - The dataset to fetch.
+    /// * `source_url` - The URL of the source, the default source of the dataset if None.
+    /// * `local_test_image_dir` - The local test image directory.
+    ///
+    /// # Returns
+    /// The number of files downloaded and skipped, or an error if no checksums are pinned for
+    /// the dataset or a file could not be downloaded or did not match its checksum.
+    pub fn fetch(
+        dataset: Dataset,
+        source_url: Option<&str>,
+        local_test_image_dir: &str,
+    ) -> Result<FetchSummary, Box<dyn Error>> {
+        let checksums = dataset.checksums()?;
+        DatasetFetcher::fetch_files(dataset, &checksums, source_url, local_test_image_dir)
+    }
+
+    /// Fetches the files of a dataset into its test set directory in the local test image
+    /// directory. Files that are already present with the expected checksum are skipped, and
+    /// downloads that do not match their checksum are removed.
+    ///
+    /// # Arguments
+    /// * `dataset` - The dataset to fetch.
+    /// * `checksums` - The expected hash and file name of each file to fetch.
+    /// * `source_url` - The URL of the source, the default source of the dataset if None.
+    /// * `local_test_image_dir` - The local test image directory.
+    ///
+    /// # Returns
+    /// The number of files downloaded and skipped, or an error if a file could not be
+    /// downloaded or did not match its checksum.
+    pub fn fetch_files(
+        dataset: Dataset,
+        checksums: &[(String, String)],
+        source_url: Option<&str>,
+        local_test_image_dir: &str,
+    ) -> Result<FetchSummary, Box<dyn Error>> {
+        let source_url = source_url
+            .unwrap_or(dataset.default_source_url())
+            .trim_end_matches('/');
+
+        let test_set_dir =
+            exists_or_create_dir(&format!("{}/{}", local_test_image_dir, dataset.name()))?;
+
+        let mut summary = FetchSummary::default();
+        for (expected_hash, file_name) in checksums {
+            let file_path = format!("{}/{}", test_set_dir, file_name);
+            if Path::new(&file_path).exists() && hash_file(&file_path)? == *expected_hash {
+                summary.skipped += 1;
+                continue;
+            }
+
+            info!("Fetching {}/{}", dataset.name(), file_name);
+            let bytes = DatasetFetcher::download(&format!("{}/{}", source_url, file_name))?;
+
+            // Verify the download before it replaces anything in the test set.
+            let part_path = format!("{}.part", file_path);
+            fs::write(&part_path, bytes)?;
+            let hash = hash_file(&part_path)?;
+            if hash != *expected_hash {
+                fs::remove_file(&part_path)?;
+                return Err(format!(
+                    "Checksum mismatch for {}: expected {}, got {}",
+                    file_name, expected_hash, hash
+                )
+                .into());
+            }
+            fs::rename(&part_path, &file_path)?;
+            summary.downloaded += 1;
+        }
+        Ok(summary)
+    }
+
+    /// Parses a checksum manifest in the format written by `sha256sum`. Empty lines and
+    /// comments starting with `#` are skipped.
+    ///
+    /// # Arguments
+    /// * `manifest` - The contents of the manifest.
+    ///
+    /// # Returns
+    /// The expected hash and file name of each file, or an error if a line is malformed or
+    /// names a file outside of the dataset directory.
+    pub fn parse_checksums(manifest: &str) -> Result<Vec<(String, String)>, String> {
+        let mut checksums = Vec::new();
+        for line in manifest
+            .lines()
+            .filter(|line| !line.trim().is_empty() && !line.starts_with('#'))
+        {
+            let (hash, file_name) = match line.split_once(char::is_whitespace) {
+                Some((hash, file_name)) => (hash, file_name.trim_start().trim_start_matches('*')),
+                None => return Err(format!("Invalid checksum line: {}", line)),
+            };
+            if hash.len() != 64 || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+                return Err(format!("Invalid checksum line: {}", line));
+            }
+            if file_name.is_empty() || file_name.contains('/') || file_name.contains("..") {
+                return Err(format!("Invalid file name in checksum line: {}", line));
+            }
+            checksums.push((hash.to_lowercase(), file_name.to_string()));
+        }
+        Ok(checksums)
+    }
+
+    /// Downloads a URL with curl.
+    ///
+    /// # Arguments
+    /// * `url` - The URL to download.
+    ///
+    /// # Returns
+    /// The downloaded bytes, or an error if the download failed.
+    fn download(url: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+        let output = Command::new("curl")
+            .arg("--fail")
+            .arg("--silent")
+            .arg("--show-error")
+            .arg("--location")
+            .arg(url)
+            .output()?;
+        if !output.status.success() {
+            return Err(format!(
+                "Could not download {}: {}",
+                url,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )
+            .into());
+        }
+        Ok(output.stdout)
+    }
+}
@@ -0,0 +1,59 @@
+use std::sync::{Condvar, Mutex};
+
+/// Limits the number of images decoded at the same time across workers, shared by the
+/// benchmarker and its workers.
+/// Decoding large images for the metrics takes a lot of memory, so with many workers the number
+/// of images in memory is bounded separately from the number of workers.
+#[derive(Debug, Default)]
+pub struct DecodeLimiter {
+    max_concurrent: usize,
+    in_flight: Mutex<usize>,
+    released: Condvar,
+}
+
+/// A permit to decode, held while an image is decoded and compared, and released when dropped.
+#[derive(Debug)]
+pub struct DecodePermit<'a> {
+    limiter: &'a DecodeLimiter,
+}
+
+impl DecodeLimiter {
+    /// Creates a decode limiter.
+    ///
+    /// # Arguments
+    /// * `max_concurrent` - The maximum number of images decoded at the same time, 0 for no limit.
+    ///
+    /// # Returns
+    /// The DecodeLimiter.
+    pub fn new(max_concurrent: usize) -> DecodeLimiter {
+        DecodeLimiter {
+            max_concurrent,
+            ..DecodeLimiter::default()
+        }
+    }
+
+    /// Waits until fewer than the maximum number of images are being decoded and takes a permit.
+    ///
+    /// # Returns
+    /// The permit, which is released when it is dropped.
+    pub fn acquire(&self) -> DecodePermit<'_> {
+        let mut in_flight = self.in_flight.lock().unwrap();
+        while self.max_concurrent > 0 && *in_flight >= self.max_concurrent {
+            in_flight = self.released.wait(in_flight).unwrap();
+        }
+        *in_flight += 1;
+        DecodePermit { limiter: self }
+    }
+
+    /// Returns the number of permits currently held.
+    pub fn in_flight(&self) -> usize {
+        *self.in_flight.lock().unwrap()
+    }
+}
+
+impl Drop for DecodePermit<'_> {
+    fn drop(&mut self) {
+        *self.limiter.in_flight.lock().unwrap() -= 1;
+        self.limiter.released.notify_one();
+    }
+}
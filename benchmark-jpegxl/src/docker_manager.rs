@@ -1,6 +1,126 @@
+use crate::cancellation::CancellationToken;
+use crate::config::BuildConfig;
+use crate::metrics::decode_time_stats;
+
 use std::collections::HashMap;
 use std::error::Error;
-use std::process::Command;
+use std::fmt::{self, Display, Formatter};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+/// Errors that prevent the benchmark from using Docker at all.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DockerError {
+    /// The `docker` binary could not be found.
+    NotInstalled,
+    /// The `docker` binary was found but the daemon could not be reached.
+    DaemonUnavailable(String),
+}
+
+impl Display for DockerError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            DockerError::NotInstalled => write!(
+                f,
+                "Docker is not installed or not on the PATH. Install Docker (https://docs.docker.com/get-docker/) and try again."
+            ),
+            DockerError::DaemonUnavailable(message) => write!(
+                f,
+                "The Docker daemon is not reachable. Start Docker and try again.\n{}",
+                message.trim()
+            ),
+        }
+    }
+}
+
+impl Error for DockerError {}
+
+/// The efforts a cjxl build accepts, as read from its help text.
+/// The range differs between libjxl versions, e.g. effort 10 was first only allowed with
+/// `--allow_expert_options` and later became a regular effort.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EffortRange {
+    pub min: u32,
+    pub max: u32,
+    /// The highest effort allowed with `--allow_expert_options`, if the build has the option.
+    pub expert_max: Option<u32>,
+}
+
+impl EffortRange {
+    /// The range assumed if the help text cannot be read, which all libjxl versions accept.
+    pub const DEFAULT: EffortRange = EffortRange {
+        min: 1,
+        max: 9,
+        expert_max: None,
+    };
+
+    /// Parses the effort range from the verbose help text of cjxl.
+    /// The regular range is read from the `Range: 1 .. 9.` line of the `--effort` option, and the
+    /// expert range from the `allows setting effort to 10` line of `--allow_expert_options`.
+    ///
+    /// # Arguments
+    /// * `help` - The output of `cjxl --help -v -v -v -v`.
+    ///
+    /// # Returns
+    /// The effort range, or None if the help text does not describe the `--effort` option.
+    pub fn parse(help: &str) -> Option<EffortRange> {
+        let effort_help = &help[help.find("--effort")?..];
+        let leading_number = |text: &str| {
+            text.trim_start()
+                .chars()
+                .take_while(|c| c.is_ascii_digit())
+                .collect::<String>()
+                .parse::<u32>()
+                .ok()
+        };
+
+        let range_line = effort_help[effort_help.find("Range:")? + "Range:".len()..]
+            .lines()
+            .next()?;
+        let mut bounds = range_line.split("..");
+        let min = leading_number(bounds.next()?)?;
+        let max = leading_number(bounds.next()?)?;
+
+        let expert_max = help.find("--allow_expert_options").and_then(|index| {
+            let expert_help = &help[index..];
+            leading_number(&expert_help[expert_help.find("effort to ")? + "effort to ".len()..])
+        });
+        Some(EffortRange {
+            min,
+            max,
+            expert_max,
+        })
+    }
+
+    /// Checks that an effort is accepted by the cjxl build.
+    ///
+    /// # Arguments
+    /// * `effort` - The effort to check.
+    ///
+    /// # Returns
+    /// Whether the effort needs `--allow_expert_options`, or an error explaining which efforts
+    /// the build accepts.
+    pub fn check(&self, effort: u32) -> Result<bool, String> {
+        match (effort, self.expert_max) {
+            (effort, _) if (self.min..=self.max).contains(&effort) => Ok(false),
+            (effort, Some(expert_max)) if effort > self.max && effort <= expert_max => Ok(true),
+            (effort, Some(expert_max)) => Err(format!(
+                "Effort {} is not supported by this cjxl build, which accepts efforts {} to {} ({} with --allow_expert_options)",
+                effort, self.min, self.max, expert_max
+            )),
+            (effort, None) => Err(format!(
+                "Effort {} is not supported by this cjxl build, which accepts efforts {} to {}",
+                effort, self.min, self.max
+            )),
+        }
+    }
+}
+
+impl Display for EffortRange {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{} to {}", self.min, self.max)
+    }
+}
 
 /// A struct that manages the docker container for a benchmark worker.
 #[derive(Debug, Clone)]
@@ -9,6 +129,23 @@ pub struct DockerManager {
     pub dockerfile: String,
     pub image_name: Option<String>,
     pub container_name: Option<String>,
+    /// Whether to attach to a running container of a prior invocation instead of recreating it,
+    /// and to leave the container running on teardown.
+    pub reuse_containers: bool,
+    /// The docker binary the commands are run with, `DEFAULT_DOCKER_BIN` unless the run
+    /// configures another one.
+    pub docker_bin: String,
+    /// The `KEY=VALUE` build arguments passed to `docker build`, e.g. `BASE_IMAGE=arm64v8/ubuntu`.
+    pub build_args: Vec<String>,
+    /// The directory the docker image is built in, `DEFAULT_CONTEXT_DIR` by default.
+    pub context_dir: String,
+    /// The name of the toolchain the image is built with, or None for the default Dockerfile.
+    pub toolchain: Option<String>,
+    /// The configuration libjxl is built with, or None to build it with the default flags.
+    pub build_config: Option<BuildConfig>,
+    /// The token of the run, commands are no longer executed in the container once it is
+    /// cancelled.
+    pub cancellation: CancellationToken,
     containers: HashMap<usize, String>,
 }
 
@@ -16,6 +153,17 @@ impl DockerManager {
     /// The name of the docker image and the base name of the docker container.
     pub const IMAGE_NAME: &'static str = "benchmark-libjxl-image";
     pub const CONTAINER_NAME: &'static str = "benchmark-libjxl-container";
+    /// The path to the djxl decoding tool in the docker container.
+    pub const DJXL_PATH: &'static str = "/libjxl/build/tools/djxl";
+    /// The path to the jxlinfo tool in the docker container.
+    pub const JXLINFO_PATH: &'static str = "/libjxl/build/tools/jxlinfo";
+    /// The diff of the local libjxl changes benchmarked as the `local` commit, relative to the
+    /// current directory.
+    pub const LOCAL_DIFF_PATH: &'static str = "local.diff";
+    /// The docker binary used unless `docker_bin` is changed.
+    pub const DEFAULT_DOCKER_BIN: &'static str = "docker";
+    /// The build context of the docker image unless `context_dir` is changed.
+    pub const DEFAULT_CONTEXT_DIR: &'static str = ".";
 
     /// Creates a new Docker manager instance.
     ///
@@ -36,10 +184,69 @@ impl DockerManager {
                 String::from(DockerManager::CONTAINER_NAME),
                 id
             )),
+            reuse_containers: false,
+            docker_bin: String::from(DockerManager::DEFAULT_DOCKER_BIN),
+            build_args: Vec::new(),
+            context_dir: String::from(DockerManager::DEFAULT_CONTEXT_DIR),
+            toolchain: None,
+            build_config: None,
+            cancellation: CancellationToken::new(),
             containers: HashMap::new(),
         }
     }
 
+    /// Creates a new Docker manager instance for a toolchain.
+    /// The toolchain is appended to the image and container names, so the containers of all
+    /// toolchains of a worker can run side by side.
+    ///
+    /// # Arguments
+    /// * `dockerfile` - The path to the Dockerfile of the toolchain.
+    /// * `id` - The ID of the worker.
+    /// * `toolchain` - The name of the toolchain.
+    ///
+    /// # Returns
+    /// * `DockerManager` - The new Docker manager instance.
+    pub fn for_toolchain(dockerfile: &str, id: usize, toolchain: &str) -> DockerManager {
+        DockerManager {
+            image_name: Some(format!("{}-{}", DockerManager::IMAGE_NAME, toolchain)),
+            container_name: Some(format!(
+                "{}-{}-{}",
+                DockerManager::CONTAINER_NAME,
+                toolchain,
+                id
+            )),
+            toolchain: Some(toolchain.to_string()),
+            ..DockerManager::new(dockerfile, id)
+        }
+    }
+
+    /// Checks that the docker binary is installed and the docker daemon is reachable.
+    ///
+    /// # Arguments
+    /// * `docker_bin` - The docker binary the run executes its docker commands with.
+    ///
+    /// # Returns
+    /// * `Result<(), DockerError>` - An error telling the user to install or start Docker if
+    /// Docker cannot be used.
+    pub fn preflight_check(docker_bin: &str) -> Result<(), DockerError> {
+        let output = Command::new(docker_bin)
+            .arg("info")
+            .output()
+            .map_err(|_| DockerError::NotInstalled)?;
+
+        match output.status.success() {
+            true => Ok(()),
+            false => Err(DockerError::DaemonUnavailable(
+                String::from_utf8_lossy(&output.stderr).to_string(),
+            )),
+        }
+    }
+
+    /// Creates a command running the docker binary of the manager.
+    fn docker(&self) -> Command {
+        Command::new(&self.docker_bin)
+    }
+
     /// Executes the given command on the given local machine and returns the output.
     ///
     /// # Arguments
@@ -51,7 +258,7 @@ impl DockerManager {
     fn execute_command(&self, command: &mut Command) -> Result<String, Box<dyn Error>> {
         let output = command
             .output()
-            .expect(format!("failed to execute command: {:?}", command).as_str());
+            .map_err(|e| format!("failed to execute command: {:?}: {}", command, e))?;
 
         if output.status.success() {
             Ok(String::from_utf8_lossy(&output.stdout).parse()?)
@@ -74,7 +281,7 @@ impl DockerManager {
         file_path: String,
         dest_path: String,
     ) -> Result<String, Box<dyn Error>> {
-        let mut command = Command::new("docker");
+        let mut command = self.docker();
         command.arg("cp");
         command.arg(format!(
             "{}:{}",
@@ -86,13 +293,109 @@ impl DockerManager {
         self.execute_command(&mut command)
     }
 
+    /// Reads a file from the docker container into memory.
+    /// The file is streamed as a tar archive from `docker cp` to stdout and extracted, so no
+    /// temporary file is written on the local machine.
+    ///
+    /// # Arguments
+    /// * `file_path` - The path to the file in the docker container.
+    ///
+    /// # Returns
+    /// * `Result<Vec<u8>, Error>` - The bytes of the file or an error if the command fails.
+    pub fn retrieve_file_bytes(&self, file_path: String) -> Result<Vec<u8>, Box<dyn Error>> {
+        let output = self
+            .docker()
+            .arg("cp")
+            .arg(format!(
+                "{}:{}",
+                self.container_name.as_ref().unwrap(),
+                file_path
+            ))
+            .arg("-")
+            .output()?;
+
+        if !output.status.success() {
+            return Err(Box::from(String::from_utf8_lossy(&output.stderr)));
+        }
+        DockerManager::extract_file_from_tar(&output.stdout)
+    }
+
+    /// Extracts the first regular file from a tar archive.
+    ///
+    /// # Arguments
+    /// * `archive` - The bytes of the tar archive.
+    ///
+    /// # Returns
+    /// * `Result<Vec<u8>, Error>` - The bytes of the file or an error if the archive does not
+    /// contain a regular file.
+    pub fn extract_file_from_tar(archive: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+        const BLOCK_SIZE: usize = 512;
+
+        let mut offset = 0;
+        while offset + BLOCK_SIZE <= archive.len() {
+            let header = &archive[offset..offset + BLOCK_SIZE];
+            // Two zero blocks mark the end of the archive.
+            if header.iter().all(|byte| *byte == 0) {
+                break;
+            }
+
+            // The size is stored as an octal number terminated by a NUL or space.
+            let size_field = String::from_utf8_lossy(&header[124..136]);
+            let size = usize::from_str_radix(size_field.trim_matches(|c| c == '\0' || c == ' '), 8)?;
+            let data_start = offset + BLOCK_SIZE;
+            let data_end = data_start + size;
+            if data_end > archive.len() {
+                return Err(Box::from("Truncated tar archive"));
+            }
+
+            // Skip extended headers, links and directories.
+            let type_flag = header[156];
+            if type_flag == b'0' || type_flag == 0 {
+                return Ok(archive[data_start..data_end].to_vec());
+            }
+            offset = data_start + (size + BLOCK_SIZE - 1) / BLOCK_SIZE * BLOCK_SIZE;
+        }
+
+        Err(Box::from("No file found in tar archive"))
+    }
+
+    /// Copies a file from the local machine to the docker container.
+    ///
+    /// # Arguments
+    /// * `file_path` - The path to the file on the local machine.
+    /// * `dest_path` - The path to copy the file to in the docker container.
+    ///
+    /// # Returns
+    /// * `Result<String, Error>` - The stdout of the command or an error with the stderr if the
+    /// command fails.
+    pub fn copy_file_to_container(
+        &self,
+        file_path: String,
+        dest_path: String,
+    ) -> Result<String, Box<dyn Error>> {
+        let mut command = self.docker();
+        command.arg("cp");
+        command.arg(file_path);
+        command.arg(format!(
+            "{}:{}",
+            self.container_name.as_ref().unwrap(),
+            dest_path
+        ));
+
+        self.execute_command(&mut command)
+    }
+
     /// Executes the cjxl encoding tool in the docker container.
     ///
     /// # Arguments
     /// * `input_file` - The path to the input image file to encode.
     /// * `output_file` - The name of the output file to create (in the docker container).
     /// * `distance` - The cjxl Butteraugli distance (quality) to use for the encoding.
+    /// * `quality` - The cjxl quality to encode with instead of the distance, if any.
     /// * `effort` - The cjxl effort level to use for the encoding.
+    /// * `modular` - Whether to force the Modular (true) or VarDCT (false) coding mode, or None
+    /// to leave the mode to cjxl's defaults.
+    /// * `extra_args` - Extra arguments passed verbatim to cjxl.
     ///
     /// # Returns
     /// * `Result<Result<String, String>, Error>` - The result of the command as a (stdout, stderr)
@@ -102,38 +405,143 @@ impl DockerManager {
         input_file: String,
         output_file: String,
         distance: f64,
+        quality: Option<f64>,
         effort: u32,
+        modular: Option<bool>,
+        extra_args: &[String],
     ) -> Result<Result<String, String>, Box<dyn Error>> {
         // Create the output directory if it doesn't exist.
         _ = self.execute_in_container(
             "mkdir",
             vec![
                 "-p",
-                format!(
-                    "{}",
-                    output_file
-                        .clone()
-                        .split("/")
-                        .take(output_file.split("/").count() - 1)
-                        .collect::<Vec<&str>>()
-                        .join("/")
-                )
-                .as_str(),
+                Path::new(&output_file)
+                    .parent()
+                    .and_then(|parent| parent.to_str())
+                    .unwrap_or("."),
             ],
         )?;
         
-        // Add the distance and effort flags to the command.
-        let distance = format!("--distance={}", distance);
-        let effort = format!("--effort={}", effort);
-        let args = vec![
-            input_file.as_str(),
-            output_file.as_str(),
-            distance.as_str(),
-            effort.as_str(),
+        // Execute the cjxl command in the docker container.
+        let args = DockerManager::cjxl_args(
+            &input_file,
+            &output_file,
+            distance,
+            quality,
+            effort,
+            modular,
+            extra_args,
+        );
+        self.execute_in_container(
+            "/libjxl/build/tools/cjxl",
+            args.iter().map(|arg| arg.as_str()).collect(),
+        )
+    }
+
+    /// Builds the arguments passed to the cjxl encoding tool.
+    ///
+    /// # Arguments
+    /// * `input_file` - The path to the input image file to encode.
+    /// * `output_file` - The name of the output file to create (in the docker container).
+    /// * `distance` - The cjxl Butteraugli distance (quality) to use for the encoding.
+    /// * `quality` - The cjxl quality to encode with instead of the distance, if any.
+    /// * `effort` - The cjxl effort level to use for the encoding.
+    /// * `modular` - Whether to force the Modular (true) or VarDCT (false) coding mode, or None
+    /// to leave the mode to cjxl's defaults.
+    /// * `extra_args` - Extra arguments appended verbatim after the benchmark's own arguments.
+    ///
+    /// # Returns
+    /// * `Vec<String>` - The arguments for the cjxl command.
+    pub fn cjxl_args(
+        input_file: &str,
+        output_file: &str,
+        distance: f64,
+        quality: Option<f64>,
+        effort: u32,
+        modular: Option<bool>,
+        extra_args: &[String],
+    ) -> Vec<String> {
+        // Add the distance, or the quality in quality mode, and effort flags to the command.
+        let mut args = vec![
+            input_file.to_string(),
+            output_file.to_string(),
+            match quality {
+                Some(quality) => format!("--quality={}", quality),
+                None => format!("--distance={}", distance),
+            },
+            format!("--effort={}", effort),
         ];
 
-        // Execute the cjxl command in the docker container.
-        self.execute_in_container("/libjxl/build/tools/cjxl", args)
+        // Add the coding mode flag to the command if a mode was selected.
+        match modular {
+            Some(true) => args.push("--modular=1".to_string()),
+            Some(false) => args.push("--modular=0".to_string()),
+            None => {}
+        }
+
+        // Add the user's extra arguments last.
+        args.extend(extra_args.iter().cloned());
+
+        args
+    }
+
+    /// Reads the efforts accepted by the cjxl build in the docker container.
+    ///
+    /// # Returns
+    /// * `Result<EffortRange, Error>` - The effort range or an error if the help text could not be
+    /// read or parsed.
+    pub fn cjxl_effort_range(&self) -> Result<EffortRange, Box<dyn Error>> {
+        // The help exits with a nonzero status on some versions, so both outputs are parsed.
+        let help = match self.execute_in_container(
+            "/libjxl/build/tools/cjxl",
+            vec!["--help", "-v", "-v", "-v", "-v"],
+        )? {
+            Ok(help) | Err(help) => help,
+        };
+        EffortRange::parse(&help).ok_or(Box::from("Failed to parse the cjxl effort range"))
+    }
+
+    /// Adds `--allow_expert_options` to the extra cjxl arguments if an effort needs it.
+    ///
+    /// # Arguments
+    /// * `extra_args` - The extra arguments passed to cjxl.
+    /// * `expert` - Whether the effort needs `--allow_expert_options`.
+    ///
+    /// # Returns
+    /// * `Vec<String>` - The extra arguments for the effort.
+    pub fn effort_extra_args(extra_args: &[String], expert: bool) -> Vec<String> {
+        let mut args = extra_args.to_vec();
+        if expert && !args.iter().any(|arg| arg == "--allow_expert_options") {
+            args.push("--allow_expert_options".to_string());
+        }
+        args
+    }
+
+    /// Checks that extra cjxl arguments do not set the distance, quality or effort, which are
+    /// swept by the benchmark. Values attached to a flag are caught too, e.g. `-d1`, `-e7` and
+    /// `--distance=1`.
+    ///
+    /// # Arguments
+    /// * `extra_args` - The extra arguments passed to cjxl.
+    ///
+    /// # Returns
+    /// * `Result<(), Error>` - An error naming the first conflicting argument.
+    pub fn validate_cjxl_extra_args(extra_args: &[String]) -> Result<(), Box<dyn Error>> {
+        const SWEPT_FLAGS: [&str; 6] = ["-d", "--distance", "-q", "--quality", "-e", "--effort"];
+        for arg in extra_args {
+            // Long flags take their value after `=`, short flags directly after the letter.
+            let flag = match arg.starts_with("--") {
+                true => arg.split('=').next().unwrap_or(""),
+                false => arg.get(..2).unwrap_or(arg),
+            };
+            if SWEPT_FLAGS.contains(&flag) {
+                return Err(Box::from(format!(
+                    "The cjxl argument {} conflicts with the distances and efforts swept by the benchmark",
+                    arg
+                )));
+            }
+        }
+        Ok(())
     }
 
     /// Executes the JPEG XL SSIMULACRA2 benchmarking tool in the docker container.
@@ -141,6 +549,7 @@ impl DockerManager {
     /// # Arguments
     /// * `orig_file` - The path to the original image file.
     /// * `comp_file` - The path to the compressed image file.
+    /// * `intensity_target` - The display peak luminance in nits for HDR images, if any.
     ///
     /// # Returns
     /// * `Result<Result<String, String>, Error>` - The result of the command as a (stdout, stderr)
@@ -149,10 +558,14 @@ impl DockerManager {
         &self,
         orig_file: String,
         comp_file: String,
+        intensity_target: Option<f64>,
     ) -> Result<Result<String, String>, Box<dyn Error>> {
-        let args = vec![orig_file.as_str(), comp_file.as_str()];
+        let args = DockerManager::metric_args(&orig_file, &comp_file, intensity_target);
 
-        self.execute_in_container("../libjxl/build/tools/ssimulacra2", args)
+        self.execute_in_container(
+            "../libjxl/build/tools/ssimulacra2",
+            args.iter().map(|arg| arg.as_str()).collect(),
+        )
     }
 
     /// Executes the libjxl Butteraugli benchmarking tool in the docker container.
@@ -160,6 +573,7 @@ impl DockerManager {
     /// # Arguments
     /// * `orig_file` - The path to the original image file.
     /// * `comp_file` - The path to the compressed image file.
+    /// * `intensity_target` - The display peak luminance in nits for HDR images, if any.
     ///
     /// # Returns
     /// * `Result<Result<String, String>, Error>` - The result of the command as a (stdout, stderr)
@@ -168,30 +582,194 @@ impl DockerManager {
         &self,
         orig_file: String,
         comp_file: String,
+        intensity_target: Option<f64>,
     ) -> Result<Result<String, String>, Box<dyn Error>> {
-        let args = vec![orig_file.as_str(), comp_file.as_str()];
+        let args = DockerManager::metric_args(&orig_file, &comp_file, intensity_target);
 
-        self.execute_in_container("/libjxl/build/tools/butteraugli_main", args)
+        self.execute_in_container(
+            "/libjxl/build/tools/butteraugli_main",
+            args.iter().map(|arg| arg.as_str()).collect(),
+        )
+    }
+
+    /// Decodes a JXL image a number of times with the djxl decoding tool in the docker
+    /// container, without writing the decoded image.
+    ///
+    /// # Arguments
+    /// * `input_file` - The path to the JXL image file to decode.
+    /// * `num_reps` - The number of decodes.
+    ///
+    /// # Returns
+    /// * `Result<Result<String, String>, Error>` - The result of the command as a (stdout and
+    ///   stderr, stderr) tuple, since djxl reports the speed of the decodes on stderr, or an
+    ///   error if there was an issue executing the command.
+    pub fn execute_djxl(
+        &self,
+        input_file: &str,
+        num_reps: u32,
+    ) -> Result<Result<String, String>, Box<dyn Error>> {
+        let num_reps = num_reps.to_string();
+        let output = self.exec_in_container(
+            DockerManager::DJXL_PATH,
+            vec![input_file, "--num_reps", &num_reps, "--disable_output"],
+        )?;
+        let stdout = String::from_utf8_lossy(&output.stdout).to_string();
+        let stderr = String::from_utf8_lossy(&output.stderr).to_string();
+        match output.status.success() {
+            true => Ok(Ok(stdout + &stderr)),
+            false => Ok(Err(stderr)),
+        }
+    }
+
+    /// Describes a JXL image with the jxlinfo tool of the toolchain in the docker container.
+    ///
+    /// # Arguments
+    /// * `input_file` - The path to the JXL image file to describe.
+    ///
+    /// # Returns
+    /// * `Result<Result<String, String>, Error>` - The result of the command as a (stdout, stderr)
+    /// tuple or an error if there was an issue executing the command.
+    pub fn execute_jxlinfo(
+        &self,
+        input_file: &str,
+    ) -> Result<Result<String, String>, Box<dyn Error>> {
+        self.execute_in_container(DockerManager::JXLINFO_PATH, vec![input_file])
+    }
+
+    /// Decodes a truncated JXL image with djxl in the docker container, which renders as much of
+    /// the image as the available bytes allow, as a progressive decoder shows it while loading.
+    ///
+    /// # Arguments
+    /// * `input_file` - The path to the truncated JXL image file to decode.
+    /// * `output_file` - The path to write the decoded image to (in the docker container).
+    ///
+    /// # Returns
+    /// * `Result<Result<String, String>, Error>` - The result of the command as a (stdout, stderr)
+    /// tuple or an error if there was an issue executing the command.
+    pub fn execute_djxl_partial(
+        &self,
+        input_file: String,
+        output_file: String,
+    ) -> Result<Result<String, String>, Box<dyn Error>> {
+        self.execute_in_container(
+            DockerManager::DJXL_PATH,
+            vec![
+                input_file.as_str(),
+                output_file.as_str(),
+                "--allow_partial_files",
+            ],
+        )
+    }
+
+    /// Times decoding a JXL image with djxl in the docker container.
+    /// djxl times the decodes itself, so the time includes neither starting the process nor
+    /// writing the decoded image. The warm-up decodes are run by a separate djxl invocation
+    /// before the timed decodes and discarded.
+    ///
+    /// # Arguments
+    /// * `input_file` - The path to the JXL image file to decode.
+    /// * `warmup` - The number of decodes to run before the timed decodes.
+    /// * `repeats` - The number of decodes to time.
+    ///
+    /// # Returns
+    /// * `Result<(f64, f64), Error>` - The (min, mean) decode time in milliseconds, or an error
+    ///   if a decode failed or djxl reported no speed.
+    pub fn time_djxl(
+        &self,
+        input_file: &str,
+        warmup: u32,
+        repeats: u32,
+    ) -> Result<(f64, f64), Box<dyn Error>> {
+        let failed = |e: String| {
+            Box::<dyn Error>::from(format!("Failed to decode {}: {}", input_file, e.trim()))
+        };
+        if warmup > 0 {
+            self.execute_djxl(input_file, warmup)?.map_err(failed)?;
+        }
+        let output = self.execute_djxl(input_file, repeats)?.map_err(failed)?;
+        decode_time_stats(&output).ok_or_else(|| {
+            Box::from(format!(
+                "No decode speed in the djxl output: {}",
+                output.trim()
+            ))
+        })
+    }
+
+    /// Quotes an argument for a shell script, so that spaces, quotes and other special
+    /// characters in paths are passed through literally.
+    ///
+    /// # Arguments
+    /// * `arg` - The argument to quote.
+    ///
+    /// # Returns
+    /// * `String` - The argument in single quotes.
+    pub fn shell_quote(arg: &str) -> String {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+
+    /// Quotes a libjxl branch, tag or commit for a git command in a shell script.
+    ///
+    /// # Arguments
+    /// * `reference` - The branch, tag or commit.
+    ///
+    /// # Returns
+    /// * `Result<String, Error>` - The quoted reference, or an error if it is empty or starts
+    /// with a dash, which git would read as an option.
+    pub fn quote_libjxl_ref(reference: &str) -> Result<String, Box<dyn Error>> {
+        match reference.is_empty() || reference.starts_with('-') {
+            true => Err(Box::from(format!("Invalid libjxl reference: {:?}", reference))),
+            false => Ok(DockerManager::shell_quote(reference)),
+        }
+    }
+
+    /// Builds the arguments passed to the SSIMULACRA2 and Butteraugli tools.
+    /// Without an intensity target the tools use their SDR default.
+    ///
+    /// # Arguments
+    /// * `orig_file` - The path to the original image file.
+    /// * `comp_file` - The path to the compressed image file.
+    /// * `intensity_target` - The display peak luminance in nits for HDR images, if any.
+    ///
+    /// # Returns
+    /// * `Vec<String>` - The arguments for the metric tool.
+    pub fn metric_args(
+        orig_file: &str,
+        comp_file: &str,
+        intensity_target: Option<f64>,
+    ) -> Vec<String> {
+        let mut args = vec![orig_file.to_string(), comp_file.to_string()];
+        if let Some(intensity_target) = intensity_target {
+            args.push("--intensity_target".to_string());
+            args.push(intensity_target.to_string());
+        }
+        args
     }
 
     /// Sets up a docker container for a benchmark worker.
     ///
     /// # Arguments
     /// * `worker_id` - The ID of the worker.
+    /// * `libjxl_src` - An absolute path to a local libjxl source tree to mount at /libjxl, if any.
     ///
     /// # Returns
     /// * `Result<(), Error>` - An error if the setup fails.
-    pub fn setup(&mut self, worker_id: usize) -> Result<(), Box<dyn Error>> {
+    pub fn setup(&mut self, worker_id: usize, libjxl_src: Option<&str>) -> Result<(), Box<dyn Error>> {
+        // Attach to the running container of a prior invocation if enabled.
+        if self.reuse_containers && self.container_running()? {
+            self.validate_reused_container()?;
+            let worker_container_name = self.container_name.as_ref().unwrap();
+            self.containers
+                .insert(worker_id, worker_container_name.clone());
+            return Ok(());
+        }
+
         // Build the docker image.
-        match self.execute_command(
-            Command::new("docker")
-                .arg("build")
-                .arg("-t")
-                .arg(format!("ubuntu:{}", self.image_name.as_ref().unwrap()))
-                .arg("-f")
-                .arg(self.dockerfile.as_str())
-                .arg("."),
-        ) {
+        match self.execute_command(self.docker().args(DockerManager::build_args(
+            self.image_name.as_ref().unwrap(),
+            &self.dockerfile,
+            &self.build_args,
+            &self.context_dir,
+        ))) {
             Ok(_) => {}
             Err(_) => {
                 return Err(Box::from("Failed to build docker image"));
@@ -203,18 +781,216 @@ impl DockerManager {
             .insert(worker_id, worker_container_name.clone());
 
         // Start the container.
-        self.execute_command(
-            Command::new("docker")
-                .arg("run")
-                .arg("--name")
-                .arg(worker_container_name)
-                .arg("-dit")
-                .arg(format!("ubuntu:{}", self.image_name.as_ref().unwrap())),
+        self.execute_command(self.docker().args(DockerManager::run_args(
+            worker_container_name,
+            self.image_name.as_ref().unwrap(),
+            libjxl_src,
+        )))?;
+
+        Ok(())
+    }
+
+    /// Checks whether the container of the worker is already running.
+    ///
+    /// # Returns
+    /// * `Result<bool, Error>` - Whether the container is running or an error if docker fails.
+    pub fn container_running(&self) -> Result<bool, Box<dyn Error>> {
+        let container_name = self.container_name.as_ref().unwrap();
+        let output = self.execute_command(
+            self.docker()
+                .arg("ps")
+                .arg("--filter")
+                .arg(format!("name=^{}$", container_name))
+                .arg("--format")
+                .arg("{{.Names}}"),
+        )?;
+        Ok(output.lines().any(|name| name.trim() == container_name))
+    }
+
+    /// Gets the state of the container of the worker, e.g. running or exited.
+    ///
+    /// # Returns
+    /// * `Result<String, Error>` - The state of the container or an error if docker fails, e.g.
+    /// because the container no longer exists.
+    pub fn container_state(&self) -> Result<String, Box<dyn Error>> {
+        let output = self.execute_command(
+            self.docker()
+                .arg("inspect")
+                .arg("--format")
+                .arg("{{.State.Status}}")
+                .arg(self.container_name.as_ref().unwrap()),
+        )?;
+        Ok(output.trim().to_string())
+    }
+
+    /// Gets the version of the docker binary of the manager.
+    ///
+    /// # Returns
+    /// * `Result<String, Error>` - The output of `docker --version` or an error if docker fails.
+    pub fn docker_version(&self) -> Result<String, Box<dyn Error>> {
+        let output = self.execute_command(self.docker().arg("--version"))?;
+        Ok(output.trim().to_string())
+    }
+
+    /// Gets the version of the cjxl build in the docker container.
+    ///
+    /// # Returns
+    /// * `Result<String, Error>` - The output of `cjxl --version` or an error if it fails.
+    pub fn cjxl_version(&self) -> Result<String, Box<dyn Error>> {
+        match self.execute_in_container("/libjxl/build/tools/cjxl", vec!["--version"])? {
+            Ok(version) => Ok(version.trim().to_string()),
+            Err(e) => Err(Box::from(e)),
+        }
+    }
+
+    /// Replaces the container of the worker with a new one, e.g. after it died mid-run.
+    /// The dead container is removed and a new one is set up like at the start of the run.
+    ///
+    /// # Arguments
+    /// * `worker_id` - The ID of the worker.
+    /// * `libjxl_src` - An absolute path to a local libjxl source tree to mount at /libjxl, if any.
+    ///
+    /// # Returns
+    /// * `Result<(), Error>` - An error if the new container could not be set up.
+    pub fn restart(
+        &mut self,
+        worker_id: usize,
+        libjxl_src: Option<&str>,
+    ) -> Result<(), Box<dyn Error>> {
+        // The container may already be gone, so a failure to remove it is ignored.
+        let _ = self.execute_command(
+            self.docker()
+                .arg("rm")
+                .arg("-f")
+                .arg(self.container_name.as_ref().unwrap()),
+        );
+        self.setup(worker_id, libjxl_src)
+    }
+
+    /// Checks that a running container from a prior invocation was created from the benchmark
+    /// image and has a libjxl build, so it can be reused.
+    ///
+    /// # Returns
+    /// * `Result<(), Error>` - An error telling the user to remove the container if it cannot be
+    /// reused.
+    fn validate_reused_container(&self) -> Result<(), Box<dyn Error>> {
+        let container_name = self.container_name.as_ref().unwrap();
+        let expected_image = format!("ubuntu:{}", self.image_name.as_ref().unwrap());
+        let image = self.execute_command(
+            self.docker()
+                .arg("inspect")
+                .arg("--format")
+                .arg("{{.Config.Image}}")
+                .arg(container_name),
         )?;
+        if image.trim() != expected_image {
+            return Err(Box::from(format!(
+                "Container {} runs image {} instead of {}, remove it with `docker rm -f {}`",
+                container_name,
+                image.trim(),
+                expected_image,
+                container_name
+            )));
+        }
 
+        self.execute_command(
+            self.docker()
+                .arg("exec")
+                .arg(container_name)
+                .arg("test")
+                .arg("-x")
+                .arg("/libjxl/build/tools/cjxl"),
+        )
+        .map_err(|_| {
+            format!(
+                "Container {} has no libjxl build, remove it with `docker rm -f {}`",
+                container_name, container_name
+            )
+        })?;
         Ok(())
     }
 
+    /// Builds the arguments passed to `docker build` to build the image of the workers.
+    ///
+    /// # Arguments
+    /// * `image_name` - The name of the docker image.
+    /// * `dockerfile` - The path to the Dockerfile.
+    /// * `build_args` - The `KEY=VALUE` build arguments, each passed with `--build-arg`.
+    /// * `context_dir` - The directory the image is built in.
+    ///
+    /// # Returns
+    /// * `Vec<String>` - The arguments for the docker command.
+    pub fn build_args(
+        image_name: &str,
+        dockerfile: &str,
+        build_args: &[String],
+        context_dir: &str,
+    ) -> Vec<String> {
+        let mut args = vec![
+            "build".to_string(),
+            "-t".to_string(),
+            format!("ubuntu:{}", image_name),
+            "-f".to_string(),
+            dockerfile.to_string(),
+        ];
+        for build_arg in build_args {
+            args.push("--build-arg".to_string());
+            args.push(build_arg.clone());
+        }
+        args.push(context_dir.to_string());
+        args
+    }
+
+    /// Checks that docker build arguments have the form `KEY=VALUE`.
+    ///
+    /// # Arguments
+    /// * `build_args` - The build arguments passed to `docker build`.
+    ///
+    /// # Returns
+    /// * `Result<(), Error>` - An error naming the first malformed argument.
+    pub fn validate_build_args(build_args: &[String]) -> Result<(), Box<dyn Error>> {
+        for build_arg in build_args {
+            match build_arg.split_once('=') {
+                Some((key, _)) if !key.is_empty() => {}
+                _ => {
+                    return Err(Box::from(format!(
+                        "Invalid docker build argument {}, expected KEY=VALUE",
+                        build_arg
+                    )))
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Builds the arguments passed to `docker run` to start a worker container.
+    ///
+    /// # Arguments
+    /// * `container_name` - The name of the container.
+    /// * `image_name` - The name of the docker image.
+    /// * `libjxl_src` - An absolute path to a local libjxl source tree to bind-mount at /libjxl
+    /// instead of the checkout in the image, if any.
+    ///
+    /// # Returns
+    /// * `Vec<String>` - The arguments for the docker command.
+    pub fn run_args(container_name: &str, image_name: &str, libjxl_src: Option<&str>) -> Vec<String> {
+        let mut args = vec![
+            "run".to_string(),
+            "--name".to_string(),
+            container_name.to_string(),
+        ];
+
+        // Mount the local libjxl source over the checkout in the image.
+        if let Some(libjxl_src) = libjxl_src {
+            args.push("-v".to_string());
+            args.push(format!("{}:/libjxl", libjxl_src));
+        }
+
+        args.push("-dit".to_string());
+        args.push(format!("ubuntu:{}", image_name));
+        args
+    }
+
     /// Executes the given command in the docker container.
     ///
     /// # Arguments
@@ -229,15 +1005,7 @@ impl DockerManager {
         subcommand: &str,
         args: Vec<&str>,
     ) -> Result<Result<String, String>, Box<dyn Error>> {
-        let mut command = Command::new("docker");
-        command.arg("exec");
-        command.arg("-w");
-        command.arg("/temp");
-        command.arg(self.container_name.as_ref().unwrap());
-        command.arg(subcommand);
-        command.args(args.as_slice());
-
-        let output = command.output()?;
+        let output = self.exec_in_container(subcommand, args)?;
 
         // Convert the output to a string.
         let stdout = String::from_utf8_lossy(&output.stdout).to_string();
@@ -254,6 +1022,34 @@ impl DockerManager {
         }
     }
 
+    /// Runs the given command in the docker container.
+    ///
+    /// # Arguments
+    /// * `subcommand` - The subcommand to execute with `docker exec`.
+    /// * `args` - The arguments to pass to the command.
+    ///
+    /// # Returns
+    /// * `Result<Output, Error>` - The output of the command, or an error if it could not be
+    ///   run, e.g. because the run was cancelled.
+    fn exec_in_container(
+        &self,
+        subcommand: &str,
+        args: Vec<&str>,
+    ) -> Result<Output, Box<dyn Error>> {
+        // A cancelled run does not start new work in the container.
+        self.cancellation.check()?;
+
+        let mut command = self.docker();
+        command.arg("exec");
+        command.arg("-w");
+        command.arg("/temp");
+        command.arg(self.container_name.as_ref().unwrap());
+        command.arg(subcommand);
+        command.args(args.as_slice());
+
+        Ok(command.output()?)
+    }
+
     /// Tears down the docker container.
     ///
     /// # Returns
@@ -261,7 +1057,7 @@ impl DockerManager {
     pub fn teardown(&self) -> Result<(), Box<dyn Error>> {
         // clean the /temp folder
         self.execute_command(
-            Command::new("docker")
+            self.docker()
                 .arg("exec")
                 .arg(self.container_name.as_ref().unwrap())
                 .arg("rm")
@@ -269,23 +1065,28 @@ impl DockerManager {
                 .arg("/temp/*"),
         )?;
 
+        // Leave the container running for the next invocation if it is reused.
+        if self.reuse_containers {
+            return Ok(());
+        }
+
         // Stop the container.
         self.execute_command(
-            Command::new("docker")
+            self.docker()
                 .arg("stop")
                 .arg(self.container_name.as_ref().unwrap()),
         )?;
 
         // Remove the container.
         self.execute_command(
-            Command::new("docker")
+            self.docker()
                 .arg("rm")
                 .arg(self.container_name.as_ref().unwrap()),
         )?;
 
         // Remove the image.
         self.execute_command(
-            Command::new("docker")
+            self.docker()
                 .arg("rmi")
                 .arg(format!("ubuntu:{}", self.image_name.as_ref().unwrap())),
         )?;
@@ -301,7 +1102,8 @@ impl DockerManager {
     /// # Returns
     /// * `Result<String, Error>` - The output of the command or an error if the command fails.
     pub fn change_libjxl_commit(&self, commit: &str) -> Result<String, Box<dyn Error>> {
-        let mut command = Command::new("docker");
+        let commit = DockerManager::quote_libjxl_ref(commit)?;
+        let mut command = self.docker();
         command.arg("exec");
         command.arg(self.container_name.as_ref().unwrap());
         command.arg("bash");
@@ -314,6 +1116,88 @@ impl DockerManager {
         self.execute_command(&mut command)
     }
 
+    /// Finds the cgroup v2 directory of the docker container on the host, as created by the
+    /// systemd or the cgroupfs cgroup driver.
+    ///
+    /// # Returns
+    /// * `Result<PathBuf, Error>` - The cgroup directory or an error if docker fails or the
+    ///   directory does not exist, e.g. on cgroup v1 hosts.
+    pub fn cgroup_dir(&self) -> Result<PathBuf, Box<dyn Error>> {
+        let output = self.execute_command(
+            self.docker()
+                .arg("inspect")
+                .arg("--format")
+                .arg("{{.Id}}")
+                .arg(self.container_name.as_ref().unwrap()),
+        )?;
+        let id = output.trim();
+        [
+            format!("/sys/fs/cgroup/system.slice/docker-{}.scope", id),
+            format!("/sys/fs/cgroup/docker/{}", id),
+        ]
+        .into_iter()
+        .map(PathBuf::from)
+        .find(|cgroup_dir| cgroup_dir.join("cpu.stat").exists())
+        .ok_or_else(|| format!("no cgroup v2 directory found for container {}", id).into())
+    }
+
+    /// Resolves the commit currently checked out in the libjxl repository in the docker container
+    /// to its full SHA.
+    ///
+    /// # Returns
+    /// * `Result<String, Error>` - The 40 character commit SHA or an error if the command fails.
+    pub fn resolve_commit(&self) -> Result<String, Box<dyn Error>> {
+        let mut command = self.docker();
+        command.arg("exec");
+        command.arg(self.container_name.as_ref().unwrap());
+        command.arg("git");
+        command.arg("-C");
+        command.arg("/libjxl");
+        command.arg("rev-parse");
+        command.arg("HEAD");
+
+        DockerManager::parse_commit_sha(&self.execute_command(&mut command)?)
+    }
+
+    /// Resolves a libjxl branch, tag or commit to the full SHA it currently points to.
+    /// Branches are resolved against the latest state of the libjxl remote.
+    ///
+    /// # Arguments
+    /// * `reference` - The branch, tag or commit to resolve, e.g. "main".
+    ///
+    /// # Returns
+    /// * `Result<String, Error>` - The 40 character commit SHA or an error if the command fails.
+    pub fn resolve_libjxl_ref(&self, reference: &str) -> Result<String, Box<dyn Error>> {
+        DockerManager::quote_libjxl_ref(reference)?;
+        let mut command = self.docker();
+        command.arg("exec");
+        command.arg(self.container_name.as_ref().unwrap());
+        command.arg("bash");
+        command.arg("-c");
+        command.arg(format!(
+            "cd /libjxl && git fetch origin && (git rev-parse --verify --quiet {} || git rev-parse --verify {})",
+            DockerManager::shell_quote(&format!("origin/{}^{{commit}}", reference)),
+            DockerManager::shell_quote(&format!("{}^{{commit}}", reference))
+        ));
+
+        DockerManager::parse_commit_sha(&self.execute_command(&mut command)?)
+    }
+
+    /// Parses the output of `git rev-parse` into a full commit SHA.
+    ///
+    /// # Arguments
+    /// * `output` - The output of the command.
+    ///
+    /// # Returns
+    /// * `Result<String, Error>` - The 40 character commit SHA or an error if the output is not one.
+    pub fn parse_commit_sha(output: &str) -> Result<String, Box<dyn Error>> {
+        let sha = output.trim();
+        match sha.len() == 40 && sha.chars().all(|c| c.is_ascii_hexdigit()) {
+            true => Ok(sha.to_lowercase()),
+            false => Err(Box::from(format!("Not a commit SHA: {}", sha))),
+        }
+    }
+
     /// Applies a local git diff to the libjxl repository in the docker container.
     ///
     /// # Arguments
@@ -322,55 +1206,223 @@ impl DockerManager {
     /// # Returns
     /// * `Result<String, Error>` - The output of the command or an error if the command fails.
     pub fn apply_diff(&self, diff: &str) -> Result<String, Box<dyn Error>> {
-        let mut command = Command::new("docker");
+        let mut command = self.docker();
         command.arg("exec");
         command.arg(self.container_name.as_ref().unwrap());
         command.arg("bash");
         command.arg("-c");
-        command.arg(format!("cd /libjxl && git apply {} && cd -", diff));
+        command.arg(format!(
+            "cd /libjxl && git apply {} && cd -",
+            DockerManager::shell_quote(diff)
+        ));
 
         self.execute_command(&mut command)
     }
 
     /// Applies libjxl changes from the local machine to the libjxl repository in the docker
-    /// container using a git diff. The local changes are stored in a file called `local.diff` in
-    /// the current directory.
+    /// container using a git diff. The local changes are usually stored in `LOCAL_DIFF_PATH`.
+    ///
+    /// # Arguments
+    /// * `diff_path` - The local path to the diff file.
     ///
     /// # Returns
-    /// * `Result<String, Error>` - The output of the command or an error if the command fails.
-    pub fn apply_local_as_diff(&self) -> Result<String, Box<dyn Error>> {
+    /// * `Result<String, Error>` - The output of the command or an error if the diff does not
+    /// exist, or could not be copied or applied, since benchmarking the base commit in its place
+    /// would silently invalidate the comparison.
+    pub fn apply_local_as_diff(&self, diff_path: &str) -> Result<String, Box<dyn Error>> {
+        if !Path::new(diff_path).is_file() {
+            return Err(Box::from(format!(
+                "The local diff {} does not exist",
+                diff_path
+            )));
+        }
+
         // Copy diff to docker container
-        let _ = self.execute_command(Command::new("docker").arg("cp").arg("local.diff").arg(
-            format!(
-                "{}:/libjxl/local.diff",
-                self.container_name.as_ref().unwrap()
-            ),
-        ));
+        self.execute_command(self.docker().arg("cp").arg(diff_path).arg(format!(
+            "{}:/libjxl/local.diff",
+            self.container_name.as_ref().unwrap()
+        )))
+        .map_err(|e| format!("Failed to copy {} into the container: {}", diff_path, e))?;
 
-        let _ = self.apply_diff("local.diff");
+        self.apply_diff("local.diff")
+            .map_err(|e| format!("Failed to apply {}: {}", diff_path, e))?;
         Ok(String::from("Applied local folder as diff"))
     }
 
+    /// Applies a local libjxl diff on top of a commit in the docker container, reverting any
+    /// previously applied diff first. The diff is copied to `/libjxl/<name>.diff`.
+    ///
+    /// # Arguments
+    /// * `diff_path` - The local path to the diff file.
+    /// * `name` - The label of the diff.
+    /// * `base_commit` - The libjxl commit to apply the diff on top of.
+    ///
+    /// # Returns
+    /// * `Result<String, Error>` - The output of the command or an error if the diff could not
+    /// be copied or applied.
+    pub fn apply_local_diff(
+        &self,
+        diff_path: &str,
+        name: &str,
+        base_commit: &str,
+    ) -> Result<String, Box<dyn Error>> {
+        let base_commit = DockerManager::quote_libjxl_ref(base_commit)?;
+
+        // Copy diff to docker container
+        self.execute_command(self.docker().arg("cp").arg(diff_path).arg(format!(
+            "{}:/libjxl/{}.diff",
+            self.container_name.as_ref().unwrap(),
+            name
+        )))?;
+
+        let mut command = self.docker();
+        command.arg("exec");
+        command.arg(self.container_name.as_ref().unwrap());
+        command.arg("bash");
+        command.arg("-c");
+        command.arg(format!(
+            "cd /libjxl && git checkout -- . && git fetch origin && git checkout {} && git apply {} && cd -",
+            base_commit,
+            DockerManager::shell_quote(&format!("{}.diff", name))
+        ));
+
+        self.execute_command(&mut command)
+    }
+
     /// Builds the libjxl library in the docker container.
     /// This should be run after changing the libjxl commit or applying a diff.
     ///
     /// # Returns
     /// * `Result<String, Error>` - The output of the command or an error if the command fails.
     pub fn build_libjxl(&self) -> Result<String, Box<dyn Error>> {
-        let mut command = Command::new("docker");
+        let mut command = self.docker();
         command.arg("exec");
         command.arg(self.container_name.as_ref().unwrap());
         command.arg("bash");
         command.arg("-c");
-        command.arg("cd /libjxl && SKIP_TEST=1 ./ci.sh opt; exit 0 && cd -");
+        command.arg(DockerManager::build_script(self.build_config.as_ref()));
 
         self.execute_command(&mut command)
     }
 
+    /// Builds the shell script building libjxl with a build configuration.
+    /// The CMake cache of the previous build is removed first, so that flags of another
+    /// configuration do not carry over into this one.
+    ///
+    /// # Arguments
+    /// * `build_config` - The build configuration, or None to build with the default flags.
+    ///
+    /// # Returns
+    /// * `String` - The script, to be run with `bash -c`.
+    pub fn build_script(build_config: Option<&BuildConfig>) -> String {
+        let build_config = match build_config {
+            Some(build_config) => build_config,
+            None => return "cd /libjxl && SKIP_TEST=1 ./ci.sh opt; exit 0 && cd -".to_string(),
+        };
+        let mut env = build_config
+            .env
+            .iter()
+            .map(|var| {
+                let (key, value) = var.split_once('=').unwrap();
+                format!("{}={}", key, DockerManager::shell_quote(value))
+            })
+            .collect::<Vec<String>>();
+        if !build_config.cmake_flags.is_empty() {
+            env.push(format!(
+                "CMAKE_FLAGS={}",
+                DockerManager::shell_quote(&build_config.cmake_flags.join(" "))
+            ));
+        }
+        format!(
+            "cd /libjxl && rm -f build/CMakeCache.txt && {}SKIP_TEST=1 ./ci.sh opt; exit 0 && cd -",
+            env.iter().map(|var| format!("{} ", var)).collect::<String>()
+        )
+    }
+
+    /// Gets the URL of the archive of the prebuilt static libjxl tools of a release.
+    ///
+    /// # Arguments
+    /// * `version` - The release tag, e.g. `v0.10.2`.
+    ///
+    /// # Returns
+    /// * `String` - The URL of the release archive.
+    pub fn prebuilt_release_url(version: &str) -> String {
+        format!(
+            "https://github.com/libjxl/libjxl/releases/download/{0}/jxl-linux-x86_64-static-{0}.tar.gz",
+            version
+        )
+    }
+
+    /// Builds the shell script installing the prebuilt cjxl and djxl of a release in place of
+    /// the built ones. The release is downloaded once per container and kept in
+    /// `/opt/libjxl-prebuilt`, so a later checkout and build of another commit does not remove it.
+    ///
+    /// # Arguments
+    /// * `version` - The release tag, e.g. `v0.10.2`.
+    ///
+    /// # Returns
+    /// * `String` - The script, to be run with `bash -c`.
+    pub fn prebuilt_install_script(version: &str) -> String {
+        let dir = DockerManager::shell_quote(&format!("/opt/libjxl-prebuilt/{}", version));
+        format!(
+            "set -eo pipefail; dir={dir}; \
+             if [ ! -x \"$dir/cjxl\" ] || [ ! -x \"$dir/djxl\" ]; then \
+             mkdir -p \"$dir/archive\"; \
+             curl -fsSL {url} | tar -xz -C \"$dir/archive\"; \
+             for tool in cjxl djxl; do \
+             cp \"$(find \"$dir/archive\" -type f -name $tool | head -n 1)\" \"$dir/$tool\"; \
+             done; fi; \
+             mkdir -p /libjxl/build/tools; \
+             cp \"$dir/cjxl\" \"$dir/djxl\" /libjxl/build/tools/",
+            dir = dir,
+            url = DockerManager::shell_quote(&DockerManager::prebuilt_release_url(version)),
+        )
+    }
+
+    /// Installs the prebuilt cjxl and djxl of a released libjxl version in the docker container
+    /// in place of the built ones, so the version is benchmarked without building libjxl.
+    ///
+    /// # Arguments
+    /// * `version` - The release tag, e.g. `v0.10.2`, downloaded if no binary directory is given.
+    /// * `binary_dir` - A local directory holding the prebuilt `cjxl` and `djxl`, if any.
+    ///
+    /// # Returns
+    /// * `Result<(), Error>` - An error if the binaries could not be downloaded or copied.
+    pub fn install_prebuilt_cjxl(
+        &self,
+        version: &str,
+        binary_dir: Option<&str>,
+    ) -> Result<(), Box<dyn Error>> {
+        match binary_dir {
+            Some(binary_dir) => {
+                self.execute_in_container("mkdir", vec!["-p", "/libjxl/build/tools"])?
+                    .map_err(|e| format!("Failed to create the tools directory: {}", e.trim()))?;
+                for tool in ["cjxl", "djxl"] {
+                    self.copy_file_to_container(
+                        format!("{}/{}", binary_dir, tool),
+                        format!("/libjxl/build/tools/{}", tool),
+                    )?;
+                }
+                Ok(())
+            }
+            None => {
+                let script = DockerManager::prebuilt_install_script(version);
+                match self.execute_in_container("bash", vec!["-c", script.as_str()])? {
+                    Ok(_) => Ok(()),
+                    Err(e) => Err(Box::from(format!(
+                        "Failed to install the prebuilt cjxl {}: {}",
+                        version,
+                        e.trim()
+                    ))),
+                }
+            }
+        }
+    }
+
     /// Cleans the libjxl repository in the docker container.
     /// This should be run before changing the libjxl commit or applying a diff for a clean slate.
     pub fn clean_libjxl(&self) -> Result<String, Box<dyn Error>> {
-        let mut command = Command::new("docker");
+        let mut command = self.docker();
         command.arg("exec");
         command.arg(self.container_name.as_ref().unwrap());
         command.arg("bash");
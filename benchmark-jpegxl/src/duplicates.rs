@@ -0,0 +1,98 @@
+use crate::image_reader::ImageReader;
+
+use log::warn;
+use serde_derive::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use std::error::Error;
+use std::path::PathBuf;
+
+/// Images of a test set with the same content under different names, which would count the
+/// same image several times in the aggregates.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DuplicateImages {
+    pub test_set: String,
+    /// The file names of the images, sorted. Only the first is benchmarked if duplicates are
+    /// skipped.
+    pub images: Vec<String>,
+    /// Whether all but the first image were skipped.
+    pub skipped: bool,
+}
+
+impl DuplicateImages {
+    /// Hashes the decoded pixels and dimensions of an image, so that the same image stored in
+    /// different formats or with different metadata has the same hash.
+    ///
+    /// # Arguments
+    /// * `file_path` - The path to the image file.
+    ///
+    /// # Returns
+    /// The SHA-256 of the image content as a hex string, or an error if the image could not be
+    /// decoded.
+    pub fn content_hash(file_path: &str) -> Result<String, Box<dyn Error>> {
+        let (width, height, samples) = ImageReader::decode_rgb16(file_path)?;
+        let mut hasher = Sha256::new();
+        hasher.update(width.to_le_bytes());
+        hasher.update(height.to_le_bytes());
+        hasher.update(
+            samples
+                .iter()
+                .flat_map(|sample| sample.to_le_bytes())
+                .collect::<Vec<u8>>(),
+        );
+        Ok(format!("{:x}", hasher.finalize()))
+    }
+
+    /// Finds the images of a test set with the same content.
+    /// Images that cannot be decoded are skipped with a warning.
+    ///
+    /// # Arguments
+    /// * `test_set` - The name of the test set.
+    /// * `image_paths` - The paths to the images of the test set.
+    ///
+    /// # Returns
+    /// The groups of images with the same content, in the order of their first image.
+    pub fn find(test_set: &str, image_paths: &[PathBuf]) -> Vec<DuplicateImages> {
+        let mut image_paths = image_paths.to_vec();
+        image_paths.sort();
+
+        let mut groups: Vec<(String, Vec<String>)> = Vec::new();
+        for image_path in &image_paths {
+            let hash = match DuplicateImages::content_hash(image_path.to_str().unwrap()) {
+                Ok(hash) => hash,
+                Err(e) => {
+                    warn!("could not hash {}: {}", image_path.display(), e);
+                    continue;
+                }
+            };
+            let name = image_path
+                .file_name()
+                .unwrap()
+                .to_str()
+                .unwrap()
+                .to_string();
+            match groups
+                .iter_mut()
+                .find(|(group_hash, _)| *group_hash == hash)
+            {
+                Some((_, names)) => names.push(name),
+                None => groups.push((hash, vec![name])),
+            }
+        }
+
+        groups
+            .into_iter()
+            .filter(|(_, names)| names.len() > 1)
+            .map(|(_, images)| DuplicateImages {
+                test_set: test_set.to_string(),
+                images,
+                skipped: false,
+            })
+            .collect()
+    }
+
+    /// Gets the images that repeat the first image of the group.
+    pub fn repeats(&self) -> &[String] {
+        &self.images[1..]
+    }
+}
@@ -0,0 +1,64 @@
+use std::any::Any;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+/// The failures of a benchmark run, shared by the benchmarker and its workers.
+/// By default failures are collected and reported at the end of the run. In fail-fast mode the
+/// first failure stops the run, and workers stop at their next measurement.
+#[derive(Debug, Default)]
+pub struct RunFailures {
+    fail_fast: bool,
+    stopped: AtomicBool,
+    messages: Mutex<Vec<String>>,
+}
+
+impl RunFailures {
+    /// Creates an empty failure record.
+    ///
+    /// # Arguments
+    /// * `fail_fast` - Whether the first failure stops the run.
+    ///
+    /// # Returns
+    /// The RunFailures.
+    pub fn new(fail_fast: bool) -> RunFailures {
+        RunFailures {
+            fail_fast,
+            ..RunFailures::default()
+        }
+    }
+
+    /// Records a failure, and stops the run in fail-fast mode.
+    ///
+    /// # Arguments
+    /// * `message` - A description of the failure.
+    pub fn record(&self, message: String) {
+        self.messages.lock().unwrap().push(message);
+        if self.fail_fast {
+            self.stopped.store(true, Ordering::SeqCst);
+        }
+    }
+
+    /// Records the panic of a worker thread as a failure.
+    ///
+    /// # Arguments
+    /// * `worker_id` - The id of the worker that panicked.
+    /// * `panic` - The payload of the panic, as returned when joining the thread.
+    pub fn record_panic(&self, worker_id: usize, panic: Box<dyn Any + Send>) {
+        let reason = match (panic.downcast_ref::<&str>(), panic.downcast_ref::<String>()) {
+            (Some(reason), _) => reason.to_string(),
+            (None, Some(reason)) => reason.clone(),
+            (None, None) => "unknown panic".to_string(),
+        };
+        self.record(format!("Worker {} failed: {}", worker_id, reason));
+    }
+
+    /// Returns whether the run was stopped by a failure in fail-fast mode.
+    pub fn stopped(&self) -> bool {
+        self.stopped.load(Ordering::SeqCst)
+    }
+
+    /// Returns the messages of all failures recorded so far, in the order they were recorded.
+    pub fn messages(&self) -> Vec<String> {
+        self.messages.lock().unwrap().clone()
+    }
+}
@@ -0,0 +1,226 @@
+use image::codecs::bmp::BmpDecoder;
+use image::codecs::dds::DdsDecoder;
+use image::codecs::farbfeld::FarbfeldDecoder;
+use image::codecs::gif::GifDecoder;
+use image::codecs::hdr::HdrAdapter;
+use image::codecs::ico::IcoDecoder;
+use image::codecs::jpeg::JpegDecoder;
+use image::codecs::openexr::OpenExrDecoder;
+use image::codecs::png::PngDecoder;
+use image::codecs::pnm::PnmDecoder;
+use image::codecs::qoi::QoiDecoder;
+use image::codecs::tga::TgaDecoder;
+use image::codecs::tiff::TiffDecoder;
+use image::codecs::webp::WebPDecoder;
+use image::io::{Limits, Reader};
+use image::{DynamicImage, ImageDecoder, ImageFormat, ImageResult};
+use jxl_oxide::JxlImage;
+use serde_derive::{Deserialize, Serialize};
+
+use std::error::Error;
+use std::fs::File;
+use std::io::{Cursor, Read};
+use std::path::Path;
+
+/// The bytes per pixel the JXL decoders render to: 32-bit float samples of up to four channels.
+const JXL_DECODE_BYTES_PER_PIXEL: u64 = 16;
+
+/// Limits on the size of the images that are decoded, so that an image declaring enormous
+/// dimensions is rejected before the decoders allocate for it.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ImageLimits {
+    /// The maximum number of pixels of an image, 0 for no limit.
+    pub max_pixels: u64,
+    /// The maximum number of bytes decoding an image may allocate, 0 for no limit.
+    pub max_decode_bytes: u64,
+}
+
+/// An image that was not benchmarked because it exceeds the image limits.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OversizedImage {
+    pub test_set: String,
+    /// The file name of the image.
+    pub image: String,
+    /// Why the image was rejected.
+    pub reason: String,
+}
+
+impl ImageLimits {
+    /// Creates image limits.
+    ///
+    /// # Arguments
+    /// * `max_pixels` - The maximum number of pixels of an image, 0 for no limit.
+    /// * `max_decode_bytes` - The maximum number of bytes decoding an image may allocate, 0 for
+    ///   no limit.
+    ///
+    /// # Returns
+    /// The ImageLimits.
+    pub fn new(max_pixels: u64, max_decode_bytes: u64) -> ImageLimits {
+        ImageLimits {
+            max_pixels,
+            max_decode_bytes,
+        }
+    }
+
+    /// Returns whether neither limit is set.
+    pub fn is_unlimited(&self) -> bool {
+        self.max_pixels == 0 && self.max_decode_bytes == 0
+    }
+
+    /// Returns the limits the image crate enforces while decoding.
+    ///
+    /// # Returns
+    /// The decoder limits, with the allocation limit set to the maximum decode bytes.
+    pub fn decoder_limits(&self) -> Limits {
+        let mut limits = Limits::no_limits();
+        if self.max_decode_bytes > 0 {
+            limits.max_alloc = Some(self.max_decode_bytes);
+        }
+        limits
+    }
+
+    /// Decodes an image with the image crate under the limits.
+    /// The image is checked against the limits from its header first, and the decoder reserves
+    /// its declared size against the allocation limit before it allocates for the pixels.
+    ///
+    /// # Arguments
+    /// * `file_path` - The path to the image file.
+    ///
+    /// # Returns
+    /// The decoded image, or an error if it exceeds the limits or cannot be decoded.
+    pub fn open(&self, file_path: &str) -> Result<DynamicImage, Box<dyn Error>> {
+        self.check(file_path)?;
+        let mut reader = Reader::open(file_path)?;
+        reader.limits(self.decoder_limits());
+        Ok(reader.decode()?)
+    }
+
+    /// Checks an image against the limits before it is benchmarked.
+    /// Only the header of the image is read: the dimensions and decode size of JXL images come
+    /// from the JXL header, those of other images from the decoder of their format.
+    ///
+    /// # Arguments
+    /// * `file_path` - The path to the image file.
+    ///
+    /// # Returns
+    /// An error describing the exceeded limit. Images that cannot be read are left for the
+    /// decoders to report.
+    pub fn check(&self, file_path: &str) -> Result<(), String> {
+        if self.is_unlimited() {
+            return Ok(());
+        }
+        let is_jxl = Path::new(file_path)
+            .extension()
+            .and_then(|extension| extension.to_str())
+            == Some("jxl");
+
+        let decoded_size = match is_jxl {
+            true => File::open(file_path)
+                .ok()
+                .and_then(ImageLimits::jxl_decoded_size),
+            false => ImageLimits::decoded_size(file_path),
+        };
+        match decoded_size {
+            Some((width, height, bytes)) => self.check_size(width, height, bytes),
+            None => Ok(()),
+        }
+    }
+
+    /// Checks a JXL image held in memory against the limits before it is decoded.
+    ///
+    /// # Arguments
+    /// * `sample` - The bytes of the JXL file.
+    ///
+    /// # Returns
+    /// An error describing the exceeded limit. Images whose header cannot be read are left for
+    /// the decoders to report.
+    pub fn check_jxl(&self, sample: &[u8]) -> Result<(), String> {
+        if self.is_unlimited() {
+            return Ok(());
+        }
+        match ImageLimits::jxl_decoded_size(Cursor::new(sample)) {
+            Some((width, height, bytes)) => self.check_size(width, height, bytes),
+            None => Ok(()),
+        }
+    }
+
+    /// Checks the dimensions and decode size of an image against the limits.
+    ///
+    /// # Arguments
+    /// * `width` - The width of the image.
+    /// * `height` - The height of the image.
+    /// * `bytes` - The number of bytes decoding the image allocates.
+    ///
+    /// # Returns
+    /// An error describing the exceeded limit.
+    fn check_size(&self, width: u32, height: u32, bytes: u64) -> Result<(), String> {
+        let pixels = width as u64 * height as u64;
+        if self.max_pixels > 0 && pixels > self.max_pixels {
+            return Err(format!(
+                "{}x{} is {} pixels, more than the limit of {}",
+                width, height, pixels, self.max_pixels
+            ));
+        }
+        if self.max_decode_bytes > 0 && bytes > self.max_decode_bytes {
+            return Err(format!(
+                "decoding {}x{} needs {} bytes, more than the limit of {}",
+                width, height, bytes, self.max_decode_bytes
+            ));
+        }
+        Ok(())
+    }
+
+    /// Reads the dimensions of a JXL image from its header and estimates its decode size.
+    ///
+    /// # Arguments
+    /// * `reader` - The reader of the JXL file.
+    ///
+    /// # Returns
+    /// The width, height and decode size in bytes, or None if the header cannot be read.
+    fn jxl_decoded_size<R: Read>(reader: R) -> Option<(u32, u32, u64)> {
+        let image = JxlImage::builder().read(reader).ok()?;
+        let pixels = image.width() as u64 * image.height() as u64;
+        Some((
+            image.width(),
+            image.height(),
+            pixels.saturating_mul(JXL_DECODE_BYTES_PER_PIXEL),
+        ))
+    }
+
+    /// Reads the dimensions and decode size of an image from the decoder of its format, without
+    /// decoding the pixels.
+    ///
+    /// # Arguments
+    /// * `file_path` - The path to the image file.
+    ///
+    /// # Returns
+    /// The width, height and decode size in bytes, or None if the image cannot be read.
+    fn decoded_size(file_path: &str) -> Option<(u32, u32, u64)> {
+        fn size<'a, D: ImageDecoder<'a>>(decoder: ImageResult<D>) -> Option<(u32, u32, u64)> {
+            let decoder = decoder.ok()?;
+            let (width, height) = decoder.dimensions();
+            Some((width, height, decoder.total_bytes()))
+        }
+
+        let reader = Reader::open(file_path).ok()?.with_guessed_format().ok()?;
+        let format = reader.format()?;
+        let reader = reader.into_inner();
+        match format {
+            ImageFormat::Png => size(PngDecoder::new(reader)),
+            ImageFormat::Jpeg => size(JpegDecoder::new(reader)),
+            ImageFormat::Gif => size(GifDecoder::new(reader)),
+            ImageFormat::WebP => size(WebPDecoder::new(reader)),
+            ImageFormat::Pnm => size(PnmDecoder::new(reader)),
+            ImageFormat::Tiff => size(TiffDecoder::new(reader)),
+            ImageFormat::Tga => size(TgaDecoder::new(reader)),
+            ImageFormat::Dds => size(DdsDecoder::new(reader)),
+            ImageFormat::Bmp => size(BmpDecoder::new(reader)),
+            ImageFormat::Ico => size(IcoDecoder::new(reader)),
+            ImageFormat::Hdr => size(HdrAdapter::new(reader)),
+            ImageFormat::OpenExr => size(OpenExrDecoder::new(reader)),
+            ImageFormat::Farbfeld => size(FarbfeldDecoder::new(reader)),
+            ImageFormat::Qoi => size(QoiDecoder::new(reader)),
+            _ => None,
+        }
+    }
+}
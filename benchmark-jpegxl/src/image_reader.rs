@@ -1,15 +1,23 @@
-use serde::{Serialize, Serializer};
-use serde_derive::Serialize;
+use crate::config::{QualityTarget, Roi};
+use crate::image_limits::ImageLimits;
+use crate::metrics::squared_error_sum;
 
-use image::DynamicImage;
+use log::warn;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use serde_derive::{Deserialize, Serialize};
+
+use image::codecs::jpeg::JpegDecoder;
+use image::codecs::png::PngDecoder;
+use image::{DynamicImage, ImageDecoder};
 use std::fmt::{self, Debug, Display, Formatter};
 use std::path::Path;
 
-use jpegxl_rs::decode::{JxlDecoder, Metadata, Pixels};
+use jpegxl_rs::decode::{JxlDecoder, Metadata, PixelFormat, Pixels};
 use jpegxl_rs::decoder_builder;
+use jxl_oxide::JxlImage;
 
 /// Supported color spaces for image reading.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum ColorType {
     L8,
     La8,
@@ -21,6 +29,11 @@ pub enum ColorType {
     Rgba16,
     Rgb32F,
     Rgba32F,
+    /// More than 4 channels, e.g. JXL images with depth, thermal or spot color channels.
+    MultiChannel {
+        channels: u32,
+        bit_depth: u32,
+    },
 }
 
 impl ColorType {
@@ -37,6 +50,10 @@ impl ColorType {
             ColorType::Rgba16 => "Rgba16".to_string(),
             ColorType::Rgb32F => "Rgb32F".to_string(),
             ColorType::Rgba32F => "Rgba32F".to_string(),
+            ColorType::MultiChannel {
+                channels,
+                bit_depth,
+            } => format!("Multi{}x{}", channels, bit_depth),
         }
     }
 
@@ -53,6 +70,72 @@ impl ColorType {
             ColorType::Rgba16 => "16-bit RGB with Alpha".to_string(),
             ColorType::Rgb32F => "32-bit Floating Point RGB".to_string(),
             ColorType::Rgba32F => "32-bit Floating Point RGB with Alpha".to_string(),
+            ColorType::MultiChannel {
+                channels,
+                bit_depth,
+            } => format!("{}-bit {}-Channel", bit_depth, channels),
+        }
+    }
+
+    /// Returns the color model of the color space, "Gray", "RGB" or "Multi".
+    pub fn color_model(&self) -> &'static str {
+        match self {
+            ColorType::L8 | ColorType::La8 | ColorType::L16 | ColorType::La16 => "Gray",
+            ColorType::MultiChannel { .. } => "Multi",
+            _ => "RGB",
+        }
+    }
+
+    /// Returns the number of bytes of a pixel in the color space.
+    pub fn bytes_per_pixel(&self) -> u32 {
+        match self {
+            ColorType::L8 => 1,
+            ColorType::La8 => 2,
+            ColorType::Rgb8 => 3,
+            ColorType::Rgba8 => 4,
+            ColorType::L16 => 2,
+            ColorType::La16 => 4,
+            ColorType::Rgb16 => 6,
+            ColorType::Rgba16 => 8,
+            ColorType::Rgb32F => 12,
+            ColorType::Rgba32F => 16,
+            ColorType::MultiChannel {
+                channels,
+                bit_depth,
+            } => channels * bit_depth.div_ceil(8),
+        }
+    }
+
+    /// Returns whether the native metrics (MSE and PSNR) can be computed on the color space.
+    /// Images with extra channels are skipped, as they have no single reference to compare the
+    /// extra channels against.
+    pub fn native_metrics_supported(&self) -> bool {
+        !matches!(self, ColorType::MultiChannel { .. })
+    }
+
+    /// Gets the color space of a decoded image from its number of channels and bit depth.
+    ///
+    /// # Arguments
+    /// * `channels` - The number of channels, including alpha and extra channels.
+    /// * `bit_depth` - The bits per sample.
+    ///
+    /// # Returns
+    /// The color space of the image.
+    pub fn from_channels(channels: u32, bit_depth: u32) -> ColorType {
+        match (channels, bit_depth) {
+            (1, 8) => ColorType::L8,
+            (1, 16) => ColorType::L16,
+            (2, 8) => ColorType::La8,
+            (2, 16) => ColorType::La16,
+            (3, 8) => ColorType::Rgb8,
+            (3, 16) => ColorType::Rgb16,
+            (4, 8) => ColorType::Rgba8,
+            (4, 16) => ColorType::Rgba16,
+            (channels, bit_depth) if channels > 4 => ColorType::MultiChannel {
+                channels,
+                bit_depth,
+            },
+            _ => panic!("Unknown jxl color space"),
         }
     }
 
@@ -66,29 +149,12 @@ impl ColorType {
     /// The colorspace of the JXL image.
     fn get_jxl_color_space(metadata: &Metadata, pixels: &Pixels) -> ColorType {
         // The color space is determined by the number of color channels and the pixel type.
-        match metadata.num_color_channels {
-            1 => match pixels {
-                Pixels::Uint8(_) => ColorType::L8,
-                Pixels::Uint16(_) => ColorType::L16,
-                _ => panic!("Unknown jxl color space"),
-            },
-            2 => match pixels {
-                Pixels::Uint8(_) => ColorType::La8,
-                Pixels::Uint16(_) => ColorType::La16,
-                _ => panic!("Unknown jxl color space"),
-            },
-            3 => match pixels {
-                Pixels::Uint8(_) => ColorType::Rgb8,
-                Pixels::Uint16(_) => ColorType::Rgb16,
-                _ => panic!("Unknown jxl color space"),
-            },
-            4 => match pixels {
-                Pixels::Uint8(_) => ColorType::Rgba8,
-                Pixels::Uint16(_) => ColorType::Rgba16,
-                _ => panic!("Unknown jxl color space"),
-            },
-            _ => todo!(),
-        }
+        let bit_depth = match pixels {
+            Pixels::Uint8(_) => 8,
+            Pixels::Uint16(_) => 16,
+            Pixels::Float(_) | Pixels::Float16(_) => 32,
+        };
+        ColorType::from_channels(metadata.num_color_channels, bit_depth)
     }
 }
 
@@ -174,10 +240,10 @@ impl ImageFormat {
         match extension {
             "jxl" => ImageFormat::JpegXl,
             "png" => ImageFormat::Png,
-            "jpeg" => ImageFormat::Jpeg,
+            "jpeg" | "jpg" => ImageFormat::Jpeg,
             "gif" => ImageFormat::Gif,
             "webp" => ImageFormat::WebP,
-            "ppm" => ImageFormat::Pnm,
+            "ppm" | "pgm" | "pbm" | "pnm" | "pam" => ImageFormat::Pnm,
             "tiff" => ImageFormat::Tiff,
             "tga" => ImageFormat::Tga,
             "dds" => ImageFormat::Dds,
@@ -194,7 +260,7 @@ impl ImageFormat {
 }
 
 /// Hacky optional f32 wrapper for serialization.
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Default)]
 pub struct JXLf32(Option<f32>);
 
 impl JXLf32 {
@@ -282,21 +348,77 @@ impl JXLString {
 }
 
 /// Metadata of an image file.
-#[derive(Debug, Clone, Serialize)]
+/// The fields are the columns of the results CSV files in order, named by their headers.
+/// Columns added after the first release have defaults, so older results files can be read.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ImageFileData {
+    #[serde(rename = "Image Name")]
     pub image_name: String,
+    #[serde(rename = "Commit")]
     pub commit: String,
+    #[serde(rename = "Test Set")]
     pub test_set: String,
+    #[serde(rename = "File Path")]
     pub file_path: String,
+    #[serde(rename = "Image Width")]
     pub width: u32,
+    #[serde(rename = "Image Height")]
     pub height: u32,
+    #[serde(rename = "File Size")]
     pub file_size: usize,
+    #[serde(rename = "Raw Image Size")]
     pub raw_size: usize,
+    #[serde(rename = "Image Color Space")]
     pub color_space: ColorType,
+    #[serde(rename = "File Format")]
     pub file_format: ImageFormat,
+    #[serde(rename = "JXL Original Image Name")]
     pub jxl_orig_image_name: JXLString,
+    #[serde(rename = "JXL Distance")]
     pub jxl_distance: JXLf32,
+    #[serde(rename = "JXL Effort")]
     pub jxl_effort: JXLu32,
+    /// The dimensions of the source before it was downscaled, 0 in older results files until
+    /// they are filled in with the image dimensions when read.
+    #[serde(rename = "Source Width", default)]
+    pub source_width: u32,
+    #[serde(rename = "Source Height", default)]
+    pub source_height: u32,
+    /// The decoder that read the image: "image", "jpegxl-rs" or the "djxl" fallback.
+    #[serde(rename = "Decoder", default)]
+    pub decoder: String,
+    /// False if the JXL header marks the image as lossy, empty if jxlinfo could not tell.
+    #[serde(rename = "Header Lossless", default)]
+    pub header_lossless: Option<bool>,
+    /// The distance parsed from the file name, kept to catch mismatches with the header.
+    #[serde(rename = "Filename Distance", default)]
+    pub filename_distance: JXLf32,
+    /// Whether the image was already lossy compressed (JPEG or lossy WebP) before the
+    /// benchmark, so its compression artifacts are part of the reference.
+    #[serde(rename = "Source Is Lossy", default)]
+    pub source_is_lossy: bool,
+    /// The color model the source image was stored in: "Gray", "RGB" or "CMYK". CMYK images
+    /// are converted to RGB before they are benchmarked.
+    #[serde(rename = "Source Color Model", default)]
+    pub source_color_model: String,
+    /// Whether the jxl-oxide decoder disagreed with the jpegxl_rs decoder on the image, if the
+    /// decode was cross-checked.
+    #[serde(rename = "Decoder Mismatch", default)]
+    pub decoder_mismatch: bool,
+}
+
+/// The largest difference in 8-bit levels between the samples of two decoders of the same JXL
+/// image that is still considered agreement, since they may round differently.
+pub const DECODE_CROSS_CHECK_TOLERANCE: u8 = 2;
+
+/// Checks whether JXL images can be decoded on this machine.
+/// The jpegxl_rs decoder needs the native libjxl library, which builds without it cannot
+/// initialize, so this is checked once at startup instead of failing on every image.
+///
+/// # Returns
+/// Whether the jpegxl_rs decoder can be created.
+pub fn jxl_decode_available() -> bool {
+    decoder_builder().build().is_ok()
 }
 
 /// Reads an image file and extracts its metadata.
@@ -306,7 +428,15 @@ pub struct ImageReader {
 }
 
 impl ImageReader {
+    /// The PSNR recorded for a lossless encode, whose true PSNR is infinite.
+    pub const LOSSLESS_PSNR: f64 = 100.0;
+    /// The PSNR peak of 8-bit samples.
+    pub const UINT8_PEAK: f64 = 255.0;
+    /// The PSNR peak of samples normalized to the 0..1 range, which float images are compared on.
+    pub const NORMALIZED_PEAK: f64 = 1.0;
+
     /// Creates a new ImageReader.
+    /// Panics if the image cannot be decoded, see `try_new`.
     ///
     /// # Arguments
     /// * `file_path` - The path to the image file.
@@ -315,6 +445,38 @@ impl ImageReader {
     /// # Returns
     /// The ImageReader.
     pub fn new(file_path: String, commit: String) -> ImageReader {
+        ImageReader::try_new(file_path, commit).unwrap()
+    }
+
+    /// Creates a new ImageReader, returning an error if the image cannot be decoded.
+    ///
+    /// # Arguments
+    /// * `file_path` - The path to the image file.
+    /// * `commit` - The commit hash of the image file.
+    ///
+    /// # Returns
+    /// The ImageReader, or an error if the image could not be decoded.
+    pub fn try_new(
+        file_path: String,
+        commit: String,
+    ) -> Result<ImageReader, Box<dyn std::error::Error>> {
+        ImageReader::try_new_with_limits(file_path, commit, &ImageLimits::default())
+    }
+
+    /// Creates a new ImageReader, decoding the image under the given image limits.
+    ///
+    /// # Arguments
+    /// * `file_path` - The path to the image file.
+    /// * `commit` - The commit hash of the image file.
+    /// * `limits` - The limits the image is decoded under.
+    ///
+    /// # Returns
+    /// The ImageReader, or an error if the image exceeds the limits or could not be decoded.
+    pub fn try_new_with_limits(
+        file_path: String,
+        commit: String,
+        limits: &ImageLimits,
+    ) -> Result<ImageReader, Box<dyn std::error::Error>> {
         let path = Path::new(&file_path);
         
         // Check that extension is supported image format.
@@ -325,14 +487,14 @@ impl ImageReader {
 
         // Read JXL files separately since the image crate does not support them.
         if extension == "jxl" {
-            return ImageReader::read_jxl(file_path, commit);
+            return ImageReader::read_jxl(file_path, commit, limits);
         }
 
-        // Read the image file with the image crate.
-        let image = image::open(&path).unwrap();
+        // Read the image file with the image crate, as one of the supported color spaces.
+        let image = ImageReader::normalize_color(limits.open(&file_path)?);
 
         // Create the ImageReader with the given image.
-        ImageReader {
+        Ok(ImageReader {
             image: Some(image.clone()),
             file_data: ImageFileData {
                 image_name: path.file_name().unwrap().to_str().unwrap().to_string(),
@@ -349,14 +511,24 @@ impl ImageReader {
                 width: image.width(),
                 height: image.height(),
                 file_size: ImageReader::get_file_size(&file_path),
-                raw_size: ImageReader::get_raw_size(&file_path),
+                raw_size: ImageReader::raw_image_size(&image)?,
                 color_space: image.color().into(),
                 file_format: ImageReader::get_format(&file_path),
                 jxl_orig_image_name: JXLString::new(None),
                 jxl_distance: JXLf32::new(None),
                 jxl_effort: JXLu32::new(None),
+                source_width: image.width(),
+                source_height: image.height(),
+                decoder: "image".to_string(),
+                header_lossless: None,
+                filename_distance: JXLf32::new(None),
+                source_is_lossy: ImageReader::is_lossy_source(&file_path),
+                source_color_model: ImageReader::jpeg_color_model(&file_path)
+                    .unwrap_or(ColorType::from(image.color()).color_model())
+                    .to_string(),
+                decoder_mismatch: false,
             },
-        }
+        })
     }
 
     /// Reads a JXL image file and extracts its metadata.
@@ -364,15 +536,63 @@ impl ImageReader {
     /// # Arguments
     /// * `file_path` - The path to the JXL image file.
     /// * `commit` - The commit hash of the JXL image file.
+    /// * `limits` - The limits the image is checked against before it is decoded.
     ///
     /// # Returns
-    /// The ImageReader.
-    fn read_jxl(file_path: String, commit: String) -> ImageReader {
-        let sample = std::fs::read(file_path.clone()).unwrap();
+    /// The ImageReader, or an error if the image exceeds the limits or could not be decoded.
+    fn read_jxl(
+        file_path: String,
+        commit: String,
+        limits: &ImageLimits,
+    ) -> Result<ImageReader, Box<dyn std::error::Error>> {
+        let sample = std::fs::read(file_path.clone())?;
+        limits.check_jxl(&sample)?;
+        ImageReader::from_jxl_bytes(&sample, file_path, commit)
+    }
 
-        // Decode the JXL image using the jpegxl_rs JXL decoder.
-        let decoder: JxlDecoder = decoder_builder().build().unwrap();
-        let (metadata, pixels) = decoder.decode(&sample).unwrap();
+    /// Reads a JXL image that is held in memory.
+    /// The file path is only used to name the image, the file does not need to exist.
+    /// Images the jpegxl_rs decoder rejects (e.g. animations or extra channels) are decoded with
+    /// the djxl tool instead if it is installed.
+    ///
+    /// # Arguments
+    /// * `sample` - The bytes of the JXL file.
+    /// * `file_path` - The path the JXL file would have on disk.
+    /// * `commit` - The commit hash of the image file.
+    ///
+    /// # Returns
+    /// The ImageReader, or an error if neither decoder could decode the image.
+    pub fn from_jxl_bytes(
+        sample: &[u8],
+        file_path: String,
+        commit: String,
+    ) -> Result<ImageReader, Box<dyn std::error::Error>> {
+        // Decode the JXL image using the jpegxl_rs JXL decoder, falling back to djxl.
+        let decoder: JxlDecoder = decoder_builder().build()?;
+        let (width, height, raw_size, color_space, decoder) = match decoder.decode(sample) {
+            Ok((metadata, pixels)) => (
+                metadata.width,
+                metadata.height,
+                ImageReader::raw_jxl_size(&metadata, &pixels)?,
+                ColorType::get_jxl_color_space(&metadata, &pixels),
+                "jpegxl-rs",
+            ),
+            Err(e) => {
+                let image = ImageReader::decode_with_djxl(sample).map_err(|fallback_e| {
+                    format!(
+                        "Failed to decode {}: jpegxl_rs: {}, djxl: {}",
+                        file_path, e, fallback_e
+                    )
+                })?;
+                (
+                    image.width(),
+                    image.height(),
+                    ImageReader::raw_image_size(&image)?,
+                    image.color().into(),
+                    "djxl",
+                )
+            }
+        };
 
         // Get the file name and extension.
         let path = Path::new(&file_path);
@@ -382,36 +602,12 @@ impl ImageReader {
             panic!("Not a .jxl file");
         }
 
-        let file_name_parts: Vec<&str> = file_name.split("-").collect();
-        let orig_image_name = file_name_parts[0..file_name_parts.len() - 2].join("-");
-
-        // Get the distance and effort values from the file name.
-        let distance = file_name_parts[file_name_parts.len() - 2].parse::<f32>();
-        let distance = match extension {
-            "jxl" => match distance {
-                Ok(value) => JXLf32::new(Some(value)),
-                Err(_) => JXLf32::new(None),
-            },
-            _ => JXLf32::new(None),
-        };
-
-        let effort = file_name_parts
-            .last()
-            .unwrap()
-            .split(".")
-            .next()
-            .unwrap()
-            .parse::<u32>();
-        let effort = match extension {
-            "jxl" => match effort {
-                Ok(value) => JXLu32::new(Some(value)),
-                Err(_) => JXLu32::new(None),
-            },
-            _ => JXLu32::new(None),
-        };
+        // Get the original image name, distance and effort values from the file name.
+        let (orig_image_name, filename_distance, effort) =
+            ImageReader::parse_compressed_image_name(&file_name);
 
         // Create the ImageReader with the given image.
-        ImageReader {
+        Ok(ImageReader {
             image: None,
             file_data: ImageFileData {
                 image_name: file_name.clone(),
@@ -425,19 +621,695 @@ impl ImageReader {
                     .unwrap()
                     .to_string(),
                 file_path: file_path.clone(),
-                width: metadata.width.clone(),
-                height: metadata.height.clone(),
-                file_size: ImageReader::get_file_size(&file_path),
-                raw_size: ImageReader::get_raw_jxl_size(&file_path),
-                color_space: ColorType::get_jxl_color_space(&metadata, &pixels),
+                width,
+                height,
+                file_size: sample.len(),
+                raw_size,
+                source_color_model: color_space.color_model().to_string(),
+                color_space,
                 file_format: ImageReader::get_format(&file_path),
                 jxl_orig_image_name: JXLString::new(Some(orig_image_name)),
-                jxl_distance: distance,
+                jxl_distance: filename_distance,
                 jxl_effort: effort,
+                source_width: width,
+                source_height: height,
+                decoder: decoder.to_string(),
+                header_lossless: None,
+                filename_distance,
+                source_is_lossy: false,
+                decoder_mismatch: false,
+            },
+        })
+    }
+
+    /// Checks the encode parameters a JXL image was read with against its header, as described
+    /// by jxlinfo run in the container of the toolchain that encoded it.
+    /// The encoder does not store the distance or effort in the file, so the header can only
+    /// tell lossy encodes apart. If the file name contradicts the header, the distance is left
+    /// empty, since the actual distance is unknown.
+    ///
+    /// # Arguments
+    /// * `file_data` - The file data of the JXL image, with the parameters from its file name.
+    /// * `jxlinfo_output` - The output of jxlinfo for the image.
+    pub fn apply_jxlinfo(file_data: &mut ImageFileData, jxlinfo_output: &str) {
+        file_data.header_lossless = ImageReader::parse_jxlinfo_lossless(jxlinfo_output);
+        if ImageReader::encode_params_mismatch(
+            file_data.filename_distance,
+            file_data.header_lossless,
+        ) {
+            warn!(
+                "the file name of {} does not match its header (lossless: {})",
+                file_data.image_name,
+                file_data.header_lossless.unwrap()
+            );
+            file_data.jxl_distance = JXLf32::new(None);
+        }
+    }
+
+    /// Parses whether a JXL image is lossless from the output of jxlinfo, e.g.
+    /// `JPEG XL image, 768x512, lossy, 8-bit RGB`.
+    /// jxlinfo only reports images without XYB as "(possibly) lossless", which they need not be,
+    /// so only lossy images are recognized.
+    ///
+    /// # Arguments
+    /// * `output` - The output of jxlinfo.
+    ///
+    /// # Returns
+    /// False for lossy images, or None if the output does not tell.
+    pub fn parse_jxlinfo_lossless(output: &str) -> Option<bool> {
+        let line = output
+            .lines()
+            .find(|line| line.starts_with("JPEG XL image"))?;
+        line.split(", ").find_map(|field| match field {
+            "lossy" => Some(false),
+            _ => None,
+        })
+    }
+
+    /// Checks whether the distance parsed from the file name of a JXL image contradicts its
+    /// header, e.g. a lossless image named as a distance 1 encode.
+    ///
+    /// # Arguments
+    /// * `filename_distance` - The distance parsed from the file name.
+    /// * `header_lossless` - Whether the header marks the image as lossless, if known.
+    ///
+    /// # Returns
+    /// True if both are known and disagree.
+    pub fn encode_params_mismatch(
+        filename_distance: JXLf32,
+        header_lossless: Option<bool>,
+    ) -> bool {
+        match (filename_distance.0, header_lossless) {
+            (Some(distance), Some(lossless)) => (distance == 0.0) != lossless,
+            _ => false,
+        }
+    }
+
+    /// Decodes a JXL image with the djxl tool, if it is installed on the local machine.
+    /// The image is written to a temporary file, decoded to PNG and read with the image crate.
+    ///
+    /// # Arguments
+    /// * `sample` - The bytes of the JXL file.
+    ///
+    /// # Returns
+    /// The decoded image, or an error if djxl is not installed or failed.
+    fn decode_with_djxl(sample: &[u8]) -> Result<DynamicImage, Box<dyn std::error::Error>> {
+        let temp_dir = std::env::temp_dir().join(format!(
+            "benchmark-jpegxl-djxl-{}-{:?}",
+            std::process::id(),
+            std::thread::current().id()
+        ));
+        std::fs::create_dir_all(&temp_dir)?;
+        let input = temp_dir.join("input.jxl");
+        let output = temp_dir.join("output.png");
+        std::fs::write(&input, sample)?;
+
+        let result = std::process::Command::new("djxl")
+            .arg(&input)
+            .arg(&output)
+            .output()
+            .map_err(|e| format!("djxl is not available: {}", e))
+            .and_then(|output| match output.status.success() {
+                true => Ok(()),
+                false => Err(String::from_utf8_lossy(&output.stderr).to_string()),
+            })
+            .and_then(|_| image::open(&output).map_err(|e| e.to_string()));
+
+        let _ = std::fs::remove_dir_all(&temp_dir);
+        Ok(result?)
+    }
+
+    /// Converts an image decoded into a color type that is not one of the supported color spaces
+    /// into the closest 8-bit color space, so that it can be measured like any other image.
+    ///
+    /// # Arguments
+    /// * `image` - The decoded image.
+    ///
+    /// # Returns
+    /// The image in a supported color space.
+    pub fn normalize_color(image: DynamicImage) -> DynamicImage {
+        let color = image.color();
+        match color {
+            image::ColorType::L8
+            | image::ColorType::La8
+            | image::ColorType::Rgb8
+            | image::ColorType::Rgba8
+            | image::ColorType::L16
+            | image::ColorType::La16
+            | image::ColorType::Rgb16
+            | image::ColorType::Rgba16
+            | image::ColorType::Rgb32F
+            | image::ColorType::Rgba32F => image,
+            _ => match (color.has_color(), color.has_alpha()) {
+                (false, false) => DynamicImage::ImageLuma8(image.to_luma8()),
+                (false, true) => DynamicImage::ImageLumaA8(image.to_luma_alpha8()),
+                (true, false) => DynamicImage::ImageRgb8(image.to_rgb8()),
+                (true, true) => DynamicImage::ImageRgba8(image.to_rgba8()),
+            },
+        }
+    }
+
+    /// Reads the color model of a JPEG file from the number of components in its frame header.
+    /// JPEG files with four components store CMYK (or YCCK, its transformed form), which the
+    /// decoder converts to RGB.
+    ///
+    /// # Arguments
+    /// * `file_path` - The path to the image file.
+    ///
+    /// # Returns
+    /// "Gray", "RGB" or "CMYK", or None if the file is not a JPEG or its header could not be read.
+    pub fn jpeg_color_model(file_path: &str) -> Option<&'static str> {
+        if ImageReader::get_format(&file_path.to_string()) != ImageFormat::Jpeg {
+            return None;
+        }
+        let data = std::fs::read(file_path).ok()?;
+        if !data.starts_with(&[0xFF, 0xD8]) {
+            return None;
+        }
+
+        // Walk the marker segments up to the start of frame, which holds the component count.
+        let mut offset = 2;
+        while offset + 4 <= data.len() {
+            if data[offset] != 0xFF {
+                return None;
+            }
+            let marker = data[offset + 1];
+            let length = u16::from_be_bytes([data[offset + 2], data[offset + 3]]) as usize;
+            let is_start_of_frame =
+                matches!(marker, 0xC0..=0xC3 | 0xC5..=0xC7 | 0xC9..=0xCB | 0xCD..=0xCF);
+            if is_start_of_frame {
+                return match data.get(offset + 9)? {
+                    1 => Some("Gray"),
+                    3 => Some("RGB"),
+                    4 => Some("CMYK"),
+                    _ => None,
+                };
+            }
+            if marker == 0xDA {
+                return None;
+            }
+            offset += 2 + length;
+        }
+        None
+    }
+
+    /// Converts a CMYK JPEG to an RGB PNG, so that cjxl and the metrics read the same RGB pixels.
+    /// The converted image is written into the given output directory, keeping the original
+    /// image name.
+    ///
+    /// # Arguments
+    /// * `file_path` - The path to the image file.
+    /// * `out_dir` - The directory to write the converted image to.
+    ///
+    /// # Returns
+    /// The path to the converted image, or None if the image is not a CMYK JPEG.
+    pub fn convert_cmyk_to_rgb(
+        file_path: &str,
+        out_dir: &str,
+    ) -> Result<Option<String>, Box<dyn std::error::Error>> {
+        if ImageReader::jpeg_color_model(file_path) != Some("CMYK") {
+            return Ok(None);
+        }
+
+        // The image crate applies the CMYK to RGB conversion when decoding.
+        let image = image::open(file_path)?.to_rgb8();
+        let image_name = Path::new(file_path)
+            .file_stem()
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_string();
+        std::fs::create_dir_all(out_dir)?;
+        let converted_path = format!("{}/{}.png", out_dir, image_name);
+        image.save(&converted_path)?;
+
+        Ok(Some(converted_path))
+    }
+
+    /// Gets the color model the source of an image was stored in, see `jpeg_color_model`.
+    ///
+    /// # Arguments
+    /// * `file_path` - The path to the image file.
+    ///
+    /// # Returns
+    /// "Gray", "RGB" or "CMYK".
+    pub fn source_color_model(file_path: &str) -> String {
+        match ImageReader::jpeg_color_model(file_path) {
+            Some(color_model) => color_model.to_string(),
+            None => match image::open(file_path) {
+                Ok(image) => ColorType::from(image.color()).color_model().to_string(),
+                Err(_) => "RGB".to_string(),
             },
         }
     }
 
+    /// Downscales an image so that its longest side fits within the given maximum dimension.
+    /// The scaled image is resized with a Lanczos3 filter and written as a PNG into the given
+    /// output directory, keeping the original image name.
+    ///
+    /// # Arguments
+    /// * `file_path` - The path to the image file.
+    /// * `out_dir` - The directory to write the scaled image to.
+    /// * `max_dimension` - The maximum length of the longest side of the image in pixels.
+    ///
+    /// # Returns
+    /// The path to the scaled image, or None if the image already fits within the maximum
+    /// dimension.
+    pub fn downscale_to_max_dimension(
+        file_path: &str,
+        out_dir: &str,
+        max_dimension: u32,
+    ) -> Result<Option<String>, Box<dyn std::error::Error>> {
+        let (width, height) = image::image_dimensions(file_path)?;
+        if width.max(height) <= max_dimension {
+            return Ok(None);
+        }
+
+        // Resize preserving the aspect ratio so the longest side is the maximum dimension.
+        let image = image::open(file_path)?;
+        let scaled = image.resize(
+            max_dimension,
+            max_dimension,
+            image::imageops::FilterType::Lanczos3,
+        );
+
+        // Write the scaled image as a PNG under the original image name.
+        let image_name = Path::new(file_path)
+            .file_stem()
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_string();
+        std::fs::create_dir_all(out_dir)?;
+        let scaled_path = format!("{}/{}.png", out_dir, image_name);
+        scaled.save(&scaled_path)?;
+
+        Ok(Some(scaled_path))
+    }
+
+    /// Decodes an image file into an 8-bit RGB pixel buffer.
+    /// JXL files are decoded with the jpegxl_rs decoder, all other formats with the image crate.
+    ///
+    /// # Arguments
+    /// * `file_path` - The path to the image file.
+    ///
+    /// # Returns
+    /// The width, height and interleaved RGB samples of the image.
+    pub fn decode_rgb8(file_path: &str) -> Result<(u32, u32, Vec<u8>), Box<dyn std::error::Error>> {
+        ImageReader::decode_rgb8_with_limits(file_path, &ImageLimits::default())
+    }
+
+    /// Decodes an image file into an 8-bit RGB pixel buffer under the given image limits.
+    ///
+    /// # Arguments
+    /// * `file_path` - The path to the image file.
+    /// * `limits` - The limits the image is decoded under.
+    ///
+    /// # Returns
+    /// The width, height and interleaved RGB samples of the image, or an error if the image
+    /// exceeds the limits or could not be decoded.
+    pub fn decode_rgb8_with_limits(
+        file_path: &str,
+        limits: &ImageLimits,
+    ) -> Result<(u32, u32, Vec<u8>), Box<dyn std::error::Error>> {
+        let path = Path::new(file_path);
+        let extension = path.extension().unwrap_or(std::ffi::OsStr::new("")).to_str().unwrap();
+        if extension == "jxl" {
+            let sample = std::fs::read(file_path)?;
+            limits.check_jxl(&sample)?;
+            return ImageReader::decode_jxl_rgb8(&sample);
+        }
+
+        let image = limits.open(file_path)?.to_rgb8();
+        Ok((image.width(), image.height(), image.into_raw()))
+    }
+
+    /// Decodes a JXL image held in memory into an 8-bit RGB pixel buffer.
+    ///
+    /// # Arguments
+    /// * `sample` - The bytes of the JXL file.
+    ///
+    /// # Returns
+    /// The width, height and interleaved RGB samples of the image.
+    pub fn decode_jxl_rgb8(sample: &[u8]) -> Result<(u32, u32, Vec<u8>), Box<dyn std::error::Error>> {
+        let decoder: JxlDecoder = decoder_builder()
+            .pixel_format(PixelFormat {
+                num_channels: 3,
+                ..PixelFormat::default()
+            })
+            .build()?;
+        let (metadata, pixels) = decoder.decode_with::<u8>(sample)?;
+        Ok((metadata.width, metadata.height, pixels))
+    }
+
+    /// Reads the ICC profile embedded in an image file.
+    /// PNG and JPEG profiles are read with the image crate, JXL profiles from the image header,
+    /// see `jxl_icc_profile`.
+    ///
+    /// # Arguments
+    /// * `file_path` - The path to the image file.
+    ///
+    /// # Returns
+    /// The ICC profile, or None if the image has none, cannot be read, or is in another format.
+    pub fn icc_profile(file_path: &str) -> Option<Vec<u8>> {
+        let path = Path::new(file_path);
+        if path.extension().and_then(|extension| extension.to_str()) == Some("jxl") {
+            return ImageReader::jxl_icc_profile(&std::fs::read(path).ok()?);
+        }
+
+        let reader = std::io::BufReader::new(std::fs::File::open(path).ok()?);
+        match image::ImageFormat::from_path(path).ok()? {
+            image::ImageFormat::Png => PngDecoder::new(reader).ok()?.icc_profile(),
+            image::ImageFormat::Jpeg => JpegDecoder::new(reader).ok()?.icc_profile(),
+            _ => None,
+        }
+    }
+
+    /// Reads the ICC profile embedded in the header of a JXL image held in memory with the
+    /// jxl-oxide decoder, without rendering its pixels.
+    ///
+    /// # Arguments
+    /// * `sample` - The bytes of the JXL file.
+    ///
+    /// # Returns
+    /// The ICC profile, or None if the image signals its color encoding without one or its
+    /// header cannot be read.
+    pub fn jxl_icc_profile(sample: &[u8]) -> Option<Vec<u8>> {
+        let image = JxlImage::builder()
+            .read(std::io::Cursor::new(sample))
+            .ok()?;
+        image.original_icc().map(|icc| icc.to_vec())
+    }
+
+    /// Decodes an image file into a 16-bit RGB pixel buffer, so 16-bit sources keep their full
+    /// precision while 8-bit sources are scaled exactly, 255 becoming 65535.
+    /// JXL files are decoded with the jpegxl_rs decoder, all other formats with the image crate.
+    ///
+    /// # Arguments
+    /// * `file_path` - The path to the image file.
+    ///
+    /// # Returns
+    /// The width, height and interleaved RGB samples of the image.
+    pub fn decode_rgb16(file_path: &str) -> Result<(u32, u32, Vec<u16>), Box<dyn std::error::Error>> {
+        let path = Path::new(file_path);
+        let extension = path.extension().unwrap_or(std::ffi::OsStr::new("")).to_str().unwrap();
+        if extension == "jxl" {
+            let sample = std::fs::read(file_path)?;
+            let decoder: JxlDecoder = decoder_builder()
+                .pixel_format(PixelFormat {
+                    num_channels: 3,
+                    ..PixelFormat::default()
+                })
+                .build()?;
+            let (metadata, pixels) = decoder.decode_with::<u16>(&sample)?;
+            return Ok((metadata.width, metadata.height, pixels));
+        }
+
+        let image = image::open(path)?.to_rgb16();
+        Ok((image.width(), image.height(), image.into_raw()))
+    }
+
+    /// Decodes a JXL image held in memory into an 8-bit RGB pixel buffer with the pure-Rust
+    /// jxl-oxide decoder, independent of libjxl. Grayscale images are expanded to RGB and extra
+    /// channels such as alpha are dropped.
+    ///
+    /// # Arguments
+    /// * `sample` - The bytes of the JXL file.
+    ///
+    /// # Returns
+    /// The width, height and interleaved RGB samples of the image.
+    pub fn decode_jxl_oxide_rgb8(
+        sample: &[u8],
+    ) -> Result<(u32, u32, Vec<u8>), Box<dyn std::error::Error>> {
+        let image = JxlImage::builder().read(std::io::Cursor::new(sample))?;
+        let render = image.render_frame(0)?;
+        let framebuffer = render.image_all_channels();
+        let channels = framebuffer.channels();
+        let pixels = framebuffer
+            .buf()
+            .chunks(channels)
+            .flat_map(|pixel| {
+                let rgb = match channels {
+                    1 | 2 => [pixel[0]; 3],
+                    _ => [pixel[0], pixel[1], pixel[2]],
+                };
+                rgb.map(|value| (value.clamp(0.0, 1.0) * 255.0).round() as u8)
+            })
+            .collect();
+        Ok((
+            framebuffer.width() as u32,
+            framebuffer.height() as u32,
+            pixels,
+        ))
+    }
+
+    /// Decodes a JXL image with both the jpegxl_rs decoder and the jxl-oxide decoder, to catch
+    /// libjxl FFI or version issues that would otherwise silently corrupt the metrics.
+    ///
+    /// # Arguments
+    /// * `sample` - The bytes of the JXL file.
+    ///
+    /// # Returns
+    /// Whether the decoders disagree on the dimensions, or on any 8-bit RGB sample by more than
+    /// `DECODE_CROSS_CHECK_TOLERANCE`. An error if either decoder fails.
+    pub fn decoders_disagree(sample: &[u8]) -> Result<bool, Box<dyn std::error::Error>> {
+        let (width, height, pixels) = ImageReader::decode_jxl_rgb8(sample)?;
+        let (oxide_width, oxide_height, oxide_pixels) = ImageReader::decode_jxl_oxide_rgb8(sample)?;
+        if (width, height) != (oxide_width, oxide_height) {
+            return Ok(true);
+        }
+        Ok(pixels
+            .iter()
+            .zip(oxide_pixels.iter())
+            .any(|(a, b)| a.abs_diff(*b) > DECODE_CROSS_CHECK_TOLERANCE))
+    }
+
+    /// Crops an image to a region of interest and writes it as an 8-bit RGB PNG.
+    /// JXL files are decoded first, so the result can be compared with any metric.
+    ///
+    /// # Arguments
+    /// * `file_path` - The path to the image file.
+    /// * `roi` - The region of interest to crop to.
+    /// * `out_path` - The path to write the cropped PNG to.
+    ///
+    /// # Returns
+    /// The path to the cropped image, or an error if the region does not fit within the image.
+    pub fn crop_to_png(
+        file_path: &str,
+        roi: &Roi,
+        out_path: &str,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        let (width, height, pixels) = ImageReader::decode_rgb8(file_path)?;
+        roi.validate(width, height)?;
+
+        let image = image::RgbImage::from_raw(width, height, pixels)
+            .ok_or("Decoded pixel buffer does not match the image dimensions")?;
+        let cropped = image::imageops::crop_imm(&image, roi.x, roi.y, roi.width, roi.height).to_image();
+
+        if let Some(parent) = Path::new(out_path).parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        cropped.save(out_path)?;
+
+        Ok(out_path.to_string())
+    }
+
+    /// Writes a heatmap PNG of the per-pixel absolute difference between two images.
+    /// The largest channel difference of each pixel is scaled so that the largest difference in
+    /// the image is white, and mapped from black over red and yellow to white.
+    ///
+    /// # Arguments
+    /// * `orig_pixels` - The interleaved RGB samples of the original image.
+    /// * `comp_pixels` - The interleaved RGB samples of the compressed image.
+    /// * `width` - The width of both images.
+    /// * `height` - The height of both images.
+    /// * `out_path` - The path to write the heatmap PNG to.
+    ///
+    /// # Returns
+    /// The path to the heatmap, or an error if the buffers do not match the dimensions.
+    pub fn write_diff_image(
+        orig_pixels: &[u8],
+        comp_pixels: &[u8],
+        width: u32,
+        height: u32,
+        out_path: &str,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        let num_samples = width as usize * height as usize * 3;
+        if orig_pixels.len() != num_samples || comp_pixels.len() != num_samples {
+            return Err(Box::from("Pixel buffers do not match the image dimensions"));
+        }
+
+        // The largest absolute channel difference of each pixel.
+        let diffs = orig_pixels
+            .chunks_exact(3)
+            .zip(comp_pixels.chunks_exact(3))
+            .map(|(orig, comp)| {
+                (0..3)
+                    .map(|channel| orig[channel].abs_diff(comp[channel]))
+                    .max()
+                    .unwrap()
+            })
+            .collect::<Vec<u8>>();
+
+        // Scale the differences for visibility, small differences would otherwise be black.
+        let max_diff = diffs.iter().copied().max().unwrap_or(0).max(1) as u32;
+        let heatmap = diffs
+            .iter()
+            .flat_map(|diff| {
+                let heat = *diff as u32 * 765 / max_diff;
+                [
+                    heat.min(255) as u8,
+                    heat.saturating_sub(255).min(255) as u8,
+                    heat.saturating_sub(510).min(255) as u8,
+                ]
+            })
+            .collect::<Vec<u8>>();
+
+        let image = image::RgbImage::from_raw(width, height, heatmap)
+            .ok_or("Heatmap buffer does not match the image dimensions")?;
+        if let Some(parent) = Path::new(out_path).parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        image.save(out_path)?;
+
+        Ok(out_path.to_string())
+    }
+
+    /// Creates the file name of a compressed image.
+    /// The distance and effort are encoded as `{name}__d{distance}__e{effort}.jxl` so that they
+    /// can be recovered even if the image name contains hyphens or underscores.
+    ///
+    /// # Arguments
+    /// * `image_name` - The name of the original image, without extension.
+    /// * `distance` - The distance the image was compressed with.
+    /// * `effort` - The effort the image was compressed with.
+    ///
+    /// # Returns
+    /// The file name of the compressed image.
+    pub fn compressed_image_name(image_name: &str, distance: f64, effort: u32) -> String {
+        format!(
+            "{}__d{}__e{}.{}",
+            image_name,
+            distance,
+            effort,
+            ImageFormat::JpegXl.to_string()
+        )
+    }
+
+    /// Parses the file name of a compressed image created by `compressed_image_name`.
+    /// File names using the older `{name}-{distance}-{effort}.jxl` scheme are also accepted.
+    /// The effort may be omitted in both schemes, e.g. in externally produced or lossless
+    /// encodes named `{name}__d{distance}.jxl` or `{name}-{distance}.jxl`.
+    ///
+    /// # Arguments
+    /// * `file_name` - The file name of the compressed image.
+    ///
+    /// # Returns
+    /// The original image name, distance and effort. The distance and effort are empty if they
+    /// could not be parsed.
+    pub fn parse_compressed_image_name(file_name: &str) -> (String, JXLf32, JXLu32) {
+        let stem = Path::new(file_name)
+            .file_stem()
+            .unwrap_or(std::ffi::OsStr::new(""))
+            .to_str()
+            .unwrap();
+
+        // Split off the last two "__" separated tokens, which hold the distance and effort.
+        let parts: Vec<&str> = stem.rsplitn(3, "__").collect();
+        if parts.len() == 3 && parts[1].starts_with('d') && parts[0].starts_with('e') {
+            return (
+                parts[2].to_string(),
+                JXLf32::new(ImageReader::parse_distance_token(&parts[1][1..])),
+                JXLu32::new(ImageReader::parse_effort_token(&parts[0][1..])),
+            );
+        }
+
+        // Without an effort token, the last token holds the distance.
+        if let Some((name, distance)) = stem.rsplit_once("__") {
+            if let Some(distance) = distance
+                .strip_prefix('d')
+                .and_then(ImageReader::parse_distance_token)
+            {
+                return (
+                    name.to_string(),
+                    JXLf32::new(Some(distance)),
+                    JXLu32::new(None),
+                );
+            }
+        }
+
+        // Fall back to the older hyphen separated scheme.
+        // If the last token is not a valid effort, the effort was omitted and the last token is
+        // the distance instead.
+        let parts: Vec<&str> = stem.split("-").collect();
+        if parts.len() >= 3 {
+            let distance = ImageReader::parse_distance_token(parts[parts.len() - 2]);
+            let effort = ImageReader::parse_effort_token(parts[parts.len() - 1]);
+            if distance.is_some() && effort.is_some() {
+                return (
+                    parts[0..parts.len() - 2].join("-"),
+                    JXLf32::new(distance),
+                    JXLu32::new(effort),
+                );
+            }
+        }
+        if parts.len() >= 2 {
+            let distance = ImageReader::parse_distance_token(parts[parts.len() - 1]);
+            if distance.is_some() {
+                return (
+                    parts[0..parts.len() - 1].join("-"),
+                    JXLf32::new(distance),
+                    JXLu32::new(None),
+                );
+            }
+        }
+        (stem.to_string(), JXLf32::new(None), JXLu32::new(None))
+    }
+
+    /// Parses a distance token of a compressed image name.
+    ///
+    /// # Returns
+    /// The distance, or None if the token is not a distance cjxl accepts.
+    fn parse_distance_token(token: &str) -> Option<f32> {
+        token.parse::<f32>().ok().filter(|distance| {
+            (QualityTarget::MIN_DISTANCE..=QualityTarget::MAX_DISTANCE)
+                .contains(&(*distance as f64))
+        })
+    }
+
+    /// Parses an effort token of a compressed image name.
+    ///
+    /// # Returns
+    /// The effort, or None if the token is not a positive integer.
+    fn parse_effort_token(token: &str) -> Option<u32> {
+        token.parse::<u32>().ok().filter(|effort| *effort > 0)
+    }
+
+    /// Returns whether an image file is stored in a lossy format.
+    /// JPEG is always lossy. WebP is lossy unless its bitstream is a lossless VP8L chunk,
+    /// which is read from the RIFF header since the extension does not tell them apart.
+    ///
+    /// # Arguments
+    /// * `file_path` - The path to the image file.
+    ///
+    /// # Returns
+    /// Whether the image file is lossy.
+    pub fn is_lossy_source(file_path: &String) -> bool {
+        match ImageReader::get_format(file_path) {
+            ImageFormat::Jpeg => true,
+            ImageFormat::WebP => {
+                let mut header = [0u8; 16];
+                match std::fs::File::open(file_path)
+                    .and_then(|mut file| std::io::Read::read_exact(&mut file, &mut header))
+                {
+                    Ok(()) => &header[12..16] != b"VP8L",
+                    Err(_) => true,
+                }
+            }
+            _ => false,
+        }
+    }
+
     /// Gets the image format from a file name.
     ///
     /// # Arguments
@@ -453,7 +1325,7 @@ impl ImageReader {
             "png" => ImageFormat::Png,
             "gif" => ImageFormat::Gif,
             "webp" => ImageFormat::WebP,
-            "ppm" => ImageFormat::Pnm,
+            "ppm" | "pgm" | "pbm" | "pnm" | "pam" => ImageFormat::Pnm,
             "tiff" => ImageFormat::Tiff,
             "tga" => ImageFormat::Tga,
             "dds" => ImageFormat::Dds,
@@ -481,47 +1353,65 @@ impl ImageReader {
         size as usize
     }
 
-    /// Gets the raw size of a JXL file.
+    /// Calculates the raw size of an image from its dimensions and bytes per pixel.
+    /// This is done in u64, since the product overflows u32 for large images, e.g. 30000x30000.
+    ///
+    /// # Arguments
+    /// * `width` - The width of the image in pixels.
+    /// * `height` - The height of the image in pixels.
+    /// * `bytes_per_pixel` - The number of bytes of a pixel in the color space.
+    ///
+    /// # Returns
+    /// The size of the raw image in bytes, or an error if it overflows.
+    pub fn raw_size(
+        width: u32,
+        height: u32,
+        bytes_per_pixel: u32,
+    ) -> Result<u64, Box<dyn std::error::Error>> {
+        (width as u64)
+            .checked_mul(height as u64)
+            .and_then(|pixels| pixels.checked_mul(bytes_per_pixel as u64))
+            .ok_or_else(|| {
+                Box::from(format!(
+                    "The raw size of a {}x{} image with {} bytes per pixel overflows",
+                    width, height, bytes_per_pixel
+                ))
+            })
+    }
+
+    /// Converts a raw size to a usize, returning an error if it does not fit on this platform.
+    fn raw_size_usize(size: u64) -> Result<usize, Box<dyn std::error::Error>> {
+        usize::try_from(size)
+            .map_err(|_| Box::from(format!("The raw size {} does not fit in a usize", size)))
+    }
+
+    /// Gets the raw size of a decoded JXL image.
     /// This is done using the image height, width, and bit depth depending on the color space.
     ///
     /// # Arguments
-    /// * `file_path` - The path to the JXL file.
+    /// * `metadata` - The metadata of the decoded image.
+    /// * `pixels` - The decoded pixels of the image.
     ///
     /// # Returns
-    /// The size of the raw image in bytes as a usize.
-    fn get_raw_jxl_size(file_path: &String) -> usize {
-        let sample = std::fs::read(file_path.clone()).unwrap();
-        let decoder: JxlDecoder = decoder_builder().build().unwrap();
-        let (metadata, pixels) = decoder.decode(&sample).unwrap();
-        let width = metadata.width;
-        let height = metadata.height;
-        let bytes_per_pixel = match ColorType::get_jxl_color_space(&metadata, &pixels) {
-            ColorType::L8 => 1,
-            ColorType::La8 => 2,
-            ColorType::Rgb8 => 3,
-            ColorType::Rgba8 => 4,
-            ColorType::L16 => 2,
-            ColorType::La16 => 4,
-            ColorType::Rgb16 => 6,
-            ColorType::Rgba16 => 8,
-            ColorType::Rgb32F => 12,
-            ColorType::Rgba32F => 16,
-        };
-        let size = width * height * bytes_per_pixel;
-        size as usize
+    /// The size of the raw image in bytes as a usize, or an error if it overflows.
+    fn raw_jxl_size(
+        metadata: &Metadata,
+        pixels: &Pixels,
+    ) -> Result<usize, Box<dyn std::error::Error>> {
+        let bytes_per_pixel = ColorType::get_jxl_color_space(&metadata, &pixels).bytes_per_pixel();
+        let size = ImageReader::raw_size(metadata.width, metadata.height, bytes_per_pixel)?;
+        ImageReader::raw_size_usize(size)
     }
 
-    /// Gets the raw size of an image file.
-    /// Does not support JXL files, use get_raw_jxl_size instead.
+    /// Gets the raw size of an image decoded with the image crate.
     /// This is done using the image height, width, and bit depth depending on the color space.
     ///
     /// # Arguments
-    /// * `file_path` - The path to the image file.
+    /// * `image` - The decoded image.
     ///
     /// # Returns
-    /// The size of the raw image in bytes as a usize.
-    fn get_raw_size(file_path: &String) -> usize {
-        let image = image::open(&file_path).unwrap();
+    /// The size of the raw image in bytes as a usize, or an error if it overflows.
+    fn raw_image_size(image: &DynamicImage) -> Result<usize, Box<dyn std::error::Error>> {
         let color_space = image.color();
         let width = image.width();
         let height = image.height();
@@ -536,10 +1426,10 @@ impl ImageReader {
             image::ColorType::Rgba16 => 8,
             image::ColorType::Rgb32F => 12,
             image::ColorType::Rgba32F => 16,
-            _ => panic!("Unsupported color space"),
+            color_space => color_space.bytes_per_pixel() as u32,
         };
-        let size = width * height * bytes_per_pixel;
-        size as usize
+        let size = ImageReader::raw_size(width, height, bytes_per_pixel)?;
+        ImageReader::raw_size_usize(size)
     }
 
     /// Calculates the mean squared error between two images.
@@ -553,47 +1443,90 @@ impl ImageReader {
     /// # Returns
     /// The mean squared error between the two images as a f64.
     pub fn calculate_mse(orig_image_path: &String, comp_image_path: &String) -> f64 {
+        ImageReader::calculate_mse_with_peak(orig_image_path, comp_image_path).0
+    }
+
+    /// Calculates the mean squared error between two images on a scale shared by both images,
+    /// along with the PSNR peak of that scale.
+    /// Integer images are compared as 8-bit samples with a peak of `UINT8_PEAK`. Float images
+    /// are compared as samples normalized to the 0..1 range with a peak of `NORMALIZED_PEAK`,
+    /// where HDR samples may exceed 1.0, and the original is normalized to the same range.
+    ///
+    /// # Arguments
+    /// * `orig_image_path` - The path to the original image.
+    /// * `comp_image_path` - The path to the compressed image.
+    ///
+    /// # Returns
+    /// The mean squared error between the two images and the peak value of its scale.
+    pub fn calculate_mse_with_peak(
+        orig_image_path: &String,
+        comp_image_path: &String,
+    ) -> (f64, f64) {
         // Read the original and compressed images, assume the compressed image is a JXL image.
         let orig_image = image::open(orig_image_path).unwrap();
         let decoder: JxlDecoder = decoder_builder().build().unwrap();
         let comp_image = std::fs::read(comp_image_path.clone()).unwrap();
         let (comp_metadata, comp_pixels) = decoder.decode(&comp_image).unwrap();
+
+        // Float samples cannot be compared against 0..255 integer samples, so float images are
+        // decoded as normalized RGB samples and compared against the normalized original.
+        if let Pixels::Float(_) | Pixels::Float16(_) = comp_pixels {
+            let decoder: JxlDecoder = decoder_builder()
+                .pixel_format(PixelFormat {
+                    num_channels: 3,
+                    ..PixelFormat::default()
+                })
+                .build()
+                .unwrap();
+            let (_, comp_samples) = decoder.decode_with::<f32>(&comp_image).unwrap();
+            let orig_image = orig_image.to_rgb32f();
+            let sum = squared_error_sum(
+                orig_image.as_raw().iter().map(|sample| *sample as f64),
+                comp_samples.iter().map(|sample| *sample as f64),
+            );
+            return (
+                sum / orig_image.as_raw().len() as f64,
+                ImageReader::NORMALIZED_PEAK,
+            );
+        }
+
         let orig_image = match ColorType::get_jxl_color_space(&comp_metadata, &comp_pixels) {
             ColorType::Rgb8 => orig_image.to_rgb8(),
+            color_space if !color_space.native_metrics_supported() => {
+                warn!(
+                    "skipping the MSE of {}, {} images are not supported",
+                    comp_image_path,
+                    color_space.full_name()
+                );
+                return (f64::NAN, ImageReader::UINT8_PEAK);
+            }
             _ => todo!(),
         };
         let orig_image = orig_image.as_flat_samples();
 
         // Calculate the mean squared error between the original and compressed images.
-        // The images are compared pixel by pixel. Match the correct pixel type.
-        let mut mse = 0.0;
-        match comp_pixels {
-            Pixels::Uint8(comp_pixels) => {
-                for i in 0..orig_image.samples.len() {
-                    mse += (orig_image.samples[i] as f64 - comp_pixels[i] as f64).powi(2);
-                }
-            }
-            Pixels::Uint16(comp_pixels) => {
-                for i in 0..orig_image.samples.len() {
-                    mse += (orig_image.samples[i] as f64 - comp_pixels[i] as f64).powi(2);
-                }
-            }
-            Pixels::Float(comp_pixels) => {
-                for i in 0..orig_image.samples.len() {
-                    mse += (orig_image.samples[i] as f64 - comp_pixels[i] as f64).powi(2);
-                }
-            }
-            Pixels::Float16(comp_pixels) => {
-                for i in 0..orig_image.samples.len() {
-                    mse += (orig_image.samples[i] as f64 - f64::from(comp_pixels[i])).powi(2);
-                }
-            }
-        }
-        mse /= orig_image.samples.len() as f64;
-        mse
+        // The images are compared sample by sample. Match the correct pixel type.
+        let orig_samples = orig_image.samples.iter().map(|sample| *sample as f64);
+        let sum = match &comp_pixels {
+            Pixels::Uint8(comp_pixels) => squared_error_sum(
+                orig_samples,
+                comp_pixels.iter().map(|sample| *sample as f64),
+            ),
+            Pixels::Uint16(comp_pixels) => squared_error_sum(
+                orig_samples,
+                comp_pixels.iter().map(|sample| *sample as f64),
+            ),
+            Pixels::Float(_) | Pixels::Float16(_) => unreachable!(),
+        };
+        (
+            sum / orig_image.samples.len() as f64,
+            ImageReader::UINT8_PEAK,
+        )
     }
 
     /// Calculates the peak signal-to-noise ratio between two images.
+    /// Identical images (mse == 0) have an infinite PSNR, which is recorded as `LOSSLESS_PSNR`
+    /// instead so that it can be averaged.
     ///
     /// # Arguments
     /// * `mse` - The mean squared error between the two images.
@@ -602,6 +1535,9 @@ impl ImageReader {
     /// # Returns
     /// The peak signal-to-noise ratio between the two images as a f64.
     pub fn calculate_psnr(mse: f64, max_value: f64) -> f64 {
+        if mse == 0.0 {
+            return ImageReader::LOSSLESS_PSNR;
+        }
         10.0 * ((max_value * max_value) / mse).log10()
     }
 }
@@ -615,20 +1551,40 @@ impl Serialize for ColorType {
     }
 }
 
-impl From<String> for ColorType {
-    fn from(color_type: String) -> Self {
-        match color_type.as_str() {
-            "L8" => ColorType::L8,
-            "La8" => ColorType::La8,
-            "Rgb8" => ColorType::Rgb8,
-            "Rgba8" => ColorType::Rgba8,
-            "L16" => ColorType::L16,
-            "La16" => ColorType::La16,
-            "Rgb16" => ColorType::Rgb16,
-            "Rgba16" => ColorType::Rgba16,
-            "Rgb32F" => ColorType::Rgb32F,
-            "Rgba32F" => ColorType::Rgba32F,
-            _ => todo!(),
+impl<'de> Deserialize<'de> for ColorType {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        ColorType::try_from(String::deserialize(deserializer)?.as_str()).map_err(de::Error::custom)
+    }
+}
+
+impl TryFrom<&str> for ColorType {
+    type Error = String;
+
+    fn try_from(color_type: &str) -> Result<Self, Self::Error> {
+        match color_type {
+            "L8" => Ok(ColorType::L8),
+            "La8" => Ok(ColorType::La8),
+            "Rgb8" => Ok(ColorType::Rgb8),
+            "Rgba8" => Ok(ColorType::Rgba8),
+            "L16" => Ok(ColorType::L16),
+            "La16" => Ok(ColorType::La16),
+            "Rgb16" => Ok(ColorType::Rgb16),
+            "Rgba16" => Ok(ColorType::Rgba16),
+            "Rgb32F" => Ok(ColorType::Rgb32F),
+            "Rgba32F" => Ok(ColorType::Rgba32F),
+            multi_channel if multi_channel.starts_with("Multi") => multi_channel["Multi".len()..]
+                .split_once('x')
+                .and_then(|(channels, bit_depth)| {
+                    Some(ColorType::MultiChannel {
+                        channels: channels.parse().ok()?,
+                        bit_depth: bit_depth.parse().ok()?,
+                    })
+                })
+                .ok_or_else(|| format!("invalid color type: {}", multi_channel)),
+            _ => Err(format!("unknown color type: {}", color_type)),
         }
     }
 }
@@ -646,7 +1602,13 @@ impl From<image::ColorType> for ColorType {
             image::ColorType::Rgba16 => ColorType::Rgba16,
             image::ColorType::Rgb32F => ColorType::Rgb32F,
             image::ColorType::Rgba32F => ColorType::Rgba32F,
-            _ => todo!(),
+            // Other color types are converted to 8-bit by `ImageReader::normalize_color`.
+            _ => match (color_type.has_color(), color_type.has_alpha()) {
+                (false, false) => ColorType::L8,
+                (false, true) => ColorType::La8,
+                (true, false) => ColorType::Rgb8,
+                (true, true) => ColorType::Rgba8,
+            },
         }
     }
 }
@@ -660,6 +1622,15 @@ impl Serialize for ImageFormat {
     }
 }
 
+impl<'de> Deserialize<'de> for ImageFormat {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Ok(String::deserialize(deserializer)?.into())
+    }
+}
+
 impl From<image::ImageFormat> for ImageFormat {
     fn from(image_format: image::ImageFormat) -> Self {
         match image_format {
@@ -688,10 +1659,10 @@ impl From<String> for ImageFormat {
         match image_format.as_str() {
             "jxl" => ImageFormat::JpegXl,
             "png" => ImageFormat::Png,
-            "jpeg" => ImageFormat::Jpeg,
+            "jpeg" | "jpg" => ImageFormat::Jpeg,
             "gif" => ImageFormat::Gif,
             "webp" => ImageFormat::WebP,
-            "ppm" => ImageFormat::Pnm,
+            "ppm" | "pgm" | "pbm" | "pnm" | "pam" => ImageFormat::Pnm,
             "tiff" => ImageFormat::Tiff,
             "tga" => ImageFormat::Tga,
             "dds" => ImageFormat::Dds,
@@ -737,13 +1708,23 @@ impl Serialize for JXLf32 {
     where
         S: Serializer,
     {
+        // Written as displayed, e.g. 1 instead of 1.0, like the distances in file names.
         match &self.0 {
-            Some(value) => serializer.serialize_f32(*value),
+            Some(value) => serializer.collect_str(value),
             None => serializer.serialize_str(""),
         }
     }
 }
 
+impl<'de> Deserialize<'de> for JXLf32 {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Ok(String::deserialize(deserializer)?.into())
+    }
+}
+
 impl Display for JXLf32 {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         match &self.0 {
@@ -799,6 +1780,15 @@ impl Serialize for JXLu32 {
     }
 }
 
+impl<'de> Deserialize<'de> for JXLu32 {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Ok(String::deserialize(deserializer)?.into())
+    }
+}
+
 impl Display for JXLu32 {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         match &self.0 {
@@ -839,6 +1829,15 @@ impl Serialize for JXLString {
     }
 }
 
+impl<'de> Deserialize<'de> for JXLString {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Ok(String::deserialize(deserializer)?.into())
+    }
+}
+
 impl Display for JXLString {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         match &self.0 {
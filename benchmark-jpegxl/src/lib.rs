@@ -1,8 +1,29 @@
 pub mod benchmark;
+pub mod binary_results;
+pub mod cache;
+pub mod cancellation;
+pub mod color;
 pub mod config;
+pub mod contact_sheet;
 pub mod context;
 pub mod csv_writer;
+pub mod datasets;
+pub mod decode_limiter;
 pub mod docker_manager;
+pub mod duplicates;
+pub mod failures;
+pub mod image_limits;
 pub mod image_reader;
+pub mod logging;
+pub mod manifest;
 pub mod metrics;
+pub mod object_store;
+pub mod reference_cache;
+pub mod report;
+pub mod resource_monitor;
+pub mod results_db;
+pub mod run_timing;
+pub mod sampling;
+pub mod smoke;
 pub mod utils;
+pub mod verify;
@@ -0,0 +1,125 @@
+use crate::config::LogLevel;
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+use std::cell::RefCell;
+use std::error::Error;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::sync::Mutex;
+
+/// The logger of the process, which writes to stderr or to the log file set with
+/// `set_log_sink`.
+struct Logger;
+
+static LOGGER: Logger = Logger;
+
+/// The file messages are appended to instead of stderr, set once per run.
+static LOG_FILE: Mutex<Option<File>> = Mutex::new(None);
+
+thread_local! {
+    /// The worker and image the messages of the current thread are about.
+    static WORKER_CONTEXT: RefCell<Option<String>> = RefCell::new(None);
+}
+
+impl From<LogLevel> for LevelFilter {
+    fn from(level: LogLevel) -> Self {
+        match level {
+            LogLevel::Quiet => LevelFilter::Warn,
+            LogLevel::Normal => LevelFilter::Info,
+            LogLevel::Verbose => LevelFilter::Debug,
+        }
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let line = WORKER_CONTEXT.with(|context| {
+            format_line(
+                record.level(),
+                &record.args().to_string(),
+                context.borrow().as_deref(),
+            )
+        });
+        // A message that cannot be written is dropped rather than failing the run.
+        match LOG_FILE.lock().unwrap().as_mut() {
+            Some(file) => {
+                let _ = writeln!(file, "{}", line);
+            }
+            None => eprintln!("{}", line),
+        }
+    }
+
+    fn flush(&self) {
+        if let Some(file) = LOG_FILE.lock().unwrap().as_mut() {
+            let _ = file.flush();
+        }
+    }
+}
+
+/// Sets which messages are logged and where they are written.
+/// Can be called again to change the level or sink, e.g. between runs.
+///
+/// # Arguments
+/// * `level` - Which messages are logged.
+/// * `log_file` - The file messages are appended to, or `None` for stderr.
+///
+/// # Returns
+/// An error if the log file could not be opened.
+pub fn set_log_sink(level: LogLevel, log_file: Option<&str>) -> Result<(), Box<dyn Error>> {
+    let file = match log_file {
+        Some(log_file) => Some(
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(log_file)?,
+        ),
+        None => None,
+    };
+    *LOG_FILE.lock().unwrap() = file;
+    // The logger is only installed once, later calls only change the level and sink.
+    let _ = log::set_logger(&LOGGER);
+    log::set_max_level(level.into());
+    Ok(())
+}
+
+/// Prefixes the messages logged on the current thread with a worker and the image it is
+/// benchmarking.
+///
+/// # Arguments
+/// * `worker_id` - The id of the worker.
+/// * `image_name` - The name of the image the worker is benchmarking.
+pub fn set_worker_context(worker_id: usize, image_name: &str) {
+    WORKER_CONTEXT.with(|context| {
+        *context.borrow_mut() = Some(format!("worker {} {}", worker_id, image_name));
+    });
+}
+
+/// Formats a logged message, e.g. `[worker 2 kodim06] Warning: ...`.
+///
+/// # Arguments
+/// * `level` - The level of the message.
+/// * `message` - The message.
+/// * `context` - The worker and image the message is about, if any.
+///
+/// # Returns
+/// The line written to the log.
+pub fn format_line(level: Level, message: &str, context: Option<&str>) -> String {
+    let prefix = match level {
+        Level::Error => "Error: ",
+        Level::Warn => "Warning: ",
+        Level::Info => "",
+        Level::Debug | Level::Trace => "Debug: ",
+    };
+    match context {
+        Some(context) => format!("[{}] {}{}", context, prefix, message),
+        None => format!("{}{}", prefix, message),
+    }
+}
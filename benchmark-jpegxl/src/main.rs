@@ -1,11 +1,22 @@
 use clap::Parser;
-use clap_derive::Parser;
+use clap_derive::{Parser, Subcommand};
+use log::{error, info};
 use std::fs;
 
 use benchmark_jpegxl::benchmark::{
-    Benchmarker, /*CollectImageMetadataBenchmark,*/ JXLCompressionBenchmark,
+    BenchmarkError, Benchmarker, /*CollectImageMetadataBenchmark,*/ JXLCompressionBenchmark,
 };
-use benchmark_jpegxl::config::Config;
+use benchmark_jpegxl::config::{
+    BuildConfig, Config, DuplicatePolicy, ExpensiveMetricSample, ExternalMetric, Gate, GridSample,
+    LocalDiff, LogLevel, MetricValidation, OutputFormat, PrebuiltCjxl, QualityTarget, Roi,
+    ScoreWeight, SizeRegressionPolicy, SizeTarget, SummaryWeighting, Toolchain,
+};
+use benchmark_jpegxl::csv_writer::{set_csv_format, CsvQuoting};
+use benchmark_jpegxl::image_reader::jxl_decode_available;
+use benchmark_jpegxl::logging::set_log_sink;
+use benchmark_jpegxl::metrics::{list_metrics, ChannelSpace, MetricKind, SizeRatio};
+use benchmark_jpegxl::smoke::SmokeTest;
+use benchmark_jpegxl::verify::RunVerifier;
 
 /// Arguments
 /// `--clean, -c` - Clean all benchmark files
@@ -13,9 +24,103 @@ use benchmark_jpegxl::config::Config;
 /// `--libjxl_commit` - Use specific lbjxl commit or branch
 /// `--compare_to_local` - Compare to local libjxl source
 /// `--compare_to_commit` - Compare to specific libjxl commit or branch
+/// `--compare-to-previous` - Benchmark only the local libjxl source and diff it against the latest run
+/// `--max-dimension` - Downscale source images whose longest side exceeds this many pixels
+/// `--modular` - Force the cjxl Modular coding mode
+/// `--vardct` - Force the cjxl VarDCT coding mode
+/// `--size-regression` - How to handle encodes larger than the original: record, warn or skip
+/// `--validate-metrics` - Warn about metrics outside of their plausible range (e.g. an SSIM above 1), which usually indicate a parsing bug or mismatched inputs
+/// `--strict` - Fail encodes with metrics outside of their plausible range, implies `--validate-metrics`
+/// `--encode-cache` - Reuse identical encodes from prior runs of the same libjxl commit
+/// `--resource-metrics` - Sample peak memory and CPU time of each encode
+/// `--tidy` - Also write comparisons in long format (one row per metric value) for plotting
+/// `--scores` - Also write each metric on a common 0 to 1 scale where higher is better (scores.csv)
+/// `--score-weight` - Weigh a metric in the composite score of each encode as `metric:weight`, can be repeated (e.g. `--score-weight=ssimulacra2:2`), implies `--scores`
+/// `--output-format` - The format of the comparison results: csv (default), binary (compact, for very large sweeps) or sqlite (csv, also recorded in results.sqlite in the benchmark directory for cross-run queries)
+/// `--roi` - Compute metrics only over the region `x,y,w,h` of each image
+/// `--discard-compressed` - Compare compressed images in memory without writing them to disk
+/// `--content-addressed` - Store each distinct compressed image once as objects/{sha256}.jxl, linked from its usual path
+/// `--keep-originals` - Copy each source image into the orig output directory, so the run does not depend on the test image directory
+/// `--docker-build-arg` - Pass a `KEY=VALUE` build argument to `docker build`, can be repeated (e.g. `--docker-build-arg=BASE_IMAGE=arm64v8/ubuntu:latest`)
+/// `--docker-context` - The directory the docker image is built in (default `.`)
+/// `--docker-bin` - The docker binary to run the docker commands with (default `docker`, e.g. `podman`)
+/// `--toolchain` - Run the sweep with a toolchain built from its own Dockerfile as `name=dockerfile` (or `dockerfile`, labelled by its extension), can be repeated to compare toolchains
+/// `--build-config` - Run the sweep with libjxl built with a configuration as `name` or `name:arg` of a `-D` CMake flag or a `KEY=VALUE` environment variable (e.g. `no-avx2:-DCMAKE_CXX_FLAGS=-DHWY_DISABLED_TARGETS=HWY_AVX2`), can be repeated to add arguments to a configuration or to compare build configurations
+/// `--cjxl-arg` - Pass an extra argument to cjxl, can be repeated (e.g. `--cjxl-arg=--photon_noise=3`)
+/// `--libjxl-src` - Mount and build a local libjxl source tree instead of checking out a commit
+/// `--libjxl-version` - Benchmark the prebuilt cjxl of a libjxl release (e.g. `v0.10.2`) instead of checking out and building a commit
+/// `--prebuilt-dir` - Use the cjxl and djxl in this directory for `--libjxl-version` instead of downloading the release
+/// `--deterministic` - Process images in sorted order on fixed workers for reproducible logs
+/// `--target-metric` - Search the distance reaching `--target-value` of this metric per image
+/// `--target-value` - The metric value to search for
+/// `--target-tolerance` - How close to the target value the search must get
+/// `--target-max-iterations` - The maximum number of encodes per image in the search
+/// `--target-effort` - The effort used for the search
+/// `--target-size` - Search the distance reaching this file size in bytes per image instead
+/// `--target-size-tolerance` - The fraction of the target size the search must get within
+/// `--baseline-csv` - Also diff each test set against a comparisons CSV of another codec
+/// `--baseline-codec` - The codec of the baseline CSV, e.g. jpeg or avif
+/// `--intensity-target` - The peak luminance in nits for SSIMULACRA2 and Butteraugli on HDR images
+/// `--diff-images` - Write a heatmap PNG of the per-pixel differences of each compressed image
+/// `--fail-fast` - Stop the run with a nonzero exit code on the first failure
+/// `--error-histograms` - Write a histogram of the per-sample absolute errors of each compressed image
+/// `--psnr-channels` - The channels of the per-channel PSNR: rgb, bt601 or bt709 (YCbCr)
+/// `--color-manage` - Convert the compressed image to the color space of the original with their embedded ICC profiles before the pixel metrics
+/// `--size-ratio` - The direction of the size ratios: compression (orig/comp, default) or relative-size (comp/orig)
+/// `--image` - Only benchmark the images with these names, comma separated, with or without their extension (e.g. `--image=kodim06,kodim23.png`)
+/// `--effort` - The efforts to sweep, comma separated (default 5 to 9), e.g. `--effort=7,10`
+/// `--distance` - The distances to sweep, comma separated, e.g. `--distance=0.5,1,2`
+/// `--quality` - Sweep cjxl qualities (0 to 100) instead of distances, e.g. `--quality=90,75,50`
+/// `--reuse-containers` - Attach to the worker containers of a prior run and leave them running
+/// `--external-metric` - Record a custom metric tool as `name,binary,pattern`, where the pattern
+///   is a regex capturing the value, can be repeated
+///   (e.g. `--external-metric="vmaf,docker:/usr/local/bin/vmaf,VMAF score: (\S+)"`)
+/// `--contact-sheet` - Write a PNG grid of each original and its compressed images with captions
+/// `--html-report` - Write a self-contained report.html with the tables and rate-distortion charts
+/// `--decode-speed` - Benchmark the decode speed of each compressed image with djxl
+/// `--decode-repeats` - The number of timed djxl decodes of each compressed image (default 5)
+/// `--decode-warmup` - The number of djxl decodes run before the timed decodes (default 1)
+/// `--progressive` - Decode these percentages of each compressed bitstream with djxl and write their quality to a progressive.csv, e.g. `--progressive=10,25,50,100`
+/// `--max-concurrent-decodes` - Decode at most this many images at once across workers (0 = no limit)
+/// `--max-pixels` - Skip source images with more pixels than this before decoding them (0 = no limit)
+/// `--max-decode-bytes` - Skip source images whose decode would allocate more bytes than this (0 = no limit)
+/// `--metric-threads` - Compute the native metrics of each image on this many threads at once (default 1)
+/// `--diff-a` - Benchmark a local libjxl diff as `name=path` (or `path`, labelled by its file stem)
+/// `--diff-b` - A second local diff, compared against `--diff-a` like two commits
+/// `--ssim-window-size` - The size of the native SSIM and MS-SSIM window (default 11, odd if Gaussian),
+///   the SSIM column of ImageMagick only uses it with `--color-manage`
+/// `--ssim-sigma` - The standard deviation of the Gaussian SSIM window (default 1.5)
+/// `--ssim-uniform` - Use a uniform SSIM window instead of a Gaussian, e.g. with `--ssim-window-size=8`
+/// `--csv-digits` - The significant digits of floating point values in CSV files (default 6, 0 = full)
+/// `--csv-delimiter` - The delimiter between CSV fields, a single character or `tab` (default `,`)
+/// `--csv-quoting` - How CSV fields are quoted: necessary, always or non-numeric
+/// `--summary-weighting` - Weigh images in averages and summaries: equal, pixels (width x height) or bytes (original file size)
+/// `--duplicates` - Look for images with the same content in each test set: ignore, report (warn and record in the manifest) or skip (also benchmark only the first)
+/// `--record-env` - Record the command line, host and docker and cjxl versions in the run manifest
+/// `--effort-curve` - With a quality target, search it at every effort of `--effort` and write each image's effort_curve.csv
+/// `--cross-check-decode` - Decode every encode with the pure-Rust jxl-oxide decoder as well and record whether it disagrees with libjxl
+/// `--sample` - Encode a random sample of this many distance and effort combinations per image
+/// `--seed` - The seed of the sampled combinations, the same seed samples the same combinations
+/// `--expensive-metric-distances` - Compute Butteraugli and SSIMULACRA2 only at these distances, comma separated, and record NaN elsewhere
+/// `--expensive-metric-effort-step` - Compute Butteraugli and SSIMULACRA2 only at every nth effort of `--effort`, starting with the first
+/// `--master-dir` - Also compare against the lossless masters in this directory, matched by image name
+/// `--reference-cache` - Keep decoded PNG references of the source images in this directory and reuse them across runs
+/// `--gate` - Fail with exit code 2 if a mean metric regresses by more than `metric:threshold` in a comparison, can be repeated (e.g. `--gate=ssimulacra2:0.5`)
+/// `--baseline-distance` - Diff the metrics at the bpp of each baseline result, interpolating the quality curve of each image, instead of at equal distance
+/// `--smoke` - Encode a generated gradient at one distance and effort and compute every metric, failing if any step of the pipeline does not work
+/// `--quiet, -q` - Only log warnings and errors
+/// `--verbose, -v` - Also log debug messages, e.g. every encode of the workers
+/// `--log-file` - Append log messages to this file instead of stderr
+///
+/// Commands
+/// `verify <run>` - Recompute the Docker-free metrics of a run and report values that differ
+/// `diff <run_a> <run_b>` - Diff the comparisons of two finished runs and write their comparison_diffs.csv and summary.csv
+/// `list-metrics` - Describe the available metrics, their requirements, ranges and directions
 #[derive(Parser)]
 #[clap(name = "Benchmark JPEG-XL")]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
     #[arg(short, long)]
     clean: bool,
     #[arg(short, long)]
@@ -26,24 +131,383 @@ struct Args {
     compare_to_local: bool,
     #[arg(long)]
     compare_to_commit: Option<String>,
+    #[arg(
+        long,
+        conflicts_with_all = ["libjxl_commit", "compare_to_local", "compare_to_commit", "diff_a", "baseline_csv"]
+    )]
+    compare_to_previous: bool,
+    #[arg(long)]
+    max_dimension: Option<u32>,
+    #[arg(long, conflicts_with = "vardct")]
+    modular: bool,
+    #[arg(long)]
+    vardct: bool,
+    #[arg(long, ignore_case = true, default_value = "record")]
+    size_regression: SizeRegressionPolicy,
+    #[arg(long)]
+    validate_metrics: bool,
+    #[arg(long)]
+    strict: bool,
+    #[arg(long)]
+    encode_cache: bool,
+    #[arg(long)]
+    resource_metrics: bool,
+    #[arg(long)]
+    tidy: bool,
+    #[arg(long)]
+    scores: bool,
+    #[arg(long)]
+    score_weight: Vec<ScoreWeight>,
+    #[arg(long, ignore_case = true, default_value = "csv")]
+    output_format: OutputFormat,
+    #[arg(long)]
+    roi: Option<Roi>,
+    #[arg(long, conflicts_with_all = ["roi", "encode_cache"])]
+    discard_compressed: bool,
+    #[arg(long, conflicts_with = "discard_compressed")]
+    content_addressed: bool,
+    #[arg(long)]
+    keep_originals: bool,
+    #[arg(long, allow_hyphen_values = true)]
+    cjxl_arg: Vec<String>,
+    #[arg(long)]
+    docker_build_arg: Vec<String>,
+    #[arg(long, default_value = ".")]
+    docker_context: String,
+    #[arg(long, default_value = "docker")]
+    docker_bin: String,
+    #[arg(long, conflicts_with = "libjxl_src")]
+    toolchain: Vec<String>,
+    #[arg(long, conflicts_with_all = ["libjxl_src", "libjxl_version"])]
+    build_config: Vec<String>,
+    #[arg(long, conflicts_with_all = ["libjxl_commit", "compare_to_local", "compare_to_commit"])]
+    libjxl_src: Option<String>,
+    #[arg(
+        long,
+        conflicts_with_all = ["libjxl_commit", "libjxl_src", "compare_to_local", "compare_to_previous", "diff_a"]
+    )]
+    libjxl_version: Option<String>,
+    #[arg(long, requires = "libjxl_version")]
+    prebuilt_dir: Option<String>,
+    #[arg(long)]
+    deterministic: bool,
+    #[arg(long, requires = "target_value", ignore_case = true)]
+    target_metric: Option<MetricKind>,
+    #[arg(long, requires = "target_metric")]
+    target_value: Option<f64>,
+    #[arg(long, default_value_t = 0.5)]
+    target_tolerance: f64,
+    #[arg(long, default_value_t = 10)]
+    target_max_iterations: u32,
+    #[arg(long, default_value_t = 7)]
+    target_effort: u32,
+    #[arg(long, conflicts_with = "target_metric")]
+    target_size: Option<u64>,
+    #[arg(long, default_value_t = 0.05)]
+    target_size_tolerance: f64,
+    #[arg(long, conflicts_with_all = ["compare_to_local", "compare_to_commit"])]
+    baseline_csv: Option<String>,
+    #[arg(long, default_value = "jpeg", requires = "baseline_csv")]
+    baseline_codec: String,
+    #[arg(long)]
+    intensity_target: Option<f64>,
+    #[arg(long)]
+    diff_images: bool,
+    #[arg(long)]
+    fail_fast: bool,
+    #[arg(long)]
+    error_histograms: bool,
+    #[arg(long, ignore_case = true)]
+    psnr_channels: Option<ChannelSpace>,
+    #[arg(long)]
+    color_manage: bool,
+    #[arg(long, ignore_case = true, default_value = "compression")]
+    size_ratio: SizeRatio,
+    #[arg(long, value_delimiter = ',')]
+    image: Vec<String>,
+    #[arg(long, value_delimiter = ',')]
+    effort: Vec<u32>,
+    #[arg(long, value_delimiter = ',')]
+    distance: Vec<f64>,
+    #[arg(long, value_delimiter = ',')]
+    quality: Vec<f64>,
+    #[arg(long)]
+    reuse_containers: bool,
+    #[arg(long)]
+    external_metric: Vec<String>,
+    #[arg(long)]
+    contact_sheet: bool,
+    #[arg(long)]
+    html_report: bool,
+    #[arg(long)]
+    decode_speed: bool,
+    #[arg(long, default_value_t = 5)]
+    decode_repeats: u32,
+    #[arg(long, default_value_t = 1)]
+    decode_warmup: u32,
+    #[arg(long, value_delimiter = ',')]
+    progressive: Vec<f64>,
+    #[arg(long, default_value_t = 0)]
+    max_concurrent_decodes: usize,
+    #[arg(long, default_value_t = 0)]
+    max_pixels: u64,
+    #[arg(long, default_value_t = 0)]
+    max_decode_bytes: u64,
+    #[arg(long, default_value_t = 1)]
+    metric_threads: usize,
+    #[arg(long, conflicts_with_all = ["compare_to_local", "compare_to_commit", "libjxl_src"])]
+    diff_a: Option<LocalDiff>,
+    #[arg(long, requires = "diff_a")]
+    diff_b: Option<LocalDiff>,
+    #[arg(long, default_value_t = 11)]
+    ssim_window_size: usize,
+    #[arg(long, default_value_t = 1.5)]
+    ssim_sigma: f64,
+    #[arg(long, conflicts_with = "ssim_sigma")]
+    ssim_uniform: bool,
+    #[arg(long, default_value_t = 6)]
+    csv_digits: usize,
+    #[arg(long, default_value = ",")]
+    csv_delimiter: String,
+    #[arg(long, ignore_case = true, default_value = "necessary")]
+    csv_quoting: CsvQuoting,
+    #[arg(long, ignore_case = true, default_value = "equal")]
+    summary_weighting: SummaryWeighting,
+    #[arg(long, ignore_case = true, default_value = "ignore")]
+    duplicates: DuplicatePolicy,
+    #[arg(long)]
+    record_env: bool,
+    #[arg(long)]
+    cross_check_decode: bool,
+    #[arg(long, requires = "target_metric")]
+    effort_curve: bool,
+    #[arg(long)]
+    sample: Option<usize>,
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+    #[arg(long, value_delimiter = ',')]
+    expensive_metric_distances: Vec<f64>,
+    #[arg(long)]
+    expensive_metric_effort_step: Option<usize>,
+    #[arg(long)]
+    master_dir: Option<String>,
+    #[arg(long)]
+    reference_cache: Option<String>,
+    #[arg(long)]
+    gate: Vec<Gate>,
+    #[arg(long)]
+    baseline_distance: bool,
+    #[arg(long)]
+    smoke: bool,
+    #[arg(short, long, conflicts_with = "verbose")]
+    quiet: bool,
+    #[arg(short, long)]
+    verbose: bool,
+    #[arg(long)]
+    log_file: Option<String>,
+}
+
+/// Commands
+/// `verify <run>` - Recompute the MSE and PSNRs of a run and compare them to the stored values
+/// `verify --tolerance` - The largest absolute difference accepted
+/// `diff <run_a> <run_b>` - Diff the comparisons of two finished runs without encoding anything, checked against `--gate`
+/// `list-metrics` - Print each metric with its backend, typical range and whether higher is better
+#[derive(Subcommand)]
+enum Command {
+    Verify {
+        run: usize,
+        #[arg(long, default_value_t = 1e-6)]
+        tolerance: f64,
+    },
+    Diff {
+        run_a: usize,
+        run_b: usize,
+    },
+    ListMetrics,
 }
 
 /**
  * Main function for the JPEG-XL benchmarking tool.
  */
 fn main() {
-    println!("Benchmark JPEG-XL");
-
     // Parse command-line arguments.
     let args = Args::parse();
 
+    // Describe the available metrics if the list-metrics command is given.
+    if let Some(Command::ListMetrics) = args.command {
+        print!("{}", list_metrics());
+        return;
+    }
+
     // Set up config.
     // Use default config and add arguments.
     let mut config = Config::default();
+
+    // Set up logging first, so that invalid arguments are logged to the log file too.
+    config.log_level = match (args.quiet, args.verbose) {
+        (true, _) => LogLevel::Quiet,
+        (_, true) => LogLevel::Verbose,
+        _ => LogLevel::Normal,
+    };
+    config.log_file = args.log_file;
+    if let Err(e) = set_log_sink(config.log_level, config.log_file.as_deref()) {
+        eprintln!("Error: Could not open the log file: {}", e);
+        std::process::exit(1);
+    }
+    info!("Benchmark JPEG-XL");
+
     config.use_temp_dir = args.temp;
     config.libjxl_commit = args.libjxl_commit;
     config.compare_to_local = args.compare_to_local;
     config.compare_to_commit = args.compare_to_commit;
+    config.compare_to_previous = args.compare_to_previous;
+    config.max_dimension = args.max_dimension;
+    config.modular = match (args.modular, args.vardct) {
+        (true, _) => Some(true),
+        (_, true) => Some(false),
+        _ => None,
+    };
+    config.size_regression_policy = args.size_regression;
+    config.metric_validation = match (args.strict, args.validate_metrics) {
+        (true, _) => MetricValidation::Strict,
+        (false, true) => MetricValidation::Warn,
+        (false, false) => MetricValidation::Off,
+    };
+    config.use_encode_cache = args.encode_cache;
+    config.resource_metrics = args.resource_metrics;
+    config.tidy_output = args.tidy;
+    config.normalized_scores = args.scores || !args.score_weight.is_empty();
+    config.score_weights = args.score_weight;
+    config.output_format = args.output_format;
+    config.roi = args.roi;
+    config.keep_compressed = !args.discard_compressed;
+    config.keep_originals = args.keep_originals;
+    config.content_addressed = args.content_addressed;
+    config.cjxl_extra_args = args.cjxl_arg;
+    config.docker_build_args = args.docker_build_arg;
+    config.docker_context_dir = args.docker_context;
+    config.docker_bin = args.docker_bin;
+    config.toolchains = match args
+        .toolchain
+        .iter()
+        .map(|toolchain| Toolchain::try_from(toolchain.as_str()))
+        .collect()
+    {
+        Ok(toolchains) => toolchains,
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    config.build_configs = match BuildConfig::parse_all(&args.build_config) {
+        Ok(build_configs) => build_configs,
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    config.libjxl_src = args.libjxl_src;
+    config.prebuilt_cjxl = args.libjxl_version.map(|version| PrebuiltCjxl {
+        version,
+        binary_dir: args.prebuilt_dir,
+    });
+    config.deterministic = args.deterministic;
+    config.quality_target = match (args.target_metric, args.target_value) {
+        (Some(metric), Some(target)) => Some(QualityTarget {
+            metric,
+            target,
+            tolerance: args.target_tolerance,
+            max_iterations: args.target_max_iterations,
+            effort: args.target_effort,
+        }),
+        _ => None,
+    };
+    config.size_target = args.target_size.map(|target_bytes| SizeTarget {
+        target_bytes,
+        tolerance: args.target_size_tolerance,
+        max_iterations: args.target_max_iterations,
+        effort: args.target_effort,
+    });
+    config.baseline_csv = args.baseline_csv;
+    config.baseline_codec = args.baseline_codec.to_lowercase();
+    config.intensity_target = args.intensity_target;
+    config.diff_images = args.diff_images;
+    config.fail_fast = args.fail_fast;
+    config.error_histograms = args.error_histograms;
+    config.channel_space = args.psnr_channels;
+    config.color_management = args.color_manage;
+    config.size_ratio = args.size_ratio;
+    config.images = args.image;
+    if !args.effort.is_empty() {
+        config.efforts = args.effort;
+    }
+    config.distances = args.distance;
+    config.qualities = args.quality;
+    config.reuse_containers = args.reuse_containers;
+    config.external_metrics = match args
+        .external_metric
+        .into_iter()
+        .map(ExternalMetric::try_from)
+        .collect()
+    {
+        Ok(external_metrics) => external_metrics,
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    config.contact_sheets = args.contact_sheet;
+    config.html_report = args.html_report;
+    config.decode_speed = args.decode_speed;
+    config.decode_repeats = args.decode_repeats;
+    config.decode_warmup = args.decode_warmup;
+    config.progressive_truncations = args.progressive;
+    config.max_concurrent_decodes = args.max_concurrent_decodes;
+    config.max_pixels = args.max_pixels;
+    config.max_decode_bytes = args.max_decode_bytes;
+    config.metric_threads = args.metric_threads;
+    config.diffs = [args.diff_a, args.diff_b].into_iter().flatten().collect();
+    config.ssim_window_size = args.ssim_window_size;
+    config.ssim_sigma = match args.ssim_uniform {
+        true => None,
+        false => Some(args.ssim_sigma),
+    };
+    config.csv_float_digits = args.csv_digits;
+    config.csv_delimiter = match args.csv_delimiter.as_str() {
+        "tab" | "\\t" => b'\t',
+        delimiter if delimiter.len() == 1 => delimiter.as_bytes()[0],
+        delimiter => {
+            error!(
+                "Invalid CSV delimiter {}, expected a single character",
+                delimiter
+            );
+            std::process::exit(1);
+        }
+    };
+    config.csv_quoting = args.csv_quoting;
+    config.summary_weighting = args.summary_weighting;
+    config.duplicate_policy = args.duplicates;
+    config.record_environment = args.record_env;
+    config.cross_check_decode = args.cross_check_decode;
+    config.effort_curve = args.effort_curve;
+    config.grid_sample = args.sample.map(|count| GridSample {
+        count,
+        seed: args.seed,
+    });
+    config.expensive_metric_sample = match (
+        args.expensive_metric_distances.is_empty(),
+        args.expensive_metric_effort_step,
+    ) {
+        (true, None) => None,
+        (_, effort_step) => Some(ExpensiveMetricSample {
+            distances: args.expensive_metric_distances,
+            effort_step: effort_step.unwrap_or(1),
+        }),
+    };
+    config.master_dir = args.master_dir;
+    config.reference_cache_dir = args.reference_cache;
+    config.gates = args.gate;
+    config.baseline_distance = args.baseline_distance;
 
     // Set up benchmark directory.
     // Append "/temp" to benchmark directory if --temp is set.
@@ -57,6 +521,83 @@ fn main() {
         };
     config.benchmark_dir_path = benchmark_path.clone();
 
+    // Verify a finished run if the verify command is given, without running a benchmark.
+    if let Some(Command::Verify { run, tolerance }) = args.command {
+        if !jxl_decode_available() {
+            error!("{}", BenchmarkError::JxlDecodeUnavailable);
+            std::process::exit(1);
+        }
+        set_csv_format(config.csv_delimiter, config.csv_quoting);
+        match RunVerifier::verify_run(&config.benchmark_dir_path, run, tolerance) {
+            Ok(mismatches) if mismatches.is_empty() => {
+                info!("Run {} verified: all recomputed metrics match.", run)
+            }
+            Ok(mismatches) => {
+                for mismatch in &mismatches {
+                    println!(
+                        "{} row {} ({}): {} stored {} but recomputed {}",
+                        mismatch.comparisons_file,
+                        mismatch.row,
+                        mismatch.comp_image_name,
+                        mismatch.metric,
+                        mismatch.stored,
+                        mismatch.recomputed
+                    );
+                }
+                error!("{} metric values do not match", mismatches.len());
+                std::process::exit(1);
+            }
+            Err(e) => {
+                error!("{}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    // Diff two finished runs if the diff command is given, without running a benchmark.
+    if let Some(Command::Diff { run_a, run_b }) = args.command {
+        set_csv_format(config.csv_delimiter, config.csv_quoting);
+        match JXLCompressionBenchmark::diff_runs(
+            &config.benchmark_dir_path,
+            run_a,
+            run_b,
+            config.summary_weighting,
+            &config.gates,
+            config.baseline_distance,
+        ) {
+            Ok(result_dirs) => {
+                for result_dir in result_dirs {
+                    info!(
+                        "Wrote the diffs of run {} against run {} to {}",
+                        run_b, run_a, result_dir
+                    );
+                }
+            }
+            Err(e) => {
+                error!("{}", e);
+                std::process::exit(exit_code(&*e));
+            }
+        }
+        return;
+    }
+
+    // Run the smoke test if --smoke is set, without benchmarking the test images.
+    if args.smoke {
+        info!("Running the smoke test...");
+        match SmokeTest::run(&config) {
+            Ok(result) => info!(
+                "Smoke test passed: {} is {} bytes with PSNR {:.2} and SSIMULACRA2 {:.2}.",
+                result.comp_image_name, result.comp_file_size, result.psnr, result.ssimulacra2
+            ),
+            Err(e) => {
+                error!("{}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
     // Clean benchmark directory if --clean is set.
     match args.clean {
         true => {
@@ -69,17 +610,32 @@ fn main() {
     fs::create_dir_all(benchmark_path.clone()).unwrap();
 
     // Set up benchmarker.
-    let mut benchmarker = Benchmarker::new(&config);
+    let mut benchmarker = match Benchmarker::new(&config) {
+        Ok(benchmarker) => benchmarker,
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    };
 
     // Run JPEG-XL Compression benchmark.
-    println!("Running JPEG-XL Compression benchmark...");
-    benchmarker.run_benchmark::<JXLCompressionBenchmark>();
+    info!("Running JPEG-XL Compression benchmark...");
+    if let Err(e) = benchmarker.run_benchmark::<JXLCompressionBenchmark>() {
+        error!("{}", e);
+        std::process::exit(exit_code(&*e));
+    }
 
     // Wait for workers to finish.
     benchmarker.wait_for_all_workers();
 
-    println!("JPEG-XL Compression benchmark finished.");
+    info!("JPEG-XL Compression benchmark finished.");
 
     // Teardown benchmarker.
     //    benchmarker.teardown();
 }
+
+/// Gets the exit code of the process for an error, 2 for violated gates and 1 otherwise.
+fn exit_code(e: &(dyn std::error::Error + 'static)) -> i32 {
+    e.downcast_ref::<BenchmarkError>()
+        .map_or(1, BenchmarkError::exit_code)
+}
@@ -0,0 +1,269 @@
+use crate::config::{OutputFormat, SummaryWeighting, Sweep};
+use crate::context::Context;
+use crate::csv_writer::DEFAULT_CSV_DELIMITER;
+use crate::docker_manager::DockerManager;
+use crate::duplicates::DuplicateImages;
+use crate::image_limits::OversizedImage;
+use crate::metrics::{MetricKind, SsimWindow};
+use crate::run_timing::RunTimingSummary;
+
+use log::warn;
+use serde_derive::{Deserialize, Serialize};
+
+use std::error::Error;
+use std::path::Path;
+
+/// A record of the settings a benchmark run was produced with, so that the run can be
+/// reproduced and interpreted later.
+/// The manifest is stored as `manifest.json` in the run directory.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RunManifest {
+    pub run: usize,
+    pub test_sets: Vec<String>,
+    /// The libjxl commits benchmarked in the run, resolved to SHAs where possible.
+    pub libjxl_commits: Vec<String>,
+    /// The extra arguments passed verbatim to cjxl, joined by spaces.
+    pub cjxl_extra_args: String,
+    /// The distances swept in the run, empty in quality mode and for runs recorded before the
+    /// sweep was recorded.
+    #[serde(default)]
+    pub distances: Vec<f64>,
+    /// The qualities swept in the run, empty in distance mode.
+    #[serde(default)]
+    pub qualities: Vec<f64>,
+    /// The efforts swept in the run, empty for runs recorded before the sweep was recorded.
+    #[serde(default)]
+    pub efforts: Vec<u32>,
+    /// How the images were weighted in the aggregates and summaries: equal, pixels or bytes.
+    /// Runs recorded before the weighting was configurable weighted images equally.
+    #[serde(default = "RunManifest::default_summary_weighting")]
+    pub summary_weighting: String,
+    /// The run whose results were reused as the baseline of this run, if it was compared to
+    /// the previous run.
+    #[serde(default)]
+    pub previous_run: Option<usize>,
+    /// The images found to have the same content as another image of their test set, if
+    /// duplicates were looked for.
+    #[serde(default)]
+    pub duplicates: Vec<DuplicateImages>,
+    /// The images that were skipped because they exceed the image limits.
+    #[serde(default)]
+    pub oversized_images: Vec<OversizedImage>,
+    /// The size of the window the native SSIM and MS-SSIM were computed over.
+    /// Runs recorded before the window was configurable used the default window.
+    #[serde(default = "RunManifest::default_ssim_window_size")]
+    pub ssim_window_size: usize,
+    /// The standard deviation of the Gaussian SSIM window, or None for a uniform window.
+    #[serde(default = "RunManifest::default_ssim_sigma")]
+    pub ssim_sigma: Option<f64>,
+    /// The metric columns computed over the SSIM window, which the window does not apply to
+    /// the SSIM column of ImageMagick.
+    #[serde(default = "RunManifest::default_ssim_window_metrics")]
+    pub ssim_window_metrics: Vec<String>,
+    /// The number of significant digits of the floating point values in the CSV files, 0 for
+    /// full precision, which runs recorded before the precision was configurable used.
+    #[serde(default)]
+    pub csv_float_digits: usize,
+    /// The delimiter of the fields in the CSV files, which runs recorded before the delimiter was
+    /// configurable separated with commas.
+    #[serde(default = "RunManifest::default_csv_delimiter")]
+    pub csv_delimiter: String,
+    /// The number of distance and effort combinations sampled per image, if the grid was
+    /// sampled instead of encoded in full.
+    #[serde(default)]
+    pub sample_count: Option<usize>,
+    /// The seed the combinations were sampled with, if the grid was sampled.
+    #[serde(default)]
+    pub sample_seed: Option<u64>,
+    /// The command line the benchmark was run with, if the environment was recorded.
+    #[serde(default)]
+    pub argv: Vec<String>,
+    /// The operating system and architecture of the host, if the environment was recorded.
+    #[serde(default)]
+    pub host_os: String,
+    /// The output of `docker --version`, if the environment was recorded and docker reported it.
+    #[serde(default)]
+    pub docker_version: Option<String>,
+    /// The output of `cjxl --version` at the start of the run, if the environment was recorded
+    /// and cjxl reported it.
+    #[serde(default)]
+    pub cjxl_version: Option<String>,
+    /// The released libjxl version benchmarked with prebuilt binaries, if any. Its results are
+    /// labelled by the version in place of a commit.
+    #[serde(default)]
+    pub libjxl_version: Option<String>,
+    /// The SHA-256 hash of the diff of the local libjxl changes, if they were benchmarked as the
+    /// `local` commit.
+    #[serde(default)]
+    pub local_diff_hash: Option<String>,
+    /// How long the run took and the time spent in each phase, once the run finished.
+    #[serde(default)]
+    pub timing: Option<RunTimingSummary>,
+}
+
+impl RunManifest {
+    /// The name of the manifest file in the run directory.
+    pub const FILE_NAME: &'static str = "manifest.json";
+
+    /// Creates the manifest for a run from the benchmark context.
+    ///
+    /// # Arguments
+    /// * `context` - The context of the benchmark run.
+    ///
+    /// # Returns
+    /// The manifest, without any libjxl commits recorded yet.
+    pub fn new(context: &Context) -> RunManifest {
+        RunManifest {
+            run: context.current_run,
+            test_sets: context.test_sets.clone(),
+            libjxl_commits: Vec::new(),
+            cjxl_extra_args: context.cjxl_extra_args.join(" "),
+            distances: match &context.sweep {
+                Sweep::Distance(distances) => distances.clone(),
+                Sweep::Quality(_) => Vec::new(),
+            },
+            qualities: match &context.sweep {
+                Sweep::Distance(_) => Vec::new(),
+                Sweep::Quality(qualities) => qualities.clone(),
+            },
+            efforts: context.efforts.clone(),
+            summary_weighting: context.summary_weighting.name().to_string(),
+            previous_run: context.previous_run.as_ref().map(|manifest| manifest.run),
+            ssim_window_size: context.ssim_window.size,
+            ssim_sigma: context.ssim_window.sigma,
+            ssim_window_metrics: SsimWindow::applied_metrics(context.color_management)
+                .iter()
+                .map(|kind| kind.name().to_string())
+                .collect(),
+            csv_float_digits: context.csv_float_digits,
+            csv_delimiter: (context.csv_delimiter as char).to_string(),
+            sample_count: context.grid_sample.map(|grid_sample| grid_sample.count),
+            sample_seed: context.grid_sample.map(|grid_sample| grid_sample.seed),
+            libjxl_version: context
+                .prebuilt_cjxl
+                .as_ref()
+                .map(|prebuilt_cjxl| prebuilt_cjxl.version.clone()),
+            local_diff_hash: context.local_diff_hash.clone(),
+            ..RunManifest::default()
+        }
+    }
+
+    /// Records the command line, host and tool versions of the run, which determine the results
+    /// beyond the settings.
+    ///
+    /// # Arguments
+    /// * `docker_manager` - The docker manager to query the docker and cjxl versions with.
+    pub fn record_environment(&mut self, docker_manager: &DockerManager) {
+        self.argv = std::env::args().collect();
+        self.host_os = format!("{} {}", std::env::consts::OS, std::env::consts::ARCH);
+        self.docker_version = match docker_manager.docker_version() {
+            Ok(version) => Some(version),
+            Err(e) => {
+                warn!("could not get the docker version: {}", e);
+                None
+            }
+        };
+        self.cjxl_version = match docker_manager.cjxl_version() {
+            Ok(version) => Some(version),
+            Err(e) => {
+                warn!("could not get the cjxl version: {}", e);
+                None
+            }
+        };
+    }
+
+    fn default_ssim_window_size() -> usize {
+        SsimWindow::DEFAULT.size
+    }
+
+    fn default_ssim_sigma() -> Option<f64> {
+        SsimWindow::DEFAULT.sigma
+    }
+
+    fn default_ssim_window_metrics() -> Vec<String> {
+        vec![MetricKind::MsSsim.name().to_string()]
+    }
+
+    fn default_summary_weighting() -> String {
+        SummaryWeighting::Equal.name().to_string()
+    }
+
+    fn default_csv_delimiter() -> String {
+        (DEFAULT_CSV_DELIMITER as char).to_string()
+    }
+
+    /// Gets the path to the manifest of a run.
+    ///
+    /// # Arguments
+    /// * `benchmark_dir` - The benchmark directory.
+    /// * `run` - The run number.
+    ///
+    /// # Returns
+    /// The path to the manifest file.
+    pub fn path(benchmark_dir: &str, run: usize) -> String {
+        format!("{}/{}/{}", benchmark_dir, run, RunManifest::FILE_NAME)
+    }
+
+    /// Loads the manifest of a run.
+    ///
+    /// # Arguments
+    /// * `benchmark_dir` - The benchmark directory.
+    /// * `run` - The run number.
+    ///
+    /// # Returns
+    /// The manifest, or an error if it could not be read.
+    pub fn load(benchmark_dir: &str, run: usize) -> Result<RunManifest, Box<dyn Error>> {
+        let json = std::fs::read_to_string(RunManifest::path(benchmark_dir, run))?;
+        Ok(serde_json::from_str(&json)?)
+    }
+
+    /// Finds the manifest of the latest run in the benchmark directory. Runs without a readable
+    /// manifest, e.g. runs that were reserved but never started, are passed over.
+    ///
+    /// # Arguments
+    /// * `benchmark_dir` - The benchmark directory.
+    ///
+    /// # Returns
+    /// The manifest of the highest numbered run that has one, or None if there is no such run.
+    pub fn latest(benchmark_dir: &str) -> Option<RunManifest> {
+        let mut runs = std::fs::read_dir(benchmark_dir)
+            .ok()?
+            .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse::<usize>().ok())
+            .collect::<Vec<usize>>();
+        runs.sort_unstable();
+        runs.into_iter()
+            .rev()
+            .find_map(|run| RunManifest::load(benchmark_dir, run).ok())
+    }
+
+    /// Gets the comparisons of the last commit benchmarked in the run for a test set, which is
+    /// the local build when the run compared commits.
+    ///
+    /// # Arguments
+    /// * `benchmark_dir` - The benchmark directory.
+    /// * `test_set` - The name of the test set.
+    ///
+    /// # Returns
+    /// The path of the comparisons CSV or binary file, or None if the run recorded none for the
+    /// test set.
+    pub fn comparisons_file(&self, benchmark_dir: &str, test_set: &str) -> Option<String> {
+        let commit = self.libjxl_commits.last()?;
+        OutputFormat::find_comparisons_file(&format!(
+            "{}/{}/results/comp/{}/{}",
+            benchmark_dir, self.run, test_set, commit
+        ))
+    }
+
+    /// Saves the manifest to its run directory.
+    ///
+    /// # Arguments
+    /// * `benchmark_dir` - The benchmark directory.
+    pub fn save(&self, benchmark_dir: &str) -> Result<(), Box<dyn Error>> {
+        let path = RunManifest::path(benchmark_dir, self.run);
+        if let Some(parent) = Path::new(&path).parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
@@ -1,7 +1,744 @@
+use crate::config::ExternalMetric;
 use crate::{docker_manager::DockerManager, image_reader::ImageReader};
 
+use clap_derive::ValueEnum;
+
+use regex::Regex;
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
 use std::io::BufRead;
+use std::path::Path;
 use std::process::Command;
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+/// The size of the default Gaussian window used by the native SSIM and MS-SSIM implementations.
+pub const SSIM_WINDOW_SIZE: usize = 11;
+
+/// The standard deviation of the default Gaussian window used by the native SSIM and MS-SSIM
+/// implementations.
+pub const SSIM_SIGMA: f64 = 1.5;
+
+/// How far a metric may exceed its plausible range from floating-point rounding, e.g. an SSIM
+/// of 1.0000000001 for identical images.
+pub const PLAUSIBLE_RANGE_TOLERANCE: f64 = 1e-6;
+
+/// The per-scale weights of MS-SSIM, from Wang et al. (2003).
+const MS_SSIM_WEIGHTS: [f64; 5] = [0.0448, 0.2856, 0.3001, 0.2363, 0.1333];
+
+/// The window the native SSIM and MS-SSIM are computed over.
+/// Reference implementations differ here, e.g. Wang et al. use an 11x11 Gaussian window while
+/// others use an 8x8 uniform window, so the window is recorded with the results.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SsimWindow {
+    /// The width and height of the window.
+    pub size: usize,
+    /// The standard deviation of a Gaussian window, or None for a uniform window.
+    pub sigma: Option<f64>,
+}
+
+impl SsimWindow {
+    /// The 11x11 Gaussian window with a standard deviation of 1.5 from Wang et al. (2004).
+    pub const DEFAULT: SsimWindow = SsimWindow {
+        size: SSIM_WINDOW_SIZE,
+        sigma: Some(SSIM_SIGMA),
+    };
+
+    /// Creates an SSIM window.
+    ///
+    /// # Arguments
+    /// * `size` - The width and height of the window.
+    /// * `sigma` - The standard deviation of a Gaussian window, or None for a uniform window.
+    ///
+    /// # Returns
+    /// The window, or an error if the size is 0, or a Gaussian window has an even size, so it
+    /// has no center sample, or a sigma that is not positive.
+    pub fn new(size: usize, sigma: Option<f64>) -> Result<SsimWindow, String> {
+        if size == 0 {
+            return Err("The SSIM window size must be at least 1".to_string());
+        }
+        if let Some(sigma) = sigma {
+            if size % 2 == 0 {
+                return Err(format!(
+                    "The size of a Gaussian SSIM window must be odd, got {}",
+                    size
+                ));
+            }
+            if !(sigma > 0.0 && sigma.is_finite()) {
+                return Err(format!("The SSIM sigma must be positive, got {}", sigma));
+            }
+        }
+        Ok(SsimWindow { size, sigma })
+    }
+
+    /// Lists the metric columns of a run that are computed over the window. The SSIM column
+    /// comes from ImageMagick, which uses its own window, unless the images are color managed
+    /// and compared natively in memory.
+    ///
+    /// # Arguments
+    /// * `color_management` - Whether the images of the run are color managed.
+    ///
+    /// # Returns
+    /// The metrics computed over the window.
+    pub fn applied_metrics(color_management: bool) -> Vec<MetricKind> {
+        match color_management {
+            true => vec![MetricKind::Ssim, MetricKind::MsSsim],
+            false => vec![MetricKind::MsSsim],
+        }
+    }
+
+    /// Builds the normalized 1D kernel of the window, shrunk to at most `size` samples.
+    fn kernel(&self, size: usize) -> Vec<f64> {
+        let size = self.size.min(size);
+        match self.sigma {
+            Some(sigma) => gaussian_kernel(size, sigma),
+            None => vec![1.0 / size as f64; size],
+        }
+    }
+}
+
+impl Default for SsimWindow {
+    fn default() -> Self {
+        SsimWindow::DEFAULT
+    }
+}
+
+impl Display for SsimWindow {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self.sigma {
+            Some(sigma) => write!(f, "{0}x{0} Gaussian (sigma {1})", self.size, sigma),
+            None => write!(f, "{0}x{0} uniform", self.size),
+        }
+    }
+}
+
+/// The image quality metrics supported by the benchmark.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum MetricKind {
+    #[value(name = "mse")]
+    Mse,
+    #[value(name = "psnr")]
+    Psnr,
+    #[value(name = "ssim")]
+    Ssim,
+    #[value(name = "ms-ssim", alias = "ms_ssim")]
+    MsSsim,
+    #[value(name = "butteraugli")]
+    Butteraugli,
+    #[value(name = "ssimulacra2")]
+    Ssimulacra2,
+}
+
+impl MetricKind {
+    /// Every metric supported by the benchmark, in the order of the result columns.
+    pub const ALL: [MetricKind; 6] = [
+        MetricKind::Mse,
+        MetricKind::Psnr,
+        MetricKind::Ssim,
+        MetricKind::MsSsim,
+        MetricKind::Butteraugli,
+        MetricKind::Ssimulacra2,
+    ];
+
+    /// Returns the name of the metric.
+    pub fn name(&self) -> &'static str {
+        match self {
+            MetricKind::Mse => "MSE",
+            MetricKind::Psnr => "PSNR",
+            MetricKind::Ssim => "SSIM",
+            MetricKind::MsSsim => "MS-SSIM",
+            MetricKind::Butteraugli => "Butteraugli",
+            MetricKind::Ssimulacra2 => "SSIMULACRA2",
+        }
+    }
+
+    /// Returns whether a higher value of the metric means a higher quality.
+    pub fn higher_is_better(&self) -> bool {
+        match self {
+            MetricKind::Mse | MetricKind::Butteraugli => false,
+            _ => true,
+        }
+    }
+
+    /// Returns whether the metric can only be computed with the libjxl tools in the Docker
+    /// container.
+    pub fn requires_docker(&self) -> bool {
+        match self {
+            MetricKind::Butteraugli | MetricKind::Ssimulacra2 => true,
+            _ => false,
+        }
+    }
+
+    /// Returns the smallest width and height of the images the metric is computed for. MS-SSIM
+    /// needs at least one full SSIM window at the first scale, the other metrics one pixel.
+    ///
+    /// # Arguments
+    /// * `window` - The window SSIM and MS-SSIM are computed over.
+    pub fn min_image_size(&self, window: &SsimWindow) -> u32 {
+        match self {
+            MetricKind::MsSsim => window.size as u32,
+            _ => 1,
+        }
+    }
+
+    /// Checks that images of the given dimensions are large enough for the metric, see
+    /// `min_image_size`.
+    ///
+    /// # Arguments
+    /// * `width` - The width of the images.
+    /// * `height` - The height of the images.
+    /// * `window` - The window SSIM and MS-SSIM are computed over.
+    pub fn check_image_size(
+        &self,
+        width: u32,
+        height: u32,
+        window: &SsimWindow,
+    ) -> Result<(), MetricError> {
+        let min_size = self.min_image_size(window);
+        match width >= min_size && height >= min_size {
+            true => Ok(()),
+            false => Err(MetricError::TooSmall(*self, (width, height), min_size)),
+        }
+    }
+
+    /// Returns what the metric is computed with.
+    pub fn backend(&self) -> &'static str {
+        match self {
+            MetricKind::Mse | MetricKind::Psnr | MetricKind::MsSsim => "native",
+            MetricKind::Ssim => "ImageMagick (native for images compared in memory)",
+            MetricKind::Butteraugli | MetricKind::Ssimulacra2 => "Docker (libjxl tools)",
+        }
+    }
+
+    /// Returns the range of values the metric typically takes on lossy encodes.
+    pub fn typical_range(&self) -> &'static str {
+        match self {
+            MetricKind::Mse => "0 to 65025 on 8-bit images, 0 when identical",
+            MetricKind::Psnr => "20 to 50 dB, infinite when identical",
+            MetricKind::Ssim | MetricKind::MsSsim => "0 to 1, 1 when identical",
+            MetricKind::Butteraugli => "0 to 10+, below 1 is visually lossless",
+            MetricKind::Ssimulacra2 => "-inf to 100, above 90 is visually lossless",
+        }
+    }
+
+    /// Returns the lowest and highest values the metric can take at all. A value outside of
+    /// this range is impossible, and usually means a parsing bug or mismatched inputs.
+    pub fn plausible_range(&self) -> (f64, f64) {
+        match self {
+            MetricKind::Mse | MetricKind::Psnr | MetricKind::Butteraugli => (0.0, f64::INFINITY),
+            MetricKind::Ssim | MetricKind::MsSsim => (-1.0, 1.0),
+            MetricKind::Ssimulacra2 => (f64::NEG_INFINITY, 100.0),
+        }
+    }
+
+    /// Checks that a value of the metric is in its plausible range, up to
+    /// `PLAUSIBLE_RANGE_TOLERANCE` for rounding. Metrics that were not computed (NaN) pass.
+    ///
+    /// # Arguments
+    /// * `value` - The value of the metric.
+    ///
+    /// # Returns
+    /// An error describing the value and the range if the value is outside of the range.
+    pub fn check_plausible(&self, value: f64) -> Result<(), String> {
+        let (min, max) = self.plausible_range();
+        if value.is_nan()
+            || (value >= min - PLAUSIBLE_RANGE_TOLERANCE
+                && value <= max + PLAUSIBLE_RANGE_TOLERANCE)
+        {
+            return Ok(());
+        }
+        Err(format!(
+            "{} of {} is outside its plausible range {} to {}",
+            self.name(),
+            value,
+            min,
+            max
+        ))
+    }
+
+    /// Maps a value of the metric to a common quality scale from 0 to 1, where higher is
+    /// better, so that metrics of different directions and units can be combined:
+    ///  - PSNR: 20 dB and below is 0, 50 dB and above is 1, linear in between.
+    ///  - MSE: the score of the PSNR of an 8-bit image with the MSE.
+    ///  - SSIM and MS-SSIM: the value, clamped to 0 to 1.
+    ///  - Butteraugli: `1 / (1 + value)`, so 0 is 1 and the visually lossless 1 is 0.5.
+    ///  - SSIMULACRA2: the value divided by 100, clamped to 0 to 1.
+    ///
+    /// # Arguments
+    /// * `value` - The value of the metric.
+    ///
+    /// # Returns
+    /// The score of the value, NaN if the value is NaN.
+    pub fn score(&self, value: f64) -> f64 {
+        match self {
+            MetricKind::Mse => MetricKind::Psnr.score(10.0 * (255.0 * 255.0 / value).log10()),
+            MetricKind::Psnr => ((value - 20.0) / 30.0).clamp(0.0, 1.0),
+            MetricKind::Ssim | MetricKind::MsSsim => value.clamp(0.0, 1.0),
+            MetricKind::Butteraugli => 1.0 / (1.0 + value.max(0.0)),
+            MetricKind::Ssimulacra2 => (value / 100.0).clamp(0.0, 1.0),
+        }
+    }
+}
+
+/// Describes every supported metric with its backend, typical range and direction, one metric
+/// per line.
+///
+/// # Returns
+/// The description of the metrics, with a header line.
+pub fn list_metrics() -> String {
+    let rows = MetricKind::ALL.iter().map(|metric| {
+        [
+            metric.name(),
+            metric.backend(),
+            metric.typical_range(),
+            match metric.higher_is_better() {
+                true => "higher",
+                false => "lower",
+            },
+        ]
+    });
+    let header = ["Metric", "Backend", "Typical Range", "Better"];
+    let rows = std::iter::once(header).chain(rows).collect::<Vec<[&str; 4]>>();
+    let widths = (0..4)
+        .map(|column| rows.iter().map(|row| row[column].len()).max().unwrap())
+        .collect::<Vec<usize>>();
+    rows.iter()
+        .map(|row| {
+            row.iter()
+                .zip(&widths)
+                .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+                .collect::<Vec<String>>()
+                .join("  ")
+                .trim_end()
+                .to_string()
+                + "\n"
+        })
+        .collect()
+}
+
+impl FromStr for MetricKind {
+    type Err = String;
+
+    /// Parses a metric from its name, ignoring case, e.g. `ms-ssim` or `SSIMULACRA2`.
+    fn from_str(kind: &str) -> Result<Self, Self::Err> {
+        <MetricKind as clap::ValueEnum>::from_str(kind, true)
+            .map_err(|_| format!("Invalid metric: {}", kind))
+    }
+}
+
+/// The decomposition of an image into channels for the per-channel PSNR.
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum ChannelSpace {
+    /// The R, G and B channels as stored.
+    #[value(name = "rgb")]
+    Rgb,
+    /// Full-range Y, Cb and Cr with the BT.601 luma coefficients, as used by JPEG (JFIF).
+    #[value(name = "bt601", alias = "ycbcr")]
+    YCbCrBt601,
+    /// Full-range Y, Cb and Cr with the BT.709 luma coefficients.
+    #[value(name = "bt709")]
+    YCbCrBt709,
+}
+
+impl ChannelSpace {
+    /// Returns the name of the channel space as used on the command line and in result files.
+    pub fn name(&self) -> &'static str {
+        match self {
+            ChannelSpace::Rgb => "rgb",
+            ChannelSpace::YCbCrBt601 => "bt601",
+            ChannelSpace::YCbCrBt709 => "bt709",
+        }
+    }
+
+    /// Converts an RGB pixel on the 8-bit scale to the channel space.
+    /// The YCbCr spaces use the full-range conversion with the luma coefficients Kr and Kb
+    /// (BT.601: 0.299 and 0.114, BT.709: 0.2126 and 0.0722):
+    ///
+    /// ```text
+    /// Y  = Kr * R + (1 - Kr - Kb) * G + Kb * B
+    /// Cb = 128 + (B - Y) / (2 * (1 - Kb))
+    /// Cr = 128 + (R - Y) / (2 * (1 - Kr))
+    /// ```
+    ///
+    /// so all channels stay on the 0 to 255 scale. The result is not rounded.
+    ///
+    /// # Arguments
+    /// * `rgb` - The R, G and B values of the pixel.
+    ///
+    /// # Returns
+    /// The three channel values of the pixel.
+    pub fn convert(&self, rgb: [f64; 3]) -> [f64; 3] {
+        let (kr, kb) = match self {
+            ChannelSpace::Rgb => return rgb,
+            ChannelSpace::YCbCrBt601 => (0.299, 0.114),
+            ChannelSpace::YCbCrBt709 => (0.2126, 0.0722),
+        };
+        let [r, g, b] = rgb;
+        let y = kr * r + (1.0 - kr - kb) * g + kb * b;
+        [
+            y,
+            128.0 + (b - y) / (2.0 * (1.0 - kb)),
+            128.0 + (r - y) / (2.0 * (1.0 - kr)),
+        ]
+    }
+}
+
+/// The direction of a size ratio, i.e. which of the two sizes is the denominator.
+#[derive(Debug, Clone, Copy, PartialEq, Default, ValueEnum)]
+pub enum SizeRatio {
+    /// The compression ratio `orig / comp`, how many times smaller the compressed file is.
+    /// Higher is better.
+    #[default]
+    #[value(name = "compression")]
+    Compression,
+    /// The relative size `comp / orig`, the compressed size as a fraction of the original.
+    /// Lower is better.
+    #[value(name = "relative-size")]
+    RelativeSize,
+}
+
+impl SizeRatio {
+    /// Calculates the ratio of an original and a compressed size in this direction.
+    /// The ratio is 0 if its denominator is 0.
+    ///
+    /// # Arguments
+    /// * `orig` - The original size.
+    /// * `comp` - The compressed size.
+    ///
+    /// # Returns
+    /// The ratio of the sizes.
+    pub fn ratio(&self, orig: usize, comp: usize) -> f64 {
+        match self {
+            SizeRatio::Compression => file_size_ratio(orig, comp, "comp"),
+            SizeRatio::RelativeSize => file_size_ratio(orig, comp, "orig"),
+        }
+    }
+
+    /// Returns the numerator and denominator of the ratio, as used in column names.
+    pub fn label(&self) -> &'static str {
+        match self {
+            SizeRatio::Compression => "orig/comp",
+            SizeRatio::RelativeSize => "comp/orig",
+        }
+    }
+
+    /// Returns the name of the ratio, as used in column names.
+    pub fn name(&self) -> &'static str {
+        match self {
+            SizeRatio::Compression => "Compression Ratio",
+            SizeRatio::RelativeSize => "Relative Size",
+        }
+    }
+
+    /// Builds the name of a column of the ratio, e.g. `Raw Compression Ratio (raw/comp)`.
+    ///
+    /// # Arguments
+    /// * `prefix` - The prefix of the column name, e.g. `Raw `.
+    /// * `orig` - The name of the size the compressed size is compared to, e.g. `raw`.
+    ///
+    /// # Returns
+    /// The column name, with the direction of the ratio.
+    pub fn column(&self, prefix: &str, orig: &str) -> String {
+        format!(
+            "{}{} ({})",
+            prefix,
+            self.name(),
+            self.label().replace("orig", orig)
+        )
+    }
+
+    /// Reads the direction of a ratio from the name of its column.
+    /// Columns without a direction, written before it was configurable, are compression ratios.
+    ///
+    /// # Arguments
+    /// * `column` - The column name, as built by `column`.
+    ///
+    /// # Returns
+    /// The direction of the ratio.
+    pub fn from_column(column: &str) -> SizeRatio {
+        match column.contains(SizeRatio::RelativeSize.name()) {
+            true => SizeRatio::RelativeSize,
+            false => SizeRatio::Compression,
+        }
+    }
+
+    /// Returns whether a higher ratio means a better compression.
+    pub fn higher_is_better(&self) -> bool {
+        *self == SizeRatio::Compression
+    }
+}
+
+/// A set of metric values computed between two images.
+/// Metrics that were not requested are None.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MetricSet {
+    pub mse: Option<f64>,
+    pub psnr: Option<f64>,
+    pub ssim: Option<f64>,
+    pub ms_ssim: Option<f64>,
+}
+
+/// Errors that can occur while computing metrics.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MetricError {
+    /// The metric can only be computed in the Docker container.
+    RequiresDocker(MetricKind),
+    /// An image could not be read or decoded.
+    Decode(String),
+    /// The images being compared do not have the same dimensions.
+    DimensionMismatch((u32, u32), (u32, u32)),
+    /// The output of a metric tool has no value, with the name of the tool and its raw output.
+    UnparsableOutput(String, String),
+    /// A metric tool could not be run, with the name of the tool and the reason.
+    Tool(String, String),
+    /// The images are smaller than the metric needs, with their dimensions and the minimum width
+    /// and height.
+    TooSmall(MetricKind, (u32, u32), u32),
+}
+
+impl Display for MetricError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            MetricError::RequiresDocker(kind) => write!(
+                f,
+                "{} requires the libjxl tools in the Docker container, use the benchmark runner",
+                kind.name()
+            ),
+            MetricError::Decode(message) => write!(f, "Failed to decode image: {}", message),
+            MetricError::DimensionMismatch(orig, comp) => write!(
+                f,
+                "Image dimensions do not match: {}x{} vs {}x{}",
+                orig.0, orig.1, comp.0, comp.1
+            ),
+            MetricError::UnparsableOutput(tool, output) => {
+                write!(f, "No value found in the {} output: {:?}", tool, output)
+            }
+            MetricError::Tool(tool, reason) => write!(f, "Could not run {}: {}", tool, reason),
+            MetricError::TooSmall(kind, (width, height), min_size) => write!(
+                f,
+                "{} needs images of at least {}x{}, got {}x{}",
+                kind.name(),
+                min_size,
+                min_size,
+                width,
+                height
+            ),
+        }
+    }
+}
+
+impl Error for MetricError {}
+
+/// Compare two local image files with the metrics that do not require Docker.
+/// Both images are decoded to 8-bit RGB; JXL files are decoded with jpegxl_rs and all other
+/// formats with the image crate. SSIM and MS-SSIM are computed natively on the luma channel, so
+/// they may differ slightly from the ImageMagick SSIM reported by the benchmark runner.
+///
+/// # Arguments
+/// * `orig` - The path to the original image.
+/// * `comp` - The path to the compressed image.
+/// * `metrics` - The metrics to compute.
+///
+/// # Returns
+/// The requested metric values, or an error if an image could not be decoded, the dimensions do
+/// not match, the images are too small for a requested metric (empty, or smaller than the SSIM
+/// window for MS-SSIM), or a requested metric requires the Docker container (Butteraugli,
+/// SSIMULACRA2). SSIM and MS-SSIM are computed over the default SSIM window.
+///
+/// # Example
+/// ```
+/// use std::path::Path;
+/// use benchmark_jpegxl::metrics::{compare_images, MetricKind};
+///
+/// let metrics = compare_images(
+///     Path::new("tests/images/orig/1.png"),
+///     Path::new("tests/images/comp/1.jxl"),
+///     &[MetricKind::Mse, MetricKind::Psnr, MetricKind::Ssim, MetricKind::MsSsim],
+/// )
+/// .unwrap();
+/// assert!(metrics.mse.unwrap() > 0.0);
+/// assert!(metrics.ssim.unwrap() <= 1.0);
+///
+/// // Butteraugli needs the libjxl tools in the container.
+/// let result = compare_images(
+///     Path::new("tests/images/orig/1.png"),
+///     Path::new("tests/images/comp/1.jxl"),
+///     &[MetricKind::Butteraugli],
+/// );
+/// assert!(result.is_err());
+/// ```
+pub fn compare_images(
+    orig: &Path,
+    comp: &Path,
+    metrics: &[MetricKind],
+) -> Result<MetricSet, MetricError> {
+    // Reject metrics that need the Docker container before doing any work.
+    if let Some(kind) = metrics.iter().find(|kind| kind.requires_docker()) {
+        return Err(MetricError::RequiresDocker(*kind));
+    }
+
+    // Decode both images to 8-bit RGB.
+    let (orig_width, orig_height, orig_pixels) =
+        ImageReader::decode_rgb8(orig.to_str().unwrap())
+            .map_err(|e| MetricError::Decode(format!("{}: {}", orig.display(), e)))?;
+    let (comp_width, comp_height, comp_pixels) =
+        ImageReader::decode_rgb8(comp.to_str().unwrap())
+            .map_err(|e| MetricError::Decode(format!("{}: {}", comp.display(), e)))?;
+    if (orig_width, orig_height) != (comp_width, comp_height) {
+        return Err(MetricError::DimensionMismatch(
+            (orig_width, orig_height),
+            (comp_width, comp_height),
+        ));
+    }
+    for kind in metrics {
+        kind.check_image_size(orig_width, orig_height, &SsimWindow::DEFAULT)?;
+    }
+
+    Ok(compare_pixels(
+        &orig_pixels,
+        &comp_pixels,
+        orig_width,
+        orig_height,
+        metrics,
+        &SsimWindow::DEFAULT,
+    ))
+}
+
+/// Compare two decoded 8-bit RGB pixel buffers of the same dimensions with the metrics that do
+/// not require Docker. Metrics that require Docker, or that the images are too small for (see
+/// `MetricKind::min_image_size`), are left out.
+///
+/// # Arguments
+/// * `orig_pixels` - The interleaved RGB samples of the original image.
+/// * `comp_pixels` - The interleaved RGB samples of the compressed image.
+/// * `width` - The width of both images.
+/// * `height` - The height of both images.
+/// * `metrics` - The metrics to compute.
+/// * `ssim_window` - The window SSIM and MS-SSIM are computed over.
+///
+/// # Returns
+/// The requested metric values.
+pub fn compare_pixels(
+    orig_pixels: &[u8],
+    comp_pixels: &[u8],
+    width: u32,
+    height: u32,
+    metrics: &[MetricKind],
+    ssim_window: &SsimWindow,
+) -> MetricSet {
+    let mut metric_set = MetricSet::default();
+    for kind in metrics {
+        if kind.check_image_size(width, height, ssim_window).is_err() {
+            continue;
+        }
+        match kind {
+            MetricKind::Mse => {
+                metric_set.mse = Some(mse_rgb8(orig_pixels, comp_pixels));
+            }
+            MetricKind::Psnr => {
+                let mse = mse_rgb8(orig_pixels, comp_pixels);
+                metric_set.psnr = Some(ImageReader::calculate_psnr(mse, 255.0));
+            }
+            MetricKind::Ssim => {
+                metric_set.ssim = Some(ssim_luma(
+                    &rgb8_to_luma(orig_pixels),
+                    &rgb8_to_luma(comp_pixels),
+                    width as usize,
+                    height as usize,
+                    ssim_window,
+                ));
+            }
+            MetricKind::MsSsim => {
+                metric_set.ms_ssim = Some(ms_ssim_luma(
+                    &rgb8_to_luma(orig_pixels),
+                    &rgb8_to_luma(comp_pixels),
+                    width as usize,
+                    height as usize,
+                    ssim_window,
+                ));
+            }
+            MetricKind::Butteraugli | MetricKind::Ssimulacra2 => {}
+        }
+    }
+    metric_set
+}
+
+/// Compare two decoded 8-bit RGB pixel buffers like `compare_pixels`, computing the metrics on
+/// up to `threads` threads at once since they all read the same buffers.
+///
+/// # Arguments
+/// * `orig_pixels` - The interleaved RGB samples of the original image.
+/// * `comp_pixels` - The interleaved RGB samples of the compressed image.
+/// * `width` - The width of both images.
+/// * `height` - The height of both images.
+/// * `metrics` - The metrics to compute.
+/// * `ssim_window` - The window SSIM and MS-SSIM are computed over.
+/// * `threads` - The maximum number of metrics computed at once.
+///
+/// # Returns
+/// The requested metric values, the same as computed by `compare_pixels`.
+pub fn compare_pixels_parallel(
+    orig_pixels: &[u8],
+    comp_pixels: &[u8],
+    width: u32,
+    height: u32,
+    metrics: &[MetricKind],
+    ssim_window: &SsimWindow,
+    threads: usize,
+) -> MetricSet {
+    let mut metric_sets = vec![MetricSet::default(); metrics.len()];
+    let jobs = metric_sets
+        .iter_mut()
+        .zip(metrics)
+        .map(|(metric_set, kind)| -> Box<dyn FnOnce() + Send + '_> {
+            Box::new(move || {
+                *metric_set = compare_pixels(
+                    orig_pixels,
+                    comp_pixels,
+                    width,
+                    height,
+                    &[*kind],
+                    ssim_window,
+                )
+            })
+        })
+        .collect();
+    run_metric_jobs(jobs, threads);
+
+    metric_sets
+        .into_iter()
+        .fold(MetricSet::default(), |merged, metric_set| MetricSet {
+            mse: merged.mse.or(metric_set.mse),
+            psnr: merged.psnr.or(metric_set.psnr),
+            ssim: merged.ssim.or(metric_set.ssim),
+            ms_ssim: merged.ms_ssim.or(metric_set.ms_ssim),
+        })
+}
+
+/// Runs independent metric computations on up to `threads` threads at once, in batches in the
+/// order given. Each job writes its result to a value it borrows, so jobs may compute
+/// different kinds of values.
+/// With a single thread, the jobs run one after another on the calling thread.
+///
+/// # Arguments
+/// * `jobs` - The computations to run.
+/// * `threads` - The maximum number of jobs run at once.
+pub fn run_metric_jobs(jobs: Vec<Box<dyn FnOnce() + Send + '_>>, threads: usize) {
+    if threads <= 1 {
+        jobs.into_iter().for_each(|job| job());
+        return;
+    }
+
+    let mut jobs = jobs.into_iter().peekable();
+    while jobs.peek().is_some() {
+        let batch = jobs.by_ref().take(threads).collect::<Vec<_>>();
+        std::thread::scope(|scope| {
+            for job in batch {
+                scope.spawn(job);
+            }
+        });
+    }
+}
 
 /// Calculate the ratio of the file sizes of the original and compressed files.
 ///
@@ -25,6 +762,57 @@ pub fn file_size_ratio(orig: usize, comp: usize, denom: &str) -> f64 {
     }
 }
 
+/// Calculate the weighted mean of values.
+///
+/// # Arguments
+/// * `values` - The values to average.
+/// * `weights` - The weight of each value, values without weight do not count.
+///
+/// # Returns
+/// The weighted mean, or None if the total weight is 0.
+pub fn weighted_mean(values: &[f64], weights: &[f64]) -> Option<f64> {
+    let total_weight = weights.iter().sum::<f64>();
+    if total_weight <= 0.0 {
+        return None;
+    }
+    let weighted_sum = values
+        .iter()
+        .zip(weights)
+        .filter(|(_, weight)| **weight > 0.0)
+        .map(|(value, weight)| value * weight)
+        .sum::<f64>();
+    Some(weighted_sum / total_weight)
+}
+
+/// Calculate the minimum and mean decode time from the speed djxl reports for its decodes, e.g.
+/// `768 x 512, geomean: 39.32 MP/s [37.45, 40.96], 5 reps, 8 threads.`
+/// djxl reports the geometric mean of the speed, or the median for many decodes, and the range
+/// of the speed, so the fastest speed is the minimum time.
+///
+/// # Arguments
+/// * `djxl_output` - The output of djxl decoding with `--num_reps`.
+///
+/// # Returns
+/// The (min, mean) decode time in milliseconds, or None if the output has no speed.
+pub fn decode_time_stats(djxl_output: &str) -> Option<(f64, f64)> {
+    static PATTERN: OnceLock<Regex> = OnceLock::new();
+    let pattern = PATTERN.get_or_init(|| {
+        Regex::new(&format!(
+            r"(\d+) x (\d+),[^\[\n]*?\s({float}) MP/s \[({float}), ({float})\]",
+            float = FLOAT_PATTERN
+        ))
+        .unwrap()
+    });
+    let captures = pattern.captures(djxl_output)?;
+    let value = |i: usize| captures[i].parse::<f64>().ok();
+    let megapixels = value(1)? * value(2)? / 1e6;
+    let (mean_speed, max_speed) = (value(3)?, value(5)?);
+    if mean_speed <= 0.0 || max_speed <= 0.0 {
+        return None;
+    }
+    Some((megapixels / max_speed * 1000.0, megapixels / mean_speed * 1000.0))
+}
+
 /// Calculate the mean squared error (MSE) between two images.
 /// Just a wrapper around the ImageReader method for a more consistent API.
 ///
@@ -38,6 +826,20 @@ pub fn calculate_mse(orig_image_path: &String, comp_image_path: &String) -> f64
     ImageReader::calculate_mse(orig_image_path, comp_image_path)
 }
 
+/// Calculate the mean squared error (MSE) between two images along with the PSNR peak of the
+/// scale it was computed on, which is 1.0 for float images and 255.0 otherwise.
+/// Just a wrapper around the ImageReader method for a more consistent API.
+///
+/// # Arguments
+/// * `orig_image_path` - The path to the original image.
+/// * `comp_image_path` - The path to the compressed image.
+///
+/// # Returns
+/// The mean squared error between the two images and the peak value of its scale.
+pub fn calculate_mse_with_peak(orig_image_path: &String, comp_image_path: &String) -> (f64, f64) {
+    ImageReader::calculate_mse_with_peak(orig_image_path, comp_image_path)
+}
+
 /// Calculate the peak signal-to-noise ratio (PSNR) between two images.
 /// Just a wrapper around the ImageReader method for a more consistent API.
 ///
@@ -83,6 +885,320 @@ pub fn calculate_ssim(orig_image_path: &String, comp_image_path: &String) -> f64
         .unwrap()
 }
 
+/// Calculate the multi-scale structural similarity index (MS-SSIM) between two images.
+/// Computed natively on the luma channel of both images decoded to 8-bit RGB.
+///
+/// # Arguments
+/// * `orig_image_path` - The path to the original image.
+/// * `comp_image_path` - The path to the compressed image.
+/// * `ssim_window` - The window the SSIM of each scale is computed over.
+///
+/// # Returns
+/// The multi-scale structural similarity index between the two images, or an error if an image
+/// could not be decoded, the dimensions do not match, or the images are smaller than the SSIM
+/// window.
+pub fn calculate_ms_ssim(
+    orig_image_path: &String,
+    comp_image_path: &String,
+    ssim_window: &SsimWindow,
+) -> Result<f64, MetricError> {
+    let (width, height, orig_pixels) = ImageReader::decode_rgb8(orig_image_path)
+        .map_err(|e| MetricError::Decode(format!("{}: {}", orig_image_path, e)))?;
+    let (comp_width, comp_height, comp_pixels) = ImageReader::decode_rgb8(comp_image_path)
+        .map_err(|e| MetricError::Decode(format!("{}: {}", comp_image_path, e)))?;
+    if (width, height) != (comp_width, comp_height) {
+        return Err(MetricError::DimensionMismatch(
+            (width, height),
+            (comp_width, comp_height),
+        ));
+    }
+    MetricKind::MsSsim.check_image_size(width, height, ssim_window)?;
+    Ok(ms_ssim_luma(
+        &rgb8_to_luma(&orig_pixels),
+        &rgb8_to_luma(&comp_pixels),
+        width as usize,
+        height as usize,
+        ssim_window,
+    ))
+}
+
+/// Calculate the mean squared error between two 8-bit sample buffers of the same length.
+fn mse_rgb8(orig: &[u8], comp: &[u8]) -> f64 {
+    let sum = squared_error_sum(
+        orig.iter().map(|sample| *sample as f64),
+        comp.iter().map(|sample| *sample as f64),
+    );
+    sum / orig.len() as f64
+}
+
+/// The labels of the absolute error bins of `error_histogram`, on the 8-bit scale.
+pub const ERROR_HISTOGRAM_BINS: [&str; 8] =
+    ["0", "1-2", "3-4", "5-8", "9-16", "17-32", "33-64", ">64"];
+
+/// Count the absolute errors between two 8-bit sample buffers of the same length in bins of
+/// doubling width, to show whether the errors are concentrated or diffuse.
+///
+/// # Arguments
+/// * `orig` - The samples of the original image.
+/// * `comp` - The samples of the compressed image.
+///
+/// # Returns
+/// The number of samples in each bin of `ERROR_HISTOGRAM_BINS`.
+pub fn error_histogram(orig: &[u8], comp: &[u8]) -> [u64; 8] {
+    let mut histogram = [0; 8];
+    for (orig, comp) in orig.iter().zip(comp) {
+        let bin = match orig.abs_diff(*comp) {
+            0 => 0,
+            1..=2 => 1,
+            3..=4 => 2,
+            5..=8 => 3,
+            9..=16 => 4,
+            17..=32 => 5,
+            33..=64 => 6,
+            _ => 7,
+        };
+        histogram[bin] += 1;
+    }
+    histogram
+}
+
+/// Calculate the PSNR of each channel of two interleaved 8-bit RGB buffers of the same size.
+/// In the RGB space the overall PSNR is the PSNR of the mean of the channel MSEs.
+///
+/// # Arguments
+/// * `orig` - The samples of the original image.
+/// * `comp` - The samples of the compressed image.
+/// * `space` - The channel space to compare the images in.
+///
+/// # Returns
+/// The PSNR of the R, G and B, or Y, Cb and Cr channels.
+pub fn channel_psnr(orig: &[u8], comp: &[u8], space: ChannelSpace) -> [f64; 3] {
+    let convert = |pixels: &[u8]| {
+        pixels
+            .chunks_exact(3)
+            .map(|p| space.convert([p[0] as f64, p[1] as f64, p[2] as f64]))
+            .collect::<Vec<[f64; 3]>>()
+    };
+    let (orig, comp) = (convert(orig), convert(comp));
+    let pixels = orig.len().max(1) as f64;
+    [0, 1, 2].map(|channel| {
+        let sum = squared_error_sum(
+            orig.iter().map(|pixel| pixel[channel]),
+            comp.iter().map(|pixel| pixel[channel]),
+        );
+        ImageReader::calculate_psnr(sum / pixels, 255.0)
+    })
+}
+
+/// Sum the squared differences of two streams of samples.
+/// The samples are consumed one at a time without collecting them, and the sum is accumulated
+/// with Neumaier's compensated (Kahan) summation, so that millions of small squared errors are
+/// not lost to rounding when added to a large running sum.
+///
+/// # Arguments
+/// * `orig` - The samples of the original image.
+/// * `comp` - The samples of the compressed image, in the same order.
+///
+/// # Returns
+/// The sum of the squared differences over the samples of the shorter stream.
+pub fn squared_error_sum(
+    orig: impl IntoIterator<Item = f64>,
+    comp: impl IntoIterator<Item = f64>,
+) -> f64 {
+    let mut sum = 0.0f64;
+    let mut compensation = 0.0f64;
+    for (orig, comp) in orig.into_iter().zip(comp) {
+        let value = (orig - comp).powi(2);
+        let total = sum + value;
+        // Recover the low-order bits of the smaller operand that were lost in the addition.
+        if sum.abs() >= value.abs() {
+            compensation += (sum - total) + value;
+        } else {
+            compensation += (value - total) + sum;
+        }
+        sum = total;
+    }
+    sum + compensation
+}
+
+/// Convert interleaved 8-bit RGB samples to luma using the BT.601 weights.
+fn rgb8_to_luma(pixels: &[u8]) -> Vec<f64> {
+    pixels
+        .chunks_exact(3)
+        .map(|p| 0.299 * p[0] as f64 + 0.587 * p[1] as f64 + 0.114 * p[2] as f64)
+        .collect()
+}
+
+/// Build a normalized 1D Gaussian kernel.
+fn gaussian_kernel(size: usize, sigma: f64) -> Vec<f64> {
+    let center = (size / 2) as f64;
+    let kernel = (0..size)
+        .map(|i| {
+            let x = i as f64 - center;
+            (-(x * x) / (2.0 * sigma * sigma)).exp()
+        })
+        .collect::<Vec<f64>>();
+    let sum: f64 = kernel.iter().sum();
+    kernel.iter().map(|k| k / sum).collect()
+}
+
+/// Filter a single channel image with a separable kernel, keeping only the region where the
+/// kernel fully overlaps the image.
+///
+/// # Returns
+/// The filtered image and its width and height.
+fn filter_valid(data: &[f64], width: usize, height: usize, kernel: &[f64]) -> (Vec<f64>, usize, usize) {
+    let k = kernel.len();
+    let out_width = width - k + 1;
+    let out_height = height - k + 1;
+
+    // Filter the rows.
+    let mut rows = vec![0.0; out_width * height];
+    for y in 0..height {
+        for x in 0..out_width {
+            let mut acc = 0.0;
+            for i in 0..k {
+                acc += data[y * width + x + i] * kernel[i];
+            }
+            rows[y * out_width + x] = acc;
+        }
+    }
+
+    // Filter the columns.
+    let mut out = vec![0.0; out_width * out_height];
+    for y in 0..out_height {
+        for x in 0..out_width {
+            let mut acc = 0.0;
+            for i in 0..k {
+                acc += rows[(y + i) * out_width + x] * kernel[i];
+            }
+            out[y * out_width + x] = acc;
+        }
+    }
+    (out, out_width, out_height)
+}
+
+/// Compute the mean SSIM and the mean contrast-structure term between two luma images.
+///
+/// # Returns
+/// The (SSIM, contrast-structure) tuple.
+fn ssim_components(orig: &[f64], comp: &[f64], width: usize, height: usize, kernel: &[f64]) -> (f64, f64) {
+    let c1 = (0.01 * 255.0_f64).powi(2);
+    let c2 = (0.03 * 255.0_f64).powi(2);
+
+    let orig_sq = orig.iter().map(|v| v * v).collect::<Vec<f64>>();
+    let comp_sq = comp.iter().map(|v| v * v).collect::<Vec<f64>>();
+    let orig_comp = orig.iter().zip(comp).map(|(a, b)| a * b).collect::<Vec<f64>>();
+
+    let (mu_orig, out_width, out_height) = filter_valid(orig, width, height, kernel);
+    let (mu_comp, _, _) = filter_valid(comp, width, height, kernel);
+    let (sigma_orig_sq, _, _) = filter_valid(&orig_sq, width, height, kernel);
+    let (sigma_comp_sq, _, _) = filter_valid(&comp_sq, width, height, kernel);
+    let (sigma_orig_comp, _, _) = filter_valid(&orig_comp, width, height, kernel);
+
+    let mut ssim_sum = 0.0;
+    let mut cs_sum = 0.0;
+    for i in 0..out_width * out_height {
+        let mu_x = mu_orig[i];
+        let mu_y = mu_comp[i];
+        let var_x = sigma_orig_sq[i] - mu_x * mu_x;
+        let var_y = sigma_comp_sq[i] - mu_y * mu_y;
+        let cov = sigma_orig_comp[i] - mu_x * mu_y;
+
+        let luminance = (2.0 * mu_x * mu_y + c1) / (mu_x * mu_x + mu_y * mu_y + c1);
+        let contrast_structure = (2.0 * cov + c2) / (var_x + var_y + c2);
+        ssim_sum += luminance * contrast_structure;
+        cs_sum += contrast_structure;
+    }
+    let n = (out_width * out_height) as f64;
+    (ssim_sum / n, cs_sum / n)
+}
+
+/// Downsample a single channel image by a factor of two by averaging 2x2 blocks.
+///
+/// # Returns
+/// The downsampled image and its width and height.
+fn downsample(data: &[f64], width: usize, height: usize) -> (Vec<f64>, usize, usize) {
+    let out_width = width / 2;
+    let out_height = height / 2;
+    let mut out = vec![0.0; out_width * out_height];
+    for y in 0..out_height {
+        for x in 0..out_width {
+            out[y * out_width + x] = (data[2 * y * width + 2 * x]
+                + data[2 * y * width + 2 * x + 1]
+                + data[(2 * y + 1) * width + 2 * x]
+                + data[(2 * y + 1) * width + 2 * x + 1])
+                / 4.0;
+        }
+    }
+    (out, out_width, out_height)
+}
+
+/// Compute SSIM between two luma images over a window, by default an 11x11 Gaussian window
+/// (sigma 1.5).
+/// Images smaller than the window are compared with a window the size of the smallest side.
+pub fn ssim_luma(
+    orig: &[f64],
+    comp: &[f64],
+    width: usize,
+    height: usize,
+    window: &SsimWindow,
+) -> f64 {
+    let kernel = window.kernel(width.min(height));
+    ssim_components(orig, comp, width, height, &kernel).0
+}
+
+/// Compute MS-SSIM between two luma images over up to five scales.
+/// Scales at which the image would be smaller than the SSIM window are dropped and the remaining
+/// weights renormalized.
+pub fn ms_ssim_luma(
+    orig: &[f64],
+    comp: &[f64],
+    width: usize,
+    height: usize,
+    window: &SsimWindow,
+) -> f64 {
+    let kernel = window.kernel(window.size);
+
+    // Determine how many scales fit in the image.
+    let mut scales = 1;
+    while scales < MS_SSIM_WEIGHTS.len()
+        && (width >> scales) >= window.size
+        && (height >> scales) >= window.size
+    {
+        scales += 1;
+    }
+    if width < window.size || height < window.size {
+        return ssim_luma(orig, comp, width, height, window);
+    }
+    let weight_sum: f64 = MS_SSIM_WEIGHTS[..scales].iter().sum();
+
+    let mut orig = orig.to_vec();
+    let mut comp = comp.to_vec();
+    let (mut width, mut height) = (width, height);
+    let mut ms_ssim = 1.0;
+    for scale in 0..scales {
+        let weight = MS_SSIM_WEIGHTS[scale] / weight_sum;
+        let (ssim, cs) = ssim_components(&orig, &comp, width, height, &kernel);
+
+        // The contrast-structure term is used at every scale but the last, where the full SSIM
+        // (including luminance) is used instead. Negative values are clamped to keep the
+        // fractional powers real.
+        let value = if scale == scales - 1 { ssim } else { cs };
+        ms_ssim *= value.max(0.0).powf(weight);
+
+        if scale < scales - 1 {
+            let (next_orig, next_width, next_height) = downsample(&orig, width, height);
+            let (next_comp, _, _) = downsample(&comp, width, height);
+            orig = next_orig;
+            comp = next_comp;
+            width = next_width;
+            height = next_height;
+        }
+    }
+    ms_ssim
+}
+
 /// Calculate the Butteraugli perceptual distance between two images.
 /// Uses the libjxl Butteraugli tool in a Docker container to calculate the distance.
 ///
@@ -90,34 +1206,54 @@ pub fn calculate_ssim(orig_image_path: &String, comp_image_path: &String) -> f64
 /// * `docker_input_path` - The path to the original image in the Docker container.
 /// * `docker_output_path` - The path to the compressed image in the Docker container.
 /// * `docker_manager` - The DockerManager instance to execute the Butteraugli command.
+/// * `intensity_target` - The display peak luminance in nits for HDR images, if any.
 ///
 /// # Returns
-/// The Butteraugli perceptual distance between the two images and the p-norm value, as a tuple.
+/// The Butteraugli perceptual distance between the two images and the p-norm value, as a tuple,
+/// or an error if the tool could not be run, e.g. because the run was cancelled, or its output
+/// has no distance.
 pub fn calculate_butteraugli(
     docker_input_path: &str,
     docker_output_path: &str,
     docker_manager: &DockerManager,
-) -> (f64, f64) {
+    intensity_target: Option<f64>,
+) -> Result<(f64, f64), Box<dyn Error>> {
     let result = docker_manager.execute_butteraugli(
         docker_input_path.to_string().clone(),
         docker_output_path.to_string().clone(),
-    );
-    let result = result.unwrap();
-    let output = result.clone().unwrap_err();
-
-    // Butteraugli may fail because of libpng warning: iCCP: known incorrect sRGB profile
-    let butteraugli = output.lines().next().unwrap().parse::<f64>().unwrap_or(0.0);
+        intensity_target,
+    )?;
+    let (Ok(output) | Err(output)) = result;
+    parse_butteraugli_output(&output)
+}
 
-    let pnorm = output
-        .lines()
-        .last()
-        .unwrap()
-        .split_whitespace()
-        .last()
-        .unwrap();
-    let pnorm = pnorm.parse::<f64>().unwrap_or(0.0);
+/// Parses the distance and p-norm from the output of the Butteraugli tool, e.g.
+/// `0.5` followed by `3-norm: 0.25`. Warnings may be printed around them, e.g.
+/// `libpng warning: iCCP: known incorrect sRGB profile`, which also makes the tool fail.
+///
+/// # Arguments
+/// * `output` - The output of the tool.
+///
+/// # Returns
+/// The distance and the p-norm, NaN if the output has none, or an error with the raw output if
+/// it has no distance.
+pub fn parse_butteraugli_output(output: &str) -> Result<(f64, f64), Box<dyn Error>> {
+    static PATTERNS: OnceLock<(Regex, Regex)> = OnceLock::new();
+    let (distance_pattern, pnorm_pattern) = PATTERNS.get_or_init(|| {
+        (
+            Regex::new(&format!(r"(?m)^\s*({})\s*$", FLOAT_PATTERN)).unwrap(),
+            Regex::new(&format!(r"(?m)^\s*\d+-norm:\s*({})\s*$", FLOAT_PATTERN)).unwrap(),
+        )
+    });
+    let butteraugli = capture_value(distance_pattern, output).ok_or_else(|| {
+        MetricError::UnparsableOutput(
+            MetricKind::Butteraugli.name().to_string(),
+            output.to_string(),
+        )
+    })?;
+    let pnorm = capture_value(pnorm_pattern, output).unwrap_or(f64::NAN);
 
-    (butteraugli, pnorm)
+    Ok((butteraugli, pnorm))
 }
 
 /// Calculate the SSIMULACRA2 perceptual distance between two images.
@@ -127,18 +1263,134 @@ pub fn calculate_butteraugli(
 /// * `docker_input_path` - The path to the original image in the Docker container.
 /// * `docker_output_path` - The path to the compressed image in the Docker container.
 /// * `docker_manager` - The DockerManager instance to execute the SSIMULACRA2 command.
+/// * `intensity_target` - The display peak luminance in nits for HDR images, if any.
 ///
 /// # Returns
-/// The SSIMULACRA2 perceptual distance between the two images.
+/// The SSIMULACRA2 perceptual distance between the two images, or an error with the raw output
+/// if the tool failed or its output has no value, or with the reason it could not be run.
 pub fn calculate_ssimulacra2(
     docker_input_path: &str,
     docker_output_path: &str,
     docker_manager: &DockerManager,
-) -> f64 {
+    intensity_target: Option<f64>,
+) -> Result<f64, MetricError> {
     let result = docker_manager.execute_ssimulacra2(
         docker_input_path.to_string().clone(),
         docker_output_path.to_string().clone(),
+        intensity_target,
     );
-    let output = result.unwrap().unwrap();
-    output.lines().next().unwrap().parse::<f64>().unwrap()
+    let result = result.map_err(|e| {
+        MetricError::Tool(MetricKind::Ssimulacra2.name().to_string(), e.to_string())
+    })?;
+    match result {
+        Ok(output) => parse_ssimulacra2_output(&output),
+        Err(output) => Err(MetricError::UnparsableOutput(
+            MetricKind::Ssimulacra2.name().to_string(),
+            output,
+        )),
+    }
+}
+
+/// A number as the metric tools print it, e.g. `85.125`, `-12.25` or `1e-3`.
+pub const FLOAT_PATTERN: &str = r"[-+]?(?:\d+\.?\d*|\.\d+)(?:[eE][-+]?\d+)?";
+
+/// Captures a value from the output of a metric tool with a pattern.
+/// The first match whose first capture group is a number is used.
+///
+/// # Arguments
+/// * `pattern` - The pattern, with a capture group for the value.
+/// * `output` - The output of the tool.
+///
+/// # Returns
+/// The captured value, or None if the pattern does not match a number.
+pub fn capture_value(pattern: &Regex, output: &str) -> Option<f64> {
+    pattern
+        .captures_iter(output)
+        .find_map(|captures| captures.get(1)?.as_str().trim().parse::<f64>().ok())
+}
+
+/// Parses the score from the output of the SSIMULACRA2 tool.
+/// Builds differ in whether they print the bare score or a label before it, e.g.
+/// `SSIMULACRA2 score: 72.5`, and warnings may be printed around it, so the score is the first
+/// line holding only a number after an optional label.
+///
+/// # Arguments
+/// * `output` - The output of the tool.
+///
+/// # Returns
+/// The SSIMULACRA2 score, or an error with the raw output if no line has a score.
+pub fn parse_ssimulacra2_output(output: &str) -> Result<f64, MetricError> {
+    static PATTERN: OnceLock<Regex> = OnceLock::new();
+    let pattern = PATTERN.get_or_init(|| {
+        Regex::new(&format!(
+            r"(?im)^\s*(?:ssimulacra ?2\s*)?(?:score\s*)?[:=]?\s*({})\s*$",
+            FLOAT_PATTERN
+        ))
+        .unwrap()
+    });
+    capture_value(pattern, output).ok_or_else(|| {
+        MetricError::UnparsableOutput(
+            MetricKind::Ssimulacra2.name().to_string(),
+            output.to_string(),
+        )
+    })
+}
+
+/// Calculate an external metric between two images with its user-provided tool.
+/// The tool is run with the original and compressed image as its arguments, either locally on
+/// the local paths or in the Docker container on the container paths.
+///
+/// # Arguments
+/// * `metric` - The external metric to calculate.
+/// * `orig_image_path` - The local path to the original image.
+/// * `comp_image_path` - The local path to the compressed image.
+/// * `docker_input_path` - The path to the original image in the Docker container.
+/// * `docker_output_path` - The path to the compressed image in the Docker container.
+/// * `docker_manager` - The DockerManager instance to execute container tools with.
+///
+/// # Returns
+/// The value captured from the output of the tool, or an error if the tool could not be run or
+/// its output does not match the pattern of the metric.
+pub fn calculate_external_metric(
+    metric: &ExternalMetric,
+    orig_image_path: &str,
+    comp_image_path: &str,
+    docker_input_path: &str,
+    docker_output_path: &str,
+    docker_manager: &DockerManager,
+) -> Result<f64, Box<dyn Error>> {
+    // Local tools may print their result to either stream, so both are searched.
+    let output = if metric.in_container {
+        match docker_manager
+            .execute_in_container(&metric.binary, vec![docker_input_path, docker_output_path])?
+        {
+            Ok(output) => output,
+            Err(output) => return Err(format!("{} failed: {}", metric.name, output.trim()).into()),
+        }
+    } else {
+        let output = Command::new(&metric.binary)
+            .arg(orig_image_path)
+            .arg(comp_image_path)
+            .output()?;
+        if !output.status.success() {
+            return Err(format!(
+                "{} failed: {}",
+                metric.name,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )
+            .into());
+        }
+        format!(
+            "{}\n{}",
+            String::from_utf8_lossy(&output.stdout),
+            String::from_utf8_lossy(&output.stderr)
+        )
+    };
+    metric.parse_output(&output).ok_or_else(|| {
+        format!(
+            "The output of {} does not match the pattern {}",
+            metric.name, metric.pattern
+        )
+        .into()
+    })
 }
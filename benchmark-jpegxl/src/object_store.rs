@@ -0,0 +1,60 @@
+use crate::utils::hash_file;
+
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+/// A content-addressed store of compressed images, so that identical encodes, e.g. of commits
+/// that do not change the encoder, are only kept once on disk.
+/// Each output is stored as `objects/{sha256}.jxl` in the benchmark directory and replaced by a
+/// symlink to its object, so that it is still found at its usual path.
+#[derive(Debug, Clone)]
+pub struct ObjectStore {
+    dir: String,
+}
+
+impl ObjectStore {
+    /// The name of the object directory in the benchmark directory.
+    pub const DIR_NAME: &'static str = "objects";
+
+    /// Creates the object store of a benchmark directory.
+    ///
+    /// # Arguments
+    /// * `benchmark_dir` - The benchmark directory.
+    pub fn new(benchmark_dir: &str) -> ObjectStore {
+        ObjectStore {
+            dir: format!("{}/{}", benchmark_dir, ObjectStore::DIR_NAME),
+        }
+    }
+
+    /// Gets the path of the object of a hash.
+    ///
+    /// # Arguments
+    /// * `hash` - The SHA-256 of the content as a hex string.
+    pub fn object_path(&self, hash: &str) -> String {
+        format!("{}/{}.jxl", self.dir, hash)
+    }
+
+    /// Moves a compressed image into the store and replaces it with a symlink to its object.
+    /// If the store already has an object with the same content, the image is dropped in favour
+    /// of the existing object.
+    ///
+    /// # Arguments
+    /// * `file_path` - The path of the compressed image.
+    ///
+    /// # Returns
+    /// The path of the object, or an error if the image could not be hashed, moved or linked.
+    pub fn store(&self, file_path: &str) -> Result<String, Box<dyn Error>> {
+        let object_path = self.object_path(&hash_file(file_path)?);
+        fs::create_dir_all(&self.dir)?;
+        match Path::new(&object_path).exists() {
+            true => fs::remove_file(file_path)?,
+            false => fs::rename(file_path, &object_path)?,
+        }
+
+        // Link to the absolute path, so the link does not depend on where the output is.
+        let object_path = fs::canonicalize(&object_path)?;
+        std::os::unix::fs::symlink(&object_path, file_path)?;
+        Ok(object_path.to_str().unwrap().to_string())
+    }
+}
@@ -0,0 +1,83 @@
+use crate::image_reader::{ImageFormat, ImageReader};
+use crate::utils::hash_file;
+
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Numbers the temporary files references are written to, so that concurrent writes of the same
+/// reference do not collide.
+static TEMP_FILE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Whether a reference was decoded in this call or read from the cache.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReferenceStatus {
+    /// The source was decoded and its reference written to the cache.
+    Written,
+    /// The reference of the source was already in the cache.
+    Reused,
+    /// The source is used as is, since it is already a PNG or has float samples that a PNG
+    /// cannot hold.
+    Source,
+}
+
+/// A directory of decoded, normalized PNG references of source images, shared across runs so
+/// that sources in other formats, e.g. JPEG or WebP, are only decoded once.
+/// References are named by the SHA-256 of the source file, so a changed source is decoded again
+/// instead of reusing a stale reference.
+#[derive(Debug, Clone)]
+pub struct ReferenceCache {
+    dir: String,
+}
+
+impl ReferenceCache {
+    /// Creates a reference cache in a directory, which is created on first use.
+    ///
+    /// # Arguments
+    /// * `dir` - The directory of the references.
+    pub fn new(dir: &str) -> ReferenceCache {
+        ReferenceCache {
+            dir: dir.to_string(),
+        }
+    }
+
+    /// Gets the PNG reference of a source image, decoding it into the cache if needed.
+    ///
+    /// # Arguments
+    /// * `source_path` - The path to the source image.
+    ///
+    /// # Returns
+    /// The path the metrics read the source from and whether it was decoded, reused or is the
+    /// source itself, or an error if the source could not be decoded or the reference written.
+    pub fn reference(
+        &self,
+        source_path: &str,
+    ) -> Result<(String, ReferenceStatus), Box<dyn Error>> {
+        if ImageFormat::from_file_name(source_path) == ImageFormat::Png {
+            return Ok((source_path.to_string(), ReferenceStatus::Source));
+        }
+
+        let reference_path = format!("{}/{}.png", self.dir, hash_file(source_path)?);
+        if Path::new(&reference_path).exists() {
+            return Ok((reference_path, ReferenceStatus::Reused));
+        }
+
+        let image = ImageReader::normalize_color(image::open(source_path)?);
+        if let image::DynamicImage::ImageRgb32F(_) | image::DynamicImage::ImageRgba32F(_) = image {
+            return Ok((source_path.to_string(), ReferenceStatus::Source));
+        }
+
+        // Write to a temporary file first, so that a worker never reads a partial reference.
+        fs::create_dir_all(&self.dir)?;
+        let temp_path = format!(
+            "{}.{}.{}.tmp",
+            reference_path,
+            std::process::id(),
+            TEMP_FILE_COUNTER.fetch_add(1, Ordering::SeqCst)
+        );
+        image.save_with_format(&temp_path, image::ImageFormat::Png)?;
+        fs::rename(&temp_path, &reference_path)?;
+        Ok((reference_path, ReferenceStatus::Written))
+    }
+}
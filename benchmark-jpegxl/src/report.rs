@@ -0,0 +1,389 @@
+use crate::benchmark::JXLCompressionBenchmark;
+use crate::binary_results::read_comparisons;
+use crate::config::SummaryWeighting;
+use crate::csv_writer::{AggregateResult, ComparisonResult};
+
+use std::error::Error;
+use std::fmt::Write;
+use std::path::Path;
+
+/// The metrics charted against the bits per pixel, with their average in an aggregate.
+const CHART_METRICS: [(&str, fn(&AggregateResult) -> f64); 7] = [
+    ("MSE", |aggregate| aggregate.avg_mse),
+    ("PSNR", |aggregate| aggregate.avg_psnr),
+    ("SSIM", |aggregate| aggregate.avg_ssim),
+    ("MS-SSIM", |aggregate| aggregate.avg_ms_ssim),
+    ("Butteraugli", |aggregate| aggregate.avg_butteraugli),
+    ("Butteraugli 3-Norm", |aggregate| {
+        aggregate.avg_butteraugli_pnorm
+    }),
+    ("SSIMULACRA2", |aggregate| aggregate.avg_ssimulacra2),
+];
+
+/// The colors of the lines of the efforts in a chart, reused when there are more efforts.
+const SERIES_COLORS: [&str; 6] = [
+    "#1f77b4", "#ff7f0e", "#2ca02c", "#d62728", "#9467bd", "#8c564b",
+];
+
+/// A self-contained HTML report of the comparison results of a run.
+/// Every section holds the aggregate table, a table of the images and a rate-distortion chart
+/// per metric of one test set and commit. Charts are inline SVG, so the report needs no scripts
+/// or other files to be viewed.
+#[derive(Debug, Default)]
+pub struct HtmlReport {
+    sections: Vec<(String, Vec<ComparisonResult>)>,
+    weighting: SummaryWeighting,
+}
+
+impl HtmlReport {
+    /// The width of a chart, including its margins.
+    pub const CHART_WIDTH: u32 = 480;
+    /// The height of a chart, including its margins.
+    pub const CHART_HEIGHT: u32 = 320;
+    /// The margin around the plot area of a chart, which holds the axis labels.
+    const CHART_MARGIN: u32 = 48;
+
+    pub fn new() -> Self {
+        HtmlReport {
+            sections: Vec::new(),
+            weighting: SummaryWeighting::Equal,
+        }
+    }
+
+    /// Creates a report whose averages weigh the images.
+    ///
+    /// # Arguments
+    /// * `weighting` - How the images are weighted in the averages.
+    pub fn with_weighting(weighting: SummaryWeighting) -> Self {
+        HtmlReport {
+            sections: Vec::new(),
+            weighting,
+        }
+    }
+
+    /// Returns the names of the metrics charted in every section, in chart order.
+    pub fn chart_metrics() -> Vec<&'static str> {
+        CHART_METRICS.iter().map(|(name, _)| *name).collect()
+    }
+
+    /// Adds a section for the comparison results of a test set and commit.
+    ///
+    /// # Arguments
+    /// * `title` - The heading of the section.
+    /// * `results` - The comparison results.
+    pub fn add_section(&mut self, title: &str, results: Vec<ComparisonResult>) {
+        self.sections.push((title.to_string(), results));
+    }
+
+    /// Adds a section for a comparison results CSV file.
+    ///
+    /// # Arguments
+    /// * `title` - The heading of the section.
+    /// * `comparisons_file` - The comparison results CSV file.
+    ///
+    /// # Returns
+    /// An error if the file could not be read.
+    pub fn add_comparisons_file(
+        &mut self,
+        title: &str,
+        comparisons_file: &str,
+    ) -> Result<(), Box<dyn Error>> {
+        let results = read_comparisons(comparisons_file)?;
+        self.add_section(title, results);
+        Ok(())
+    }
+
+    /// Renders the report as an HTML document.
+    pub fn render(&self) -> String {
+        let mut html = String::new();
+        html.push_str("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n");
+        html.push_str("<title>JPEG XL benchmark report</title>\n<style>\n");
+        html.push_str("body { font-family: sans-serif; margin: 2em; }\n");
+        html.push_str("table { border-collapse: collapse; margin-bottom: 1.5em; }\n");
+        html.push_str(
+            "th, td { border: 1px solid #ccc; padding: 0.25em 0.5em; text-align: right; }\n",
+        );
+        html.push_str("th:first-child, td:first-child { text-align: left; }\n");
+        html.push_str(".chart { margin: 0 1em 1em 0; }\n");
+        html.push_str("</style>\n</head>\n<body>\n<h1>JPEG XL benchmark report</h1>\n");
+
+        for (title, results) in &self.sections {
+            let aggregates =
+                JXLCompressionBenchmark::aggregate_results(title, results, self.weighting);
+            let _ = writeln!(html, "<section>\n<h2>{}</h2>", HtmlReport::escape(title));
+
+            match self.weighting {
+                SummaryWeighting::Equal => {
+                    html.push_str("<h3>Averages per distance and effort</h3>\n")
+                }
+                weighting => {
+                    let _ = writeln!(
+                        html,
+                        "<h3>Averages per distance and effort, weighted by {}</h3>",
+                        weighting.name()
+                    );
+                }
+            }
+            html.push_str(&HtmlReport::aggregate_table(&aggregates));
+
+            html.push_str("<h3>Images</h3>\n");
+            html.push_str(&HtmlReport::image_table(results));
+
+            html.push_str("<h3>Rate-distortion</h3>\n<div>\n");
+            for (metric, value) in CHART_METRICS.iter() {
+                html.push_str(&HtmlReport::chart(metric, *value, &aggregates));
+            }
+            html.push_str("</div>\n</section>\n");
+        }
+
+        html.push_str("</body>\n</html>\n");
+        html
+    }
+
+    /// Renders the report and writes it to a file.
+    ///
+    /// # Arguments
+    /// * `out_path` - The path to write the report to.
+    ///
+    /// # Returns
+    /// The path to the report, or an error if it could not be written.
+    pub fn save(&self, out_path: &str) -> Result<String, Box<dyn Error>> {
+        if let Some(parent) = Path::new(out_path).parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(out_path, self.render())?;
+        Ok(out_path.to_string())
+    }
+
+    /// Renders the table of the averages per distance and effort.
+    fn aggregate_table(aggregates: &[AggregateResult]) -> String {
+        let mut table = String::from(
+            "<table>\n<tr><th>Distance</th><th>Effort</th><th>Images</th><th>BPP</th>",
+        );
+        for (metric, _) in CHART_METRICS.iter() {
+            let _ = write!(table, "<th>{}</th>", metric);
+        }
+        table.push_str("</tr>\n");
+        for aggregate in aggregates {
+            let _ = write!(
+                table,
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td>",
+                aggregate.distance,
+                aggregate.effort,
+                aggregate.num_images,
+                HtmlReport::format_value(aggregate.avg_bpp)
+            );
+            for (_, value) in CHART_METRICS.iter() {
+                let _ = write!(
+                    table,
+                    "<td>{}</td>",
+                    HtmlReport::format_value(value(aggregate))
+                );
+            }
+            table.push_str("</tr>\n");
+        }
+        table.push_str("</table>\n");
+        table
+    }
+
+    /// Renders the table with one row per original image, averaging its successful encodes.
+    fn image_table(results: &[ComparisonResult]) -> String {
+        let mut images = Vec::<(&str, Vec<&ComparisonResult>)>::new();
+        for result in results {
+            match images
+                .iter_mut()
+                .find(|(image, _)| *image == result.orig_image_name)
+            {
+                Some((_, image_results)) => image_results.push(result),
+                None => images.push((result.orig_image_name.as_str(), vec![result])),
+            }
+        }
+        images.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        let mut table = String::from(
+            "<table>\n<tr><th>Image</th><th>Encodes</th><th>Failed</th><th>BPP</th><th>PSNR</th><th>Butteraugli</th><th>SSIMULACRA2</th></tr>\n",
+        );
+        for (image, image_results) in images {
+            let ok = image_results
+                .iter()
+                .filter(|result| result.is_ok())
+                .collect::<Vec<&&ComparisonResult>>();
+            let avg = |value: fn(&ComparisonResult) -> f64| match ok.is_empty() {
+                true => f64::NAN,
+                false => ok.iter().map(|result| value(result)).sum::<f64>() / ok.len() as f64,
+            };
+            let _ = writeln!(
+                table,
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                HtmlReport::escape(image),
+                ok.len(),
+                image_results.len() - ok.len(),
+                HtmlReport::format_value(avg(|result| result.bpp)),
+                HtmlReport::format_value(avg(|result| result.psnr)),
+                HtmlReport::format_value(avg(|result| result.butteraugli)),
+                HtmlReport::format_value(avg(|result| result.ssimulacra2)),
+            );
+        }
+        table.push_str("</table>\n");
+        table
+    }
+
+    /// Renders the rate-distortion chart of a metric as inline SVG, with a line per effort
+    /// through the averages of its distances ordered by bits per pixel.
+    /// Averages that are not finite, e.g. the PSNR of a lossless encode, are left out.
+    fn chart(
+        metric: &str,
+        value: fn(&AggregateResult) -> f64,
+        aggregates: &[AggregateResult],
+    ) -> String {
+        let (width, height, margin) = (
+            HtmlReport::CHART_WIDTH as f64,
+            HtmlReport::CHART_HEIGHT as f64,
+            HtmlReport::CHART_MARGIN as f64,
+        );
+        let mut svg = String::new();
+        let _ = writeln!(
+            svg,
+            "<svg class=\"chart\" data-metric=\"{}\" xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" viewBox=\"0 0 {} {}\">",
+            HtmlReport::escape(metric),
+            width,
+            height,
+            width,
+            height
+        );
+        let _ = writeln!(
+            svg,
+            "<text x=\"{}\" y=\"20\" text-anchor=\"middle\" font-size=\"14\">{} vs BPP</text>",
+            width / 2.0,
+            HtmlReport::escape(metric)
+        );
+        let _ = writeln!(
+            svg,
+            "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"none\" stroke=\"#888\"/>",
+            margin,
+            margin,
+            width - 2.0 * margin,
+            height - 2.0 * margin
+        );
+
+        let points = aggregates
+            .iter()
+            .map(|aggregate| (aggregate.effort, aggregate.avg_bpp, value(aggregate)))
+            .filter(|(_, bpp, value)| bpp.is_finite() && value.is_finite())
+            .collect::<Vec<(u32, f64, f64)>>();
+        if points.is_empty() {
+            let _ = writeln!(
+                svg,
+                "<text x=\"{}\" y=\"{}\" text-anchor=\"middle\" font-size=\"12\">No data</text>",
+                width / 2.0,
+                height / 2.0
+            );
+            svg.push_str("</svg>\n");
+            return svg;
+        }
+
+        // Scale the points to the plot area, widening empty ranges so single points are centered.
+        let range = |values: Vec<f64>| {
+            let min = values.iter().cloned().fold(f64::INFINITY, f64::min);
+            let max = values.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+            match max > min {
+                true => (min, max),
+                false => (min - 0.5, max + 0.5),
+            }
+        };
+        let (min_x, max_x) = range(points.iter().map(|(_, bpp, _)| *bpp).collect());
+        let (min_y, max_y) = range(points.iter().map(|(_, _, value)| *value).collect());
+        let scale_x = |bpp: f64| margin + (bpp - min_x) / (max_x - min_x) * (width - 2.0 * margin);
+        let scale_y = |value: f64| {
+            height - margin - (value - min_y) / (max_y - min_y) * (height - 2.0 * margin)
+        };
+
+        // Label the axes with their ranges.
+        let _ = writeln!(
+            svg,
+            "<text x=\"{}\" y=\"{}\" font-size=\"10\">{}</text><text x=\"{}\" y=\"{}\" text-anchor=\"end\" font-size=\"10\">{}</text>",
+            margin,
+            height - margin + 14.0,
+            HtmlReport::format_value(min_x),
+            width - margin,
+            height - margin + 14.0,
+            HtmlReport::format_value(max_x)
+        );
+        let _ = writeln!(
+            svg,
+            "<text x=\"{}\" y=\"{}\" text-anchor=\"end\" font-size=\"10\">{}</text><text x=\"{}\" y=\"{}\" text-anchor=\"end\" font-size=\"10\">{}</text>",
+            margin - 4.0,
+            height - margin,
+            HtmlReport::format_value(min_y),
+            margin - 4.0,
+            margin + 10.0,
+            HtmlReport::format_value(max_y)
+        );
+        let _ = writeln!(
+            svg,
+            "<text x=\"{}\" y=\"{}\" text-anchor=\"middle\" font-size=\"12\">BPP</text>",
+            width / 2.0,
+            height - 12.0
+        );
+
+        // Draw a line per effort.
+        let mut efforts = points
+            .iter()
+            .map(|(effort, _, _)| *effort)
+            .collect::<Vec<u32>>();
+        efforts.sort();
+        efforts.dedup();
+        for (index, effort) in efforts.iter().enumerate() {
+            let color = SERIES_COLORS[index % SERIES_COLORS.len()];
+            let mut series = points
+                .iter()
+                .filter(|(point_effort, _, _)| point_effort == effort)
+                .map(|(_, bpp, value)| (scale_x(*bpp), scale_y(*value)))
+                .collect::<Vec<(f64, f64)>>();
+            series.sort_by(|a, b| a.0.total_cmp(&b.0));
+            let coordinates = series
+                .iter()
+                .map(|(x, y)| format!("{:.1},{:.1}", x, y))
+                .collect::<Vec<String>>()
+                .join(" ");
+            let _ = writeln!(
+                svg,
+                "<polyline points=\"{}\" fill=\"none\" stroke=\"{}\" stroke-width=\"1.5\"/>",
+                coordinates, color
+            );
+            for (x, y) in &series {
+                let _ = writeln!(
+                    svg,
+                    "<circle cx=\"{:.1}\" cy=\"{:.1}\" r=\"2.5\" fill=\"{}\"/>",
+                    x, y, color
+                );
+            }
+            let _ = writeln!(
+                svg,
+                "<text x=\"{}\" y=\"{}\" font-size=\"10\" fill=\"{}\">e{}</text>",
+                width - margin + 4.0,
+                margin + 12.0 * (index as f64 + 1.0),
+                color,
+                effort
+            );
+        }
+        svg.push_str("</svg>\n");
+        svg
+    }
+
+    /// Formats a metric value with 4 decimals, or "-" if it is not finite.
+    fn format_value(value: f64) -> String {
+        match value.is_finite() {
+            true => format!("{:.4}", value),
+            false => "-".to_string(),
+        }
+    }
+
+    /// Escapes text for use in HTML.
+    fn escape(text: &str) -> String {
+        text.replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;")
+            .replace('"', "&quot;")
+    }
+}
@@ -0,0 +1,173 @@
+use crate::docker_manager::DockerManager;
+
+use log::warn;
+use std::error::Error;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+/// A sample of a container's resource counters as a (peak memory in bytes, cumulative CPU time
+/// in microseconds) tuple.
+pub type ResourceSample = (u64, u64);
+
+/// The resources used while running a command, e.g. an encode.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ResourceUsage {
+    /// The peak memory charged to the container while the command ran, in bytes.
+    pub peak_rss_bytes: u64,
+    /// The CPU time used by the container while the command ran, in seconds.
+    pub cpu_seconds: f64,
+}
+
+/// Samples resource counters on a separate thread until stopped.
+pub struct ResourceMonitor {
+    stop: Arc<AtomicBool>,
+    handle: JoinHandle<(u64, Option<ResourceSample>, Option<ResourceSample>)>,
+}
+
+impl ResourceMonitor {
+    /// The interval between two samples.
+    pub const SAMPLE_INTERVAL: Duration = Duration::from_millis(50);
+
+    /// Starts sampling the cgroup counters of a worker's docker container.
+    /// The counters are read from the host, so sampling does not run inside the container and add
+    /// to them. Nothing is sampled if the counters cannot be opened.
+    ///
+    /// # Arguments
+    /// * `docker_manager` - The DockerManager of the container to sample.
+    ///
+    /// # Returns
+    /// The running ResourceMonitor.
+    pub fn start(docker_manager: &DockerManager) -> ResourceMonitor {
+        match docker_manager
+            .cgroup_dir()
+            .and_then(|cgroup_dir| CgroupCounters::open(&cgroup_dir))
+        {
+            Ok(mut counters) => ResourceMonitor::with_sampler(move || counters.sample().ok()),
+            Err(e) => {
+                warn!(
+                    "could not read the resource counters of the container: {}",
+                    e
+                );
+                ResourceMonitor::with_sampler(|| None)
+            }
+        }
+    }
+
+    /// Starts sampling with the given sampler.
+    /// The sampler is called once when starting, every `SAMPLE_INTERVAL` while running, and once
+    /// more when stopping. Samples that fail (None) are ignored.
+    ///
+    /// # Arguments
+    /// * `sampler` - The function returning the current resource counters.
+    ///
+    /// # Returns
+    /// The running ResourceMonitor.
+    pub fn with_sampler<F>(mut sampler: F) -> ResourceMonitor
+    where
+        F: FnMut() -> Option<ResourceSample> + Send + 'static,
+    {
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        let handle = std::thread::spawn(move || {
+            let first = sampler();
+            let mut last = first;
+            let mut peak = first.map(|sample| sample.0).unwrap_or(0);
+            loop {
+                let stopping = thread_stop.load(Ordering::SeqCst);
+                if let Some(sample) = sampler() {
+                    peak = peak.max(sample.0);
+                    last = Some(sample);
+                }
+                if stopping {
+                    break;
+                }
+                std::thread::sleep(ResourceMonitor::SAMPLE_INTERVAL);
+            }
+            (peak, first, last)
+        });
+        ResourceMonitor { stop, handle }
+    }
+
+    /// Stops sampling and waits for the sampling thread to finish.
+    ///
+    /// # Returns
+    /// The resources used between starting and stopping the monitor.
+    pub fn stop(self) -> ResourceUsage {
+        self.stop.store(true, Ordering::SeqCst);
+        let (peak, first, last) = self.handle.join().unwrap_or((0, None, None));
+        let cpu_usec = match (first, last) {
+            (Some(first), Some(last)) => last.1.saturating_sub(first.1),
+            _ => 0,
+        };
+        ResourceUsage {
+            peak_rss_bytes: peak,
+            cpu_seconds: cpu_usec as f64 / 1_000_000.0,
+        }
+    }
+}
+
+/// The cgroup v2 counters of a docker container, read from its cgroup directory on the host.
+pub struct CgroupCounters {
+    /// The open `memory.peak` file, which is read through the descriptor its peak was reset on.
+    memory_peak: File,
+    cpu_stat: PathBuf,
+}
+
+impl CgroupCounters {
+    /// Opens the counters of a cgroup and resets its memory peak, so that the peaks read
+    /// afterwards are the peaks since opening. Kernels before 6.12 cannot reset the peak, which is
+    /// then the peak since the container started.
+    ///
+    /// # Arguments
+    /// * `cgroup_dir` - The cgroup v2 directory of the container on the host.
+    ///
+    /// # Returns
+    /// The CgroupCounters, or an error if the counters cannot be opened.
+    pub fn open(cgroup_dir: &Path) -> Result<CgroupCounters, Box<dyn Error>> {
+        let mut memory_peak = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(cgroup_dir.join("memory.peak"))?;
+        let _ = memory_peak.write_all(b"reset");
+        Ok(CgroupCounters {
+            memory_peak,
+            cpu_stat: cgroup_dir.join("cpu.stat"),
+        })
+    }
+
+    /// Reads the current counters.
+    ///
+    /// # Returns
+    /// The resource sample, or an error if the counters could not be read.
+    pub fn sample(&mut self) -> Result<ResourceSample, Box<dyn Error>> {
+        let mut output = String::new();
+        self.memory_peak.seek(SeekFrom::Start(0))?;
+        self.memory_peak.read_to_string(&mut output)?;
+        output.push_str(&std::fs::read_to_string(&self.cpu_stat)?);
+        parse_cgroup_sample(&output).ok_or(Box::from("Failed to parse cgroup counters"))
+    }
+}
+
+/// Parses the output of reading the cgroup v2 `memory.peak` and `cpu.stat` files.
+///
+/// # Arguments
+/// * `output` - The contents of `memory.peak` followed by the contents of `cpu.stat`.
+///
+/// # Returns
+/// The resource sample, or None if the output could not be parsed.
+pub fn parse_cgroup_sample(output: &str) -> Option<ResourceSample> {
+    let mut lines = output.lines();
+    let memory = lines.next()?.trim().parse::<u64>().ok()?;
+    let cpu_usec = lines
+        .find(|line| line.starts_with("usage_usec"))?
+        .split_whitespace()
+        .last()?
+        .parse::<u64>()
+        .ok()?;
+    Some((memory, cpu_usec))
+}
@@ -0,0 +1,174 @@
+use crate::csv_writer::ComparisonResult;
+
+use rusqlite::{params, Connection};
+
+use std::error::Error;
+
+/// The schema of the results database. Commits are stored once and images once per run, since
+/// the images of a test set may change between runs, and both are referenced by the comparisons,
+/// which are keyed by the run, commit, image, distance, effort and quality.
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS commits (
+    id INTEGER PRIMARY KEY,
+    label TEXT NOT NULL UNIQUE
+);
+CREATE TABLE IF NOT EXISTS images (
+    id INTEGER PRIMARY KEY,
+    run INTEGER NOT NULL,
+    test_set TEXT NOT NULL,
+    name TEXT NOT NULL,
+    file_size INTEGER NOT NULL,
+    width INTEGER NOT NULL,
+    height INTEGER NOT NULL,
+    UNIQUE (run, test_set, name)
+);
+CREATE TABLE IF NOT EXISTS comparisons (
+    run INTEGER NOT NULL,
+    commit_id INTEGER NOT NULL REFERENCES commits (id),
+    image_id INTEGER NOT NULL REFERENCES images (id),
+    distance REAL NOT NULL,
+    effort INTEGER NOT NULL,
+    quality TEXT NOT NULL,
+    status TEXT NOT NULL,
+    comp_image_name TEXT NOT NULL,
+    comp_file_size INTEGER NOT NULL,
+    comp_file_size_ratio REAL,
+    bpp REAL,
+    mse REAL,
+    psnr REAL,
+    ssim REAL,
+    ms_ssim REAL,
+    butteraugli REAL,
+    butteraugli_pnorm REAL,
+    ssimulacra2 REAL,
+    cpu_seconds REAL,
+    peak_rss_bytes INTEGER NOT NULL,
+    decode_time_ms_min REAL,
+    decode_time_ms_mean REAL,
+    PRIMARY KEY (run, commit_id, image_id, distance, effort, quality)
+);
+";
+
+/// A SQLite database of the comparison results of all runs of a benchmark directory, so that
+/// results can be queried across runs, e.g. for the best effort per image over time.
+/// The per-run CSV files are written as usual; the database is an additional output.
+pub struct ResultsDatabase {
+    connection: Connection,
+}
+
+impl ResultsDatabase {
+    /// The name of the database file in the benchmark directory.
+    pub const FILE_NAME: &'static str = "results.sqlite";
+
+    /// Gets the path of the results database of a benchmark directory.
+    ///
+    /// # Arguments
+    /// * `benchmark_dir` - The benchmark directory.
+    pub fn path(benchmark_dir: &str) -> String {
+        format!("{}/{}", benchmark_dir, ResultsDatabase::FILE_NAME)
+    }
+
+    /// Opens a results database, creating it and its tables if they do not exist.
+    ///
+    /// # Arguments
+    /// * `file_name` - The path to the database file.
+    ///
+    /// # Returns
+    /// The ResultsDatabase, or an error if it could not be opened.
+    pub fn open(file_name: &str) -> Result<ResultsDatabase, Box<dyn Error>> {
+        let connection = Connection::open(file_name)?;
+        connection.execute_batch(SCHEMA)?;
+        Ok(ResultsDatabase { connection })
+    }
+
+    /// Gets the connection to the database, to query it.
+    pub fn connection(&self) -> &Connection {
+        &self.connection
+    }
+
+    /// Records the comparison results of a commit on a test set in a run. Results that were
+    /// already recorded for the same key are replaced, so recording them again is harmless.
+    /// NaN values, e.g. of metrics that were not computed, are stored as NULL.
+    ///
+    /// # Arguments
+    /// * `run` - The run the results belong to.
+    /// * `test_set` - The test set of the images.
+    /// * `commit` - The label of the libjxl commit the images were compressed with.
+    /// * `results` - The comparison results.
+    ///
+    /// # Returns
+    /// An error if the results could not be recorded, in which case none of them are.
+    pub fn insert(
+        &mut self,
+        run: usize,
+        test_set: &str,
+        commit: &str,
+        results: &[ComparisonResult],
+    ) -> Result<(), Box<dyn Error>> {
+        let real = |value: f64| if value.is_nan() { None } else { Some(value) };
+        let transaction = self.connection.transaction()?;
+        transaction.execute(
+            "INSERT OR IGNORE INTO commits (label) VALUES (?1)",
+            params![commit],
+        )?;
+        let commit_id: i64 = transaction.query_row(
+            "SELECT id FROM commits WHERE label = ?1",
+            params![commit],
+            |row| row.get(0),
+        )?;
+        for result in results {
+            transaction.execute(
+                "INSERT INTO images (run, test_set, name, file_size, width, height)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+                 ON CONFLICT (run, test_set, name) DO UPDATE SET
+                     file_size = excluded.file_size,
+                     width = excluded.width,
+                     height = excluded.height",
+                params![
+                    run,
+                    test_set,
+                    result.orig_image_name,
+                    result.orig_file_size,
+                    result.width,
+                    result.height
+                ],
+            )?;
+            let image_id: i64 = transaction.query_row(
+                "SELECT id FROM images WHERE run = ?1 AND test_set = ?2 AND name = ?3",
+                params![run, test_set, result.orig_image_name],
+                |row| row.get(0),
+            )?;
+            transaction.execute(
+                "INSERT OR REPLACE INTO comparisons VALUES
+                 (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17,
+                  ?18, ?19, ?20, ?21, ?22)",
+                params![
+                    run,
+                    commit_id,
+                    image_id,
+                    result.distance as f64,
+                    result.effort,
+                    result.quality,
+                    result.status,
+                    result.comp_image_name,
+                    result.comp_file_size,
+                    real(result.comp_file_size_ratio),
+                    real(result.bpp),
+                    real(result.mse),
+                    real(result.psnr),
+                    real(result.ssim),
+                    real(result.ms_ssim),
+                    real(result.butteraugli),
+                    real(result.butteraugli_pnorm),
+                    real(result.ssimulacra2),
+                    real(result.cpu_seconds),
+                    result.peak_rss_bytes,
+                    real(result.decode_time_ms_min),
+                    real(result.decode_time_ms_mean)
+                ],
+            )?;
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,129 @@
+use serde_derive::{Deserialize, Serialize};
+
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// The phases of a benchmark run that are timed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Phase {
+    /// Checking out and building libjxl for a commit.
+    Build,
+    /// Encoding images with cjxl.
+    Encode,
+    /// Decoding the compressed images.
+    Decode,
+    /// Computing the metrics of the compressed images.
+    Metrics,
+}
+
+/// The time spent in each phase of a benchmark run, shared by the benchmarker and its workers.
+/// The phases are summed over all workers, so with several workers they can exceed the wall
+/// time of the run.
+#[derive(Debug)]
+pub struct RunTiming {
+    started_at: SystemTime,
+    started: Instant,
+    phase_us: [AtomicU64; 4],
+    images: AtomicUsize,
+}
+
+impl Default for RunTiming {
+    fn default() -> Self {
+        RunTiming::new()
+    }
+}
+
+impl RunTiming {
+    /// Starts timing a run.
+    pub fn new() -> RunTiming {
+        RunTiming {
+            started_at: SystemTime::now(),
+            started: Instant::now(),
+            phase_us: Default::default(),
+            images: AtomicUsize::new(0),
+        }
+    }
+
+    /// Adds time spent in a phase.
+    ///
+    /// # Arguments
+    /// * `phase` - The phase the time was spent in.
+    /// * `duration` - The time spent.
+    pub fn add(&self, phase: Phase, duration: Duration) {
+        self.phase_us[phase as usize].fetch_add(duration.as_micros() as u64, Ordering::SeqCst);
+    }
+
+    /// Runs a step of a phase and adds the time it took.
+    ///
+    /// # Arguments
+    /// * `phase` - The phase the step belongs to.
+    /// * `step` - The step to run.
+    ///
+    /// # Returns
+    /// The result of the step.
+    pub fn time<T>(&self, phase: Phase, step: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let result = step();
+        self.add(phase, start.elapsed());
+        result
+    }
+
+    /// Counts an image whose benchmark finished.
+    pub fn image_finished(&self) {
+        self.images.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Summarizes the run up to now.
+    ///
+    /// # Returns
+    /// The wall time of the run, the time spent in each phase and the throughput.
+    pub fn summary(&self) -> RunTimingSummary {
+        let wall_time_s = self.started.elapsed().as_secs_f64();
+        let phase_s =
+            |phase: Phase| self.phase_us[phase as usize].load(Ordering::SeqCst) as f64 / 1e6;
+        let images = self.images.load(Ordering::SeqCst);
+        let unix_time = |time: SystemTime| {
+            time.duration_since(UNIX_EPOCH)
+                .map_or(0, |duration| duration.as_secs())
+        };
+        RunTimingSummary {
+            started_at: unix_time(self.started_at),
+            finished_at: unix_time(SystemTime::now()),
+            wall_time_s,
+            build_time_s: phase_s(Phase::Build),
+            encode_time_s: phase_s(Phase::Encode),
+            decode_time_s: phase_s(Phase::Decode),
+            metrics_time_s: phase_s(Phase::Metrics),
+            images,
+            images_per_s: match wall_time_s > 0.0 {
+                true => images as f64 / wall_time_s,
+                false => 0.0,
+            },
+        }
+    }
+}
+
+/// The timing of a finished benchmark run, recorded in its manifest.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RunTimingSummary {
+    /// The start of the run in seconds since the Unix epoch.
+    pub started_at: u64,
+    /// The end of the run in seconds since the Unix epoch.
+    pub finished_at: u64,
+    pub wall_time_s: f64,
+    /// The time spent in each phase, summed over the workers.
+    pub build_time_s: f64,
+    pub encode_time_s: f64,
+    pub decode_time_s: f64,
+    pub metrics_time_s: f64,
+    /// The number of images benchmarked, counted once per commit.
+    pub images: usize,
+    pub images_per_s: f64,
+}
+
+impl RunTimingSummary {
+    /// Returns the time spent in all phases together.
+    pub fn phase_time_s(&self) -> f64 {
+        self.build_time_s + self.encode_time_s + self.decode_time_s + self.metrics_time_s
+    }
+}
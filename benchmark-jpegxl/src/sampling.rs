@@ -0,0 +1,77 @@
+/// A small seeded pseudo-random number generator (SplitMix64), so that randomly sampled runs
+/// can be reproduced exactly from their seed on any platform.
+#[derive(Debug, Clone)]
+pub struct SeededRng {
+    state: u64,
+}
+
+impl SeededRng {
+    /// Creates a generator.
+    ///
+    /// # Arguments
+    /// * `seed` - The seed, the same seed always yields the same sequence.
+    ///
+    /// # Returns
+    /// The SeededRng.
+    pub fn new(seed: u64) -> SeededRng {
+        SeededRng { state: seed }
+    }
+
+    /// Returns the next pseudo-random number of the sequence.
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Returns a pseudo-random number below a bound.
+    ///
+    /// # Arguments
+    /// * `bound` - The exclusive upper bound, must be positive.
+    pub fn below(&mut self, bound: usize) -> usize {
+        (self.next_u64() % bound as u64) as usize
+    }
+}
+
+/// Derives the seed for an image from the seed of the run, so that each image samples its own
+/// combinations while the run stays reproducible.
+///
+/// # Arguments
+/// * `seed` - The seed of the run.
+/// * `image_name` - The name of the image.
+///
+/// # Returns
+/// The seed of the image.
+pub fn image_seed(seed: u64, image_name: &str) -> u64 {
+    // FNV-1a, which unlike the hasher of the standard library is stable across releases.
+    image_name
+        .bytes()
+        .fold(0xCBF2_9CE4_8422_2325 ^ seed, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01B3)
+        })
+}
+
+/// Samples distinct indices out of a range without replacement.
+///
+/// # Arguments
+/// * `total` - The number of indices to sample from.
+/// * `count` - The number of indices to sample, all indices if it is at least `total`.
+/// * `seed` - The seed of the sampling.
+///
+/// # Returns
+/// The sampled indices in ascending order.
+pub fn sample_indices(total: usize, count: usize, seed: u64) -> Vec<usize> {
+    // A partial Fisher-Yates shuffle moves a random index to each of the first count positions.
+    let mut indices = (0..total).collect::<Vec<usize>>();
+    let count = count.min(total);
+    let mut rng = SeededRng::new(seed);
+    for i in 0..count {
+        let j = i + rng.below(total - i);
+        indices.swap(i, j);
+    }
+    indices.truncate(count);
+    indices.sort_unstable();
+    indices
+}
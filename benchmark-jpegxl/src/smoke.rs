@@ -0,0 +1,159 @@
+use crate::benchmark::{Benchmarker, JXLCompressionBenchmark};
+use crate::binary_results::read_comparisons;
+use crate::config::Config;
+use crate::csv_writer::ComparisonResult;
+use crate::manifest::RunManifest;
+use crate::metrics::MetricKind;
+
+use image::{Rgb, RgbImage};
+
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+/// Checks that the benchmark pipeline works end to end on the local setup, without a dataset.
+/// A small gradient image is generated, encoded at one distance and effort with the configured
+/// libjxl, and compared to the original with every metric. The smoke test runs in its own
+/// `smoke` directory of the benchmark directory, which is recreated on every smoke test and
+/// left in place afterwards for inspection.
+pub struct SmokeTest {}
+
+impl SmokeTest {
+    /// The name of the test set of the generated image.
+    pub const TEST_SET: &'static str = "smoke";
+    /// The file name of the generated image.
+    pub const IMAGE_NAME: &'static str = "gradient.png";
+    /// The width and height of the generated image in pixels.
+    pub const IMAGE_SIZE: u32 = 64;
+    /// The distance the image is encoded at.
+    pub const DISTANCE: f64 = 1.0;
+    /// The effort the image is encoded with.
+    pub const EFFORT: u32 = 7;
+
+    /// Runs the smoke test.
+    ///
+    /// # Arguments
+    /// * `config` - The configuration of the benchmark, whose Docker and libjxl settings are
+    ///   used. The images, distances, efforts and outputs of the configuration are ignored.
+    ///
+    /// # Returns
+    /// The comparison result of the encode, or an error naming the first step that failed.
+    pub fn run(config: &Config) -> Result<ComparisonResult, Box<dyn Error>> {
+        let smoke_dir = format!("{}/{}", config.benchmark_dir_path, SmokeTest::TEST_SET);
+        if Path::new(&smoke_dir).exists() {
+            fs::remove_dir_all(&smoke_dir)?;
+        }
+        let config = SmokeTest::config(config, &smoke_dir);
+        SmokeTest::generate_image(
+            &Path::new(&config.local_test_image_dir_path)
+                .join(SmokeTest::TEST_SET)
+                .join(SmokeTest::IMAGE_NAME),
+        )?;
+
+        let mut benchmarker = Benchmarker::new(&config)?;
+        benchmarker.run_benchmark::<JXLCompressionBenchmark>()?;
+        benchmarker.wait_for_all_workers();
+        if let Some(message) = benchmarker.failures.messages().into_iter().next() {
+            return Err(Box::from(format!("The smoke test failed: {}", message)));
+        }
+
+        SmokeTest::check_results(&config.benchmark_dir_path, benchmarker.context.current_run)
+    }
+
+    /// Generates the smoke test image, a gradient with a different direction in each channel
+    /// so that every channel has detail to compress.
+    ///
+    /// # Arguments
+    /// * `path` - The path to write the PNG image to. Its directory is created if needed.
+    pub fn generate_image(path: &Path) -> Result<(), Box<dyn Error>> {
+        let max = SmokeTest::IMAGE_SIZE - 1;
+        let image = RgbImage::from_fn(SmokeTest::IMAGE_SIZE, SmokeTest::IMAGE_SIZE, |x, y| {
+            Rgb([
+                (x * 255 / max) as u8,
+                (y * 255 / max) as u8,
+                ((x + y) * 255 / (2 * max)) as u8,
+            ])
+        });
+        fs::create_dir_all(path.parent().unwrap())?;
+        image.save(path)?;
+        Ok(())
+    }
+
+    /// Builds the configuration of the smoke test from the configuration of the benchmark.
+    /// Only the settings that choose and build libjxl and its container are kept, everything
+    /// else is left at its default. The compressed image is held in memory, so the metrics do
+    /// not depend on tools installed on the local machine.
+    ///
+    /// # Arguments
+    /// * `config` - The configuration of the benchmark.
+    /// * `smoke_dir` - The directory the smoke test runs in.
+    pub fn config(config: &Config, smoke_dir: &str) -> Config {
+        Config {
+            benchmark_dir_path: format!("{}/benchmarks", smoke_dir),
+            local_test_image_dir_path: format!("{}/test_images", smoke_dir),
+            docker_file_path: config.docker_file_path.clone(),
+            docker_build_args: config.docker_build_args.clone(),
+            docker_context_dir: config.docker_context_dir.clone(),
+            docker_bin: config.docker_bin.clone(),
+            docker_test_image_dir_path: config.docker_test_image_dir_path.clone(),
+            libjxl_commit: config.libjxl_commit.clone(),
+            libjxl_src: config.libjxl_src.clone(),
+            prebuilt_cjxl: config.prebuilt_cjxl.clone(),
+            reuse_containers: config.reuse_containers,
+            log_level: config.log_level,
+            log_file: config.log_file.clone(),
+            num_workers: 1,
+            distances: vec![SmokeTest::DISTANCE],
+            qualities: vec![],
+            efforts: vec![SmokeTest::EFFORT],
+            keep_compressed: false,
+            fail_fast: true,
+            ..Config::default()
+        }
+    }
+
+    /// Checks the results of a smoke test run: the image must have been encoded once and every
+    /// metric must have a finite value.
+    ///
+    /// # Arguments
+    /// * `benchmark_dir` - The benchmark directory of the smoke test.
+    /// * `run` - The run of the smoke test.
+    ///
+    /// # Returns
+    /// The comparison result of the encode, or an error describing what is missing.
+    pub fn check_results(
+        benchmark_dir: &str,
+        run: usize,
+    ) -> Result<ComparisonResult, Box<dyn Error>> {
+        let manifest = RunManifest::load(benchmark_dir, run)?;
+        let comparisons_file = manifest
+            .comparisons_file(benchmark_dir, SmokeTest::TEST_SET)
+            .ok_or("The smoke test wrote no comparison results")?;
+        let mut results = read_comparisons(&comparisons_file)?;
+        if results.len() != 1 {
+            return Err(Box::from(format!(
+                "The smoke test wrote {} comparison results instead of 1 to {}",
+                results.len(),
+                comparisons_file
+            )));
+        }
+        let result = results.remove(0);
+        if !result.is_ok() {
+            return Err(Box::from(format!(
+                "The smoke test could not encode {}: {}",
+                SmokeTest::IMAGE_NAME,
+                result.status
+            )));
+        }
+        for metric in MetricKind::ALL {
+            if !result.metric(metric).is_finite() {
+                return Err(Box::from(format!(
+                    "The smoke test computed no {} value for {}",
+                    metric.name(),
+                    result.comp_image_name
+                )));
+            }
+        }
+        Ok(result)
+    }
+}
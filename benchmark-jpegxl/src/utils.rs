@@ -1,6 +1,38 @@
+use sha2::{Digest, Sha256};
+
 use std::fs;
 use std::path::Path;
 
+/// Compute the SHA-256 hash of a file's contents.
+///
+/// # Arguments
+/// * `path` - A string slice that holds the path to the file.
+///
+/// # Returns
+/// * The hex encoded hash, or an error if the file could not be read.
+pub fn hash_file(path: &str) -> Result<String, std::io::Error> {
+    let bytes = fs::read(path)?;
+    Ok(format!("{:x}", Sha256::digest(&bytes)))
+}
+
+/// Compute a 64-bit FNV-1a hash of a string.
+/// Unlike the standard library hasher, the hash is stable across runs, platforms and Rust
+/// versions.
+///
+/// # Arguments
+/// * `value` - A string slice to hash.
+///
+/// # Returns
+/// * The hash of the string.
+pub fn stable_hash(value: &str) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in value.as_bytes() {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
 /// Check if a direcotry exists or create it.
 ///
 /// # Arguments
@@ -0,0 +1,248 @@
+use crate::binary_results::read_comparisons;
+use crate::config::{OutputFormat, Roi};
+use crate::csv_writer::{
+    format_float, CSVReader, ComparisonResult, ImageFileDataCSV, DEFAULT_FLOAT_DIGITS,
+};
+use crate::image_reader::ImageReader;
+use crate::manifest::RunManifest;
+use crate::metrics::{
+    calculate_mse_with_peak, channel_psnr, compare_pixels, ChannelSpace, MetricKind, SsimWindow,
+};
+
+use clap::ValueEnum;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+/// A stored metric value of a comparison result that does not match the recomputed value.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MetricMismatch {
+    pub comparisons_file: String,
+    /// The index of the row in the comparisons file, not counting the header.
+    pub row: usize,
+    pub comp_image_name: String,
+    pub metric: String,
+    pub stored: f64,
+    pub recomputed: f64,
+}
+
+/// Recomputes the metrics of a finished run that do not need Docker and compares them against
+/// the values stored in its comparison results, to catch regressions in the metric code.
+/// MSE, PSNR and the per-channel PSNRs are verified. SSIM and MS-SSIM are not, since the runner
+/// computes them with ImageMagick unless the images were color managed.
+pub struct RunVerifier {}
+
+impl RunVerifier {
+    /// Verifies every comparison results file of a run.
+    ///
+    /// # Arguments
+    /// * `benchmark_dir` - The benchmark directory.
+    /// * `run` - The number of the run to verify.
+    /// * `tolerance` - The largest absolute difference accepted between a stored and a
+    ///   recomputed value.
+    ///
+    /// # Returns
+    /// The mismatching values of all comparison results files, or an error if the run could not
+    /// be read.
+    pub fn verify_run(
+        benchmark_dir: &str,
+        run: usize,
+        tolerance: f64,
+    ) -> Result<Vec<MetricMismatch>, Box<dyn Error>> {
+        // Comparison results are stored per test set and commit under results/comp, next to
+        // the original image results under results/orig.
+        let results_dir = PathBuf::from(format!("{}/{}/results", benchmark_dir, run));
+        let comp_dir = results_dir.join("comp");
+        if !comp_dir.is_dir() {
+            return Err(format!("No results found for run {} in {}", run, benchmark_dir).into());
+        }
+
+        // The recomputed values are rounded like the stored values of the run.
+        let float_digits = RunManifest::load(benchmark_dir, run)
+            .map(|manifest| manifest.csv_float_digits)
+            .unwrap_or(DEFAULT_FLOAT_DIGITS);
+
+        let mut mismatches = Vec::new();
+        for res_comp_path in RunVerifier::sorted_dirs(&comp_dir)?
+            .iter()
+            .map(|test_set| RunVerifier::sorted_dirs(test_set))
+            .collect::<Result<Vec<Vec<PathBuf>>, _>>()?
+            .concat()
+        {
+            if OutputFormat::find_comparisons_file(res_comp_path.to_str().unwrap()).is_none() {
+                continue;
+            }
+            let relative_path = res_comp_path.strip_prefix(&comp_dir)?;
+            let res_orig_path = results_dir.join("orig").join(relative_path);
+            mismatches.extend(RunVerifier::verify_comparisons(
+                res_orig_path.to_str().unwrap(),
+                res_comp_path.to_str().unwrap(),
+                tolerance,
+                float_digits,
+            )?);
+        }
+        Ok(mismatches)
+    }
+
+    /// Verifies the comparison results of one test set and commit.
+    /// Failed encodes and results whose compressed image was not kept are skipped.
+    ///
+    /// # Arguments
+    /// * `res_orig_path` - The original image results path.
+    /// * `res_comp_path` - The compressed image results path.
+    /// * `tolerance` - The largest absolute difference accepted between a stored and a
+    ///   recomputed value.
+    /// * `float_digits` - The number of significant digits the stored values were written with,
+    ///   0 for full precision.
+    ///
+    /// # Returns
+    /// The mismatching values, or an error if a results file or image could not be read.
+    pub fn verify_comparisons(
+        res_orig_path: &str,
+        res_comp_path: &str,
+        tolerance: f64,
+        float_digits: usize,
+    ) -> Result<Vec<MetricMismatch>, Box<dyn Error>> {
+        let comparisons_file = OutputFormat::find_comparisons_file(res_comp_path)
+            .ok_or_else(|| format!("No comparison results found in {}", res_comp_path))?;
+        let comparisons = read_comparisons(&comparisons_file)?;
+        let orig_entries =
+            ImageFileDataCSV::new().read_csv(&format!("{}/results.csv", res_orig_path))?;
+        let comp_entries =
+            ImageFileDataCSV::new().read_csv(&format!("{}/results.csv", res_comp_path))?;
+
+        let mut mismatches = Vec::new();
+        for (row, result) in comparisons.iter().enumerate() {
+            if !result.is_ok() {
+                continue;
+            }
+            let orig_entry = orig_entries
+                .iter()
+                .find(|entry| entry.image_name == result.orig_image_name)
+                .ok_or_else(|| {
+                    format!(
+                        "No original image {} in {}",
+                        result.orig_image_name, res_orig_path
+                    )
+                })?;
+            let comp_entry = match comp_entries
+                .iter()
+                .find(|entry| entry.image_name == result.comp_image_name)
+            {
+                Some(comp_entry) if Path::new(&comp_entry.file_path).exists() => comp_entry,
+                _ => continue,
+            };
+
+            for (metric, stored, recomputed) in
+                RunVerifier::recompute(result, &orig_entry.file_path, &comp_entry.file_path)?
+            {
+                // Stored values are rounded to the significant digits of the CSV files, so the
+                // recomputed values are rounded the same way.
+                let recomputed = format_float(recomputed, float_digits).parse::<f64>()?;
+                let difference = (stored - recomputed).abs();
+                if difference.is_nan() || difference > tolerance {
+                    mismatches.push(MetricMismatch {
+                        comparisons_file: comparisons_file.clone(),
+                        row,
+                        comp_image_name: result.comp_image_name.clone(),
+                        metric,
+                        stored,
+                        recomputed,
+                    });
+                }
+            }
+        }
+        Ok(mismatches)
+    }
+
+    /// Recomputes the verified metrics of a comparison result.
+    /// Metrics restricted to a region of interest are recomputed on the same region.
+    ///
+    /// # Arguments
+    /// * `result` - The stored comparison result.
+    /// * `orig_path` - The path to the original image.
+    /// * `comp_path` - The path to the compressed image.
+    ///
+    /// # Returns
+    /// The name, stored value and recomputed value of each verified metric.
+    fn recompute(
+        result: &ComparisonResult,
+        orig_path: &str,
+        comp_path: &str,
+    ) -> Result<Vec<(String, f64, f64)>, Box<dyn Error>> {
+        let (mut width, mut height, mut orig_pixels) = ImageReader::decode_rgb8(orig_path)?;
+        let (_, _, mut comp_pixels) = ImageReader::decode_rgb8(comp_path)?;
+        if !result.roi.is_empty() {
+            let roi = result.roi.parse::<Roi>()?;
+            roi.validate(width, height)?;
+            orig_pixels = RunVerifier::crop(&orig_pixels, width, &roi);
+            comp_pixels = RunVerifier::crop(&comp_pixels, width, &roi);
+            (width, height) = (roi.width, roi.height);
+        }
+
+        // The MSE and PSNR are recomputed like the runner computes them, at the native depth of
+        // a whole compressed image, and on 8-bit samples of a region or of other formats.
+        let (mse, psnr) = match result.roi.is_empty() && comp_path.ends_with(".jxl") {
+            true => {
+                let (mse, peak) =
+                    calculate_mse_with_peak(&orig_path.to_string(), &comp_path.to_string());
+                (mse, ImageReader::calculate_psnr(mse, peak))
+            }
+            false => {
+                let metric_set = compare_pixels(
+                    &orig_pixels,
+                    &comp_pixels,
+                    width,
+                    height,
+                    &[MetricKind::Mse, MetricKind::Psnr],
+                    &SsimWindow::DEFAULT,
+                );
+                (metric_set.mse.unwrap(), metric_set.psnr.unwrap())
+            }
+        };
+        let mut values = vec![
+            ("mse".to_string(), result.mse, mse),
+            ("psnr".to_string(), result.psnr, psnr),
+        ];
+
+        // Older results have no per-channel PSNR.
+        if !result.channel_space.is_empty() {
+            let space = ChannelSpace::from_str(&result.channel_space, true)?;
+            let recomputed = channel_psnr(&orig_pixels, &comp_pixels, space);
+            let names = match space {
+                ChannelSpace::Rgb => ["psnr_r", "psnr_g", "psnr_b"],
+                _ => ["psnr_y", "psnr_cb", "psnr_cr"],
+            };
+            for (channel, name) in names.iter().enumerate() {
+                values.push((
+                    name.to_string(),
+                    result.channel_psnr[channel],
+                    recomputed[channel],
+                ));
+            }
+        }
+        Ok(values)
+    }
+
+    /// Crops interleaved 8-bit RGB samples to a region of interest.
+    fn crop(pixels: &[u8], width: u32, roi: &Roi) -> Vec<u8> {
+        let row_len = roi.width as usize * 3;
+        (roi.y..roi.y + roi.height)
+            .flat_map(|y| {
+                let start = (y as usize * width as usize + roi.x as usize) * 3;
+                pixels[start..start + row_len].iter().copied()
+            })
+            .collect()
+    }
+
+    /// Lists the subdirectories of a directory in sorted order.
+    fn sorted_dirs(path: &Path) -> Result<Vec<PathBuf>, std::io::Error> {
+        let mut dirs = std::fs::read_dir(path)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<Result<Vec<PathBuf>, _>>()?
+            .into_iter()
+            .filter(|path| path.is_dir())
+            .collect::<Vec<PathBuf>>();
+        dirs.sort();
+        Ok(dirs)
+    }
+}
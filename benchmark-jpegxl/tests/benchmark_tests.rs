@@ -0,0 +1,1244 @@
+use std::path::PathBuf;
+
+use benchmark_jpegxl::benchmark::*;
+use benchmark_jpegxl::config::{Gate, OutputFormat, QualityTarget, SizeTarget, SizeTargetStatus, SummaryWeighting, Sweep};
+use benchmark_jpegxl::csv_writer::*;
+use benchmark_jpegxl::docker_manager::DockerManager;
+use benchmark_jpegxl::manifest::RunManifest;
+use benchmark_jpegxl::metrics::{MetricKind, SizeRatio, SsimWindow};
+
+mod common;
+use common::{mock_docker, test_dir};
+
+/// Creates a comparison result for an image at the given distance and effort.
+fn comparison_result(image_name: &str, distance: f32, effort: u32) -> ComparisonResult {
+	ComparisonResult {
+		orig_image_name: format!("{}.png", image_name),
+		comp_image_name: format!("{}__d{}__e{}.jxl", image_name, distance, effort),
+		distance,
+		effort,
+		orig_file_size: 1000,
+		comp_file_size: 100,
+		orig_raw_size: 3000,
+		comp_raw_size: 3000,
+		comp_file_size_ratio: 10.0,
+		raw_file_size_ratio: 30.0,
+		size_ratio: SizeRatio::Compression,
+		mse: 10.0,
+		psnr: 38.0,
+		ssim: 0.95,
+		ms_ssim: 0.0,
+		butteraugli: 1.5,
+		butteraugli_pnorm: 0.7,
+		ssimulacra2: 80.0,
+		coding_mode: "default".to_string(),
+		size_regression: false,
+		peak_rss_bytes: 0,
+		cpu_seconds: 0.0,
+		roi: "".to_string(),
+		bpp: 0.8,
+		intensity_target: "".to_string(),
+		status: "ok".to_string(),
+		channel_psnr: [40.0; 3],
+		channel_space: "rgb".to_string(),
+		source_is_lossy: false,
+		quality: "".to_string(),
+		decode_time_ms_min: f64::NAN,
+		decode_time_ms_mean: f64::NAN,
+		mse_vs_master: f64::NAN,
+		psnr_vs_master: f64::NAN,
+		ssim_vs_master: f64::NAN,
+		ms_ssim_vs_master: f64::NAN,
+		width: 32,
+		height: 32,
+		color_managed: false,
+		external_metrics: Vec::new(),
+	}
+}
+
+#[test]
+fn test_coding_mode_flag_and_column() {
+	// The mode flag is added to the cjxl arguments only when a mode is selected.
+	let modular_args = DockerManager::cjxl_args("in.png", "out.jxl", 1.0, None, 7, Some(true), &[]);
+	assert!(modular_args.contains(&"--modular=1".to_string()));
+	let vardct_args = DockerManager::cjxl_args("in.png", "out.jxl", 1.0, None, 7, Some(false), &[]);
+	assert!(vardct_args.contains(&"--modular=0".to_string()));
+	let default_args = DockerManager::cjxl_args("in.png", "out.jxl", 1.0, None, 7, None, &[]);
+	assert!(default_args.iter().all(|arg| !arg.starts_with("--modular")));
+
+	// The chosen mode is recorded in the comparison row.
+	let dir = test_dir("coding_mode");
+	let result_file = dir.join("comparisons.csv");
+	let result_file = result_file.to_str().unwrap();
+	let mut result = comparison_result("kodim01", 1.0, 7);
+	result.coding_mode = JXLCompressionBenchmark::coding_mode(Some(true));
+
+	let csv = ComparisonResultCSV::new();
+	csv.write_csv_header(result_file).unwrap();
+	csv.write_csv(&vec![result], result_file, DEFAULT_FLOAT_DIGITS).unwrap();
+	let read = csv.read_csv(result_file).unwrap();
+	assert_eq!(read.len(), 1);
+	assert_eq!(read[0].coding_mode, "modular");
+}
+
+#[test]
+fn test_size_regression_column() {
+	use benchmark_jpegxl::binary_results::read_comparisons;
+	use benchmark_jpegxl::config::Config;
+
+	// The mock docker logs every invocation next to itself.
+	let dir = test_dir("size_regression");
+	let docker = mock_docker(&dir);
+
+	// A pre-compressed source: a flat PNG is smaller than any encode of noise, which the mock
+	// cjxl writes for a high distance.
+	let image_path = dir.join("test_images/set/precompressed.png");
+	std::fs::create_dir_all(image_path.parent().unwrap()).unwrap();
+	image::RgbImage::from_pixel(64, 64, image::Rgb([128, 128, 128])).save(&image_path).unwrap();
+	let noise = image::RgbImage::from_fn(64, 64, |x, y| {
+		let hash = (x * 7919 + y * 104729).wrapping_mul(2654435761);
+		image::Rgb([(hash >> 8) as u8, (hash >> 16) as u8, (hash >> 24) as u8])
+	});
+	let mut encoder = jpegxl_rs::encoder_builder().build().unwrap();
+	let encoded: jpegxl_rs::encode::EncoderResult<u8> =
+		encoder.encode::<u8, u8>(noise.as_raw(), noise.width(), noise.height()).unwrap();
+	std::fs::write(dir.join("cjxl.jxl"), &encoded.data).unwrap();
+
+	let benchmark_dir = dir.join("benchmarks");
+	let config = Config {
+		docker_bin: docker,
+		benchmark_dir_path: benchmark_dir.to_str().unwrap().to_string(),
+		local_test_image_dir_path: dir.join("test_images").to_str().unwrap().to_string(),
+		num_workers: 1,
+		libjxl_commit: Some("abc123".to_string()),
+		distances: vec![14.0],
+		efforts: vec![7],
+		keep_compressed: false,
+		..Config::default()
+	};
+	let mut benchmarker = Benchmarker::new(&config).unwrap();
+	benchmarker.run_benchmark::<JXLCompressionBenchmark>().unwrap();
+	benchmarker.wait_for_all_workers();
+
+	// The row records the sizes of both files and flags the inflated output.
+	let orig_file_size = std::fs::metadata(&image_path).unwrap().len();
+	let comp_file_size = encoded.data.len() as u64;
+	assert!(comp_file_size > orig_file_size);
+	let comparisons_file = benchmark_dir.join("0/results/comp/set/abc123/comparisons.csv");
+	let results = read_comparisons(comparisons_file.to_str().unwrap()).unwrap();
+	assert_eq!(results.len(), 1);
+	assert_eq!((results[0].orig_file_size, results[0].comp_file_size), (orig_file_size, comp_file_size));
+	assert!(results[0].size_regression);
+	assert!(!JXLCompressionBenchmark::is_size_regression(comp_file_size, orig_file_size));
+	assert!(!JXLCompressionBenchmark::is_size_regression(orig_file_size, orig_file_size));
+}
+
+#[test]
+fn test_tidy_export() {
+	let results = vec![
+		comparison_result("kodim01", 1.0, 7),
+		comparison_result("kodim01", 2.0, 7),
+		comparison_result("kodim02", 1.0, 9),
+	];
+	let rows = TidyResult::from_comparison_results(&results, "main");
+	assert_eq!(rows.len(), results.len() * TidyResult::METRIC_NAMES.len());
+	assert!(rows.iter().all(|row| row.commit == "main"));
+	let psnr = rows
+		.iter()
+		.find(|row| row.image == "kodim02.png" && row.metric_name == "psnr")
+		.unwrap();
+	assert_eq!(psnr.value, 38.0);
+	assert_eq!(psnr.effort, 9);
+
+	// Every tidy row is written below a single header.
+	let dir = test_dir("tidy");
+	let tidy_file = dir.join("comparisons_tidy.csv");
+	let tidy_file = tidy_file.to_str().unwrap();
+	let csv = TidyResultCSV::new();
+	csv.write_csv_header(tidy_file).unwrap();
+	csv.write_csv(&rows, tidy_file, DEFAULT_FLOAT_DIGITS).unwrap();
+	let lines = std::fs::read_to_string(tidy_file).unwrap().lines().count();
+	assert_eq!(lines, rows.len() + 1);
+}
+
+#[test]
+fn test_default_commit_is_resolved_to_sha() {
+	// The default branch name is never accepted as a recorded commit.
+	assert!(DockerManager::parse_commit_sha("main\n").is_err());
+	assert!(DockerManager::parse_commit_sha("").is_err());
+
+	// The output of rev-parse is recorded as a 40 character SHA.
+	let sha = DockerManager::parse_commit_sha("0123456789ABCDEF0123456789abcdef01234567\n").unwrap();
+	assert_eq!(sha.len(), 40);
+	assert_eq!(sha, "0123456789abcdef0123456789abcdef01234567");
+}
+
+#[test]
+fn test_aggregate_results() {
+	// Two images where the second image is missing the distance 2.0 encode.
+	let mut image_2 = comparison_result("kodim02", 1.0, 7);
+	image_2.psnr = 42.0;
+	image_2.bpp = 1.2;
+	let results = vec![
+		comparison_result("kodim01", 2.0, 7),
+		comparison_result("kodim01", 1.0, 7),
+		image_2,
+	];
+
+	let aggregates = JXLCompressionBenchmark::aggregate_results("kodak", &results, SummaryWeighting::Equal);
+	assert_eq!(aggregates.len(), 2);
+
+	// Combinations are ordered by distance and average over the images they were recorded for.
+	assert_eq!((aggregates[0].distance, aggregates[0].effort), (1.0, 7));
+	assert_eq!(aggregates[0].num_images, 2);
+	assert_eq!(aggregates[0].avg_psnr, 40.0);
+	assert!((aggregates[0].avg_bpp - 1.0).abs() < 1e-9);
+	assert_eq!(aggregates[0].avg_ssim, 0.95);
+	assert_eq!(aggregates[1].num_images, 1);
+	assert_eq!(aggregates[1].avg_psnr, 38.0);
+
+	// The aggregate table is written next to the comparisons.
+	let dir = test_dir("aggregate");
+	let comparisons_file = dir.join("comparisons.csv");
+	let comparisons_file = comparisons_file.to_str().unwrap();
+	let csv = ComparisonResultCSV::new();
+	csv.write_csv_header(comparisons_file).unwrap();
+	csv.write_csv(&results, comparisons_file, DEFAULT_FLOAT_DIGITS).unwrap();
+	JXLCompressionBenchmark::write_aggregate("kodak", comparisons_file, SummaryWeighting::Equal, DEFAULT_FLOAT_DIGITS);
+	let aggregate = std::fs::read_to_string(dir.join("aggregate.csv")).unwrap();
+	assert_eq!(aggregate.lines().count(), 3);
+	assert!(aggregate.lines().nth(1).unwrap().starts_with("kodak,1,7,2,"));
+}
+
+#[test]
+fn test_summary_weighting() {
+	// A small image of 16x16 pixels and a large image of 256x256 pixels that compresses better.
+	let mut small = comparison_result("small", 1.0, 7);
+	(small.width, small.height, small.orig_file_size) = (16, 16, 1000);
+	(small.psnr, small.bpp) = (30.0, 4.0);
+	let mut large = comparison_result("large", 1.0, 7);
+	(large.width, large.height, large.orig_file_size) = (256, 256, 3000);
+	(large.psnr, large.bpp) = (40.0, 1.0);
+	let results = vec![small, large];
+
+	// Equal weighting averages the images, pixel weighting is dominated by the large image.
+	let equal = JXLCompressionBenchmark::aggregate_results("set", &results, SummaryWeighting::Equal);
+	assert_eq!(equal[0].avg_psnr, 35.0);
+	assert_eq!(equal[0].avg_bpp, 2.5);
+	assert_eq!(equal[0].weighting, "equal");
+	let by_pixels = JXLCompressionBenchmark::aggregate_results("set", &results, SummaryWeighting::ByPixels);
+	assert!((by_pixels[0].avg_psnr - (30.0 * 256.0 + 40.0 * 65536.0) / 65792.0).abs() < 1e-9);
+	assert!(by_pixels[0].avg_psnr > 39.9);
+	assert!(by_pixels[0].avg_bpp < 1.02);
+	assert_eq!(by_pixels[0].weighting, "pixels");
+	let by_bytes = JXLCompressionBenchmark::aggregate_results("set", &results, SummaryWeighting::ByBytes);
+	assert_eq!(by_bytes[0].avg_psnr, 37.5);
+	assert_eq!(by_bytes[0].weighting, "bytes");
+
+	// Summaries of differences are weighted the same way.
+	let diffs = vec![comparison_result_diff(1.0), comparison_result_diff(3.0)];
+	assert_eq!(JXLCompressionBenchmark::summarize_diffs(&diffs, &[1.0, 1.0]).diff_mse, 2.0);
+	assert_eq!(JXLCompressionBenchmark::summarize_diffs(&diffs, &[1.0, 3.0]).diff_mse, 2.5);
+
+	// Images without dimensions have no pixel weight.
+	let mut unknown = results[0].clone();
+	(unknown.width, unknown.height) = (0, 0);
+	assert!(JXLCompressionBenchmark::aggregate_results("set", &vec![unknown], SummaryWeighting::ByPixels)[0]
+		.avg_psnr
+		.is_nan());
+}
+
+#[test]
+fn test_cjxl_extra_args() {
+	// Extra arguments are appended verbatim to the generated command.
+	let extra_args = vec!["--photon_noise_iso=3200".to_string(), "--faster_decoding=2".to_string()];
+	let args = DockerManager::cjxl_args("in.png", "out.jxl", 1.0, None, 7, None, &extra_args);
+	assert_eq!(&args[args.len() - 2..], extra_args.as_slice());
+	assert!(DockerManager::validate_cjxl_extra_args(&extra_args).is_ok());
+
+	// Arguments conflicting with the swept distance and effort are rejected.
+	for conflicting in ["--distance=2", "-d", "--effort=9", "-e", "-d1", "-e7", "-q90", "-d=1", "--quality=90"] {
+		assert!(DockerManager::validate_cjxl_extra_args(&vec![conflicting.to_string()]).is_err(), "{}", conflicting);
+	}
+
+	// Other flags starting with the same letters are accepted.
+	for arg in ["--disable_perceptual_optimizations", "--dec-hints=color_space=RGB", "-x", "--quiet"] {
+		assert!(DockerManager::validate_cjxl_extra_args(&vec![arg.to_string()]).is_ok(), "{}", arg);
+	}
+}
+
+/// Creates a comparison result difference with every difference set to the given value.
+fn comparison_result_diff(value: f64) -> ComparisonResultDiff {
+	ComparisonResultDiff {
+		orig_image_name: "kodim01.png".to_string(),
+		comp_image_name: "kodim01__d1__e7.jxl".to_string(),
+		distance: 1.0,
+		effort: 7,
+		diff_orig_file_size: 0.0,
+		diff_comp_file_size: value,
+		diff_orig_raw_size: 0.0,
+		diff_comp_raw_size: 0.0,
+		diff_comp_file_size_ratio: value,
+		diff_raw_file_size_ratio: value,
+		diff_mse: value,
+		diff_psnr: value,
+		diff_ssim: value,
+		diff_ms_ssim: value,
+		diff_butteraugli: value,
+		diff_butteraugli_pnorm: value,
+		diff_ssimulacra2: value,
+		non_finite_values: 0,
+		baseline_codec: "jxl".to_string(),
+		diff_channel_psnr: [value; 3],
+	}
+}
+
+#[test]
+fn test_lossless_psnr_summary_is_finite() {
+	// An identical image pair records the lossless PSNR instead of infinity.
+	let dir = test_dir("lossless_psnr");
+	let orig = dir.join("orig.png");
+	image::RgbImage::from_fn(16, 16, |x, y| image::Rgb([x as u8 * 16, y as u8 * 16, 128]))
+		.save(&orig)
+		.unwrap();
+	let metrics = benchmark_jpegxl::metrics::compare_images(
+		&orig,
+		&orig,
+		&[benchmark_jpegxl::metrics::MetricKind::Mse, benchmark_jpegxl::metrics::MetricKind::Psnr],
+	)
+	.unwrap();
+	assert_eq!(metrics.mse, Some(0.0));
+	assert_eq!(metrics.psnr, Some(benchmark_jpegxl::image_reader::ImageReader::LOSSLESS_PSNR));
+
+	// Non-finite differences are skipped in the summary and counted separately.
+	let mut lossless = comparison_result_diff(1.0);
+	lossless.diff_psnr = f64::INFINITY;
+	let results = vec![comparison_result_diff(3.0), lossless];
+	let summary = JXLCompressionBenchmark::summarize_diffs(&results, &[1.0, 1.0]);
+	assert!(summary.diff_psnr.is_finite());
+	assert_eq!(summary.diff_psnr, 3.0);
+	assert_eq!(summary.diff_mse, 2.0);
+	assert_eq!(summary.non_finite_values, 1);
+}
+
+#[test]
+fn test_deterministic_assignment() {
+	let image_paths = vec![
+		PathBuf::from("images/kodak/kodim03.png"),
+		PathBuf::from("images/kodak/kodim01.png"),
+		PathBuf::from("images/kodak/kodim02.png"),
+		PathBuf::from("images/kodak/kodim04.png"),
+	];
+	let mut shuffled = image_paths.clone();
+	shuffled.reverse();
+
+	// Two planning passes over differently ordered listings produce the same plan.
+	let first = Benchmarker::plan_assignments(&image_paths, 3);
+	let second = Benchmarker::plan_assignments(&shuffled, 3);
+	assert_eq!(first, second);
+
+	// Images are processed in sorted order and every worker id is valid.
+	assert_eq!(first[0].0, PathBuf::from("images/kodak/kodim01.png"));
+	assert!(first.iter().all(|(_, worker_id)| *worker_id < 3));
+}
+
+#[test]
+fn test_output_collisions_are_rejected() {
+	let encodes = Sweep::new(&[1.0, 2.0], &[]).unwrap().encodes();
+	let image_paths = vec![PathBuf::from("images/kodak/kodim01.png"), PathBuf::from("images/kodak/kodim02.png")];
+	assert!(Benchmarker::check_output_collisions(&image_paths, &encodes, &[7, 9]).is_ok());
+
+	// The same distance configured twice writes the same files.
+	let duplicate_distances = Sweep::new(&[1.0, 1.00], &[]).unwrap().encodes();
+	let error = Benchmarker::check_output_collisions(&image_paths, &duplicate_distances, &[7]).unwrap_err();
+	assert!(error.contains("kodim01__d1__e7.jxl"), "{}", error);
+
+	// Images whose names only differ in their extension write the same files.
+	let image_paths = vec![PathBuf::from("images/kodak/kodim01.png"), PathBuf::from("images/kodak/kodim01.jpg")];
+	let error = Benchmarker::check_output_collisions(&image_paths, &encodes, &[7]).unwrap_err();
+	assert!(error.contains("kodim01.png at distance 1 and effort 7"), "{}", error);
+	assert!(error.contains("kodim01.jpg at distance 1 and effort 7"), "{}", error);
+
+	// Qualities that map to the same distance write the same files.
+	let qualities = Sweep::new(&[], &[90.0, 90.0001]).unwrap().encodes();
+	let image_paths = vec![PathBuf::from("images/kodak/kodim01.png")];
+	let error = Benchmarker::check_output_collisions(&image_paths, &qualities, &[7]).unwrap_err();
+	assert!(error.contains("at quality 90.0001"), "{}", error);
+	assert_eq!(Benchmarker::image_name(&PathBuf::from("images/kodak/kodim01.png")), "kodim01");
+}
+
+#[test]
+fn test_paths_with_spaces_and_unicode() {
+	// A test set and an image whose names contain spaces and non-ASCII characters.
+	let dir = test_dir("paths_with_spaces_and_unicode");
+	let test_set_dir = dir.join("my test set ünïcødé");
+	std::fs::create_dir_all(&test_set_dir).unwrap();
+	std::fs::write(test_set_dir.join("café au lait 日本.png"), b"").unwrap();
+
+	let image_paths = Benchmarker::get_test_set_image_paths(test_set_dir.to_str().unwrap());
+	assert_eq!(image_paths, vec![test_set_dir.join("café au lait 日本.png")]);
+	assert_eq!(Benchmarker::image_name(&image_paths[0]), "café au lait 日本");
+
+	// The container path keeps the test set and image name as they are.
+	let container_path = Benchmarker::container_image_path(
+		dir.to_str().unwrap(),
+		"/test_images",
+		image_paths[0].to_str().unwrap(),
+	);
+	assert_eq!(container_path, "/test_images/my test set ünïcødé/café au lait 日本.png");
+
+	// A trailing slash on the local directory does not change the container path.
+	let container_path = Benchmarker::container_image_path(
+		&format!("{}/", dir.to_str().unwrap()),
+		"/test_images",
+		image_paths[0].to_str().unwrap(),
+	);
+	assert_eq!(container_path, "/test_images/my test set ünïcødé/café au lait 日本.png");
+
+	// Encodes of the image are planned under its full name.
+	let encodes = Sweep::new(&[1.0], &[]).unwrap().encodes();
+	assert!(Benchmarker::check_output_collisions(&image_paths, &encodes, &[7]).is_ok());
+}
+
+#[test]
+fn test_missing_test_image_dir() {
+	let dir = test_dir("missing_test_image_dir");
+
+	// A directory that does not exist is reported with how to populate it.
+	let missing = dir.join("test_images");
+	let error = Benchmarker::find_test_sets(missing.to_str().unwrap()).err().unwrap().to_string();
+	assert!(error.contains(missing.to_str().unwrap()), "{}", error);
+	assert!(error.contains("does not exist"), "{}", error);
+	assert!(error.contains("fetch"), "{}", error);
+
+	// So is a directory without test set directories, even if it holds images.
+	std::fs::create_dir_all(&missing).unwrap();
+	std::fs::write(missing.join("kodim01.png"), b"").unwrap();
+	let error = Benchmarker::find_test_sets(missing.to_str().unwrap()).err().unwrap().to_string();
+	assert!(error.contains("has no test sets"), "{}", error);
+
+	// Test set directories are found in order.
+	std::fs::create_dir_all(missing.join("set b")).unwrap();
+	std::fs::create_dir_all(missing.join("set a")).unwrap();
+	assert_eq!(
+		Benchmarker::find_test_sets(missing.to_str().unwrap()).unwrap(),
+		vec!["set a".to_string(), "set b".to_string()]
+	);
+}
+
+#[test]
+fn test_keep_originals() {
+	let dir = test_dir("keep_originals");
+	let image_path = dir.join("test_images/set/kodim01.png");
+	std::fs::create_dir_all(image_path.parent().unwrap()).unwrap();
+	std::fs::write(&image_path, b"source image").unwrap();
+	let out_orig_path = dir.join("benchmarks/1/output/orig/set/main");
+
+	// Originals are only kept when enabled.
+	assert!(!benchmark_jpegxl::config::Config::default().keep_originals);
+
+	// The source is copied into the original output directory of the run.
+	let copy_path = Benchmarker::keep_original(
+		image_path.to_str().unwrap(),
+		out_orig_path.to_str().unwrap(),
+		None,
+	)
+	.unwrap();
+	assert_eq!(PathBuf::from(&copy_path), out_orig_path.join("kodim01.png"));
+	assert_eq!(std::fs::read(&copy_path).unwrap(), b"source image");
+
+	// The copy remains when the test image directory is removed.
+	std::fs::remove_dir_all(dir.join("test_images")).unwrap();
+	assert_eq!(std::fs::read(&copy_path).unwrap(), b"source image");
+
+	// A downscaled reference of the same name is not overwritten.
+	std::fs::create_dir_all(image_path.parent().unwrap()).unwrap();
+	std::fs::write(&image_path, b"source image").unwrap();
+	let reference_path = out_orig_path.join("kodim01.png");
+	std::fs::write(&reference_path, b"downscaled image").unwrap();
+	let copy_path = Benchmarker::keep_original(
+		image_path.to_str().unwrap(),
+		out_orig_path.to_str().unwrap(),
+		Some(reference_path.to_str().unwrap()),
+	)
+	.unwrap();
+	assert_eq!(PathBuf::from(&copy_path), out_orig_path.join("source/kodim01.png"));
+	assert_eq!(std::fs::read(&copy_path).unwrap(), b"source image");
+	assert_eq!(std::fs::read(&reference_path).unwrap(), b"downscaled image");
+}
+
+#[test]
+fn test_compare_to_master() {
+	// A lossless master and a noisy compressed image of a lossy original of the same name.
+	let dir = test_dir("compare_to_master");
+	let master = image::RgbImage::from_fn(32, 32, |x, y| image::Rgb([(x * 8) as u8, (y * 8) as u8, 128]));
+	master.save(dir.join("kodim01.png")).unwrap();
+	image::RgbImage::new(8, 8).save(dir.join("kodim02.png")).unwrap();
+	std::fs::write(dir.join("kodim01.txt"), "not an image").unwrap();
+	let comp_pixels = master
+		.as_raw()
+		.iter()
+		.enumerate()
+		.map(|(i, sample)| sample.saturating_add((i % 5) as u8))
+		.collect::<Vec<u8>>();
+
+	let master_dir = dir.to_str().unwrap();
+	let master_path = JXLCompressionBenchmark::find_master(master_dir, "kodim01.jpg").unwrap();
+	assert!(master_path.ends_with("kodim01.png"), "{}", master_path);
+	assert_eq!(JXLCompressionBenchmark::find_master(master_dir, "kodim03.jpg"), None);
+
+	let metrics =
+		JXLCompressionBenchmark::compare_to_master(&master_path, &comp_pixels, 32, 32, &SsimWindow::default()).unwrap();
+	assert!(metrics.mse.unwrap() > 0.0);
+	assert!(metrics.psnr.unwrap().is_finite());
+	assert!(metrics.ssim.unwrap() > 0.0 && metrics.ssim.unwrap() < 1.0);
+	assert!(metrics.ms_ssim.unwrap() > 0.0 && metrics.ms_ssim.unwrap() < 1.0);
+
+	// The master is compared against itself losslessly.
+	let lossless =
+		JXLCompressionBenchmark::compare_to_master(&master_path, master.as_raw(), 32, 32, &SsimWindow::default())
+			.unwrap();
+	assert_eq!(lossless.mse, Some(0.0));
+
+	// A master of different dimensions is rejected.
+	let mismatched = JXLCompressionBenchmark::find_master(master_dir, "kodim02.jpg").unwrap();
+	assert!(
+		JXLCompressionBenchmark::compare_to_master(&mismatched, &comp_pixels, 32, 32, &SsimWindow::default()).is_err()
+	);
+
+	// The metrics against the master are written to and read back from the comparison CSV.
+	let mut result = comparison_result("kodim01", 1.0, 7);
+	result.mse_vs_master = metrics.mse.unwrap();
+	result.psnr_vs_master = metrics.psnr.unwrap();
+	result.ssim_vs_master = metrics.ssim.unwrap();
+	result.ms_ssim_vs_master = metrics.ms_ssim.unwrap();
+	let csv_file = dir.join("comparisons.csv");
+	let csv_file = csv_file.to_str().unwrap();
+	let csv_writer = ComparisonResultCSV::new();
+	csv_writer.write_csv_header(csv_file).unwrap();
+	csv_writer.write_csv(&vec![result.clone()], csv_file, DEFAULT_FLOAT_DIGITS).unwrap();
+	let read = csv_writer.read_csv(csv_file).unwrap();
+	assert!((read[0].psnr_vs_master - result.psnr_vs_master).abs() < 1e-3);
+	assert!((read[0].ssim_vs_master - result.ssim_vs_master).abs() < 1e-3);
+}
+
+#[test]
+fn test_quality_target_search() {
+	// A synthetic SSIMULACRA2 score that decreases linearly with the distance.
+	let quality_target = QualityTarget {
+		metric: MetricKind::Ssimulacra2,
+		target: 70.0,
+		tolerance: 0.5,
+		max_iterations: 10,
+		effort: 7,
+	};
+	let mut encodes = 0;
+	let (distance, value, iterations, converged) =
+		JXLCompressionBenchmark::search_distance(&quality_target, |distance| {
+			encodes += 1;
+			Some(90.0 - 10.0 * distance)
+		})
+		.unwrap();
+	assert!(converged);
+	assert!((value - 70.0).abs() <= 0.5);
+	assert!((distance - 2.0).abs() <= 0.05);
+	assert_eq!(iterations, encodes);
+	assert!(iterations <= 10);
+
+	// A lower is better metric searches in the other direction.
+	let quality_target = QualityTarget {
+		metric: MetricKind::Butteraugli,
+		target: 3.0,
+		tolerance: 0.01,
+		..quality_target
+	};
+	let (distance, _, _, converged) =
+		JXLCompressionBenchmark::search_distance(&quality_target, |distance| Some(distance))
+			.unwrap();
+	assert!(converged);
+	assert!((distance - 3.0).abs() <= 0.01);
+}
+
+#[test]
+fn test_effort_curve() {
+	// A synthetic score that higher efforts keep up to larger distances, at fewer bits per pixel.
+	let dir = test_dir("effort_curve");
+	let quality_target = QualityTarget {
+		metric: MetricKind::Ssimulacra2,
+		target: 70.0,
+		tolerance: 0.5,
+		max_iterations: 12,
+		effort: 7,
+	};
+	let mut curve = [9, 3, 7]
+		.iter()
+		.map(|&effort| {
+			let (distance, value, _, converged) = JXLCompressionBenchmark::search_distance(&quality_target, |distance| {
+				Some(90.0 - 10.0 * distance / (1.0 + effort as f64 / 10.0))
+			})
+			.unwrap();
+			EffortCurvePoint {
+				image_name: "kodim01".to_string(),
+				effort,
+				metric: quality_target.metric.name().to_string(),
+				target: quality_target.target,
+				distance,
+				value,
+				bpp: 2.0 / distance,
+				encode_time_ms: 100.0 * effort as f64,
+				converged,
+			}
+		})
+		.collect::<Vec<EffortCurvePoint>>();
+
+	// One row per effort in increasing order, and a rewrite replaces the previous curve.
+	let res_comp_path = dir.to_str().unwrap();
+	JXLCompressionBenchmark::write_effort_curve(res_comp_path, "kodim01", &mut curve, DEFAULT_FLOAT_DIGITS);
+	let curve_file = JXLCompressionBenchmark::write_effort_curve(res_comp_path, "kodim01", &mut curve, DEFAULT_FLOAT_DIGITS);
+	assert_eq!(curve_file, format!("{}/kodim01/effort_curve.csv", res_comp_path));
+	let contents = std::fs::read_to_string(&curve_file).unwrap();
+	let lines = contents.lines().collect::<Vec<&str>>();
+	assert_eq!(lines[0], "Image Name,Effort,Metric,Target,Distance,Value,BPP,Encode Time (ms),Converged");
+	assert_eq!(lines.len(), 4);
+	let rows = lines[1..]
+		.iter()
+		.map(|line| line.split(',').collect::<Vec<&str>>())
+		.collect::<Vec<Vec<&str>>>();
+	assert_eq!(rows.iter().map(|row| row[1]).collect::<Vec<&str>>(), vec!["3", "7", "9"]);
+	assert!(rows.iter().all(|row| row[0] == "kodim01" && row[2] == "SSIMULACRA2" && row[8] == "true"));
+
+	// Higher efforts reach the target at larger distances and lower rates.
+	let distances = rows.iter().map(|row| row[4].parse::<f64>().unwrap()).collect::<Vec<f64>>();
+	let bpps = rows.iter().map(|row| row[6].parse::<f64>().unwrap()).collect::<Vec<f64>>();
+	assert!(distances[0] < distances[1] && distances[1] < distances[2]);
+	assert!(bpps[0] > bpps[1] && bpps[1] > bpps[2]);
+}
+
+#[test]
+fn test_size_target_search() {
+	// A synthetic size model where the file shrinks with the distance, 50000 bytes at distance 3.
+	let size_target = SizeTarget {
+		target_bytes: 50_000,
+		tolerance: 0.01,
+		max_iterations: 12,
+		effort: 7,
+	};
+	let size_model = |distance: f64| (200_000.0 / (1.0 + distance)) as u64;
+	let mut encodes = 0;
+	let (distance, size, iterations, status) =
+		JXLCompressionBenchmark::search_size_distance(&size_target, |distance| {
+			encodes += 1;
+			Some(size_model(distance))
+		})
+		.unwrap();
+	assert_eq!(status, SizeTargetStatus::Converged);
+	assert!(size_target.within_tolerance(size));
+	assert!((distance - 3.0).abs() <= 0.05);
+	assert_eq!(iterations, encodes);
+	assert!(iterations <= 12);
+
+	// A budget that even the highest distance exceeds is unreachable after a single encode.
+	let size_target = SizeTarget {
+		target_bytes: 1_000,
+		..size_target
+	};
+	let (distance, size, iterations, status) =
+		JXLCompressionBenchmark::search_size_distance(&size_target, |distance| {
+			Some(size_model(distance))
+		})
+		.unwrap();
+	assert_eq!(status, SizeTargetStatus::Unreachable);
+	assert_eq!(distance, SizeTarget::MAX_DISTANCE);
+	assert_eq!(size, size_model(SizeTarget::MAX_DISTANCE));
+	assert_eq!(iterations, 1);
+	assert_eq!(status.name(), "budget unreachable");
+
+	// The search is bounded and keeps the closest size when it runs out of iterations.
+	let size_target = SizeTarget {
+		target_bytes: 50_000,
+		tolerance: 0.0,
+		max_iterations: 4,
+		effort: 7,
+	};
+	let (_, size, iterations, status) =
+		JXLCompressionBenchmark::search_size_distance(&size_target, |distance| {
+			Some(size_model(distance) + 1)
+		})
+		.unwrap();
+	assert_eq!(status, SizeTargetStatus::NotConverged);
+	assert_eq!(iterations, 4);
+	assert!(size.abs_diff(50_000) < 10_000);
+
+	// No successful encode is no result.
+	assert!(JXLCompressionBenchmark::search_size_distance(&size_target, |_| None).is_none());
+}
+
+#[test]
+fn test_diff_against_baseline_codec() {
+	// A JPEG XL run at distance 1 (quality 90) and 2 (quality 79).
+	let mut jxl_1 = comparison_result("kodim01", 1.0, 7);
+	jxl_1.comp_file_size = 1000;
+	jxl_1.ssimulacra2 = 85.0;
+	let jxl_2 = comparison_result("kodim01", 2.0, 7);
+	let jxl = vec![jxl_1, jxl_2];
+
+	// A mock AVIF baseline storing its quality in the distance column, without an effort.
+	let mut avif = comparison_result("kodim01", 90.0, 0);
+	avif.comp_image_name = "kodim01__q90.avif".to_string();
+	avif.comp_file_size = 1200;
+	avif.ssimulacra2 = 80.0;
+	let mut unmatched = comparison_result("kodim01", 50.0, 0);
+	unmatched.comp_image_name = "kodim01__q50.avif".to_string();
+	let baseline = vec![avif, unmatched];
+
+	assert_eq!(JXLCompressionBenchmark::quality_bucket(1.0, "jxl"), 90);
+	assert_eq!(JXLCompressionBenchmark::quality_bucket(90.0, "avif"), 90);
+
+	// Only the JPEG XL result in the quality bucket of a baseline result is diffed.
+	let diffs = JXLCompressionBenchmark::diff_results(&baseline, &jxl, Some("avif"));
+	assert_eq!(diffs.len(), 1);
+	assert_eq!(diffs[0].comp_image_name, "kodim01__d1__e7.jxl");
+	assert_eq!(diffs[0].distance, 1.0);
+	assert_eq!(diffs[0].diff_comp_file_size, -200.0);
+	assert_eq!(diffs[0].diff_ssimulacra2, 5.0);
+	assert_eq!(diffs[0].baseline_codec, "avif");
+}
+
+#[test]
+fn test_baseline_csv_per_test_set() {
+	use benchmark_jpegxl::config::Config;
+	use benchmark_jpegxl::smoke::SmokeTest;
+
+	// The mock docker logs every invocation next to itself.
+	let dir = test_dir("baseline_csv_test_sets");
+	let docker = mock_docker(&dir);
+
+	// Two test sets of the same gradient, and a mock cjxl writing a real encode of it.
+	for test_set in ["a", "b"] {
+		SmokeTest::generate_image(&dir.join(format!("test_images/{}/gradient.png", test_set))).unwrap();
+	}
+	let image = image::open(dir.join("test_images/a/gradient.png")).unwrap().to_rgb8();
+	let mut encoder = jpegxl_rs::encoder_builder().build().unwrap();
+	let encoded: jpegxl_rs::encode::EncoderResult<u8> =
+		encoder.encode::<u8, u8>(image.as_raw(), image.width(), image.height()).unwrap();
+	std::fs::write(dir.join("cjxl.jxl"), &encoded.data).unwrap();
+
+	// A mock AVIF baseline of the gradient at quality 90.
+	let mut avif = comparison_result("gradient", 90.0, 0);
+	avif.comp_image_name = "gradient__q90.avif".to_string();
+	let baseline_csv = dir.join("baseline.csv");
+	let csv = ComparisonResultCSV::new();
+	csv.write_csv_header(baseline_csv.to_str().unwrap()).unwrap();
+	csv.write_csv(&vec![avif], baseline_csv.to_str().unwrap(), DEFAULT_FLOAT_DIGITS).unwrap();
+
+	let benchmark_dir = dir.join("benchmarks");
+	let config = Config {
+		docker_bin: docker,
+		benchmark_dir_path: benchmark_dir.to_str().unwrap().to_string(),
+		local_test_image_dir_path: dir.join("test_images").to_str().unwrap().to_string(),
+		num_workers: 1,
+		libjxl_commit: Some("abc123".to_string()),
+		distances: vec![1.0],
+		efforts: vec![7],
+		keep_compressed: false,
+		baseline_csv: Some(baseline_csv.to_str().unwrap().to_string()),
+		baseline_codec: "avif".to_string(),
+		..Config::default()
+	};
+	let mut benchmarker = Benchmarker::new(&config).unwrap();
+	benchmarker.run_benchmark::<JXLCompressionBenchmark>().unwrap();
+	benchmarker.wait_for_all_workers();
+
+	// Every test set is diffed against the baseline, not only the first.
+	for test_set in ["a", "b"] {
+		let diffs_file = benchmark_dir.join(format!("0/results/comp/{}/abc123/comparison_diffs.csv", test_set));
+		let diffs = std::fs::read_to_string(&diffs_file).unwrap();
+		assert_eq!(diffs.lines().count(), 2, "{}: {}", test_set, diffs);
+		assert!(diffs.contains("avif"), "{}: {}", test_set, diffs);
+	}
+}
+
+#[test]
+fn test_local_diffs_per_test_set() {
+	use benchmark_jpegxl::config::{Config, LocalDiff};
+	use benchmark_jpegxl::smoke::SmokeTest;
+
+	// The mock docker logs every invocation next to itself.
+	let dir = test_dir("local_diffs_test_sets");
+	let docker = mock_docker(&dir);
+
+	// Two test sets of the same gradient, and a mock cjxl writing a real encode of it.
+	for test_set in ["a", "b"] {
+		SmokeTest::generate_image(&dir.join(format!("test_images/{}/gradient.png", test_set))).unwrap();
+	}
+	let image = image::open(dir.join("test_images/a/gradient.png")).unwrap().to_rgb8();
+	let mut encoder = jpegxl_rs::encoder_builder().build().unwrap();
+	let encoded: jpegxl_rs::encode::EncoderResult<u8> =
+		encoder.encode::<u8, u8>(image.as_raw(), image.width(), image.height()).unwrap();
+	std::fs::write(dir.join("cjxl.jxl"), &encoded.data).unwrap();
+
+	let diff_a = dir.join("faster-dct.diff");
+	let diff_b = dir.join("tweak.diff");
+	std::fs::write(&diff_a, "diff a").unwrap();
+	std::fs::write(&diff_b, "diff b").unwrap();
+
+	let benchmark_dir = dir.join("benchmarks");
+	let config = Config {
+		docker_bin: docker,
+		benchmark_dir_path: benchmark_dir.to_str().unwrap().to_string(),
+		local_test_image_dir_path: dir.join("test_images").to_str().unwrap().to_string(),
+		num_workers: 1,
+		libjxl_commit: Some("abc123".to_string()),
+		distances: vec![1.0],
+		efforts: vec![7],
+		keep_compressed: false,
+		diffs: vec![
+			diff_a.to_str().unwrap().parse::<LocalDiff>().unwrap(),
+			diff_b.to_str().unwrap().parse::<LocalDiff>().unwrap(),
+		],
+		..Config::default()
+	};
+	let mut benchmarker = Benchmarker::new(&config).unwrap();
+	benchmarker.run_benchmark::<JXLCompressionBenchmark>().unwrap();
+	benchmarker.wait_for_all_workers();
+
+	// Both diffs are benchmarked and diffed against each other on every test set.
+	for test_set in ["a", "b"] {
+		let set_dir = benchmark_dir.join(format!("0/results/comp/{}", test_set));
+		assert!(set_dir.join("tweak/comparisons.csv").exists(), "{}", test_set);
+		let diffs = std::fs::read_to_string(set_dir.join("faster-dct/comparison_diffs.csv")).unwrap();
+		assert_eq!(diffs.lines().count(), 2, "{}: {}", test_set, diffs);
+	}
+}
+
+#[test]
+fn test_rate_aligned_diff() {
+	// A point of a synthetic rate-quality curve.
+	let point = |distance: f32, bpp: f64, ssimulacra2: f64| {
+		let mut result = comparison_result("kodim01", distance, 7);
+		result.bpp = bpp;
+		result.comp_file_size = (bpp * 1000.0) as u64;
+		result.ssimulacra2 = ssimulacra2;
+		result.psnr = 20.0 + 10.0 * bpp;
+		result
+	};
+
+	// The baseline run at distances 1, 2 and 3.
+	let baseline = vec![point(1.0, 3.0, 90.0), point(2.0, 2.0, 80.0), point(3.0, 1.0, 70.0)];
+
+	// The same curve, but each distance now spends more bits. Diffed at equal distance it
+	// looks better, although the quality at equal rate is unchanged.
+	let shifted = vec![point(1.0, 4.0, 95.0), point(2.0, 3.0, 90.0), point(3.0, 2.0, 80.0)];
+	let diffs = JXLCompressionBenchmark::diff_results(&baseline, &shifted, None);
+	assert_eq!(diffs[1].diff_ssimulacra2, 10.0);
+
+	// At the rates of the baseline, the quality is the same. The lowest rate of the baseline is
+	// below the curve of the run, so it is not extrapolated.
+	let aligned = JXLCompressionBenchmark::rate_aligned_results(&baseline, &shifted);
+	assert_eq!(aligned.len(), 2);
+	let diffs = JXLCompressionBenchmark::diff_results(&baseline, &aligned, None);
+	assert_eq!(diffs.len(), 2);
+	for diff in &diffs {
+		assert_eq!(diff.diff_ssimulacra2, 0.0);
+		assert_eq!(diff.diff_comp_file_size, 0.0);
+		assert!(diff.diff_psnr.abs() < 1e-9);
+	}
+
+	// A curve that is better by 2 at every rate, sampled at other rates, is interpolated
+	// between its points.
+	let better = vec![point(1.0, 3.5, 97.0), point(2.0, 2.5, 87.0), point(3.0, 0.5, 67.0)];
+	let aligned = JXLCompressionBenchmark::rate_aligned_results(&baseline, &better);
+	assert_eq!(aligned.len(), 3);
+	assert_eq!(aligned[0].comp_image_name, baseline[0].comp_image_name);
+	assert_eq!(aligned[0].bpp, 3.0);
+	let diffs = JXLCompressionBenchmark::diff_results(&baseline, &aligned, None);
+	assert_eq!(diffs.len(), 3);
+	for diff in &diffs {
+		assert!((diff.diff_ssimulacra2 - 2.0).abs() < 1e-9, "{}", diff.diff_ssimulacra2);
+	}
+
+	// Failed encodes are neither anchors nor points of the curve.
+	let mut failed = point(4.0, 0.2, 10.0);
+	failed.status = ComparisonResult::STATUS_ENCODE_FAILED.to_string();
+	let mut with_failure = better.clone();
+	with_failure.push(failed.clone());
+	assert_eq!(JXLCompressionBenchmark::rate_aligned_results(&baseline, &with_failure).len(), 3);
+	let mut baseline_with_failure = baseline.clone();
+	baseline_with_failure.push(failed);
+	assert_eq!(JXLCompressionBenchmark::rate_aligned_results(&baseline_with_failure, &better).len(), 3);
+}
+
+#[test]
+fn test_sorted_discovery_and_comparisons() {
+	let dir = test_dir("sorted_discovery");
+	let test_set = dir.join("test_images/kodak");
+	std::fs::create_dir_all(&test_set).unwrap();
+	std::fs::create_dir_all(dir.join("test_images/clic")).unwrap();
+	for name in ["kodim03.png", "kodim01.png", "notes.txt", "kodim02.png"] {
+		std::fs::write(test_set.join(name), []).unwrap();
+	}
+
+	// Test sets and images are listed in sorted order, skipping unsupported files.
+	let test_sets = Benchmarker::get_all_test_set_names(dir.join("test_images").to_str().unwrap().to_string());
+	assert_eq!(test_sets, vec!["clic", "kodak"]);
+	let image_paths = Benchmarker::get_test_set_image_paths(test_set.to_str().unwrap());
+	let names = image_paths
+		.iter()
+		.map(|path| path.file_name().unwrap().to_str().unwrap())
+		.collect::<Vec<&str>>();
+	assert_eq!(names, vec!["kodim01.png", "kodim02.png", "kodim03.png"]);
+
+	// Comparisons appended by workers in completion order are sorted by image name.
+	let comparisons_file = dir.join("comparisons.csv");
+	let comparisons_file = comparisons_file.to_str().unwrap();
+	let results = vec![
+		comparison_result("kodim02", 1.0, 7),
+		comparison_result("kodim01", 2.0, 7),
+		comparison_result("kodim03", 1.0, 7),
+		comparison_result("kodim01", 1.0, 7),
+	];
+	let csv_writer = ComparisonResultCSV::new();
+	csv_writer.write_csv_header(comparisons_file).unwrap();
+	csv_writer.write_csv(&results, comparisons_file, DEFAULT_FLOAT_DIGITS).unwrap();
+
+	JXLCompressionBenchmark::sort_comparisons(comparisons_file, DEFAULT_FLOAT_DIGITS);
+	let sorted = ComparisonResultCSV::new().read_csv(comparisons_file).unwrap();
+	let rows = sorted
+		.iter()
+		.map(|result| result.comp_image_name.as_str())
+		.collect::<Vec<&str>>();
+	assert_eq!(rows, vec!["kodim01__d1__e7.jxl", "kodim01__d2__e7.jxl", "kodim02__d1__e7.jxl", "kodim03__d1__e7.jxl"]);
+}
+
+#[test]
+fn test_empty_or_truncated_encode_is_recorded_as_failed() {
+	use benchmark_jpegxl::image_reader::ImageReader;
+	use benchmark_jpegxl::smoke::SmokeTest;
+
+	let dir = test_dir("empty_encode");
+	let comp_image_name = ImageReader::compressed_image_name("kodim01", 1.0, 7);
+	let comp = dir.join(&comp_image_name);
+	std::fs::write(&comp, []).unwrap();
+
+	// An empty output file is rejected before any metric is computed.
+	let image_reader = ImageReader::try_new(comp.to_str().unwrap().to_string(), "main".to_string());
+	assert!(JXLCompressionBenchmark::validate_encode(image_reader, 32, 32).is_err());
+
+	// So is a real encode cut off mid-codestream, whose header still reads.
+	let image_path = dir.join("gradient.png");
+	SmokeTest::generate_image(&image_path).unwrap();
+	let image = image::open(&image_path).unwrap().to_rgb8();
+	let mut encoder = jpegxl_rs::encoder_builder().build().unwrap();
+	let encoded: jpegxl_rs::encode::EncoderResult<u8> =
+		encoder.encode::<u8, u8>(image.as_raw(), image.width(), image.height()).unwrap();
+	let truncated = dir.join(ImageReader::compressed_image_name("gradient", 1.0, 7));
+	std::fs::write(&truncated, &encoded.data[..encoded.data.len() / 2]).unwrap();
+	assert!(encoded.data.starts_with(&[0xff, 0x0a]));
+	let image_reader = ImageReader::try_new(truncated.to_str().unwrap().to_string(), "main".to_string());
+	let error = JXLCompressionBenchmark::validate_encode(image_reader, image.width(), image.height());
+	assert!(error.is_err(), "{:?}", error);
+
+	// The failure is recorded as an explicit row rather than silently missing.
+	let res_comp_path = dir.to_str().unwrap();
+	let failure = ComparisonResult::encode_failed("kodim01.png", &comp_image_name, 1.0, 7);
+	let csv_sink = std::sync::Mutex::new(CsvSink::new(DEFAULT_FLOAT_DIGITS));
+	JXLCompressionBenchmark::record_encode_failure(res_comp_path, OutputFormat::Csv, &failure, &csv_sink);
+	let results = ComparisonResultCSV::new()
+		.read_csv(&format!("{}/comparisons.csv", res_comp_path))
+		.unwrap();
+	assert_eq!(results.len(), 1);
+	assert_eq!(results[0].status, ComparisonResult::STATUS_ENCODE_FAILED);
+	assert_eq!(results[0].comp_file_size, 0);
+	assert!(results[0].ssimulacra2.is_nan());
+
+	// Failed encodes are left out of the aggregates.
+	let aggregates = JXLCompressionBenchmark::aggregate_results(
+		"kodak",
+		&vec![results[0].clone(), comparison_result("kodim02", 1.0, 7)],
+		SummaryWeighting::Equal,
+	);
+	assert_eq!(aggregates.len(), 1);
+	assert_eq!(aggregates[0].num_images, 1);
+}
+
+#[test]
+fn test_external_metric_is_parsed_and_recorded() {
+	use benchmark_jpegxl::config::ExternalMetric;
+	use benchmark_jpegxl::metrics::calculate_external_metric;
+	use std::os::unix::fs::PermissionsExt;
+
+	let dir = test_dir("external_metric");
+	let script = dir.join("score.sh");
+	std::fs::write(&script, "#!/bin/sh\necho \"comparing $1 to $2\"\necho \"score: 42.5 (higher is better)\"\n").unwrap();
+	std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+	// The value is captured from the line matching the pattern.
+	let metric = ExternalMetric::try_from(format!("myscore,{},score: (\\S+) \\(higher", script.to_str().unwrap())).unwrap();
+	assert!(!metric.in_container);
+	let docker_manager = DockerManager::new("Dockerfile", 0);
+	let value = calculate_external_metric(&metric, "orig.png", "comp.jxl", "", "", &docker_manager).unwrap();
+	assert_eq!(value, 42.5);
+
+	// Output that does not match the pattern is an error.
+	let unmatched = ExternalMetric::try_from(format!("other,{},vmaf=(\\S+)", script.to_str().unwrap())).unwrap();
+	assert!(calculate_external_metric(&unmatched, "orig.png", "comp.jxl", "", "", &docker_manager).is_err());
+
+	// Invalid metrics are errors: missing fields, an invalid regex, or no single capture group.
+	assert!(ExternalMetric::try_from("myscore,score.sh".to_string()).is_err());
+	assert!(ExternalMetric::try_from("myscore,score.sh,score: ([0-9.]+".to_string()).is_err());
+	assert!(ExternalMetric::try_from("myscore,score.sh,score: [0-9.]+".to_string()).is_err());
+	assert!(ExternalMetric::try_from("myscore,score.sh,(\\w+): ([0-9.]+)".to_string()).is_err());
+
+	// The value is recorded under a column named after the metric and survives sorting.
+	let comparisons_file = dir.join("comparisons.csv");
+	let comparisons_file = comparisons_file.to_str().unwrap();
+	let mut result = comparison_result("kodim01", 1.0, 7);
+	result.external_metrics = vec![(metric.name.clone(), value)];
+	let csv_writer = ComparisonResultCSV::with_external_metrics(result.external_metric_names());
+	csv_writer.write_csv_header(comparisons_file).unwrap();
+	csv_writer.write_csv(&vec![result], comparisons_file, DEFAULT_FLOAT_DIGITS).unwrap();
+	JXLCompressionBenchmark::sort_comparisons(comparisons_file, DEFAULT_FLOAT_DIGITS);
+
+	let header = std::fs::read_to_string(comparisons_file).unwrap();
+	assert!(header.lines().next().unwrap().ends_with(",myscore"));
+	let results = ComparisonResultCSV::new().read_csv(comparisons_file).unwrap();
+	assert_eq!(results[0].external_metrics, vec![("myscore".to_string(), 42.5)]);
+}
+
+#[test]
+fn test_distances_join_across_formatting() {
+	// A CSV written with fewer digits reads 1.5 back, one written at full precision 1.5000001.
+	let run_1 = vec![comparison_result("kodim01", 1.5, 7), comparison_result("kodim01", 0.5, 7), comparison_result("kodim02", 1.5, 7)];
+	let mut run_2 = run_1.iter().rev().cloned().collect::<Vec<ComparisonResult>>();
+	run_2[0].distance = 1.5000001;
+	run_2[0].comp_image_name = "kodim02__d1.5000001__e7.jxl".to_string();
+	assert_ne!(run_1[2].distance, run_2[0].distance);
+	assert_eq!(run_1[2].distance_key(), run_2[0].distance_key());
+	assert_eq!(DistanceKey::new(1.5), DistanceKey(1500));
+	assert!(DistanceKey::new(0.5) < DistanceKey::new(1.5));
+
+	// The rows of both runs are joined on the image, distance and effort in any order.
+	let diffs = JXLCompressionBenchmark::diff_results(&run_1, &run_2, None);
+	assert_eq!(diffs.len(), 3);
+	let joined = diffs.iter().map(|diff| (diff.orig_image_name.as_str(), diff.distance)).collect::<Vec<(&str, f32)>>();
+	assert_eq!(joined, vec![("kodim01.png", 0.5), ("kodim01.png", 1.5), ("kodim02.png", 1.5000001)]);
+	assert!(diffs.iter().all(|diff| diff.diff_comp_file_size == 0.0));
+
+	// A key repeated in the second run is diffed against the same row of the first, and rows
+	// only in one run are left out.
+	let repeated = vec![run_1[0].clone(), run_1[0].clone()];
+	assert_eq!(JXLCompressionBenchmark::diff_results(&run_1[..1].to_vec(), &repeated, None).len(), 2);
+	assert_eq!(JXLCompressionBenchmark::diff_results(&run_1, &repeated, None).len(), 2);
+
+	// Aggregates group both spellings of the distance together.
+	let mut results = run_1.clone();
+	results.push(run_2[0].clone());
+	let aggregates = JXLCompressionBenchmark::aggregate_results("set", &results, SummaryWeighting::Equal);
+	let distances = aggregates.iter().map(|aggregate| (aggregate.distance, aggregate.num_images)).collect::<Vec<(f32, usize)>>();
+	assert_eq!(distances, vec![(0.5, 1), (1.5, 3)]);
+}
+
+#[test]
+fn test_mismatched_originals_are_detected() {
+	let run_1 = vec![comparison_result("kodim01", 1.0, 7), comparison_result("kodim02", 1.0, 7)];
+
+	// Runs of the same originals pass the guard.
+	let diffs = JXLCompressionBenchmark::diff_results(&run_1, &run_1.clone(), None);
+	assert!(JXLCompressionBenchmark::mismatched_originals(&diffs).is_empty());
+
+	// A different source image triggers the guard.
+	let mut run_2 = run_1.clone();
+	run_2[1].orig_file_size += 1;
+	run_2[1].orig_raw_size += 3;
+	let diffs = JXLCompressionBenchmark::diff_results(&run_1, &run_2, None);
+	assert_eq!(JXLCompressionBenchmark::mismatched_originals(&diffs), vec!["kodim02.png".to_string()]);
+
+	// The placeholder sizes of a failed encode are not mistaken for a different original.
+	let mut run_2 = run_1.clone();
+	run_2[0] = ComparisonResult::encode_failed("kodim01.png", &run_1[0].comp_image_name, 1.0, 7);
+	let diffs = JXLCompressionBenchmark::diff_results(&run_1, &run_2, None);
+	assert!(diffs[0].diff_orig_file_size.is_nan());
+	assert!(JXLCompressionBenchmark::mismatched_originals(&diffs).is_empty());
+}
+
+#[test]
+fn test_quality_sweep() {
+	// Distances and qualities cannot both be swept.
+	assert!(Sweep::new(&[1.0], &[90.0]).is_err());
+	assert!(Sweep::new(&[], &[101.0]).is_err());
+	assert_eq!(Sweep::new(&[], &[]).unwrap(), Sweep::Distance(Sweep::DEFAULT_DISTANCES.to_vec()));
+
+	// Qualities are encoded at the distance cjxl maps them to.
+	let sweep = Sweep::new(&[], &[90.0, 100.0]).unwrap();
+	assert_eq!(sweep.encodes(), vec![(1.0, Some(90.0)), (0.0, Some(100.0))]);
+
+	// The quality flag replaces the distance flag.
+	let args = DockerManager::cjxl_args("in.png", "out.jxl", 1.0, Some(90.0), 7, None, &[]);
+	assert!(args.contains(&"--quality=90".to_string()));
+	assert!(args.iter().all(|arg| !arg.starts_with("--distance")));
+
+	// The quality is recorded in the comparison row.
+	let dir = test_dir("quality_sweep");
+	let result_file = dir.join("comparisons.csv");
+	let result_file = result_file.to_str().unwrap();
+	let mut result = comparison_result("kodim01", 1.0, 7);
+	result.quality = "90".to_string();
+
+	let csv = ComparisonResultCSV::new();
+	csv.write_csv_header(result_file).unwrap();
+	csv.write_csv(&vec![result], result_file, DEFAULT_FLOAT_DIGITS).unwrap();
+	let read = csv.read_csv(result_file).unwrap();
+	assert_eq!(read[0].quality, "90");
+	assert_eq!(read[0].distance, 1.0);
+}
+
+#[test]
+fn test_concurrent_run_reservations_differ() {
+	let dir = test_dir("reserve_run");
+	std::fs::create_dir_all(dir.join("0")).unwrap();
+	let benchmark_dir = dir.to_str().unwrap().to_string();
+
+	// Both threads start reserving at the same time, and would both pick run 1 by scanning.
+	let barrier = std::sync::Arc::new(std::sync::Barrier::new(2));
+	let threads = (0..2)
+		.map(|_| {
+			let barrier = barrier.clone();
+			let benchmark_dir = benchmark_dir.clone();
+			std::thread::spawn(move || {
+				barrier.wait();
+				Benchmarker::reserve_run(&benchmark_dir).unwrap()
+			})
+		})
+		.collect::<Vec<_>>();
+	let mut runs = threads.into_iter().map(|thread| thread.join().unwrap()).collect::<Vec<usize>>();
+	runs.sort();
+
+	assert_eq!(runs, vec![1, 2]);
+	assert!(dir.join("1").is_dir() && dir.join("2").is_dir());
+	assert_eq!(Benchmarker::get_current_run(benchmark_dir), 3);
+}
+
+#[test]
+fn test_diff_runs() {
+	let dir = test_dir("diff_runs");
+	let benchmark_dir = dir.to_str().unwrap();
+
+	// Seed two finished runs of the local build, where the second also benchmarked kodim03 and
+	// a test set the first did not.
+	let seed_run = |run: usize, test_sets: &[&str], results: Vec<ComparisonResult>| {
+		RunManifest {
+			run,
+			test_sets: test_sets.iter().map(|test_set| test_set.to_string()).collect(),
+			libjxl_commits: vec!["local".to_string()],
+			..RunManifest::default()
+		}
+		.save(benchmark_dir)
+		.unwrap();
+		let comparisons_file = dir.join(format!("{}/results/comp/set/local/comparisons.csv", run));
+		std::fs::create_dir_all(comparisons_file.parent().unwrap()).unwrap();
+		let comparisons_file = comparisons_file.to_str().unwrap();
+		ComparisonResultCSV::new().write_csv_header(comparisons_file).unwrap();
+		ComparisonResultCSV::new().write_csv(&results, comparisons_file, DEFAULT_FLOAT_DIGITS).unwrap();
+	};
+	seed_run(1, &["set"], vec![comparison_result("kodim01", 1.0, 7), comparison_result("kodim02", 1.0, 7)]);
+	let mut kodim01 = comparison_result("kodim01", 1.0, 7);
+	kodim01.comp_file_size = 90;
+	seed_run(2, &["set", "other"], vec![comparison_result("kodim03", 1.0, 7), kodim01, comparison_result("kodim02", 1.0, 7)]);
+
+	// Only the images both runs benchmarked are diffed.
+	let result_dirs = JXLCompressionBenchmark::diff_runs(benchmark_dir, 1, 2, SummaryWeighting::Equal, &[], false).unwrap();
+	assert_eq!(result_dirs, vec![format!("{}/2/results/diff/1/set", benchmark_dir)]);
+	let mut reader = csv::Reader::from_path(format!("{}/comparison_diffs.csv", result_dirs[0])).unwrap();
+	let diffs = reader.records().map(|record| {
+		let record = record.unwrap();
+		(record[0].to_string(), record[5].parse::<f64>().unwrap())
+	});
+	assert_eq!(diffs.collect::<Vec<(String, f64)>>(), vec![("kodim01.png".to_string(), -10.0), ("kodim02.png".to_string(), 0.0)]);
+	assert!(PathBuf::from(&result_dirs[0]).join("summary.csv").exists());
+
+	// A run without a manifest cannot be diffed.
+	let error = JXLCompressionBenchmark::diff_runs(benchmark_dir, 1, 3, SummaryWeighting::Equal, &[], false).err().unwrap().to_string();
+	assert!(error.contains("run 3"), "{}", error);
+}
+
+#[test]
+fn test_gates() {
+	let dir = test_dir("gates");
+	let benchmark_dir = dir.to_str().unwrap();
+
+	// Seed a baseline run and a run whose mean SSIMULACRA2 dropped by 1 and Butteraugli rose by 0.1.
+	let seed_run = |run: usize, results: Vec<ComparisonResult>| {
+		RunManifest {
+			run,
+			test_sets: vec!["set".to_string()],
+			libjxl_commits: vec!["local".to_string()],
+			..RunManifest::default()
+		}
+		.save(benchmark_dir)
+		.unwrap();
+		let comparisons_file = dir.join(format!("{}/results/comp/set/local/comparisons.csv", run));
+		std::fs::create_dir_all(comparisons_file.parent().unwrap()).unwrap();
+		let comparisons_file = comparisons_file.to_str().unwrap();
+		ComparisonResultCSV::new().write_csv_header(comparisons_file).unwrap();
+		ComparisonResultCSV::new().write_csv(&results, comparisons_file, DEFAULT_FLOAT_DIGITS).unwrap();
+	};
+	seed_run(1, vec![comparison_result("kodim01", 1.0, 7), comparison_result("kodim02", 1.0, 7)]);
+	let regressed = ["kodim01", "kodim02"].map(|image_name| {
+		let mut result = comparison_result(image_name, 1.0, 7);
+		result.ssimulacra2 -= 1.0;
+		result.butteraugli += 0.1;
+		result
+	});
+	seed_run(2, regressed.to_vec());
+
+	// Gates are parsed from metric:threshold.
+	let ssimulacra2_gate = "ssimulacra2:0.5".parse::<Gate>().unwrap();
+	assert_eq!(ssimulacra2_gate.threshold, 0.5);
+	assert_eq!(ssimulacra2_gate.to_string(), "ssimulacra2:0.5");
+	assert!("ssimulacra2".parse::<Gate>().is_err());
+	assert!("ssimulacra2:-1".parse::<Gate>().is_err());
+	assert!("vmaf:0.5".parse::<Gate>().is_err());
+	assert_eq!("MS_SSIM".parse::<MetricKind>(), Ok(MetricKind::MsSsim));
+	assert!("vmaf".parse::<MetricKind>().is_err());
+
+	// Regressions within the gates pass.
+	let gates = ["ssimulacra2:1.5".parse::<Gate>().unwrap(), "butteraugli:0.2".parse::<Gate>().unwrap()];
+	assert!(JXLCompressionBenchmark::diff_runs(benchmark_dir, 1, 2, SummaryWeighting::Equal, &gates, false).is_ok());
+
+	// A drop of the mean SSIMULACRA2 by more than the gate fails with a nonzero exit code, naming
+	// the gate, while the diffs are still written.
+	let gates = [ssimulacra2_gate, "butteraugli:0.2".parse::<Gate>().unwrap()];
+	let error = JXLCompressionBenchmark::diff_runs(benchmark_dir, 1, 2, SummaryWeighting::Equal, &gates, false).err().unwrap();
+	let error = error.downcast_ref::<BenchmarkError>().unwrap();
+	assert!(matches!(error, BenchmarkError::GateFailed(failures) if failures.len() == 1));
+	assert_ne!(error.exit_code(), 0);
+	assert!(error.to_string().contains("set: gate ssimulacra2:0.5 failed"), "{}", error);
+	assert!(dir.join("2/results/diff/1/set/summary.csv").exists());
+
+	// An improvement passes any gate.
+	assert!(JXLCompressionBenchmark::diff_runs(benchmark_dir, 2, 1, SummaryWeighting::Equal, &gates, false).is_ok());
+}
+
+#[test]
+fn test_local_diff_hash() {
+	let dir = test_dir("local_diff_hash");
+	let diff = dir.join("local.diff");
+	let diff_path = diff.to_str().unwrap();
+
+	// A missing diff is a clear configuration error naming the diff and how to write it.
+	match Benchmarker::local_diff_hash(diff_path) {
+		Err(BenchmarkError::Config(message)) => {
+			assert!(message.contains(diff_path), "{}", message);
+			assert!(message.contains("git diff > local.diff"), "{}", message);
+		}
+		Err(e) => panic!("unexpected error: {}", e),
+		Ok(hash) => panic!("a missing diff should not have a hash: {}", hash),
+	}
+
+	// The hash identifies the changes that were benchmarked.
+	std::fs::write(&diff, "diff --git a/lib/jxl/enc.cc b/lib/jxl/enc.cc\n").unwrap();
+	let hash = Benchmarker::local_diff_hash(diff_path).unwrap();
+	assert_eq!(hash.len(), 64);
+	std::fs::write(&diff, "diff --git a/lib/jxl/dec.cc b/lib/jxl/dec.cc\n").unwrap();
+	assert_ne!(Benchmarker::local_diff_hash(diff_path).unwrap(), hash);
+}
@@ -0,0 +1,72 @@
+use std::os::unix::fs::PermissionsExt;
+
+use benchmark_jpegxl::benchmark::{BenchmarkError, Benchmarker, JXLCompressionBenchmark};
+use benchmark_jpegxl::config::Config;
+use benchmark_jpegxl::docker_manager::DockerManager;
+
+mod common;
+use common::{mock_docker, test_dir};
+
+#[test]
+fn test_worker_setup_failure_names_the_worker() {
+	// A mock docker binary that logs its invocations and fails to start the second container.
+	let dir = test_dir("benchmarker_setup");
+	let script = format!(
+		"#!/bin/sh\necho \"$@\" >> \"$(dirname \"$0\")/docker.log\"\nif [ \"$1\" = run ] && [ \"$3\" = {}-1 ]; then\n  echo 'port is already allocated' >&2\n  exit 1\nfi\nexit 0\n",
+		DockerManager::CONTAINER_NAME
+	);
+	let docker = dir.join("docker");
+	std::fs::write(&docker, script).unwrap();
+	std::fs::set_permissions(&docker, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+	std::fs::create_dir_all(dir.join("test_images/set")).unwrap();
+	let config = Config {
+		benchmark_dir_path: dir.join("benchmarks").to_str().unwrap().to_string(),
+		local_test_image_dir_path: dir.join("test_images").to_str().unwrap().to_string(),
+		num_workers: 2,
+		docker_bin: docker.to_str().unwrap().to_string(),
+		..Config::default()
+	};
+	let benchmarker = Benchmarker::new(&config);
+
+	match benchmarker {
+		Err(BenchmarkError::WorkerSetup { worker_id, message }) => {
+			assert_eq!(worker_id, 1);
+			assert!(message.contains("port is already allocated"));
+
+			// The container of the first worker, which was set up, is torn down.
+			let log = std::fs::read_to_string(dir.join("docker.log")).unwrap();
+			let container = format!("{}-0", DockerManager::CONTAINER_NAME);
+			assert!(log.lines().any(|line| line == format!("stop {}", container)));
+			assert!(log.lines().any(|line| line == format!("rm {}", container)));
+		}
+		Err(e) => panic!("unexpected error: {}", e),
+		Ok(_) => panic!("Benchmarker::new should fail when a container cannot be started"),
+	}
+}
+
+#[test]
+fn test_unresolved_default_commit_is_a_setup_error() {
+	// The mock docker prints no SHA for rev-parse without a `rev_parse` file.
+	let dir = test_dir("benchmarker_default_commit");
+	let docker = mock_docker(&dir);
+	std::fs::create_dir_all(dir.join("test_images/set")).unwrap();
+	let config = Config {
+		benchmark_dir_path: dir.join("benchmarks").to_str().unwrap().to_string(),
+		local_test_image_dir_path: dir.join("test_images").to_str().unwrap().to_string(),
+		num_workers: 1,
+		docker_bin: docker,
+		..Config::default()
+	};
+	let mut benchmarker = Benchmarker::new(&config).unwrap();
+
+	// The run fails before anything is benchmarked, instead of panicking.
+	let error = benchmarker.run_benchmark::<JXLCompressionBenchmark>().err().unwrap();
+	match error.downcast_ref::<BenchmarkError>() {
+		Some(BenchmarkError::WorkerSetup { worker_id, message }) => {
+			assert_eq!(*worker_id, 0);
+			assert!(message.contains("default libjxl commit main"), "{}", message);
+		}
+		_ => panic!("unexpected error: {}", error),
+	}
+}
@@ -0,0 +1,112 @@
+use std::time::Instant;
+
+use benchmark_jpegxl::binary_results::*;
+use benchmark_jpegxl::config::OutputFormat;
+use benchmark_jpegxl::csv_writer::*;
+use benchmark_jpegxl::metrics::SizeRatio;
+
+mod common;
+use common::test_dir;
+
+/// Generates a varied comparison result, with full precision values and some metrics missing.
+fn comparison_result(i: u64) -> ComparisonResult {
+	let value = |salt: u64| ((i * 7919 + salt * 104729) % 1000003) as f64 / 997.0;
+	let missing = |value: f64, every: u64| if i % every == 0 { f64::NAN } else { value };
+	ComparisonResult {
+		orig_image_name: format!("image {} ünïcode.png", i % 1000),
+		comp_image_name: format!("image_{}-{}-{}.jxl", i % 1000, i % 25, i % 9 + 1),
+		distance: (i % 250) as f32 / 10.0,
+		effort: (i % 9 + 1) as u32,
+		orig_file_size: 1_000_000 + i * 13,
+		comp_file_size: 10_000 + i % 50_000,
+		orig_raw_size: 3 * 1024 * 1024,
+		comp_raw_size: 3 * 1024 * 1024,
+		comp_file_size_ratio: value(1),
+		raw_file_size_ratio: value(2),
+		size_ratio: SizeRatio::Compression,
+		mse: value(3),
+		psnr: value(4),
+		ssim: value(5) / 1003.0,
+		ms_ssim: value(6) / 1003.0,
+		butteraugli: missing(value(7), 3),
+		butteraugli_pnorm: missing(value(8), 3),
+		ssimulacra2: missing(-value(9), 5),
+		coding_mode: "VarDCT".to_string(),
+		size_regression: i % 11 == 0,
+		peak_rss_bytes: 50_000_000 + i,
+		cpu_seconds: value(10),
+		roi: if i % 4 == 0 { "10,20,30,40".to_string() } else { String::new() },
+		bpp: value(11),
+		intensity_target: String::new(),
+		status: if i % 97 == 0 {
+			ComparisonResult::STATUS_ENCODE_FAILED.to_string()
+		} else {
+			ComparisonResult::STATUS_OK.to_string()
+		},
+		channel_psnr: [value(12), value(13), value(14)],
+		channel_space: "rgb".to_string(),
+		source_is_lossy: i % 2 == 0,
+		quality: if i % 3 == 0 { format!("{}", i % 100) } else { String::new() },
+		decode_time_ms_min: f64::NAN,
+		decode_time_ms_mean: f64::NAN,
+		mse_vs_master: missing(value(15), 2),
+		psnr_vs_master: missing(value(16), 2),
+		ssim_vs_master: missing(value(17), 2),
+		ms_ssim_vs_master: missing(value(18), 2),
+		width: 1024,
+		height: 768,
+		color_managed: i % 5 == 0,
+		external_metrics: vec![("dssim".to_string(), missing(value(19), 7))],
+	}
+}
+
+#[test]
+fn test_binary_round_trip_of_a_large_sweep() {
+	let dir = test_dir("binary_round_trip");
+	let binary_file = dir.join("comparisons.bin").to_str().unwrap().to_string();
+	let csv_file = dir.join("comparisons.csv").to_str().unwrap().to_string();
+	assert_eq!(OutputFormat::from_file_name(&binary_file), OutputFormat::Binary);
+	assert_eq!(OutputFormat::from_file_name(&csv_file), OutputFormat::Csv);
+
+	// The CSV is written at full precision so both files hold the same values.
+	let results = (0..100_000).map(comparison_result).collect::<Vec<ComparisonResult>>();
+	write_comparisons(&binary_file, &results, 0).unwrap();
+	write_comparisons(&csv_file, &results, 0).unwrap();
+
+	// Every value reads back exactly, NaN included.
+	let binary_start = Instant::now();
+	let read_back = read_comparisons(&binary_file).unwrap();
+	let binary_time = binary_start.elapsed();
+	assert_eq!(read_back.len(), results.len());
+	for (read, written) in read_back.iter().zip(results.iter()) {
+		assert_eq!(format!("{:?}", read), format!("{:?}", written));
+	}
+
+	// Appending adds to the existing records.
+	let mut csv_sink = CsvSink::new(DEFAULT_FLOAT_DIGITS);
+	append_comparisons(&mut csv_sink, &binary_file, &vec![comparison_result(100_000)]).unwrap();
+	let appended = read_comparisons(&binary_file).unwrap();
+	assert_eq!(appended.len(), results.len() + 1);
+	assert_eq!(format!("{:?}", appended.last().unwrap()), format!("{:?}", comparison_result(100_000)));
+
+	// The binary file is smaller than the CSV.
+	let binary_size = std::fs::metadata(&binary_file).unwrap().len();
+	let csv_size = std::fs::metadata(&csv_file).unwrap().len();
+	assert!(binary_size < csv_size, "binary {} bytes, CSV {} bytes", binary_size, csv_size);
+
+	let csv_start = Instant::now();
+	assert_eq!(read_comparisons(&csv_file).unwrap().len(), results.len());
+	let csv_time = csv_start.elapsed();
+	println!(
+		"binary: {} bytes read in {:?}, CSV: {} bytes read in {:?}",
+		binary_size, binary_time, csv_size, csv_time
+	);
+}
+
+#[test]
+fn test_binary_reader_rejects_other_files() {
+	let dir = test_dir("binary_rejects");
+	let file = dir.join("comparisons.bin").to_str().unwrap().to_string();
+	std::fs::write(&file, "Original Image,Compressed Image\n").unwrap();
+	assert!(read_comparisons(&file).is_err());
+}
@@ -0,0 +1,144 @@
+use benchmark_jpegxl::benchmark::{Benchmarker, JXLCompressionBenchmark};
+use benchmark_jpegxl::binary_results::read_comparisons;
+use benchmark_jpegxl::config::{BuildConfig, Config};
+use benchmark_jpegxl::docker_manager::DockerManager;
+use benchmark_jpegxl::manifest::RunManifest;
+use benchmark_jpegxl::smoke::SmokeTest;
+
+mod common;
+use common::{mock_docker, test_dir};
+
+#[test]
+fn test_sweep_per_build_config() {
+	// The mock docker logs every invocation next to itself.
+	let dir = test_dir("build_configs");
+	let docker = mock_docker(&dir);
+
+	// The mock cjxl writes a real encode of the gradient.
+	let image_path = dir.join("test_images/set/gradient.png");
+	SmokeTest::generate_image(&image_path).unwrap();
+	let image = image::open(&image_path).unwrap().to_rgb8();
+	let mut encoder = jpegxl_rs::encoder_builder().build().unwrap();
+	let encoded: jpegxl_rs::encode::EncoderResult<u8> =
+		encoder.encode::<u8, u8>(image.as_raw(), image.width(), image.height()).unwrap();
+	std::fs::write(dir.join("cjxl.jxl"), &encoded.data).unwrap();
+
+	// Repeated build configurations collect their arguments into CMake flags and environment
+	// variables, and each argument is taken whole, commas included.
+	let specs = [
+		"no-avx2:-DCMAKE_CXX_FLAGS=-DHWY_DISABLED_TARGETS=HWY_AVX2",
+		"clang:CC=clang",
+		"no-avx2:-DJPEGXL_ENABLE_SKCMS=OFF",
+		"clang:CXX=clang++",
+	];
+	let build_configs = BuildConfig::parse_all(&specs.map(String::from)).unwrap();
+	assert_eq!(build_configs.len(), 2);
+	assert_eq!(
+		build_configs[0].cmake_flags,
+		["-DCMAKE_CXX_FLAGS=-DHWY_DISABLED_TARGETS=HWY_AVX2", "-DJPEGXL_ENABLE_SKCMS=OFF"]
+	);
+	assert!(build_configs[0].env.is_empty());
+	assert_eq!(build_configs[1].env, ["CC=clang", "CXX=clang++"]);
+	assert_eq!(build_configs[1].spec(), r#"clang:["CC=clang", "CXX=clang++"]"#);
+	let with_comma = BuildConfig::try_from("avx:-DHWY_DISABLED_TARGETS=HWY_SSE4,HWY_AVX3").unwrap();
+	assert_eq!(with_comma.cmake_flags, ["-DHWY_DISABLED_TARGETS=HWY_SSE4,HWY_AVX3"]);
+	assert_eq!(BuildConfig::try_from("default").unwrap().spec(), "default");
+
+	// Invalid specifications are errors rather than panics.
+	assert!(BuildConfig::try_from(":CC=clang").is_err());
+	assert!(BuildConfig::try_from("clang:clang").is_err());
+	assert!(BuildConfig::parse_all(&["clang:CC=clang".to_string(), "clang:=clang".to_string()]).is_err());
+	assert_eq!(BuildConfig::label("abc123@gcc", Some("clang")), "abc123@gcc+clang");
+
+	// Without a build configuration, libjxl is built as before.
+	assert_eq!(DockerManager::build_script(None), "cd /libjxl && SKIP_TEST=1 ./ci.sh opt; exit 0 && cd -");
+
+	let benchmark_dir = dir.join("benchmarks");
+	let mut config = Config {
+		docker_bin: docker,
+		benchmark_dir_path: benchmark_dir.to_str().unwrap().to_string(),
+		local_test_image_dir_path: dir.join("test_images").to_str().unwrap().to_string(),
+		num_workers: 1,
+		libjxl_commit: Some("abc123".to_string()),
+		distances: vec![1.0],
+		efforts: vec![7],
+		keep_compressed: false,
+		build_configs: vec![build_configs[0].clone(), build_configs[0].clone()],
+		..Config::default()
+	};
+	assert!(Benchmarker::new(&config).is_err());
+	config.build_configs = build_configs;
+	let mut benchmarker = Benchmarker::new(&config).unwrap();
+	benchmarker.run_benchmark::<JXLCompressionBenchmark>().unwrap();
+	benchmarker.wait_for_all_workers();
+
+	// The flags of each build configuration reach the build, without the cache of the other.
+	let log = std::fs::read_to_string(dir.join("docker.log")).unwrap();
+	let builds = log.lines().filter(|line| line.contains("./ci.sh opt")).collect::<Vec<&str>>();
+	assert_eq!(builds.len(), 2, "{}", log);
+	assert!(builds.iter().all(|build| build.contains("rm -f build/CMakeCache.txt")), "{}", log);
+	assert!(
+		builds[0].contains("CMAKE_FLAGS='-DCMAKE_CXX_FLAGS=-DHWY_DISABLED_TARGETS=HWY_AVX2 -DJPEGXL_ENABLE_SKCMS=OFF' SKIP_TEST=1"),
+		"{}",
+		builds[0]
+	);
+	assert!(builds[1].contains("CC='clang' CXX='clang++' SKIP_TEST=1"), "{}", builds[1]);
+	assert!(!builds[1].contains("CMAKE_FLAGS"), "{}", builds[1]);
+
+	// The results of each build configuration are labelled by the commit and configuration.
+	for label in ["abc123+no-avx2", "abc123+clang"] {
+		let comparisons_file = benchmark_dir.join(format!("0/results/comp/set/{}/comparisons.csv", label));
+		let results = read_comparisons(comparisons_file.to_str().unwrap()).unwrap();
+		assert_eq!(results.len(), 1, "{}", label);
+		assert!(results[0].is_ok() && results[0].ssim.is_finite(), "{:?}", results[0]);
+	}
+	let manifest = RunManifest::load(benchmark_dir.to_str().unwrap(), 0).unwrap();
+	assert_eq!(manifest.libjxl_commits, vec!["abc123+no-avx2", "abc123+clang"]);
+	assert!(!benchmark_dir.join("0/results/comp/set/abc123").exists());
+}
+
+#[test]
+fn test_compare_commits_per_build_config() {
+	let dir = test_dir("build_configs_compare");
+	let docker = mock_docker(&dir);
+
+	// The mock cjxl writes a real encode of the gradient.
+	let image_path = dir.join("test_images/set/gradient.png");
+	SmokeTest::generate_image(&image_path).unwrap();
+	let image = image::open(&image_path).unwrap().to_rgb8();
+	let mut encoder = jpegxl_rs::encoder_builder().build().unwrap();
+	let encoded: jpegxl_rs::encode::EncoderResult<u8> =
+		encoder.encode::<u8, u8>(image.as_raw(), image.width(), image.height()).unwrap();
+	std::fs::write(dir.join("cjxl.jxl"), &encoded.data).unwrap();
+
+	// Two build configurations of two commits make four comparison CSVs, diffed per build
+	// configuration instead of panicking.
+	let specs = ["no-avx2:-DJPEGXL_ENABLE_SKCMS=OFF", "clang:CC=clang"];
+	let benchmark_dir = dir.join("benchmarks");
+	let config = Config {
+		docker_bin: docker,
+		benchmark_dir_path: benchmark_dir.to_str().unwrap().to_string(),
+		local_test_image_dir_path: dir.join("test_images").to_str().unwrap().to_string(),
+		num_workers: 1,
+		libjxl_commit: Some("abc123".to_string()),
+		compare_to_commit: Some("def456".to_string()),
+		distances: vec![1.0],
+		efforts: vec![7],
+		keep_compressed: false,
+		build_configs: BuildConfig::parse_all(&specs.map(String::from)).unwrap(),
+		..Config::default()
+	};
+	let mut benchmarker = Benchmarker::new(&config).unwrap();
+	benchmarker.run_benchmark::<JXLCompressionBenchmark>().unwrap();
+	benchmarker.wait_for_all_workers();
+
+	// Each build configuration of the first commit is diffed against the same one of the second.
+	let set_dir = benchmark_dir.join("0/results/comp/set");
+	for label in ["abc123+no-avx2", "abc123+clang", "def456+no-avx2", "def456+clang"] {
+		assert!(set_dir.join(label).join("comparisons.csv").exists(), "{}", label);
+	}
+	for label in ["abc123+no-avx2", "abc123+clang"] {
+		assert!(set_dir.join(label).join("comparison_diffs.csv").exists(), "{}", label);
+	}
+	assert!(!set_dir.join("def456+no-avx2/comparison_diffs.csv").exists());
+}
@@ -0,0 +1,42 @@
+use benchmark_jpegxl::cache::EncodeCache;
+
+mod common;
+use common::test_dir;
+
+#[test]
+fn test_encode_cache_hit_on_same_sha() {
+	let dir = test_dir("encode_cache");
+	let benchmark_dir = dir.to_str().unwrap();
+	let sha = "0123456789abcdef0123456789abcdef01234567";
+	let other_sha = "fedcba9876543210fedcba9876543210fedcba98";
+
+	// The first run encodes the image and records the output.
+	let output = dir.join("kodim01__d1__e7.jxl");
+	std::fs::write(&output, b"jxl").unwrap();
+	let mut first_run = EncodeCache::load(benchmark_dir).unwrap();
+	let key = EncodeCache::key("source-hash", 1.0, 7, "default", sha, "");
+	assert!(first_run.lookup(&key).is_none());
+	first_run.insert(key.clone(), output.to_str().unwrap().to_string());
+	first_run.save(benchmark_dir).unwrap();
+
+	// A second run with the same SHA hits the cache, a different SHA does not.
+	let second_run = EncodeCache::load(benchmark_dir).unwrap();
+	assert_eq!(second_run.lookup(&key), Some(output.to_str().unwrap().to_string()));
+	assert!(second_run
+		.lookup(&EncodeCache::key("source-hash", 1.0, 7, "default", other_sha, ""))
+		.is_none());
+	assert!(second_run
+		.lookup(&EncodeCache::key("source-hash", 1.0, 8, "default", sha, ""))
+		.is_none());
+
+	// Encodes in another coding mode at the same parameters do not collide.
+	for coding_mode in ["modular", "vardct"] {
+		assert!(second_run
+			.lookup(&EncodeCache::key("source-hash", 1.0, 7, coding_mode, sha, ""))
+			.is_none());
+	}
+
+	// Entries whose output was removed are misses.
+	std::fs::remove_file(&output).unwrap();
+	assert!(second_run.lookup(&key).is_none());
+}
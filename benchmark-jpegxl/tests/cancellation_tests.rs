@@ -0,0 +1,97 @@
+use std::time::{Duration, Instant};
+
+use benchmark_jpegxl::benchmark::{BenchmarkError, Benchmarker, JXLCompressionBenchmark};
+use benchmark_jpegxl::binary_results::read_comparisons;
+use benchmark_jpegxl::config::Config;
+use benchmark_jpegxl::smoke::SmokeTest;
+
+mod common;
+use common::{mock_docker, test_dir};
+
+#[test]
+fn test_cancel_after_combinations() {
+	// The mock docker logs every invocation next to itself.
+	let dir = test_dir("cancellation");
+	let docker = mock_docker(&dir);
+
+	// The mock cjxl writes a real encode of the gradient, and holds the encode after the first
+	// two until it is resumed.
+	let completed = 2;
+	let image_path = dir.join("test_images/set/gradient.png");
+	SmokeTest::generate_image(&image_path).unwrap();
+	let image = image::open(&image_path).unwrap().to_rgb8();
+	let mut encoder = jpegxl_rs::encoder_builder().build().unwrap();
+	let encoded: jpegxl_rs::encode::EncoderResult<u8> =
+		encoder.encode::<u8, u8>(image.as_raw(), image.width(), image.height()).unwrap();
+	std::fs::write(dir.join("cjxl.jxl"), &encoded.data).unwrap();
+	std::fs::write(dir.join("block_cjxl"), completed.to_string()).unwrap();
+
+	let benchmark_dir = dir.join("benchmarks");
+	let config = Config {
+		docker_bin: docker,
+		benchmark_dir_path: benchmark_dir.to_str().unwrap().to_string(),
+		local_test_image_dir_path: dir.join("test_images").to_str().unwrap().to_string(),
+		num_workers: 1,
+		libjxl_commit: Some("abc123".to_string()),
+		distances: vec![1.0, 2.0, 3.0, 4.0],
+		efforts: vec![7],
+		keep_compressed: false,
+		..Config::default()
+	};
+	let mut benchmarker = Benchmarker::new(&config).unwrap();
+
+	// Cancel the run from another thread while the third encode is in progress.
+	let cancellation = benchmarker.cancellation.clone();
+	let blocked_dir = dir.clone();
+	let canceller = std::thread::spawn(move || {
+		let start = Instant::now();
+		while !blocked_dir.join("blocked").exists() {
+			assert!(start.elapsed() < Duration::from_secs(60), "the third encode never started");
+			std::thread::sleep(Duration::from_millis(10));
+		}
+		cancellation.cancel();
+		std::fs::write(blocked_dir.join("resume"), "").unwrap();
+	});
+	let error = benchmarker.run_benchmark::<JXLCompressionBenchmark>().unwrap_err();
+	canceller.join().unwrap();
+	assert!(matches!(error.downcast_ref::<BenchmarkError>(), Some(BenchmarkError::Cancelled)), "{}", error);
+	assert!(benchmarker.cancellation.is_cancelled());
+	assert!(benchmarker.failures.messages().is_empty(), "{:?}", benchmarker.failures.messages());
+
+	// No command is run for the combinations after the cancellation.
+	let log = std::fs::read_to_string(dir.join("docker.log")).unwrap();
+	let encodes = log
+		.lines()
+		.filter(|line| line.contains("/cjxl ") && !line.contains("--help") && !line.contains("--version"))
+		.count();
+	assert_eq!(encodes, completed + 1, "{}", log);
+
+	// The comparisons hold exactly the completed combinations, fully measured.
+	let results =
+		read_comparisons(benchmark_dir.join("0/results/comp/set/abc123/comparisons.csv").to_str().unwrap()).unwrap();
+	assert_eq!(results.len(), completed, "{:?}", results);
+	for (result, distance) in results.iter().zip([1.0, 2.0]) {
+		assert_eq!(result.distance, distance);
+		assert!(result.is_ok(), "{:?}", result);
+		assert!(result.ssim.is_finite() && result.ssimulacra2.is_finite(), "{:?}", result);
+	}
+
+	// The other combinations are recorded as skipped by the cancellation.
+	let mut reader = csv::Reader::from_path(benchmark_dir.join("0/results/status.csv")).unwrap();
+	let statuses = reader
+		.records()
+		.map(|record| {
+			let record = record.unwrap();
+			(record[3].to_string(), record[6].to_string(), record[7].to_string())
+		})
+		.collect::<Vec<(String, String, String)>>();
+	assert_eq!(
+		statuses,
+		vec![
+			("1".to_string(), "encoded".to_string(), "".to_string()),
+			("2".to_string(), "encoded".to_string(), "".to_string()),
+			("3".to_string(), "skipped".to_string(), "the run was cancelled".to_string()),
+			("4".to_string(), "skipped".to_string(), "the run was cancelled".to_string()),
+		]
+	);
+}
@@ -0,0 +1,115 @@
+use benchmark_jpegxl::color::*;
+use benchmark_jpegxl::image_reader::ImageReader;
+use benchmark_jpegxl::metrics::*;
+
+/// A 32x32 gradient of saturated colors tagged with a Display P3 profile.
+const DISPLAY_P3_IMAGE: &str = "tests/images/icc/display_p3.png";
+
+/// The same colors as `DISPLAY_P3_IMAGE` converted to sRGB and tagged with an sRGB profile, as a
+/// decoder returning sRGB pixels would.
+const SRGB_IMAGE: &str = "tests/images/icc/srgb.png";
+
+/// Computes the MSE of two 8-bit RGB images.
+fn mse(orig_pixels: &[u8], comp_pixels: &[u8], width: u32, height: u32) -> f64 {
+	compare_pixels(orig_pixels, comp_pixels, width, height, &[MetricKind::Mse], &SsimWindow::DEFAULT)
+		.mse
+		.unwrap()
+}
+
+#[test]
+fn test_display_p3_profile() {
+	let icc = ImageReader::icc_profile(DISPLAY_P3_IMAGE).unwrap();
+	let profile = IccProfile::parse(&icc).unwrap();
+
+	// Both spaces share the D65 white point, so grays are unchanged.
+	let mut grays = vec![0, 0, 0, 128, 128, 128, 255, 255, 255];
+	profile.convert_to(&IccProfile::srgb(), &mut grays);
+	for (converted, gray) in grays.iter().zip([0, 0, 0, 128, 128, 128, 255, 255, 255]) {
+		assert!(converted.abs_diff(gray) <= 1, "{:?}", grays);
+	}
+
+	// The P3 red is outside the sRGB gamut and is clipped, other colors need more saturated sRGB samples.
+	let mut colors = vec![255, 0, 0, 200, 100, 50];
+	profile.convert_to(&IccProfile::srgb(), &mut colors);
+	assert_eq!(colors[0..3], [255, 0, 0]);
+	assert!(colors[3] > 200 && colors[4] < 110 && colors[5] < 50, "{:?}", colors);
+
+	// Every sRGB color is inside the P3 gamut, so converting to P3 and back keeps it, up to the
+	// rounding of the 8-bit P3 samples, which the steep sRGB curve near black amplifies.
+	let srgb_colors = (0..=255).step_by(15).flat_map(|v| [v, 255 - v, v / 2]).collect::<Vec<u8>>();
+	let mut round_trip = srgb_colors.clone();
+	IccProfile::srgb().convert_to(&profile, &mut round_trip);
+	profile.convert_to(&IccProfile::srgb(), &mut round_trip);
+	assert!(round_trip.iter().zip(&srgb_colors).all(|(a, b)| a.abs_diff(*b) <= 3), "{:?}", round_trip);
+
+	// Images without a profile have none, and profiles of other color models are rejected.
+	assert_eq!(ImageReader::icc_profile("tests/images/pnm/gradient8.ppm"), None);
+	let mut gray_icc = icc.clone();
+	gray_icc[16..20].copy_from_slice(b"GRAY");
+	assert!(IccProfile::parse(&gray_icc).is_err());
+	assert!(IccProfile::parse(&icc[..100]).is_err());
+}
+
+#[test]
+fn test_color_managed_metrics_differ_from_naive() {
+	let (width, height, orig_pixels) = ImageReader::decode_rgb8(DISPLAY_P3_IMAGE).unwrap();
+	let (_, _, mut comp_pixels) = ImageReader::decode_rgb8(SRGB_IMAGE).unwrap();
+	let orig_icc = ImageReader::icc_profile(DISPLAY_P3_IMAGE);
+	let comp_icc = ImageReader::icc_profile(SRGB_IMAGE);
+
+	// Assuming both images are sRGB compares P3 samples to sRGB samples of the same colors.
+	let naive_mse = mse(&orig_pixels, &comp_pixels, width, height);
+	assert!(naive_mse > 100.0, "{}", naive_mse);
+
+	// Converting both images to sRGB clips the saturated P3 colors of the original the same way
+	// the sRGB image was clipped, so they seem to only differ by rounding.
+	let mut clipped_orig = orig_pixels.clone();
+	IccProfile::parse(orig_icc.as_deref().unwrap()).unwrap().convert_to(&IccProfile::srgb(), &mut clipped_orig);
+	let clipped_mse = mse(&clipped_orig, &comp_pixels, width, height);
+	assert!(clipped_mse < 1.0, "{}", clipped_mse);
+
+	// Compared by color in the space of the original, the colors the sRGB image could not hold
+	// still count as error, while the colors it could hold match.
+	let color_managed = to_original_space(orig_icc.as_deref(), &mut comp_pixels, comp_icc.as_deref()).unwrap();
+	assert!(color_managed);
+	let managed_mse = mse(&orig_pixels, &comp_pixels, width, height);
+	assert!(managed_mse > clipped_mse && managed_mse < naive_mse, "{} vs {}", managed_mse, naive_mse);
+}
+
+#[test]
+fn test_color_management_needs_both_profiles() {
+	let (_, _, comp_pixels) = ImageReader::decode_rgb8(SRGB_IMAGE).unwrap();
+	let orig_icc = ImageReader::icc_profile(DISPLAY_P3_IMAGE).unwrap();
+	let comp_icc = ImageReader::icc_profile(SRGB_IMAGE).unwrap();
+
+	// Without the profile of the compressed image it is left as it is.
+	let mut comp = comp_pixels.clone();
+	assert!(!to_original_space(Some(orig_icc.as_slice()), &mut comp, None).unwrap());
+	assert_eq!(comp, comp_pixels);
+
+	// Images with the same profile are compared by color without converting anything.
+	let mut comp = comp_pixels.clone();
+	assert!(to_original_space(Some(comp_icc.as_slice()), &mut comp, Some(comp_icc.as_slice())).unwrap());
+	assert_eq!(comp, comp_pixels);
+
+	// A profile that cannot be applied is an error and the image is not changed.
+	let mut comp = comp_pixels.clone();
+	let invalid_icc = b"not a profile".to_vec();
+	assert!(to_original_space(Some(orig_icc.as_slice()), &mut comp, Some(invalid_icc.as_slice())).is_err());
+	assert_eq!(comp, comp_pixels);
+}
+
+#[test]
+fn test_jxl_icc_profile() {
+	// An image encoded without a profile signals its color encoding in the header instead of
+	// embedding one.
+	let image = image::open(SRGB_IMAGE).unwrap().to_rgb8();
+	let mut encoder = jpegxl_rs::encoder_builder().build().unwrap();
+	let encoded: jpegxl_rs::encode::EncoderResult<u8> = encoder
+		.encode::<u8, u8>(image.as_raw(), image.width(), image.height())
+		.unwrap();
+	assert_eq!(ImageReader::jxl_icc_profile(&encoded.data), None);
+
+	// A truncated header cannot be read.
+	assert_eq!(ImageReader::jxl_icc_profile(&encoded.data[..4]), None);
+}
@@ -0,0 +1,20 @@
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+/// Creates (or recreates) an empty scratch directory for a test under the system temp directory.
+pub fn test_dir(name: &str) -> PathBuf {
+	let dir = std::env::temp_dir().join("benchmark-jpegxl-tests").join(name);
+	let _ = std::fs::remove_dir_all(&dir);
+	std::fs::create_dir_all(&dir).unwrap();
+	dir
+}
+
+/// Installs the mock docker of `tests/mock_docker.sh` in a test directory, and returns its path
+/// to be set as the docker binary of the run.
+#[allow(dead_code)]
+pub fn mock_docker(dir: &Path) -> String {
+	let docker = dir.join("docker");
+	std::fs::copy(PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/mock_docker.sh"), &docker).unwrap();
+	std::fs::set_permissions(&docker, std::fs::Permissions::from_mode(0o755)).unwrap();
+	docker.to_str().unwrap().to_string()
+}
@@ -0,0 +1,77 @@
+use benchmark_jpegxl::benchmark::{Benchmarker, JXLCompressionBenchmark};
+use benchmark_jpegxl::config::{Config, Sweep};
+use benchmark_jpegxl::csv_writer::*;
+use benchmark_jpegxl::manifest::RunManifest;
+
+mod common;
+use common::{mock_docker, test_dir};
+
+#[test]
+fn test_compare_to_previous_run() {
+	// The mock docker logs every invocation next to itself.
+	let dir = test_dir("compare_to_previous");
+	let docker = mock_docker(&dir);
+
+	// Seed a previous run that compared a commit to the local build, and a later run that was
+	// reserved but never started.
+	let benchmark_dir = dir.join("benchmarks");
+	let benchmark_dir = benchmark_dir.to_str().unwrap();
+	let previous_run = RunManifest {
+		run: 1,
+		test_sets: vec!["set".to_string()],
+		libjxl_commits: vec!["abc123".to_string(), "local".to_string()],
+		distances: vec![1.0, 3.0],
+		efforts: vec![7],
+		..RunManifest::default()
+	};
+	RunManifest { run: 0, ..previous_run.clone() }.save(benchmark_dir).unwrap();
+	previous_run.save(benchmark_dir).unwrap();
+	std::fs::create_dir_all(dir.join("benchmarks/2")).unwrap();
+	let previous_file = dir.join("benchmarks/1/results/comp/set/local/comparisons.csv");
+	std::fs::create_dir_all(previous_file.parent().unwrap()).unwrap();
+	let previous_file = previous_file.to_str().unwrap();
+	ComparisonResultCSV::new().write_csv_header(previous_file).unwrap();
+	assert_eq!(RunManifest::latest(benchmark_dir), Some(previous_run.clone()));
+	assert_eq!(previous_run.comparisons_file(benchmark_dir, "set").as_deref(), Some(previous_file));
+	assert_eq!(previous_run.comparisons_file(benchmark_dir, "other"), None);
+
+	// The local build is benchmarked with the distances and efforts of the previous run.
+	std::fs::create_dir_all(dir.join("test_images/set")).unwrap();
+	let config = Config {
+		docker_bin: docker,
+		benchmark_dir_path: benchmark_dir.to_string(),
+		local_test_image_dir_path: dir.join("test_images").to_str().unwrap().to_string(),
+		num_workers: 1,
+		compare_to_previous: true,
+		distances: vec![2.0],
+		efforts: vec![3, 5],
+		..Config::default()
+	};
+	let mut benchmarker = Benchmarker::new(&config).unwrap();
+	assert_eq!(benchmarker.context.sweep, Sweep::Distance(vec![1.0, 3.0]));
+	assert_eq!(benchmarker.context.efforts, vec![7]);
+	assert_eq!(benchmarker.context.libjxl_commit.as_deref(), Some("local"));
+	benchmarker.run_benchmark::<JXLCompressionBenchmark>().unwrap();
+	benchmarker.wait_for_all_workers();
+
+	// Only the new side is benchmarked, the commit of the previous run is not encoded again.
+	let manifest = RunManifest::load(benchmark_dir, 3).unwrap();
+	assert_eq!(manifest.libjxl_commits, vec!["local".to_string()]);
+	assert_eq!(manifest.previous_run, Some(1));
+	assert_eq!(manifest.distances, vec![1.0, 3.0]);
+	assert_eq!(manifest.efforts, vec![7]);
+	let timing = manifest.timing.unwrap();
+	assert_eq!(timing.images, 0);
+	assert!(timing.phase_time_s() <= timing.wall_time_s, "{:?}", timing);
+	let log = std::fs::read_to_string(dir.join("docker.log")).unwrap();
+	assert!(!log.contains("abc123"), "{}", log);
+	assert!(!log.contains("cjxl"), "{}", log);
+
+	// A benchmark directory without a previous run cannot be compared to.
+	let config = Config {
+		benchmark_dir_path: dir.join("empty").to_str().unwrap().to_string(),
+		..config
+	};
+	let error = Benchmarker::new(&config).err().unwrap().to_string();
+	assert!(error.contains("No previous run"), "{}", error);
+}
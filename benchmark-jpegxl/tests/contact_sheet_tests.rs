@@ -0,0 +1,78 @@
+use benchmark_jpegxl::benchmark::{Benchmarker, JXLCompressionBenchmark};
+use benchmark_jpegxl::config::Config;
+use benchmark_jpegxl::contact_sheet::ContactSheet;
+use benchmark_jpegxl::smoke::SmokeTest;
+
+mod common;
+use common::{mock_docker, test_dir};
+
+#[test]
+fn test_contact_sheet_of_two_distance_sweep() {
+	// The mock docker logs every invocation next to itself.
+	let dir = test_dir("contact_sheet");
+	let docker = mock_docker(&dir);
+
+	// The mock cjxl writes a real encode of the gradient.
+	let image_path = dir.join("test_images/set/gradient.png");
+	SmokeTest::generate_image(&image_path).unwrap();
+	let image = image::open(&image_path).unwrap().to_rgb8();
+	let mut encoder = jpegxl_rs::encoder_builder().build().unwrap();
+	let encoded: jpegxl_rs::encode::EncoderResult<u8> =
+		encoder.encode::<u8, u8>(image.as_raw(), image.width(), image.height()).unwrap();
+	std::fs::write(dir.join("cjxl.jxl"), &encoded.data).unwrap();
+
+	// The compressed images are discarded, so the tiles come from the decoded images.
+	let benchmark_dir = dir.join("benchmarks");
+	let config = Config {
+		docker_bin: docker,
+		benchmark_dir_path: benchmark_dir.to_str().unwrap().to_string(),
+		local_test_image_dir_path: dir.join("test_images").to_str().unwrap().to_string(),
+		num_workers: 1,
+		libjxl_commit: Some("abc123".to_string()),
+		distances: vec![1.0, 2.0],
+		efforts: vec![7],
+		keep_compressed: false,
+		contact_sheets: true,
+		..Config::default()
+	};
+	let mut benchmarker = Benchmarker::new(&config).unwrap();
+	benchmarker.run_benchmark::<JXLCompressionBenchmark>().unwrap();
+	benchmarker.wait_for_all_workers();
+
+	// The sheet holds the original and both compressed images in one row.
+	let sheet = benchmark_dir.join("0/output/comp/set/abc123/contact_sheets/gradient.contact.png");
+	let expected = (3 * ContactSheet::THUMBNAIL_SIZE, ContactSheet::THUMBNAIL_SIZE + ContactSheet::CAPTION_HEIGHT);
+	assert_eq!(ContactSheet::dimensions(3), expected);
+	assert_eq!(image::image_dimensions(&sheet).unwrap(), expected);
+}
+
+#[test]
+fn test_captions_cover_the_lowercase_alphabet() {
+	// A tile per character, so every caption is drawn in its own caption area.
+	let characters = ('a'..='z').chain('0'..='9').chain(['.', '-', ':', '/', '%', '(', ')']);
+	let mut sheet = ContactSheet::new();
+	let thumbnail = image::RgbImage::from_pixel(8, 8, image::Rgb([255, 255, 255]));
+	let characters = characters.collect::<Vec<char>>();
+	for character in &characters {
+		sheet.add(&thumbnail, &character.to_string());
+	}
+	let rendered = sheet.render();
+
+	// Every character draws some black pixels below its thumbnail.
+	let tile_height = ContactSheet::THUMBNAIL_SIZE + ContactSheet::CAPTION_HEIGHT;
+	for (index, character) in characters.iter().enumerate() {
+		let x = (index as u32 % ContactSheet::COLUMNS) * ContactSheet::THUMBNAIL_SIZE;
+		let y = (index as u32 / ContactSheet::COLUMNS) * tile_height + ContactSheet::THUMBNAIL_SIZE;
+		let drawn = (x..x + ContactSheet::THUMBNAIL_SIZE)
+			.flat_map(|px| (y..y + ContactSheet::CAPTION_HEIGHT).map(move |py| (px, py)))
+			.any(|(px, py)| rendered.get_pixel(px, py).0 == [0, 0, 0]);
+		assert!(drawn, "no glyph for {:?}", character);
+	}
+
+	// Decoded pixel buffers are added as tiles, and must match their dimensions.
+	let mut sheet = ContactSheet::new();
+	assert!(sheet.is_empty());
+	sheet.add_pixels(2, 1, &[0; 6], "original").unwrap();
+	assert!(!sheet.is_empty());
+	assert!(sheet.add_pixels(2, 2, &[0; 6], "d1 e7").is_err());
+}
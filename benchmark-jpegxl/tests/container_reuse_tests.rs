@@ -0,0 +1,59 @@
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+
+use benchmark_jpegxl::docker_manager::DockerManager;
+
+mod common;
+use common::test_dir;
+
+/// Installs a mock docker binary that logs its arguments and reports the given running
+/// containers, and returns the paths of the binary and the log.
+fn mock_docker(dir: &PathBuf, running: &str) -> (String, PathBuf) {
+	let log = dir.join("docker.log");
+	let script = format!(
+		"#!/bin/sh\necho \"$@\" >> {}\ncase \"$1\" in\n  ps) printf '{}' ;;\n  inspect) echo ubuntu:{} ;;\nesac\nexit 0\n",
+		log.to_str().unwrap(),
+		running,
+		DockerManager::IMAGE_NAME
+	);
+	let docker = dir.join("docker");
+	std::fs::write(&docker, script).unwrap();
+	std::fs::set_permissions(&docker, std::fs::Permissions::from_mode(0o755)).unwrap();
+	let _ = std::fs::remove_file(&log);
+	(docker.to_str().unwrap().to_string(), log)
+}
+
+/// Returns the docker subcommands that were run, in order.
+fn docker_commands(log: &PathBuf) -> Vec<String> {
+	std::fs::read_to_string(log)
+		.unwrap_or_default()
+		.lines()
+		.map(|line| line.split_whitespace().next().unwrap_or("").to_string())
+		.collect()
+}
+
+#[test]
+fn test_reuse_running_container() {
+	let dir = test_dir("container_reuse");
+
+	// A running container of a prior invocation is attached to without building or running.
+	let (docker, log) = mock_docker(&dir, &format!("{}-0\\n", DockerManager::CONTAINER_NAME));
+	let mut docker_manager = DockerManager::new("Dockerfile", 0);
+	docker_manager.docker_bin = docker;
+	docker_manager.reuse_containers = true;
+	docker_manager.setup(0, None).unwrap();
+	assert_eq!(docker_commands(&log), vec!["ps", "inspect", "exec"]);
+
+	// Teardown leaves the reused container running.
+	docker_manager.teardown().unwrap();
+	let commands = docker_commands(&log);
+	assert!(!commands.iter().any(|command| command == "stop" || command == "rm"));
+
+	// Without a running container the image is built and the container started.
+	let (docker, log) = mock_docker(&dir, "");
+	let mut docker_manager = DockerManager::new("Dockerfile", 1);
+	docker_manager.docker_bin = docker;
+	docker_manager.reuse_containers = true;
+	docker_manager.setup(1, None).unwrap();
+	assert_eq!(docker_commands(&log), vec!["ps", "build", "run"]);
+}
@@ -0,0 +1,37 @@
+use benchmark_jpegxl::csv_writer::*;
+
+mod common;
+use common::test_dir;
+
+#[test]
+fn test_semicolon_delimiter_round_trip() {
+	// The CSV format is set for the whole process, so this is the only test in this file.
+	let dir = test_dir("csv_format");
+	let file = dir.join("comparisons.csv").to_str().unwrap().to_string();
+	set_csv_format(b';', CsvQuoting::NonNumeric);
+	assert_eq!(csv_format(), (b';', CsvQuoting::NonNumeric));
+
+	let mut result = ComparisonResult::encode_failed("kodim01.png", "kodim01__d1.5__e7.jxl", 1.5, 7);
+	result.roi = "0,0,16,16".to_string();
+	result.psnr = 44.15;
+	let csv_writer = ComparisonResultCSV::new();
+	csv_writer.write_csv_header(&file).unwrap();
+	csv_writer.write_csv(&vec![result.clone()], &file, DEFAULT_FLOAT_DIGITS).unwrap();
+
+	// Fields are separated by semicolons and text fields are quoted.
+	let contents = std::fs::read_to_string(&file).unwrap();
+	let row = contents.lines().nth(1).unwrap();
+	assert!(row.starts_with("\"kodim01.png\";\"kodim01__d1.5__e7.jxl\";1.5;7;"), "{}", row);
+	assert!(row.contains(";\"0,0,16,16\";"), "{}", row);
+
+	// The file is read back with the same delimiter.
+	let read = csv_writer.read_csv(&file).unwrap();
+	assert_eq!(read.len(), 1);
+	assert_eq!(read[0].comp_image_name, result.comp_image_name);
+	assert_eq!(read[0].distance, 1.5);
+	assert_eq!(read[0].psnr, 44.15);
+	assert_eq!(read[0].roi, "0,0,16,16");
+	assert_eq!(read[0].status, ComparisonResult::STATUS_ENCODE_FAILED);
+
+	set_csv_format(DEFAULT_CSV_DELIMITER, CsvQuoting::Necessary);
+}
@@ -0,0 +1,332 @@
+use std::sync::{Arc, Mutex};
+
+use benchmark_jpegxl::csv_writer::*;
+use benchmark_jpegxl::image_reader::{ColorType, ImageFileData, ImageFormat, JXLString, JXLf32, JXLu32};
+use benchmark_jpegxl::metrics::SizeRatio;
+
+mod common;
+use common::test_dir;
+
+#[test]
+fn test_concurrent_writes_do_not_interleave() {
+	let dir = test_dir("csv_sink");
+	let file = dir.join("results.csv").to_str().unwrap().to_string();
+	let csv_sink = Arc::new(Mutex::new(CsvSink::new(DEFAULT_FLOAT_DIGITS)));
+
+	// Several workers append batches of rows to the same file at the same time.
+	let threads = 8;
+	let batches = 25;
+	let rows = 20;
+	let handles = (0..threads)
+		.map(|thread| {
+			let csv_sink = csv_sink.clone();
+			let file = file.clone();
+			std::thread::spawn(move || {
+				for batch in 0..batches {
+					let data = (0..rows)
+						.map(|row| ErrorHistogramBin {
+							bin: format!("thread {} batch {}", thread, batch),
+							count: row,
+						})
+						.collect::<Vec<ErrorHistogramBin>>();
+					csv_sink.lock().unwrap().append(&ErrorHistogramCSV::new(), &data, &file).unwrap();
+				}
+			})
+		})
+		.collect::<Vec<_>>();
+	for handle in handles {
+		handle.join().unwrap();
+	}
+
+	// The header is written once and every row is well-formed.
+	let contents = std::fs::read_to_string(&file).unwrap();
+	let lines = contents.lines().collect::<Vec<&str>>();
+	assert_eq!(lines[0], "Absolute Error,Count");
+	assert_eq!(lines.len(), 1 + threads * batches * rows as usize);
+
+	// The rows of each batch are contiguous and in order.
+	for batch in lines[1..].chunks(rows as usize) {
+		let (bin, _) = batch[0].split_once(',').unwrap();
+		for (row, line) in batch.iter().enumerate() {
+			assert_eq!(*line, format!("{},{}", bin, row));
+		}
+	}
+}
+
+#[test]
+fn test_size_ratio_direction() {
+	// The default ratios are compression ratios, so a 4x smaller file has a ratio of 4.
+	let compression = SizeRatio::default();
+	assert_eq!(compression, SizeRatio::Compression);
+	assert_eq!(compression.ratio(1000, 250), 4.0);
+	assert!(compression.higher_is_better());
+	assert_eq!(compression.label(), "orig/comp");
+	let relative_size = SizeRatio::RelativeSize;
+	assert_eq!(relative_size.ratio(1000, 250), 0.25);
+	assert!(!relative_size.higher_is_better());
+
+	// The header names the direction.
+	let dir = test_dir("size_ratio_direction");
+	let file = dir.join("comparisons.csv").to_str().unwrap().to_string();
+	ComparisonResultCSV::new().write_csv_header(&file).unwrap();
+	let header = std::fs::read_to_string(&file).unwrap();
+	assert!(header.contains("Compression Ratio (orig/comp),Raw Compression Ratio (raw/comp)"));
+
+	// Relative sizes are named as such and read back in their direction.
+	let relative_file = dir.join("relative_comparisons.csv").to_str().unwrap().to_string();
+	let mut result = ComparisonResult::encode_failed("kodim01.png", "kodim01__d1__e7.jxl", 1.0, 7);
+	result.size_ratio = relative_size;
+	result.comp_file_size_ratio = 0.25;
+	let csv_writer = ComparisonResultCSV::new().with_size_ratio(relative_size);
+	csv_writer.write_csv_header(&relative_file).unwrap();
+	csv_writer.write_csv(&vec![result], &relative_file, DEFAULT_FLOAT_DIGITS).unwrap();
+	let header = std::fs::read_to_string(&relative_file).unwrap();
+	assert!(header.contains("Relative Size (comp/orig),Raw Relative Size (comp/raw)"));
+	let results = ComparisonResultCSV::new().read_csv(&relative_file).unwrap();
+	assert_eq!(results[0].size_ratio, SizeRatio::RelativeSize);
+	assert_eq!(results[0].comp_file_size_ratio, 0.25);
+
+	// Files written with the older header names still parse.
+	let old_file = dir.join("old_comparisons.csv").to_str().unwrap().to_string();
+	std::fs::write(
+		&old_file,
+		"Original Image Name,Compressed Image Name,Distance,Effort,Original File Size,Compressed File Size,Original Raw Size,Compressed Raw Size,File Size Ratio,Raw Size Ratio,MSE,PSNR,SSIM,MS-SSIM,Butteraugli,Butteraugli 3-Norm,SSIMULACRA2\n\
+		kodim01.png,kodim01__d1__e7.jxl,1,7,1000,250,3000,3000,4,12,1.5,46.3,0.99,0.99,1.1,0.6,85\n",
+	)
+	.unwrap();
+	let results = ComparisonResultCSV::new().read_csv(&old_file).unwrap();
+	assert_eq!(results[0].size_ratio, SizeRatio::Compression);
+	assert_eq!(results[0].comp_file_size_ratio, 4.0);
+	assert_eq!(results[0].raw_file_size_ratio, 12.0);
+}
+
+#[test]
+fn test_float_formatting_is_stable() {
+	// Values are written with a fixed number of significant digits, without trailing zeros.
+	assert_eq!(format_float(2.0 / 3.0, 6), "0.666667");
+	assert_eq!(format_float(44.15, 6), "44.15");
+	assert_eq!(format_float(-0.000123456789, 3), "-0.000123");
+	assert_eq!(format_float(1234567.0, 6), "1234570");
+	assert_eq!(format_float(0.0, 6), "0");
+	assert_eq!(format_float(f64::NAN, 6), "NaN");
+	assert_eq!(format_float(f64::INFINITY, 6), "inf");
+
+	// 0 digits writes the values at full precision.
+	assert_eq!(format_float(2.0 / 3.0, 0), (2.0f64 / 3.0).to_string());
+
+	// Values computed slightly differently are written the same way.
+	assert_eq!(format_float(0.1 + 0.2, 6), format_float(0.3, 6));
+
+	// The same values are written identically and parse back within the precision.
+	let dir = test_dir("float_formatting");
+	let values = [2.0 / 3.0, 44.15, 0.987654321, 1e-7 / 3.0, 12345.678901];
+	let data = values
+		.iter()
+		.map(|value| TidyResult {
+			image: "kodim01.png".to_string(),
+			distance: 1.0,
+			effort: 7,
+			commit: "main".to_string(),
+			metric_name: "psnr".to_string(),
+			value: *value,
+		})
+		.collect::<Vec<TidyResult>>();
+	let mut contents = Vec::new();
+	for name in ["first.csv", "second.csv"] {
+		let file = dir.join(name).to_str().unwrap().to_string();
+		TidyResultCSV::new().write_csv_header(&file).unwrap();
+		TidyResultCSV::new().write_csv(&data, &file, DEFAULT_FLOAT_DIGITS).unwrap();
+		contents.push(std::fs::read_to_string(&file).unwrap());
+	}
+	assert_eq!(contents[0], contents[1]);
+	for (line, value) in contents[0].lines().skip(1).zip(values) {
+		let written = line.rsplit(',').next().unwrap().parse::<f64>().unwrap();
+		assert!(((written - value) / value).abs() < 1e-5, "{} != {}", written, value);
+	}
+}
+
+#[test]
+fn test_size_differences_are_not_rounded() {
+	// Byte counts are written exactly, while the other differences use the precision of the sink.
+	let dir = test_dir("size_differences");
+	let file = dir.join("comparison_diffs.csv").to_str().unwrap().to_string();
+	let diff = ComparisonResultDiff {
+		orig_image_name: "kodim01.png".to_string(),
+		comp_image_name: "kodim01__d1__e7.jxl".to_string(),
+		distance: 1.0,
+		effort: 7,
+		diff_orig_file_size: 0.0,
+		diff_comp_file_size: -1234567.0,
+		diff_orig_raw_size: 0.0,
+		diff_comp_raw_size: 7654321.0,
+		diff_comp_file_size_ratio: 2.0 / 3.0,
+		diff_raw_file_size_ratio: 0.0,
+		diff_mse: 0.0,
+		diff_psnr: 0.0,
+		diff_ssim: 0.0,
+		diff_ms_ssim: 0.0,
+		diff_butteraugli: 0.0,
+		diff_butteraugli_pnorm: 0.0,
+		diff_ssimulacra2: 0.0,
+		non_finite_values: 0,
+		baseline_codec: "jxl".to_string(),
+		diff_channel_psnr: [0.0; 3],
+	};
+	CsvSink::new(3).append(&ComparisonResultDiffCSV::new(), &vec![diff], &file).unwrap();
+	let contents = std::fs::read_to_string(&file).unwrap();
+	let row = contents.lines().nth(1).unwrap().split(',').collect::<Vec<&str>>();
+	assert_eq!(&row[4..9], &["0", "-1234567", "0", "7654321", "0.667"]);
+}
+
+#[test]
+fn test_image_file_data_round_trip() {
+	let dir = test_dir("image_file_data_round_trip");
+	let file = dir.join("results.csv").to_str().unwrap().to_string();
+	let orig = ImageFileData {
+		image_name: "kodim01.png".to_string(),
+		commit: "main".to_string(),
+		test_set: "kodak".to_string(),
+		file_path: "/images/kodak/kodim01.png".to_string(),
+		width: 768,
+		height: 512,
+		file_size: 736501,
+		raw_size: 1179648,
+		color_space: ColorType::Rgb8,
+		file_format: ImageFormat::Png,
+		jxl_orig_image_name: JXLString::new(None),
+		jxl_distance: JXLf32::new(None),
+		jxl_effort: JXLu32::new(None),
+		source_width: 1536,
+		source_height: 1024,
+		decoder: "image".to_string(),
+		header_lossless: None,
+		filename_distance: JXLf32::new(None),
+		source_is_lossy: false,
+		source_color_model: "RGB".to_string(),
+		decoder_mismatch: false,
+	};
+	let comp = ImageFileData {
+		image_name: "kodim01__d1__e7.jxl".to_string(),
+		file_path: "/out/kodim01__d1__e7.jxl".to_string(),
+		file_size: 98304,
+		file_format: ImageFormat::JpegXl,
+		jxl_orig_image_name: JXLString::new(Some("kodim01.png".to_string())),
+		jxl_distance: JXLf32::new(Some(1.0)),
+		jxl_effort: JXLu32::new(Some(7)),
+		decoder: "jpegxl-rs".to_string(),
+		header_lossless: Some(false),
+		filename_distance: JXLf32::new(Some(1.0)),
+		..orig.clone()
+	};
+	let csv_writer = ImageFileDataCSV::new();
+	csv_writer.write_csv_header(&file).unwrap();
+
+	// A results file without rows has the header, and reads back as empty.
+	let header = std::fs::read_to_string(&file).unwrap();
+	assert!(header.starts_with("Image Name,Commit,Test Set,"), "{}", header);
+	assert!(csv_writer.read_csv(&file).unwrap().is_empty());
+
+	csv_writer.write_csv(&vec![orig.clone()], &file, DEFAULT_FLOAT_DIGITS).unwrap();
+	csv_writer.write_csv(&vec![comp.clone()], &file, DEFAULT_FLOAT_DIGITS).unwrap();
+
+	// The header is generated from the fields and written once, values are written as before.
+	let contents = std::fs::read_to_string(&file).unwrap();
+	let lines = contents.lines().collect::<Vec<&str>>();
+	assert_eq!(lines.len(), 3);
+	assert_eq!(
+		lines[0],
+		"Image Name,Commit,Test Set,File Path,Image Width,Image Height,File Size,Raw Image Size,Image Color Space,File Format,JXL Original Image Name,JXL Distance,JXL Effort,Source Width,Source Height,Decoder,Header Lossless,Filename Distance,Source Is Lossy,Source Color Model,Decoder Mismatch"
+	);
+	assert_eq!(lines[2], "kodim01__d1__e7.jxl,main,kodak,/out/kodim01__d1__e7.jxl,768,512,98304,1179648,Rgb8,jxl,kodim01.png,1,7,1536,1024,jpegxl-rs,false,1,false,RGB,false");
+
+	// Every field is read back by its header.
+	let read = csv_writer.read_csv(&file).unwrap();
+	assert_eq!(format!("{:?}", read), format!("{:?}", vec![orig.clone(), comp.clone()]));
+	assert_eq!(format!("{:?}", csv_writer.read_entry(&file, 1).unwrap()), format!("{:?}", comp));
+	assert_eq!(format!("{:?}", csv_writer.find_entry(&file, 0, "kodim01.png").unwrap()), format!("{:?}", orig));
+	assert!(csv_writer.read_entry(&file, 2).is_err());
+
+	// Results files written before the source columns were added are still read.
+	let legacy = dir.join("legacy.csv");
+	std::fs::write(
+		&legacy,
+		"Image Name,Commit,Test Set,File Path,Image Width,Image Height,File Size,Raw Image Size,Image Color Space,File Format,JXL Original Image Name,JXL Distance,JXL Effort\n\
+		kodim02.jpeg,main,kodak,/images/kodak/kodim02.jpeg,64,32,1000,6144,Rgb8,jpeg,,,\n",
+	)
+	.unwrap();
+	let read = csv_writer.read_csv(legacy.to_str().unwrap()).unwrap();
+	assert_eq!((read[0].source_width, read[0].source_height), (64, 32));
+	assert!(read[0].source_is_lossy);
+	assert_eq!(read[0].header_lossless, None);
+	assert_eq!(read[0].decoder, "");
+}
+
+#[test]
+fn test_normalized_scores() {
+	use benchmark_jpegxl::config::ScoreWeight;
+	use benchmark_jpegxl::metrics::MetricKind;
+
+	// Every score is higher for a better value, whatever the direction of the metric.
+	assert_eq!(MetricKind::Psnr.score(20.0), 0.0);
+	assert_eq!(MetricKind::Psnr.score(35.0), 0.5);
+	assert_eq!(MetricKind::Psnr.score(f64::INFINITY), 1.0);
+	assert!(MetricKind::Mse.score(1.0) > MetricKind::Mse.score(10.0));
+	assert_eq!(MetricKind::Mse.score(0.0), 1.0);
+	assert_eq!(MetricKind::Butteraugli.score(0.0), 1.0);
+	assert_eq!(MetricKind::Butteraugli.score(1.0), 0.5);
+	assert!(MetricKind::Butteraugli.score(3.0) < MetricKind::Butteraugli.score(1.0));
+	assert_eq!(MetricKind::Ssimulacra2.score(-10.0), 0.0);
+	assert_eq!(MetricKind::Ssimulacra2.score(90.0), 0.9);
+	assert_eq!(MetricKind::Ssim.score(0.95), 0.95);
+	assert!(MetricKind::Ssim.score(f64::NAN).is_nan());
+
+	// One encode is better by SSIMULACRA2, the other by Butteraugli.
+	let mut a = ComparisonResult::encode_failed("a.png", "a.jxl", 1.0, 7);
+	a.ssimulacra2 = 90.0;
+	a.butteraugli = 3.0;
+	let mut b = ComparisonResult::encode_failed("b.png", "b.jxl", 1.0, 7);
+	b.ssimulacra2 = 60.0;
+	b.butteraugli = 0.5;
+
+	// The composite follows the weighted metric.
+	let ssimulacra2 = vec!["ssimulacra2:1".parse::<ScoreWeight>().unwrap()];
+	let butteraugli = vec!["butteraugli:1".parse::<ScoreWeight>().unwrap()];
+	assert!(NormalizedScores::new(&a, &ssimulacra2).composite > NormalizedScores::new(&b, &ssimulacra2).composite);
+	assert!(NormalizedScores::new(&a, &butteraugli).composite < NormalizedScores::new(&b, &butteraugli).composite);
+
+	// The composite is the weighted mean of the scores.
+	let weights = vec![
+		"ssimulacra2:3".parse::<ScoreWeight>().unwrap(),
+		"butteraugli:1".parse::<ScoreWeight>().unwrap(),
+	];
+	assert_eq!(weights[0].metric, MetricKind::Ssimulacra2);
+	assert_eq!(weights[0].weight, 3.0);
+	assert!("ssimulacra2".parse::<ScoreWeight>().is_err());
+	assert!("ssimulacra2:0".parse::<ScoreWeight>().is_err());
+	assert!("vmaf:1".parse::<ScoreWeight>().is_err());
+	let scores = NormalizedScores::new(&a, &weights);
+	assert!((scores.composite - (3.0 * 0.9 + 0.25) / 4.0).abs() < 1e-12);
+	assert_eq!(scores.scores[5], 0.9);
+	assert_eq!(scores.scores[4], 0.25);
+
+	// Weighted metrics that were not computed are left out of the mean.
+	let mut with_missing = weights.clone();
+	with_missing.push("ms-ssim:10".parse::<ScoreWeight>().unwrap());
+	assert_eq!(NormalizedScores::new(&a, &with_missing).composite, scores.composite);
+
+	// Without weights there is no composite.
+	assert!(NormalizedScores::new(&a, &[]).composite.is_nan());
+
+	// The scores are written with a column per metric and the composite.
+	let dir = test_dir("normalized_scores");
+	let file = dir.join("scores.csv").to_str().unwrap().to_string();
+	let mut csv_sink = CsvSink::new(DEFAULT_FLOAT_DIGITS);
+	csv_sink.append(&NormalizedScoresCSV::new(), &vec![scores], &file).unwrap();
+	let contents = std::fs::read_to_string(&file).unwrap();
+	let lines = contents.lines().collect::<Vec<&str>>();
+	assert_eq!(
+		lines[0],
+		"Compressed Image Name,Distance,Effort,MSE Score,PSNR Score,SSIM Score,MS-SSIM Score,Butteraugli Score,SSIMULACRA2 Score,Composite Score"
+	);
+	assert!(lines[1].starts_with("a.jxl,1,7,NaN,NaN,NaN,NaN,0.25,0.9,"));
+}
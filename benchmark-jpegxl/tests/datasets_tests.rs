@@ -0,0 +1,63 @@
+use benchmark_jpegxl::datasets::{Dataset, DatasetFetcher, FetchSummary};
+use benchmark_jpegxl::utils::hash_file;
+use clap::ValueEnum;
+
+mod common;
+use common::test_dir;
+
+#[test]
+fn test_parse_checksums() {
+	let hash = "a".repeat(64);
+	let manifest = format!("# comment\n{}  kodim01.png\n{} *kodim02.png\n\n", hash, hash.to_uppercase());
+	let checksums = DatasetFetcher::parse_checksums(&manifest).unwrap();
+	assert_eq!(checksums, vec![(hash.clone(), "kodim01.png".to_string()), (hash.clone(), "kodim02.png".to_string())]);
+
+	// Malformed hashes and paths outside of the dataset directory are rejected.
+	assert!(DatasetFetcher::parse_checksums("abc  kodim01.png").is_err());
+	assert!(DatasetFetcher::parse_checksums(&format!("{}  ../kodim01.png", hash)).is_err());
+
+	// Datasets are named on the command line, unknown names are rejected instead of panicking.
+	assert_eq!(Dataset::from_str("Kodak", true), Ok(Dataset::Kodak));
+	assert_eq!(Dataset::from_str("clic-professional-valid", true), Ok(Dataset::ClicProfessionalValid));
+	assert!(Dataset::from_str("imagenet", true).is_err());
+
+	// Nothing is fetched from the default sources until verified checksums are pinned.
+	for dataset in [Dataset::Kodak, Dataset::ClicProfessionalValid] {
+		assert!(dataset.checksums().is_err());
+	}
+}
+
+#[test]
+fn test_fetch_mock_dataset() {
+	let dir = test_dir("fetch_dataset");
+
+	// A mock source serving two files, checked against checksums pinned by the caller.
+	let source = dir.join("source");
+	std::fs::create_dir_all(&source).unwrap();
+	std::fs::write(source.join("kodim01.png"), b"first image").unwrap();
+	std::fs::write(source.join("kodim02.png"), b"second image").unwrap();
+	let checksums = ["kodim01.png", "kodim02.png"]
+		.iter()
+		.map(|name| (hash_file(source.join(name).to_str().unwrap()).unwrap(), name.to_string()))
+		.collect::<Vec<(String, String)>>();
+	let source_url = format!("file://{}", source.to_str().unwrap());
+
+	let test_images = dir.join("test_images");
+	let test_images = test_images.to_str().unwrap();
+	let fetch = || DatasetFetcher::fetch_files(Dataset::Kodak, &checksums, Some(&source_url), test_images);
+	let summary = fetch().unwrap();
+	assert_eq!(summary, FetchSummary { downloaded: 2, skipped: 0 });
+	assert_eq!(std::fs::read(dir.join("test_images/kodak/kodim01.png")).unwrap(), b"first image");
+
+	// Files that are already present are not downloaded again.
+	let summary = fetch().unwrap();
+	assert_eq!(summary, FetchSummary { downloaded: 0, skipped: 2 });
+
+	// A corrupted file is rejected and not left in the test set.
+	std::fs::write(source.join("kodim02.png"), b"corrupted image").unwrap();
+	std::fs::remove_file(dir.join("test_images/kodak/kodim02.png")).unwrap();
+	let e = fetch().unwrap_err();
+	assert!(e.to_string().contains("Checksum mismatch for kodim02.png"));
+	assert!(!dir.join("test_images/kodak/kodim02.png").exists());
+	assert!(!dir.join("test_images/kodak/kodim02.png.part").exists());
+}
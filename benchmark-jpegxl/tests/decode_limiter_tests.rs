@@ -0,0 +1,52 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Barrier};
+use std::time::Duration;
+
+use benchmark_jpegxl::decode_limiter::DecodeLimiter;
+
+/// Runs a number of threads that each decode once under the limiter, and returns the highest
+/// number of decodes that ran at the same time.
+fn max_concurrent_decodes(limiter: Arc<DecodeLimiter>, threads: usize) -> usize {
+	let running = Arc::new(AtomicUsize::new(0));
+	let max_running = Arc::new(AtomicUsize::new(0));
+	let barrier = Arc::new(Barrier::new(threads));
+	let handles = (0..threads)
+		.map(|_| {
+			let (limiter, running, max_running, barrier) =
+				(limiter.clone(), running.clone(), max_running.clone(), barrier.clone());
+			std::thread::spawn(move || {
+				barrier.wait();
+				let _permit = limiter.acquire();
+				let now_running = running.fetch_add(1, Ordering::SeqCst) + 1;
+				max_running.fetch_max(now_running, Ordering::SeqCst);
+				std::thread::sleep(Duration::from_millis(20));
+				running.fetch_sub(1, Ordering::SeqCst);
+			})
+		})
+		.collect::<Vec<_>>();
+	for handle in handles {
+		handle.join().unwrap();
+	}
+	max_running.load(Ordering::SeqCst)
+}
+
+#[test]
+fn test_decode_limiter_bounds_concurrent_decodes() {
+	let limiter = Arc::new(DecodeLimiter::new(2));
+	let max_running = max_concurrent_decodes(limiter.clone(), 8);
+	assert!(max_running <= 2, "{} decodes ran at the same time", max_running);
+	assert!(max_running >= 1);
+
+	// Every permit is released after its decode.
+	assert_eq!(limiter.in_flight(), 0);
+}
+
+#[test]
+fn test_decode_limiter_without_limit() {
+	// Without a limit, permits never wait.
+	let limiter = DecodeLimiter::new(0);
+	let permits = (0..16).map(|_| limiter.acquire()).collect::<Vec<_>>();
+	assert_eq!(limiter.in_flight(), 16);
+	drop(permits);
+	assert_eq!(limiter.in_flight(), 0);
+}
@@ -0,0 +1,69 @@
+use benchmark_jpegxl::benchmark::Benchmarker;
+use benchmark_jpegxl::config::Config;
+use benchmark_jpegxl::docker_manager::*;
+
+#[test]
+fn test_missing_docker_binary() {
+	// A docker binary that does not exist.
+	let docker_bin = std::env::temp_dir().join("benchmark-jpegxl-tests").join("missing").join("docker");
+	let docker_bin = docker_bin.to_str().unwrap().to_string();
+
+	let preflight = DockerManager::preflight_check(&docker_bin);
+	let benchmarker = Benchmarker::new(&Config { docker_bin, ..Config::default() });
+
+	assert_eq!(preflight, Err(DockerError::NotInstalled));
+	let message = benchmarker.err().expect("Benchmarker::new should fail without docker").to_string();
+	assert!(message.contains("Install Docker"));
+}
+
+#[test]
+fn test_libjxl_src_mount_args() {
+	// A local source tree is bind-mounted over the libjxl checkout in the image.
+	let args = DockerManager::run_args("container-0", "image", Some("/home/user/libjxl"));
+	let mount = args.iter().position(|arg| arg == "-v").expect("mount flag");
+	assert_eq!(args[mount + 1], "/home/user/libjxl:/libjxl");
+	assert_eq!(args.last().unwrap(), "ubuntu:image");
+
+	// Without a source tree nothing is mounted.
+	let args = DockerManager::run_args("container-0", "image", None);
+	assert!(!args.contains(&"-v".to_string()));
+}
+
+#[test]
+fn test_intensity_target_metric_args() {
+	// HDR comparisons pass the intensity target to the metric tools.
+	let args = DockerManager::metric_args("/temp/orig.png", "/temp/comp.jxl", Some(1000.0));
+	assert_eq!(args, vec!["/temp/orig.png", "/temp/comp.jxl", "--intensity_target", "1000"]);
+
+	// SDR comparisons only pass the two images.
+	let args = DockerManager::metric_args("/temp/orig.png", "/temp/comp.jxl", None);
+	assert_eq!(args, vec!["/temp/orig.png", "/temp/comp.jxl"]);
+}
+
+#[test]
+fn test_effort_range_per_version() {
+	// An older cjxl accepting efforts up to 9, with effort 10 as an expert option.
+	let older_help = " -e EFFORT, --effort=EFFORT\n    Encoder effort setting. Range: 1 .. 9.\n     Default: 7.\n --allow_expert_options\n    Allow specifying advanced options; this allows setting effort to 10, for\n    somewhat better lossless compression at the cost of a massive speed hit.\n";
+	let older = EffortRange::parse(older_help).unwrap();
+	assert_eq!(older, EffortRange { min: 1, max: 9, expert_max: Some(10) });
+	assert_eq!(older.check(9), Ok(false));
+	assert_eq!(older.check(10), Ok(true));
+	let e = older.check(11).unwrap_err();
+	assert_eq!(e, "Effort 11 is not supported by this cjxl build, which accepts efforts 1 to 9 (10 with --allow_expert_options)");
+
+	// A cjxl without expert options rejects effort 10 with a clear message.
+	let oldest = EffortRange::parse(" -e EFFORT, --effort=EFFORT\n    Encoder effort setting. Range: 3 .. 9.\n").unwrap();
+	assert_eq!(oldest.check(10).unwrap_err(), "Effort 10 is not supported by this cjxl build, which accepts efforts 3 to 9");
+	assert!(oldest.check(1).is_err());
+
+	// A newer cjxl accepts effort 10 as a regular effort.
+	let newer = EffortRange::parse(" -e EFFORT, --effort=EFFORT\n    Encoder effort setting. Range: 1 .. 10.\n").unwrap();
+	assert_eq!(newer.check(10), Ok(false));
+	assert!(EffortRange::parse("Usage: cjxl INPUT OUTPUT").is_none());
+
+	// Expert efforts are encoded with the expert options flag, added only once.
+	let args = DockerManager::effort_extra_args(&["--photon_noise=3".to_string()], true);
+	assert_eq!(args, vec!["--photon_noise=3", "--allow_expert_options"]);
+	assert_eq!(DockerManager::effort_extra_args(&args, true), args);
+	assert!(DockerManager::effort_extra_args(&[], false).is_empty());
+}
@@ -0,0 +1,41 @@
+use std::path::PathBuf;
+
+use benchmark_jpegxl::duplicates::DuplicateImages;
+
+mod common;
+use common::test_dir;
+
+#[test]
+fn test_find_duplicates() {
+	let dir = test_dir("duplicates");
+	let image = image::RgbImage::from_fn(16, 8, |x, y| image::Rgb([(x * 16) as u8, (y * 32) as u8, 128]));
+	image.save(dir.join("a.png")).unwrap();
+	image.save(dir.join("b.png")).unwrap();
+	// The same pixels in another format are a duplicate too.
+	image.save(dir.join("c.ppm")).unwrap();
+	let mut other = image.clone();
+	other.put_pixel(0, 0, image::Rgb([1, 2, 3]));
+	other.save(dir.join("d.png")).unwrap();
+
+	let hash = |name: &str| DuplicateImages::content_hash(dir.join(name).to_str().unwrap()).unwrap();
+	assert_eq!(hash("a.png"), hash("b.png"));
+	assert_eq!(hash("a.png"), hash("c.ppm"));
+	assert_ne!(hash("a.png"), hash("d.png"));
+
+	let image_paths = ["d.png", "c.ppm", "b.png", "a.png"].iter().map(|name| dir.join(name)).collect::<Vec<PathBuf>>();
+	let duplicates = DuplicateImages::find("set", &image_paths);
+	assert_eq!(
+		duplicates,
+		vec![DuplicateImages {
+			test_set: "set".to_string(),
+			images: vec!["a.png".to_string(), "b.png".to_string(), "c.ppm".to_string()],
+			skipped: false,
+		}]
+	);
+	assert_eq!(duplicates[0].repeats(), ["b.png".to_string(), "c.ppm".to_string()]);
+
+	// Images that cannot be decoded are skipped.
+	std::fs::write(dir.join("e.png"), b"not a png").unwrap();
+	let duplicates = DuplicateImages::find("set", &[dir.join("e.png"), dir.join("d.png")]);
+	assert!(duplicates.is_empty());
+}
@@ -0,0 +1,60 @@
+use benchmark_jpegxl::benchmark::{Benchmarker, JXLCompressionBenchmark};
+use benchmark_jpegxl::binary_results::read_comparisons;
+use benchmark_jpegxl::config::{Config, ExpensiveMetricSample};
+use benchmark_jpegxl::smoke::SmokeTest;
+
+mod common;
+use common::{mock_docker, test_dir};
+
+#[test]
+fn test_expensive_metrics_are_sampled() {
+	// The mock docker logs every invocation next to itself.
+	let dir = test_dir("expensive_metrics");
+	let docker = mock_docker(&dir);
+
+	// The mock cjxl writes a real encode of the gradient for every combination.
+	let image_path = dir.join("test_images/set/gradient.png");
+	SmokeTest::generate_image(&image_path).unwrap();
+	let image = image::open(&image_path).unwrap().to_rgb8();
+	let mut encoder = jpegxl_rs::encoder_builder().build().unwrap();
+	let encoded: jpegxl_rs::encode::EncoderResult<u8> =
+		encoder.encode::<u8, u8>(image.as_raw(), image.width(), image.height()).unwrap();
+	std::fs::write(dir.join("cjxl.jxl"), &encoded.data).unwrap();
+
+	let benchmark_dir = dir.join("benchmarks");
+	let mut config = Config {
+		docker_bin: docker,
+		benchmark_dir_path: benchmark_dir.to_str().unwrap().to_string(),
+		local_test_image_dir_path: dir.join("test_images").to_str().unwrap().to_string(),
+		num_workers: 1,
+		libjxl_commit: Some("abc123".to_string()),
+		distances: vec![1.0, 2.0, 3.0],
+		efforts: vec![5, 6, 7],
+		keep_compressed: false,
+		expensive_metric_sample: Some(ExpensiveMetricSample { distances: vec![1.0, 3.0], effort_step: 0 }),
+		..Config::default()
+	};
+	assert!(Benchmarker::new(&config).is_err());
+	config.expensive_metric_sample = Some(ExpensiveMetricSample { distances: vec![1.0, 3.0], effort_step: 2 });
+	let mut benchmarker = Benchmarker::new(&config).unwrap();
+	benchmarker.run_benchmark::<JXLCompressionBenchmark>().unwrap();
+	benchmarker.wait_for_all_workers();
+
+	// Only the sampled combinations run the Docker metrics, the native ones are computed for all.
+	let log = std::fs::read_to_string(dir.join("docker.log")).unwrap();
+	assert_eq!(log.matches("butteraugli_main").count(), 4, "{}", log);
+	assert_eq!(log.matches("ssimulacra2").count(), 4, "{}", log);
+	let mut results = read_comparisons(
+		benchmark_dir.join("0/results/comp/set/abc123/comparisons.csv").to_str().unwrap(),
+	)
+	.unwrap();
+	results.sort_by(|a, b| (a.distance, a.effort).partial_cmp(&(b.distance, b.effort)).unwrap());
+	assert_eq!(results.len(), 9);
+	for result in &results {
+		let sampled = [1.0, 3.0].contains(&result.distance) && [5, 7].contains(&result.effort);
+		assert_eq!(result.butteraugli.is_finite(), sampled, "{:?}", result);
+		assert_eq!(result.butteraugli_pnorm.is_finite(), sampled, "{:?}", result);
+		assert_eq!(result.ssimulacra2.is_finite(), sampled, "{:?}", result);
+		assert!(result.psnr.is_finite() && result.ssim.is_finite(), "{:?}", result);
+	}
+}
@@ -0,0 +1,177 @@
+use benchmark_jpegxl::benchmark::{Benchmarker, JXLCompressionBenchmark};
+use benchmark_jpegxl::config::Config;
+use benchmark_jpegxl::docker_manager::DockerManager;
+use benchmark_jpegxl::failures::RunFailures;
+use benchmark_jpegxl::smoke::SmokeTest;
+
+mod common;
+use common::{mock_docker, test_dir};
+
+#[test]
+fn test_fail_fast_stops_run() {
+	// The mock docker logs every invocation next to itself. Without a `cjxl.jxl` the mock cjxl
+	// writes its arguments as the encoded file, so the first encode cannot be decoded.
+	let dir = test_dir("fail_fast");
+	let docker = mock_docker(&dir);
+	let test_set = dir.join("test_images/set");
+	SmokeTest::generate_image(&test_set.join("a.png")).unwrap();
+	SmokeTest::generate_image(&test_set.join("b.png")).unwrap();
+
+	// The images are dispatched in sorted order in deterministic mode.
+	let benchmark_dir = dir.join("benchmarks");
+	let config = Config {
+		docker_bin: docker,
+		benchmark_dir_path: benchmark_dir.to_str().unwrap().to_string(),
+		local_test_image_dir_path: dir.join("test_images").to_str().unwrap().to_string(),
+		num_workers: 1,
+		libjxl_commit: Some("abc123".to_string()),
+		distances: vec![1.0, 2.0],
+		efforts: vec![7],
+		keep_compressed: false,
+		fail_fast: true,
+		deterministic: true,
+		..Config::default()
+	};
+	let mut benchmarker = Benchmarker::new(&config).unwrap();
+	let error = benchmarker.run_benchmark::<JXLCompressionBenchmark>().unwrap_err().to_string();
+	assert!(error.starts_with("Stopped the run after a failure: Could not decode a"), "{}", error);
+	assert!(benchmarker.failures.stopped());
+	assert_eq!(benchmarker.failures.messages().len(), 1, "{:?}", benchmarker.failures.messages());
+
+	// Only the failing encode was run, the second image was never dispatched.
+	let log = std::fs::read_to_string(dir.join("docker.log")).unwrap();
+	let encodes = log
+		.lines()
+		.filter(|line| line.contains("/cjxl ") && !line.contains("--help") && !line.contains("--version"))
+		.collect::<Vec<&str>>();
+	assert_eq!(encodes.len(), 1, "{}", log);
+	assert!(encodes[0].contains("a.png"), "{}", log);
+
+	// The rest of the combinations of the failing image are recorded as skipped.
+	let mut reader = csv::Reader::from_path(benchmark_dir.join("0/results/status.csv")).unwrap();
+	let statuses = reader
+		.records()
+		.map(|record| {
+			let record = record.unwrap();
+			(record[2].to_string(), record[3].to_string(), record[6].to_string(), record[7].to_string())
+		})
+		.collect::<Vec<(String, String, String, String)>>();
+	assert_eq!(statuses.len(), 2, "{:?}", statuses);
+	assert_eq!((statuses[0].1.as_str(), statuses[0].2.as_str()), ("1", "failed"), "{:?}", statuses);
+	assert!(statuses[0].3.starts_with("Could not decode a"), "{:?}", statuses);
+	assert_eq!(
+		statuses[1],
+		("a".to_string(), "2".to_string(), "skipped".to_string(), "the run was stopped".to_string())
+	);
+}
+
+#[test]
+fn test_worker_with_dead_container_is_retired() {
+	let dir = test_dir("retired_worker");
+	let docker = mock_docker(&dir);
+	let test_set = dir.join("test_images/set");
+	SmokeTest::generate_image(&test_set.join("a.png")).unwrap();
+	SmokeTest::generate_image(&test_set.join("b.png")).unwrap();
+
+	// The mock cjxl writes a real encode of the gradient.
+	let image = image::open(test_set.join("a.png")).unwrap().to_rgb8();
+	let mut encoder = jpegxl_rs::encoder_builder().build().unwrap();
+	let encoded: jpegxl_rs::encode::EncoderResult<u8> =
+		encoder.encode::<u8, u8>(image.as_raw(), image.width(), image.height()).unwrap();
+	std::fs::write(dir.join("cjxl.jxl"), &encoded.data).unwrap();
+
+	let benchmark_dir = dir.join("benchmarks");
+	let config = Config {
+		docker_bin: docker,
+		benchmark_dir_path: benchmark_dir.to_str().unwrap().to_string(),
+		local_test_image_dir_path: dir.join("test_images").to_str().unwrap().to_string(),
+		num_workers: 2,
+		libjxl_commit: Some("abc123".to_string()),
+		distances: vec![1.0],
+		efforts: vec![7],
+		keep_compressed: false,
+		..Config::default()
+	};
+	let mut benchmarker = Benchmarker::new(&config).unwrap();
+
+	// The container of the second worker dies and cannot be set up again.
+	let dead_container = format!("{}-1", DockerManager::CONTAINER_NAME);
+	std::fs::write(dir.join(format!("state.{}", dead_container)), "exited\n").unwrap();
+	std::fs::write(dir.join("fail"), "build").unwrap();
+	std::fs::remove_file(dir.join("docker.log")).unwrap();
+	benchmarker.run_benchmark::<JXLCompressionBenchmark>().unwrap();
+	benchmarker.wait_for_all_workers();
+
+	// The failed restart is recorded once, and the worker is not handed any image.
+	let messages = benchmarker.failures.messages();
+	assert_eq!(messages.len(), 1, "{:?}", messages);
+	assert!(messages[0].starts_with("Could not restart the container of worker 1"), "{:?}", messages);
+	assert!(benchmarker.workers[1].retired);
+	let log = std::fs::read_to_string(dir.join("docker.log")).unwrap();
+	let dispatched = log
+		.lines()
+		.filter(|line| line.contains(&dead_container))
+		.any(|line| !line.starts_with("inspect") && !line.starts_with("rm"));
+	assert!(!dispatched, "{}", log);
+
+	// Both images are benchmarked by the live worker.
+	let comparisons_file = benchmark_dir.join("0/results/comp/set/abc123/comparisons.csv");
+	let results = benchmark_jpegxl::binary_results::read_comparisons(comparisons_file.to_str().unwrap()).unwrap();
+	assert_eq!(results.len(), 2, "{:?}", results);
+}
+
+#[test]
+fn test_failures_collected_without_fail_fast() {
+	// By default failures are collected for the report at the end of the run.
+	let failures = RunFailures::new(false);
+	failures.record("Could not decode kodim01__d1__e7.jxl".to_string());
+	failures.record("Could not decode kodim02__d1__e7.jxl".to_string());
+	assert!(!failures.stopped());
+	assert_eq!(failures.messages().len(), 2);
+}
+
+#[test]
+fn test_unsupported_efforts_are_reported_once_per_build() {
+	// The mock cjxl accepts efforts 1 to 9, and is asked for effort 11 on two images.
+	let dir = test_dir("unsupported_efforts");
+	let docker = mock_docker(&dir);
+	std::fs::write(dir.join("cjxl_help"), " -e EFFORT, --effort=EFFORT\n    Encoder effort setting. Range: 1 .. 9.\n").unwrap();
+	let test_set = dir.join("test_images/set");
+	SmokeTest::generate_image(&test_set.join("a.png")).unwrap();
+	SmokeTest::generate_image(&test_set.join("b.png")).unwrap();
+	let config = |efforts: Vec<u32>| Config {
+		docker_bin: docker.clone(),
+		benchmark_dir_path: dir.join("benchmarks").to_str().unwrap().to_string(),
+		local_test_image_dir_path: dir.join("test_images").to_str().unwrap().to_string(),
+		num_workers: 1,
+		libjxl_commit: Some("abc123".to_string()),
+		distances: vec![1.0],
+		efforts,
+		keep_compressed: false,
+		deterministic: true,
+		..Config::default()
+	};
+	let effort_failures = |benchmarker: &Benchmarker| {
+		benchmarker.failures.messages().into_iter().filter(|message| message.contains("Effort 11")).count()
+	};
+	let log_lines = |pattern: &str| {
+		let log = std::fs::read_to_string(dir.join("docker.log")).unwrap();
+		log.lines().filter(|line| line.contains("/cjxl ") && line.contains(pattern)).count()
+	};
+
+	// The build is probed and the effort reported once, and the supported effort is encoded.
+	let mut benchmarker = Benchmarker::new(&config(vec![7, 11])).unwrap();
+	benchmarker.run_benchmark::<JXLCompressionBenchmark>().unwrap();
+	assert_eq!(effort_failures(&benchmarker), 1, "{:?}", benchmarker.failures.messages());
+	assert_eq!(log_lines("--help"), 1);
+	assert_eq!(log_lines("--effort=7"), 2);
+	assert_eq!(log_lines("--effort=11"), 0);
+
+	// Without any supported effort, the images are skipped after the effort is reported.
+	std::fs::remove_file(dir.join("docker.log")).unwrap();
+	let mut benchmarker = Benchmarker::new(&config(vec![11])).unwrap();
+	benchmarker.run_benchmark::<JXLCompressionBenchmark>().unwrap();
+	assert_eq!(effort_failures(&benchmarker), 1, "{:?}", benchmarker.failures.messages());
+	assert_eq!(log_lines("--help"), 1);
+	assert_eq!(log_lines("--effort="), 0);
+}
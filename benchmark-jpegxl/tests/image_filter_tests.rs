@@ -0,0 +1,69 @@
+use std::path::PathBuf;
+
+use benchmark_jpegxl::benchmark::{Benchmarker, JXLCompressionBenchmark};
+use benchmark_jpegxl::config::Config;
+
+mod common;
+use common::{mock_docker, test_dir};
+
+#[test]
+fn test_image_filter() {
+	// The mock docker logs every invocation next to itself.
+	let dir = test_dir("image_filter");
+	let docker = mock_docker(&dir);
+
+	// The selected image is in both test sets, next to images that are not selected.
+	for (test_set, image_name) in [("set1", "a.png"), ("set1", "b.png"), ("set1", "c.png"), ("set2", "b.png"), ("set2", "d.png")] {
+		let image_path = dir.join("test_images").join(test_set).join(image_name);
+		std::fs::create_dir_all(image_path.parent().unwrap()).unwrap();
+		image::RgbImage::new(8, 8).save(image_path).unwrap();
+	}
+
+	let benchmark_dir = dir.join("benchmarks");
+	let mut config = Config {
+		docker_bin: docker,
+		benchmark_dir_path: benchmark_dir.to_str().unwrap().to_string(),
+		local_test_image_dir_path: dir.join("test_images").to_str().unwrap().to_string(),
+		num_workers: 1,
+		libjxl_commit: Some("abc123".to_string()),
+		distances: vec![1.0, 2.0],
+		efforts: vec![7],
+		images: vec!["b".to_string(), "e.png".to_string()],
+		..Config::default()
+	};
+
+	// An image that is in no test set is an error.
+	match Benchmarker::new(&config) {
+		Err(e) => assert!(e.to_string().contains("The image e.png is not in any test set"), "{}", e),
+		Ok(_) => panic!("expected an error for an image that is in no test set"),
+	}
+
+	// Images are selected by their name with or without the extension.
+	assert!(Benchmarker::image_selected(&config.images, &PathBuf::from("set/b.png")));
+	assert!(Benchmarker::image_selected(&["b.png".to_string()], &PathBuf::from("set/b.png")));
+	assert!(!Benchmarker::image_selected(&config.images, &PathBuf::from("set/bb.png")));
+	assert!(Benchmarker::image_selected(&[], &PathBuf::from("set/a.png")));
+
+	config.images = vec!["b".to_string()];
+	let mut benchmarker = Benchmarker::new(&config).unwrap();
+	benchmarker.run_benchmark::<JXLCompressionBenchmark>().unwrap();
+	benchmarker.wait_for_all_workers();
+
+	// Only the combinations of the selected image are run, in both test sets.
+	let mut reader = csv::Reader::from_path(benchmark_dir.join("0/results/status.csv")).unwrap();
+	let mut planned = reader
+		.records()
+		.map(|record| {
+			let record = record.unwrap();
+			(record[0].to_string(), record[2].to_string(), record[3].to_string())
+		})
+		.collect::<Vec<(String, String, String)>>();
+	planned.sort();
+	let expected = [("set1", "b", "1"), ("set1", "b", "2"), ("set2", "b", "1"), ("set2", "b", "2")]
+		.map(|(test_set, image, distance)| (test_set.to_string(), image.to_string(), distance.to_string()));
+	assert_eq!(planned, expected.to_vec());
+	let log = std::fs::read_to_string(dir.join("docker.log")).unwrap();
+	let encodes = log.lines().filter(|line| line.contains("/cjxl ") && !line.contains("--help") && !line.contains("--version")).collect::<Vec<&str>>();
+	assert_eq!(encodes.len(), 4, "{}", log);
+	assert!(encodes.iter().all(|line| line.contains("b.png")), "{}", log);
+}
@@ -0,0 +1,97 @@
+use std::path::PathBuf;
+
+use benchmark_jpegxl::image_limits::ImageLimits;
+use benchmark_jpegxl::image_reader::ImageReader;
+
+mod common;
+use common::test_dir;
+
+/// Computes the CRC-32 of a PNG chunk type and data.
+fn crc32(bytes: &[u8]) -> u32 {
+	let mut crc = 0xffffffffu32;
+	for byte in bytes {
+		crc ^= *byte as u32;
+		for _ in 0..8 {
+			crc = match crc & 1 {
+				1 => (crc >> 1) ^ 0xedb88320,
+				_ => crc >> 1,
+			};
+		}
+	}
+	!crc
+}
+
+/// Writes a PNG chunk with its length and CRC.
+fn write_chunk(png: &mut Vec<u8>, chunk_type: &[u8], data: &[u8]) {
+	png.extend((data.len() as u32).to_be_bytes());
+	let start = png.len();
+	png.extend(chunk_type);
+	png.extend(data);
+	let crc = crc32(&png[start..]);
+	png.extend(crc.to_be_bytes());
+}
+
+/// Creates a PNG that declares the given dimensions without holding their pixels.
+fn declared_png(width: u32, height: u32) -> Vec<u8> {
+	let mut png = vec![0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a];
+	let mut header = Vec::new();
+	header.extend(width.to_be_bytes());
+	header.extend(height.to_be_bytes());
+	// 8-bit RGB, deflate, adaptive filtering, no interlacing.
+	header.extend([8, 2, 0, 0, 0]);
+	write_chunk(&mut png, b"IHDR", &header);
+	write_chunk(&mut png, b"IDAT", &[0x78, 0x9c, 0x03, 0x00, 0x00, 0x00, 0x00, 0x01]);
+	write_chunk(&mut png, b"IEND", &[]);
+	png
+}
+
+#[test]
+fn test_image_limits() {
+	let dir = test_dir("image_limits");
+	let image = image::RgbImage::from_fn(16, 8, |x, y| image::Rgb([(x * 16) as u8, (y * 32) as u8, 128]));
+	let tiny_path = dir.join("tiny.png");
+	image.save(&tiny_path).unwrap();
+	let mut encoder = jpegxl_rs::encoder_builder().build().unwrap();
+	let encoded: jpegxl_rs::encode::EncoderResult<u8> =
+		encoder.encode::<u8, u8>(image.as_raw(), image.width(), image.height()).unwrap();
+	let jxl_path = dir.join("tiny.jxl");
+	std::fs::write(&jxl_path, &encoded.data).unwrap();
+	let bomb_path = dir.join("bomb.png");
+	std::fs::write(&bomb_path, declared_png(100000, 100000)).unwrap();
+	let check = |limits: ImageLimits, path: &PathBuf| limits.check(path.to_str().unwrap());
+
+	// Without limits nothing is checked, even the declared dimensions of the bomb.
+	assert!(ImageLimits::default().is_unlimited());
+	assert!(check(ImageLimits::default(), &bomb_path).is_ok());
+
+	// The tiny images are well under the limits.
+	let limits = ImageLimits::new(1000, 1 << 20);
+	assert!(!limits.is_unlimited());
+	assert_eq!(check(limits, &tiny_path), Ok(()));
+	assert_eq!(check(limits, &jxl_path), Ok(()));
+
+	// The bomb is rejected by its declared dimensions, for either limit.
+	let error = check(ImageLimits::new(1000, 0), &bomb_path).unwrap_err();
+	assert!(error.contains("100000x100000"), "{}", error);
+	assert!(check(ImageLimits::new(0, 1 << 20), &bomb_path).is_err());
+
+	// The limits apply to the tiny images too once they are lower than their size.
+	assert!(check(ImageLimits::new(100, 0), &tiny_path).is_err());
+	assert!(check(ImageLimits::new(100, 0), &jxl_path).is_err());
+	assert!(check(ImageLimits::new(0, 100), &tiny_path).is_err());
+	assert!(check(ImageLimits::new(0, 1000), &jxl_path).is_err());
+
+	// The decode paths apply the limits too, rejecting the bomb before allocating for it.
+	let tiny = tiny_path.to_str().unwrap();
+	let bomb = bomb_path.to_str().unwrap();
+	assert_eq!(limits.open(tiny).unwrap().width(), 16);
+	assert!(ImageLimits::new(0, 1 << 20).open(bomb).is_err());
+	assert!(ImageReader::decode_rgb8_with_limits(bomb, &limits).is_err());
+	assert!(ImageReader::try_new_with_limits(bomb.to_string(), "main".to_string(), &limits).is_err());
+	assert_eq!(ImageReader::decode_rgb8_with_limits(tiny, &limits).unwrap().0, 16);
+
+	// Images that cannot be read are left for the decoders to report.
+	let broken_path = dir.join("broken.png");
+	std::fs::write(&broken_path, b"not a png").unwrap();
+	assert!(check(limits, &broken_path).is_ok());
+}
@@ -0,0 +1,425 @@
+use std::path::{Path, PathBuf};
+
+use benchmark_jpegxl::config::Roi;
+use benchmark_jpegxl::image_reader::*;
+use benchmark_jpegxl::metrics::*;
+
+mod common;
+use common::test_dir;
+
+/// Writes a synthetic RGB gradient PNG of the given dimensions.
+fn write_gradient_png(path: &PathBuf, width: u32, height: u32) {
+	image::RgbImage::from_fn(width, height, |x, y| {
+		image::Rgb([(x % 256) as u8, (y % 256) as u8, ((x + y) % 256) as u8])
+	})
+	.save(path)
+	.unwrap();
+}
+
+/// Encodes a PNG to a JXL file with the jpegxl_rs encoder.
+fn encode_jxl(png_path: &PathBuf, jxl_path: &PathBuf) {
+	let image = image::open(png_path).unwrap().to_rgb8();
+	let mut encoder = jpegxl_rs::encoder_builder().build().unwrap();
+	let result: jpegxl_rs::encode::EncoderResult<u8> = encoder
+		.encode::<u8, u8>(image.as_raw(), image.width(), image.height())
+		.unwrap();
+	std::fs::write(jxl_path, &result.data).unwrap();
+}
+
+#[test]
+fn test_downscale_to_max_dimension() {
+	let dir = test_dir("downscale");
+	let src = dir.join("large.png");
+	write_gradient_png(&src, 1000, 600);
+
+	// A 1000px wide image must be scaled to fit within 512px.
+	let out_dir = dir.join("orig");
+	let scaled = ImageReader::downscale_to_max_dimension(
+		src.to_str().unwrap(),
+		out_dir.to_str().unwrap(),
+		512,
+	)
+	.unwrap()
+	.expect("image larger than max dimension should be scaled");
+	let (width, height) = image::image_dimensions(&scaled).unwrap();
+	assert_eq!(width, 512);
+	assert!(height <= 512);
+	assert!(scaled.starts_with(out_dir.to_str().unwrap()));
+
+	// An image that already fits is left alone.
+	let not_scaled = ImageReader::downscale_to_max_dimension(
+		src.to_str().unwrap(),
+		out_dir.to_str().unwrap(),
+		1000,
+	)
+	.unwrap();
+	assert!(not_scaled.is_none());
+
+	// Metrics run against the scaled version of the image.
+	let comp = dir.join("large-1-7.jxl");
+	encode_jxl(&PathBuf::from(&scaled), &comp);
+	let mse = calculate_mse(&scaled, &comp.to_str().unwrap().to_string());
+	assert!(mse.is_finite());
+	let comp_data = ImageReader::new(comp.to_str().unwrap().to_string(), "main".to_string()).file_data;
+	assert_eq!(comp_data.width, width);
+	assert_eq!(comp_data.height, height);
+}
+
+#[test]
+fn test_compressed_image_name_round_trip() {
+	let dir = test_dir("naming");
+	let src = dir.join("my-photo.png");
+	write_gradient_png(&src, 64, 48);
+
+	// Encode a hyphenated image name and read the parameters back from the JXL.
+	let comp_name = ImageReader::compressed_image_name("my-photo", 1.5, 7);
+	let comp = dir.join(&comp_name);
+	encode_jxl(&src, &comp);
+	let comp_data = ImageReader::new(comp.to_str().unwrap().to_string(), "main".to_string()).file_data;
+	assert_eq!(comp_data.jxl_orig_image_name.to_string(), "my-photo");
+	assert_eq!(comp_data.jxl_distance.to_string(), "1.5");
+	assert_eq!(comp_data.jxl_effort.to_string(), "7");
+
+	// Names using the older hyphen separated scheme still parse.
+	let (name, distance, effort) = ImageReader::parse_compressed_image_name("kodim06-0.5-9.jxl");
+	assert_eq!(name, "kodim06");
+	assert_eq!(distance.to_string(), "0.5");
+	assert_eq!(effort.to_string(), "9");
+}
+
+#[test]
+fn test_parse_compressed_image_name_without_effort() {
+	let parse = |file_name: &str| {
+		let (name, distance, effort) = ImageReader::parse_compressed_image_name(file_name);
+		(name, distance.to_string(), effort.to_string())
+	};
+	let parsed = |name: &str, distance: &str, effort: &str| (name.to_string(), distance.to_string(), effort.to_string());
+
+	// With an effort token, both schemes parse the distance and effort.
+	assert_eq!(parse("my__photo__d1.5__e7.jxl"), parsed("my__photo", "1.5", "7"));
+	assert_eq!(parse("my-photo-1.5-7.jxl"), parsed("my-photo", "1.5", "7"));
+
+	// Without one, the distance is not shifted into the effort.
+	assert_eq!(parse("my__photo__d1.5.jxl"), parsed("my__photo", "1.5", ""));
+	assert_eq!(parse("kodim06__d0.jxl"), parsed("kodim06", "0", ""));
+	assert_eq!(parse("my-photo-1.5.jxl"), parsed("my-photo", "1.5", ""));
+	assert_eq!(parse("kodim06-2-0.5.jxl"), parsed("kodim06-2", "0.5", ""));
+
+	// Names without a distance are kept whole.
+	assert_eq!(parse("my-photo.jxl"), parsed("my-photo", "", ""));
+	assert_eq!(parse("kodim06.jxl"), parsed("kodim06", "", ""));
+}
+
+#[test]
+fn test_raw_size_does_not_overflow() {
+	// A 30000x30000 RGBA16 image is 7.2 GB, more than fits in a u32.
+	let size = ImageReader::raw_size(30000, 30000, ColorType::Rgba16.bytes_per_pixel()).unwrap();
+	assert_eq!(size, 7_200_000_000u64);
+	assert!(size > u32::MAX as u64);
+	assert_eq!(ImageReader::raw_size(16, 8, 3).unwrap(), 16 * 8 * 3);
+
+	// Sizes that do not fit in a u64 are an error instead of wrapping.
+	assert!(ImageReader::raw_size(u32::MAX, u32::MAX, 16).is_err());
+}
+
+#[test]
+fn test_read_pnm() {
+	// 8-bit binary PPM (P6).
+	let ppm = ImageReader::new("tests/images/pnm/gradient8.ppm".to_string(), "main".to_string()).file_data;
+	assert_eq!((ppm.width, ppm.height), (16, 8));
+	assert_eq!(ppm.color_space.to_string(), "Rgb8");
+	assert_eq!(ppm.raw_size, 16 * 8 * 3);
+	assert_eq!(ppm.file_format, ImageFormat::Pnm);
+
+	// 16-bit binary PGM (P5).
+	let pgm = ImageReader::new("tests/images/pnm/gradient16.pgm".to_string(), "main".to_string()).file_data;
+	assert_eq!((pgm.width, pgm.height), (16, 8));
+	assert_eq!(pgm.color_space.to_string(), "L16");
+	assert_eq!(pgm.raw_size, 16 * 8 * 2);
+	assert_eq!(pgm.file_format, ImageFormat::Pnm);
+	assert_eq!(ImageFormat::from_file_name("gradient16.pgm"), ImageFormat::Pnm);
+}
+
+#[test]
+fn test_crop_to_roi() {
+	let dir = test_dir("roi");
+	let orig = dir.join("orig.png");
+	write_gradient_png(&orig, 64, 32);
+
+	// Distort only the right half of the image.
+	let comp = dir.join("comp.png");
+	let mut distorted = image::open(&orig).unwrap().to_rgb8();
+	for (x, _, pixel) in distorted.enumerate_pixels_mut() {
+		if x >= 32 {
+			pixel[0] = 255 - pixel[0];
+		}
+	}
+	distorted.save(&comp).unwrap();
+
+	let full = compare_images(&orig, &comp, &[MetricKind::Mse]).unwrap();
+	assert!(full.mse.unwrap() > 0.0);
+
+	// Metrics over the undistorted left half differ from the full image values.
+	let roi = "0,0,32,32".parse::<Roi>().unwrap();
+	let orig_roi = ImageReader::crop_to_png(orig.to_str().unwrap(), &roi, dir.join("roi/orig.png").to_str().unwrap()).unwrap();
+	let comp_roi = ImageReader::crop_to_png(comp.to_str().unwrap(), &roi, dir.join("roi/comp.png").to_str().unwrap()).unwrap();
+	assert_eq!(image::image_dimensions(&orig_roi).unwrap(), (32, 32));
+	let cropped = compare_images(PathBuf::from(&orig_roi).as_path(), PathBuf::from(&comp_roi).as_path(), &[MetricKind::Mse]).unwrap();
+	assert_eq!(cropped.mse.unwrap(), 0.0);
+	assert_ne!(cropped.mse, full.mse);
+
+	// Malformed regions and regions outside of the image bounds are rejected.
+	assert!("0,0,32".parse::<Roi>().is_err());
+	assert!("0,0,32,x".parse::<Roi>().is_err());
+	let out_of_bounds = "40,0,32,32".parse::<Roi>().unwrap();
+	assert!(out_of_bounds.validate(64, 32).is_err());
+	assert!(ImageReader::crop_to_png(orig.to_str().unwrap(), &out_of_bounds, dir.join("roi/bad.png").to_str().unwrap()).is_err());
+}
+
+#[test]
+fn test_in_memory_retrieval_matches_disk_copy() {
+	let dir = test_dir("in_memory");
+	let src = dir.join("photo.png");
+	write_gradient_png(&src, 48, 32);
+	let comp_name = ImageReader::compressed_image_name("photo", 1.0, 7);
+	let comp = dir.join(&comp_name);
+	encode_jxl(&src, &comp);
+
+	// Stream the file as a tar archive to stdout, like `docker cp <container>:<path> -`.
+	let archive = std::process::Command::new("tar")
+		.arg("-cf")
+		.arg("-")
+		.arg("-C")
+		.arg(&dir)
+		.arg(&comp_name)
+		.output()
+		.unwrap()
+		.stdout;
+	let bytes = benchmark_jpegxl::docker_manager::DockerManager::extract_file_from_tar(&archive).unwrap();
+	assert_eq!(bytes, std::fs::read(&comp).unwrap());
+
+	// The in-memory image reads the same as the on-disk copy.
+	let path = comp.to_str().unwrap().to_string();
+	let from_disk = ImageReader::new(path.clone(), "main".to_string()).file_data;
+	let from_memory = ImageReader::from_jxl_bytes(&bytes, path, "main".to_string()).unwrap().file_data;
+	assert_eq!(from_memory.file_size, from_disk.file_size);
+	assert_eq!(from_memory.raw_size, from_disk.raw_size);
+	assert_eq!((from_memory.width, from_memory.height), (from_disk.width, from_disk.height));
+}
+
+#[test]
+fn test_cross_check_decode_agrees() {
+	let dir = test_dir("cross_check_decode");
+	for (name, width, height) in [("photo", 48, 32), ("odd", 33, 17)] {
+		let src = dir.join(format!("{}.png", name));
+		write_gradient_png(&src, width, height);
+		let comp = dir.join(ImageReader::compressed_image_name(name, 1.0, 7));
+		encode_jxl(&src, &comp);
+
+		// Both decoders read the same dimensions and nearly the same pixels.
+		let bytes = std::fs::read(&comp).unwrap();
+		let (oxide_width, oxide_height, oxide_pixels) = ImageReader::decode_jxl_oxide_rgb8(&bytes).unwrap();
+		assert_eq!((oxide_width, oxide_height), (width, height));
+		assert_eq!(oxide_pixels.len(), (width * height * 3) as usize);
+		assert!(!ImageReader::decoders_disagree(&bytes).unwrap());
+	}
+
+	// A truncated codestream cannot be cross-checked.
+	let bytes = std::fs::read(dir.join(ImageReader::compressed_image_name("photo", 1.0, 7))).unwrap();
+	assert!(ImageReader::decoders_disagree(&bytes[..bytes.len() / 2]).is_err());
+}
+
+#[test]
+fn test_undecodable_jxl_is_skipped() {
+	let dir = test_dir("undecodable_jxl");
+	let src = dir.join("photo.png");
+	write_gradient_png(&src, 32, 32);
+	let comp = dir.join(ImageReader::compressed_image_name("photo", 1.0, 7));
+	encode_jxl(&src, &comp);
+
+	// A truncated codestream is rejected by the primary decoder.
+	let bytes = std::fs::read(&comp).unwrap();
+	let truncated = dir.join(ImageReader::compressed_image_name("truncated", 1.0, 7));
+	std::fs::write(&truncated, &bytes[..bytes.len() / 2]).unwrap();
+
+	// Reading it is a handled error (or a djxl fallback decode) rather than a panic.
+	match ImageReader::try_new(truncated.to_str().unwrap().to_string(), "main".to_string()) {
+		Ok(reader) => assert_eq!(reader.file_data.decoder, "djxl"),
+		Err(e) => assert!(e.to_string().contains("jpegxl_rs")),
+	}
+
+	// Valid images record the primary decoder.
+	let reader = ImageReader::try_new(comp.to_str().unwrap().to_string(), "main".to_string()).unwrap();
+	assert_eq!(reader.file_data.decoder, "jpegxl-rs");
+}
+
+#[test]
+fn test_diff_image() {
+	let dir = test_dir("diff_image");
+	let (width, height, orig) = ImageReader::decode_rgb8("tests/images/pnm/gradient8.ppm").unwrap();
+
+	// Distort a single pixel of the fixture.
+	let mut comp = orig.clone();
+	comp[0] = comp[0].wrapping_add(16);
+
+	let out_path = dir.join("diff/gradient8.diff.png");
+	let diff_path = ImageReader::write_diff_image(&orig, &comp, width, height, out_path.to_str().unwrap()).unwrap();
+	assert_eq!(image::image_dimensions(&diff_path).unwrap(), (width, height));
+
+	// The distorted pixel is the hottest, all other pixels are black.
+	let heatmap = image::open(&diff_path).unwrap().to_rgb8();
+	assert_eq!(heatmap.get_pixel(0, 0).0, [255, 255, 255]);
+	assert!(heatmap.enumerate_pixels().skip(1).all(|(_, _, pixel)| pixel.0 == [0, 0, 0]));
+
+	// Buffers that do not match the dimensions are rejected.
+	assert!(ImageReader::write_diff_image(&orig, &comp[3..], width, height, out_path.to_str().unwrap()).is_err());
+}
+
+#[test]
+fn test_header_params_check_file_name() {
+	// A lossy encode named as a lossless encode.
+	let (_, filename_distance, effort) = ImageReader::parse_compressed_image_name("kodim01__d0__e7.jxl");
+	let mut file_data = ImageReader::new("tests/images/icc/srgb.png".to_string(), "main".to_string()).file_data;
+	file_data.filename_distance = filename_distance;
+	file_data.jxl_distance = filename_distance;
+	let lossy = "JPEG XL file format container (ISO/IEC 18181-2)\nJPEG XL image, 64x32, lossy, 8-bit RGB\n";
+	assert_eq!(ImageReader::parse_jxlinfo_lossless(lossy), Some(false));
+
+	// The disagreement is caught and the unknown distance is left empty, while the effort still
+	// comes from the name.
+	ImageReader::apply_jxlinfo(&mut file_data, lossy);
+	assert_eq!(file_data.header_lossless, Some(false));
+	assert_eq!(file_data.jxl_distance.to_string(), "");
+	assert_eq!(effort.to_string(), "7");
+
+	// A lossy header agrees with a distance 1 name, which is kept.
+	let (_, filename_distance, _) = ImageReader::parse_compressed_image_name("kodim01__d1__e7.jxl");
+	file_data.filename_distance = filename_distance;
+	file_data.jxl_distance = filename_distance;
+	ImageReader::apply_jxlinfo(&mut file_data, lossy);
+	let distance: f32 = file_data.jxl_distance.into();
+	assert_eq!(distance, 1.0);
+
+	// "(possibly) lossless" does not tell, so the file name is kept.
+	ImageReader::apply_jxlinfo(&mut file_data, "JPEG XL image, 64x32, (possibly) lossless, 8-bit RGB");
+	assert_eq!(file_data.header_lossless, None);
+	let distance: f32 = file_data.jxl_distance.into();
+	assert_eq!(distance, 1.0);
+
+	// Without jxlinfo output the file name is used.
+	assert_eq!(ImageReader::parse_jxlinfo_lossless("jxlinfo: command not found"), None);
+	assert!(!ImageReader::encode_params_mismatch(filename_distance, None));
+}
+
+#[test]
+fn test_float_psnr_is_normalized() {
+	let dir = test_dir("float_psnr");
+	let src = dir.join("gradient.png");
+	write_gradient_png(&src, 64, 48);
+
+	// Encode the normalized samples of the original as a float JXL.
+	let image = image::open(&src).unwrap().to_rgb32f();
+	let mut encoder = jpegxl_rs::encoder_builder().build().unwrap();
+	let result: jpegxl_rs::encode::EncoderResult<f32> = encoder
+		.encode::<f32, f32>(image.as_raw(), image.width(), image.height())
+		.unwrap();
+	let comp = dir.join("gradient-1-7.jxl");
+	std::fs::write(&comp, &result.data).unwrap();
+
+	// Both images are compared on the 0..1 scale with a matching peak.
+	let (mse, peak) =
+		calculate_mse_with_peak(&src.to_str().unwrap().to_string(), &comp.to_str().unwrap().to_string());
+	assert_eq!(peak, ImageReader::NORMALIZED_PEAK);
+	assert!(mse > 0.0 && mse < 1.0);
+	let psnr = ImageReader::calculate_psnr(mse, peak);
+	assert!(psnr > 20.0 && psnr < 100.0, "unexpected float PSNR {}", psnr);
+}
+
+#[test]
+fn test_source_is_lossy() {
+	let dir = test_dir("source_is_lossy");
+	let png = dir.join("gradient.png");
+	write_gradient_png(&png, 32, 32);
+	let jpg = dir.join("gradient.jpg");
+	image::open(&png).unwrap().to_rgb8().save(&jpg).unwrap();
+
+	let png_data = ImageReader::new(png.to_str().unwrap().to_string(), "local".to_string()).file_data;
+	let jpg_data = ImageReader::new(jpg.to_str().unwrap().to_string(), "local".to_string()).file_data;
+	assert!(!png_data.source_is_lossy);
+	assert!(jpg_data.source_is_lossy);
+}
+
+#[test]
+fn test_read_cmyk_and_grayscale_jpeg() {
+	// An Adobe CMYK JPEG with a block of red ink next to a block of blue ink is read as RGB.
+	let cmyk = "tests/images/jpeg/cmyk.jpg";
+	let data = ImageReader::new(cmyk.to_string(), "main".to_string()).file_data;
+	assert_eq!((data.width, data.height), (16, 8));
+	assert_eq!(data.color_space.to_string(), "Rgb8");
+	assert_eq!(data.raw_size, 16 * 8 * 3);
+	assert_eq!(data.source_color_model, "CMYK");
+	assert!(data.source_is_lossy);
+	assert_eq!(ImageReader::jpeg_color_model(cmyk), Some("CMYK"));
+	let (_, _, pixels) = ImageReader::decode_rgb8(cmyk).unwrap();
+	let near = |pixel: &[u8], expected: [u8; 3]| pixel.iter().zip(expected).all(|(a, b)| a.abs_diff(b) <= 2);
+	assert!(near(&pixels[0..3], [255, 0, 0]), "{:?}", &pixels[0..3]);
+	assert!(near(&pixels[pixels.len() - 3..], [0, 0, 255]), "{:?}", &pixels[pixels.len() - 3..]);
+
+	// The conversion writes an RGB PNG that is measured against the JPEG like any other image.
+	let dir = test_dir("cmyk_jpeg");
+	let converted = ImageReader::convert_cmyk_to_rgb(cmyk, dir.to_str().unwrap()).unwrap().unwrap();
+	assert_eq!(converted, format!("{}/cmyk.png", dir.to_str().unwrap()));
+	let converted_data = ImageReader::new(converted.clone(), "main".to_string()).file_data;
+	assert_eq!(converted_data.color_space.to_string(), "Rgb8");
+	assert_eq!(converted_data.source_color_model, "RGB");
+	assert_eq!(ImageReader::source_color_model(cmyk), "CMYK");
+	let metrics = compare_images(Path::new(cmyk), Path::new(&converted), &[MetricKind::Mse]).unwrap();
+	assert_eq!(metrics.mse.unwrap(), 0.0);
+
+	// Images that are not CMYK JPEGs are left alone.
+	assert!(ImageReader::convert_cmyk_to_rgb("tests/images/pnm/gradient8.ppm", dir.to_str().unwrap()).unwrap().is_none());
+	assert_eq!(ImageReader::jpeg_color_model("tests/images/pnm/gradient8.ppm"), None);
+
+	// A grayscale JPEG keeps its single channel.
+	let gray = dir.join("gray.jpg");
+	image::GrayImage::from_fn(16, 8, |x, y| image::Luma([(x * 16 + y) as u8])).save(&gray).unwrap();
+	let gray = gray.to_str().unwrap().to_string();
+	let data = ImageReader::new(gray.clone(), "main".to_string()).file_data;
+	assert_eq!(data.color_space.to_string(), "L8");
+	assert_eq!(data.raw_size, 16 * 8);
+	assert_eq!(data.source_color_model, "Gray");
+	assert!(ImageReader::convert_cmyk_to_rgb(&gray, dir.to_str().unwrap()).unwrap().is_none());
+}
+
+#[test]
+fn test_jxl_decode_available() {
+	// The test environment links libjxl, so JXL images can be decoded.
+	assert!(jxl_decode_available());
+	assert_eq!(
+		benchmark_jpegxl::benchmark::BenchmarkError::JxlDecodeUnavailable.to_string(),
+		"JXL decoding unavailable; install libjxl"
+	);
+}
+
+#[test]
+fn test_multi_channel_color_type() {
+	// Up to 4 channels map to the fixed color types.
+	assert_eq!(ColorType::from_channels(3, 8), ColorType::Rgb8);
+	assert_eq!(ColorType::from_channels(4, 16), ColorType::Rgba16);
+	assert_eq!(ColorType::from_channels(3, 8).bytes_per_pixel(), 3);
+	assert!(ColorType::Rgb8.native_metrics_supported());
+
+	// RGB with alpha, depth and a spot color channel at 16 bits.
+	let color_space = ColorType::from_channels(6, 16);
+	assert_eq!(color_space, ColorType::MultiChannel { channels: 6, bit_depth: 16 });
+	assert_eq!(color_space.bytes_per_pixel(), 12);
+	assert_eq!(ColorType::from_channels(5, 12).bytes_per_pixel(), 10);
+	assert_eq!(color_space.color_model(), "Multi");
+	assert!(!color_space.native_metrics_supported());
+
+	// The color type survives the results CSV.
+	assert_eq!(color_space.to_string(), "Multi6x16");
+	assert_eq!(ColorType::try_from(color_space.to_string().as_str()), Ok(color_space));
+	assert!(ColorType::try_from("Multi6").is_err());
+	assert!(ColorType::try_from("Cmyk8").is_err());
+}
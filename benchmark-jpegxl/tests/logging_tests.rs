@@ -0,0 +1,49 @@
+use benchmark_jpegxl::config::LogLevel;
+use benchmark_jpegxl::logging::{format_line, set_log_sink, set_worker_context};
+use log::{debug, error, info, warn, Level};
+
+mod common;
+use common::test_dir;
+
+#[test]
+fn test_quiet_log_level() {
+	// The logger is set for the whole process, so this is the only test in this file.
+	let dir = test_dir("logging");
+	let log_file = dir.join("benchmark.log");
+	set_log_sink(LogLevel::Quiet, Some(log_file.to_str().unwrap())).unwrap();
+
+	info!("Running JPEG-XL Compression benchmark...");
+	debug!("Encoding kodim06__d1__e7.jxl");
+	warn!("no master found for kodim06");
+	error!("Failed to execute cjxl on image kodim06");
+	// Messages of a worker are prefixed with the worker and image.
+	std::thread::spawn(|| {
+		set_worker_context(2, "kodim06");
+		info!("Encoding kodim06__d1__e7.jxl");
+		error!("Could not decode kodim06__d1__e7.jxl");
+	})
+	.join()
+	.unwrap();
+
+	// Informational lines are suppressed, warnings and errors are still written.
+	let log = std::fs::read_to_string(&log_file).unwrap();
+	assert_eq!(
+		log.lines().collect::<Vec<&str>>(),
+		vec![
+			"Warning: no master found for kodim06",
+			"Error: Failed to execute cjxl on image kodim06",
+			"[worker 2 kodim06] Error: Could not decode kodim06__d1__e7.jxl",
+		]
+	);
+
+	// Verbose also writes debug messages, appended to the same file.
+	set_log_sink(LogLevel::Verbose, Some(log_file.to_str().unwrap())).unwrap();
+	debug!("Encoding kodim06__d1__e7.jxl");
+	let log = std::fs::read_to_string(&log_file).unwrap();
+	assert_eq!(log.lines().last(), Some("Debug: Encoding kodim06__d1__e7.jxl"));
+
+	assert_eq!(format_line(Level::Info, "Wrote the report", None), "Wrote the report");
+	assert_eq!(format_line(Level::Warn, "skipping a.png", Some("worker 0 a")), "[worker 0 a] Warning: skipping a.png");
+
+	set_log_sink(LogLevel::Normal, None).unwrap();
+}
@@ -1,7 +1,14 @@
 use approx::relative_eq;
+use clap::ValueEnum;
 
+use benchmark_jpegxl::benchmark::JXLCompressionBenchmark;
+use benchmark_jpegxl::config::MetricValidation;
+use benchmark_jpegxl::csv_writer::ComparisonResult;
 use benchmark_jpegxl::metrics::*;
 
+mod common;
+use common::test_dir;
+
 const TEST_ORIG_IMAGES: [&str; 5] = [
 	"tests/images/orig/1.png",
 	"tests/images/orig/2.png",
@@ -104,3 +111,314 @@ fn test_calculate_ssim() {
 		relative_eq!(ssim, expected[i], epsilon = f64::EPSILON);
 	}
 }
+
+#[test]
+fn test_squared_error_sum_is_stable() {
+	// One large squared error followed by a million unit squared errors. Each unit error is below
+	// the precision of the large running sum, so a naive sum drops all of them.
+	let len = 1_000_001;
+	let orig = (0..len).map(|i| match i {
+		0 => 1e8f64,
+		_ => 1.0,
+	});
+	let comp = (0..len).map(|_| 0.0f64);
+
+	let mut naive = 0.0f64;
+	for (o, c) in orig.clone().zip(comp.clone()) {
+		naive += (o - c).powi(2);
+	}
+	let stable = squared_error_sum(orig, comp);
+
+	assert_eq!(stable, 1e16 + 1e6);
+	assert_eq!(naive, 1e16);
+}
+
+#[test]
+fn test_squared_error_sum_matches_naive() {
+	// Without large magnitude differences the stable sum agrees with the naive sum.
+	let orig = (0..300_000).map(|i| (i % 256) as f64).collect::<Vec<f64>>();
+	let comp = (0..300_000).map(|i| ((i * 7) % 256) as f64).collect::<Vec<f64>>();
+
+	let mut naive = 0.0f64;
+	for i in 0..orig.len() {
+		naive += (orig[i] - comp[i]).powi(2);
+	}
+	let stable = squared_error_sum(orig.iter().copied(), comp.iter().copied());
+
+	assert!(relative_eq!(stable, naive, max_relative = 1e-12));
+}
+
+#[test]
+fn test_error_histogram() {
+	// Every absolute error from 0 to 255 once per channel of a synthetic 256 pixel RGB image.
+	let orig = vec![0u8; 256 * 3];
+	let comp = (0..256 * 3).map(|i| (i / 3) as u8).collect::<Vec<u8>>();
+	let histogram = error_histogram(&orig, &comp);
+
+	// The bins cover every pixel and channel exactly once.
+	assert_eq!(histogram.iter().sum::<u64>(), (256 * 3) as u64);
+	assert_eq!(histogram.len(), ERROR_HISTOGRAM_BINS.len());
+	assert_eq!(histogram, [3, 6, 6, 12, 24, 48, 96, 573]);
+}
+
+#[test]
+fn test_channel_psnr() {
+	let (width, height, orig) = benchmark_jpegxl::image_reader::ImageReader::decode_rgb8("tests/images/pnm/gradient8.ppm").unwrap();
+
+	// Change the blue channel of every pixel by 16 in either direction.
+	let comp = orig
+		.iter()
+		.enumerate()
+		.map(|(i, sample)| match i % 3 {
+			2 => sample ^ 16,
+			_ => *sample,
+		})
+		.collect::<Vec<u8>>();
+	let psnr = |mse: f64| 10.0 * (255.0f64 * 255.0 / mse).log10();
+	let lossless = benchmark_jpegxl::image_reader::ImageReader::LOSSLESS_PSNR;
+
+	// Only the blue channel is degraded in RGB.
+	let [r, g, b] = channel_psnr(&orig, &comp, ChannelSpace::Rgb);
+	assert_eq!((r, g), (lossless, lossless));
+	assert!(relative_eq!(b, psnr(256.0), max_relative = 1e-12));
+
+	// The overall PSNR is the PSNR of the mean of the channel MSEs.
+	let overall = compare_pixels(&orig, &comp, width, height, &[MetricKind::Mse, MetricKind::Psnr], &SsimWindow::DEFAULT);
+	let mean_mse = [psnr(256.0), f64::INFINITY, f64::INFINITY]
+		.iter()
+		.map(|psnr| 255.0 * 255.0 / 10f64.powf(psnr / 10.0))
+		.sum::<f64>()
+		/ 3.0;
+	assert!(relative_eq!(overall.mse.unwrap(), mean_mse, max_relative = 1e-12));
+	assert!(relative_eq!(overall.psnr.unwrap(), psnr(mean_mse), max_relative = 1e-12));
+
+	// In BT.601 YCbCr a blue error of 16 moves Y by 0.114 * 16, Cb by 8 and Cr by 0.114 * 16 / 1.402.
+	let [y, cb, cr] = channel_psnr(&orig, &comp, ChannelSpace::YCbCrBt601);
+	assert!(relative_eq!(y, psnr((0.114f64 * 16.0).powi(2)), max_relative = 1e-9));
+	assert!(relative_eq!(cb, psnr(64.0), max_relative = 1e-9));
+	assert!(relative_eq!(cr, psnr((0.114f64 * 16.0 / 1.402).powi(2)), max_relative = 1e-9));
+
+	// BT.709 weighs blue less in luma.
+	let [y_709, _, _] = channel_psnr(&orig, &comp, ChannelSpace::YCbCrBt709);
+	assert!(y_709 > y);
+
+	// The channel spaces are parsed by the names stored in the results, unknown names are errors.
+	for space in [ChannelSpace::Rgb, ChannelSpace::YCbCrBt601, ChannelSpace::YCbCrBt709] {
+		assert_eq!(ChannelSpace::from_str(space.name(), true), Ok(space));
+	}
+	assert_eq!(ChannelSpace::from_str("YCbCr", true), Ok(ChannelSpace::YCbCrBt601));
+	assert!(ChannelSpace::from_str("xyz", true).is_err());
+}
+
+#[test]
+fn test_decode_time_stats() {
+	// The fastest speed of a 0.4 MP image is the min time, the geomean speed the mean time.
+	let output = "Read 1234 compressed bytes.\n800 x 500, geomean: 40.00 MP/s [32.00, 50.00], geomean: 1.23 MB/s [0.99, 1.54], 5 reps, 8 threads.\n";
+	let (min, mean) = decode_time_stats(output).unwrap();
+	assert!(relative_eq!(min, 8.0));
+	assert!(relative_eq!(mean, 10.0));
+	assert!(min <= mean);
+
+	// A single decode has no spread.
+	let (min, mean) = decode_time_stats("800 x 500, 40.00 MP/s [40.00, 40.00], 1 reps, 1 threads.").unwrap();
+	assert!(relative_eq!(min, mean));
+
+	// Output without a speed has no times.
+	assert!(decode_time_stats("Decoded to pixels.\n").is_none());
+	assert!(decode_time_stats("").is_none());
+}
+
+#[test]
+fn test_list_metrics_describes_every_metric() {
+	let listing = list_metrics();
+	let lines = listing.lines().collect::<Vec<&str>>();
+	assert!(lines[0].starts_with("Metric"));
+	assert_eq!(lines.len(), MetricKind::ALL.len() + 1);
+
+	// Every registered metric has a line with its backend, range and direction.
+	for metric in MetricKind::ALL {
+		let line = lines
+			.iter()
+			.find(|line| line.split("  ").next() == Some(metric.name()))
+			.unwrap_or_else(|| panic!("{} is not listed", metric.name()));
+		assert!(line.contains(metric.backend()));
+		assert!(line.contains(metric.typical_range()));
+		let direction = if metric.higher_is_better() { "higher" } else { "lower" };
+		assert!(line.ends_with(direction));
+	}
+	assert!(lines.iter().any(|line| line.starts_with("Butteraugli") && line.contains("Docker")));
+}
+
+#[test]
+fn test_ssim_window_configurations_differ() {
+	use benchmark_jpegxl::image_reader::ImageReader;
+
+	let (width, height, orig) = ImageReader::decode_rgb8(TEST_ORIG_IMAGES[0]).unwrap();
+	let (_, _, comp) = ImageReader::decode_rgb8(TEST_COMP_IMAGES[0]).unwrap();
+	let ssim = |window: &SsimWindow| {
+		compare_pixels(&orig, &comp, width, height, &[MetricKind::Ssim, MetricKind::MsSsim], window)
+	};
+
+	// An 11x11 Gaussian and an 8x8 uniform window weigh the same errors differently.
+	let gaussian = ssim(&SsimWindow::new(11, Some(1.5)).unwrap());
+	let uniform = ssim(&SsimWindow::new(8, None).unwrap());
+	for value in [gaussian.ssim, uniform.ssim, gaussian.ms_ssim, uniform.ms_ssim] {
+		let value = value.unwrap();
+		assert!((0.0..=1.0).contains(&value), "{} is not in [0, 1]", value);
+	}
+	assert_ne!(gaussian.ssim.unwrap(), uniform.ssim.unwrap());
+	assert_eq!(gaussian.ssim, ssim(&SsimWindow::DEFAULT).ssim);
+	assert_eq!(SsimWindow::DEFAULT.to_string(), "11x11 Gaussian (sigma 1.5)");
+	assert_eq!(SsimWindow::new(8, None).unwrap().to_string(), "8x8 uniform");
+
+	// Gaussian windows need an odd size and a positive sigma.
+	assert!(SsimWindow::new(8, Some(1.5)).is_err());
+	assert!(SsimWindow::new(11, Some(0.0)).is_err());
+	assert!(SsimWindow::new(11, Some(-1.0)).is_err());
+	assert!(SsimWindow::new(0, None).is_err());
+}
+
+#[test]
+fn test_images_too_small_for_a_metric() {
+	let dir = test_dir("small_images");
+	let small = dir.join("small.png");
+	image::RgbImage::from_pixel(8, 8, image::Rgb([128, 128, 128])).save(&small).unwrap();
+
+	// An 8x8 image fits no 11x11 window, so MS-SSIM is rejected while MSE is computed.
+	let window = SsimWindow::DEFAULT;
+	assert_eq!(MetricKind::MsSsim.min_image_size(&window), 11);
+	assert_eq!(MetricKind::Mse.min_image_size(&window), 1);
+	let result = compare_images(&small, &small, &[MetricKind::Mse, MetricKind::MsSsim]);
+	assert!(matches!(result, Err(MetricError::TooSmall(MetricKind::MsSsim, (8, 8), 11))));
+	let path = small.to_str().unwrap().to_string();
+	assert!(calculate_ms_ssim(&path, &path, &window).is_err());
+	assert_eq!(compare_images(&small, &small, &[MetricKind::Mse]).unwrap().mse, Some(0.0));
+
+	// Pixel buffers leave out the metrics they are too small for instead of dividing by zero.
+	let metric_set = compare_pixels(&[], &[], 0, 0, &MetricKind::ALL, &window);
+	assert_eq!(metric_set, MetricSet::default());
+	let pixels = [128; 8 * 8 * 3];
+	let metric_set = compare_pixels(&pixels, &pixels, 8, 8, &MetricKind::ALL, &window);
+	assert_eq!((metric_set.mse, metric_set.ms_ssim), (Some(0.0), None));
+}
+
+#[test]
+fn test_ssim_window_applied_metrics() {
+	// The SSIM column only uses the window when it is computed natively on color managed images.
+	assert_eq!(SsimWindow::applied_metrics(false), vec![MetricKind::MsSsim]);
+	assert_eq!(SsimWindow::applied_metrics(true), vec![MetricKind::Ssim, MetricKind::MsSsim]);
+}
+
+#[test]
+fn test_parse_ssimulacra2_output() {
+	// The bare score of current builds.
+	assert_eq!(parse_ssimulacra2_output("85.12345678\n"), Ok(85.12345678));
+	// A label before the score, in the formats of older and newer builds.
+	assert_eq!(parse_ssimulacra2_output("SSIMULACRA2 score: 72.5\n"), Ok(72.5));
+	assert_eq!(parse_ssimulacra2_output("ssimulacra2 = -12.25"), Ok(-12.25));
+	assert_eq!(parse_ssimulacra2_output("SSIMULACRA 2: 90"), Ok(90.0));
+	// Warnings printed before the score.
+	assert_eq!(parse_ssimulacra2_output("libpng warning: iCCP: known incorrect sRGB profile\n64.75\n"), Ok(64.75));
+
+	// Output without a score is an error with the raw output.
+	let error = parse_ssimulacra2_output("Usage: ssimulacra2 orig.png distorted.png\n").unwrap_err();
+	assert_eq!(
+		error,
+		MetricError::UnparsableOutput("SSIMULACRA2".to_string(), "Usage: ssimulacra2 orig.png distorted.png\n".to_string())
+	);
+	assert!(error.to_string().contains("Usage: ssimulacra2"), "{}", error);
+	assert!(parse_ssimulacra2_output("").is_err());
+}
+
+#[test]
+fn test_parse_butteraugli_output() {
+	// The distance and the p-norm, also after warnings.
+	assert_eq!(parse_butteraugli_output("0.5\n3-norm: 0.25\n").unwrap(), (0.5, 0.25));
+	assert_eq!(
+		parse_butteraugli_output("libpng warning: iCCP: known incorrect sRGB profile\n1.75\n3-norm: 0.8125\n").unwrap(),
+		(1.75, 0.8125)
+	);
+
+	// A missing p-norm is NaN, a missing distance is an error.
+	assert!(parse_butteraugli_output("2.5\n").unwrap().1.is_nan());
+	assert!(parse_butteraugli_output("Usage: butteraugli_main orig.png distorted.png\n").is_err());
+}
+
+#[test]
+fn test_parallel_metrics_match_serial() {
+	use benchmark_jpegxl::image_reader::ImageReader;
+
+	let metrics = [MetricKind::Mse, MetricKind::Psnr, MetricKind::Ssim, MetricKind::MsSsim, MetricKind::Butteraugli];
+	for (orig, comp) in TEST_ORIG_IMAGES.iter().zip(TEST_COMP_IMAGES).take(2) {
+		let (width, height, orig) = ImageReader::decode_rgb8(orig).unwrap();
+		let (_, _, comp) = ImageReader::decode_rgb8(comp).unwrap();
+		let serial = compare_pixels(&orig, &comp, width, height, &metrics, &SsimWindow::DEFAULT);
+		for threads in [1, 2, 8] {
+			let parallel = compare_pixels_parallel(&orig, &comp, width, height, &metrics, &SsimWindow::DEFAULT, threads);
+			for (serial, parallel) in [
+				(serial.mse, parallel.mse),
+				(serial.psnr, parallel.psnr),
+				(serial.ssim, parallel.ssim),
+				(serial.ms_ssim, parallel.ms_ssim),
+			] {
+				assert!(relative_eq!(serial.unwrap(), parallel.unwrap(), epsilon = 1e-12));
+			}
+		}
+	}
+
+	// Jobs of different kinds each write their own value, whatever the number of threads.
+	for threads in [1, 3] {
+		let mut values = [0.0; 5];
+		let mut name = String::new();
+		let mut jobs = values
+			.iter_mut()
+			.enumerate()
+			.map(|(i, value)| -> Box<dyn FnOnce() + Send + '_> { Box::new(move || *value = i as f64 * 2.0) })
+			.collect::<Vec<_>>();
+		jobs.push(Box::new(|| name = "ssim".to_string()));
+		run_metric_jobs(jobs, threads);
+		assert_eq!(values, [0.0, 2.0, 4.0, 6.0, 8.0]);
+		assert_eq!(name, "ssim");
+	}
+}
+
+#[test]
+fn test_implausible_metrics() {
+	// A SSIMULACRA2 score above 100 is impossible, e.g. when the wrong number of the output is
+	// parsed.
+	let ssimulacra2 = parse_ssimulacra2_output("SSIMULACRA2 score: 150\n").unwrap();
+	assert!(MetricKind::Ssimulacra2.check_plausible(ssimulacra2).is_err());
+	assert!(MetricKind::Ssimulacra2.check_plausible(-40.0).is_ok());
+
+	// The bounds of the other metrics, with some tolerance for rounding. Identical images have
+	// an infinite PSNR, and metrics that were not computed are not checked.
+	assert!(MetricKind::Ssim.check_plausible(1.0 + 1e-9).is_ok());
+	assert!(MetricKind::Ssim.check_plausible(1.5).is_err());
+	assert!(MetricKind::MsSsim.check_plausible(-1.5).is_err());
+	assert!(MetricKind::Psnr.check_plausible(f64::INFINITY).is_ok());
+	assert!(MetricKind::Psnr.check_plausible(-3.0).is_err());
+	assert!(MetricKind::Mse.check_plausible(-1.0).is_err());
+	assert!(MetricKind::Butteraugli.check_plausible(-0.5).is_err());
+	assert!(MetricKind::ALL.iter().all(|metric| metric.check_plausible(f64::NAN).is_ok()));
+
+	let mut result = ComparisonResult::encode_failed("gradient.png", "gradient_d1_e7.jxl", 1.0, 7);
+	result.status = ComparisonResult::STATUS_OK.to_string();
+	(result.mse, result.psnr, result.ssim, result.ms_ssim) = (10.0, 38.1, 0.95, 0.97);
+	(result.butteraugli, result.ssimulacra2) = (1.2, ssimulacra2);
+
+	// Without validation nothing is checked, warnings keep the result and strict validation marks
+	// it as failed.
+	assert!(JXLCompressionBenchmark::validate_metrics(&mut result, MetricValidation::Off).is_empty());
+	let implausible = JXLCompressionBenchmark::validate_metrics(&mut result, MetricValidation::Warn);
+	assert_eq!(implausible, vec!["SSIMULACRA2 of 150 is outside its plausible range -inf to 100"]);
+	assert!(result.is_ok());
+	assert_eq!(JXLCompressionBenchmark::validate_metrics(&mut result, MetricValidation::Strict), implausible);
+	assert_eq!(result.status, ComparisonResult::STATUS_IMPLAUSIBLE_METRICS);
+	assert!(!result.is_ok());
+
+	// Plausible metrics pass in strict validation.
+	result.status = ComparisonResult::STATUS_OK.to_string();
+	result.ssimulacra2 = 85.0;
+	assert!(JXLCompressionBenchmark::validate_metrics(&mut result, MetricValidation::Strict).is_empty());
+	assert!(result.is_ok());
+}
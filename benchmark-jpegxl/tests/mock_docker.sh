@@ -0,0 +1,133 @@
+#!/bin/sh
+# A mock of the docker commands used by DockerManager, for tests without Docker installed.
+# The files of the container live in the `container` directory next to the script, every
+# invocation is appended to `docker.log` next to the script, and the subcommand named in a
+# `fail` file next to the script exits with an error instead of running. The state of the
+# container is read from a `state` file next to the script, running if there is none, or from a
+# `state.<container>` file for a single container.
+# A `cjxl.jxl` file next to the script is written as every encoded file, so that a whole run
+# can be decoded and compared. With a `djxl` directory next to the script, djxl writes the
+# `<size of the input in bytes>.png` file of the directory as the decoded image. A `cjxl_help`
+# file next to the script is printed as the help text of cjxl. With a `block_cjxl` file holding a number of encodes, the encodes after that many write a `blocked`
+# file and wait for a `resume` file before encoding.
+dir="$(cd "$(dirname "$0")" && pwd)"
+root="$dir/container"
+echo "$@" >> "$dir/docker.log"
+
+if [ -f "$dir/fail" ] && [ "$(cat "$dir/fail")" = "$1" ]; then
+  echo "mock docker: $1 failed" >&2
+  exit 1
+fi
+
+# Maps a path in the container to the mock container directory, relative to /temp.
+container_path() {
+  case "$1" in
+    /*) echo "$root$1" ;;
+    *) echo "$root/temp/$1" ;;
+  esac
+}
+
+case "$1" in
+  --version)
+    echo "Docker version 0.0.0, build mock"
+    ;;
+  inspect)
+    eval "container=\${$#}"
+    if [ -f "$dir/state.$container" ]; then cat "$dir/state.$container"
+    elif [ -f "$dir/state" ]; then cat "$dir/state"
+    else echo running; fi
+    ;;
+  run)
+    echo running > "$dir/state"
+    ;;
+  cp)
+    case "$2" in
+      *:*)
+        src="$(container_path "${2#*:}")"
+        [ -f "$src" ] || { echo "Error: No such container:path: $2" >&2; exit 1; }
+        if [ "$3" = "-" ]; then
+          tar -C "$(dirname "$src")" -cf - "$(basename "$src")"
+        else
+          cp "$src" "$3"
+        fi
+        ;;
+      *)
+        dest="$(container_path "${3#*:}")"
+        mkdir -p "$(dirname "$dest")"
+        cp "$2" "$dest" || exit 1
+        ;;
+    esac
+    ;;
+  exec)
+    shift
+    [ "$1" = "-w" ] && shift 2
+    shift
+    case "$1" in
+      mkdir)
+        shift
+        for arg in "$@"; do
+          [ "$arg" = "-p" ] || mkdir -p "$(container_path "$arg")"
+        done
+        ;;
+      */cjxl)
+        if [ "$2" = "--version" ]; then
+          echo "JPEG XL encoder v0.0.0 mock"
+          exit 0
+        fi
+        if [ "$2" = "--help" ] && [ -f "$dir/cjxl_help" ]; then
+          cat "$dir/cjxl_help"
+          exit 0
+        fi
+        if [ -f "$dir/block_cjxl" ] && [ "$2" != "--help" ]; then
+          encodes=$(( $(cat "$dir/encodes" 2>/dev/null || echo 0) + 1 ))
+          echo "$encodes" > "$dir/encodes"
+          if [ "$encodes" -gt "$(cat "$dir/block_cjxl")" ]; then
+            touch "$dir/blocked"
+            while [ ! -f "$dir/resume" ]; do sleep 0.05; done
+          fi
+        fi
+        # Write the arguments as the encoded file, so tests can check what was passed.
+        out="$(container_path "$3")"
+        mkdir -p "$(dirname "$out")"
+        echo "Compressed to $3"
+        shift
+        if [ -f "$dir/cjxl.jxl" ]; then
+          cp "$dir/cjxl.jxl" "$out"
+        else
+          echo "$@" > "$out"
+        fi
+        ;;
+      */butteraugli_main)
+        # The runner reads the scores of butteraugli_main from the output of a failed command.
+        printf '0.5\n3-norm: 0.25\n'
+        exit 1
+        ;;
+      */ssimulacra2)
+        echo "90.0"
+        ;;
+      bash)
+        # Scripts resolving a libjxl reference print the SHA in a `rev_parse` file next to the
+        # script, if there is one.
+        case "$3" in
+          *rev-parse*) [ -f "$dir/rev_parse" ] && cat "$dir/rev_parse" ;;
+          *) echo "$@" ;;
+        esac
+        ;;
+      */djxl)
+        if [ "$3" = "--num_reps" ]; then
+          # Report the speed of the decodes on stderr as djxl does.
+          echo "768 x 512, geomean: 39.32 MP/s [37.45, 40.96], $4 reps, 1 threads." >&2
+        elif [ -d "$dir/djxl" ]; then
+          size="$(wc -c < "$(container_path "$2")" | tr -d ' ')"
+          cp "$dir/djxl/$size.png" "$(container_path "$3")" || exit 1
+        else
+          echo "$@"
+        fi
+        ;;
+      *)
+        echo "$@"
+        ;;
+    esac
+    ;;
+esac
+exit 0
@@ -0,0 +1,389 @@
+use std::path::PathBuf;
+
+use benchmark_jpegxl::benchmark::{BenchmarkWorker, Benchmarker};
+use benchmark_jpegxl::config::{LocalDiff, PrebuiltCjxl};
+use benchmark_jpegxl::docker_manager::DockerManager;
+use benchmark_jpegxl::manifest::RunManifest;
+
+mod common;
+use common::{mock_docker, test_dir};
+
+/// Creates a DockerManager running the mock docker script in its own directory, which holds the
+/// files of the container, the log of the invocations and the subcommand to fail, if any.
+fn mock_manager(dir: &PathBuf) -> DockerManager {
+	let mut docker_manager = DockerManager::new("Dockerfile", 0);
+	docker_manager.docker_bin = mock_docker(dir);
+	docker_manager
+}
+
+/// Writes a file into the mock container.
+fn write_container_file(dir: &PathBuf, path: &str, contents: &[u8]) {
+	let file = dir.join("container").join(path.trim_start_matches('/'));
+	std::fs::create_dir_all(file.parent().unwrap()).unwrap();
+	std::fs::write(file, contents).unwrap();
+}
+
+#[test]
+fn test_default_docker_bin() {
+	assert_eq!(DockerManager::new("Dockerfile", 0).docker_bin, DockerManager::DEFAULT_DOCKER_BIN);
+}
+
+#[test]
+fn test_retrieve_file_with_mock_docker() {
+	let dir = test_dir("mock_docker_retrieve");
+	let docker_manager = mock_manager(&dir);
+	write_container_file(&dir, "/temp/kodim01__d1__e7.jxl", b"compressed");
+
+	// Files are copied out of the container to disk or into memory.
+	let dest = dir.join("kodim01__d1__e7.jxl");
+	docker_manager
+		.retrieve_file("/temp/kodim01__d1__e7.jxl".to_string(), dest.to_str().unwrap().to_string())
+		.unwrap();
+	assert_eq!(std::fs::read(&dest).unwrap(), b"compressed");
+	let bytes = docker_manager.retrieve_file_bytes("/temp/kodim01__d1__e7.jxl".to_string()).unwrap();
+	assert_eq!(bytes, b"compressed");
+
+	// Files are copied into the container.
+	let src = dir.join("kodim02.png");
+	std::fs::write(&src, b"original").unwrap();
+	docker_manager
+		.copy_file_to_container(src.to_str().unwrap().to_string(), "/temp/orig/kodim02.png".to_string())
+		.unwrap();
+	assert_eq!(std::fs::read(dir.join("container/temp/orig/kodim02.png")).unwrap(), b"original");
+}
+
+#[test]
+fn test_execute_cjxl_with_mock_docker() {
+	let dir = test_dir("mock_docker_cjxl");
+	let docker_manager = mock_manager(&dir);
+
+	let output = docker_manager
+		.execute_cjxl(
+			"/test_images/kodak/kodim01.png".to_string(),
+			"kodak/kodim01__d1__e7.jxl".to_string(),
+			1.0,
+			None,
+			7,
+			Some(false),
+			&["--photon_noise_iso=3200".to_string()],
+		)
+		.unwrap();
+	assert!(output.unwrap().contains("kodim01__d1__e7.jxl"));
+
+	// The output directory is created and cjxl runs in the container with the built arguments.
+	let log = std::fs::read_to_string(dir.join("docker.log")).unwrap();
+	let container_name = docker_manager.container_name.clone().unwrap();
+	assert!(log.contains(&format!("exec -w /temp {} mkdir -p kodak", container_name)));
+	let args = DockerManager::cjxl_args(
+		"/test_images/kodak/kodim01.png",
+		"kodak/kodim01__d1__e7.jxl",
+		1.0,
+		None,
+		7,
+		Some(false),
+		&["--photon_noise_iso=3200".to_string()],
+	);
+	let encoded = std::fs::read_to_string(dir.join("container/temp/kodak/kodim01__d1__e7.jxl")).unwrap();
+	assert_eq!(encoded.trim(), args.join(" "));
+}
+
+#[test]
+fn test_errors_propagate_from_mock_docker() {
+	let dir = test_dir("mock_docker_errors");
+	let docker_manager = mock_manager(&dir);
+
+	// A missing file in the container is an error with the stderr of docker.
+	let error = docker_manager
+		.retrieve_file("/temp/missing.jxl".to_string(), dir.join("missing.jxl").to_str().unwrap().to_string())
+		.unwrap_err();
+	assert!(error.to_string().contains("No such container:path"));
+	assert!(docker_manager.retrieve_file_bytes("/temp/missing.jxl".to_string()).is_err());
+
+	// A failing command in the container is returned as its stderr.
+	std::fs::write(dir.join("fail"), "exec").unwrap();
+	let output = docker_manager.execute_in_container("ls", vec!["/temp"]).unwrap();
+	assert_eq!(output, Err("mock docker: exec failed\n".to_string()));
+
+	// Failing docker commands are errors.
+	std::fs::write(dir.join("fail"), "stop").unwrap();
+	let error = docker_manager.teardown().unwrap_err();
+	assert!(error.to_string().contains("mock docker: stop failed"));
+}
+
+#[test]
+fn test_time_djxl_with_mock_docker() {
+	let dir = test_dir("mock_docker_time_djxl");
+	let docker_manager = mock_manager(&dir);
+
+	// The warm-up decodes run in their own djxl invocation before the timed decodes, neither of
+	// which writes the decoded image.
+	let (min, mean) = docker_manager.time_djxl("kodim01__d1__e7.jxl", 2, 5).unwrap();
+	let log = std::fs::read_to_string(dir.join("docker.log")).unwrap();
+	let decodes = log.lines().filter(|line| line.contains("/djxl ")).collect::<Vec<&str>>();
+	assert_eq!(decodes.len(), 2);
+	assert!(decodes[0].ends_with("kodim01__d1__e7.jxl --num_reps 2 --disable_output"));
+	assert!(decodes[1].ends_with("kodim01__d1__e7.jxl --num_reps 5 --disable_output"));
+
+	// The times come from the speed djxl reports for the timed decodes.
+	assert!(min > 0.0);
+	assert!(min <= mean);
+
+	// A failing decode is an error instead of a time.
+	std::fs::write(dir.join("fail"), "exec").unwrap();
+	assert!(docker_manager.time_djxl("kodim01__d1__e7.jxl", 0, 5).is_err());
+}
+
+#[test]
+fn test_shell_quote_paths() {
+	// An image whose path has spaces, a single quote and non-ASCII characters.
+	let dir = test_dir("shell_quote");
+	let input = dir.join("it's a tëst 日本__d1__e7.jxl");
+	std::fs::write(&input, b"").unwrap();
+	assert_eq!(DockerManager::shell_quote("it's"), "'it'\\''s'");
+
+	// `ls` only succeeds if it is passed the path as a single argument.
+	let script = format!("ls {}", DockerManager::shell_quote(input.to_str().unwrap()));
+	let output = std::process::Command::new("bash").arg("-c").arg(&script).output().unwrap();
+	assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+}
+
+#[test]
+fn test_local_diffs_with_mock_docker() {
+	let dir = test_dir("mock_docker_diffs");
+	let docker_manager = mock_manager(&dir);
+	let diff_a = dir.join("faster-dct.diff");
+	let diff_b = dir.join("patch-b.diff");
+	std::fs::write(&diff_a, "diff a").unwrap();
+	std::fs::write(&diff_b, "diff b").unwrap();
+
+	// Diffs are labelled by their name, or by their file stem without one.
+	let diffs = [
+		diff_a.to_str().unwrap().parse::<LocalDiff>().unwrap(),
+		format!("tweak={}", diff_b.to_str().unwrap()).parse::<LocalDiff>().unwrap(),
+	];
+	assert_eq!(diffs[0].name, "faster-dct");
+	assert_eq!(diffs[1].name, "tweak");
+	assert_eq!(diffs[1].path, diff_b.to_str().unwrap());
+	assert!("=faster-dct.diff".parse::<LocalDiff>().is_err());
+	assert!("tweak=".parse::<LocalDiff>().is_err());
+	assert_eq!(LocalDiff::next(&diffs, "faster-dct"), Some("tweak".to_string()));
+	assert_eq!(LocalDiff::next(&diffs, "tweak"), None);
+
+	// Both diffs are copied into the container under their labels and applied on the base commit.
+	for diff in &diffs {
+		docker_manager.apply_local_diff(&diff.path, &diff.name, "abc123").unwrap();
+	}
+	assert_eq!(std::fs::read_to_string(dir.join("container/libjxl/faster-dct.diff")).unwrap(), "diff a");
+	assert_eq!(std::fs::read_to_string(dir.join("container/libjxl/tweak.diff")).unwrap(), "diff b");
+	let log = std::fs::read_to_string(dir.join("docker.log")).unwrap();
+	let applies = log.lines().filter(|line| line.contains("git apply")).collect::<Vec<&str>>();
+	assert_eq!(applies.len(), 2);
+	assert!(applies[0].contains("git checkout 'abc123' && git apply 'faster-dct.diff'"));
+	assert!(applies[1].contains("git checkout 'abc123' && git apply 'tweak.diff'"));
+
+	// A diff that cannot be copied into the container is an error.
+	assert!(docker_manager.apply_local_diff(dir.join("missing.diff").to_str().unwrap(), "missing", "abc123").is_err());
+}
+
+#[test]
+fn test_resolve_libjxl_ref_with_mock_docker() {
+	let dir = test_dir("mock_docker_resolve_ref");
+	let docker_manager = mock_manager(&dir);
+	let sha = "0123456789abcdef0123456789abcdef01234567";
+	std::fs::write(dir.join("rev_parse"), format!("{}\n", sha.to_uppercase())).unwrap();
+
+	// The branch is resolved to the SHA reported by rev-parse.
+	assert_eq!(docker_manager.resolve_libjxl_ref("main").unwrap(), sha);
+	let log = std::fs::read_to_string(dir.join("docker.log")).unwrap();
+	assert!(log.contains("git rev-parse --verify --quiet 'origin/main^{commit}'"), "{}", log);
+
+	// References are passed to the shell literally, and option-like references are rejected
+	// before anything is run.
+	docker_manager.resolve_libjxl_ref("main; touch /pwned").unwrap();
+	docker_manager.change_libjxl_commit("main; touch /pwned").unwrap();
+	let log = std::fs::read_to_string(dir.join("docker.log")).unwrap();
+	assert!(log.contains("'origin/main; touch /pwned^{commit}'"), "{}", log);
+	assert!(log.contains("git checkout 'main; touch /pwned' &&"), "{}", log);
+	let invocations = log.lines().count();
+	assert!(docker_manager.resolve_libjxl_ref("--output=/tmp/x").is_err());
+	assert!(docker_manager.change_libjxl_commit("-b").is_err());
+	assert!(docker_manager.apply_local_diff("missing.diff", "diff", "").is_err());
+	let log = std::fs::read_to_string(dir.join("docker.log")).unwrap();
+	assert_eq!(log.lines().count(), invocations, "{}", log);
+
+	// Output that is not a SHA is an error instead of a recorded commit.
+	std::fs::write(dir.join("rev_parse"), "main\n").unwrap();
+	assert!(docker_manager.resolve_libjxl_ref("main").is_err());
+}
+
+#[test]
+fn test_local_diff_with_mock_docker() {
+	let dir = test_dir("mock_docker_local_diff");
+	let docker_manager = mock_manager(&dir);
+	let diff = dir.join("local.diff");
+	let diff_path = diff.to_str().unwrap();
+
+	// A missing diff is an error instead of silently benchmarking the base commit.
+	let error = docker_manager.apply_local_as_diff(diff_path).unwrap_err();
+	assert_eq!(error.to_string(), format!("The local diff {} does not exist", diff_path));
+	assert!(!std::fs::read_to_string(dir.join("docker.log")).unwrap_or_default().contains("git apply"));
+
+	// The diff is copied into the container and applied.
+	std::fs::write(&diff, "diff local").unwrap();
+	docker_manager.apply_local_as_diff(diff_path).unwrap();
+	assert_eq!(std::fs::read_to_string(dir.join("container/libjxl/local.diff")).unwrap(), "diff local");
+	let log = std::fs::read_to_string(dir.join("docker.log")).unwrap();
+	assert!(log.contains("cd /libjxl && git apply 'local.diff'"), "{}", log);
+
+	// A diff that does not apply is an error.
+	std::fs::write(dir.join("fail"), "exec").unwrap();
+	let error = docker_manager.apply_local_as_diff(diff_path).unwrap_err();
+	assert!(error.to_string().starts_with(&format!("Failed to apply {}", diff_path)), "{}", error);
+}
+
+#[test]
+fn test_prebuilt_cjxl_skips_the_build_with_mock_docker() {
+	let dir = test_dir("mock_docker_prebuilt");
+	let docker_manager = mock_manager(&dir);
+	let binary_dir = dir.join("prebuilt");
+	std::fs::create_dir_all(&binary_dir).unwrap();
+	std::fs::write(binary_dir.join("cjxl"), "prebuilt cjxl").unwrap();
+	std::fs::write(binary_dir.join("djxl"), "prebuilt djxl").unwrap();
+	let prebuilt_cjxl = PrebuiltCjxl {
+		version: "v0.10.2".to_string(),
+		binary_dir: Some(binary_dir.to_str().unwrap().to_string()),
+	};
+	assert!(prebuilt_cjxl.validate().is_ok());
+	assert!(PrebuiltCjxl { version: "v1; rm -rf /".to_string(), binary_dir: None }.validate().is_err());
+
+	// The prebuilt binaries replace the built tools, without a checkout or build of libjxl.
+	Benchmarker::prepare_libjxl(&docker_manager, "v0.10.2", &[], "v0.10.2", Some(&prebuilt_cjxl)).unwrap();
+	assert_eq!(std::fs::read_to_string(dir.join("container/libjxl/build/tools/cjxl")).unwrap(), "prebuilt cjxl");
+	assert_eq!(std::fs::read_to_string(dir.join("container/libjxl/build/tools/djxl")).unwrap(), "prebuilt djxl");
+	let log = std::fs::read_to_string(dir.join("docker.log")).unwrap();
+	assert!(!log.contains("ci.sh"), "{}", log);
+	assert!(!log.contains("git "), "{}", log);
+
+	// Another commit of the same run is still checked out and built.
+	Benchmarker::prepare_libjxl(&docker_manager, "abc123", &[], "v0.10.2", Some(&prebuilt_cjxl)).unwrap();
+	let log = std::fs::read_to_string(dir.join("docker.log")).unwrap();
+	assert!(log.contains("git checkout 'abc123'"), "{}", log);
+	assert!(log.contains("ci.sh"), "{}", log);
+
+	// Without a binary directory, the release is downloaded in the container.
+	let script = DockerManager::prebuilt_install_script("v0.10.2");
+	assert!(script.contains(
+		"'https://github.com/libjxl/libjxl/releases/download/v0.10.2/jxl-linux-x86_64-static-v0.10.2.tar.gz'"
+	));
+	assert!(script.contains("/libjxl/build/tools/"));
+}
+
+#[test]
+fn test_dead_container_is_restarted_with_mock_docker() {
+	let dir = test_dir("mock_docker_restart");
+	let mut worker = BenchmarkWorker {
+		id: 0,
+		docker_manager: Some(mock_manager(&dir)),
+		toolchain_managers: Vec::new(),
+		thread_handle: None,
+		payload: None,
+		working: false,
+		restarts: 0,
+		retired: false,
+	};
+
+	// A running container is left alone.
+	assert!(!worker.ensure_container_running(None).unwrap());
+	assert_eq!(worker.restarts, 0);
+
+	// A container that exited is removed and set up again before the next job.
+	std::fs::write(dir.join("state"), "exited\n").unwrap();
+	std::fs::remove_file(dir.join("docker.log")).unwrap();
+	assert!(worker.ensure_container_running(None).unwrap());
+	assert_eq!(worker.restarts, 1);
+	let container_name = worker.docker_manager.as_ref().unwrap().container_name.clone().unwrap();
+	let log = std::fs::read_to_string(dir.join("docker.log")).unwrap();
+	let commands = log.lines().map(|line| line.split_whitespace().next().unwrap()).collect::<Vec<&str>>();
+	assert_eq!(commands, vec!["inspect", "rm", "build", "run"]);
+	assert!(log.contains(&format!("rm -f {}", container_name)));
+	assert_eq!(worker.docker_manager.as_ref().unwrap().container_state().unwrap(), "running");
+
+	// The restarted container is healthy for the following jobs.
+	assert!(!worker.ensure_container_running(None).unwrap());
+	assert_eq!(worker.restarts, 1);
+
+	// A container that cannot be set up again is an error.
+	std::fs::write(dir.join("state"), "exited\n").unwrap();
+	std::fs::write(dir.join("fail"), "build").unwrap();
+	assert!(worker.ensure_container_running(None).is_err());
+	assert_eq!(worker.restarts, 1);
+
+	// The idle container of another toolchain of the worker is restarted too.
+	std::fs::remove_file(dir.join("fail")).unwrap();
+	std::fs::remove_file(dir.join("state")).unwrap();
+	let toolchain_dir = test_dir("mock_docker_restart_toolchain");
+	let mut toolchain_manager = mock_manager(&toolchain_dir);
+	toolchain_manager.toolchain = Some("clang".to_string());
+	worker.toolchain_managers.push(toolchain_manager);
+	std::fs::write(toolchain_dir.join("state"), "exited\n").unwrap();
+	assert!(worker.ensure_container_running(None).unwrap());
+	assert_eq!(worker.restarts, 2);
+	assert_eq!(worker.toolchain_managers[0].container_state().unwrap(), "running");
+}
+
+#[test]
+fn test_manifest_records_environment_with_mock_docker() {
+	let dir = test_dir("mock_docker_environment");
+	let docker_manager = mock_manager(&dir);
+
+	// The command line, host and tool versions are recorded and saved with the manifest.
+	let mut manifest = RunManifest {
+		run: 1,
+		..RunManifest::default()
+	};
+	manifest.record_environment(&docker_manager);
+	assert!(!manifest.argv.is_empty());
+	assert!(!manifest.host_os.is_empty());
+	assert_eq!(manifest.docker_version.as_deref(), Some("Docker version 0.0.0, build mock"));
+	assert_eq!(manifest.cjxl_version.as_deref(), Some("JPEG XL encoder v0.0.0 mock"));
+	let benchmark_dir = dir.to_str().unwrap();
+	manifest.save(benchmark_dir).unwrap();
+	assert_eq!(RunManifest::load(benchmark_dir, 1).unwrap(), manifest);
+
+	// Versions that cannot be queried are left out instead of failing the run.
+	std::fs::write(dir.join("fail"), "exec").unwrap();
+	manifest.record_environment(&docker_manager);
+	assert!(manifest.docker_version.is_some());
+	assert_eq!(manifest.cjxl_version, None);
+}
+
+#[test]
+fn test_build_args_and_context_with_mock_docker() {
+	let dir = test_dir("mock_docker_build_args");
+	let mut docker_manager = mock_manager(&dir);
+
+	// By default the image is built from the Dockerfile in the current directory.
+	assert_eq!(
+		DockerManager::build_args("image", "Dockerfile", &[], DockerManager::DEFAULT_CONTEXT_DIR),
+		vec!["build", "-t", "ubuntu:image", "-f", "Dockerfile", "."]
+	);
+
+	// The configured build arguments and context are passed to docker build.
+	docker_manager.build_args = vec!["BASE_IMAGE=arm64v8/ubuntu:22.04".to_string(), "CMAKE_VERSION=3.28".to_string()];
+	docker_manager.context_dir = "/home/user/context".to_string();
+	docker_manager.setup(0, None).unwrap();
+	let log = std::fs::read_to_string(dir.join("docker.log")).unwrap();
+	let build = log.lines().find(|line| line.starts_with("build")).expect("build command");
+	assert_eq!(
+		build,
+		format!(
+			"build -t ubuntu:{} -f Dockerfile --build-arg BASE_IMAGE=arm64v8/ubuntu:22.04 --build-arg CMAKE_VERSION=3.28 /home/user/context",
+			DockerManager::IMAGE_NAME
+		)
+	);
+
+	// Build arguments must be KEY=VALUE.
+	assert!(DockerManager::validate_build_args(&docker_manager.build_args).is_ok());
+	assert!(DockerManager::validate_build_args(&["BASE_IMAGE".to_string()]).is_err());
+	assert!(DockerManager::validate_build_args(&["=ubuntu".to_string()]).is_err());
+}
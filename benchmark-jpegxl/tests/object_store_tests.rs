@@ -0,0 +1,47 @@
+use std::path::PathBuf;
+
+use benchmark_jpegxl::object_store::ObjectStore;
+use benchmark_jpegxl::utils::hash_file;
+
+mod common;
+use common::test_dir;
+
+#[test]
+fn test_identical_encodes_share_an_object() {
+	let dir = test_dir("object_store");
+	let store = ObjectStore::new(dir.to_str().unwrap());
+
+	// Two commits that encode the same image to the same bytes, and one that does not.
+	let encode = |commit: &str, bytes: &[u8]| {
+		let path = dir.join(commit).join("kodim01__d1__e7.jxl");
+		std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+		std::fs::write(&path, bytes).unwrap();
+		path
+	};
+	let path_1 = encode("abc123", b"\xff\x0a identical encode");
+	let path_2 = encode("def456", b"\xff\x0a identical encode");
+	let path_3 = encode("main", b"\xff\x0a different encode");
+	let hash = hash_file(path_1.to_str().unwrap()).unwrap();
+
+	let object_1 = store.store(path_1.to_str().unwrap()).unwrap();
+	let object_2 = store.store(path_2.to_str().unwrap()).unwrap();
+	let object_3 = store.store(path_3.to_str().unwrap()).unwrap();
+
+	// The identical encodes are a single object with two references.
+	assert_eq!(object_1, object_2);
+	assert_ne!(object_1, object_3);
+	assert!(object_1.ends_with(&format!("objects/{}.jxl", hash)), "{}", object_1);
+	let objects = std::fs::read_dir(dir.join(ObjectStore::DIR_NAME)).unwrap().count();
+	assert_eq!(objects, 2);
+	for path in [&path_1, &path_2] {
+		assert!(std::fs::symlink_metadata(path).unwrap().file_type().is_symlink());
+		assert_eq!(std::fs::read_link(path).unwrap(), PathBuf::from(&object_1));
+	}
+
+	// The outputs are still read at their usual paths.
+	assert_eq!(std::fs::read(&path_2).unwrap(), b"\xff\x0a identical encode");
+
+	// Storing an output that already links to its object keeps the link.
+	assert_eq!(store.store(path_1.to_str().unwrap()).unwrap(), object_1);
+	assert_eq!(std::fs::read(&path_1).unwrap(), b"\xff\x0a identical encode");
+}
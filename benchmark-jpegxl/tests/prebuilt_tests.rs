@@ -0,0 +1,54 @@
+use benchmark_jpegxl::benchmark::{Benchmarker, JXLCompressionBenchmark};
+use benchmark_jpegxl::config::{Config, PrebuiltCjxl};
+use benchmark_jpegxl::manifest::RunManifest;
+
+mod common;
+use common::{mock_docker, test_dir};
+
+#[test]
+fn test_prebuilt_cjxl_run() {
+	// The mock docker logs every invocation next to itself.
+	let dir = test_dir("prebuilt_cjxl");
+	let docker = mock_docker(&dir);
+
+	let binary_dir = dir.join("prebuilt");
+	std::fs::create_dir_all(&binary_dir).unwrap();
+	let benchmark_dir = dir.join("benchmarks");
+	let benchmark_dir = benchmark_dir.to_str().unwrap();
+	std::fs::create_dir_all(dir.join("test_images/set")).unwrap();
+	let mut config = Config {
+		docker_bin: docker,
+		benchmark_dir_path: benchmark_dir.to_string(),
+		local_test_image_dir_path: dir.join("test_images").to_str().unwrap().to_string(),
+		num_workers: 1,
+		prebuilt_cjxl: Some(PrebuiltCjxl {
+			version: "v0.10.2".to_string(),
+			binary_dir: Some(binary_dir.to_str().unwrap().to_string()),
+		}),
+		..Config::default()
+	};
+
+	// The binary directory must hold both tools.
+	match Benchmarker::new(&config) {
+		Err(e) => assert!(e.to_string().contains("has no cjxl"), "{}", e),
+		Ok(_) => panic!("expected an error for an empty binary directory"),
+	}
+	std::fs::write(binary_dir.join("cjxl"), "prebuilt cjxl").unwrap();
+	std::fs::write(binary_dir.join("djxl"), "prebuilt djxl").unwrap();
+
+	// A commit cannot be benchmarked in the same run.
+	config.libjxl_commit = Some("abc123".to_string());
+	assert!(Benchmarker::new(&config).is_err());
+	config.libjxl_commit = None;
+
+	let mut benchmarker = Benchmarker::new(&config).unwrap();
+	benchmarker.run_benchmark::<JXLCompressionBenchmark>().unwrap();
+	benchmarker.wait_for_all_workers();
+
+	// The run is labelled by the version, and libjxl is never built.
+	let manifest = RunManifest::load(benchmark_dir, 0).unwrap();
+	assert_eq!(manifest.libjxl_commits, vec!["v0.10.2".to_string()]);
+	assert_eq!(manifest.libjxl_version.as_deref(), Some("v0.10.2"));
+	let log = std::fs::read_to_string(dir.join("docker.log")).unwrap();
+	assert!(!log.contains("ci.sh"), "{}", log);
+}
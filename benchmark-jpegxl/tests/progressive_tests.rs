@@ -0,0 +1,98 @@
+use std::path::PathBuf;
+
+use benchmark_jpegxl::benchmark::{Benchmarker, JXLCompressionBenchmark};
+use benchmark_jpegxl::config::Config;
+use benchmark_jpegxl::image_reader::ImageReader;
+use benchmark_jpegxl::smoke::SmokeTest;
+
+mod common;
+use common::{mock_docker, test_dir};
+
+#[test]
+fn test_progressive_decodes() {
+	// The mock docker logs every invocation next to itself.
+	let dir = test_dir("progressive");
+	let docker = mock_docker(&dir);
+
+	// The mock cjxl writes a real encode of the gradient.
+	let image_path = dir.join("test_images/set/gradient.png");
+	SmokeTest::generate_image(&image_path).unwrap();
+	let image = image::open(&image_path).unwrap().to_rgb8();
+	let mut encoder = jpegxl_rs::encoder_builder().build().unwrap();
+	let encoded: jpegxl_rs::encode::EncoderResult<u8> =
+		encoder.encode::<u8, u8>(image.as_raw(), image.width(), image.height()).unwrap();
+	std::fs::write(dir.join("cjxl.jxl"), &encoded.data).unwrap();
+
+	// The mock djxl decodes each prefix to a coarser image the shorter it is, and the whole
+	// bitstream to its real decode.
+	let truncations = vec![10.0, 25.0, 50.0, 100.0];
+	let file_size = encoded.data.len() as u64;
+	std::fs::create_dir_all(dir.join("djxl")).unwrap();
+	for (truncation, step) in truncations.iter().zip([64u8, 32, 16]) {
+		let bytes = JXLCompressionBenchmark::truncated_size(file_size, *truncation);
+		let mut coarse = image.clone();
+		for sample in coarse.iter_mut() {
+			*sample = (*sample / step * step).saturating_add(step / 2);
+		}
+		coarse.save(dir.join(format!("djxl/{}.png", bytes))).unwrap();
+	}
+	let (width, height, decoded) = ImageReader::decode_jxl_rgb8(&encoded.data).unwrap();
+	image::RgbImage::from_raw(width, height, decoded)
+		.unwrap()
+		.save(dir.join(format!("djxl/{}.png", file_size)))
+		.unwrap();
+
+	let benchmark_dir = dir.join("benchmarks");
+	let mut config = Config {
+		docker_bin: docker,
+		benchmark_dir_path: benchmark_dir.to_str().unwrap().to_string(),
+		local_test_image_dir_path: dir.join("test_images").to_str().unwrap().to_string(),
+		num_workers: 1,
+		libjxl_commit: Some("abc123".to_string()),
+		distances: vec![1.0],
+		efforts: vec![7],
+		keep_compressed: false,
+		progressive_truncations: vec![50.0, 0.0],
+		..Config::default()
+	};
+	assert!(Benchmarker::new(&config).is_err());
+	config.progressive_truncations = vec![10.0, 150.0];
+	assert!(Benchmarker::new(&config).is_err());
+	config.progressive_truncations = truncations.clone();
+	let mut benchmarker = Benchmarker::new(&config).unwrap();
+	benchmarker.run_benchmark::<JXLCompressionBenchmark>().unwrap();
+	benchmarker.wait_for_all_workers();
+	let log = std::fs::read_to_string(dir.join("docker.log")).unwrap();
+	assert_eq!(log.matches("--allow_partial_files").count(), 4, "{}", log);
+
+	// One row per truncation, in order, whose quality improves with every byte.
+	let results_dir = benchmark_dir.join("0/results/comp/set/abc123");
+	let progressive_files = std::fs::read_dir(&results_dir)
+		.unwrap()
+		.map(|entry| entry.unwrap().path())
+		.filter(|path| path.to_str().unwrap().ends_with(".progressive.csv"))
+		.collect::<Vec<PathBuf>>();
+	assert_eq!(progressive_files.len(), 1, "{:?}", progressive_files);
+	let contents = std::fs::read_to_string(&progressive_files[0]).unwrap();
+	let lines = contents.lines().collect::<Vec<&str>>();
+	assert_eq!(lines[0], "Truncation (%),Bytes,MSE,PSNR,SSIM");
+	let rows = lines[1..]
+		.iter()
+		.map(|line| line.split(',').map(|value| value.parse::<f64>().unwrap()).collect::<Vec<f64>>())
+		.collect::<Vec<Vec<f64>>>();
+	assert_eq!(rows.len(), truncations.len());
+	for (row, truncation) in rows.iter().zip(&truncations) {
+		assert_eq!(row[0], *truncation);
+		assert_eq!(row[1] as u64, JXLCompressionBenchmark::truncated_size(file_size, *truncation));
+		assert!(row[2..].iter().all(|value| value.is_finite()), "{:?}", rows);
+	}
+	assert_eq!(rows[3][1] as u64, file_size);
+	for pair in rows.windows(2) {
+		assert!(pair[1][2] < pair[0][2], "{:?}", rows);
+		assert!(pair[1][3] > pair[0][3], "{:?}", rows);
+		assert!(pair[1][4] > pair[0][4], "{:?}", rows);
+	}
+
+	// The prefixes are not left behind.
+	assert!(std::fs::read_dir(results_dir.join("progressive")).unwrap().next().is_none());
+}
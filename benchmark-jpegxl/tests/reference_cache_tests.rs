@@ -0,0 +1,38 @@
+use benchmark_jpegxl::reference_cache::{ReferenceCache, ReferenceStatus};
+
+mod common;
+use common::test_dir;
+
+#[test]
+fn test_reference_cache_is_reused() {
+	let dir = test_dir("reference_cache");
+	let cache = ReferenceCache::new(dir.join("references").to_str().unwrap());
+	let source = dir.join("kodim01.jpg");
+	image::RgbImage::from_fn(16, 8, |x, y| image::Rgb([(x * 16) as u8, (y * 32) as u8, 128])).save(&source).unwrap();
+	let source = source.to_str().unwrap();
+
+	// The first run decodes the JPEG into a PNG reference with the same pixels.
+	let (reference, status) = cache.reference(source).unwrap();
+	assert_eq!(status, ReferenceStatus::Written);
+	assert!(reference.ends_with(".png"), "{}", reference);
+	assert_eq!(image::open(&reference).unwrap().to_rgb8(), image::open(source).unwrap().to_rgb8());
+
+	// The second run reads the cached reference instead of decoding the source again, which a
+	// marker written over the reference shows.
+	let marker = image::RgbImage::from_pixel(16, 8, image::Rgb([1, 2, 3]));
+	marker.save(&reference).unwrap();
+	assert_eq!(cache.reference(source).unwrap(), (reference.clone(), ReferenceStatus::Reused));
+	assert_eq!(image::open(&reference).unwrap().to_rgb8(), marker);
+
+	// A changed source gets a new reference.
+	image::RgbImage::from_pixel(16, 8, image::Rgb([200, 100, 50])).save(source).unwrap();
+	let (changed, status) = cache.reference(source).unwrap();
+	assert_eq!(status, ReferenceStatus::Written);
+	assert_ne!(changed, reference);
+
+	// PNG sources are read as they are.
+	let png = dir.join("kodim02.png");
+	marker.save(&png).unwrap();
+	let png = png.to_str().unwrap();
+	assert_eq!(cache.reference(png).unwrap(), (png.to_string(), ReferenceStatus::Source));
+}
@@ -0,0 +1,65 @@
+use benchmark_jpegxl::csv_writer::*;
+use benchmark_jpegxl::report::HtmlReport;
+
+mod common;
+use common::test_dir;
+
+/// Creates a successful comparison result whose metrics improve as the distance decreases.
+fn comparison_result(image_name: &str, distance: f32, effort: u32) -> ComparisonResult {
+	let mut result = ComparisonResult::encode_failed(
+		&format!("{}.png", image_name),
+		&format!("{}__d{}__e{}.jxl", image_name, distance, effort),
+		distance,
+		effort,
+	);
+	result.status = ComparisonResult::STATUS_OK.to_string();
+	result.orig_file_size = 100_000;
+	result.comp_file_size = (20_000.0 / distance) as u64;
+	result.bpp = 4.0 / distance as f64;
+	result.mse = distance as f64;
+	result.psnr = 45.0 - distance as f64;
+	result.ssim = 1.0 - distance as f64 / 100.0;
+	result.ms_ssim = 1.0 - distance as f64 / 200.0;
+	result.butteraugli = distance as f64;
+	result.butteraugli_pnorm = distance as f64 / 2.0;
+	result.ssimulacra2 = 90.0 - 5.0 * distance as f64;
+	result
+}
+
+#[test]
+fn test_html_report_has_image_rows_and_charts() {
+	let dir = test_dir("html_report");
+	let images = ["kodim01", "kodim02", "kodim03"];
+	let results = images
+		.iter()
+		.flat_map(|image| {
+			[1.0, 2.0, 4.0]
+				.into_iter()
+				.flat_map(move |distance| [5, 7].into_iter().map(move |effort| comparison_result(image, distance, effort)))
+		})
+		.collect::<Vec<ComparisonResult>>();
+
+	// Write the results as the runner would and build the report from the file.
+	let comparisons_file = dir.join("comparisons.csv");
+	let comparisons_file = comparisons_file.to_str().unwrap();
+	let csv = ComparisonResultCSV::new();
+	csv.write_csv_header(comparisons_file).unwrap();
+	csv.write_csv(&results, comparisons_file, DEFAULT_FLOAT_DIGITS).unwrap();
+	let mut report = HtmlReport::new();
+	report.add_comparisons_file("kodak (main)", comparisons_file).unwrap();
+	let report_path = report.save(dir.join("report.html").to_str().unwrap()).unwrap();
+	let html = std::fs::read_to_string(report_path).unwrap();
+
+	// One row per original image.
+	for image in images {
+		assert_eq!(html.matches(&format!("<tr><td>{}.png</td>", image)).count(), 1);
+	}
+
+	// One chart per metric, each with a line per effort.
+	assert_eq!(html.matches("<svg class=\"chart\"").count(), HtmlReport::chart_metrics().len());
+	for metric in HtmlReport::chart_metrics() {
+		assert!(html.contains(&format!("data-metric=\"{}\"", metric)));
+	}
+	assert_eq!(html.matches("<polyline").count(), 2 * HtmlReport::chart_metrics().len());
+	assert!(!html.contains("<script"));
+}
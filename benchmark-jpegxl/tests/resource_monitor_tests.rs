@@ -0,0 +1,33 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use benchmark_jpegxl::resource_monitor::*;
+
+#[test]
+fn test_resource_monitor_populates_usage() {
+	// Mock cgroup counters where memory rises and falls and CPU time keeps increasing.
+	let samples = Arc::new(AtomicU64::new(0));
+	let sampler_samples = samples.clone();
+	let monitor = ResourceMonitor::with_sampler(move || {
+		let n = sampler_samples.fetch_add(1, Ordering::SeqCst);
+		let memory = if n == 2 { 64 * 1024 * 1024 } else { 16 * 1024 * 1024 };
+		Some((memory, n * 250_000))
+	});
+	std::thread::sleep(ResourceMonitor::SAMPLE_INTERVAL * 4);
+	let usage = monitor.stop();
+
+	// The sampling thread stopped and the fields are populated.
+	let taken = samples.load(Ordering::SeqCst);
+	std::thread::sleep(ResourceMonitor::SAMPLE_INTERVAL * 2);
+	assert_eq!(samples.load(Ordering::SeqCst), taken);
+	assert_eq!(usage.peak_rss_bytes, 64 * 1024 * 1024);
+	assert!(usage.cpu_seconds > 0.0);
+	assert_eq!(usage.cpu_seconds, (taken - 1) as f64 * 0.25);
+}
+
+#[test]
+fn test_parse_cgroup_sample() {
+	let output = "1048576\nusage_usec 2500000\nuser_usec 2000000\nsystem_usec 500000\n";
+	assert_eq!(parse_cgroup_sample(output), Some((1048576, 2500000)));
+	assert_eq!(parse_cgroup_sample("max\n"), None);
+}
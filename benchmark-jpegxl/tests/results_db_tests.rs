@@ -0,0 +1,133 @@
+use benchmark_jpegxl::binary_results::{append_comparisons, read_comparisons};
+use benchmark_jpegxl::config::OutputFormat;
+use benchmark_jpegxl::csv_writer::{ComparisonResult, CsvSink, DEFAULT_FLOAT_DIGITS};
+use benchmark_jpegxl::results_db::ResultsDatabase;
+
+mod common;
+use common::test_dir;
+
+/// Creates a successful comparison result of an image.
+fn comparison_result(image: &str, distance: f32, effort: u32, comp_file_size: u64, ssimulacra2: f64) -> ComparisonResult {
+	let mut result = ComparisonResult::encode_failed(image, &format!("{}-{}-{}.jxl", image, distance, effort), distance, effort);
+	result.status = ComparisonResult::STATUS_OK.to_string();
+	result.orig_file_size = 30000;
+	result.comp_file_size = comp_file_size;
+	result.width = 64;
+	result.height = 48;
+	result.bpp = comp_file_size as f64 * 8.0 / (64.0 * 48.0);
+	result.ssim = 0.95;
+	result.ssimulacra2 = ssimulacra2;
+	result
+}
+
+#[test]
+fn test_results_database() {
+	let dir = test_dir("results_db");
+	assert_eq!(<OutputFormat as clap::ValueEnum>::from_str("SQLite", true), Ok(OutputFormat::Sqlite));
+	assert_eq!(OutputFormat::Sqlite.comparisons_file("res"), "res/comparisons.csv");
+
+	let path = ResultsDatabase::path(dir.to_str().unwrap());
+	assert_eq!(path, format!("{}/results.sqlite", dir.to_str().unwrap()));
+	let mut database = ResultsDatabase::open(&path).unwrap();
+	database
+		.insert(0, "set", "abc123", &[comparison_result("a.png", 1.0, 7, 1000, 80.0), comparison_result("a.png", 1.0, 9, 900, 81.0)])
+		.unwrap();
+	database
+		.insert(1, "set", "def456", &[comparison_result("a.png", 1.0, 7, 950, 80.5), comparison_result("b.png", 2.0, 7, 500, 70.0)])
+		.unwrap();
+
+	// The schema is normalized into commits, images and comparisons.
+	let tables = database
+		.connection()
+		.prepare("SELECT name FROM sqlite_master WHERE type = 'table' ORDER BY name")
+		.unwrap()
+		.query_map([], |row| row.get::<_, String>(0))
+		.unwrap()
+		.collect::<Result<Vec<String>, _>>()
+		.unwrap();
+	assert_eq!(tables, ["commits", "comparisons", "images"]);
+	let count = |table: &str| {
+		database
+			.connection()
+			.query_row(&format!("SELECT COUNT(*) FROM {}", table), [], |row| row.get::<_, i64>(0))
+			.unwrap()
+	};
+	// Images are recorded per run, so a.png is recorded for both runs.
+	assert_eq!((count("commits"), count("images"), count("comparisons")), (2, 3, 4));
+
+	// Recording the same results again replaces them, and the database persists across opens.
+	database.insert(0, "set", "abc123", &[comparison_result("a.png", 1.0, 7, 1000, 80.0)]).unwrap();
+	drop(database);
+	let database = ResultsDatabase::open(&path).unwrap();
+	assert_eq!(
+		database.connection().query_row("SELECT COUNT(*) FROM comparisons", [], |row| row.get::<_, i64>(0)).unwrap(),
+		4
+	);
+
+	// The values are read back, with the metrics that were not computed as NULL.
+	let (run, commit, image, width, effort, comp_file_size, ssim, ms_ssim, status) = database
+		.connection()
+		.query_row(
+			"SELECT comparisons.run, commits.label, images.name, images.width, effort, comp_file_size, ssim, ms_ssim, status
+			 FROM comparisons
+			 JOIN commits ON commits.id = comparisons.commit_id
+			 JOIN images ON images.id = comparisons.image_id
+			 WHERE images.name = 'b.png'",
+			[],
+			|row| {
+				Ok((
+					row.get::<_, i64>(0)?,
+					row.get::<_, String>(1)?,
+					row.get::<_, String>(2)?,
+					row.get::<_, i64>(3)?,
+					row.get::<_, i64>(4)?,
+					row.get::<_, i64>(5)?,
+					row.get::<_, f64>(6)?,
+					row.get::<_, Option<f64>>(7)?,
+					row.get::<_, String>(8)?,
+				))
+			},
+		)
+		.unwrap();
+	assert_eq!((run, commit.as_str(), image.as_str(), width, effort), (1, "def456", "b.png", 64, 7));
+	assert_eq!((comp_file_size, ssim, ms_ssim, status.as_str()), (500, 0.95, None, "ok"));
+
+	// The best effort per image over all runs is a single query.
+	let best = database
+		.connection()
+		.prepare(
+			"SELECT images.name, comparisons.run, effort, MAX(ssimulacra2)
+			 FROM comparisons JOIN images ON images.id = comparisons.image_id
+			 GROUP BY images.name ORDER BY images.name",
+		)
+		.unwrap()
+		.query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?, row.get::<_, i64>(2)?, row.get::<_, f64>(3)?)))
+		.unwrap()
+		.collect::<Result<Vec<(String, i64, i64, f64)>, _>>()
+		.unwrap();
+	assert_eq!(best, [("a.png".to_string(), 0, 9, 81.0), ("b.png".to_string(), 1, 7, 70.0)]);
+}
+
+#[test]
+fn test_recorded_results_keep_full_precision() {
+	// The sink of a run with a results database keeps the appended comparisons unrounded.
+	let dir = test_dir("results_db_precision");
+	let file = dir.join("comparisons.csv").to_str().unwrap().to_string();
+	let mut sink = CsvSink::new(DEFAULT_FLOAT_DIGITS).with_kept_comparisons();
+	let result = comparison_result("a.png", 1.0, 7, 1000, 80.123456789);
+	append_comparisons(&mut sink, &file, &vec![result]).unwrap();
+	assert_eq!(read_comparisons(&file).unwrap()[0].ssimulacra2, 80.1235);
+	let kept = sink.take_comparisons(&file);
+	assert_eq!(kept.len(), 1);
+	assert_eq!(kept[0].ssimulacra2, 80.123456789);
+	assert!(sink.take_comparisons(&file).is_empty());
+
+	// They are recorded as kept.
+	let mut database = ResultsDatabase::open(&ResultsDatabase::path(dir.to_str().unwrap())).unwrap();
+	database.insert(0, "set", "abc123", &kept).unwrap();
+	let ssimulacra2 = database
+		.connection()
+		.query_row("SELECT ssimulacra2 FROM comparisons", [], |row| row.get::<_, f64>(0))
+		.unwrap();
+	assert_eq!(ssimulacra2, 80.123456789);
+}
@@ -0,0 +1,39 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use benchmark_jpegxl::run_timing::{Phase, RunTiming, RunTimingSummary};
+
+#[test]
+fn test_run_timing_summary() {
+	let run_timing = Arc::new(RunTiming::new());
+
+	// A build, then an image encoded, decoded and compared on a worker thread.
+	run_timing.time(Phase::Build, || std::thread::sleep(Duration::from_millis(20)));
+	let worker_timing = run_timing.clone();
+	std::thread::spawn(move || {
+		worker_timing.time(Phase::Encode, || std::thread::sleep(Duration::from_millis(20)));
+		worker_timing.time(Phase::Decode, || std::thread::sleep(Duration::from_millis(10)));
+		// The metrics are timed by the worker itself and added once they are computed.
+		std::thread::sleep(Duration::from_millis(5));
+		worker_timing.add(Phase::Metrics, Duration::from_millis(5));
+		worker_timing.image_finished();
+	})
+	.join()
+	.unwrap();
+	let summary = run_timing.summary();
+
+	// Every field is populated, and the phases run one after another fit in the wall time.
+	assert!(summary.started_at > 0);
+	assert!(summary.finished_at >= summary.started_at);
+	assert!(summary.build_time_s >= 0.02, "{:?}", summary);
+	assert!(summary.encode_time_s >= 0.02, "{:?}", summary);
+	assert!(summary.decode_time_s >= 0.01, "{:?}", summary);
+	assert_eq!(summary.metrics_time_s, 0.005);
+	assert_eq!(summary.images, 1);
+	assert!(summary.images_per_s > 0.0);
+	assert!(summary.phase_time_s() <= summary.wall_time_s, "{:?}", summary);
+
+	// The summary round trips through the manifest.
+	let json = serde_json::to_string(&summary).unwrap();
+	assert_eq!(serde_json::from_str::<RunTimingSummary>(&json).unwrap(), summary);
+}
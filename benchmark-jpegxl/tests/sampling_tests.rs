@@ -0,0 +1,53 @@
+use benchmark_jpegxl::config::{ExpensiveMetricSample, GridSample};
+use benchmark_jpegxl::sampling::*;
+
+#[test]
+fn test_seeded_sampling_is_reproducible() {
+	// The same seed samples the same combinations.
+	let grid_sample = GridSample { count: 5, seed: 42 };
+	let sampled = grid_sample.sample(40, "kodim01.png");
+	assert_eq!(sampled, grid_sample.sample(40, "kodim01.png"));
+	assert_eq!(sampled.len(), 5);
+	assert!(sampled.windows(2).all(|pair| pair[0] < pair[1]));
+	assert!(sampled.iter().all(|index| *index < 40));
+
+	// Different seeds and different images generally sample different combinations.
+	let other_seeds = (0..20)
+		.filter(|seed| GridSample { count: 5, seed: *seed }.sample(40, "kodim01.png") != sampled)
+		.count();
+	assert!(other_seeds >= 19);
+	assert_ne!(sampled, grid_sample.sample(40, "kodim02.png"));
+
+	// Sampling at least the full grid keeps every combination.
+	assert_eq!(sample_indices(6, 10, 7), vec![0, 1, 2, 3, 4, 5]);
+	assert!(sample_indices(0, 3, 7).is_empty());
+
+	// The generator is stable across platforms and releases, so recorded seeds stay valid.
+	let mut rng = SeededRng::new(0);
+	assert_eq!(rng.next_u64(), 0xE220_A839_7B1D_CDAF);
+	assert_eq!(image_seed(0, "kodim01.png"), image_seed(0, "kodim01.png"));
+	assert_ne!(image_seed(0, "kodim01.png"), image_seed(1, "kodim01.png"));
+}
+
+#[test]
+fn test_expensive_metric_sample() {
+	let efforts = [3, 5, 7, 9];
+	let sample = ExpensiveMetricSample { distances: vec![1.0, 3.0], effort_step: 2 };
+	let included = [0.5, 1.0, 2.0, 3.0]
+		.iter()
+		.flat_map(|distance| efforts.map(|effort| (*distance, effort)))
+		.filter(|(distance, effort)| sample.includes(*distance, *effort, &efforts))
+		.collect::<Vec<(f64, u32)>>();
+	assert_eq!(included, vec![(1.0, 3), (1.0, 7), (3.0, 3), (3.0, 7)]);
+
+	// Distances recorded as f32 still match, and efforts outside the sweep are always included.
+	assert!(!sample.includes(0.1f32 as f64, 3, &efforts));
+	assert!(ExpensiveMetricSample { distances: vec![0.1], effort_step: 1 }.includes(0.1f32 as f64, 3, &efforts));
+	assert!(sample.includes(1.0, 10, &efforts));
+
+	// Without distances, every distance is included.
+	let sample = ExpensiveMetricSample { distances: vec![], effort_step: 3 };
+	assert!(sample.includes(25.0, 3, &efforts));
+	assert!(!sample.includes(25.0, 5, &efforts));
+	assert!(sample.includes(25.0, 9, &efforts));
+}
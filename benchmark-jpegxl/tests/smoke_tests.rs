@@ -0,0 +1,51 @@
+use benchmark_jpegxl::config::Config;
+use benchmark_jpegxl::metrics::MetricKind;
+use benchmark_jpegxl::smoke::SmokeTest;
+
+mod common;
+use common::{mock_docker, test_dir};
+
+#[test]
+fn test_smoke() {
+	// The mock docker logs every invocation next to itself.
+	let dir = test_dir("smoke");
+	let docker = mock_docker(&dir);
+
+	// The mock cjxl writes a real encode of the generated image, made with the libjxl encoder
+	// that jpegxl-rs links.
+	let image_path = dir.join("gradient.png");
+	SmokeTest::generate_image(&image_path).unwrap();
+	let image = image::open(&image_path).unwrap().to_rgb8();
+	assert_eq!(image.dimensions(), (SmokeTest::IMAGE_SIZE, SmokeTest::IMAGE_SIZE));
+	let mut encoder = jpegxl_rs::encoder_builder().build().unwrap();
+	let encoded: jpegxl_rs::encode::EncoderResult<u8> =
+		encoder.encode::<u8, u8>(image.as_raw(), image.width(), image.height()).unwrap();
+	std::fs::write(dir.join("cjxl.jxl"), &encoded.data).unwrap();
+
+	// Settings other than the libjxl and Docker ones are not used by the smoke test.
+	let config = Config {
+		docker_bin: docker,
+		benchmark_dir_path: dir.join("benchmarks").to_str().unwrap().to_string(),
+		local_test_image_dir_path: dir.join("missing").to_str().unwrap().to_string(),
+		libjxl_commit: Some("abc123".to_string()),
+		distances: vec![0.5, 2.0],
+		efforts: vec![3, 9],
+		..Config::default()
+	};
+	let result = SmokeTest::run(&config).unwrap();
+	assert!(result.orig_image_name.starts_with("gradient"), "{}", result.orig_image_name);
+	assert_eq!(result.distance, 1.0);
+	assert_eq!(result.effort, 7);
+	assert_eq!(result.comp_file_size, encoded.data.len() as u64);
+	for metric in MetricKind::ALL {
+		assert!(result.metric(metric).is_finite(), "{}", metric.name());
+	}
+	assert_eq!(result.butteraugli, 0.5);
+	assert_eq!(result.ssimulacra2, 90.0);
+	let log = std::fs::read_to_string(dir.join("docker.log")).unwrap();
+	assert!(log.contains("git checkout 'abc123'"), "{}", log);
+
+	// The smoke test fails if the encode cannot be decoded.
+	std::fs::write(dir.join("cjxl.jxl"), "not a jxl file").unwrap();
+	assert!(SmokeTest::run(&config).is_err());
+}
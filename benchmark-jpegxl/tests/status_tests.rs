@@ -0,0 +1,72 @@
+use benchmark_jpegxl::benchmark::{Benchmarker, JXLCompressionBenchmark};
+use benchmark_jpegxl::config::Config;
+use benchmark_jpegxl::smoke::SmokeTest;
+
+mod common;
+use common::{mock_docker, test_dir};
+
+#[test]
+fn test_status_of_planned_encodes() {
+	// The mock docker logs every invocation next to itself.
+	let dir = test_dir("status");
+	let docker = mock_docker(&dir);
+
+	// The mock cjxl writes an encode of the gradient for every image, so the encodes of the
+	// smaller image have the wrong size and fail.
+	let test_set = dir.join("test_images/set");
+	SmokeTest::generate_image(&test_set.join("gradient.png")).unwrap();
+	image::RgbImage::new(32, 32).save(test_set.join("small.png")).unwrap();
+	let image = image::open(test_set.join("gradient.png")).unwrap().to_rgb8();
+	let mut encoder = jpegxl_rs::encoder_builder().build().unwrap();
+	let encoded: jpegxl_rs::encode::EncoderResult<u8> =
+		encoder.encode::<u8, u8>(image.as_raw(), image.width(), image.height()).unwrap();
+	std::fs::write(dir.join("cjxl.jxl"), &encoded.data).unwrap();
+
+	let benchmark_dir = dir.join("benchmarks");
+	let config = Config {
+		docker_bin: docker,
+		benchmark_dir_path: benchmark_dir.to_str().unwrap().to_string(),
+		local_test_image_dir_path: dir.join("test_images").to_str().unwrap().to_string(),
+		num_workers: 1,
+		libjxl_commit: Some("abc123".to_string()),
+		distances: vec![1.0, 2.0],
+		efforts: vec![7],
+		keep_compressed: false,
+		..Config::default()
+	};
+	let mut benchmarker = Benchmarker::new(&config).unwrap();
+	benchmarker.run_benchmark::<JXLCompressionBenchmark>().unwrap();
+	benchmarker.wait_for_all_workers();
+
+	// There is one row for every planned combination, with the outcome of its encode.
+	let mut reader = csv::Reader::from_path(benchmark_dir.join("0/results/status.csv")).unwrap();
+	assert_eq!(
+		reader.headers().unwrap().iter().collect::<Vec<&str>>(),
+		vec!["Test Set", "Commit", "Image Name", "Distance", "Quality", "Effort", "Status", "Reason"]
+	);
+	let mut rows = reader
+		.records()
+		.map(|record| record.unwrap().iter().map(String::from).collect::<Vec<String>>())
+		.collect::<Vec<Vec<String>>>();
+	rows.sort();
+	assert_eq!(rows.len(), 4, "{:?}", rows);
+	let planned = rows
+		.iter()
+		.map(|row| (row[2].split('.').next().unwrap().to_string(), row[3].clone(), row[5].clone()))
+		.collect::<Vec<(String, String, String)>>();
+	let expected = ["gradient", "small"]
+		.iter()
+		.flat_map(|image| ["1", "2"].map(|distance| (image.to_string(), distance.to_string(), "7".to_string())))
+		.collect::<Vec<(String, String, String)>>();
+	assert_eq!(planned, expected);
+	for row in &rows {
+		assert_eq!((row[0].as_str(), row[1].as_str(), row[4].as_str()), ("set", "abc123", ""));
+		match row[2].starts_with("gradient") {
+			true => assert_eq!((row[6].as_str(), row[7].as_str()), ("encoded", ""), "{:?}", row),
+			false => {
+				assert_eq!(row[6], "failed", "{:?}", row);
+				assert!(row[7].starts_with("Could not decode small"), "{:?}", row);
+			}
+		}
+	}
+}
@@ -0,0 +1,120 @@
+use benchmark_jpegxl::benchmark::{Benchmarker, JXLCompressionBenchmark};
+use benchmark_jpegxl::binary_results::read_comparisons;
+use benchmark_jpegxl::config::{Config, Toolchain};
+use benchmark_jpegxl::manifest::RunManifest;
+use benchmark_jpegxl::smoke::SmokeTest;
+
+mod common;
+use common::{mock_docker, test_dir};
+
+#[test]
+fn test_sweep_per_toolchain() {
+	// The mock docker logs every invocation next to itself.
+	let dir = test_dir("toolchains");
+	let docker = mock_docker(&dir);
+
+	// The mock cjxl writes a real encode of the gradient.
+	let image_path = dir.join("test_images/set/gradient.png");
+	SmokeTest::generate_image(&image_path).unwrap();
+	let image = image::open(&image_path).unwrap().to_rgb8();
+	let mut encoder = jpegxl_rs::encoder_builder().build().unwrap();
+	let encoded: jpegxl_rs::encode::EncoderResult<u8> =
+		encoder.encode::<u8, u8>(image.as_raw(), image.width(), image.height()).unwrap();
+	std::fs::write(dir.join("cjxl.jxl"), &encoded.data).unwrap();
+
+	// Two trivial toolchains, labelled by name and by the extension of their Dockerfile.
+	let gcc_dockerfile = dir.join("Dockerfile.gcc");
+	let clang_dockerfile = dir.join("Dockerfile.clang");
+	std::fs::write(&gcc_dockerfile, "FROM ubuntu:22.04\nENV CC=gcc CXX=g++\n").unwrap();
+	std::fs::write(&clang_dockerfile, "FROM ubuntu:22.04\nENV CC=clang CXX=clang++\n").unwrap();
+	let toolchains = vec![
+		Toolchain::try_from(format!("gcc={}", gcc_dockerfile.to_str().unwrap()).as_str()).unwrap(),
+		Toolchain::try_from(clang_dockerfile.to_str().unwrap()).unwrap(),
+	];
+	assert_eq!(toolchains[1].name, "clang");
+
+	// Invalid toolchains are errors rather than panics.
+	assert!(Toolchain::try_from("=Dockerfile.gcc").is_err());
+	assert!(Toolchain::try_from("gcc=").is_err());
+	assert!(Toolchain::try_from("a/b=Dockerfile.gcc").is_err());
+
+	let benchmark_dir = dir.join("benchmarks");
+	let mut config = Config {
+		docker_bin: docker,
+		benchmark_dir_path: benchmark_dir.to_str().unwrap().to_string(),
+		local_test_image_dir_path: dir.join("test_images").to_str().unwrap().to_string(),
+		num_workers: 1,
+		libjxl_commit: Some("abc123".to_string()),
+		distances: vec![1.0],
+		efforts: vec![7],
+		keep_compressed: false,
+		toolchains: vec![toolchains[0].clone(), toolchains[0].clone()],
+		..Config::default()
+	};
+	assert!(Benchmarker::new(&config).is_err());
+	config.toolchains = vec![Toolchain::try_from(format!("missing={}", dir.join("Dockerfile.missing").to_str().unwrap()).as_str()).unwrap()];
+	assert!(Benchmarker::new(&config).is_err());
+	config.toolchains = toolchains;
+	let mut benchmarker = Benchmarker::new(&config).unwrap();
+	benchmarker.run_benchmark::<JXLCompressionBenchmark>().unwrap();
+	benchmarker.wait_for_all_workers();
+
+	// Each toolchain builds its own image from its Dockerfile and encodes in its own container.
+	let log = std::fs::read_to_string(dir.join("docker.log")).unwrap();
+	for (name, dockerfile) in [("gcc", &gcc_dockerfile), ("clang", &clang_dockerfile)] {
+		let build = format!(
+			"build -t ubuntu:benchmark-libjxl-image-{} -f {}",
+			name,
+			dockerfile.to_str().unwrap()
+		);
+		assert!(log.contains(&build), "{}", log);
+		let container = format!("benchmark-libjxl-container-{}-0", name);
+		assert!(log.contains(&format!("run --name {} -dit", container)), "{}", log);
+		assert!(
+			log.lines().any(|line| line.starts_with("exec") && line.contains(&container) && line.contains("cjxl")),
+			"{}",
+			log
+		);
+	}
+
+	// The results of each toolchain are labelled by the commit and toolchain.
+	for label in ["abc123@gcc", "abc123@clang"] {
+		let comparisons_file = benchmark_dir.join(format!("0/results/comp/set/{}/comparisons.csv", label));
+		let results = read_comparisons(comparisons_file.to_str().unwrap()).unwrap();
+		assert_eq!(results.len(), 1, "{}", label);
+		assert!(results[0].is_ok() && results[0].ssim.is_finite(), "{:?}", results[0]);
+	}
+	let manifest = RunManifest::load(benchmark_dir.to_str().unwrap(), 0).unwrap();
+	assert_eq!(manifest.libjxl_commits, vec!["abc123@gcc", "abc123@clang"]);
+	assert!(!benchmark_dir.join("0/results/comp/set/abc123").exists());
+}
+
+#[test]
+fn test_comparison_pairs_per_toolchain() {
+	let csvs = |labels: &[&str]| {
+		labels.iter().map(|label| format!("res/set/{}/comparisons.csv", label)).collect::<Vec<String>>()
+	};
+	let pair = |a: &str, b: &str, output_dir: &str| {
+		(format!("res/set/{}/comparisons.csv", a), format!("res/set/{}/comparisons.csv", b), format!("res/set/{}", output_dir))
+	};
+
+	// Three toolchains of one commit are each diffed against the first, next to themselves.
+	let toolchains = csvs(&["abc123@gcc", "abc123@clang", "abc123@icx"]);
+	assert_eq!(
+		Benchmarker::comparison_pairs(&toolchains, 3).unwrap(),
+		vec![pair("abc123@gcc", "abc123@clang", "abc123@clang"), pair("abc123@gcc", "abc123@icx", "abc123@icx")]
+	);
+
+	// Two toolchains of two commits are each diffed against the same toolchain of the other.
+	let commits = csvs(&["abc123@gcc", "abc123@clang", "def456@gcc", "def456@clang"]);
+	assert_eq!(
+		Benchmarker::comparison_pairs(&commits, 2).unwrap(),
+		vec![pair("abc123@gcc", "def456@gcc", "abc123@gcc"), pair("abc123@clang", "def456@clang", "abc123@clang")]
+	);
+
+	// A single variant of a single commit has nothing to diff, and files that are not a whole
+	// number of commits, or more than two commits, cannot be paired.
+	assert!(Benchmarker::comparison_pairs(&csvs(&["abc123"]), 1).unwrap().is_empty());
+	assert!(Benchmarker::comparison_pairs(&commits[..3], 2).is_err());
+	assert!(Benchmarker::comparison_pairs(&csvs(&["a", "b", "c"]), 1).is_err());
+}
@@ -0,0 +1,59 @@
+use std::path::PathBuf;
+
+use benchmark_jpegxl::binary_results::{read_comparisons, write_comparisons};
+use benchmark_jpegxl::csv_writer::*;
+use benchmark_jpegxl::image_reader::ImageReader;
+use benchmark_jpegxl::verify::RunVerifier;
+
+mod common;
+use common::test_dir;
+
+/// The comparison of two fixture images as a run with the default precision stores it, with its
+/// MSE and PSNRs computed independently of the metric code.
+const KNOWN_GOOD_COMPARISONS: &str = "tests/images/verify/comparisons.csv";
+
+/// The original and compressed images of `KNOWN_GOOD_COMPARISONS`.
+const ORIG_IMAGE: &str = "tests/images/icc/srgb.png";
+const COMP_IMAGE: &str = "tests/images/icc/display_p3.png";
+
+/// Writes image file data rows to a results CSV file.
+fn write_results(res_path: &PathBuf, image_paths: &[&str]) {
+	std::fs::create_dir_all(res_path).unwrap();
+	let data = image_paths
+		.iter()
+		.map(|path| ImageReader::new(path.to_string(), "main".to_string()).file_data)
+		.collect::<Vec<_>>();
+	let result_file = res_path.join("results.csv");
+	let csv_writer = ImageFileDataCSV::new();
+	csv_writer.write_csv_header(result_file.to_str().unwrap()).unwrap();
+	csv_writer.write_csv(&data, result_file.to_str().unwrap(), DEFAULT_FLOAT_DIGITS).unwrap();
+}
+
+#[test]
+fn test_verify_known_good_row() {
+	let dir = test_dir("verify");
+	let res_orig_path = dir.join("1/results/orig/icc/main");
+	let res_comp_path = dir.join("1/results/comp/icc/main");
+	write_results(&res_orig_path, &[ORIG_IMAGE]);
+	write_results(&res_comp_path, &[COMP_IMAGE]);
+	let comparisons_file = res_comp_path.join("comparisons.csv");
+	let comparisons_file = comparisons_file.to_str().unwrap();
+	std::fs::copy(KNOWN_GOOD_COMPARISONS, comparisons_file).unwrap();
+
+	// The known-good row verifies.
+	let benchmark_dir = dir.to_str().unwrap();
+	assert!(RunVerifier::verify_run(benchmark_dir, 1, 1e-6).unwrap().is_empty());
+
+	// Perturbing one stored value flags exactly that metric.
+	let mut perturbed = read_comparisons(comparisons_file).unwrap();
+	assert_eq!(perturbed[0].psnr, 22.2217);
+	perturbed[0].psnr += 0.5;
+	write_comparisons(comparisons_file, &perturbed, DEFAULT_FLOAT_DIGITS).unwrap();
+	let mismatches = RunVerifier::verify_run(benchmark_dir, 1, 1e-6).unwrap();
+	assert_eq!(mismatches.len(), 1);
+	assert_eq!(mismatches[0].row, 0);
+	assert_eq!(mismatches[0].comp_image_name, "display_p3.png");
+	assert_eq!(mismatches[0].metric, "psnr");
+	assert_eq!(mismatches[0].stored, 22.7217);
+	assert_eq!(mismatches[0].recomputed, 22.2217);
+}